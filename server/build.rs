@@ -0,0 +1,14 @@
+//! Records the commit the server is built from, see `ServerCapabilities`.
+
+use std::process::Command;
+
+fn main() {
+    let commit = Command::new("git")
+        .args(&["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|o| o.status.success())
+        .map(|o| String::from_utf8_lossy(&o.stdout).trim().to_string())
+        .unwrap_or_default();
+    println!("cargo:rustc-env=UOSQL_BUILD_COMMIT={}", commit);
+}
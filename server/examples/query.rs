@@ -3,6 +3,7 @@ use server::auth;
 use server::net;
 use server::net::types::DataSet;
 use server::parse;
+use server::process::CancelToken;
 use server::query;
 use server::storage::SqlType;
 use std::cmp::{max, min};
@@ -31,7 +32,8 @@ fn execute(query: &str, user: &mut auth::User) {
     match ast {
         Ok(tree) => {
             println!("{:?}", tree);
-            match query::execute_from_ast(tree, user) {
+            // registered connections are numbered from 1 on, 0 is free
+            match query::execute_from_ast(tree, user, &CancelToken::new(), 0) {
                 Ok(s) => display(&mut net::types::preprocess(&s)),
                 Err(error) => println!("{:?}", error),
             };
@@ -88,6 +90,38 @@ fn display_data(table: &mut DataSet) {
             SqlType::Char(size) => {
                 cols.push(max(size as usize, table.get_col_name(i).unwrap().len()));
             }
+            SqlType::VarChar(size) => {
+                cols.push(max(size as usize, table.get_col_name(i).unwrap().len()));
+            }
+            SqlType::Text => {
+                cols.push(max(30, table.get_col_name(i).unwrap().len()));
+            }
+            SqlType::Float | SqlType::Double | SqlType::BigInt => {
+                cols.push(max(20, table.get_col_name(i).unwrap().len()));
+            }
+            SqlType::Date => {
+                cols.push(max(10, table.get_col_name(i).unwrap().len()));
+            }
+            SqlType::Time => {
+                cols.push(max(15, table.get_col_name(i).unwrap().len()));
+            }
+            SqlType::Timestamp => {
+                cols.push(max(26, table.get_col_name(i).unwrap().len()));
+            }
+            SqlType::Decimal(precision, _) => {
+                cols.push(max(
+                    precision as usize + 2,
+                    table.get_col_name(i).unwrap().len(),
+                ));
+            }
+            // two hex digits per byte behind \x
+            SqlType::VarBinary(size) => {
+                let width = min(size as usize * 2 + 2, 30);
+                cols.push(max(width, table.get_col_name(i).unwrap().len()));
+            }
+            SqlType::Blob => {
+                cols.push(max(30, table.get_col_name(i).unwrap().len()));
+            }
         }
     }
 
@@ -117,16 +151,16 @@ fn display_data(table: &mut DataSet) {
                 Some(t) => match t {
                     SqlType::Int => match table.next_int_by_idx(i) {
                         Some(val) => print!("| {1: ^0$} ", min(30, cols[i]), val),
-                        None => print!("| {1: ^0$} ", min(30, cols[i]), "none"),
+                        None => print!("| {1: ^0$} ", min(30, cols[i]), "NULL"),
                     },
                     SqlType::Bool => match table.next_bool_by_idx(i) {
                         Some(val) => print!("| {1: ^0$} ", min(30, cols[i]), val),
-                        None => print!("| {1: ^0$} ", min(30, cols[i]), "none"),
+                        None => print!("| {1: ^0$} ", min(30, cols[i]), "NULL"),
                     },
-                    SqlType::Char(_) => print!(
+                    _ => print!(
                         "| {1: ^0$} ",
                         min(30, cols[i]),
-                        table.next_char_by_idx(i).unwrap_or("none".into())
+                        table.next_as_string_by_idx(i).unwrap_or("NULL".into())
                     ),
                 },
                 None => continue,
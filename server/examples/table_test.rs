@@ -21,6 +21,7 @@ fn main() {
         allow_null: false,
         description: "Heiner".to_string(),
         is_primary_key: true,
+        default: None,
     });
     cols.push(Column {
         name: "Mathias".into(),
@@ -28,6 +29,7 @@ fn main() {
         allow_null: true,
         description: "Mathias".to_string(),
         is_primary_key: false,
+        default: None,
     });
     cols.push(Column {
         name: "Dennis".into(),
@@ -35,6 +37,7 @@ fn main() {
         allow_null: false,
         description: "Dennis".to_string(),
         is_primary_key: false,
+        default: None,
     });
 
     let _storage_team = db
@@ -132,6 +135,7 @@ fn flat_file_test() {
         allow_null: false,
         description: "Heiner".to_string(),
         is_primary_key: true,
+        default: None,
     });
 
     // let db = Database::create("test").unwrap();
@@ -2,111 +2,164 @@ extern crate docopt;
 #[macro_use]
 extern crate log;
 extern crate serde;
-extern crate serde_json;
 extern crate server;
 
 use serde::Deserialize;
 
 use docopt::Docopt;
-use std::fs::File;
-use std::io::Read;
-use std::net::Ipv4Addr;
-use std::str::FromStr;
+use server::config::{self, Config, Source};
+use std::env;
+use std::path::Path;
+use std::process;
 
 /// For console input, manages flags and arguments
 const USAGE: &'static str = "
-Usage: uosql-server [--cfg=<file>] [--bind=<address>] [--port=<port>]
-[--dir=<directory>]
+Usage: uosql-server [options]
 
 Options:
-    --cfg=<file>        Enter a configuration file.
-    --bind=<address>    Change the bind address.
-    --port=<port>       Change the port.
-    --dir=<directory>   Change the path of the database.
+    --cfg=<file>              Enter a configuration file, TOML or JSON.
+    --bind=<address>          Change the bind address.
+    --port=<port>             Change the port.
+    --dir=<directory>         Change the path of the database.
+    --log-level=<level>       Change the log level: off, error, warn, info,
+                              debug or trace.
+    --log-file=<file>         Change the file the log is appended to.
+    --max-connections=<n>     Change the maximum number of connections.
+    --workers=<n>             Change the number of threads serving them.
+    --memory-budget=<bytes>   Change the memory of a sort or join.
+    --audit-log=<file>        Change the file of the audit log.
+    --query-log=<file>        Change the file every statement is logged to.
+    --replication-log=<file>  Change the file changes are logged to for
+                              followers.
+    --replicate-from=<addr>   Follow the leader at host:port.
+    --tls-cert=<file>         Enter the certificate for TLS.
+    --tls-key=<file>          Enter the private key for TLS.
+    --tls-ca=<file>           Enter the authorities whose client
+                              certificates log in without a password.
+    -h, --help                Show this text.
 ";
 
+/// Files read if no configuration file is entered, the first one found
+const DEFAULT_FILES: &'static [&'static str] = &["config.toml", "config.json"];
+
 #[derive(Debug, Deserialize)]
 struct Args {
     flag_cfg: Option<String>,
     flag_bind: Option<String>,
-    flag_port: Option<u16>,
+    flag_port: Option<String>,
     flag_dir: Option<String>,
+    flag_log_level: Option<String>,
+    flag_log_file: Option<String>,
+    flag_max_connections: Option<String>,
+    flag_workers: Option<String>,
+    flag_memory_budget: Option<String>,
+    flag_audit_log: Option<String>,
+    flag_query_log: Option<String>,
+    flag_replication_log: Option<String>,
+    flag_replicate_from: Option<String>,
+    flag_tls_cert: Option<String>,
+    flag_tls_key: Option<String>,
+    flag_tls_ca: Option<String>,
 }
 
 /// Entry point for server.
 fn main() {
-    // Configure and enable the logger. We may `unwrap` here, because a panic
-    // would happen right after starting the program
-    server::logger::with_loglevel(log::LevelFilter::Trace)
-        .with_logfile(std::path::Path::new("log.txt"))
-        .enable()
-        .unwrap();
-    info!("Starting uoSQL server...");
-
     // Getting the information for a possible configuration
     let args: Args = Docopt::new(USAGE)
         .and_then(|d| d.deserialize())
         .unwrap_or_else(|e| e.exit());
 
-    // If a cfg is entered, use this file name to set configurations
-    let mut config = read_conf_from_json(args.flag_cfg.unwrap_or("config.json".into()));
-
-    // Change the bind address if flag is set
-    config.address = args
-        .flag_bind
-        .and_then(|b| Ipv4Addr::from_str(&b).ok())
-        .unwrap_or(config.address);
-
-    // Change port if flag is set
-    config.port = args.flag_port.unwrap_or(config.port);
+    // The logger is not enabled yet, as the configuration sets it up
+    let config = match configure(args) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("uosql-server: invalid configuration: {}", e);
+            process::exit(1);
+        }
+    };
 
-    // Change directory is flag is set
-    config.dir = args.flag_dir.unwrap_or(config.dir);
+    // Configure and enable the logger. We may `unwrap` here, because a panic
+    // would happen right after starting the program
+    let logger = server::logger::with_loglevel(config.log_level);
+    match config.log_file {
+        Some(ref file) => logger.with_logfile(Path::new(file)).enable(),
+        None => logger.enable(),
+    }
+    .unwrap();
+    info!("Starting uoSQL server...");
 
     info!(
         "Bind: {}  Port: {}  Directory: {}",
         config.address, config.port, config.dir
     );
+    info!(
+        "Max connections: {}  Max connections per user: {}  Idle timeout: {} min",
+        config.max_connections, config.max_user_connections, config.idle_timeout
+    );
+    info!("Workers: {}", config.workers);
+    info!(
+        "Max response size: {} bytes  Rows per second: {}  Send timeout: {} s",
+        config.max_response_size, config.rows_per_second, config.send_timeout
+    );
+    if let Some(ref file) = config.audit_log {
+        info!(
+            "Audit log: {}  Rotated at: {} bytes  Files kept: {}",
+            file, config.audit_log_max_size, config.audit_log_files
+        );
+    }
+    if let Some(ref leader) = config.replicate_from {
+        info!("Following the leader at {}, read-only", leader);
+    }
+    info!(
+        "Vacuum interval: {} s  Vacuum threshold: {} %  Memory budget: {} bytes",
+        config.vacuum_interval, config.vacuum_threshold, config.memory_budget
+    );
 
     // Start listening for incoming Tcp connections
     server::listen(config);
 }
 
-/// Creates a Config struct out of a config file
-/// returns default values for everything that is
-/// not entered manually
-fn read_conf_from_json(name: String) -> server::Config {
-    #[derive(Debug, Default, Deserialize)]
-    struct CfgFile {
-        address: Option<String>,
-        port: Option<u16>,
-        dir: Option<String>,
-    }
-
-    // Read from JSON file and decode to CfgFile
-    let mut config = CfgFile::default();
-    if let Ok(mut f) = File::open(name) {
-        let mut s = String::new();
-        if let Err(e) = f.read_to_string(&mut s) {
-            error!("Could not read JSON-file: {:?}", e)
-        } else {
-            config = serde_json::from_str(&s).unwrap();
-        }
-    }
-
-    let s = config.address.unwrap_or("127.0.0.1".into());
-    let bind = match Ipv4Addr::from_str(&s) {
-        Ok(n) => n,
-        Err(_) => {
-            warn!("Invalid bind address, set to default");
-            Ipv4Addr::new(127, 0, 0, 1)
-        }
-    };
+/// Reads the configuration file and overrides its settings by the flags.
+/// Without a file entered, the default ones are optional.
+fn configure(args: Args) -> Result<Config, config::Error> {
+    let file = args.flag_cfg.or_else(|| {
+        DEFAULT_FILES
+            .iter()
+            .find(|file| Path::new(file).exists())
+            .map(|file| file.to_string())
+    });
+    let flags = vec![
+        ("address", args.flag_bind),
+        ("port", args.flag_port),
+        ("dir", args.flag_dir),
+        ("log_level", args.flag_log_level),
+        ("log_file", args.flag_log_file),
+        ("max_connections", args.flag_max_connections),
+        ("workers", args.flag_workers),
+        ("memory_budget", args.flag_memory_budget),
+        ("audit_log", args.flag_audit_log),
+        ("query_log", args.flag_query_log),
+        ("replication_log", args.flag_replication_log),
+        ("replicate_from", args.flag_replicate_from),
+        // like the configuration file, read again in the data directory
+        ("tls_cert", args.flag_tls_cert.map(|file| absolute(&file))),
+        ("tls_key", args.flag_tls_key.map(|file| absolute(&file))),
+        ("tls_ca", args.flag_tls_ca.map(|file| absolute(&file))),
+    ];
+    config::init(Source {
+        // reloads read the same file, even after changing into the data
+        // directory
+        file: file.map(|file| absolute(&file)),
+        flags: flags
+            .into_iter()
+            .filter_map(|(setting, value)| value.map(|value| (setting, value)))
+            .collect(),
+    })
+}
 
-    // Return configuration, all None datafields set to default
-    server::Config {
-        address: bind,
-        port: config.port.unwrap_or(4242),
-        dir: config.dir.unwrap_or("data".into()),
+fn absolute(file: &str) -> String {
+    match env::current_dir() {
+        Ok(dir) => dir.join(file).to_string_lossy().into_owned(),
+        Err(_) => file.into(),
     }
 }
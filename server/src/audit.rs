@@ -0,0 +1,381 @@
+//! Audit log of logins, logouts and changes
+//!
+//! If the configuration has an `audit_log`, every login and logout with the
+//! address of the client, every failed authentication and every statement
+//! changing the schema, the data or the privileges (`Query::is_change`) is
+//! appended to that file, relative to the data directory. A record is a line
+//! of JSON, see `Record`.
+//!
+//! The records form a hash chain: every record holds the SHA-256 hash of the
+//! record before it (`prev`) and its own hash (`hash`), taken over its text
+//! without the hash. Changing, inserting or removing a record breaks the
+//! chain from there on, which `verify` finds. Only records removed at the
+//! end cannot be told from the file alone, so the hash of the last record is
+//! also written to the server log whenever the audit log is opened or
+//! rotated.
+//!
+//! A file growing beyond `audit_log_max_size` is rotated: it is renamed to
+//! `<file>.1`, the older ones to `<file>.2` and so on, and only the newest
+//! `audit_log_files` of them are kept. The chain goes on in the new file.
+
+use serde::{Deserialize, Serialize};
+use serde_json;
+use sha2::{Digest, Sha256};
+
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+
+/// `prev` of the very first record
+const GENESIS: &'static str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// Text between the record and its hash in a line
+const HASH_FIELD: &'static str = ",\"hash\":\"";
+
+/// The audit log of the server, `None` unless `open` was called
+static LOG: Mutex<Option<Log>> = Mutex::new(None);
+static MAX_SIZE: AtomicU64 = AtomicU64::new(0);
+static FILES: AtomicUsize = AtomicUsize::new(0);
+
+/// A line of the audit log, without its hash
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Record {
+    /// Number of the record, counting up from 1 over all files
+    pub seq: u64,
+    /// Milliseconds since the Unix epoch
+    pub time: u64,
+    /// `login`, `login_failed`, `logout` or `statement`
+    pub event: String,
+    /// Id of the connection, unknown for failed logins
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connection: Option<u64>,
+    pub user: String,
+    /// Address of the client
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub database: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub statement: Option<String>,
+    /// Why a login or a statement failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Hash of the record before
+    pub prev: String,
+}
+
+/// Returned by `verify` for a file whose chain is broken
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    /// The file and number of the line, and what is wrong with it
+    Tampered(PathBuf, usize, String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &Error::Io(ref e) => write!(f, "cannot read the audit log: {}", e),
+            &Error::Tampered(ref file, line, ref why) => {
+                write!(f, "{}, line {}: {}", file.display(), line, why)
+            }
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+/// An open audit log file and the end of its chain
+pub struct Log {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    seq: u64,
+    last_hash: String,
+}
+
+impl Log {
+    /// Opens the file for appending, the chain goes on after the last
+    /// record in it.
+    pub fn open(path: &Path) -> io::Result<Log> {
+        let (mut seq, mut last_hash) = (0, GENESIS.to_string());
+        let text = match fs::read(path) {
+            Ok(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+        for line in text.lines() {
+            if let Some((record, hash)) = split(line) {
+                seq = record.seq;
+                last_hash = hash.into();
+            }
+        }
+        let mut file = try!(open_append(path));
+        let size = try!(file.metadata()).len();
+        // A record cut off by a crash must not swallow the next one
+        if !text.is_empty() && !text.ends_with('\n') {
+            try!(file.write_all(b"\n"));
+        }
+        Ok(Log {
+            path: path.into(),
+            file: file,
+            size: size,
+            seq: seq,
+            last_hash: last_hash,
+        })
+    }
+
+    /// Appends a record, its `seq`, `time` and `prev` are set here. The file
+    /// is rotated before if the record would make it larger than `max_size`
+    /// bytes, `0` means never.
+    pub fn append(&mut self, mut record: Record, max_size: u64, files: usize) -> io::Result<()> {
+        record.seq = self.seq + 1;
+        record.time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|t| t.as_millis() as u64)
+            .unwrap_or(0);
+        record.prev = self.last_hash.clone();
+        let text = try!(serde_json::to_string(&record));
+        let hash = hash(&text);
+        let line = format!("{}{}{}\"}}\n", &text[..text.len() - 1], HASH_FIELD, hash);
+
+        if max_size > 0 && self.size > 0 && self.size + line.len() as u64 > max_size {
+            try!(self.rotate(files));
+        }
+        try!(self.file.write_all(line.as_bytes()));
+        self.size += line.len() as u64;
+        self.seq = record.seq;
+        self.last_hash = hash;
+        Ok(())
+    }
+
+    /// Renames the file and its rotated ones and starts a new file.
+    fn rotate(&mut self, files: usize) -> io::Result<()> {
+        let rotated = |n: usize| PathBuf::from(format!("{}.{}", self.path.display(), n));
+        if files == 0 {
+            try!(fs::remove_file(&self.path));
+        } else {
+            for n in (1..files).rev() {
+                match fs::rename(rotated(n), rotated(n + 1)) {
+                    Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+                    other => try!(other),
+                }
+            }
+            try!(fs::rename(&self.path, rotated(1)));
+        }
+        self.file = try!(open_append(&self.path));
+        self.size = 0;
+        info!(
+            "Audit log rotated after record {} with hash {}",
+            self.seq, self.last_hash
+        );
+        Ok(())
+    }
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.append(true).create(true);
+    // records name users and hold statements with their values
+    #[cfg(unix)]
+    options.mode(0o600);
+    options.open(path)
+}
+
+fn hash(text: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(text.as_bytes());
+    hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Splits a line into the record and the hash stored with it, `None` if it
+/// is no record.
+fn split(line: &str) -> Option<(Record, &str)> {
+    let at = match line.rfind(HASH_FIELD) {
+        Some(at) if line.ends_with("\"}") => at,
+        _ => return None,
+    };
+    let hash = &line[at + HASH_FIELD.len()..line.len() - 2];
+    let text = format!("{}}}", &line[..at]);
+    serde_json::from_str(&text)
+        .ok()
+        .map(|record| (record, hash))
+}
+
+/// Checks the chain of the files, oldest first, and returns the number of
+/// records in them. The first record may follow any hash, as older files may
+/// have been rotated away.
+pub fn verify(files: &[PathBuf]) -> Result<u64, Error> {
+    let mut last: Option<(u64, String)> = None;
+    let mut records = 0;
+    for path in files {
+        let file = try!(File::open(path));
+        for (i, line) in BufReader::new(file).lines().enumerate() {
+            let line = try!(line);
+            let tampered = |why: String| Error::Tampered(path.clone(), i + 1, why);
+            let (record, stored) = match split(&line) {
+                Some(split) => split,
+                None => return Err(tampered("is no record".into())),
+            };
+            let text = format!("{}}}", &line[..line.rfind(HASH_FIELD).unwrap()]);
+            if hash(&text) != stored {
+                return Err(tampered(format!("record {} was changed", record.seq)));
+            }
+            if let Some((seq, ref hash)) = last {
+                if record.seq != seq + 1 || record.prev != *hash {
+                    return Err(tampered(format!(
+                        "record {} does not follow record {}",
+                        record.seq, seq
+                    )));
+                }
+            }
+            last = Some((record.seq, stored.into()));
+            records += 1;
+        }
+    }
+    Ok(records)
+}
+
+/// Opens the audit log of the server, records are dropped until then.
+pub fn open(path: &Path) -> io::Result<()> {
+    let log = try!(Log::open(path));
+    info!(
+        "Audit log '{}' continues after record {} with hash {}",
+        path.display(),
+        log.seq,
+        log.last_hash
+    );
+    *LOG.lock().unwrap() = Some(log);
+    Ok(())
+}
+
+/// Sets the size at which the audit log is rotated and the number of rotated
+/// files kept.
+pub fn set_rotation(max_size: u64, files: usize) {
+    MAX_SIZE.store(max_size, Ordering::SeqCst);
+    FILES.store(files, Ordering::SeqCst);
+}
+
+/// Records a successful login.
+pub fn login(connection: u64, user: &str, host: &str) {
+    append(|| Record {
+        event: "login".into(),
+        connection: Some(connection),
+        user: user.into(),
+        host: Some(host.into()),
+        ..Record::default()
+    });
+}
+
+/// Records a failed authentication.
+pub fn login_failed(user: &str, host: &str, error: &str) {
+    append(|| Record {
+        event: "login_failed".into(),
+        user: user.into(),
+        host: Some(host.into()),
+        error: Some(error.into()),
+        ..Record::default()
+    });
+}
+
+/// Records the end of a connection.
+pub fn logout(connection: u64, user: &str, host: &str) {
+    append(|| Record {
+        event: "logout".into(),
+        connection: Some(connection),
+        user: user.into(),
+        host: Some(host.into()),
+        ..Record::default()
+    });
+}
+
+/// Records a statement and, if it failed, why.
+pub fn statement(
+    connection: u64,
+    user: &str,
+    database: Option<&str>,
+    statement: &str,
+    error: Option<String>,
+) {
+    append(|| Record {
+        event: "statement".into(),
+        connection: Some(connection),
+        user: user.into(),
+        database: database.map(|d| d.into()),
+        statement: Some(statement.into()),
+        error: error,
+        ..Record::default()
+    });
+}
+
+fn append<F: FnOnce() -> Record>(record: F) {
+    let mut log = LOG.lock().unwrap();
+    if let Some(ref mut log) = *log {
+        let max_size = MAX_SIZE.load(Ordering::SeqCst);
+        if let Err(e) = log.append(record(), max_size, FILES.load(Ordering::SeqCst)) {
+            error!("Cannot write the audit log: {}", e);
+        }
+    }
+}
+
+#[test]
+fn test_audit_log() {
+    use std::env;
+
+    let dir = env::temp_dir().join(format!("uosql-audit-{}", ::std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("audit.log");
+    let record = |user: &str| Record {
+        event: "login".into(),
+        user: user.into(),
+        ..Record::default()
+    };
+
+    let mut log = Log::open(&path).unwrap();
+    log.append(record("alice"), 0, 2).unwrap();
+    log.append(record("bob"), 0, 2).unwrap();
+    // the chain goes on after reopening
+    let mut log = Log::open(&path).unwrap();
+    assert_eq!(log.seq, 2);
+    log.append(record("carol"), 0, 2).unwrap();
+    assert_eq!(verify(&[path.clone()]).unwrap(), 3);
+
+    // every record gets a file of its own, the oldest is dropped
+    let size = fs::metadata(&path).unwrap().len() / 3;
+    for user in &["dave", "erin", "frank"] {
+        log.append(record(user), size + 1, 2).unwrap();
+    }
+    let rotated = |n: usize| PathBuf::from(format!("{}.{}", path.display(), n));
+    assert!(!rotated(3).exists());
+    let files = vec![rotated(2), rotated(1), path.clone()];
+    assert_eq!(verify(&files).unwrap(), 3);
+    match verify(&[rotated(1), rotated(2)]) {
+        Err(Error::Tampered(..)) => {}
+        other => panic!("{:?}", other),
+    }
+
+    let text = fs::read_to_string(&path).unwrap();
+    assert!(text.contains("\"user\":\"frank\""));
+    fs::write(&path, text.replace("frank", "mallory")).unwrap();
+    match verify(&files) {
+        Err(Error::Tampered(_, 1, _)) => {}
+        other => panic!("{:?}", other),
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
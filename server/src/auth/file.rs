@@ -0,0 +1,109 @@
+//! Accounts of a password file
+//!
+//! The file has a line `name:hash` for every user, like the files written
+//! by `htpasswd` of the Apache HTTP Server. Empty lines and lines starting
+//! with `#` are skipped. Hashes of bcrypt (`htpasswd -B`, starting with
+//! `$2y$`, `$2b$` or `$2a$`) and of SHA-1 (`htpasswd -s`, starting with
+//! `{SHA}`) are understood, others never match a password.
+//!
+//! The file is read again for every login, so changes to it take effect
+//! right away.
+
+use super::{AuthError, AuthProvider};
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use bcrypt;
+use sha1::{Digest, Sha1};
+
+use std::fs;
+
+/// The accounts of a password file, relative to the data directory
+pub struct PasswordFile {
+    path: String,
+}
+
+impl PasswordFile {
+    pub fn new(path: &str) -> PasswordFile {
+        PasswordFile { path: path.into() }
+    }
+
+    /// Reads the users and the hashes of their passwords.
+    fn accounts(&self) -> Result<Vec<(String, String)>, AuthError> {
+        Ok(parse(&try!(fs::read_to_string(&self.path))))
+    }
+}
+
+impl AuthProvider for PasswordFile {
+    fn authenticate(&self, name: &str, passwd: &str) -> Result<(), AuthError> {
+        let accounts = try!(self.accounts());
+        match accounts.iter().find(|&&(ref user, _)| user == name) {
+            None => Err(AuthError::UserNotFound),
+            Some(&(_, ref hash)) if !verify(passwd, hash) => Err(AuthError::WrongPassword),
+            Some(_) => Ok(()),
+        }
+    }
+
+    fn user_exists(&self, name: &str) -> Result<bool, AuthError> {
+        Ok(try!(self.accounts())
+            .iter()
+            .any(|&(ref user, _)| user == name))
+    }
+
+    fn list_users(&self) -> Result<Vec<String>, AuthError> {
+        Ok(try!(self.accounts())
+            .into_iter()
+            .map(|(user, _)| user)
+            .collect())
+    }
+}
+
+/// Returns the users and hashes of the lines of a password file.
+fn parse(text: &str) -> Vec<(String, String)> {
+    text.lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let mut parts = line.splitn(2, ':');
+            match (parts.next(), parts.next()) {
+                (Some(user), Some(hash)) if !user.is_empty() => Some((user.into(), hash.into())),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Returns true if the password matches the hash of a password file.
+fn verify(passwd: &str, hash: &str) -> bool {
+    if hash.starts_with("$2y$") || hash.starts_with("$2b$") || hash.starts_with("$2a$") {
+        bcrypt::verify(passwd, hash).unwrap_or(false)
+    } else if hash.starts_with("{SHA}") {
+        STANDARD.encode(Sha1::digest(passwd.as_bytes())) == hash["{SHA}".len()..]
+    } else {
+        warn!("Password file has a hash of an unknown kind");
+        false
+    }
+}
+
+#[test]
+fn test_password_file() {
+    let bcrypt_hash = bcrypt::hash("secret", 4).unwrap();
+    let text = format!(
+        "# users of the shop\nalice:{}\n\n\
+         bob:{{SHA}}5en6G6MezRroT3XKqkdPOmY/BfQ=\ncarol:$apr1$x$y\n",
+        bcrypt_hash
+    );
+    let accounts = parse(&text);
+    assert_eq!(accounts.len(), 3);
+    assert_eq!(
+        accounts[1],
+        ("bob".into(), "{SHA}5en6G6MezRroT3XKqkdPOmY/BfQ=".into())
+    );
+
+    assert!(verify("secret", &accounts[0].1));
+    assert!(!verify("Secret", &accounts[0].1));
+    assert!(verify("secret", &accounts[1].1));
+    assert!(!verify("secret!", &accounts[1].1));
+    // MD5 of htpasswd is not supported
+    assert!(!verify("secret", &accounts[2].1));
+}
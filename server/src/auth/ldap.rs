@@ -0,0 +1,274 @@
+//! Accounts of an LDAP directory
+//!
+//! A user logs in if the directory accepts a simple bind (RFC 4511) with
+//! the password as the user's entry, whose DN is the template of the
+//! configuration with the name in place of `{user}`, like
+//! `uid={user},ou=people,dc=example,dc=org`. The connection is plain LDAP,
+//! without TLS, so the directory should be reachable over a trusted
+//! network only.
+//!
+//! Every login binds anew, nothing is cached. The directory is not
+//! searched: every name is taken to exist, so privileges can be granted to
+//! users before they logged in, and no users are listed.
+
+use super::{AuthError, AuthProvider};
+
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+/// How long connecting to the directory and waiting for its answer take at
+/// most
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Results of a bind, see RFC 4511, 4.1.9
+const SUCCESS: u64 = 0;
+const NO_SUCH_OBJECT: u64 = 32;
+const INVALID_CREDENTIALS: u64 = 49;
+
+/// The accounts of an LDAP directory
+pub struct Ldap {
+    /// Address of the directory, as `host:port`
+    server: String,
+    /// DN of the entry of a user, with `{user}` in place of the name
+    user_dn: String,
+}
+
+impl Ldap {
+    pub fn new(server: &str, user_dn: &str) -> Ldap {
+        Ldap {
+            server: server.into(),
+            user_dn: user_dn.into(),
+        }
+    }
+
+    /// Binds as the user and returns the result code of the directory.
+    fn bind(&self, name: &str, passwd: &str) -> io::Result<u64> {
+        let addr = match try!(self.server.to_socket_addrs()).next() {
+            Some(addr) => addr,
+            None => return Err(io::Error::new(io::ErrorKind::NotFound, "no address")),
+        };
+        let mut stream = try!(TcpStream::connect_timeout(&addr, TIMEOUT));
+        try!(stream.set_read_timeout(Some(TIMEOUT)));
+        try!(stream.set_write_timeout(Some(TIMEOUT)));
+        let dn = self.user_dn.replace("{user}", &escape(name));
+        try!(stream.write_all(&bind_request(1, &dn, passwd)));
+        let (tag, response) = try!(read_element(&mut stream));
+        let code = match (tag, bind_result(&response)) {
+            (0x30, Some(code)) => code,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "no bind response",
+                ))
+            }
+        };
+        // UnbindRequest, the directory closes the connection then
+        let _ = stream.write_all(&element(0x30, &[0x02, 0x01, 0x02, 0x42, 0x00]));
+        Ok(code)
+    }
+}
+
+impl AuthProvider for Ldap {
+    fn authenticate(&self, name: &str, passwd: &str) -> Result<(), AuthError> {
+        // a bind without password succeeds anonymously, see RFC 4513, 5.1.2
+        if passwd.is_empty() {
+            return Err(AuthError::WrongPassword);
+        }
+        match self.bind(name, passwd) {
+            Ok(SUCCESS) => Ok(()),
+            Ok(NO_SUCH_OBJECT) => Err(AuthError::UserNotFound),
+            Ok(INVALID_CREDENTIALS) => Err(AuthError::WrongPassword),
+            Ok(code) => Err(AuthError::Unavailable(format!("LDAP result code {}", code))),
+            Err(e) => {
+                error!("Cannot bind to LDAP server '{}': {}", self.server, e);
+                Err(AuthError::Unavailable(e.to_string()))
+            }
+        }
+    }
+
+    fn user_exists(&self, _name: &str) -> Result<bool, AuthError> {
+        Ok(true)
+    }
+
+    fn list_users(&self) -> Result<Vec<String>, AuthError> {
+        Ok(Vec::new())
+    }
+}
+
+/// Escapes the characters of a name that are special in a DN, see RFC 4514,
+/// 2.4.
+fn escape(name: &str) -> String {
+    let last = name.chars().count().saturating_sub(1);
+    let mut escaped = String::new();
+    for (i, c) in name.chars().enumerate() {
+        match c {
+            ',' | '+' | '"' | '\\' | '<' | '>' | ';' | '=' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '#' | ' ' if i == 0 => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            ' ' if i == last => escaped.push_str("\\ "),
+            '\0' => escaped.push_str("\\00"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Returns the BER encoding of an element: its tag, length and contents.
+fn element(tag: u8, contents: &[u8]) -> Vec<u8> {
+    let mut encoded = vec![tag];
+    let len = contents.len();
+    if len < 0x80 {
+        encoded.push(len as u8);
+    } else {
+        let bytes: Vec<u8> = (len as u64)
+            .to_be_bytes()
+            .iter()
+            .cloned()
+            .skip_while(|&b| b == 0)
+            .collect();
+        encoded.push(0x80 | bytes.len() as u8);
+        encoded.extend(bytes);
+    }
+    encoded.extend(contents);
+    encoded
+}
+
+/// Returns the LDAPMessage of a simple BindRequest of LDAPv3.
+fn bind_request(id: u8, dn: &str, passwd: &str) -> Vec<u8> {
+    let mut bind = element(0x02, &[3]);
+    bind.extend(element(0x04, dn.as_bytes()));
+    // [0] simple
+    bind.extend(element(0x80, passwd.as_bytes()));
+    let mut message = element(0x02, &[id]);
+    // [APPLICATION 0] BindRequest
+    message.extend(element(0x60, &bind));
+    element(0x30, &message)
+}
+
+/// Returns the result code of the contents of the LDAPMessage of a
+/// BindResponse.
+fn bind_result(message: &[u8]) -> Option<u64> {
+    // the id of the message, then [APPLICATION 1] BindResponse
+    let response = match split_element(message) {
+        Some((0x02, _, rest)) => match split_element(rest) {
+            Some((0x61, response, _)) => response,
+            _ => return None,
+        },
+        _ => return None,
+    };
+    match split_element(response) {
+        Some((0x0a, code, _)) if !code.is_empty() && code.len() <= 8 => {
+            Some(code.iter().fold(0, |n, &b| n << 8 | b as u64))
+        }
+        _ => None,
+    }
+}
+
+/// Splits the first element of BER off the data and returns its tag, its
+/// contents and the data after it.
+fn split_element(data: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    if data.len() < 2 {
+        return None;
+    }
+    let (len, start) = if data[1] < 0x80 {
+        (data[1] as usize, 2)
+    } else {
+        let count = (data[1] & 0x7f) as usize;
+        if count == 0 || count > 4 || data.len() < 2 + count {
+            return None;
+        }
+        let len = data[2..2 + count]
+            .iter()
+            .fold(0, |n, &b| n << 8 | b as usize);
+        (len, 2 + count)
+    };
+    if data.len() < start + len {
+        return None;
+    }
+    Some((data[0], &data[start..start + len], &data[start + len..]))
+}
+
+/// Reads an element of BER and returns its tag and contents.
+fn read_element<R: Read>(reader: &mut R) -> io::Result<(u8, Vec<u8>)> {
+    let mut head = [0; 2];
+    try!(reader.read_exact(&mut head));
+    let len = if head[1] < 0x80 {
+        head[1] as usize
+    } else {
+        let count = (head[1] & 0x7f) as usize;
+        if count == 0 || count > 4 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "bad length"));
+        }
+        let mut bytes = [0; 4];
+        try!(reader.read_exact(&mut bytes[..count]));
+        bytes[..count].iter().fold(0, |n, &b| n << 8 | b as usize)
+    };
+    let mut contents = vec![0; len];
+    try!(reader.read_exact(&mut contents));
+    Ok((head[0], contents))
+}
+
+#[test]
+fn test_ldap() {
+    use std::net::TcpListener;
+    use std::thread;
+
+    assert_eq!(escape("doe, john"), "doe\\, john");
+    assert_eq!(escape("#admin "), "\\#admin\\ ");
+    assert_eq!(
+        bind_request(1, "cn=a", "pw"),
+        vec![
+            0x30, 0x12, 0x02, 0x01, 0x01, 0x60, 0x0d, 0x02, 0x01, 0x03, 0x04, 0x04, b'c', b'n',
+            b'=', b'a', 0x80, 0x02, b'p', b'w'
+        ]
+    );
+    let long = element(0x04, &[0; 300]);
+    assert_eq!(&long[..4], &[0x04, 0x82, 0x01, 0x2c]);
+    assert_eq!(split_element(&long).map(|e| e.1.len()), Some(300));
+
+    // a directory knowing the password "secret" of everybody
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let server = listener.local_addr().unwrap().to_string();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let (_, message) = read_element(&mut stream).unwrap();
+            let (_, _, request) = split_element(&message).unwrap();
+            let (_, bind, _) = split_element(request).unwrap();
+            let (_, _, rest) = split_element(bind).unwrap();
+            let (_, dn, rest) = split_element(rest).unwrap();
+            let (_, passwd, _) = split_element(rest).unwrap();
+            let code = match (dn, passwd) {
+                (b"uid=alice,dc=shop", b"secret") => SUCCESS,
+                (b"uid=alice,dc=shop", _) => INVALID_CREDENTIALS,
+                _ => NO_SUCH_OBJECT,
+            };
+            let mut response = element(0x0a, &[code as u8]);
+            response.extend(element(0x04, b""));
+            response.extend(element(0x04, b""));
+            let mut reply = element(0x02, &[1]);
+            reply.extend(element(0x61, &response));
+            stream.write_all(&element(0x30, &reply)).unwrap();
+        }
+    });
+    let ldap = Ldap::new(&server, "uid={user},dc=shop");
+    assert!(ldap.authenticate("alice", "secret").is_ok());
+    match ldap.authenticate("alice", "guess") {
+        Err(AuthError::WrongPassword) => (),
+        other => panic!("{:?}", other),
+    }
+    match ldap.authenticate("bob", "secret") {
+        Err(AuthError::UserNotFound) => (),
+        other => panic!("{:?}", other),
+    }
+    match ldap.authenticate("alice", "") {
+        Err(AuthError::WrongPassword) => (),
+        other => panic!("{:?}", other),
+    }
+}
@@ -0,0 +1,541 @@
+//! Module for authenticating users and check privileges
+//!
+//! Contains the `User` type and functions to:
+//!
+//! - check if a username-password pair is valid
+//! - load the corresponding user
+//! - create, alter and drop user accounts
+//! - check user permissions for every query
+//!
+//! User accounts are stored in the file `users.meta` inside the data
+//! directory, their passwords hashed by bcrypt. The file is created with an
+//! `admin` account on first startup, whose default password has expired:
+//! the administrator has to change it with `ALTER USER` before running
+//! anything else (`password_expired`). Privileges are stored per database in
+//! its `db.meta` file. The `admin` account implicitly holds every privilege.
+//!
+//! Instead of `users.meta`, the configuration may name another
+//! `AuthProvider` for the accounts: a password file like the ones of
+//! `htpasswd` (see `PasswordFile`) or an LDAP directory (see `Ldap`). Their
+//! accounts are managed there, `CREATE USER` and the like only work on the
+//! built-in store. The `admin` account always logs in with the built-in
+//! store, so that a directory that is down or misconfigured cannot lock the
+//! administrator out, and a follower can still log in to its leader.
+//!
+
+mod file;
+mod ldap;
+
+pub use self::file::PasswordFile;
+pub use self::ldap::Ldap;
+
+use super::storage;
+
+use bincode::{deserialize_from, serialize_into};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+
+use std::fs::{self, OpenOptions};
+use std::io;
+use std::mem;
+use std::sync::{Arc, Mutex};
+
+/// Name of the user store inside the data directory
+const USERS_FILE: &'static str = "users.meta";
+const MAGIC_NUMBER: u64 = 0x556F5553714C5532;
+/// Magic number of stores written before passwords were hashed by bcrypt,
+/// see `AccountV1`
+const MAGIC_NUMBER_V1: u64 = 0x556F5553714C5573;
+/// Name and initial password of the account created on first startup
+pub const ADMIN_NAME: &'static str = "admin";
+const ADMIN_DEFAULT_PASSWORD: &'static str = "admin";
+/// Work factor of bcrypt for new passwords
+const BCRYPT_COST: u32 = bcrypt::DEFAULT_COST;
+
+/// Serializes all read-modify-write cycles on the user store
+static STORE_LOCK: Mutex<()> = Mutex::new(());
+
+/// Accounts of the user store whose password has expired, kept in memory
+/// because every statement checks them
+static EXPIRED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Provider of the accounts of all users but the administrator, the
+/// built-in store if `None`, see `set_provider`
+static PROVIDER: Mutex<Option<Arc<dyn AuthProvider>>> = Mutex::new(None);
+
+/// Contains information about the user that opened the connection. Is used
+/// for every type of access control.
+pub struct User {
+    pub _name: String,
+    pub _currentDatabase: Option<storage::Database>,
+}
+
+impl User {
+    /// Returns true if this is the built-in administrator account.
+    pub fn is_admin(&self) -> bool {
+        self._name == ADMIN_NAME
+    }
+}
+
+/// Errors that may occur during user authentication
+#[derive(Debug)]
+pub enum AuthError {
+    UserNotFound,
+    WrongPassword,
+    UserExists,
+    PermissionDenied,
+    CorruptStore,
+    /// The accounts are managed outside the database, see `AuthProvider`
+    ManagedElsewhere,
+    /// The provider cannot check the password, holds why
+    Unavailable(String),
+    /// The password has to be changed before anything else, or cannot be
+    /// changed to the expired one
+    PasswordExpired,
+    Io(io::Error),
+    Bin(bincode::Error),
+    Hash(bcrypt::BcryptError),
+}
+
+impl From<io::Error> for AuthError {
+    fn from(err: io::Error) -> AuthError {
+        AuthError::Io(err)
+    }
+}
+
+impl From<bincode::Error> for AuthError {
+    fn from(err: bincode::Error) -> AuthError {
+        AuthError::Bin(err)
+    }
+}
+
+impl From<bcrypt::BcryptError> for AuthError {
+    fn from(err: bcrypt::BcryptError) -> AuthError {
+        AuthError::Hash(err)
+    }
+}
+
+/// Where the accounts of the users come from, see `AuthProvider`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Backend {
+    BuiltIn,
+    File,
+    Ldap,
+}
+
+impl Backend {
+    /// Returns the backend of the name used by the configuration,
+    /// `builtin`, `file` or `ldap`.
+    pub fn from_name(name: &str) -> Option<Backend> {
+        match name {
+            "builtin" => Some(Backend::BuiltIn),
+            "file" => Some(Backend::File),
+            "ldap" => Some(Backend::Ldap),
+            _ => None,
+        }
+    }
+}
+
+/// A source of user accounts that checks their passwords
+pub trait AuthProvider: Send + Sync {
+    /// Checks the password of a user.
+    ///
+    /// # Failures
+    /// Fails with `UserNotFound` or `WrongPassword` if the user cannot log
+    /// in with the password.
+    fn authenticate(&self, name: &str, passwd: &str) -> Result<(), AuthError>;
+
+    /// Returns true if an account with the given name exists.
+    fn user_exists(&self, name: &str) -> Result<bool, AuthError>;
+
+    /// Returns the names of all user accounts.
+    fn list_users(&self) -> Result<Vec<String>, AuthError>;
+
+    /// Creates a new user account. Only the built-in store can.
+    fn create_user(&self, _name: &str, _passwd: &str) -> Result<(), AuthError> {
+        Err(AuthError::ManagedElsewhere)
+    }
+
+    /// Sets a new password for an existing user account. Only the built-in
+    /// store can.
+    fn alter_user_password(&self, _name: &str, _passwd: &str) -> Result<(), AuthError> {
+        Err(AuthError::ManagedElsewhere)
+    }
+
+    /// Removes a user account. Only the built-in store can.
+    fn drop_user(&self, _name: &str) -> Result<(), AuthError> {
+        Err(AuthError::ManagedElsewhere)
+    }
+}
+
+/// The accounts of the user store `users.meta`
+pub struct BuiltIn;
+
+impl AuthProvider for BuiltIn {
+    fn authenticate(&self, name: &str, passwd: &str) -> Result<(), AuthError> {
+        let _guard = STORE_LOCK.lock().unwrap();
+        let mut accounts = try!(load_accounts());
+        {
+            let account = match accounts.iter_mut().find(|a| a.name == name) {
+                None => return Err(AuthError::UserNotFound),
+                Some(a) => a,
+            };
+            if !account.check_password(passwd) {
+                return Err(AuthError::WrongPassword);
+            }
+            match account.password {
+                Password::Bcrypt(_) => return Ok(()),
+                // only now the password is known to hash it by bcrypt
+                Password::Sha256 { .. } => {
+                    account.password = Password::Bcrypt(try!(bcrypt::hash(passwd, BCRYPT_COST)))
+                }
+            }
+        }
+        info!("hashed the password of user '{}' by bcrypt", name);
+        save_accounts(&accounts)
+    }
+
+    fn user_exists(&self, name: &str) -> Result<bool, AuthError> {
+        let _guard = STORE_LOCK.lock().unwrap();
+        let accounts = try!(load_accounts());
+        Ok(accounts.iter().any(|a| a.name == name))
+    }
+
+    fn list_users(&self) -> Result<Vec<String>, AuthError> {
+        let _guard = STORE_LOCK.lock().unwrap();
+        Ok(try!(load_accounts()).into_iter().map(|a| a.name).collect())
+    }
+
+    fn create_user(&self, name: &str, passwd: &str) -> Result<(), AuthError> {
+        let _guard = STORE_LOCK.lock().unwrap();
+        let mut accounts = try!(load_accounts());
+        if accounts.iter().any(|a| a.name == name) {
+            return Err(AuthError::UserExists);
+        }
+        accounts.push(try!(Account::new(name, passwd)));
+        info!("created user '{}'", name);
+        save_accounts(&accounts)
+    }
+
+    fn alter_user_password(&self, name: &str, passwd: &str) -> Result<(), AuthError> {
+        let _guard = STORE_LOCK.lock().unwrap();
+        let mut accounts = try!(load_accounts());
+        match accounts.iter_mut().find(|a| a.name == name) {
+            // an expired password is not changed by setting it again
+            Some(ref a) if a.expired && a.check_password(passwd) => {
+                return Err(AuthError::PasswordExpired)
+            }
+            Some(a) => try!(a.set_password(passwd)),
+            None => return Err(AuthError::UserNotFound),
+        }
+        info!("changed password of user '{}'", name);
+        save_accounts(&accounts)
+    }
+
+    fn drop_user(&self, name: &str) -> Result<(), AuthError> {
+        let _guard = STORE_LOCK.lock().unwrap();
+        let mut accounts = try!(load_accounts());
+        let len = accounts.len();
+        accounts.retain(|a| a.name != name);
+        if accounts.len() == len {
+            return Err(AuthError::UserNotFound);
+        }
+        info!("dropped user '{}'", name);
+        save_accounts(&accounts)
+    }
+}
+
+/// Uses the provider for the accounts of all users but the administrator
+/// from now on.
+pub fn set_provider(provider: Arc<dyn AuthProvider>) {
+    *PROVIDER.lock().unwrap() = Some(provider);
+}
+
+/// Returns the provider of the accounts of all users but the administrator.
+fn provider() -> Arc<dyn AuthProvider> {
+    match *PROVIDER.lock().unwrap() {
+        Some(ref provider) => provider.clone(),
+        None => Arc::new(BuiltIn),
+    }
+}
+
+/// Returns the provider of the account of the user.
+fn provider_of(name: &str) -> Arc<dyn AuthProvider> {
+    if name == ADMIN_NAME {
+        Arc::new(BuiltIn)
+    } else {
+        provider()
+    }
+}
+
+/// A persisted user account. Only a hash of the password is stored.
+#[derive(Debug, Serialize, Deserialize)]
+struct Account {
+    name: String,
+    password: Password,
+    /// Must be changed before anything else, see `password_expired`
+    expired: bool,
+}
+
+/// Hash of the password of an account
+#[derive(Debug, Serialize, Deserialize)]
+enum Password {
+    /// Hash of bcrypt, which contains its random salt
+    Bcrypt(String),
+    /// Salted SHA-256 of stores written before, replaced by a hash of bcrypt
+    /// on the next login
+    Sha256 { salt: Vec<u8>, hash: Vec<u8> },
+}
+
+/// A user account as stored before passwords were hashed by bcrypt
+#[derive(Deserialize)]
+struct AccountV1 {
+    name: String,
+    salt: Vec<u8>,
+    hash: Vec<u8>,
+}
+
+impl Account {
+    fn new(name: &str, passwd: &str) -> Result<Account, AuthError> {
+        Ok(Account {
+            name: name.into(),
+            password: Password::Bcrypt(try!(bcrypt::hash(passwd, BCRYPT_COST))),
+            expired: false,
+        })
+    }
+
+    fn set_password(&mut self, passwd: &str) -> Result<(), AuthError> {
+        self.password = Password::Bcrypt(try!(bcrypt::hash(passwd, BCRYPT_COST)));
+        self.expired = false;
+        Ok(())
+    }
+
+    /// Compares in constant time, so the time taken tells nothing about the
+    /// hash.
+    fn check_password(&self, passwd: &str) -> bool {
+        match self.password {
+            Password::Bcrypt(ref hash) => bcrypt::verify(passwd, hash).unwrap_or(false),
+            Password::Sha256 { ref salt, ref hash } => {
+                let mut hasher = Sha256::new();
+                hasher.update(salt);
+                hasher.update(passwd.as_bytes());
+                hasher.finalize().as_slice().ct_eq(hash).into()
+            }
+        }
+    }
+}
+
+impl From<AccountV1> for Account {
+    fn from(old: AccountV1) -> Account {
+        Account {
+            name: old.name,
+            password: Password::Sha256 {
+                salt: old.salt,
+                hash: old.hash,
+            },
+            expired: false,
+        }
+    }
+}
+
+/// Reads all accounts from the user store.
+fn load_accounts() -> Result<Vec<Account>, AuthError> {
+    let mut file = try!(OpenOptions::new().read(true).open(USERS_FILE));
+    let magic = try!(file.read_uint::<BigEndian>(mem::size_of_val(&MAGIC_NUMBER)));
+    match magic {
+        MAGIC_NUMBER => Ok(try!(deserialize_from(&mut file))),
+        MAGIC_NUMBER_V1 => {
+            let old: Vec<AccountV1> = try!(deserialize_from(&mut file));
+            Ok(old.into_iter().map(Account::from).collect())
+        }
+        _ => Err(AuthError::CorruptStore),
+    }
+}
+
+/// Notes the accounts whose password has expired, see `password_expired`.
+fn remember_expired(accounts: &[Account]) {
+    *EXPIRED.lock().unwrap() = accounts
+        .iter()
+        .filter(|a| a.expired)
+        .map(|a| a.name.clone())
+        .collect();
+}
+
+/// Writes all accounts to the user store. The data is written to a temporary
+/// file first, so a crash never leaves a half written store behind.
+fn save_accounts(accounts: &[Account]) -> Result<(), AuthError> {
+    let tmp_path = format!("{}.tmp", USERS_FILE);
+    {
+        let mut file = try!(OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&tmp_path));
+        try!(file.write_u64::<BigEndian>(MAGIC_NUMBER));
+        try!(serialize_into(&mut file, &accounts));
+        try!(file.sync_all());
+    }
+    try!(fs::rename(&tmp_path, USERS_FILE));
+    remember_expired(accounts);
+    Ok(())
+}
+
+/// Creates the user store with an admin account, unless it already exists.
+/// Must be called once on server startup, inside the data directory.
+pub fn init() -> Result<(), AuthError> {
+    let _guard = STORE_LOCK.lock().unwrap();
+    if fs::metadata(USERS_FILE).is_err() {
+        warn!(
+            "Creating user store with account '{}' and default password, \
+             which has to be changed with ALTER USER on the first login",
+            ADMIN_NAME
+        );
+        let mut admin = try!(Account::new(ADMIN_NAME, ADMIN_DEFAULT_PASSWORD));
+        admin.expired = true;
+        return save_accounts(&[admin]);
+    }
+    let mut accounts = try!(load_accounts());
+    // stores of older servers may still have the default password
+    let default = accounts.iter().position(|a| {
+        a.name == ADMIN_NAME && !a.expired && a.check_password(ADMIN_DEFAULT_PASSWORD)
+    });
+    match default {
+        Some(i) => {
+            warn!(
+                "Account '{}' still has the default password, which has to be \
+                 changed with ALTER USER on the next login",
+                ADMIN_NAME
+            );
+            accounts[i].expired = true;
+            save_accounts(&accounts)
+        }
+        None => {
+            remember_expired(&accounts);
+            Ok(())
+        }
+    }
+}
+
+/// Returns true if the user has to change the expired password with
+/// `ALTER USER` before running anything else.
+pub fn password_expired(name: &str) -> bool {
+    EXPIRED.lock().unwrap().iter().any(|n| n == name)
+}
+
+/// Validates username and password and returns the matched user.
+///
+/// # Failures
+/// If the user was not found or the password does not match, an `Err` value
+/// is returned. See `AuthError` for more information.
+pub fn find_user(name: &str, passwd: &str) -> Result<User, AuthError> {
+    try!(provider_of(name).authenticate(name, passwd));
+    debug!("User '{}' was succesfully authenticated", name);
+    Ok(User {
+        _name: name.into(),
+        _currentDatabase: None,
+    })
+}
+
+/// Returns true if an account with the given name exists.
+pub fn user_exists(name: &str) -> Result<bool, AuthError> {
+    provider_of(name).user_exists(name)
+}
+
+/// Checks whether the user may perform `privilege` on `table` of the given
+/// database. If `table` is `None`, a database wide grant is required.
+pub fn has_privilege(
+    user: &User,
+    base: &storage::Database,
+    table: Option<&str>,
+    privilege: storage::Privilege,
+) -> Result<bool, storage::Error> {
+    if user.is_admin() {
+        return Ok(true);
+    }
+    let meta = try!(base.load_meta_data());
+    Ok(meta.has_privilege(&user._name, table, privilege))
+}
+
+/// Checks whether the user holds any privilege in the given database.
+pub fn has_any_privilege(user: &User, base: &storage::Database) -> Result<bool, storage::Error> {
+    if user.is_admin() {
+        return Ok(true);
+    }
+    let meta = try!(base.load_meta_data());
+    Ok(meta.has_any_privilege(&user._name))
+}
+
+/// Returns the names of all user accounts, the administrator first.
+pub fn list_users() -> Result<Vec<String>, AuthError> {
+    let mut users = try!(provider().list_users());
+    users.retain(|name| name != ADMIN_NAME);
+    users.insert(0, ADMIN_NAME.into());
+    Ok(users)
+}
+
+/// Creates a new user account.
+pub fn create_user(name: &str, passwd: &str) -> Result<(), AuthError> {
+    if name == ADMIN_NAME {
+        return Err(AuthError::UserExists);
+    }
+    provider().create_user(name, passwd)
+}
+
+/// Sets a new password for an existing user account.
+pub fn alter_user_password(name: &str, passwd: &str) -> Result<(), AuthError> {
+    provider_of(name).alter_user_password(name, passwd)
+}
+
+/// Removes a user account. The admin account cannot be removed.
+pub fn drop_user(name: &str) -> Result<(), AuthError> {
+    if name == ADMIN_NAME {
+        return Err(AuthError::PermissionDenied);
+    }
+    provider().drop_user(name)
+}
+
+#[test]
+fn test_account_passwords() {
+    let mut account = Account::new("alice", "secret").unwrap();
+    match account.password {
+        Password::Bcrypt(ref hash) => assert!(hash.starts_with("$2")),
+        ref other => panic!("{:?}", other),
+    }
+    assert!(account.check_password("secret"));
+    assert!(!account.check_password("Secret"));
+    // the salt is random
+    let other = Account::new("alice", "secret").unwrap();
+    match (&account.password, &other.password) {
+        (&Password::Bcrypt(ref a), &Password::Bcrypt(ref b)) => assert!(a != b),
+        _ => unreachable!(),
+    }
+    account.expired = true;
+    account.set_password("geheim").unwrap();
+    assert!(!account.expired);
+    assert!(account.check_password("geheim"));
+
+    // stores of older servers hold salted SHA-256 hashes
+    let mut hasher = Sha256::new();
+    hasher.update(b"salt");
+    hasher.update(b"secret");
+    let old = Account::from(AccountV1 {
+        name: "bob".into(),
+        salt: b"salt".to_vec(),
+        hash: hasher.finalize().to_vec(),
+    });
+    assert!(old.check_password("secret"));
+    assert!(!old.check_password("secret!"));
+}
+
+#[test]
+fn test_password_expired() {
+    let mut admin = Account::new("test-expired", ADMIN_DEFAULT_PASSWORD).unwrap();
+    admin.expired = true;
+    remember_expired(&[admin]);
+    assert!(password_expired("test-expired"));
+    assert!(!password_expired("alice"));
+    remember_expired(&[]);
+    assert!(!password_expired("test-expired"));
+}
@@ -0,0 +1,235 @@
+//! Cache of parsed statements
+//!
+//! Clients like ORMs send the same statements over and over, parsing them
+//! again every time is wasted work. The cache keeps the syntax trees of the
+//! last `CAPACITY` queries parsed, keyed by their text with every run of
+//! whitespace outside of string literals and comments turned into a single
+//! space, and drops the least recently used one when it is full. A query
+//! may be a script of several statements, see `Parser::parse_statements`.
+//!
+//! A statement changing the schema (`DefStmt`) clears the cache and starts a
+//! new generation. Prepared statements, see `Command::Prepare`, remember the
+//! generation they were parsed in and are parsed again from their text in a
+//! later one, so that nothing cached outlives the schema it was made for.
+//! Plans are not cached: the executor plans a statement every time it runs
+//! it, as the best plan depends on the current statistics, see `planner`.
+
+use parse;
+use parse::ast::Query;
+use parse::parser::ParseError;
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// Most queries the cache keeps
+pub const CAPACITY: usize = 256;
+
+static CACHE: Mutex<Option<Cache>> = Mutex::new(None);
+static HITS: AtomicU64 = AtomicU64::new(0);
+static MISSES: AtomicU64 = AtomicU64::new(0);
+
+/// Counters of the cache, shown by `SHOW STATUS`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Status {
+    pub hits: u64,
+    pub misses: u64,
+    pub statements: u64,
+}
+
+struct Cache {
+    entries: HashMap<String, Entry>,
+    /// Counts the lookups, for finding the least recently used entry
+    clock: u64,
+    generation: u64,
+}
+
+struct Entry {
+    queries: Vec<Query>,
+    used: u64,
+}
+
+impl Cache {
+    fn new() -> Cache {
+        Cache {
+            entries: HashMap::new(),
+            clock: 0,
+            generation: 0,
+        }
+    }
+
+    fn get(&mut self, key: &str) -> Option<Vec<Query>> {
+        self.clock += 1;
+        let clock = self.clock;
+        self.entries.get_mut(key).map(|entry| {
+            entry.used = clock;
+            entry.queries.clone()
+        })
+    }
+
+    fn insert(&mut self, key: String, queries: Vec<Query>) {
+        if self.entries.len() >= CAPACITY && !self.entries.contains_key(&key) {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|&(_, entry)| entry.used)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.clock += 1;
+        let entry = Entry {
+            queries: queries,
+            used: self.clock,
+        };
+        self.entries.insert(key, entry);
+    }
+}
+
+/// Calls `f` with the cache, which is created on first use.
+fn with_cache<T, F: FnOnce(&mut Cache) -> T>(f: F) -> T {
+    let mut cache = CACHE.lock().unwrap();
+    f(cache.get_or_insert_with(Cache::new))
+}
+
+/// Parses the statements of a query, or returns the trees of the same query
+/// parsed before. Syntax errors are not cached.
+pub fn parse(query: &str) -> Result<Vec<Query>, ParseError> {
+    let key = normalize(query);
+    let (cached, generation) = with_cache(|cache| (cache.get(&key), cache.generation));
+    if let Some(trees) = cached {
+        HITS.fetch_add(1, Ordering::SeqCst);
+        return Ok(trees);
+    }
+    MISSES.fetch_add(1, Ordering::SeqCst);
+    let trees = try!(parse::parse_statements(query));
+    with_cache(|cache| {
+        // the schema may have changed while parsing
+        if cache.generation == generation {
+            cache.insert(key, trees.clone());
+        }
+    });
+    Ok(trees)
+}
+
+/// Returns the current generation, which `invalidate` starts anew.
+pub fn generation() -> u64 {
+    with_cache(|cache| cache.generation)
+}
+
+/// Drops all statements and starts a new generation, after the schema
+/// changed.
+pub fn invalidate() {
+    with_cache(|cache| {
+        cache.entries.clear();
+        cache.generation += 1;
+    });
+}
+
+/// Returns the current counters of the cache.
+pub fn status() -> Status {
+    Status {
+        hits: HITS.load(Ordering::SeqCst),
+        misses: MISSES.load(Ordering::SeqCst),
+        statements: with_cache(|cache| cache.entries.len() as u64),
+    }
+}
+
+/// Returns the key of a query in the cache: its text with every run of
+/// whitespace outside of string literals, quoted names and comments
+/// replaced by a single space, which does not change the tokens of the
+/// query. Like in the lexer, a doubled quotation mark does not end them,
+/// nor does an escaped one in a string. A comment to the end of the line
+/// keeps its line break.
+pub fn normalize(query: &str) -> String {
+    let mut key = String::with_capacity(query.len());
+    let mut chars = query.trim().chars().peekable();
+    let mut quote = None;
+    while let Some(c) = chars.next() {
+        if quote.is_none() && c == '-' && chars.peek() == Some(&'-') {
+            key.push(c);
+            while let Some(c) = chars.next() {
+                key.push(c);
+                if c == '\n' {
+                    break;
+                }
+            }
+            while chars.peek().map_or(false, |c| c.is_whitespace()) {
+                chars.next();
+            }
+        } else if quote.is_none() && c == '/' && chars.peek() == Some(&'*') {
+            key.push(c);
+            key.extend(chars.next());
+            let mut last = None;
+            while let Some(c) = chars.next() {
+                key.push(c);
+                if last == Some('*') && c == '/' {
+                    break;
+                }
+                last = Some(c);
+            }
+        } else if let Some(open) = quote {
+            key.push(c);
+            if c == '\\' && open == '\'' {
+                key.extend(chars.next());
+            } else if c == open {
+                if chars.peek() == Some(&c) {
+                    key.push(c);
+                    chars.next();
+                } else {
+                    quote = None;
+                }
+            }
+        } else if c.is_whitespace() {
+            while chars.peek().map_or(false, |c| c.is_whitespace()) {
+                chars.next();
+            }
+            key.push(' ');
+        } else {
+            if c == '\'' || c == '"' || c == '`' {
+                quote = Some(c);
+            }
+            key.push(c);
+        }
+    }
+    key
+}
+
+#[test]
+fn test_normalize() {
+    assert_eq!(
+        normalize("  select *\n\tfrom  foo where a = 'x  y'  "),
+        "select * from foo where a = 'x  y'"
+    );
+    assert_eq!(
+        normalize("select 'it''s  ', \"a\"  b"),
+        "select 'it''s  ', \"a\" b"
+    );
+    // a literal opened by ' does not end at " or an escaped '
+    assert_eq!(normalize("select 'a\"  b  '"), "select 'a\"  b  '");
+    assert_eq!(
+        normalize("select 'a\\'  b',  `x  y`"),
+        "select 'a\\'  b', `x  y`"
+    );
+    // quotation marks in comments start no literal, line breaks end them
+    assert_eq!(
+        normalize("select  1 -- it's  one\n  ;  /* it's\n*/  select 2"),
+        "select 1 -- it's  one\n; /* it's\n*/ select 2"
+    );
+}
+
+#[test]
+fn test_cache_eviction() {
+    let mut cache = Cache::new();
+    for i in 0..CAPACITY {
+        cache.insert(i.to_string(), vec![Query::Dummy]);
+    }
+    // the first entry was used last, the second is the oldest now
+    assert!(cache.get("0").is_some());
+    cache.insert("new".into(), vec![Query::Dummy]);
+    assert_eq!(cache.entries.len(), CAPACITY);
+    assert!(cache.get("0").is_some());
+    assert!(cache.get("1").is_none());
+    assert!(cache.get("new").is_some());
+}
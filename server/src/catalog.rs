@@ -0,0 +1,222 @@
+//! Schema catalog tables
+//!
+//! The tables of the schema `information_schema` describe the current
+//! database, so that clients can look at its schema with ordinary queries,
+//! e.g. `SELECT * FROM information_schema.columns WHERE table_name = 'foo'`.
+//! They are not stored anywhere: whenever a query reads one of them, the
+//! executor builds its rows from the meta data of the tables the user may
+//! read and from the user accounts.
+
+use dump;
+use parse::token::Lit;
+use storage::{Column, Error, Rows, SqlType, Table};
+
+use std::io::Cursor;
+
+/// Name of the schema of the catalog tables
+pub const SCHEMA: &'static str = "information_schema";
+
+/// The tables of `information_schema`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Catalog {
+    /// A row per table
+    Tables,
+    /// A row per column of a table
+    Columns,
+    /// A row per column of a secondary index
+    Indexes,
+    /// A row per user account
+    Users,
+}
+
+impl Catalog {
+    /// Returns the catalog table of a qualified name like
+    /// `information_schema.tables`, if it is one.
+    pub fn from_name(name: &str) -> Option<Catalog> {
+        let mut parts = name.splitn(2, '.');
+        let (schema, table) = match (parts.next(), parts.next()) {
+            (Some(schema), Some(table)) => (schema, table.to_lowercase()),
+            _ => return None,
+        };
+        if !schema.eq_ignore_ascii_case(SCHEMA) {
+            return None;
+        }
+        match &*table {
+            "tables" => Some(Catalog::Tables),
+            "columns" => Some(Catalog::Columns),
+            "indexes" => Some(Catalog::Indexes),
+            "users" => Some(Catalog::Users),
+            _ => None,
+        }
+    }
+
+    pub fn columns(&self) -> Vec<Column> {
+        let name = |name: &str, description: &str| {
+            Column::new(name, SqlType::VarChar(64), false, description, false)
+        };
+        let number = |name: &str, description: &str| {
+            Column::new(name, SqlType::Int, false, description, false)
+        };
+        let flag = |name: &str, description: &str| {
+            Column::new(name, SqlType::Bool, false, description, false)
+        };
+        match *self {
+            Catalog::Tables => vec![
+                name("table_name", "name of the table"),
+                name("engine", "storage engine"),
+                name("compression", "compression of the data file"),
+                flag("is_encrypted", "whether the data file is encrypted"),
+                number("column_count", "number of columns"),
+            ],
+            Catalog::Columns => vec![
+                name("table_name", "name of the table"),
+                name("column_name", "name of the column"),
+                number("ordinal_position", "position of the column, from 1"),
+                name("data_type", "type as written in CREATE TABLE"),
+                flag("is_nullable", "whether the column allows null"),
+                flag(
+                    "is_primary_key",
+                    "whether the column is part of the primary key",
+                ),
+                Column::new(
+                    "column_default",
+                    SqlType::Text,
+                    true,
+                    "default value as written in SQL, null if there is none",
+                    false,
+                ),
+                Column::new(
+                    "description",
+                    SqlType::Text,
+                    false,
+                    "comment of the column",
+                    false,
+                ),
+            ],
+            Catalog::Indexes => vec![
+                name("index_name", "name of the index"),
+                name("table_name", "name of the table"),
+                name("column_name", "name of the indexed column"),
+                number(
+                    "seq_in_index",
+                    "position of the column in the index, from 1",
+                ),
+                flag("is_unique", "whether the index keeps a UNIQUE constraint"),
+            ],
+            Catalog::Users => vec![name("user_name", "name of the account")],
+        }
+    }
+
+    /// Returns the rows of the catalog table describing `tables` and the
+    /// accounts `users`.
+    pub fn rows(&self, tables: &[Table], users: &[String]) -> Result<Rows<Cursor<Vec<u8>>>, Error> {
+        let columns = self.columns();
+        let mut rows = Rows::new(Cursor::new(Vec::<u8>::new()), &columns);
+        match *self {
+            Catalog::Tables => {
+                for table in tables {
+                    try!(add_row(
+                        &mut rows,
+                        &[
+                            Lit::String(table.name.clone()),
+                            Lit::String(table.engine_id().name().into()),
+                            Lit::String(table.meta_data.compression.name().into()),
+                            Lit::Bool(table.meta_data.encrypted as u8),
+                            Lit::Int(table.columns().len() as i64),
+                        ]
+                    ));
+                }
+            }
+            Catalog::Columns => {
+                for table in tables {
+                    for (i, column) in table.columns().iter().enumerate() {
+                        try!(add_row(
+                            &mut rows,
+                            &[
+                                Lit::String(table.name.clone()),
+                                Lit::String(column.name.clone()),
+                                Lit::Int(i as i64 + 1),
+                                Lit::String(dump::type_name(&column.sql_type)),
+                                Lit::Bool(column.allow_null as u8),
+                                Lit::Bool(column.is_primary_key as u8),
+                                column
+                                    .default
+                                    .as_ref()
+                                    .map_or(Lit::Null, |d| Lit::String(dump::literal(d))),
+                                Lit::String(column.description.clone()),
+                            ]
+                        ));
+                    }
+                }
+            }
+            Catalog::Indexes => {
+                for table in tables {
+                    for index in table.indexes() {
+                        for (i, column) in index.columns.iter().enumerate() {
+                            try!(add_row(
+                                &mut rows,
+                                &[
+                                    Lit::String(index.name.clone()),
+                                    Lit::String(table.name.clone()),
+                                    Lit::String(column.clone()),
+                                    Lit::Int(i as i64 + 1),
+                                    Lit::Bool(index.unique as u8),
+                                ]
+                            ));
+                        }
+                    }
+                }
+            }
+            Catalog::Users => {
+                for user in users {
+                    try!(add_row(&mut rows, &[Lit::String(user.clone())]));
+                }
+            }
+        }
+        Ok(rows)
+    }
+}
+
+/// Adds a row of literals, `Lit::Null` stands for null.
+pub fn add_row(rows: &mut Rows<Cursor<Vec<u8>>>, values: &[Lit]) -> Result<u64, Error> {
+    let mut encoded = Vec::new();
+    for (column, value) in rows.columns.iter().zip(values) {
+        encoded.push(match value {
+            &Lit::Null => None,
+            value => {
+                let mut data = Vec::new();
+                try!(column.sql_type.encode_into(&mut data, value));
+                Some(data)
+            }
+        });
+    }
+    rows.add_values(&encoded)
+}
+
+#[test]
+fn test_from_name() {
+    assert_eq!(
+        Catalog::from_name("information_schema.tables"),
+        Some(Catalog::Tables)
+    );
+    assert_eq!(
+        Catalog::from_name("INFORMATION_SCHEMA.Columns"),
+        Some(Catalog::Columns)
+    );
+    assert_eq!(Catalog::from_name("information_schema.views"), None);
+    assert_eq!(Catalog::from_name("users"), None);
+    assert_eq!(Catalog::from_name("other.users"), None);
+}
+
+#[test]
+fn test_users_rows() {
+    let users = vec!["admin".to_string(), "peter".to_string()];
+    let mut rows = Catalog::Users.rows(&[], &users).unwrap();
+    rows.reset_pos().unwrap();
+    let mut row = Vec::new();
+    rows.next_row(&mut row).unwrap();
+    assert_eq!(
+        dump::value(&rows.columns[0].sql_type, &rows.get_value(&row, 0).unwrap()).unwrap(),
+        Lit::String("admin".into())
+    );
+}
@@ -0,0 +1,1061 @@
+//! Configuration of the server
+//!
+//! The settings are read from a TOML file, like
+//!
+//! ```toml
+//! address = "0.0.0.0"
+//! port = 4242
+//! dir = "/var/lib/uosql"
+//! log_level = "info"
+//! max_connections = 500
+//! ```
+//!
+//! Every setting is optional, those left out keep the values of
+//! `Config::default`. Some of them can be overridden by the flags of
+//! `uosql-server`, which take precedence over the file. Files ending in
+//! `.json` are read as JSON, like the configurations of older versions.
+//!
+//! The settings are checked once all of them are known, see
+//! `Config::validate`: the server does not start with a setting it cannot
+//! use, and the error names the setting and what is wrong with it.
+//!
+//! On `SIGHUP` or `ADMIN RELOAD` the configuration is read again from the
+//! same file and flags, see `reload`. The settings of `CHANGEABLE` take
+//! effect right away, for connections from then on; the others need a
+//! restart and keep their values until then.
+
+use audit;
+use auth::{self, AuthProvider, Backend, BuiltIn, Ldap, PasswordFile};
+use log::{self, LevelFilter};
+use net::tls::{self, CertRule};
+use net::SendLimits;
+use process::Limits;
+use querylog::{self, Format};
+use serde::Deserialize;
+use serde_json;
+use spill;
+use storage::encrypt::{self, KEY_SIZE};
+use toml;
+
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::{Ipv4Addr, ToSocketAddrs};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Smallest memory budget of sorts and joins
+pub const MIN_MEMORY_BUDGET: usize = 64 * 1024;
+
+/// Environment variable holding the key of encrypted tables in hexadecimal,
+/// instead of `encryption_key_file`
+pub const ENCRYPTION_KEY_VAR: &'static str = "UOSQL_ENCRYPTION_KEY";
+
+/// Settings `reload` changes while the server runs
+pub const CHANGEABLE: &'static [&'static str] = &[
+    "log_level",
+    "max_connections",
+    "max_user_connections",
+    "idle_timeout",
+    "max_response_size",
+    "rows_per_second",
+    "send_timeout",
+    "memory_budget",
+    "shutdown_timeout",
+    "audit_log_max_size",
+    "audit_log_files",
+    "query_log",
+    "query_log_format",
+    "auth_backend",
+    "auth_file",
+    "ldap_server",
+    "ldap_user_dn",
+    "cert_users",
+];
+
+/// The configuration the server runs with and where it comes from
+static RUNNING: Mutex<Option<(Config, Source)>> = Mutex::new(None);
+
+/// A struct for managing configurations
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub address: Ipv4Addr,
+    pub port: u16,
+    pub dir: String,
+    /// Most important level of the messages that are logged
+    pub log_level: LevelFilter,
+    /// File the log is appended to, besides the standard output
+    pub log_file: Option<String>,
+    /// Maximum number of simultaneous connections, `0` means unlimited
+    pub max_connections: usize,
+    /// Number of threads serving the connections, see `pool`
+    pub workers: usize,
+    /// Maximum number of simultaneous connections per user, `0` means
+    /// unlimited
+    pub max_user_connections: usize,
+    /// Minutes after which a connection that sends nothing is closed, `0`
+    /// disables the timeout
+    pub idle_timeout: u64,
+    /// Maximum size of a query result in bytes, `0` means unlimited
+    pub max_response_size: u64,
+    /// Maximum number of result rows sent per second, `0` means unlimited
+    pub rows_per_second: u64,
+    /// Seconds a client has to read a query result, `0` means forever
+    pub send_timeout: u64,
+    /// Database new connections start in
+    pub default_database: Option<String>,
+    /// Port of the MySQL compatibility listener, `None` disables it
+    pub mysql_port: Option<u16>,
+    /// Port of the PostgreSQL compatibility listener, `None` disables it
+    pub pg_port: Option<u16>,
+    /// Port of the HTTP endpoint of the metrics, `None` disables it, see
+    /// `metrics`
+    pub metrics_port: Option<u16>,
+    /// Seconds between looking for tables to compact, `0` disables it
+    pub vacuum_interval: u64,
+    /// Percentage of deleted rows from which a table is compacted
+    pub vacuum_threshold: u8,
+    /// Bytes every sort and hash join may keep in memory before it writes
+    /// rows to temporary files, see `spill`
+    pub memory_budget: usize,
+    /// Seconds running queries may take to finish when the server shuts
+    /// down, see `shutdown`
+    pub shutdown_timeout: u64,
+    /// File the audit log is appended to, relative to the data directory,
+    /// `None` disables it, see `audit`
+    pub audit_log: Option<String>,
+    /// Bytes from which the audit log is rotated, `0` means never
+    pub audit_log_max_size: u64,
+    /// Number of rotated audit log files kept
+    pub audit_log_files: usize,
+    /// File every statement is appended to, relative to the data directory,
+    /// `None` disables it, see `querylog`. Refused together with an
+    /// encryption key, as it is kept in plain text.
+    pub query_log: Option<String>,
+    /// Format of the lines of the query log
+    pub query_log_format: Format,
+    /// File the changes for the followers are logged to, relative to the
+    /// data directory, `None` disables it, see `replication`
+    pub replication_log: Option<String>,
+    /// Address of the leader, as `host:port`, `None` if this server follows
+    /// no leader
+    pub replicate_from: Option<String>,
+    /// Password of the administrator of the leader
+    pub replication_password: Option<String>,
+    /// Where the accounts of the users but the administrator come from,
+    /// see `auth::AuthProvider`
+    pub auth_backend: Backend,
+    /// Password file of the backend `file`, relative to the data directory
+    pub auth_file: Option<String>,
+    /// Address of the directory of the backend `ldap`, as `host:port`
+    pub ldap_server: Option<String>,
+    /// DN of the entry of a user in the directory, with `{user}` in place
+    /// of the name
+    pub ldap_user_dn: Option<String>,
+    /// File holding the key of encrypted tables, relative to the data
+    /// directory, see `storage::encrypt`
+    pub encryption_key_file: Option<String>,
+    /// Certificate and private key for TLS, both in PEM files relative to
+    /// the configuration file
+    pub tls_cert: Option<String>,
+    pub tls_key: Option<String>,
+    /// PEM file of the authorities whose client certificates are accepted,
+    /// like `tls_cert`, see `net::tls`
+    pub tls_ca: Option<String>,
+    /// Rules mapping client certificates to users, the first matching one
+    /// counts
+    pub cert_users: Vec<CertRule>,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            address: Ipv4Addr::new(127, 0, 0, 1),
+            port: 4242,
+            dir: "data".into(),
+            log_level: LevelFilter::Info,
+            log_file: Some("log.txt".into()),
+            max_connections: 100,
+            workers: 8,
+            max_user_connections: 0,
+            idle_timeout: 30,
+            max_response_size: 64 * 1024 * 1024,
+            rows_per_second: 0,
+            send_timeout: 60,
+            default_database: None,
+            mysql_port: None,
+            pg_port: None,
+            metrics_port: None,
+            vacuum_interval: 60,
+            vacuum_threshold: 20,
+            memory_budget: spill::DEFAULT_MEMORY_BUDGET,
+            shutdown_timeout: 30,
+            audit_log: None,
+            audit_log_max_size: 16 * 1024 * 1024,
+            audit_log_files: 10,
+            query_log: None,
+            query_log_format: Format::Text,
+            replication_log: None,
+            replicate_from: None,
+            replication_password: None,
+            auth_backend: Backend::BuiltIn,
+            auth_file: None,
+            ldap_server: None,
+            ldap_user_dn: None,
+            encryption_key_file: None,
+            tls_cert: None,
+            tls_key: None,
+            tls_ca: None,
+            cert_users: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// The file cannot be read
+    Io(String, io::Error),
+    /// The file is no TOML or JSON, or has an unknown setting or one of the
+    /// wrong type, holds the file and the message of the parser
+    Syntax(String, String),
+    /// A setting cannot be used, holds its name and why
+    Invalid(&'static str, String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &Error::Io(ref file, ref e) => write!(f, "cannot read '{}': {}", file, e),
+            &Error::Syntax(ref file, ref message) => write!(f, "in '{}': {}", file, message),
+            &Error::Invalid(setting, ref why) => write!(f, "setting '{}' {}", setting, why),
+        }
+    }
+}
+
+/// Where the configuration comes from: a file, if any, and the flags
+/// overriding its settings
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Source {
+    pub file: Option<String>,
+    pub flags: Vec<(&'static str, String)>,
+}
+
+impl Source {
+    /// Reads and validates the configuration.
+    pub fn read(&self) -> Result<Config, Error> {
+        let mut config = match self.file {
+            Some(ref file) => try!(Config::load(file)),
+            None => Config::default(),
+        };
+        for &(setting, ref value) in &self.flags {
+            try!(config.set(setting, value));
+        }
+        try!(config.validate());
+        Ok(config)
+    }
+}
+
+/// The settings a `reload` found changed
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Reload {
+    /// Settings that took effect
+    pub applied: Vec<&'static str>,
+    /// Settings that take effect after a restart
+    pub restart: Vec<&'static str>,
+}
+
+/// Reads the configuration the server starts with, which `reload` reads
+/// again from the same source.
+pub fn init(source: Source) -> Result<Config, Error> {
+    let config = try!(source.read());
+    *RUNNING.lock().unwrap() = Some((config.clone(), source));
+    Ok(config)
+}
+
+/// Returns the configuration the server runs with, the default one before
+/// `init`.
+pub fn current() -> Config {
+    match *RUNNING.lock().unwrap() {
+        Some((ref config, _)) => config.clone(),
+        None => Config::default(),
+    }
+}
+
+/// Reads the configuration again and applies the changeable settings. If
+/// the configuration is not valid, nothing changes.
+pub fn reload() -> Result<Reload, Error> {
+    let mut running = RUNNING.lock().unwrap();
+    let (mut config, source) = running.clone().unwrap_or_default();
+    let new = try!(source.read());
+    let (applied, restart) = config
+        .changes(&new)
+        .into_iter()
+        .partition(|setting| CHANGEABLE.contains(setting));
+    let reload = Reload {
+        applied: applied,
+        restart: restart,
+    };
+    config.log_level = new.log_level;
+    config.max_connections = new.max_connections;
+    config.max_user_connections = new.max_user_connections;
+    config.idle_timeout = new.idle_timeout;
+    config.max_response_size = new.max_response_size;
+    config.rows_per_second = new.rows_per_second;
+    config.send_timeout = new.send_timeout;
+    config.memory_budget = new.memory_budget;
+    config.shutdown_timeout = new.shutdown_timeout;
+    config.audit_log_max_size = new.audit_log_max_size;
+    config.audit_log_files = new.audit_log_files;
+    config.query_log = new.query_log;
+    config.query_log_format = new.query_log_format;
+    config.auth_backend = new.auth_backend;
+    config.auth_file = new.auth_file;
+    config.ldap_server = new.ldap_server;
+    config.ldap_user_dn = new.ldap_user_dn;
+    config.cert_users = new.cert_users;
+    config.apply();
+    *running = Some((config, source));
+
+    if reload.applied.is_empty() && reload.restart.is_empty() {
+        info!("Configuration reloaded, nothing changed");
+    }
+    if !reload.applied.is_empty() {
+        info!(
+            "Configuration reloaded, changed: {}",
+            reload.applied.join(", ")
+        );
+    }
+    if !reload.restart.is_empty() {
+        warn!(
+            "Configuration reloaded, changes take effect after a restart: {}",
+            reload.restart.join(", ")
+        );
+    }
+    Ok(reload)
+}
+
+/// The settings of a file, all of them optional
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct File {
+    address: Option<String>,
+    port: Option<u16>,
+    dir: Option<String>,
+    log_level: Option<String>,
+    log_file: Option<String>,
+    max_connections: Option<usize>,
+    workers: Option<usize>,
+    max_user_connections: Option<usize>,
+    idle_timeout: Option<u64>,
+    max_response_size: Option<u64>,
+    rows_per_second: Option<u64>,
+    send_timeout: Option<u64>,
+    default_database: Option<String>,
+    mysql_port: Option<u16>,
+    pg_port: Option<u16>,
+    metrics_port: Option<u16>,
+    vacuum_interval: Option<u64>,
+    vacuum_threshold: Option<u8>,
+    memory_budget: Option<usize>,
+    shutdown_timeout: Option<u64>,
+    audit_log: Option<String>,
+    audit_log_max_size: Option<u64>,
+    audit_log_files: Option<usize>,
+    query_log: Option<String>,
+    query_log_format: Option<String>,
+    replication_log: Option<String>,
+    replicate_from: Option<String>,
+    replication_password: Option<String>,
+    auth_backend: Option<String>,
+    auth_file: Option<String>,
+    ldap_server: Option<String>,
+    ldap_user_dn: Option<String>,
+    encryption_key_file: Option<String>,
+    tls_cert: Option<String>,
+    tls_key: Option<String>,
+    tls_ca: Option<String>,
+    cert_users: Option<Vec<String>>,
+}
+
+impl Config {
+    /// Reads the settings of a file on top of the default ones. They are
+    /// not validated yet, as flags may still override them.
+    pub fn load(path: &str) -> Result<Config, Error> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) => return Err(Error::Io(path.into(), e)),
+        };
+        let file = if path.ends_with(".json") {
+            serde_json::from_str(&text).map_err(|e| e.to_string())
+        } else {
+            toml::from_str(&text).map_err(|e| e.to_string())
+        };
+        let mut config = match file {
+            Ok(file) => try!(Config::default().with_file(file)),
+            Err(message) => return Err(Error::Syntax(path.into(), message)),
+        };
+        // the file is read again after the server changed into its data
+        // directory
+        let dir = Path::new(path).parent().unwrap_or(Path::new(""));
+        for file in &mut [
+            &mut config.tls_cert,
+            &mut config.tls_key,
+            &mut config.tls_ca,
+        ] {
+            if let Some(ref mut file) = **file {
+                *file = dir.join(&file).to_string_lossy().into_owned();
+            }
+        }
+        Ok(config)
+    }
+
+    /// Parses the settings of the text of a TOML file on top of the default
+    /// ones, like `load`.
+    pub fn from_toml(text: &str) -> Result<Config, Error> {
+        match toml::from_str(text) {
+            Ok(file) => Config::default().with_file(file),
+            Err(e) => Err(Error::Syntax("<text>".into(), e.to_string())),
+        }
+    }
+
+    fn with_file(mut self, file: File) -> Result<Config, Error> {
+        if let Some(address) = file.address {
+            self.address = try!(parse_address(&address));
+        }
+        if let Some(level) = file.log_level {
+            self.log_level = try!(parse_log_level(&level));
+        }
+        if let Some(format) = file.query_log_format {
+            self.query_log_format = try!(parse_query_log_format(&format));
+        }
+        if let Some(backend) = file.auth_backend {
+            self.auth_backend = try!(parse_auth_backend(&backend));
+        }
+        if let Some(rules) = file.cert_users {
+            self.cert_users = try!(rules
+                .iter()
+                .map(|rule| rule.parse().map_err(|e| Error::Invalid("cert_users", e)))
+                .collect());
+        }
+        self.port = file.port.unwrap_or(self.port);
+        self.dir = file.dir.unwrap_or(self.dir);
+        self.log_file = file.log_file.or(self.log_file);
+        self.max_connections = file.max_connections.unwrap_or(self.max_connections);
+        self.workers = file.workers.unwrap_or(self.workers);
+        self.max_user_connections = file
+            .max_user_connections
+            .unwrap_or(self.max_user_connections);
+        self.idle_timeout = file.idle_timeout.unwrap_or(self.idle_timeout);
+        self.max_response_size = file.max_response_size.unwrap_or(self.max_response_size);
+        self.rows_per_second = file.rows_per_second.unwrap_or(self.rows_per_second);
+        self.send_timeout = file.send_timeout.unwrap_or(self.send_timeout);
+        self.default_database = file.default_database.or(self.default_database);
+        self.mysql_port = file.mysql_port.or(self.mysql_port);
+        self.pg_port = file.pg_port.or(self.pg_port);
+        self.metrics_port = file.metrics_port.or(self.metrics_port);
+        self.vacuum_interval = file.vacuum_interval.unwrap_or(self.vacuum_interval);
+        self.vacuum_threshold = file.vacuum_threshold.unwrap_or(self.vacuum_threshold);
+        self.memory_budget = file.memory_budget.unwrap_or(self.memory_budget);
+        self.shutdown_timeout = file.shutdown_timeout.unwrap_or(self.shutdown_timeout);
+        self.audit_log = file.audit_log.or(self.audit_log);
+        self.audit_log_max_size = file.audit_log_max_size.unwrap_or(self.audit_log_max_size);
+        self.audit_log_files = file.audit_log_files.unwrap_or(self.audit_log_files);
+        self.query_log = file.query_log.or(self.query_log);
+        self.replication_log = file.replication_log.or(self.replication_log);
+        self.replicate_from = file.replicate_from.or(self.replicate_from);
+        self.replication_password = file.replication_password.or(self.replication_password);
+        self.auth_file = file.auth_file.or(self.auth_file);
+        self.ldap_server = file.ldap_server.or(self.ldap_server);
+        self.ldap_user_dn = file.ldap_user_dn.or(self.ldap_user_dn);
+        self.encryption_key_file = file.encryption_key_file.or(self.encryption_key_file);
+        self.tls_cert = file.tls_cert.or(self.tls_cert);
+        self.tls_key = file.tls_key.or(self.tls_key);
+        self.tls_ca = file.tls_ca.or(self.tls_ca);
+        Ok(self)
+    }
+
+    /// Overrides a setting by the value of a flag, given as text.
+    pub fn set(&mut self, setting: &'static str, value: &str) -> Result<(), Error> {
+        match setting {
+            "address" => self.address = try!(parse_address(value)),
+            "port" => self.port = try!(parse_number(setting, value)),
+            "dir" => self.dir = value.into(),
+            "log_level" => self.log_level = try!(parse_log_level(value)),
+            "log_file" => self.log_file = Some(value.into()),
+            "max_connections" => self.max_connections = try!(parse_number(setting, value)),
+            "workers" => self.workers = try!(parse_number(setting, value)),
+            "memory_budget" => self.memory_budget = try!(parse_number(setting, value)),
+            "audit_log" => self.audit_log = Some(value.into()),
+            "query_log" => self.query_log = Some(value.into()),
+            "query_log_format" => self.query_log_format = try!(parse_query_log_format(value)),
+            "replication_log" => self.replication_log = Some(value.into()),
+            "replicate_from" => self.replicate_from = Some(value.into()),
+            "tls_cert" => self.tls_cert = Some(value.into()),
+            "tls_key" => self.tls_key = Some(value.into()),
+            "tls_ca" => self.tls_ca = Some(value.into()),
+            _ => return Err(Error::Invalid(setting, "cannot be set by a flag".into())),
+        }
+        Ok(())
+    }
+
+    /// Puts the settings into effect that are kept by other modules: the
+    /// log level, the memory budget, the rotation of the audit log, the
+    /// query log and the provider of the accounts.
+    pub fn apply(&self) {
+        log::set_max_level(self.log_level);
+        spill::set_memory_budget(self.memory_budget);
+        audit::set_rotation(self.audit_log_max_size, self.audit_log_files);
+        let query_log = self.query_log.as_ref().map(|file| &file[..]);
+        if let Err(e) = querylog::configure(query_log, self.query_log_format) {
+            error!("Cannot open query log '{}': {}", query_log.unwrap_or(""), e);
+        }
+        auth::set_provider(self.auth_provider());
+    }
+
+    /// Returns the provider of the accounts of the backend, whose settings
+    /// `validate` checked.
+    pub fn auth_provider(&self) -> Arc<dyn AuthProvider> {
+        let setting = |value: &Option<String>| value.clone().unwrap_or_default();
+        match self.auth_backend {
+            Backend::BuiltIn => Arc::new(BuiltIn),
+            Backend::File => Arc::new(PasswordFile::new(&setting(&self.auth_file))),
+            Backend::Ldap => Arc::new(Ldap::new(
+                &setting(&self.ldap_server),
+                &setting(&self.ldap_user_dn),
+            )),
+        }
+    }
+
+    /// Returns the key of encrypted tables, from the environment variable
+    /// `ENCRYPTION_KEY_VAR` or the key file, None if there is neither.
+    pub fn encryption_key(&self) -> Result<Option<[u8; KEY_SIZE]>, Error> {
+        let var = env::var_os(ENCRYPTION_KEY_VAR);
+        let (setting, text): (&'static str, Vec<u8>) = match (var, &self.encryption_key_file) {
+            (None, &None) => return Ok(None),
+            (Some(_), &Some(_)) => {
+                return Err(Error::Invalid(
+                    "encryption_key_file",
+                    format!("must not be set together with {}", ENCRYPTION_KEY_VAR),
+                ))
+            }
+            (Some(var), &None) => (
+                ENCRYPTION_KEY_VAR,
+                var.to_string_lossy().as_bytes().to_vec(),
+            ),
+            (None, &Some(ref file)) => match fs::read(file) {
+                Ok(text) => ("encryption_key_file", text),
+                Err(e) => return Err(Error::Io(file.clone(), e)),
+            },
+        };
+        match encrypt::parse_key(&text) {
+            Some(key) => Ok(Some(key)),
+            None => Err(Error::Invalid(
+                setting,
+                format!("must hold 64 hexadecimal digits or {} bytes", KEY_SIZE),
+            )),
+        }
+    }
+
+    /// Returns the connection limits of the configuration.
+    pub fn limits(&self) -> Limits {
+        Limits {
+            max_connections: self.max_connections,
+            max_user_connections: self.max_user_connections,
+        }
+    }
+
+    /// Returns the time after which an idle connection is closed, if any.
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        match self.idle_timeout {
+            0 => None,
+            minutes => Some(Duration::from_secs(minutes * 60)),
+        }
+    }
+
+    /// Returns the limits for sending query results.
+    pub fn send_limits(&self) -> SendLimits {
+        SendLimits {
+            max_response_size: self.max_response_size,
+            rows_per_second: self.rows_per_second,
+            send_timeout: match self.send_timeout {
+                0 => None,
+                seconds => Some(Duration::from_secs(seconds)),
+            },
+        }
+    }
+
+    /// Returns the time running queries get to finish on shutdown.
+    pub fn shutdown_timeout(&self) -> Duration {
+        Duration::from_secs(self.shutdown_timeout)
+    }
+
+    /// Returns the names of the settings that differ in another
+    /// configuration.
+    pub fn changes(&self, other: &Config) -> Vec<&'static str> {
+        let differs = [
+            ("address", self.address != other.address),
+            ("port", self.port != other.port),
+            ("dir", self.dir != other.dir),
+            ("log_level", self.log_level != other.log_level),
+            ("log_file", self.log_file != other.log_file),
+            (
+                "max_connections",
+                self.max_connections != other.max_connections,
+            ),
+            (
+                "max_user_connections",
+                self.max_user_connections != other.max_user_connections,
+            ),
+            ("workers", self.workers != other.workers),
+            ("idle_timeout", self.idle_timeout != other.idle_timeout),
+            (
+                "max_response_size",
+                self.max_response_size != other.max_response_size,
+            ),
+            (
+                "rows_per_second",
+                self.rows_per_second != other.rows_per_second,
+            ),
+            ("send_timeout", self.send_timeout != other.send_timeout),
+            (
+                "default_database",
+                self.default_database != other.default_database,
+            ),
+            ("mysql_port", self.mysql_port != other.mysql_port),
+            ("pg_port", self.pg_port != other.pg_port),
+            ("metrics_port", self.metrics_port != other.metrics_port),
+            (
+                "vacuum_interval",
+                self.vacuum_interval != other.vacuum_interval,
+            ),
+            (
+                "vacuum_threshold",
+                self.vacuum_threshold != other.vacuum_threshold,
+            ),
+            ("memory_budget", self.memory_budget != other.memory_budget),
+            (
+                "shutdown_timeout",
+                self.shutdown_timeout != other.shutdown_timeout,
+            ),
+            ("audit_log", self.audit_log != other.audit_log),
+            (
+                "audit_log_max_size",
+                self.audit_log_max_size != other.audit_log_max_size,
+            ),
+            (
+                "audit_log_files",
+                self.audit_log_files != other.audit_log_files,
+            ),
+            ("query_log", self.query_log != other.query_log),
+            (
+                "query_log_format",
+                self.query_log_format != other.query_log_format,
+            ),
+            (
+                "replication_log",
+                self.replication_log != other.replication_log,
+            ),
+            (
+                "replicate_from",
+                self.replicate_from != other.replicate_from,
+            ),
+            (
+                "replication_password",
+                self.replication_password != other.replication_password,
+            ),
+            ("auth_backend", self.auth_backend != other.auth_backend),
+            ("auth_file", self.auth_file != other.auth_file),
+            ("ldap_server", self.ldap_server != other.ldap_server),
+            ("ldap_user_dn", self.ldap_user_dn != other.ldap_user_dn),
+            (
+                "encryption_key_file",
+                self.encryption_key_file != other.encryption_key_file,
+            ),
+            ("tls_cert", self.tls_cert != other.tls_cert),
+            ("tls_key", self.tls_key != other.tls_key),
+            ("tls_ca", self.tls_ca != other.tls_ca),
+            ("cert_users", self.cert_users != other.cert_users),
+        ];
+        differs
+            .iter()
+            .filter(|&&(_, differs)| differs)
+            .map(|&(setting, _)| setting)
+            .collect()
+    }
+
+    /// Checks that the server can use the settings.
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.port == 0 {
+            return Err(Error::Invalid("port", "must not be 0".into()));
+        }
+        let ports = [
+            ("mysql_port", self.mysql_port, "MySQL listener"),
+            ("pg_port", self.pg_port, "PostgreSQL listener"),
+            ("metrics_port", self.metrics_port, "metrics endpoint"),
+        ];
+        for (i, &(setting, port, _)) in ports.iter().enumerate() {
+            if port == Some(0) {
+                return Err(Error::Invalid(setting, "must not be 0".into()));
+            }
+            if port == Some(self.port) {
+                return Err(Error::Invalid(
+                    setting,
+                    format!("is the port of the server already: {}", self.port),
+                ));
+            }
+            let taken = ports[..i]
+                .iter()
+                .find(|other| port.is_some() && other.1 == port);
+            if let Some(&(_, _, user)) = taken {
+                return Err(Error::Invalid(
+                    setting,
+                    format!("is the port of the {} already", user),
+                ));
+            }
+        }
+        if self.workers == 0 {
+            return Err(Error::Invalid("workers", "must not be 0".into()));
+        }
+        if self.dir.is_empty() {
+            return Err(Error::Invalid("dir", "must name a directory".into()));
+        }
+        for &(setting, file) in &[
+            ("audit_log", &self.audit_log),
+            ("query_log", &self.query_log),
+            ("replication_log", &self.replication_log),
+            ("encryption_key_file", &self.encryption_key_file),
+        ] {
+            if file.as_ref().map_or(false, |file| file.is_empty()) {
+                return Err(Error::Invalid(setting, "must name a file".into()));
+            }
+        }
+        let encrypted =
+            self.encryption_key_file.is_some() || env::var_os(ENCRYPTION_KEY_VAR).is_some();
+        if encrypted && self.query_log.is_some() {
+            return Err(Error::Invalid(
+                "query_log",
+                "must not be set together with an encryption key, it would keep the \
+                 statements of encrypted tables in plain text"
+                    .into(),
+            ));
+        }
+        if let Some(ref leader) = self.replicate_from {
+            if leader.to_socket_addrs().is_err() {
+                return Err(Error::Invalid(
+                    "replicate_from",
+                    format!("is no address of a server: '{}'", leader),
+                ));
+            }
+            if self.replication_password.is_none() {
+                return Err(Error::Invalid(
+                    "replication_password",
+                    "is missing for replicate_from".into(),
+                ));
+            }
+        }
+        match self.auth_backend {
+            Backend::BuiltIn => (),
+            Backend::File => {
+                if self.auth_file.as_ref().map_or(true, |file| file.is_empty()) {
+                    return Err(Error::Invalid(
+                        "auth_file",
+                        "must name a file for auth_backend \"file\"".into(),
+                    ));
+                }
+            }
+            Backend::Ldap => {
+                match self.ldap_server {
+                    None => {
+                        return Err(Error::Invalid(
+                            "ldap_server",
+                            "is missing for auth_backend \"ldap\"".into(),
+                        ))
+                    }
+                    Some(ref server) if server.to_socket_addrs().is_err() => {
+                        return Err(Error::Invalid(
+                            "ldap_server",
+                            format!("is no address of a server: '{}'", server),
+                        ))
+                    }
+                    Some(_) => (),
+                }
+                if !self
+                    .ldap_user_dn
+                    .as_ref()
+                    .map_or(false, |dn| dn.contains("{user}"))
+                {
+                    return Err(Error::Invalid(
+                        "ldap_user_dn",
+                        "must be a DN with {user} for auth_backend \"ldap\"".into(),
+                    ));
+                }
+            }
+        }
+        if self.vacuum_threshold > 100 {
+            return Err(Error::Invalid(
+                "vacuum_threshold",
+                format!("is a percentage, not {}", self.vacuum_threshold),
+            ));
+        }
+        if self.memory_budget < MIN_MEMORY_BUDGET {
+            return Err(Error::Invalid(
+                "memory_budget",
+                format!("must be at least {} bytes", MIN_MEMORY_BUDGET),
+            ));
+        }
+        if !self.cert_users.is_empty() && self.tls_ca.is_none() {
+            return Err(Error::Invalid(
+                "cert_users",
+                "needs tls_ca to verify the certificates".into(),
+            ));
+        }
+        match (&self.tls_cert, &self.tls_key) {
+            (&None, &None) if self.tls_ca.is_some() => Err(Error::Invalid(
+                "tls_ca",
+                "needs tls_cert and tls_key".into(),
+            )),
+            (&None, &None) => Ok(()),
+            (&Some(_), &None) => Err(Error::Invalid("tls_key", "is missing for tls_cert".into())),
+            (&None, &Some(_)) => Err(Error::Invalid("tls_cert", "is missing for tls_key".into())),
+            (&Some(ref cert), &Some(ref key)) => {
+                let files = [
+                    ("tls_cert", Some(cert)),
+                    ("tls_key", Some(key)),
+                    ("tls_ca", self.tls_ca.as_ref()),
+                ];
+                for &(setting, file) in &files {
+                    match file {
+                        Some(file) if !Path::new(file).is_file() => {
+                            return Err(Error::Invalid(
+                                setting,
+                                format!("names no file: '{}'", file),
+                            ))
+                        }
+                        _ => {}
+                    }
+                }
+                let ca = self.tls_ca.as_ref().map(|ca| &ca[..]);
+                match tls::acceptor(cert, key, ca) {
+                    Ok(_) => Ok(()),
+                    Err(e) => Err(Error::Invalid(
+                        "tls_cert",
+                        format!("cannot be used with tls_key and tls_ca: {}", e),
+                    )),
+                }
+            }
+        }
+    }
+}
+
+fn parse_address(address: &str) -> Result<Ipv4Addr, Error> {
+    Ipv4Addr::from_str(address)
+        .map_err(|_| Error::Invalid("address", format!("is no IPv4 address: '{}'", address)))
+}
+
+fn parse_number<T: FromStr>(setting: &'static str, value: &str) -> Result<T, Error> {
+    value
+        .parse()
+        .map_err(|_| Error::Invalid(setting, format!("is no valid number: '{}'", value)))
+}
+
+fn parse_log_level(level: &str) -> Result<LevelFilter, Error> {
+    LevelFilter::from_str(level).map_err(|_| {
+        Error::Invalid(
+            "log_level",
+            format!(
+                "is none of off, error, warn, info, debug and trace: '{}'",
+                level
+            ),
+        )
+    })
+}
+
+fn parse_auth_backend(backend: &str) -> Result<Backend, Error> {
+    Backend::from_name(backend).ok_or_else(|| {
+        Error::Invalid(
+            "auth_backend",
+            format!("is none of builtin, file and ldap: '{}'", backend),
+        )
+    })
+}
+
+fn parse_query_log_format(format: &str) -> Result<Format, Error> {
+    Format::from_name(format).ok_or_else(|| {
+        Error::Invalid(
+            "query_log_format",
+            format!("is neither text nor json: '{}'", format),
+        )
+    })
+}
+
+#[test]
+fn test_config() {
+    let config =
+        Config::from_toml("port = 4300\nlog_level = \"debug\"\nmysql_port = 3306").unwrap();
+    assert_eq!(config.port, 4300);
+    assert_eq!(config.log_level, LevelFilter::Debug);
+    assert_eq!(config.mysql_port, Some(3306));
+    // settings left out keep their defaults
+    assert_eq!(config.dir, "data");
+    assert!(config.validate().is_ok());
+
+    assert!(Config::from_toml("prot = 4300").is_err());
+    assert!(Config::from_toml("port = \"4300\"").is_err());
+    match Config::from_toml("log_level = \"loud\"") {
+        Err(Error::Invalid("log_level", _)) => (),
+        other => panic!("{:?}", other),
+    }
+    let config = Config::from_toml("query_log_format = \"json\"").unwrap();
+    assert_eq!(config.query_log_format, Format::Json);
+    match Config::from_toml("query_log_format = \"xml\"") {
+        Err(Error::Invalid("query_log_format", _)) => (),
+        other => panic!("{:?}", other),
+    }
+
+    let mut config = Config::default();
+    assert!(config.set("port", "70000").is_err());
+    config.set("port", "3306").unwrap();
+    config.mysql_port = Some(3306);
+    match config.validate() {
+        Err(Error::Invalid("mysql_port", _)) => (),
+        other => panic!("{:?}", other),
+    }
+    let mut config = Config::from_toml("pg_port = 5432\nmetrics_port = 5432").unwrap();
+    match config.validate() {
+        Err(Error::Invalid("metrics_port", _)) => (),
+        other => panic!("{:?}", other),
+    }
+    config.metrics_port = Some(9100);
+    assert!(config.validate().is_ok());
+    let mut config = Config::default();
+    config.set("workers", "0").unwrap();
+    match config.validate() {
+        Err(Error::Invalid("workers", _)) => (),
+        other => panic!("{:?}", other),
+    }
+    let mut config = Config::default();
+    config.set("audit_log", "").unwrap();
+    match config.validate() {
+        Err(Error::Invalid("audit_log", _)) => (),
+        other => panic!("{:?}", other),
+    }
+    let mut config = Config::default();
+    config.set("query_log", "q.log").unwrap();
+    assert!(config.validate().is_ok());
+    config.encryption_key_file = Some("key".into());
+    match config.validate() {
+        Err(Error::Invalid("query_log", _)) => (),
+        other => panic!("{:?}", other),
+    }
+    let mut config = Config::default();
+    config.set("replicate_from", "leader").unwrap();
+    match config.validate() {
+        Err(Error::Invalid("replicate_from", _)) => (),
+        other => panic!("{:?}", other),
+    }
+    config.set("replicate_from", "127.0.0.1:4242").unwrap();
+    match config.validate() {
+        Err(Error::Invalid("replication_password", _)) => (),
+        other => panic!("{:?}", other),
+    }
+    config.replication_password = Some("secret".into());
+    assert!(config.validate().is_ok());
+    match Config::from_toml("auth_backend = \"pam\"") {
+        Err(Error::Invalid("auth_backend", _)) => (),
+        other => panic!("{:?}", other),
+    }
+    let mut config = Config::from_toml("auth_backend = \"file\"").unwrap();
+    match config.validate() {
+        Err(Error::Invalid("auth_file", _)) => (),
+        other => panic!("{:?}", other),
+    }
+    config.auth_file = Some("users.htpasswd".into());
+    assert!(config.validate().is_ok());
+    let mut config =
+        Config::from_toml("auth_backend = \"ldap\"\nldap_server = \"127.0.0.1:389\"").unwrap();
+    assert_eq!(config.auth_backend, Backend::Ldap);
+    config.ldap_user_dn = Some("cn=admin,dc=shop".into());
+    match config.validate() {
+        Err(Error::Invalid("ldap_user_dn", _)) => (),
+        other => panic!("{:?}", other),
+    }
+    config.ldap_user_dn = Some("uid={user},dc=shop".into());
+    assert!(config.validate().is_ok());
+    let mut config = Config::default();
+    assert_eq!(config.encryption_key().unwrap(), None);
+    let file = env::temp_dir().join("uosql-test-table.key");
+    fs::write(&file, "0123456789abcdef".repeat(4) + "\n").unwrap();
+    config.encryption_key_file = Some(file.to_string_lossy().into_owned());
+    assert!(config.validate().is_ok());
+    assert_eq!(config.encryption_key().unwrap().unwrap()[..2], [0x01, 0x23]);
+    fs::write(&file, "secret").unwrap();
+    match config.encryption_key() {
+        Err(Error::Invalid("encryption_key_file", _)) => (),
+        other => panic!("{:?}", other),
+    }
+    let _ = fs::remove_file(&file);
+    let mut config = Config::default();
+    config.set("tls_key", "server.key").unwrap();
+    match config.validate() {
+        Err(Error::Invalid("tls_cert", _)) => (),
+        other => panic!("{:?}", other),
+    }
+    config.set("tls_cert", "server.pem").unwrap();
+    match config.validate() {
+        Err(Error::Invalid("tls_cert", _)) => (),
+        other => panic!("{:?}", other),
+    }
+    let config = Config::from_toml("tls_ca = \"ca.pem\"").unwrap();
+    match config.validate() {
+        Err(Error::Invalid("tls_ca", _)) => (),
+        other => panic!("{:?}", other),
+    }
+    let config = Config::from_toml("cert_users = [\"CN=*:*\", \"SAN=*.ops:ops\"]").unwrap();
+    assert_eq!(config.cert_users[1].to_string(), "SAN=*.ops:ops");
+    match config.validate() {
+        Err(Error::Invalid("cert_users", _)) => (),
+        other => panic!("{:?}", other),
+    }
+    match Config::from_toml("cert_users = [\"OU=ops:ops\"]") {
+        Err(Error::Invalid("cert_users", _)) => (),
+        other => panic!("{:?}", other),
+    }
+}
+
+#[test]
+fn test_tls_files_relative_to_config() {
+    let dir = env::temp_dir().join(format!("uosql-test-config-{}", ::std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("uosql.toml");
+    fs::write(
+        &file,
+        "tls_cert = \"server.pem\"\ntls_key = \"/etc/uosql/server.key\"\n",
+    )
+    .unwrap();
+    let config = Config::load(&file.to_string_lossy()).unwrap();
+    let _ = fs::remove_dir_all(&dir);
+    assert_eq!(
+        config.tls_cert,
+        Some(dir.join("server.pem").to_string_lossy().into_owned())
+    );
+    assert_eq!(config.tls_key, Some("/etc/uosql/server.key".into()));
+}
+
+#[test]
+fn test_config_changes() {
+    let old = Config::default();
+    let new = Config::from_toml("port = 4300\nlog_level = \"warn\"\nidle_timeout = 5").unwrap();
+    assert_eq!(old.changes(&new), vec!["port", "log_level", "idle_timeout"]);
+    assert!(old.changes(&old.clone()).is_empty());
+    assert!(!CHANGEABLE.contains(&"port"));
+    assert_eq!(new.idle_timeout(), Some(Duration::from_secs(5 * 60)));
+
+    let source = Source {
+        file: None,
+        flags: vec![("max_connections", "7".into())],
+    };
+    assert_eq!(source.read().unwrap().max_connections, 7);
+}
@@ -1,16 +1,101 @@
 //! Contains the entry point code for handling an incoming connection.
 //!
 use super::query;
+use audit;
 use auth;
+use cache;
+use dump;
+use metrics;
 use net;
+use net::tls;
 use net::types::*;
+use net::Stream;
 use parse;
+use parse::ast::Query;
+use parse::parser::ParseError;
+use parse::token::Lit;
+use process;
+use query::ExecutionError;
+use querylog;
+use replication;
+use std::cmp;
+use std::collections::HashMap;
 use std::error::Error;
+use std::io::Cursor;
 use std::net::TcpStream;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+use storage;
 use storage::types::{Column, SqlType};
-use storage::ResultSet;
+use storage::{ResultSet, Rows};
 
-pub fn handle(mut stream: TcpStream) {
+/// Time a new connection has to send its login or session token
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A logged in connection of the native protocol between two commands
+pub struct Session {
+    stream: Stream,
+    user: auth::User,
+    process: process::Handle,
+    negotiation: Negotiation,
+    idle_timeout: Option<Duration>,
+    send_limits: net::SendLimits,
+    /// Whether the client may prepare statements
+    prepare: bool,
+    /// Statements prepared by the client, by their ids
+    prepared: HashMap<u64, Prepared>,
+    next_statement: u64,
+    /// End of the last command
+    idle_since: Instant,
+}
+
+/// A statement prepared by `Command::Prepare`
+struct Prepared {
+    text: String,
+    queries: Vec<Query>,
+    /// The generation of the cache it was parsed in
+    generation: u64,
+}
+
+/// Handles one client connection until the client quits, the connection is
+/// killed, the client sends nothing for `idle_timeout` or does not read its
+/// results within the time given by `send_limits`. A new session starts in
+/// `default_database` if the user may access it.
+pub fn handle(
+    stream: TcpStream,
+    limits: process::Limits,
+    idle_timeout: Option<Duration>,
+    send_limits: net::SendLimits,
+    default_database: Option<&str>,
+) {
+    let mut session = open(stream, limits, idle_timeout, send_limits, default_database);
+    while let Some(s) = session {
+        session = s.serve();
+    }
+}
+
+/// Performs the handshake of a new connection and logs the user in, like
+/// `handle`. Returns the session, unless the connection was closed.
+pub fn open(
+    mut stream: TcpStream,
+    limits: process::Limits,
+    idle_timeout: Option<Duration>,
+    send_limits: net::SendLimits,
+    default_database: Option<&str>,
+) -> Option<Session> {
+    if !greet(&mut stream, &send_limits, default_database) {
+        return None;
+    }
+    login(stream, limits, idle_timeout, send_limits, default_database)
+}
+
+/// Sends the greeting to a new connection, the first half of `open`.
+/// Returns false if the connection was closed.
+pub fn greet(
+    stream: &mut TcpStream,
+    send_limits: &net::SendLimits,
+    default_database: Option<&str>,
+) -> bool {
     // Logging about the new connection
     let addr = stream
         .peer_addr()
@@ -18,105 +103,837 @@ pub fn handle(mut stream: TcpStream) {
         .unwrap_or("???".into());
     info!("Handling connection from {}", addr);
 
-    // Perform handshake, check user login.
-    let res = net::do_handshake(&mut stream);
+    // A single blocked write must not outlast the whole send timeout
+    if let Err(e) = stream.set_write_timeout(send_limits.send_timeout) {
+        warn!("Cannot set send timeout: {:?}", e);
+    }
+    match net::send_greeting(stream, default_database) {
+        Ok(_) => true,
+        Err(e) => {
+            warn!("Failed to greet {}: {}", addr, e.description());
+            false
+        }
+    }
+}
 
-    let mut user;
-    match res {
-        Ok((name, pw)) => {
-            info!("Connection established. Handshake sent");
-            user = match auth::find_user(&name, &pw) {
-                Ok(u) => match net::send_info_package(&mut stream, PkgType::AccGranted) {
-                    Ok(_) => u,
-                    Err(e) => {
-                        error!("{}", e.description());
-                        return;
+/// Reads the login or session token of a greeted connection and logs the
+/// user in, the second half of `open`. Returns the session, unless the
+/// connection was closed.
+pub fn login(
+    mut stream: TcpStream,
+    limits: process::Limits,
+    idle_timeout: Option<Duration>,
+    send_limits: net::SendLimits,
+    default_database: Option<&str>,
+) -> Option<Session> {
+    let addr = stream
+        .peer_addr()
+        .map(|a| a.to_string())
+        .unwrap_or("???".into());
+
+    // A client that does not log in must not hold its worker for the whole
+    // idle timeout
+    if let Err(e) = stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT)) {
+        warn!("Cannot set handshake timeout: {:?}", e);
+    }
+    let mut res = net::read_hello(&mut stream, false);
+    let starts_tls = match res {
+        Ok(net::Hello::StartTls) => true,
+        _ => false,
+    };
+    // The login follows encrypted
+    let mut stream = if starts_tls {
+        match start_tls(stream, &addr) {
+            Some(mut stream) => {
+                res = net::read_hello(&mut stream, true);
+                stream
+            }
+            None => return None,
+        }
+    } else {
+        Stream::Plain(stream)
+    };
+    // Reads fail after the idle timeout, so a crashed client cannot keep its
+    // connection open forever
+    if let Err(e) = stream.tcp().set_read_timeout(idle_timeout) {
+        warn!("Cannot set idle timeout: {:?}", e);
+    }
+
+    let (user, negotiation, resumed) = match res {
+        Ok(net::Hello::Login(login, negotiation)) => {
+            info!(
+                "Connection established. Protocol version {}, capabilities {:?}",
+                negotiation.version, negotiation.capabilities
+            );
+            // A verified certificate replaces the password of its user
+            let certified = tls::user_of(&stream)
+                .filter(|name| login.username.is_empty() || login.username == *name);
+            let found = match certified {
+                Some(name) => match auth::user_exists(&name) {
+                    Ok(true) => {
+                        info!("User '{}' logged in by certificate.", name);
+                        Ok(auth::User {
+                            _name: name,
+                            _currentDatabase: None,
+                        })
                     }
+                    Ok(false) => Err(auth::AuthError::UserNotFound),
+                    Err(e) => Err(e),
                 },
-                Err(_) => {
+                None => auth::find_user(&login.username, &login.password),
+            };
+            match found {
+                Ok(mut u) => {
+                    u._currentDatabase = default_database
+                        .and_then(|d| storage::Database::load(d).ok())
+                        .filter(|d| auth::has_any_privilege(&u, d).unwrap_or(false));
+                    (u, negotiation, None)
+                }
+                Err(e) => {
                     let _ = net::send_info_package(&mut stream, PkgType::AccDenied);
+                    audit::login_failed(&login.username, &addr, &format!("{:?}", e));
                     error!("Authentication failed. Connection closed.");
-                    return;
+                    return None;
                 }
             }
         }
+        Ok(net::Hello::Resume(token)) => match process::resume(&token) {
+            // The user may have been dropped in the meantime
+            Some(s) => {
+                if !auth::user_exists(&s.user).unwrap_or(false) {
+                    process::discard(&s);
+                    let _ = net::send_info_package(&mut stream, PkgType::AccDenied);
+                    error!(
+                        "User '{}' of the session is gone. Connection closed.",
+                        s.user
+                    );
+                    return None;
+                }
+                info!("Session of user '{}' resumed.", s.user);
+                let user = auth::User {
+                    _name: s.user.clone(),
+                    _currentDatabase: s
+                        .database
+                        .as_ref()
+                        .and_then(|d| storage::Database::load(d).ok()),
+                };
+                (user, s.negotiation, Some(s))
+            }
+            None => {
+                let _ = net::send_info_package(&mut stream, PkgType::AccDenied);
+                audit::login_failed("", &addr, "unknown or expired session token");
+                error!("Unknown or expired session token. Connection closed.");
+                return None;
+            }
+        },
+        Err(net::Error::ProtocolMismatch) => {
+            let _ = net::send_error_package(&mut stream, net::Error::ProtocolMismatch.into());
+            error!("Incompatible protocol version. Connection closed.");
+            return None;
+        }
         _ => {
             let _ = net::send_info_package(&mut stream, PkgType::AccDenied);
             error!("Authentication failed. Connection closed.");
-            return;
+            return None;
+        }
+    };
+
+    // Make the connection visible to SHOW PROCESSLIST and killable, unless a
+    // connection limit is reached
+    let registered = match resumed {
+        Some(ref s) => process::register_resumed(s, &addr, stream.try_clone().ok(), limits),
+        None => process::register(&user._name, &addr, stream.try_clone().ok(), limits),
+    };
+    let process = match registered {
+        Ok(p) => p,
+        Err(e) => {
+            if let Some(ref s) = resumed {
+                process::discard(s);
+            }
+            let _ = net::send_info_package(&mut stream, PkgType::TooManyConnections);
+            warn!("{:?} for user '{}'. Connection closed.", e, user._name);
+            return None;
         }
     };
+    // the default database may have as many connections as it allows
+    if let Some(ref base) = user._currentDatabase {
+        let max = base
+            .load_meta_data()
+            .map(|m| m.limits.max_connections)
+            .unwrap_or(0);
+        if let Err(e) = process::enter_database(process.id(), &base.name, max) {
+            let _ = net::send_info_package(&mut stream, PkgType::TooManyConnections);
+            warn!("{:?} to database '{}'. Connection closed.", e, base.name);
+            return None;
+        }
+    }
+    for channel in resumed.iter().flat_map(|s| &s.channels) {
+        process.listen(channel);
+    }
+    process.warn_on_shutdown();
+    // Results must fit into the packets the client accepts
+    let max_packet_size = negotiation.max_packet_size as u64;
+    let send_limits = net::SendLimits {
+        max_response_size: match send_limits.max_response_size {
+            0 => max_packet_size,
+            max => cmp::min(max, max_packet_size),
+        },
+        ..send_limits
+    };
+    let info = SessionInfo {
+        id: process.id(),
+        token: process.session_token().into(),
+    };
+    let prepare = negotiation
+        .capabilities
+        .contains(Capabilities::PREPARED_STATEMENTS);
+    if let Err(e) = net::send_acc_granted(&mut stream, &negotiation, &info) {
+        error!("{}", e.description());
+        return None;
+    }
+    Some(Session {
+        stream: stream,
+        user: user,
+        process: process,
+        negotiation: negotiation,
+        idle_timeout: idle_timeout,
+        send_limits: send_limits,
+        prepare: prepare,
+        prepared: HashMap::new(),
+        next_statement: 0,
+        idle_since: Instant::now(),
+    })
+}
+
+/// Answers `Command::StartTls` and performs the TLS handshake. Returns the
+/// encrypted stream, unless the connection was closed.
+fn start_tls(mut stream: TcpStream, addr: &str) -> Option<Stream> {
+    if !tls::is_enabled() {
+        let _ = net::send_error_package(&mut stream, net::Error::TlsUnavailable.into());
+        warn!(
+            "{} asked for TLS, which is not configured. Connection closed.",
+            addr
+        );
+        return None;
+    }
+    if net::send_info_package(&mut stream, PkgType::Ok).is_err() {
+        warn!("Failed to send packet.");
+        return None;
+    }
+    match tls::accept(stream) {
+        Ok(stream) => Some(stream),
+        Err(e) => {
+            warn!("TLS handshake with {} failed: {}", addr, e);
+            None
+        }
+    }
+}
+
+impl Session {
+    /// Reads and answers the next command of the client. Returns the
+    /// session, unless the connection was closed.
+    pub fn serve(mut self) -> Option<Session> {
+        if self.process.is_killed() {
+            info!(
+                "Connection {} was killed. Connection closed.",
+                self.process.id()
+            );
+            return None;
+        }
 
-    // Read commands from the client (with help of `net`)
-    loop {
         //get the command from the stream
-        let command_res = net::read_commands(&mut stream);
+        let command_res = net::read_commands(&mut self.stream, self.negotiation.max_packet_size);
 
         // Dispatch commands (handle easy ones directly, forward others)
         match command_res {
             Ok(cmd) => {
                 match cmd {
                     // exit the session and shutdown the connection
-                    Command::Quit => match net::send_info_package(&mut stream, PkgType::Ok) {
-                        Ok(_) => {
-                            debug!("Client disconnected properly.");
-                            return;
+                    Command::Quit => {
+                        let _writing = self.process.lock_writes();
+                        match net::send_info_package(&mut self.stream, PkgType::Ok) {
+                            Ok(_) => {
+                                debug!("Client disconnected properly.");
+                                return None;
+                            }
+                            Err(_) => warn!("Failed to send packet. Connection close."),
                         }
-                        Err(_) => warn!("Failed to send packet. Connection close."),
-                    },
+                    }
                     // send OK-Package, unused value can be checked to try again and
                     // eventually close to connection as timeout issue
-                    Command::Ping => match net::send_info_package(&mut stream, PkgType::Ok) {
-                        Ok(_) => {}
-                        Err(_) => warn!("Failed to send packet."),
-                    },
+                    Command::Ping => {
+                        let _writing = self.process.lock_writes();
+                        match net::send_info_package(&mut self.stream, PkgType::Ok) {
+                            Ok(_) => {}
+                            Err(_) => warn!("Failed to send packet."),
+                        }
+                    }
                     // send the query string for parsing
                     Command::Query(q) => {
                         debug!("Query received, dispatch query to parser.");
-
-                        // Call parser to obtain AST
-                        let ast = parse::parse(&q);
-
-                        match ast {
-                            Ok(tree) => {
-                                debug!("{:?}", tree);
-
-                                // Pass AST to query executer
-                                let r2 = query::execute_from_ast(tree, &mut user);
-
-                                debug!("{:?}", r2);
-
-                                let r =
-                                    r2.unwrap_or(ResultSet {
-                                        data: vec![],
-                                        columns: vec![
-                                        Column::new("error", SqlType::Int, false,
-                                        "error mind the error, not an error again, I hate errors",
-                                        false)],
-                                    });
-                                // Send response package
-                                match net::send_response_package(&mut stream, r) {
-                                    Ok(_) => {}
-                                    Err(_) => warn!("Failed to send packet."),
-                                }
+                        let res = run_query(&q, &mut self.user, &self.process);
+                        if !send_result(&mut self.stream, &q, res, &self.process, &self.send_limits)
+                        {
+                            return None;
+                        }
+                    }
+                    // parse a statement to run it by its id later
+                    Command::Prepare(q) if self.prepare => {
+                        let res = cache::parse(&q)
+                            .map_err(ExecutionError::ParseError)
+                            .and_then(|trees| {
+                                self.next_statement += 1;
+                                let statement = Prepared {
+                                    text: q.clone(),
+                                    queries: trees,
+                                    generation: cache::generation(),
+                                };
+                                self.prepared.insert(self.next_statement, statement);
+                                statement_id(self.next_statement)
+                            });
+                        if !send_result(&mut self.stream, &q, res, &self.process, &self.send_limits)
+                        {
+                            return None;
+                        }
+                    }
+                    Command::Execute(id) if self.prepare => {
+                        let sent = match self.prepared.get_mut(&id) {
+                            Some(statement) => {
+                                let res = run_prepared(statement, &mut self.user, &self.process);
+                                let text = &statement.text;
+                                send_result(
+                                    &mut self.stream,
+                                    text,
+                                    res,
+                                    &self.process,
+                                    &self.send_limits,
+                                )
                             }
-
-                            Err(error) => {
-                                error!("{:?}", error);
-                                match net::send_error_package(
-                                    &mut stream,
-                                    net::Error::UnEoq(error).into(),
-                                ) {
-                                    Ok(_) => {}
-                                    Err(_) => warn!("Failed to send error."),
+                            None => {
+                                let _writing = self.process.lock_writes();
+                                let err = net::Error::UnknownStatement.into();
+                                if net::send_error_package(&mut self.stream, err).is_err() {
+                                    warn!("Failed to send packet.");
                                 }
+                                true
                             }
+                        };
+                        if !sent {
+                            return None;
+                        }
+                    }
+                    Command::Deallocate(id) if self.prepare => {
+                        let _writing = self.process.lock_writes();
+                        let res = match self.prepared.remove(&id) {
+                            Some(_) => net::send_info_package(&mut self.stream, PkgType::Ok),
+                            None => net::send_error_package(
+                                &mut self.stream,
+                                net::Error::UnknownStatement.into(),
+                            ),
+                        };
+                        if res.is_err() {
+                            warn!("Failed to send packet.");
+                        }
+                    }
+                    // cancel the query of another connection and close it
+                    Command::Kill(id) => {
+                        let _writing = self.process.lock_writes();
+                        let res = match process::kill(id, &self.user._name, self.user.is_admin()) {
+                            Ok(_) => net::send_info_package(&mut self.stream, PkgType::Ok),
+                            Err(e) => net::send_error_package(
+                                &mut self.stream,
+                                net::Error::from(e).into(),
+                            ),
+                        };
+                        match res {
+                            Ok(_) => {}
+                            Err(_) => warn!("Failed to send packet."),
+                        }
+                    }
+                    // only valid instead of a login, or without the capability
+                    Command::Resume(_)
+                    | Command::StartTls
+                    | Command::Prepare(_)
+                    | Command::Execute(_)
+                    | Command::Deallocate(_) => {
+                        let _writing = self.process.lock_writes();
+                        match net::send_error_package(
+                            &mut self.stream,
+                            net::Error::UnknownCmd.into(),
+                        ) {
+                            Ok(_) => {}
+                            Err(_) => warn!("Failed to send packet."),
+                        }
+                    }
+                    // stream the text of the database, a response for every chunk
+                    Command::Dump(database) => {
+                        if !dump_database(
+                            &mut self.stream,
+                            &database,
+                            &mut self.user,
+                            &self.process,
+                            &self.send_limits,
+                        ) {
+                            return None;
+                        }
+                    }
+                    // insert the CSV text of the commands that follow
+                    Command::CopyIn(table) => {
+                        let max_size = self.negotiation.max_packet_size;
+                        if !copy_in(
+                            &mut self.stream,
+                            &table,
+                            &mut self.user,
+                            &self.process,
+                            max_size,
+                            &self.send_limits,
+                        ) {
+                            return None;
+                        }
+                    }
+                    // only valid after CopyIn
+                    Command::CopyData(_) | Command::CopyDone | Command::CopyFail(_) => {
+                        let _writing = self.process.lock_writes();
+                        match net::send_error_package(
+                            &mut self.stream,
+                            net::Error::UnexpectedPkg.into(),
+                        ) {
+                            Ok(_) => {}
+                            Err(_) => warn!("Failed to send packet."),
+                        }
+                    }
+                    // stream the replication log to a follower, which sends
+                    // nothing anymore, without blocking a worker
+                    Command::Replicate(from) if self.user.is_admin() => {
+                        thread::spawn(move || {
+                            replication::stream(&mut self.stream, from, &self.process)
+                        });
+                        return None;
+                    }
+                    Command::Replicate(_) => {
+                        let _writing = self.process.lock_writes();
+                        match net::send_error_package(
+                            &mut self.stream,
+                            net::Error::PermissionDenied.into(),
+                        ) {
+                            Ok(_) => {}
+                            Err(_) => warn!("Failed to send packet."),
                         }
-                        continue;
                     }
+                    // receive notifications sent to the channel from now on
+                    Command::Listen(channel) => {
+                        self.process.listen(&channel);
+                        let _writing = self.process.lock_writes();
+                        match net::send_info_package(&mut self.stream, PkgType::Ok) {
+                            Ok(_) => {}
+                            Err(_) => warn!("Failed to send packet."),
+                        }
+                    }
+                }
+            }
+            Err(ref e) if e.is_timeout() => {
+                self.close_idle();
+                return None;
+            }
+            // The client may come back and resume the session
+            Err(ref e) if e.is_disconnect() => {
+                info!(
+                    "Connection {} of user '{}' was closed by the client.",
+                    self.process.id(),
+                    self.user._name
+                );
+                let database = self.user._currentDatabase.as_ref().map(|d| d.name.clone());
+                self.process.suspend(database, self.negotiation);
+                return None;
+            }
+            // The stream is out of sync, nothing read from it can be trusted
+            Err(e @ net::Error::CorruptPacket) | Err(e @ net::Error::PacketTooLarge) => {
+                warn!(
+                    "Connection {}: {}. Connection closed.",
+                    self.process.id(),
+                    e.description()
+                );
+                let _writing = self.process.lock_writes();
+                let _ = net::send_error_package(&mut self.stream, e.into());
+                return None;
+            }
+            Err(_) => {} // TODO: error handling
+        }
+        self.idle_since = Instant::now();
+        Some(self)
+    }
+
+    /// The socket of the connection, which becomes readable with the next
+    /// command.
+    pub fn stream(&self) -> &TcpStream {
+        self.stream.tcp()
+    }
+
+    /// Returns true if the next command was read from the socket already,
+    /// which then does not become readable for it.
+    pub fn has_buffered(&self) -> bool {
+        self.stream.has_buffered()
+    }
+
+    /// Returns true if the client sent nothing for the idle timeout since
+    /// the last command.
+    pub fn timed_out(&self) -> bool {
+        match self.idle_timeout {
+            Some(timeout) => self.idle_since.elapsed() >= timeout,
+            None => false,
+        }
+    }
+
+    /// Closes the connection after the idle timeout.
+    pub fn close_idle(self) {
+        info!(
+            "Connection {} of user '{}' was idle for {} seconds. Connection closed.",
+            self.process.id(),
+            self.user._name,
+            self.idle_timeout.map(|t| t.as_secs()).unwrap_or(0)
+        );
+        // Dropping releases everything held by the connection
+    }
+}
+
+/// Sends the text of `DUMP DATABASE` in a response package for every chunk,
+/// followed by an `Ok` or an error package. Returns false if the client does
+/// not read the responses and the connection has to be closed.
+fn dump_database(
+    stream: &mut Stream,
+    database: &str,
+    user: &mut auth::User,
+    process: &process::Handle,
+    send_limits: &net::SendLimits,
+) -> bool {
+    let mut sent = Ok(());
+    process.set_query(Some(&format!("DUMP DATABASE {}", database)));
+    let res = query::dump_database(
+        database,
+        user,
+        process.cancel_token(),
+        process.id(),
+        |chunk| {
+            let mut rows = try!(dump::rows(&[chunk]));
+            let _writing = process.lock_writes();
+            sent = net::send_response_package(stream, try!(rows.to_result_set()), send_limits);
+            match sent {
+                Ok(_) => Ok(()),
+                // stops the dump
+                Err(_) => Err(ExecutionError::Cancelled),
+            }
+        },
+    );
+    process.set_query(None);
+
+    let _writing = process.lock_writes();
+    let res = match (sent, res) {
+        (Err(ref e), _) if e.is_timeout() => {
+            info!(
+                "Connection {} does not read its results. Connection closed.",
+                process.id()
+            );
+            return false;
+        }
+        (Err(e), _) => net::send_error_package(stream, e.into()),
+        (Ok(_), Ok(_)) => net::send_info_package(stream, PkgType::Ok),
+        (Ok(_), Err(e)) => net::send_error_package(stream, ClientErrMsg::from_execution_error(&e)),
+    };
+    if res.is_err() {
+        warn!("Failed to send packet.");
+    }
+    true
+}
+
+/// Inserts the CSV text of the `CopyData` commands following a `CopyIn`
+/// into the table until `CopyDone` or `CopyFail`, then sends a response with
+/// the number of rows inserted or an error package. Commands that arrive
+/// after the insert failed are skipped up to the end of the text. Returns
+/// false if reading fails and the connection has to be closed.
+fn copy_in(
+    stream: &mut Stream,
+    table: &str,
+    user: &mut auth::User,
+    process: &process::Handle,
+    max_size: u32,
+    send_limits: &net::SendLimits,
+) -> bool {
+    let mut ended = false;
+    let mut read = Ok(());
+    let text = format!("COPY {} FROM STDIN", table);
+    process.set_query(Some(&text));
+    let read_only = replication::is_read_only(process.id());
+    let res = query::copy_in(table, user, process.cancel_token(), process.id(), || {
+        if read_only {
+            return Err(ExecutionError::ReadOnly);
+        }
+        match net::read_commands(&mut *stream, max_size) {
+            Ok(Command::CopyData(chunk)) => Ok(Some(chunk)),
+            Ok(Command::CopyDone) => {
+                ended = true;
+                Ok(None)
+            }
+            Ok(Command::CopyFail(reason)) => {
+                ended = true;
+                Err(ExecutionError::DebugError(format!(
+                    "COPY failed: {}",
+                    reason
+                )))
+            }
+            Ok(_) => {
+                ended = true;
+                read = Err(net::Error::UnexpectedPkg);
+                Err(ExecutionError::Cancelled)
+            }
+            Err(e) => {
+                read = Err(e);
+                Err(ExecutionError::Cancelled)
+            }
+        }
+    });
+    process.set_query(None);
+    audit::statement(
+        process.id(),
+        &user._name,
+        user._currentDatabase.as_ref().map(|d| &d.name[..]),
+        &text,
+        res.as_ref().err().map(|e| format!("{:?}", e)),
+    );
+    while !ended && read.is_ok() {
+        match net::read_commands(&mut *stream, max_size) {
+            Ok(Command::CopyData(_)) => (),
+            Ok(Command::CopyDone) | Ok(Command::CopyFail(_)) => ended = true,
+            Ok(_) => {
+                ended = true;
+                read = Err(net::Error::UnexpectedPkg);
+            }
+            Err(e) => read = Err(e),
+        }
+    }
+
+    let _writing = process.lock_writes();
+    let sent = match (read, res) {
+        (Err(net::Error::UnexpectedPkg), _) => {
+            net::send_error_package(stream, net::Error::UnexpectedPkg.into())
+        }
+        (Err(e), _) => {
+            info!(
+                "Connection {}: {} during COPY. Connection closed.",
+                process.id(),
+                e.description()
+            );
+            return false;
+        }
+        (Ok(_), Ok(rows)) => net::send_response_package(stream, rows, send_limits),
+        (Ok(_), Err(e)) => net::send_error_package(stream, ClientErrMsg::from_execution_error(&e)),
+    };
+    if sent.is_err() {
+        warn!("Failed to send packet.");
+    }
+    true
+}
+
+/// Sends the result of a statement, or the error it failed with, see
+/// `run_query`. Returns false if the client does not read the result and the
+/// connection has to be closed.
+fn send_result(
+    stream: &mut Stream,
+    q: &str,
+    res: Result<ResultSet, ExecutionError>,
+    process: &process::Handle,
+    send_limits: &net::SendLimits,
+) -> bool {
+    match res {
+        Err(ExecutionError::ParseError(error)) => {
+            error!("{:?}", error);
+            let _writing = process.lock_writes();
+            match net::send_error_package(stream, ClientErrMsg::from_parse_error(&error, q)) {
+                Ok(_) => {}
+                Err(_) => warn!("Failed to send error."),
+            }
+        }
+        Err(e) => {
+            error!("{:?}", e);
+            let _writing = process.lock_writes();
+            match net::send_error_package(stream, ClientErrMsg::from_execution_error(&e)) {
+                Ok(_) => {}
+                Err(_) => warn!("Failed to send error."),
+            }
+        }
+        Ok(r) => {
+            // Send response package
+            let _writing = process.lock_writes();
+            match net::send_response_package(stream, r, send_limits) {
+                Ok(_) => {}
+                Err(net::Error::ResponseTooLarge) => {
+                    warn!("Result of connection {} too large.", process.id());
+                    let err = net::Error::ResponseTooLarge.into();
+                    if net::send_error_package(stream, err).is_err() {
+                        warn!("Failed to send error.");
+                    }
+                }
+                // The stream ends in the middle of a package
+                Err(ref e) if e.is_timeout() => {
+                    info!(
+                        "Connection {} does not read its results. Connection closed.",
+                        process.id()
+                    );
+                    return false;
                 }
+                Err(_) => warn!("Failed to send packet."),
             }
-            Err(_) => continue, // TODO: error handling
         }
     }
+    true
+}
+
+/// Returns the answer to `Command::Prepare`, the id of the statement.
+fn statement_id(id: u64) -> Result<ResultSet, ExecutionError> {
+    let columns = [Column::new(
+        "statement",
+        SqlType::BigInt,
+        false,
+        "id of the prepared statement",
+        true,
+    )];
+    let mut rows = Rows::new(Cursor::new(Vec::new()), &columns);
+    let mut row = Vec::new();
+    try!(columns[0]
+        .sql_type
+        .encode_into(&mut row, &Lit::Int(id as i64)));
+    try!(rows.add_row(&row));
+    Ok(try!(rows.to_result_set()))
+}
+
+/// Executes a prepared statement, which is parsed again from its text if
+/// the schema changed since it was parsed, see `cache`.
+fn run_prepared(
+    statement: &mut Prepared,
+    user: &mut auth::User,
+    process: &process::Handle,
+) -> Result<ResultSet, ExecutionError> {
+    let generation = cache::generation();
+    if statement.generation != generation {
+        statement.queries = try!(cache::parse(&statement.text));
+        statement.generation = generation;
+    }
+    run_trees(&statement.text, statement.queries.clone(), user, process)
+}
+
+/// Parses and executes a query for a registered connection and keeps its
+/// entry in the process list up to date. Syntax errors are returned as
+/// `ExecutionError::ParseError`. Queries parsed before are taken from the
+/// cache, see `cache`.
+pub fn run_query(
+    q: &str,
+    user: &mut auth::User,
+    process: &process::Handle,
+) -> Result<ResultSet, ExecutionError> {
+    // Call parser to obtain the ASTs of the statements
+    let (started, start) = (SystemTime::now(), Instant::now());
+    let trees = match cache::parse(q) {
+        Ok(trees) => trees,
+        Err(e) => {
+            metrics::record(metrics::Kind::Other, start.elapsed(), false);
+            querylog::record(&querylog::Entry {
+                connection: process.id(),
+                user: &user._name,
+                database: user._currentDatabase.as_ref().map(|d| &d.name[..]),
+                sql: q,
+                started: started,
+                duration: start.elapsed(),
+                error: Some(format!("{:?}", e)),
+            });
+            return Err(e.into());
+        }
+    };
+    run_trees(q, trees, user, process)
+}
+
+/// Executes the statements of a query one after another and returns the
+/// result of the last one. The first statement failing ends the query with
+/// its error, the statements after it are not executed. The statements
+/// are shown to the replication, see `replication::before`.
+fn run_trees(
+    q: &str,
+    trees: Vec<Query>,
+    user: &mut auth::User,
+    process: &process::Handle,
+) -> Result<ResultSet, ExecutionError> {
+    let texts = if replication::is_logging() {
+        parse::split_statements(q)
+    } else {
+        Vec::new()
+    };
+    let count = trees.len();
+    let mut res = Err(ExecutionError::ParseError(ParseError::EmptyQueryError));
+    for (i, tree) in trees.into_iter().enumerate() {
+        let text = if texts.len() == count { texts[i] } else { q };
+        let database = process.database();
+        replication::before(process.id(), &tree, text, database.as_ref().map(|d| &d[..]));
+        res = run_tree(q, tree, user, process);
+        replication::after(process.id(), res.is_ok());
+        if res.is_err() {
+            break;
+        }
+    }
+    res
+}
+
+/// Executes the tree of a query, see `run_query`. A statement changing the
+/// schema invalidates the cache, one changing anything fails on a follower.
+fn run_tree(
+    q: &str,
+    tree: Query,
+    user: &mut auth::User,
+    process: &process::Handle,
+) -> Result<ResultSet, ExecutionError> {
+    debug!("{:?}", tree);
+    let kind = metrics::Kind::of(&tree);
+    let changes = tree.is_change();
+    let schema_changed = match tree {
+        Query::DefStmt(_) => true,
+        _ => false,
+    };
+
+    // another connection may have renamed the current database
+    if let Some(ref mut base) = user._currentDatabase {
+        if let Some(name) = process.database() {
+            base.name = name;
+        }
+    }
+
+    // Pass AST to query executer
+    process.set_query(Some(q));
+    let (started, start) = (SystemTime::now(), Instant::now());
+    let res = if changes && replication::is_read_only(process.id()) {
+        Err(ExecutionError::ReadOnly)
+    } else {
+        query::execute_from_ast(tree, user, process.cancel_token(), process.id())
+    };
+    let duration = start.elapsed();
+    metrics::record(kind, duration, res.is_ok());
+    process.set_query(None);
+
+    let database = user._currentDatabase.as_ref().map(|d| &d.name[..]);
+    let error = res.as_ref().err().map(|e| format!("{:?}", e));
+    querylog::record(&querylog::Entry {
+        connection: process.id(),
+        user: &user._name,
+        database: database,
+        sql: q,
+        started: started,
+        duration: duration,
+        error: error.clone(),
+    });
+    if changes {
+        audit::statement(process.id(), &user._name, database, q, error);
+    }
+    process.set_database(database);
+    if schema_changed && res.is_ok() {
+        cache::invalidate();
+    }
+
+    debug!("{:?}", res);
+    res
 }
@@ -0,0 +1,300 @@
+//! Reading and writing of CSV text
+//!
+//! `COPY` and `Command::CopyIn` insert rows given as CSV text: fields are
+//! separated by commas and records by line breaks. Fields in double quotes
+//! may hold commas, line breaks and doubled double quotes. An empty field
+//! without quotes is null, `""` is the empty string. Blank lines are
+//! skipped.
+//!
+//! The text arrives in chunks, which may end anywhere, also in the middle
+//! of a field.
+//!
+//! `record` writes text in the same format, which clients export results
+//! as.
+
+use parse::token::Lit;
+use storage::decimal;
+use storage::SqlType;
+
+use std::fmt;
+use std::io;
+
+/// A record of the text that cannot be read
+#[derive(Debug, PartialEq)]
+pub struct Error {
+    /// 1-based line the record starts on
+    pub line: u64,
+    pub reason: String,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.reason)
+    }
+}
+
+/// The fields of a record, `None` for null
+#[derive(Debug, PartialEq)]
+pub struct Record {
+    /// 1-based line the record starts on
+    pub line: u64,
+    pub fields: Vec<Option<String>>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    /// before the first character of a field
+    Start,
+    Unquoted,
+    Quoted,
+    /// after a double quote in a quoted field, which ends it unless
+    /// another one follows
+    QuoteInQuoted,
+}
+
+/// Splits CSV text into records
+pub struct Reader {
+    state: State,
+    field: Vec<u8>,
+    fields: Vec<Option<String>>,
+    /// line of the next character
+    line: u64,
+    /// line the current record starts on
+    start: u64,
+}
+
+impl Reader {
+    pub fn new() -> Reader {
+        Reader {
+            state: State::Start,
+            field: Vec::new(),
+            fields: Vec::new(),
+            line: 1,
+            start: 1,
+        }
+    }
+
+    /// Reads the next chunk of the text and appends the records it
+    /// completes.
+    pub fn read(&mut self, chunk: &[u8], records: &mut Vec<Record>) -> Result<(), Error> {
+        for &b in chunk {
+            match (self.state, b) {
+                (State::Quoted, b'"') => self.state = State::QuoteInQuoted,
+                (State::Quoted, _) => self.field.push(b),
+                (State::QuoteInQuoted, b'"') => {
+                    self.field.push(b);
+                    self.state = State::Quoted;
+                }
+                // carriage returns of line breaks
+                (_, b'\r') => (),
+                (State::Start, b'"') => self.state = State::Quoted,
+                (State::Start, b'\n') if self.fields.is_empty() => self.start = self.line + 1,
+                (State::Start, b',') | (State::Start, b'\n') => {
+                    self.fields.push(None);
+                    if b == b'\n' {
+                        self.end_record(records);
+                    }
+                }
+                (_, b',') => {
+                    try!(self.end_field());
+                    self.state = State::Start;
+                }
+                (_, b'\n') => {
+                    try!(self.end_field());
+                    self.end_record(records);
+                }
+                (State::QuoteInQuoted, _) => return Err(self.error("text after closing quote")),
+                (_, b'"') => return Err(self.error("quote in unquoted field")),
+                _ => {
+                    self.field.push(b);
+                    self.state = State::Unquoted;
+                }
+            }
+            if b == b'\n' {
+                self.line += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Ends the text and appends its last record if it does not end with a
+    /// line break.
+    pub fn finish(&mut self, records: &mut Vec<Record>) -> Result<(), Error> {
+        match self.state {
+            State::Quoted => return Err(self.error("unclosed quote")),
+            State::Start if self.fields.is_empty() => return Ok(()),
+            State::Start => self.fields.push(None),
+            _ => try!(self.end_field()),
+        }
+        self.end_record(records);
+        Ok(())
+    }
+
+    fn end_field(&mut self) -> Result<(), Error> {
+        let field = try!(
+            String::from_utf8(self.field.split_off(0)).map_err(|_| self.error("invalid UTF-8"))
+        );
+        self.fields.push(Some(field));
+        Ok(())
+    }
+
+    fn end_record(&mut self, records: &mut Vec<Record>) {
+        records.push(Record {
+            line: self.start,
+            fields: self.fields.split_off(0),
+        });
+        self.state = State::Start;
+        // the line break is counted after the record ends
+        self.start = self.line + 1;
+    }
+
+    fn error(&self, reason: &str) -> Error {
+        Error {
+            line: self.start,
+            reason: reason.into(),
+        }
+    }
+}
+
+/// Returns the fields as a record of CSV text, ending with a line break.
+/// Fields holding commas, double quotes or line breaks and empty strings
+/// are quoted, null is an empty field.
+pub fn record(fields: &[Option<String>]) -> String {
+    let mut text = String::new();
+    for (i, field) in fields.iter().enumerate() {
+        if i > 0 {
+            text.push(',');
+        }
+        match field {
+            &Some(ref f) if f.is_empty() || f.contains(|c| ",\"\r\n".contains(c)) => {
+                text.push('"');
+                text.push_str(&f.replace('"', "\"\""));
+                text.push('"');
+            }
+            &Some(ref f) => text.push_str(f),
+            &None => (),
+        }
+    }
+    text.push('\n');
+    text
+}
+
+/// Returns the value of a field for a column of the type, or None if the
+/// field is no such value. Bytes may be given as `\x` followed by hex
+/// digits.
+pub fn value(sql_type: &SqlType, field: &str) -> Option<Lit> {
+    let value = match parse(sql_type, field) {
+        Some(value) => value,
+        None => return None,
+    };
+    // BLOB values are not encoded into the row
+    if *sql_type != SqlType::Blob && sql_type.encode_into(&mut io::sink(), &value).is_err() {
+        return None;
+    }
+    Some(value)
+}
+
+fn parse(sql_type: &SqlType, field: &str) -> Option<Lit> {
+    match sql_type {
+        &SqlType::Int | &SqlType::BigInt => field.trim().parse().ok().map(Lit::Int),
+        &SqlType::Float | &SqlType::Double => field.trim().parse().ok().map(Lit::Float),
+        &SqlType::Decimal(..) => decimal::parse(field.trim()).map(|(d, s)| Lit::Decimal(d, s)),
+        &SqlType::Bool => match &field.trim().to_lowercase()[..] {
+            "true" | "t" | "1" => Some(Lit::Bool(1)),
+            "false" | "f" | "0" => Some(Lit::Bool(0)),
+            _ => None,
+        },
+        &SqlType::VarBinary(_) | &SqlType::Blob if field.starts_with("\\x") => {
+            let hex = &field[2..];
+            if hex.len() % 2 != 0 || !hex.is_ascii() {
+                return None;
+            }
+            let mut bytes = Vec::with_capacity(hex.len() / 2);
+            for i in (0..hex.len()).step_by(2) {
+                bytes.push(match u8::from_str_radix(&hex[i..i + 2], 16) {
+                    Ok(b) => b,
+                    Err(_) => return None,
+                });
+            }
+            Some(Lit::Bytes(bytes))
+        }
+        // dates and times are parsed when they are encoded
+        _ => Some(Lit::String(field.into())),
+    }
+}
+
+#[test]
+fn test_read_records() {
+    let text = "1,\"a, \"\"b\"\"\",\r\n\n2,\"\",x\n3,\"two\nlines\",\"\"";
+    // any split of the text gives the same records
+    for split in 0..text.len() {
+        let mut reader = Reader::new();
+        let mut records = Vec::new();
+        reader
+            .read(&text.as_bytes()[..split], &mut records)
+            .unwrap();
+        reader
+            .read(&text.as_bytes()[split..], &mut records)
+            .unwrap();
+        reader.finish(&mut records).unwrap();
+        let fields = |f: &[Option<&str>]| f.iter().map(|f| f.map(String::from)).collect();
+        assert_eq!(
+            records,
+            vec![
+                Record {
+                    line: 1,
+                    fields: fields(&[Some("1"), Some("a, \"b\""), None]),
+                },
+                Record {
+                    line: 3,
+                    fields: fields(&[Some("2"), Some(""), Some("x")]),
+                },
+                Record {
+                    line: 4,
+                    fields: fields(&[Some("3"), Some("two\nlines"), Some("")]),
+                },
+            ]
+        );
+    }
+
+    let mut reader = Reader::new();
+    let mut records = Vec::new();
+    let err = reader.read(b"1,2\n3,\"4\"5\n", &mut records);
+    assert_eq!(
+        err.unwrap_err().to_string(),
+        "line 2: text after closing quote"
+    );
+    let mut reader = Reader::new();
+    reader.read(b"1,\"2", &mut records).unwrap();
+    assert!(reader.finish(&mut records).is_err());
+
+    assert_eq!(value(&SqlType::Int, " 42"), Some(Lit::Int(42)));
+    assert_eq!(value(&SqlType::Int, "4x"), None);
+    assert_eq!(value(&SqlType::Int, "9999999999"), None);
+    assert_eq!(value(&SqlType::Date, "yesterday"), None);
+    assert_eq!(value(&SqlType::Bool, "F"), Some(Lit::Bool(0)));
+    assert_eq!(
+        value(&SqlType::Blob, "\\x0aFF"),
+        Some(Lit::Bytes(vec![10, 255]))
+    );
+}
+
+#[test]
+fn test_write_records() {
+    let fields = vec![
+        Some("1".to_string()),
+        Some("a, \"b\"".to_string()),
+        None,
+        Some("".to_string()),
+        Some("two\nlines".to_string()),
+    ];
+    let text = record(&fields);
+    assert_eq!(text, "1,\"a, \"\"b\"\"\",,\"\",\"two\nlines\"\n");
+
+    // the reader gives the fields back
+    let mut reader = Reader::new();
+    let mut records = Vec::new();
+    reader.read(text.as_bytes(), &mut records).unwrap();
+    reader.finish(&mut records).unwrap();
+    assert_eq!(records[0].fields, fields);
+}
@@ -0,0 +1,528 @@
+//! SQL text of databases
+//!
+//! `DUMP DATABASE` writes the tables of a database as the statements that
+//! create them again, one per line: a `CREATE TABLE` with the constraints
+//! and storage options of the table, a `CREATE INDEX` for every index that
+//! does not keep a `UNIQUE` constraint and an `INSERT` for every row. Views
+//! follow the tables as `CREATE VIEW` statements, after the `CREATE
+//! FUNCTION` statements of the functions they may call, and the `CREATE
+//! EVENT` statements come last. The functions of this module build these
+//! statements, the executor reads the rows.
+//!
+//! Names of check constraints and foreign keys are not part of the text,
+//! they are given anew when it runs. Conditions of check constraints are
+//! written as ORs of ANDs, which need no parentheses.
+
+use parse::ast::{CompType, CondType, Condition, Conditions};
+use parse::parser::is_keyword;
+use parse::token::Lit;
+use storage::decimal;
+use storage::temporal;
+use storage::{
+    Column, Compression, Error, Event, Function, ReferentialAction, Rows, SqlType, Table, View,
+};
+
+use std::io::Cursor;
+
+/// Maximum length of a chunk of the text in bytes
+pub const CHUNK_SIZE: u16 = 16 * 1024;
+
+/// Returns chunks of the text as rows of a single column `sql`, the result
+/// of `DUMP DATABASE`.
+pub fn rows<S: AsRef<str>>(chunks: &[S]) -> Result<Rows<Cursor<Vec<u8>>>, Error> {
+    let columns = vec![Column::new(
+        "sql",
+        SqlType::VarChar(CHUNK_SIZE),
+        false,
+        "SQL text",
+        false,
+    )];
+    let mut rows = Rows::new(Cursor::new(Vec::<u8>::new()), &columns);
+    for chunk in chunks {
+        let mut row = Vec::<u8>::new();
+        try!(columns[0]
+            .sql_type
+            .encode_into(&mut row, &Lit::String(chunk.as_ref().into())));
+        try!(rows.add_row(&row));
+    }
+    Ok(rows)
+}
+
+/// Collects lines of the text into chunks of at most `CHUNK_SIZE` bytes and
+/// hands them to `write`. Lines are only split if they do not fit into a
+/// chunk of their own.
+pub struct Chunks<F> {
+    chunk: String,
+    write: F,
+}
+
+impl<F, E> Chunks<F>
+where
+    F: FnMut(&str) -> Result<(), E>,
+{
+    pub fn new(write: F) -> Chunks<F> {
+        Chunks {
+            chunk: String::new(),
+            write: write,
+        }
+    }
+
+    /// Appends a line.
+    pub fn push(&mut self, line: &str) -> Result<(), E> {
+        let max = CHUNK_SIZE as usize;
+        if !self.chunk.is_empty() && self.chunk.len() + line.len() + 1 > max {
+            try!(self.flush());
+        }
+        self.chunk.push_str(line);
+        self.chunk.push('\n');
+        while self.chunk.len() > max {
+            let mut end = max;
+            while !self.chunk.is_char_boundary(end) {
+                end -= 1;
+            }
+            let rest = self.chunk.split_off(end);
+            try!(self.flush());
+            self.chunk = rest;
+        }
+        Ok(())
+    }
+
+    /// Hands the last chunk to `write`.
+    pub fn finish(mut self) -> Result<(), E> {
+        if self.chunk.is_empty() {
+            return Ok(());
+        }
+        self.flush()
+    }
+
+    fn flush(&mut self) -> Result<(), E> {
+        try!((self.write)(&self.chunk));
+        self.chunk.clear();
+        Ok(())
+    }
+}
+
+/// Returns the tables in an order in which they can be created: tables
+/// referenced by foreign keys come before the tables referencing them. Tables
+/// referencing each other keep their order.
+pub fn creation_order<'a, 'b>(tables: &'a [Table<'b>]) -> Vec<&'a Table<'b>> {
+    let mut ordered: Vec<&Table> = Vec::with_capacity(tables.len());
+    let mut left: Vec<&Table> = tables.iter().collect();
+    while !left.is_empty() {
+        let ready = left.iter().position(|t| {
+            t.foreign_keys()
+                .iter()
+                .all(|fk| fk.ref_table == t.name || !left.iter().any(|l| l.name == fk.ref_table))
+        });
+        // a cycle: take the first table of those left
+        ordered.push(left.remove(ready.unwrap_or(0)));
+    }
+    ordered
+}
+
+/// Returns the value of a column as a literal. BLOB values must be read
+/// through the engine of the table and are given as `Lit::Bytes`.
+pub fn value(sql_type: &SqlType, value: &[u8]) -> Result<Lit, Error> {
+    match sql_type {
+        // without the padding
+        &SqlType::Char(_) => Ok(Lit::String(
+            String::from_utf8_lossy(sql_type.content(value)).into_owned(),
+        )),
+        // the shortest text that is read as the same f32
+        &SqlType::Float => match try!(sql_type.decode_from(&mut Cursor::new(value))) {
+            Lit::Float(f) => Ok(Lit::Float((f as f32).to_string().parse().unwrap_or(f))),
+            lit => Ok(lit),
+        },
+        _ => sql_type.decode_from(&mut Cursor::new(value)),
+    }
+}
+
+/// Returns the `CREATE TABLE` statement of a table.
+pub fn create_table(table: &Table) -> String {
+    let mut parts = Vec::new();
+    for column in table.columns() {
+        let mut part = format!("{} {}", name(&column.name), type_name(&column.sql_type));
+        if column.is_primary_key {
+            part.push_str(" PRIMARY KEY");
+        } else if !column.allow_null {
+            part.push_str(" NOT NULL");
+        }
+        if let Some(ref default) = column.default {
+            part.push_str(&format!(" DEFAULT {}", literal(default)));
+        }
+        parts.push(part);
+    }
+    for index in table.indexes().iter().filter(|i| i.unique) {
+        parts.push(format!("UNIQUE ({})", names(&index.columns)));
+    }
+    for foreign_key in table.foreign_keys() {
+        let on_delete = match foreign_key.on_delete {
+            ReferentialAction::Restrict => "RESTRICT",
+            ReferentialAction::Cascade => "CASCADE",
+            ReferentialAction::SetNull => "SET NULL",
+        };
+        parts.push(format!(
+            "FOREIGN KEY ({}) REFERENCES {} ({}) ON DELETE {}",
+            names(&foreign_key.columns),
+            name(&foreign_key.ref_table),
+            names(&foreign_key.ref_columns),
+            on_delete
+        ));
+    }
+    for check in table.checks() {
+        parts.push(format!("CHECK ({})", conditions(&check.condition)));
+    }
+    let mut sql = format!(
+        "CREATE TABLE {} ({}) ENGINE {}",
+        name(&table.name),
+        parts.join(", "),
+        table.engine_id().name()
+    );
+    if table.meta_data.compression != Compression::None {
+        sql.push_str(&format!(
+            " COMPRESSION {}",
+            table.meta_data.compression.name()
+        ));
+    }
+    if table.meta_data.encrypted {
+        sql.push_str(" ENCRYPTION 'Y'");
+    }
+    sql.push(';');
+    sql
+}
+
+/// Returns the `CREATE INDEX` statements of the indexes of a table that do
+/// not keep a `UNIQUE` constraint.
+pub fn create_indexes(table: &Table) -> Vec<String> {
+    table
+        .indexes()
+        .iter()
+        .filter(|i| !i.unique)
+        .map(|i| {
+            format!(
+                "CREATE INDEX {} ON {} ({});",
+                name(&i.name),
+                name(&table.name),
+                names(&i.columns)
+            )
+        })
+        .collect()
+}
+
+/// Returns the `INSERT` statement of a row, given by its decoded values.
+pub fn insert(table: &str, values: &[Lit]) -> String {
+    let values: Vec<_> = values.iter().map(literal).collect();
+    format!(
+        "INSERT INTO {} VALUES ({});",
+        name(table),
+        values.join(", ")
+    )
+}
+
+/// Returns the `CREATE VIEW` statement of a view on a single line.
+pub fn create_view(view: &View) -> String {
+    let lines: Vec<_> = view.definition.lines().map(str::trim).collect();
+    format!("CREATE VIEW {} AS {};", name(&view.name), lines.join(" "))
+}
+
+/// Returns the `CREATE FUNCTION` statement of a function on a single line.
+pub fn create_function(function: &Function) -> String {
+    let params: Vec<_> = function
+        .params
+        .iter()
+        .map(|&(ref param, ref sql_type)| format!("{} {}", name(param), type_name(sql_type)))
+        .collect();
+    let lines: Vec<_> = function.body.lines().map(str::trim).collect();
+    format!(
+        "CREATE FUNCTION {}({}) RETURNS {} AS {};",
+        name(&function.name),
+        params.join(", "),
+        type_name(&function.returns),
+        quote(&lines.join(" "))
+    )
+}
+
+/// Returns the `CREATE EVENT` statement of an event on a single line, its
+/// interval in the largest unit that divides it.
+pub fn create_event(event: &Event) -> String {
+    let units = [
+        ("WEEK", 7 * 24 * 60 * 60),
+        ("DAY", 24 * 60 * 60),
+        ("HOUR", 60 * 60),
+        ("MINUTE", 60),
+        ("SECOND", 1),
+    ];
+    let &(unit, seconds) = units
+        .iter()
+        .find(|&&(_, seconds)| event.every % seconds == 0)
+        .unwrap_or(&units[4]);
+    let lines: Vec<_> = event.body.lines().map(str::trim).collect();
+    format!(
+        "CREATE EVENT {} ON SCHEDULE EVERY {} {} DO {};",
+        name(&event.name),
+        event.every / seconds,
+        unit,
+        lines.join(" ")
+    )
+}
+
+/// Returns a name as written in SQL, in double quotes unless it is a plain
+/// word and no keyword.
+pub fn name(name: &str) -> String {
+    let plain = name.starts_with(|c: char| c.is_ascii_alphabetic())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !is_keyword(name)
+        && !["true", "false", "null"].contains(&&name.to_lowercase()[..]);
+    if plain {
+        name.to_string()
+    } else {
+        format!("\"{}\"", name.replace('"', "\"\""))
+    }
+}
+
+/// Returns names as written in a list of columns.
+fn names(names: &[String]) -> String {
+    let names: Vec<_> = names.iter().map(|n| name(n)).collect();
+    names.join(", ")
+}
+
+/// Returns the name of a type as written in `CREATE TABLE`.
+pub fn type_name(sql_type: &SqlType) -> String {
+    match sql_type {
+        &SqlType::Int => "int".into(),
+        &SqlType::Bool => "bool".into(),
+        &SqlType::Char(len) => format!("char({})", len),
+        &SqlType::VarChar(len) => format!("varchar({})", len),
+        &SqlType::Text => "text".into(),
+        &SqlType::Float => "float".into(),
+        &SqlType::Double => "double".into(),
+        &SqlType::BigInt => "bigint".into(),
+        &SqlType::Date => "date".into(),
+        &SqlType::Time => "time".into(),
+        &SqlType::Timestamp => "timestamp".into(),
+        &SqlType::Decimal(precision, scale) => format!("decimal({}, {})", precision, scale),
+        &SqlType::VarBinary(len) => format!("varbinary({})", len),
+        &SqlType::Blob => "blob".into(),
+    }
+}
+
+/// Returns a literal as written in SQL. Quotation marks in strings are
+/// doubled, backslashes escaped.
+pub fn literal(lit: &Lit) -> String {
+    match lit {
+        &Lit::String(ref s) => quote(s),
+        &Lit::Int(i) => i.to_string(),
+        &Lit::Float(f) => f.to_string(),
+        &Lit::Decimal(d, scale) => decimal::format(d, scale),
+        &Lit::Bool(b) => if b != 0 { "TRUE" } else { "FALSE" }.into(),
+        &Lit::Bytes(ref bytes) => {
+            let hex: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+            format!("X'{}'", hex.concat())
+        }
+        &Lit::Null => "NULL".into(),
+        &Lit::Date(d) => format!("DATE {}", quote(&temporal::format_date(d))),
+        &Lit::Time(t) => format!("TIME {}", quote(&temporal::format_time(t))),
+        &Lit::Timestamp(t) => format!("TIMESTAMP {}", quote(&temporal::format_timestamp(t))),
+        // operators are calls with a name of symbols
+        &Lit::Call(ref name, ref args) if !name.chars().any(char::is_alphanumeric) => {
+            format!("({} {} {})", literal(&args[0]), name, literal(&args[1]))
+        }
+        &Lit::Call(ref name, ref args) => {
+            let args: Vec<_> = args.iter().map(literal).collect();
+            format!("{}({})", name.to_uppercase(), args.join(", "))
+        }
+        &Lit::Column(Some(ref alias), ref column) => format!("{}.{}", name(alias), name(column)),
+        &Lit::Column(None, ref column) => name(column),
+        // subqueries are never stored, see `Lit::Subquery`
+        &Lit::Subquery(_) => "(SELECT ...)".into(),
+    }
+}
+
+/// Quotes a string. A backslash starts an escape sequence in a string, so
+/// it is escaped itself.
+fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\\', "\\\\").replace('\'', "''"))
+}
+
+/// Returns conditions as written in a WHERE clause, as ORs of ANDs.
+pub fn conditions(conditions: &Conditions) -> String {
+    let terms: Vec<_> = disjunction(conditions)
+        .iter()
+        .map(|term| term.join(" AND "))
+        .collect();
+    terms.join(" OR ")
+}
+
+/// Splits conditions into terms of which any must hold, all conditions of
+/// a term must hold. AND distributes over OR also if values are null.
+fn disjunction(conditions: &Conditions) -> Vec<Vec<String>> {
+    match conditions {
+        &Conditions::Leaf(ref c) => vec![vec![condition(c)]],
+        &Conditions::Expr(ref lhs, op, ref rhs) => {
+            vec![vec![comparison(literal(lhs), op, literal(rhs))]]
+        }
+        &Conditions::In(ref lhs, ref values, negated) => {
+            let values: Vec<_> = values.iter().map(literal).collect();
+            let not = if negated { "NOT " } else { "" };
+            vec![vec![format!(
+                "{} {}IN ({})",
+                literal(lhs),
+                not,
+                values.join(", ")
+            )]]
+        }
+        // subqueries are never stored, see `Lit::Subquery`
+        &Conditions::Exists(_, negated) => {
+            let not = if negated { "NOT " } else { "" };
+            vec![vec![format!("{}EXISTS (SELECT ...)", not)]]
+        }
+        &Conditions::Or(ref a, ref b) => {
+            let mut terms = disjunction(a);
+            terms.extend(disjunction(b));
+            terms
+        }
+        &Conditions::And(ref a, ref b) => {
+            let right = disjunction(b);
+            let mut terms = Vec::new();
+            for left in disjunction(a) {
+                for term in &right {
+                    let mut both = left.clone();
+                    both.extend(term.iter().cloned());
+                    terms.push(both);
+                }
+            }
+            terms
+        }
+    }
+}
+
+fn condition(c: &Condition) -> String {
+    let column = match c.aliascol {
+        Some(ref alias) => format!("{}.{}", name(alias), name(&c.col)),
+        None => name(&c.col),
+    };
+    let rhs = match c.rhs {
+        CondType::Literal(ref lit) => literal(lit),
+        CondType::Word(ref word) => match c.aliasrhs {
+            Some(ref alias) => format!("{}.{}", name(alias), name(word)),
+            None => name(word),
+        },
+    };
+    comparison(column, c.op, rhs)
+}
+
+fn comparison(column: String, op: CompType, rhs: String) -> String {
+    let op = match op {
+        CompType::Equ => "=",
+        CompType::NEqu => "<>",
+        CompType::GThan => ">",
+        CompType::SThan => "<",
+        CompType::GEThan => ">=",
+        CompType::SEThan => "<=",
+        CompType::Like => "LIKE",
+        CompType::NLike => "NOT LIKE",
+        CompType::Regexp => "REGEXP",
+        CompType::NRegexp => "NOT REGEXP",
+        CompType::Match => return format!("MATCH({}, {})", column, rhs),
+        CompType::NMatch => return format!("NOT MATCH({}, {})", column, rhs),
+        CompType::IsNull => return format!("{} IS NULL", column),
+        CompType::IsNotNull => return format!("{} IS NOT NULL", column),
+    };
+    format!("{} {} {}", column, op, rhs)
+}
+
+#[test]
+fn test_statements_parse_again() {
+    use parse::ast::{CreateStmt, DefStmt, ManipulationStmt, Query};
+    use parse::parser::Parser;
+
+    let create_table = |sql: &str| match Parser::create(sql).parse().unwrap() {
+        Query::DefStmt(DefStmt::Create(CreateStmt::Table(stmt))) => stmt,
+        q => panic!("unexpected query {:?}", q),
+    };
+    let stmt = create_table(
+        "create table t (id int primary key, name varchar(20) default 'it''s', \
+         created timestamp default now(), check ((name = 'a' or name = 'b') and id > 0))",
+    );
+    let text = conditions(&stmt.checks[0]);
+    assert_eq!(text, "name = 'a' AND id > 0 OR name = 'b' AND id > 0");
+    let again = create_table(&format!("create table u (id int, check ({}))", text));
+    assert_eq!(conditions(&again.checks[0]), text);
+    assert_eq!(literal(stmt.cols[1].default.as_ref().unwrap()), "'it''s'");
+    assert_eq!(literal(stmt.cols[2].default.as_ref().unwrap()), "NOW()");
+    assert_eq!(name("id_2"), "id_2");
+    assert_eq!(name("order"), "\"order\"");
+    assert_eq!(
+        literal(&Lit::Column(Some("t".into()), "say \"hi\"".into())),
+        "t.\"say \"\"hi\"\"\""
+    );
+    assert_eq!(literal(&Lit::Column(None, "null".into())), "\"null\"");
+
+    let values = vec![
+        Lit::Int(-3),
+        Lit::String("say \"hi\" to C:\\".into()),
+        Lit::Null,
+        Lit::Bytes(vec![0x0a, 0xff]),
+        Lit::Date(0),
+        Lit::Decimal(-1250, 2),
+    ];
+    let sql = insert("t", &values);
+    match Parser::create(sql.trim_end_matches(';')).parse().unwrap() {
+        Query::ManipulationStmt(ManipulationStmt::Insert(stmt)) => {
+            assert_eq!(stmt.val, vec![values])
+        }
+        q => panic!("unexpected query {:?}", q),
+    }
+
+    let view = View {
+        name: "v".into(),
+        definition: "select id\n    from t where id > 1".into(),
+    };
+    let sql = create_view(&view);
+    assert_eq!(sql, "CREATE VIEW v AS select id from t where id > 1;");
+    match Parser::create(sql.trim_end_matches(';')).parse().unwrap() {
+        Query::DefStmt(DefStmt::Create(CreateStmt::View(stmt))) => {
+            assert_eq!(stmt.text, "select id from t where id > 1")
+        }
+        q => panic!("unexpected query {:?}", q),
+    }
+
+    let function = Function {
+        name: "greet".into(),
+        params: vec![("who".into(), SqlType::VarChar(20))],
+        returns: SqlType::Text,
+        body: "'it''s ' ||\n  who".into(),
+    };
+    let sql = create_function(&function);
+    assert_eq!(
+        sql,
+        "CREATE FUNCTION greet(who varchar(20)) RETURNS text AS '''it''''s '' || who';"
+    );
+    match Parser::create(sql.trim_end_matches(';')).parse().unwrap() {
+        Query::DefStmt(DefStmt::Create(CreateStmt::Function(stmt))) => {
+            assert_eq!(stmt.params, function.params);
+            assert_eq!(stmt.body, "'it''s ' || who")
+        }
+        q => panic!("unexpected query {:?}", q),
+    }
+
+    let event = Event {
+        name: "cleanup".into(),
+        every: 2 * 60 * 60,
+        starts: 0,
+        definer: "admin".into(),
+        body: "delete from logs\n  where ts < 5".into(),
+    };
+    let sql = create_event(&event);
+    assert_eq!(
+        sql,
+        "CREATE EVENT cleanup ON SCHEDULE EVERY 2 HOUR DO delete from logs where ts < 5;"
+    );
+    match Parser::create(sql.trim_end_matches(';')).parse().unwrap() {
+        Query::DefStmt(DefStmt::Create(CreateStmt::Event(stmt))) => {
+            assert_eq!(stmt.every, event.every);
+            assert_eq!(stmt.body, "delete from logs where ts < 5")
+        }
+        q => panic!("unexpected query {:?}", q),
+    }
+}
@@ -0,0 +1,351 @@
+//! Query plans shown by `EXPLAIN`
+//!
+//! `EXPLAIN SELECT ...` returns the operators the executor would use for a
+//! select without running it, as a tree: every operator reads the rows of
+//! its children, the first child of a join is its outer side. The plan is
+//! made by the same decisions the executor takes, e.g. whether an index is
+//! used, see `Executor::explain_select`.
+//!
+//! Numbers of rows are estimates. Those of a table come from the statistics
+//! of `ANALYZE` or from the count kept by the flat file engine, the shares
+//! of the rows fulfilling a condition from the statistics; without them the
+//! number is unknown. The executor chooses the operator of a join by the
+//! actual numbers of rows, the plan by the estimates, taking unknown ones
+//! as large. Costs are those of `planner`, an operator without an estimate
+//! of its own shows the sum of the costs of the operators it reads from.
+
+use dump;
+use parse::ast::{Limit, Order, Sort, Window};
+use parse::token::Lit;
+use planner::Estimate;
+use storage::types::TEXT_SIZE;
+use storage::{Column, Error, Rows, SqlType};
+
+use std::io::Cursor;
+
+/// An operator of a plan with the operators it reads from
+#[derive(Clone, Debug, PartialEq)]
+pub struct Plan {
+    pub operator: &'static str,
+    /// The table or view the operator reads
+    pub table: Option<String>,
+    /// What the operator works with, like its index or condition
+    pub detail: String,
+    /// Estimated number of rows the operator returns
+    pub rows: Option<u64>,
+    /// Estimated cost of the operator with those it reads from
+    pub cost: Option<f64>,
+    pub children: Vec<Plan>,
+}
+
+impl Plan {
+    pub fn new(operator: &'static str, detail: String, rows: Option<u64>) -> Plan {
+        Plan {
+            operator: operator,
+            table: None,
+            detail: detail,
+            rows: rows,
+            cost: None,
+            children: Vec::new(),
+        }
+    }
+
+    /// Returns the plan with an estimated cost.
+    pub fn cost(mut self, cost: Option<f64>) -> Plan {
+        self.cost = cost;
+        self
+    }
+
+    /// Returns the estimated cost of the operator, or else the sum of the
+    /// costs of the operators it reads from, if any is known.
+    pub fn total_cost(&self) -> Option<f64> {
+        self.cost.or_else(|| {
+            self.children
+                .iter()
+                .filter_map(Plan::total_cost)
+                .fold(None, |sum, cost| Some(sum.unwrap_or(0.0) + cost))
+        })
+    }
+
+    /// Returns the plan reading a table.
+    pub fn table(mut self, table: &str) -> Plan {
+        self.table = Some(table.to_string());
+        self
+    }
+
+    /// Returns the plan with another operator to read from.
+    pub fn child(mut self, child: Plan) -> Plan {
+        self.children.push(child);
+        self
+    }
+
+    /// Returns the result of `EXPLAIN`, a row for every operator in the
+    /// order of a depth-first walk. An operator refers to the one reading
+    /// its rows by `parent` and is indented by its depth.
+    pub fn rows(&self) -> Result<Rows<Cursor<Vec<u8>>>, Error> {
+        let columns = vec![
+            Column::new("id", SqlType::Int, false, "operator", true),
+            Column::new(
+                "parent",
+                SqlType::Int,
+                true,
+                "operator reading the rows",
+                false,
+            ),
+            Column::new("operator", SqlType::VarChar(64), false, "operator", false),
+            Column::new(
+                "table",
+                SqlType::VarChar(64),
+                true,
+                "table or view read",
+                false,
+            ),
+            Column::new("detail", SqlType::Text, false, "index or condition", false),
+            Column::new("rows", SqlType::BigInt, true, "estimated rows", false),
+            Column::new("cost", SqlType::Double, true, "estimated cost", false),
+        ];
+        let mut rows = Rows::new(Cursor::new(Vec::new()), &columns);
+        let mut id = 0;
+        try!(self.add_rows(&mut rows, &mut id, None, 0));
+        Ok(rows)
+    }
+
+    fn add_rows(
+        &self,
+        rows: &mut Rows<Cursor<Vec<u8>>>,
+        id: &mut i64,
+        parent: Option<i64>,
+        depth: usize,
+    ) -> Result<(), Error> {
+        *id += 1;
+        let own = *id;
+        let mut detail = self.detail.clone();
+        if detail.len() > TEXT_SIZE as usize {
+            let mut end = TEXT_SIZE as usize;
+            while !detail.is_char_boundary(end) {
+                end -= 1;
+            }
+            detail.truncate(end);
+        }
+        let values = [
+            Some(Lit::Int(own)),
+            parent.map(Lit::Int),
+            Some(Lit::String(format!(
+                "{}{}",
+                "  ".repeat(depth),
+                self.operator
+            ))),
+            self.table.clone().map(Lit::String),
+            Some(Lit::String(detail)),
+            self.rows.map(|rows| Lit::Int(rows as i64)),
+            self.total_cost().map(|cost| Lit::Float(cost.round())),
+        ];
+        let mut encoded = Vec::with_capacity(values.len());
+        for (column, value) in rows.columns.iter().zip(&values) {
+            encoded.push(match *value {
+                Some(ref value) => {
+                    let mut data = Vec::new();
+                    try!(column.sql_type.encode_into(&mut data, value));
+                    Some(data)
+                }
+                None => None,
+            });
+        }
+        try!(rows.add_values(&encoded));
+        for child in &self.children {
+            try!(child.add_rows(rows, id, Some(own), depth + 1));
+        }
+        Ok(())
+    }
+}
+
+/// Returns the estimated number of rows a share of them makes up.
+pub fn share(rows: Option<u64>, share: Option<f64>) -> Option<u64> {
+    match (rows, share) {
+        (Some(rows), Some(share)) => Some((rows as f64 * share.max(0.0).min(1.0)).ceil() as u64),
+        _ => None,
+    }
+}
+
+/// Estimates the rows of a join: an equi-join is taken to find about one
+/// row of the smaller side for every row of the larger one, other joins
+/// combine every pair.
+pub fn join_rows(outer: Option<u64>, inner: Option<u64>, equi: bool) -> Option<u64> {
+    match (outer, inner) {
+        (Some(outer), Some(inner)) if equi => Some(outer.max(inner)),
+        (Some(outer), Some(inner)) => Some(outer.saturating_mul(inner)),
+        _ => None,
+    }
+}
+
+/// Returns an estimated number of rows for `join::choose`, unknown ones
+/// count as large.
+pub fn count(rows: Option<u64>) -> usize {
+    rows.map_or(usize::max_value(), |rows| rows as usize)
+}
+
+/// Describes the columns of `ORDER BY`.
+pub fn order(order: &[Sort]) -> String {
+    order
+        .iter()
+        .map(|sort| {
+            let column = match sort.alias {
+                Some(ref alias) => format!("{}.{}", alias, sort.col),
+                None => sort.col.clone(),
+            };
+            match sort.order {
+                Some(Order::Desc) => format!("{} desc", column),
+                _ => column,
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Returns the plan sorting the rows of a plan by the columns of
+/// `ORDER BY`.
+pub fn sort(plan: Plan, sort: &[Sort]) -> Plan {
+    let cost = match (plan.rows, plan.total_cost()) {
+        (Some(rows), Some(cost)) => Some(
+            Estimate {
+                rows: rows as f64,
+                cost: cost,
+            }
+            .sort()
+            .cost,
+        ),
+        _ => None,
+    };
+    let rows = plan.rows;
+    Plan::new("sort", order(sort), rows).cost(cost).child(plan)
+}
+
+/// Returns the plan computing a window function for the rows of a plan,
+/// which are sorted by its partition and order first.
+pub fn window(plan: Plan, window: &Window) -> Plan {
+    let mut detail = dump::literal(&window.function.call());
+    if !window.partition.is_empty() {
+        detail.push_str(&format!(" partition by {}", order(&window.partition)));
+    }
+    if !window.order.is_empty() {
+        detail.push_str(&format!(" order by {}", order(&window.order)));
+    }
+    let keys: Vec<Sort> = window
+        .partition
+        .iter()
+        .chain(&window.order)
+        .cloned()
+        .collect();
+    let rows = plan.rows;
+    let input = if keys.is_empty() {
+        plan
+    } else {
+        sort(plan, &keys)
+    };
+    Plan::new("window", detail, rows).child(input)
+}
+
+/// Returns the plan taking the rows of `LIMIT` from a plan.
+pub fn limit(plan: Plan, limit: &Limit) -> Plan {
+    let count = limit.count.map(|count| count.max(0) as u64);
+    let offset = limit.offset.unwrap_or(0).max(0) as u64;
+    let detail = match (count, offset) {
+        (Some(count), 0) => format!("{} rows", count),
+        (Some(count), offset) => format!("{} rows after {}", count, offset),
+        (None, offset) => format!("rows after {}", offset),
+    };
+    let rows = plan.rows.map(|rows| {
+        let rest = rows.saturating_sub(offset);
+        count.map_or(rest, |count| rest.min(count))
+    });
+    Plan::new("limit", detail, rows).child(plan)
+}
+
+#[test]
+fn test_plan_rows() {
+    let scan = |table: &str, rows: Option<u64>| {
+        Plan::new("full scan", String::new(), rows)
+            .table(table)
+            .cost(rows.map(|rows| rows as f64))
+    };
+    let join = Plan::new(
+        "hash join",
+        "a.id = b.a".into(),
+        join_rows(Some(10), Some(40), true),
+    )
+    .child(scan("a", Some(10)))
+    .child(scan("b", Some(40)));
+    let plan = limit(
+        sort(
+            join,
+            &[Sort {
+                alias: Some("a".into()),
+                col: "id".into(),
+                order: Some(Order::Desc),
+            }],
+        ),
+        &Limit {
+            count: Some(5),
+            offset: Some(37),
+        },
+    );
+    assert_eq!(plan.rows, Some(3));
+
+    let mut rows = plan.rows().unwrap();
+    let mut all = Vec::new();
+    let mut row = Vec::new();
+    rows.reset_pos().unwrap();
+    while rows.next_row(&mut row).is_ok() {
+        let mut values = Vec::new();
+        for i in 0..rows.columns.len() {
+            let value = rows.get_nullable_value(&row, i).unwrap();
+            values.push(
+                match value.map(|v| rows.columns[i].sql_type.decode_from(&mut &v[..])) {
+                    Some(Ok(Lit::Int(i))) => i.to_string(),
+                    Some(Ok(Lit::String(s))) => s,
+                    Some(Ok(Lit::Float(f))) => f.to_string(),
+                    Some(other) => panic!("{:?}", other),
+                    None => "null".into(),
+                },
+            );
+        }
+        all.push(values);
+        row.clear();
+    }
+    let ids: Vec<_> = all.iter().map(|r| (&r[0][..], &r[1][..])).collect();
+    assert_eq!(
+        ids,
+        vec![
+            ("1", "null"),
+            ("2", "1"),
+            ("3", "2"),
+            ("4", "3"),
+            ("5", "3")
+        ]
+    );
+    assert_eq!(all[3][2], "      full scan");
+    assert_eq!(all[4][3], "b");
+    assert_eq!(all[2][5], "40");
+    // the join shows the costs of its scans, the sort adds its own
+    assert_eq!(all[2][6], "50");
+    assert_eq!(all[1][3], "null");
+    assert_eq!(all[1][6], (50.0 + 40.0 * 40f64.log2()).round().to_string());
+}
+
+#[test]
+fn test_estimates() {
+    assert_eq!(share(Some(200), Some(0.015)), Some(3));
+    assert_eq!(share(None, Some(0.5)), None);
+    assert_eq!(join_rows(Some(3), Some(4), false), Some(12));
+    assert_eq!(join_rows(Some(3), None, true), None);
+    assert_eq!(count(None), usize::max_value());
+    let sort = |alias: Option<&str>, order| Sort {
+        alias: alias.map(Into::into),
+        col: "x".into(),
+        order: order,
+    };
+    assert_eq!(
+        order(&[sort(Some("t"), Some(Order::Desc)), sort(None, None)]),
+        "t.x desc, x"
+    );
+}
@@ -0,0 +1,297 @@
+//! Functions defined by `CREATE FUNCTION`
+//!
+//! A function like `CREATE FUNCTION double(x INT) RETURNS INT AS 'x * 2'`
+//! is an expression of its parameters, a macro: before a statement is
+//! executed, every call of it is replaced by its body with the arguments of
+//! the call in place of the parameters, see `expand_query`. So `SELECT
+//! double(price) FROM items` runs like `SELECT price * 2 FROM items`, and a
+//! condition on a call is planned like any other expression.
+//!
+//! The body is kept as written and parsed whenever it is expanded, like the
+//! select of a view. It may call built-in functions and functions defined
+//! before, but no function may call itself: calls nested deeper than
+//! `MAX_DEPTH` are taken to be recursive. The types of the parameters and
+//! of the result are checked once, when the function is created.
+
+use parse::ast::{
+    Col, CompoundStmt, CondType, Conditions, CreateStmt, DefStmt, ManipulationStmt, OnDuplicate,
+    Query, SelectStmt, SetExpr, WindowFunction,
+};
+use parse::parser::{ParseError, Parser};
+use parse::token::Lit;
+use storage::Function;
+
+/// Most calls of functions nested in the expansion of a call
+pub const MAX_DEPTH: usize = 32;
+
+/// Names of the built-in functions, which no function may be named like
+pub const BUILT_IN: &'static [&'static str] = &[
+    "now",
+    "current_timestamp",
+    "current_date",
+    "curdate",
+    "current_time",
+    "curtime",
+    "datediff",
+    "concat",
+    "upper",
+    "lower",
+    "length",
+    "substr",
+    "substring",
+    "abs",
+    "round",
+    "count",
+    "row_number",
+    "rank",
+    "sum",
+    "avg",
+];
+
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    /// A function calls itself, directly or through other functions, holds
+    /// its name
+    Recursive(String),
+    /// A call has another number of arguments than the function has
+    /// parameters, holds its name
+    Arguments(String),
+    /// The body of a function is no expression
+    Parse(ParseError),
+}
+
+impl From<ParseError> for Error {
+    fn from(error: ParseError) -> Error {
+        Error::Parse(error)
+    }
+}
+
+/// Returns the parsed body of a function.
+pub fn body(function: &Function) -> Result<Lit, ParseError> {
+    Parser::create(&function.body).parse_expression()
+}
+
+/// Replaces the calls of functions in a statement, other statements than
+/// queries, data changes and `CREATE VIEW` stay as they are.
+pub fn expand_query(query: &mut Query, functions: &[Function]) -> Result<(), Error> {
+    if functions.is_empty() {
+        return Ok(());
+    }
+    match *query {
+        Query::ManipulationStmt(ref mut stmt) => match *stmt {
+            ManipulationStmt::Select(ref mut stmt) => expand_select(stmt, functions),
+            ManipulationStmt::Compound(ref mut stmt) | ManipulationStmt::Explain(ref mut stmt) => {
+                expand_compound(stmt, functions)
+            }
+            ManipulationStmt::Insert(ref mut stmt) => {
+                for row in &mut stmt.val {
+                    for value in row.iter_mut() {
+                        try!(expand(value, functions));
+                    }
+                }
+                if let Some(OnDuplicate::Update(ref mut set)) = stmt.on_duplicate {
+                    for assignment in set.iter_mut() {
+                        if let CondType::Literal(ref mut value) = assignment.rhs {
+                            try!(expand(value, functions));
+                        }
+                    }
+                }
+                Ok(())
+            }
+            ManipulationStmt::InsertSelect(ref mut stmt) => {
+                expand_compound(&mut stmt.select, functions)
+            }
+            ManipulationStmt::Update(ref mut stmt) => {
+                for assignment in &mut stmt.set {
+                    if let CondType::Literal(ref mut value) = assignment.rhs {
+                        try!(expand(value, functions));
+                    }
+                }
+                match stmt.conds {
+                    Some(ref mut cond) => expand_condition(cond, functions),
+                    None => Ok(()),
+                }
+            }
+            ManipulationStmt::Delete(ref mut stmt) => {
+                if let Some(ref mut cond) = stmt.cond {
+                    try!(expand_condition(cond, functions));
+                }
+                for target in &mut stmt.returning {
+                    try!(expand_col(&mut target.col, functions));
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        },
+        Query::DefStmt(DefStmt::Create(CreateStmt::View(ref mut stmt))) => {
+            expand_select(&mut stmt.sel, functions)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Replaces the calls of functions in selects combined by set operations.
+pub fn expand_compound(stmt: &mut CompoundStmt, functions: &[Function]) -> Result<(), Error> {
+    expand_set(&mut stmt.select, functions)
+}
+
+fn expand_set(set: &mut SetExpr, functions: &[Function]) -> Result<(), Error> {
+    match *set {
+        SetExpr::Select(ref mut stmt) => expand_select(stmt, functions),
+        SetExpr::Op(_, ref mut a, ref mut b) => {
+            try!(expand_set(a, functions));
+            expand_set(b, functions)
+        }
+    }
+}
+
+/// Replaces the calls of functions in the targets and conditions of a
+/// select and of its subqueries.
+pub fn expand_select(stmt: &mut SelectStmt, functions: &[Function]) -> Result<(), Error> {
+    for target in &mut stmt.target {
+        try!(expand_col(&mut target.col, functions));
+    }
+    for join in &mut stmt.joins {
+        try!(expand_condition(&mut join.cond, functions));
+    }
+    match stmt.cond {
+        Some(ref mut cond) => expand_condition(cond, functions),
+        None => Ok(()),
+    }
+}
+
+fn expand_col(col: &mut Col, functions: &[Function]) -> Result<(), Error> {
+    match *col {
+        Col::Expr(ref mut lit) => expand(lit, functions),
+        Col::Window(ref mut window) => match window.function {
+            WindowFunction::Sum(ref mut lit) | WindowFunction::Avg(ref mut lit) => {
+                expand(lit, functions)
+            }
+            WindowFunction::RowNumber | WindowFunction::Rank => Ok(()),
+        },
+        Col::Specified(_) | Col::Every | Col::Count => Ok(()),
+    }
+}
+
+/// Replaces the calls of functions in a condition.
+pub fn expand_condition(cond: &mut Conditions, functions: &[Function]) -> Result<(), Error> {
+    match *cond {
+        Conditions::Leaf(ref mut c) => match c.rhs {
+            CondType::Literal(ref mut lit) => expand(lit, functions),
+            CondType::Word(_) => Ok(()),
+        },
+        Conditions::And(ref mut a, ref mut b) | Conditions::Or(ref mut a, ref mut b) => {
+            try!(expand_condition(a, functions));
+            expand_condition(b, functions)
+        }
+        Conditions::Expr(ref mut lhs, _, ref mut rhs) => {
+            try!(expand(lhs, functions));
+            expand(rhs, functions)
+        }
+        Conditions::In(ref mut lhs, ref mut values, _) => {
+            try!(expand(lhs, functions));
+            for value in values.iter_mut() {
+                try!(expand(value, functions));
+            }
+            Ok(())
+        }
+        Conditions::Exists(ref mut stmt, _) => expand_select(stmt, functions),
+    }
+}
+
+/// Replaces the calls of functions in an expression by their bodies.
+pub fn expand(lit: &mut Lit, functions: &[Function]) -> Result<(), Error> {
+    expand_nested(lit, functions, 0)
+}
+
+fn expand_nested(lit: &mut Lit, functions: &[Function], depth: usize) -> Result<(), Error> {
+    let expanded = match *lit {
+        Lit::Call(ref name, ref mut args) => {
+            for arg in args.iter_mut() {
+                try!(expand_nested(arg, functions, depth));
+            }
+            let function = match functions.iter().find(|f| f.name == *name) {
+                Some(function) => function,
+                None => return Ok(()),
+            };
+            if depth >= MAX_DEPTH {
+                return Err(Error::Recursive(name.clone()));
+            }
+            if args.len() != function.params.len() {
+                return Err(Error::Arguments(name.clone()));
+            }
+            let mut body = try!(body(function));
+            try!(expand_nested(&mut body, functions, depth + 1));
+            substitute(&mut body, &function.params, args);
+            body
+        }
+        Lit::Subquery(ref mut stmt) => return expand_select(stmt, functions),
+        _ => return Ok(()),
+    };
+    *lit = expanded;
+    Ok(())
+}
+
+/// Replaces the parameters in a body by the arguments of a call.
+fn substitute<T>(body: &mut Lit, params: &[(String, T)], args: &[Lit]) {
+    let arg = match *body {
+        Lit::Column(None, ref name) => match params.iter().position(|p| p.0 == *name) {
+            Some(i) => args[i].clone(),
+            None => return,
+        },
+        Lit::Call(_, ref mut inner) => {
+            for lit in inner.iter_mut() {
+                substitute(lit, params, args);
+            }
+            return;
+        }
+        _ => return,
+    };
+    *body = arg;
+}
+
+#[test]
+fn test_expand_calls() {
+    use storage::SqlType;
+
+    let function = |name: &str, params: &[&str], body: &str| Function {
+        name: name.into(),
+        params: params
+            .iter()
+            .map(|p| (p.to_string(), SqlType::Int))
+            .collect(),
+        returns: SqlType::Int,
+        body: body.into(),
+    };
+    let parse = |text: &str| Parser::create(text).parse_expression().unwrap();
+    let functions = vec![
+        function("double", &["x"], "x * 2"),
+        function("add", &["a", "b"], "a + b"),
+        function("quad", &["x"], "double(double(x))"),
+    ];
+    let expanded = |text: &str| {
+        let mut lit = parse(text);
+        expand(&mut lit, &functions).map(|_| lit)
+    };
+
+    // the arguments take the place of the parameters as a whole
+    assert_eq!(expanded("double(1 + price)"), Ok(parse("(1 + price) * 2")));
+    assert_eq!(
+        expanded("add(double(a), b) - 1"),
+        Ok(parse("(a * 2 + b) - 1"))
+    );
+    assert_eq!(expanded("quad(y)"), Ok(parse("(y * 2) * 2")));
+    assert_eq!(expanded("upper(name)"), Ok(parse("upper(name)")));
+    assert_eq!(expanded("add(1)"), Err(Error::Arguments("add".into())));
+
+    let functions = vec![
+        function("f", &["x"], "g(x)"),
+        function("g", &["x"], "f(x) + 1"),
+    ];
+    let mut lit = parse("f(1)");
+    assert_eq!(
+        expand(&mut lit, &functions),
+        Err(Error::Recursive("f".into()))
+    );
+    assert!(Parser::create("x * 2 y").parse_expression().is_err());
+}
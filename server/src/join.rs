@@ -0,0 +1,324 @@
+//! Join operators
+//!
+//! The executor combines the rows of a joined table with the rows read so
+//! far by one of three operators, see `choose`. A nested loop compares
+//! every pair of rows and handles any condition. Joins on the equality of
+//! columns (equi-joins) of larger tables use a hash join or a sort-merge
+//! join, which only look at pairs whose key values are equal.
+//!
+//! The hash join builds a hash table of the smaller input and probes it
+//! with the rows of the other one. If the smaller input does not fit into
+//! the memory budget, both inputs are first split by the hash of their keys
+//! into partitions written to temporary files, which are then joined one
+//! after another, see `spill`. The sort-merge join sorts both inputs by
+//! their keys and walks through them side by side, its result is ordered by
+//! the keys.
+//!
+//! Rows whose key has a null value never match, like in SQL.
+
+use spill::SpillFile;
+use storage::types::{self, row_size};
+use storage::{Column, Error};
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::io::Write;
+
+/// Number of row pairs up to which a nested loop is used for equi-joins too
+pub const NESTED_LOOP_PAIRS: usize = 4096;
+
+/// Most partitions a hash join splits its inputs into
+const MAX_PARTITIONS: usize = 64;
+
+/// The operator joining two inputs
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Method {
+    NestedLoop,
+    Hash,
+    SortMerge,
+}
+
+/// Chooses the operator for joining `outer` rows with `inner` rows. Only
+/// equi-joins can use a hash join or a sort-merge join; the latter is
+/// chosen if the result is wanted in the order of the join key, so that it
+/// need not be sorted afterwards.
+pub fn choose(outer: usize, inner: usize, equi: bool, ordered_by_key: bool) -> Method {
+    if !equi || outer.saturating_mul(inner) <= NESTED_LOOP_PAIRS {
+        Method::NestedLoop
+    } else if ordered_by_key {
+        Method::SortMerge
+    } else {
+        Method::Hash
+    }
+}
+
+/// One side of a join: its rows and the columns they are made of
+pub struct Input<'a> {
+    pub columns: &'a [Column],
+    pub rows: Vec<Vec<u8>>,
+    /// columns making up the join key, compared in pairs with those of the
+    /// other side
+    pub keys: Vec<usize>,
+}
+
+impl<'a> Input<'a> {
+    /// Returns the key of a row, a value of the same order for equal values
+    /// of the key columns, or None if one of them is null.
+    fn key(&self, row: &[u8]) -> Option<Vec<u8>> {
+        let mut key = Vec::new();
+        for &i in &self.keys {
+            if types::is_null(self.columns, row, i) {
+                return None;
+            }
+            let value = &row[types::column_range(self.columns, i)];
+            key.extend(self.columns[i].sql_type.sort_key(value));
+        }
+        Some(key)
+    }
+
+    fn size(&self) -> usize {
+        self.rows.len() * row_size(self.columns)
+    }
+}
+
+/// Joins two inputs with a hash table of the smaller one. Calls `emit` with
+/// the left and the right row of every pair whose keys are equal.
+pub fn hash_join<E, F>(left: Input, right: Input, budget: usize, mut emit: F) -> Result<(), E>
+where
+    E: From<Error>,
+    F: FnMut(&[u8], &[u8]) -> Result<(), E>,
+{
+    // the hash table is built from the smaller input
+    let swapped = right.size() < left.size();
+    let (mut build, mut probe) = if swapped {
+        (right, left)
+    } else {
+        (left, right)
+    };
+    let mut emit_pair = |build_row: &[u8], probe_row: &[u8]| {
+        if swapped {
+            emit(probe_row, build_row)
+        } else {
+            emit(build_row, probe_row)
+        }
+    };
+
+    if build.size() <= budget {
+        let table = build_table(&build, build.rows.iter().cloned());
+        return probe_table(
+            &table,
+            &probe,
+            probe.rows.iter().map(|r| &r[..]),
+            &mut emit_pair,
+        );
+    }
+
+    let partitions = ((build.size() / budget.max(1)) * 2)
+        .max(2)
+        .min(MAX_PARTITIONS);
+    let build_files = try!(partition(&build, partitions));
+    let probe_files = try!(partition(&probe, partitions));
+    // the rows live in the partitions now
+    build.rows = Vec::new();
+    probe.rows = Vec::new();
+    for (build_file, probe_file) in build_files.iter().zip(&probe_files) {
+        // partitions larger than the budget are still joined in memory
+        let build_rows = try!(build_file.read(row_size(build.columns)));
+        let table = build_table(&build, build_rows.into_iter());
+        if table.is_empty() {
+            continue;
+        }
+        let probe_rows = try!(probe_file.read(row_size(probe.columns)));
+        try!(probe_table(
+            &table,
+            &probe,
+            probe_rows.iter().map(|r| &r[..]),
+            &mut emit_pair
+        ));
+    }
+    Ok(())
+}
+
+/// Joins two inputs by sorting both by their keys. Calls `emit` with the
+/// left and the right row of every pair whose keys are equal, ordered by
+/// the keys and otherwise in the order of the inputs.
+pub fn merge_join<E, F>(left: Input, right: Input, mut emit: F) -> Result<(), E>
+where
+    E: From<Error>,
+    F: FnMut(&[u8], &[u8]) -> Result<(), E>,
+{
+    let left_rows = sorted(&left);
+    let right_rows = sorted(&right);
+    let (mut i, mut j) = (0, 0);
+    while i < left_rows.len() && j < right_rows.len() {
+        let key = &left_rows[i].0;
+        if key < &right_rows[j].0 {
+            i += 1;
+        } else if key > &right_rows[j].0 {
+            j += 1;
+        } else {
+            let left_end = i + left_rows[i..].iter().take_while(|r| &r.0 == key).count();
+            let right_end = j + right_rows[j..].iter().take_while(|r| &r.0 == key).count();
+            for &(_, left_row) in &left_rows[i..left_end] {
+                for &(_, right_row) in &right_rows[j..right_end] {
+                    try!(emit(left_row, right_row));
+                }
+            }
+            i = left_end;
+            j = right_end;
+        }
+    }
+    Ok(())
+}
+
+/// Returns the rows with a key, stably sorted by it.
+fn sorted<'a>(input: &'a Input) -> Vec<(Vec<u8>, &'a [u8])> {
+    let mut rows: Vec<_> = input
+        .rows
+        .iter()
+        .filter_map(|row| input.key(row).map(|key| (key, &row[..])))
+        .collect();
+    rows.sort_by(|a, b| a.0.cmp(&b.0));
+    rows
+}
+
+fn build_table<I>(build: &Input, rows: I) -> HashMap<Vec<u8>, Vec<Vec<u8>>>
+where
+    I: Iterator<Item = Vec<u8>>,
+{
+    let mut table = HashMap::new();
+    for row in rows {
+        if let Some(key) = build.key(&row) {
+            table.entry(key).or_insert_with(Vec::new).push(row);
+        }
+    }
+    table
+}
+
+fn probe_table<'r, I, E, F>(
+    table: &HashMap<Vec<u8>, Vec<Vec<u8>>>,
+    probe: &Input,
+    rows: I,
+    emit: &mut F,
+) -> Result<(), E>
+where
+    I: Iterator<Item = &'r [u8]>,
+    F: FnMut(&[u8], &[u8]) -> Result<(), E>,
+{
+    for row in rows {
+        let matches = match probe.key(row).and_then(|key| table.get(&key)) {
+            Some(matches) => matches,
+            None => continue,
+        };
+        for build_row in matches {
+            try!(emit(build_row, row));
+        }
+    }
+    Ok(())
+}
+
+/// Writes the rows with a key into `count` temporary files, chosen by the
+/// hash of the key.
+fn partition(input: &Input, count: usize) -> Result<Vec<SpillFile>, Error> {
+    let mut files = Vec::new();
+    let mut writers = Vec::new();
+    for _ in 0..count {
+        let (file, writer) = try!(SpillFile::create("join"));
+        files.push(file);
+        writers.push(writer);
+    }
+    for row in &input.rows {
+        if let Some(key) = input.key(row) {
+            let mut hasher = DefaultHasher::new();
+            key.hash(&mut hasher);
+            try!(writers[hasher.finish() as usize % count].write_all(row));
+        }
+    }
+    for mut writer in writers {
+        try!(writer.flush());
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+fn test_inputs(columns: &[Column]) -> (Vec<Vec<u8>>, Vec<Vec<u8>>) {
+    use parse::token::Lit;
+    let row = |values: &[Option<i64>]| {
+        let encoded: Vec<_> = values
+            .iter()
+            .map(|v| {
+                v.map(|v| {
+                    let mut data = Vec::new();
+                    columns[0]
+                        .sql_type
+                        .encode_into(&mut data, &Lit::Int(v))
+                        .unwrap();
+                    data
+                })
+            })
+            .collect();
+        types::encode_row(columns, &encoded).unwrap()
+    };
+    let left = (0..100).map(|i| row(&[Some(i % 10), Some(i)])).collect();
+    let mut right: Vec<_> = (0..30).map(|i| row(&[Some(i % 15), Some(i)])).collect();
+    right.push(row(&[None, Some(99)]));
+    (left, right)
+}
+
+/// Returns the keys of the pairs found by a join and the pairs.
+#[cfg(test)]
+fn test_join(method: Method, budget: usize) -> Vec<(Vec<u8>, Vec<u8>, Vec<u8>)> {
+    use storage::SqlType;
+    let columns = vec![
+        Column::new("k", SqlType::Int, true, "", false),
+        Column::new("v", SqlType::Int, false, "", false),
+    ];
+    let (left, right) = test_inputs(&columns);
+    let input = |rows| Input {
+        columns: &columns,
+        rows: rows,
+        keys: vec![0],
+    };
+    let keyed = input(Vec::new());
+    let mut pairs = Vec::new();
+    let result: Result<(), Error> = {
+        let emit = |l: &[u8], r: &[u8]| {
+            assert_eq!(keyed.key(l), keyed.key(r));
+            pairs.push((keyed.key(l).unwrap(), l.to_vec(), r.to_vec()));
+            Ok(())
+        };
+        match method {
+            Method::Hash => hash_join(input(left), input(right), budget, emit),
+            _ => merge_join(input(left), input(right), emit),
+        }
+    };
+    result.unwrap();
+    pairs
+}
+
+#[test]
+fn test_choose() {
+    assert_eq!(choose(10, 10, true, false), Method::NestedLoop);
+    assert_eq!(choose(1000, 1000, false, true), Method::NestedLoop);
+    assert_eq!(choose(1000, 1000, true, false), Method::Hash);
+    assert_eq!(choose(1000, 1000, true, true), Method::SortMerge);
+}
+
+#[test]
+fn test_joins_agree() {
+    // keys 0 to 9 appear 10 times on the left, twice on the right
+    let mut expected = test_join(Method::Hash, ::spill::DEFAULT_MEMORY_BUDGET);
+    assert_eq!(expected.len(), 10 * 10 * 2);
+    expected.sort();
+
+    let mut spilled = test_join(Method::Hash, 64);
+    spilled.sort();
+    assert_eq!(spilled, expected);
+
+    // the result of a sort-merge join is ordered by the keys
+    let mut merged = test_join(Method::SortMerge, ::spill::DEFAULT_MEMORY_BUDGET);
+    assert!(merged.windows(2).all(|w| w[0].0 <= w[1].0));
+    merged.sort();
+    assert_eq!(merged, expected);
+}
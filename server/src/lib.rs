@@ -1,43 +1,267 @@
+extern crate aes_gcm;
+extern crate base64;
+extern crate bcrypt;
 extern crate bincode;
 extern crate byteorder;
+extern crate crc32fast;
 #[macro_use]
 extern crate log;
+extern crate lz4_flex;
+extern crate mio;
+extern crate openssl;
+extern crate regex;
 extern crate serde;
+extern crate serde_json;
+extern crate sha1;
+extern crate sha2;
+extern crate signal_hook;
+extern crate subtle;
 extern crate term_painter as term;
+extern crate toml;
+extern crate zstd;
 
+pub mod audit;
 pub mod auth;
+pub mod cache;
+pub mod catalog;
+pub mod config;
 pub mod conn;
+pub mod csv;
+pub mod dump;
+pub mod explain;
+pub mod function;
+pub mod join;
 pub mod logger;
+pub mod metrics;
 pub mod net;
 pub mod parse;
+pub mod planner;
+pub mod pool;
+pub mod process;
 pub mod query;
+pub mod querylog;
+pub mod replication;
+pub mod scheduler;
+pub mod setop;
+pub mod shutdown;
+pub mod sort;
+pub mod spill;
 pub mod storage;
+pub mod window;
 
-use std::net::{Ipv4Addr, SocketAddrV4};
+pub use config::Config;
 
-/// A struct for managing configurations
-#[derive(Debug)]
-pub struct Config {
-    pub address: Ipv4Addr,
-    pub port: u16,
-    pub dir: String,
-}
+#[cfg(unix)]
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
+#[cfg(unix)]
+use signal_hook::iterator::Signals;
+
+use std::env;
+use std::fs;
+use std::net::{Ipv4Addr, SocketAddrV4, TcpListener, TcpStream};
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
 
 /// Listens for incoming TCP streams
 pub fn listen(config: Config) {
-    use std::net::TcpListener;
-    use std::thread;
+    if let (&Some(ref cert), &Some(ref key)) = (&config.tls_cert, &config.tls_key) {
+        if let Err(e) = net::tls::init(cert, key, config.tls_ca.as_ref().map(|ca| &ca[..])) {
+            error!("Cannot enable TLS: {}", e);
+            return;
+        }
+    }
+    // Everything lives inside the data directory: databases, tables and the
+    // user store. Relative paths used by `storage` and `auth` start there.
+    if let Err(e) = fs::create_dir_all(&config.dir).and_then(|_| env::set_current_dir(&config.dir))
+    {
+        error!("Cannot use data directory '{}': {:?}", config.dir, e);
+        return;
+    }
+    // encrypted tables cannot be opened without it
+    match config.encryption_key() {
+        Ok(key) => storage::encrypt::set_key(key.as_ref()),
+        Err(e) => {
+            error!("Cannot load the encryption key: {}", e);
+            return;
+        }
+    }
+    if let Err(e) = auth::init() {
+        error!("Cannot initialize user store: {:?}", e);
+        return;
+    }
+    if let Err(e) = storage::Database::empty_trash() {
+        warn!("Cannot delete dropped databases: {:?}", e);
+    }
+    if let Err(e) = storage::Database::upgrade_all() {
+        warn!("Cannot upgrade the databases: {:?}", e);
+    }
+    storage::recovery::check_all();
+    storage::transaction::spawn_cleanup();
+    process::spawn_expiry();
+    config.apply();
+    if let Some(ref file) = config.audit_log {
+        if let Err(e) = audit::open(Path::new(file)) {
+            error!("Cannot open audit log '{}': {}", file, e);
+            return;
+        }
+    }
+    if let Some(ref file) = config.replication_log {
+        if let Err(e) = replication::open(Path::new(file)) {
+            error!("Cannot open replication log '{}': {}", file, e);
+            return;
+        }
+    }
+    if let (&Some(ref leader), &Some(ref password)) =
+        (&config.replicate_from, &config.replication_password)
+    {
+        replication::spawn_follower(leader, password);
+    }
+    handle_signals();
+    if config.vacuum_interval > 0 {
+        storage::vacuum::spawn_maintenance(
+            Duration::from_secs(config.vacuum_interval),
+            config.vacuum_threshold,
+        );
+    }
+    scheduler::spawn();
 
     // Converting configurations to a valid socket address
     let sock_addr = SocketAddrV4::new(config.address, config.port);
-    let listener = TcpListener::bind(sock_addr).unwrap();
+    let listener = match TcpListener::bind(sock_addr) {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("Cannot listen on {}: {}", sock_addr, e);
+            return;
+        }
+    };
+
+    // Limits and timeouts are those of the current configuration, which a
+    // reload may change, when a connection is accepted
+
+    // Lives as long as the server, so that the handlers can share it
+    let default_database: Option<&'static str> = config
+        .default_database
+        .map(|d| &*Box::leak(d.into_boxed_str()));
+
+    if let Some(port) = config.mysql_port {
+        spawn_listener("MySQL", config.address, port, move |stream| {
+            let config = config::current();
+            net::mysql::handle(stream, config.limits(), config.idle_timeout())
+        });
+    }
+    if let Some(port) = config.pg_port {
+        spawn_listener("PostgreSQL", config.address, port, move |stream| {
+            let config = config::current();
+            net::pgwire::handle(stream, config.limits(), config.idle_timeout())
+        });
+    }
+
+    if let Some(port) = config.metrics_port {
+        metrics::spawn_endpoint(config.address, port);
+    }
 
-    // Accept connections and process them
+    serve(listener, config.workers, default_database);
+}
+
+/// Serves the connections of the native protocol by the worker pool, see
+/// `pool`.
+#[cfg(unix)]
+fn serve(listener: TcpListener, workers: usize, default_database: Option<&'static str>) {
+    if let Err(e) = pool::serve(listener, workers, default_database) {
+        error!("Cannot serve connections: {}", e);
+    }
+}
+
+/// Serves every connection of the native protocol by a thread of its own,
+/// as there is no poller for the pool.
+#[cfg(not(unix))]
+fn serve(listener: TcpListener, _: usize, default_database: Option<&'static str>) {
+    accept(listener, move |stream| {
+        let config = config::current();
+        conn::handle(
+            stream,
+            config.limits(),
+            config.idle_timeout(),
+            config.send_limits(),
+            default_database,
+        )
+    });
+}
+
+/// Reloads the configuration whenever the server receives `SIGHUP`, see
+/// `config::reload`, and shuts the server down on `SIGTERM` and `SIGINT`,
+/// see `shutdown`.
+#[cfg(unix)]
+fn handle_signals() {
+    let mut signals = match Signals::new(&[SIGHUP, SIGTERM, SIGINT]) {
+        Ok(signals) => signals,
+        Err(e) => {
+            warn!("Cannot handle signals: {}", e);
+            return;
+        }
+    };
+    thread::spawn(move || {
+        for signal in signals.forever() {
+            match signal {
+                SIGHUP => {
+                    if let Err(e) = config::reload() {
+                        error!("Cannot reload the configuration: {}", e);
+                    }
+                }
+                _ => {
+                    let name = if signal == SIGTERM {
+                        "SIGTERM"
+                    } else {
+                        "SIGINT"
+                    };
+                    if !shutdown::begin(name) {
+                        warn!("Exiting without waiting for the connections");
+                        std::process::exit(1);
+                    }
+                }
+            }
+        }
+    });
+}
+
+#[cfg(not(unix))]
+fn handle_signals() {}
+
+/// Accepts connections of a compatibility protocol in a separate thread
+fn spawn_listener<F>(protocol: &str, address: Ipv4Addr, port: u16, handler: F)
+where
+    F: Fn(TcpStream) + Copy + Send + 'static,
+{
+    match TcpListener::bind(SocketAddrV4::new(address, port)) {
+        Ok(listener) => {
+            info!("{} compatibility listener on port {}", protocol, port);
+            thread::spawn(move || accept(listener, handler));
+        }
+        Err(e) => error!(
+            "Cannot listen for {} clients on port {}: {:?}",
+            protocol, port, e
+        ),
+    }
+}
+
+/// Accepts connections and hands each of them to the worker pool, or to a
+/// thread of its own where there is no pool
+fn accept<F>(listener: TcpListener, handler: F)
+where
+    F: Fn(TcpStream) + Copy + Send + 'static,
+{
     for stream in listener.incoming() {
         match stream {
+            // The server shuts down, the stream is closed when dropped
+            Ok(_) if shutdown::is_shutting_down() => {
+                debug!("Refusing connection, the server shuts down");
+            }
+            #[cfg(unix)]
+            Ok(stream) => pool::submit(move || handler(stream)),
+            #[cfg(not(unix))]
             Ok(stream) => {
-                // Connection succeeded: Spawn thread and handle
-                thread::spawn(move || conn::handle(stream));
+                thread::spawn(move || handler(stream));
             }
             Err(e) => {
                 // Something went wrong...
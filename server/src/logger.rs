@@ -60,7 +60,7 @@ impl<'a> Builder<'a> {
     /// more than once in one running program.
     pub fn enable(self) -> io::Result<()> {
         // Try to open the logfile in write-append mode, if any was specified
-        let _file = match self.logfile {
+        let file = match self.logfile {
             Some(path) => Some(try!(fs::OpenOptions::new()
                 .write(true)
                 .append(true)
@@ -69,20 +69,18 @@ impl<'a> Builder<'a> {
             None => None,
         };
 
-        // log::set_logger(|filter: log::LevelFilter| {
-        //     filter.set(self.lvl);
-        //     Box::new(Logger {
-        //         level_filter: filter,
-        //         logfile: file.map(|f| Mutex::new(f)),
-        //         stdout: self.stdout,
-        //     })
-        // })
-        // .map_err(|_| {
-        //     io::Error::new(
-        //         io::ErrorKind::AlreadyExists,
-        //         "method 'enable' was called more than once!",
-        //     )
-        // })
+        // the logger lives as long as the program
+        let logger = Box::new(Logger {
+            logfile: file.map(|f| Mutex::new(f)),
+            stdout: self.stdout,
+        });
+        try!(log::set_logger(Box::leak(logger)).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "method 'enable' was called more than once!",
+            )
+        }));
+        log::set_max_level(self.lvl);
         Ok(())
     }
 }
@@ -90,14 +88,14 @@ impl<'a> Builder<'a> {
 /// Type to do the actual logging. You don't need to interact with it directly:
 /// Use macros and functions of the `log` crate.
 struct Logger {
-    level_filter: log::LevelFilter,
     logfile: Option<Mutex<fs::File>>,
     stdout: bool,
 }
 
 impl log::Log for Logger {
     fn enabled(&self, metadata: &log::Metadata) -> bool {
-        metadata.level() <= self.level_filter.to_level().expect("not set to off")
+        // the level may be changed by `log::set_max_level` at any time
+        metadata.level() <= log::max_level()
     }
 
     fn log(&self, record: &log::Record) {
@@ -156,25 +154,26 @@ impl log::Log for Logger {
 }
 
 fn get_colors(lvl: log::Level) -> (term::Style, term::Style) {
+    use log::Level;
     use term::Color::*;
     use term::{Attr, ToStyle};
 
     // Style for the user's message
     let msg_col = match lvl {
-        _Error => Attr::Bold.fg(Red),
-        _Warn => Attr::Plain.fg(Yellow),
-        _Info => Attr::Plain.fg(White),
-        _Debug => Attr::Plain.fg(NotSet),
-        _Trace => Attr::Dim.fg(NotSet),
+        Level::Error => Attr::Bold.fg(Red),
+        Level::Warn => Attr::Plain.fg(Yellow),
+        Level::Info => Attr::Plain.fg(White),
+        Level::Debug => Attr::Plain.fg(NotSet),
+        Level::Trace => Attr::Dim.fg(NotSet),
     };
 
     // Color for the first info field: The log level
     let lvl_col = match lvl {
-        _Error => Attr::Bold.fg(Red),
-        _Warn => Attr::Plain.fg(Yellow),
-        _Info => Attr::Plain.fg(White),
-        _Debug => Attr::Plain.fg(NotSet),
-        _Trace => Attr::Dim.fg(NotSet),
+        Level::Error => Attr::Bold.fg(Red),
+        Level::Warn => Attr::Plain.fg(Yellow),
+        Level::Info => Attr::Plain.fg(White),
+        Level::Debug => Attr::Plain.fg(NotSet),
+        Level::Trace => Attr::Dim.fg(NotSet),
     };
 
     (lvl_col, msg_col)
@@ -0,0 +1,359 @@
+//! Metrics of the server for monitoring
+//!
+//! Every statement is counted by its type, failed statements also as
+//! errors, and its duration goes into a histogram, see `record`. Together
+//! with the counters of the connections (`process`), of the statement cache
+//! (`cache`) and of the rows read and written (`storage::mvcc`) they are
+//! shown by `SHOW STATUS`, see `status`. If the configuration has a
+//! `metrics_port`, they are also served over HTTP at `/metrics` in the text
+//! format of Prometheus, see `render` and `spawn_endpoint`.
+
+use cache;
+use parse::ast::{ManipulationStmt, Query};
+use process;
+use storage::mvcc;
+
+use std::fmt::Write as FmtWrite;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{Ipv4Addr, SocketAddrV4, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::Duration;
+
+/// Upper bounds of the buckets of the durations of statements in
+/// microseconds, the last bucket has none
+pub const BUCKETS: [u64; 10] = [
+    500, 1_000, 5_000, 10_000, 50_000, 100_000, 500_000, 1_000_000, 5_000_000, 10_000_000,
+];
+
+/// Time a client of the endpoint has to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+const ZERO: AtomicU64 = AtomicU64::new(0);
+
+static STATEMENTS: [AtomicU64; 7] = [ZERO; 7];
+static ERRORS: [AtomicU64; 7] = [ZERO; 7];
+/// Statements by the bucket of their duration, not cumulative
+static DURATIONS: [AtomicU64; 11] = [ZERO; 11];
+/// Sum of the durations of all statements in microseconds
+static DURATION_SUM: AtomicU64 = AtomicU64::new(0);
+
+/// Types statements are counted by
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kind {
+    Select,
+    Insert,
+    Update,
+    Delete,
+    /// Statements changing the schema
+    Definition,
+    Transaction,
+    /// Everything else, also text that is no statement
+    Other,
+}
+
+const KINDS: [Kind; 7] = [
+    Kind::Select,
+    Kind::Insert,
+    Kind::Update,
+    Kind::Delete,
+    Kind::Definition,
+    Kind::Transaction,
+    Kind::Other,
+];
+
+impl Kind {
+    /// Returns the type of a statement.
+    pub fn of(query: &Query) -> Kind {
+        match query {
+            &Query::ManipulationStmt(ref stmt) => match stmt {
+                &ManipulationStmt::Select(_) | &ManipulationStmt::Compound(_) => Kind::Select,
+                &ManipulationStmt::Insert(_) | &ManipulationStmt::InsertSelect(_) => Kind::Insert,
+                &ManipulationStmt::Update(_) => Kind::Update,
+                &ManipulationStmt::Delete(_) => Kind::Delete,
+                _ => Kind::Other,
+            },
+            &Query::DefStmt(_) => Kind::Definition,
+            &Query::TransactionStmt(_) => Kind::Transaction,
+            _ => Kind::Other,
+        }
+    }
+
+    /// Returns the name of the type, as used in the names of the counters.
+    pub fn name(self) -> &'static str {
+        match self {
+            Kind::Select => "select",
+            Kind::Insert => "insert",
+            Kind::Update => "update",
+            Kind::Delete => "delete",
+            Kind::Definition => "ddl",
+            Kind::Transaction => "transaction",
+            Kind::Other => "other",
+        }
+    }
+}
+
+/// Counts a statement of the given type that took `duration`.
+pub fn record(kind: Kind, duration: Duration, success: bool) {
+    STATEMENTS[kind as usize].fetch_add(1, Ordering::SeqCst);
+    if !success {
+        ERRORS[kind as usize].fetch_add(1, Ordering::SeqCst);
+    }
+    let micros = duration.as_micros() as u64;
+    let bucket = BUCKETS
+        .iter()
+        .position(|&bound| micros <= bound)
+        .unwrap_or(BUCKETS.len());
+    DURATIONS[bucket].fetch_add(1, Ordering::SeqCst);
+    DURATION_SUM.fetch_add(micros, Ordering::SeqCst);
+}
+
+/// Returns the counters shown by `SHOW STATUS` by their names.
+pub fn status() -> Vec<(String, u64)> {
+    let connections = process::status();
+    let statements = cache::status();
+    let mut counters = vec![
+        ("connections".to_string(), connections.connections),
+        (
+            "max_used_connections".into(),
+            connections.max_used_connections,
+        ),
+        ("total_connections".into(), connections.total_connections),
+        (
+            "rejected_connections".into(),
+            connections.rejected_connections,
+        ),
+        ("statement_cache_hits".into(), statements.hits),
+        ("statement_cache_misses".into(), statements.misses),
+        ("cached_statements".into(), statements.statements),
+        (
+            "statement_cache_hit_rate".into(),
+            (hit_ratio(&statements) * 100.0).round() as u64,
+        ),
+        ("statements".into(), sum(&STATEMENTS)),
+        ("statement_errors".into(), sum(&ERRORS)),
+    ];
+    for &kind in KINDS.iter() {
+        counters.push((
+            format!("statements_{}", kind.name()),
+            STATEMENTS[kind as usize].load(Ordering::SeqCst),
+        ));
+    }
+    counters.push(("rows_read".into(), mvcc::rows_read()));
+    counters.push(("rows_written".into(), mvcc::rows_written()));
+    counters.push((
+        "statement_time_ms".into(),
+        DURATION_SUM.load(Ordering::SeqCst) / 1000,
+    ));
+    counters
+}
+
+/// Returns all metrics in the text format of Prometheus.
+pub fn render() -> String {
+    let connections = process::status();
+    let statements = cache::status();
+    let mut out = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, samples: &[(String, String)]| {
+        let _ = writeln!(out, "# HELP uosql_{} {}", name, help);
+        let _ = writeln!(out, "# TYPE uosql_{} {}", name, kind);
+        for &(ref suffix, ref value) in samples {
+            let _ = writeln!(out, "uosql_{}{} {}", name, suffix, value);
+        }
+    };
+    let single = |value: u64| vec![(String::new(), value.to_string())];
+    let by_kind = |counters: &[AtomicU64; 7]| {
+        KINDS
+            .iter()
+            .map(|&kind| {
+                (
+                    format!("{{type=\"{}\"}}", kind.name()),
+                    counters[kind as usize].load(Ordering::SeqCst).to_string(),
+                )
+            })
+            .collect::<Vec<_>>()
+    };
+
+    metric(
+        "connections",
+        "gauge",
+        "Open connections",
+        &single(connections.connections),
+    );
+    metric(
+        "max_used_connections",
+        "gauge",
+        "Most connections open at once",
+        &single(connections.max_used_connections),
+    );
+    metric(
+        "connections_total",
+        "counter",
+        "Connections accepted",
+        &single(connections.total_connections),
+    );
+    metric(
+        "rejected_connections_total",
+        "counter",
+        "Connections rejected by a connection limit",
+        &single(connections.rejected_connections),
+    );
+    metric(
+        "statements_total",
+        "counter",
+        "Statements executed",
+        &by_kind(&STATEMENTS),
+    );
+    metric(
+        "statement_errors_total",
+        "counter",
+        "Statements that failed",
+        &by_kind(&ERRORS),
+    );
+    metric(
+        "rows_read_total",
+        "counter",
+        "Rows read from tables",
+        &single(mvcc::rows_read()),
+    );
+    metric(
+        "rows_written_total",
+        "counter",
+        "Rows inserted, deleted or changed",
+        &single(mvcc::rows_written()),
+    );
+    metric(
+        "statement_cache_hits_total",
+        "counter",
+        "Statements found in the statement cache",
+        &single(statements.hits),
+    );
+    metric(
+        "statement_cache_misses_total",
+        "counter",
+        "Statements parsed as they were not in the statement cache",
+        &single(statements.misses),
+    );
+    metric(
+        "statement_cache_hit_ratio",
+        "gauge",
+        "Share of the lookups in the statement cache that found the statement",
+        &[(String::new(), hit_ratio(&statements).to_string())],
+    );
+
+    let mut samples = Vec::new();
+    let mut count = 0;
+    for (i, bound) in BUCKETS.iter().enumerate() {
+        count += DURATIONS[i].load(Ordering::SeqCst);
+        samples.push((
+            format!("_bucket{{le=\"{}\"}}", *bound as f64 / 1e6),
+            count.to_string(),
+        ));
+    }
+    count += DURATIONS[BUCKETS.len()].load(Ordering::SeqCst);
+    samples.push(("_bucket{le=\"+Inf\"}".into(), count.to_string()));
+    let seconds = DURATION_SUM.load(Ordering::SeqCst) as f64 / 1e6;
+    samples.push(("_sum".into(), seconds.to_string()));
+    samples.push(("_count".into(), count.to_string()));
+    metric(
+        "statement_duration_seconds",
+        "histogram",
+        "Durations of statements",
+        &samples,
+    );
+    out
+}
+
+fn sum(counters: &[AtomicU64]) -> u64 {
+    counters.iter().map(|c| c.load(Ordering::SeqCst)).sum()
+}
+
+fn hit_ratio(statements: &cache::Status) -> f64 {
+    match statements.hits + statements.misses {
+        0 => 0.0,
+        lookups => statements.hits as f64 / lookups as f64,
+    }
+}
+
+/// Serves the metrics over HTTP at `/metrics` in a separate thread.
+pub fn spawn_endpoint(address: Ipv4Addr, port: u16) {
+    match TcpListener::bind(SocketAddrV4::new(address, port)) {
+        Ok(listener) => {
+            info!("Metrics endpoint on port {}", port);
+            // Requests are rare and quickly answered, one after another
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    match stream {
+                        Ok(stream) => {
+                            if let Err(e) = answer(stream) {
+                                debug!("Failed to answer a request for the metrics: {}", e);
+                            }
+                        }
+                        Err(e) => warn!("Failed to accept incoming connection: {:?}", e),
+                    }
+                }
+            });
+        }
+        Err(e) => error!("Cannot serve the metrics on port {}: {:?}", port, e),
+    }
+}
+
+/// Answers a single HTTP request, the connection is closed afterwards.
+fn answer(stream: TcpStream) -> ::std::io::Result<()> {
+    try!(stream.set_read_timeout(Some(REQUEST_TIMEOUT)));
+    try!(stream.set_write_timeout(Some(REQUEST_TIMEOUT)));
+    let mut reader = BufReader::new(try!(stream.try_clone()));
+    let mut request = String::new();
+    try!(reader.read_line(&mut request));
+    // The headers are not needed, but must be read before answering
+    let mut header = String::new();
+    while try!(reader.read_line(&mut header)) > 0 && header.trim() != "" {
+        header.clear();
+    }
+
+    let mut words = request.split_whitespace();
+    let (status, body) = match (words.next(), words.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", render()),
+        (Some("GET"), _) => ("404 Not Found", "Not found, see /metrics\n".into()),
+        _ => ("405 Method Not Allowed", "Only GET is allowed\n".into()),
+    };
+    let mut stream = stream;
+    try!(write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    ));
+    stream.flush()
+}
+
+#[test]
+fn test_metrics() {
+    use parse::parser::Parser;
+
+    let kind = |text: &str| Kind::of(&Parser::create(text).parse().unwrap());
+    assert_eq!(kind("select a from t"), Kind::Select);
+    assert_eq!(kind("insert into t values (1)"), Kind::Insert);
+    assert_eq!(kind("drop table t"), Kind::Definition);
+    assert_eq!(kind("begin"), Kind::Transaction);
+    assert_eq!(kind("show status"), Kind::Other);
+
+    record(Kind::Update, Duration::from_millis(2), false);
+    record(Kind::Update, Duration::from_secs(60), true);
+    let text = render();
+    let value = |sample: &str| -> u64 {
+        let line = text.lines().find(|line| line.starts_with(sample)).unwrap();
+        line[sample.len()..].trim().parse().unwrap()
+    };
+    // other tests count statements meanwhile
+    assert!(value("uosql_statements_total{type=\"update\"}") >= 2);
+    assert!(value("uosql_statement_errors_total{type=\"update\"}") >= 1);
+    assert!(value("uosql_statement_duration_seconds_bucket{le=\"0.005\"}") >= 1);
+    assert!(
+        value("uosql_statement_duration_seconds_bucket{le=\"10\"}")
+            < value("uosql_statement_duration_seconds_bucket{le=\"+Inf\"}")
+    );
+    assert!(text.contains("# TYPE uosql_statement_duration_seconds histogram"));
+    assert!(status().iter().any(|&(ref name, _)| name == "rows_written"));
+}
@@ -9,26 +9,109 @@
 //! All communication is send using TCP, which emulates a data stream. On top
 //! of TCP, this database sends single packets.
 //!
-//! Every packet begins with a four byte `length` field that contains the
-//! size of the packet in network byte order.
+//! Every packet consists of a four byte length field, its `PkgType`, the
+//! payload belonging to that type and a CRC32 checksum. The length and the
+//! checksum cover the type and the payload, all numbers are sent in network
+//! byte order. A packet longer than the maximum packet size is rejected with
+//! `Error::PacketTooLarge`, a packet whose checksum does not match with
+//! `Error::CorruptPacket`. After both errors the stream cannot be trusted
+//! anymore.
 //!
-//! ...
+//! Until the login, packets may be up to `MAX_PACKET_SIZE` bytes long.
+//! Afterwards both sides stick to the smaller maximum of server and client,
+//! `Negotiation::max_packet_size`.
 //!
+//! # Handshake
+//! 1. The server sends a `Greeting` with its highest protocol version and
+//!    the `ServerCapabilities`: version, SQL features, default database and
+//!    authentication methods.
+//! 2. The client answers with a `Login` containing the credentials, the
+//!    range of protocol versions and the capabilities it supports.
+//! 3. If the version ranges do not overlap, the server answers with an
+//!    error packet (`Error::ProtocolMismatch`) and closes the connection.
+//!    Otherwise it answers with `AccDenied`, with `TooManyConnections` if a
+//!    connection limit is reached, or with `AccGranted` followed by the
+//!    `Negotiation` both sides have to stick to and the `SessionInfo`.
+//!
+//! Instead of the `Login`, a client whose connection dropped may send
+//! `Command::Resume` with the token of the `SessionInfo`. The server then
+//! answers with `AccDenied` or with `AccGranted` as above, continuing the
+//! session with the negotiation of the original login.
+//!
+//! Before either of them, the client may send `Command::StartTls` to
+//! encrypt the connection, see `tls`. The `Login` or `Command::Resume`
+//! follows after the TLS handshake.
+//!
+//! # Sending results
+//! A response is serialized into a buffer before it is sent. Responses
+//! bigger than `SendLimits::max_response_size` are answered with
+//! `Error::ResponseTooLarge` instead. The buffer is written in small chunks,
+//! at most `SendLimits::rows_per_second` rows per second, and a client that
+//! does not read the whole response within `SendLimits::send_timeout` is
+//! treated like a vanished one.
+//!
+//! # Replication
+//! A follower logs in as the administrator and sends `Command::Replicate`
+//! with the position of the first change it needs. From then on the server
+//! only sends: a `Change` packet for every change of its replication log,
+//! and a `Heartbeat` whenever nothing changed for a while. Nothing is
+//! acknowledged, the follower reconnects with the position it needs next.
+//!
+pub mod mysql;
+pub mod pgwire;
+pub mod tls;
 pub mod types;
 
+pub use self::tls::Stream;
+
 use std;
 use std::fmt;
 use std::io::{self, Read, Write};
+use std::thread;
+use std::time::{Duration, Instant};
 // to encode and decode the structs to the given stream
 use self::types::*;
 
-use bincode::{deserialize_from, serialize_into};
+use bincode::Options;
+use byteorder::{BigEndian, ByteOrder, ReadBytesExt, WriteBytesExt};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 
 use parse::parser::ParseError;
+use process::KillError;
+use storage::types::row_size;
 use storage::ResultSet;
 
-const PROTOCOL_VERSION: u8 = 1;
+/// Highest protocol version this server speaks.
+pub const PROTOCOL_VERSION: u8 = 20;
+/// Lowest protocol version this server still accepts.
+pub const MIN_PROTOCOL_VERSION: u8 = 20;
+/// Optional features implemented by this server.
+pub const SERVER_CAPABILITIES: Capabilities = Capabilities::PREPARED_STATEMENTS;
+/// Longest packet this server accepts, see `Negotiation::max_packet_size`.
+pub const MAX_PACKET_SIZE: u32 = 16 * 1024 * 1024;
 const WELCOME_MSG: &'static str = "Welcome to the fabulous uoSQL database.";
+/// Statements and SQL features announced in the `Greeting`
+const SQL_FEATURES: &'static [&'static str] = &[
+    "SELECT", "INSERT", "DELETE", "CREATE", "ALTER", "DROP", "USE", "DESCRIBE", "SHOW", "GRANT",
+    "REVOKE", "NOTIFY",
+];
+/// Login methods announced in the `Greeting`: a password or the token of a
+/// dropped session
+const AUTH_METHODS: &'static [&'static str] = &["password", "resume"];
+/// Number of bytes written at once if sending is not throttled
+const SEND_CHUNK_SIZE: usize = 8192;
+
+/// Limits for sending query results to slow clients
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SendLimits {
+    /// Maximum length of a response packet in bytes, `0` means unlimited
+    pub max_response_size: u64,
+    /// Maximum number of rows sent per second, `0` means unlimited
+    pub rows_per_second: u64,
+    /// Time the client has to read a whole response, `None` means forever
+    pub send_timeout: Option<Duration>,
+}
 
 /// Collection of possible errors while communicating with the client.
 #[derive(Debug)]
@@ -38,6 +121,52 @@ pub enum Error {
     UnknownCmd,
     Bincode(bincode::Error),
     UnEoq(ParseError),
+    ProtocolMismatch,
+    UnknownConnection,
+    PermissionDenied,
+    ResponseTooLarge,
+    CorruptPacket,
+    PacketTooLarge,
+    UnknownStatement,
+    ReplicationOff,
+    UnknownLsn,
+    /// The client asked for TLS, which the server is not configured for
+    TlsUnavailable,
+}
+
+impl Error {
+    /// Returns true if a read did not complete within the socket read
+    /// timeout.
+    pub fn is_timeout(&self) -> bool {
+        match self.io_kind() {
+            Some(io::ErrorKind::WouldBlock) | Some(io::ErrorKind::TimedOut) => true,
+            _ => false,
+        }
+    }
+
+    /// Returns true if the client closed the connection or vanished.
+    pub fn is_disconnect(&self) -> bool {
+        match self.io_kind() {
+            Some(io::ErrorKind::UnexpectedEof)
+            | Some(io::ErrorKind::ConnectionReset)
+            | Some(io::ErrorKind::ConnectionAborted)
+            | Some(io::ErrorKind::BrokenPipe) => true,
+            _ => false,
+        }
+    }
+
+    /// Returns the kind of the underlying IO error, also if it was wrapped
+    /// by bincode.
+    fn io_kind(&self) -> Option<io::ErrorKind> {
+        match self {
+            &Error::Io(ref e) => Some(e.kind()),
+            &Error::Bincode(ref e) => match **e {
+                bincode::ErrorKind::Io(ref e) => Some(e.kind()),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
 }
 
 /// Implement display for description of Error
@@ -56,6 +185,16 @@ impl std::error::Error for Error {
             &Error::UnknownCmd => "cannot interpret command: unknown",
             &Error::Bincode(_) => "could not encode/decode package",
             &Error::UnEoq(_) => "parsing error",
+            &Error::ProtocolMismatch => "client and server share no protocol version",
+            &Error::UnknownConnection => "no connection with this id",
+            &Error::PermissionDenied => "permission denied",
+            &Error::ResponseTooLarge => "result exceeds the maximum response size",
+            &Error::CorruptPacket => "package checksum mismatch",
+            &Error::PacketTooLarge => "package exceeds the maximum packet size",
+            &Error::UnknownStatement => "no prepared statement with this id",
+            &Error::ReplicationOff => "this server keeps no replication log",
+            &Error::UnknownLsn => "the replication log has no change at this position",
+            &Error::TlsUnavailable => "this server does not offer TLS",
         }
     }
 }
@@ -81,83 +220,373 @@ impl From<ParseError> for Error {
     }
 }
 
-/// Write a welcome-message to the given server-client-stream.
-pub fn do_handshake<W: Write + Read>(mut stream: &mut W) -> Result<(String, String), Error> {
-    let greet = Greeting::make_greeting(PROTOCOL_VERSION, WELCOME_MSG.into());
+/// Implement the conversion from KillError to NetworkError
+impl From<KillError> for Error {
+    fn from(err: KillError) -> Error {
+        match err {
+            KillError::UnknownConnection => Error::UnknownConnection,
+            KillError::PermissionDenied => Error::PermissionDenied,
+        }
+    }
+}
+
+/// Returns the bincode configuration of the wire protocol: fixed size
+/// integers in network byte order.
+fn wire_format() -> impl Options {
+    bincode::DefaultOptions::new()
+        .with_big_endian()
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
+}
+
+/// Encode `value` in the wire format and write it into the stream.
+pub fn serialize_into<W: Write, T: Serialize>(stream: W, value: &T) -> bincode::Result<()> {
+    wire_format().serialize_into(stream, value)
+}
+
+/// Read a value encoded in the wire format from the stream.
+pub fn deserialize_from<R: Read, T: DeserializeOwned>(stream: R) -> bincode::Result<T> {
+    wire_format().deserialize_from(stream)
+}
+
+/// A packet built in memory, so that its length is known before it is sent.
+pub struct PacketWriter {
+    buf: Vec<u8>,
+}
+
+impl PacketWriter {
+    /// Starts a packet of the given type.
+    pub fn new(pkg: PkgType) -> PacketWriter {
+        // the length is filled in by `finish`
+        let mut buf = vec![0; 4];
+        // writing into a Vec cannot fail
+        let _ = serialize_into(&mut buf, &pkg);
+        PacketWriter { buf: buf }
+    }
+
+    /// Appends a value to the payload.
+    pub fn add<T: Serialize>(&mut self, value: &T) -> Result<(), Error> {
+        try!(serialize_into(&mut self.buf, value));
+        Ok(())
+    }
+
+    /// Returns the encoded packet: length, type, payload and checksum.
+    pub fn finish(mut self) -> Vec<u8> {
+        let len = self.buf.len() as u32 - 4;
+        let checksum = crc32fast::hash(&self.buf[4..]);
+        BigEndian::write_u32(&mut self.buf[..4], len);
+        let _ = self.buf.write_u32::<BigEndian>(checksum);
+        self.buf
+    }
+
+    /// Writes the packet into the stream.
+    pub fn send<W: Write>(self, stream: &mut W) -> Result<(), Error> {
+        try!(stream.write_all(&self.finish()));
+        try!(stream.flush());
+        Ok(())
+    }
+}
+
+/// A received packet whose length and checksum were checked
+pub struct Packet {
+    pub pkg: PkgType,
+    payload: Vec<u8>,
+}
+
+impl Packet {
+    /// Returns the payload, to read the values belonging to the packet type
+    /// from.
+    pub fn payload(&self) -> &[u8] {
+        &self.payload
+    }
+}
+
+/// Reads the next packet from the stream.
+///
+/// # Failures
+/// Returns `Error::PacketTooLarge` if the packet is longer than `max_size`
+/// and `Error::CorruptPacket` if its checksum does not match. In both cases
+/// the rest of the stream cannot be read anymore.
+pub fn read_packet<R: Read>(mut stream: R, max_size: u32) -> Result<Packet, Error> {
+    let len = try!(stream.read_u32::<BigEndian>());
+    if len > max_size {
+        return Err(Error::PacketTooLarge);
+    }
+    let mut buf = vec![0; len as usize];
+    try!(stream.read_exact(&mut buf));
+    let checksum = try!(stream.read_u32::<BigEndian>());
+    if checksum != crc32fast::hash(&buf) {
+        return Err(Error::CorruptPacket);
+    }
+
+    let (pkg, header_len) = {
+        let mut rest = &buf[..];
+        let pkg = try!(deserialize_from(&mut rest));
+        (pkg, buf.len() - rest.len())
+    };
+    Ok(Packet {
+        pkg: pkg,
+        payload: buf.split_off(header_len),
+    })
+}
+
+/// The answer of the client to the `Greeting`
+pub enum Hello {
+    /// A login and the negotiated protocol settings of the new session
+    Login(Login, Negotiation),
+    /// The token of a dropped session the client wants to continue
+    Resume(String),
+    /// The client wants to encrypt the connection first
+    StartTls,
+}
+
+/// Returns the description of this server sent in the `Greeting`.
+pub fn server_capabilities(default_database: Option<&str>) -> ServerCapabilities {
+    ServerCapabilities {
+        version: env!("CARGO_PKG_VERSION").into(),
+        build_commit: env!("UOSQL_BUILD_COMMIT").into(),
+        sql_features: SQL_FEATURES.iter().map(|f| f.to_string()).collect(),
+        default_database: default_database.map(|d| d.into()),
+        auth_methods: AUTH_METHODS
+            .iter()
+            .cloned()
+            .chain(if tls::verifies_clients() {
+                Some("certificate")
+            } else {
+                None
+            })
+            .map(|m| m.to_string())
+            .collect(),
+    }
+}
+
+/// Write a welcome-message to the given server-client-stream, read the login
+/// and negotiate the protocol version and capabilities.
+///
+/// Returns `Error::ProtocolMismatch` if the client does not support any
+/// version this server speaks.
+pub fn do_handshake<W: Write + Read>(
+    stream: &mut W,
+    default_database: Option<&str>,
+) -> Result<Hello, Error> {
+    try!(send_greeting(stream, default_database));
+    read_hello(stream, false)
+}
 
-    // send handshake packet to client
-    try!(serialize_into(&mut stream, &PkgType::Greet));
-    try!(serialize_into(&mut stream, &greet));
+/// Sends the welcome-message, the first half of `do_handshake`.
+pub fn send_greeting<W: Write>(
+    stream: &mut W,
+    default_database: Option<&str>,
+) -> Result<(), Error> {
+    let greet = Greeting::make_greeting(
+        PROTOCOL_VERSION,
+        WELCOME_MSG.into(),
+        server_capabilities(default_database),
+    );
+    let mut packet = PacketWriter::new(PkgType::Greet);
+    try!(packet.add(&greet));
+    packet.send(stream)
+}
 
-    // receive login data from client
-    let login = read_login(stream);
-    match login {
-        Ok(sth) => Ok((sth.username, sth.password)),
-        Err(msg) => Err(msg),
+/// Reads the answer to the welcome-message, the second half of
+/// `do_handshake`. On an encrypted connection, `tls` adds the capability
+/// `TLS` to the negotiation.
+pub fn read_hello<R: Read>(stream: &mut R, tls: bool) -> Result<Hello, Error> {
+    // receive login data or a session token from client
+    let packet = try!(read_packet(&mut *stream, MAX_PACKET_SIZE));
+    let login: Login = match packet.pkg {
+        PkgType::Login => try!(deserialize_from(packet.payload())),
+        PkgType::Command => match try!(deserialize_from(packet.payload())) {
+            Command::Resume(token) => return Ok(Hello::Resume(token)),
+            Command::StartTls => return Ok(Hello::StartTls),
+            _ => return Err(Error::UnexpectedPkg),
+        },
+        _ => return Err(Error::UnexpectedPkg),
+    };
+    match Negotiation::choose(
+        &login,
+        MIN_PROTOCOL_VERSION,
+        PROTOCOL_VERSION,
+        if tls {
+            SERVER_CAPABILITIES.with(Capabilities::TLS)
+        } else {
+            SERVER_CAPABILITIES
+        },
+        MAX_PACKET_SIZE,
+    ) {
+        Some(negotiation) => Ok(Hello::Login(login, negotiation)),
+        None => {
+            warn!(
+                "Client speaks protocol {}-{}, server {}-{}",
+                login.min_version, login.max_version, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION
+            );
+            Err(Error::ProtocolMismatch)
+        }
     }
 }
 
 /// Read the data from the response to the handshake,
 /// username and password extracted and returned.
-pub fn read_login<R: Read>(stream: R) -> Result<Login, Error> {
-    // read package-type
-    let status: PkgType = try!(deserialize_from(stream));
-
-    match status {
-        PkgType::Login =>
+pub fn read_login<R: Read>(stream: R, max_size: u32) -> Result<Login, Error> {
+    let packet = try!(read_packet(stream, max_size));
+    match packet.pkg {
         // read the login data
-        {
-            // deserialize_from(stream).map_err(|e| e.into())
-            Err(Error::UnexpectedPkg)
-        }
-        PkgType::Command => {
-            // free the stream
-            // let _: Command = try!(deserialize_from(stream));
-            Err(Error::UnexpectedPkg)
-        }
+        PkgType::Login => Ok(try!(deserialize_from(packet.payload()))),
         _ => Err(Error::UnexpectedPkg),
     }
 }
 
 /// Read the sent bytes, extract the kind of command.
-pub fn read_commands<R: Read>(stream: R) -> Result<Command, Error> {
-    // read the first byte for code numeric value
-    let status: PkgType = try!(deserialize_from(stream));
-
-    match status {
-        PkgType::Login => {
-            // free the stream
-            // let _: Login = try!(deserialize_from(stream));
-            Err(Error::UnexpectedPkg)
-        }
-        PkgType::Command => {
-            // deserialize_from(stream).map_err(|e| e.into());
-            Err(Error::UnexpectedPkg)
-        }
+pub fn read_commands<R: Read>(stream: R, max_size: u32) -> Result<Command, Error> {
+    let packet = try!(read_packet(stream, max_size));
+    match packet.pkg {
+        PkgType::Command => Ok(try!(deserialize_from(packet.payload()))),
         _ => Err(Error::UnexpectedPkg),
     }
 }
 
 /// Send error package with given error code status.
-pub fn send_error_package<W: Write>(mut stream: &mut W, err: ClientErrMsg) -> Result<(), Error> {
-    try!(serialize_into(&mut stream, &PkgType::Error));
-    try!(serialize_into(&mut stream, &err));
-    Ok(())
+pub fn send_error_package<W: Write>(stream: &mut W, err: ClientErrMsg) -> Result<(), Error> {
+    let mut packet = PacketWriter::new(PkgType::Error);
+    try!(packet.add(&err));
+    packet.send(stream)
 }
 
 /// Send information package only with package type information.
-pub fn send_info_package<W: Write>(mut stream: &mut W, pkg: PkgType) -> Result<(), Error> {
-    try!(serialize_into(&mut stream, &pkg));
-    Ok(())
+pub fn send_info_package<W: Write>(stream: &mut W, pkg: PkgType) -> Result<(), Error> {
+    PacketWriter::new(pkg).send(stream)
+}
+
+/// Grant access to the client and tell it the negotiated protocol settings
+/// and how to resume the session.
+pub fn send_acc_granted<W: Write>(
+    stream: &mut W,
+    negotiation: &Negotiation,
+    session: &SessionInfo,
+) -> Result<(), Error> {
+    let mut packet = PacketWriter::new(PkgType::AccGranted);
+    try!(packet.add(negotiation));
+    try!(packet.add(session));
+    packet.send(stream)
 }
 
-/// Send Result package as response to a query.
-pub fn send_response_package<W: Write>(mut stream: &mut W, data: ResultSet) -> Result<(), Error> {
-    try!(serialize_into(&mut stream, &PkgType::Response));
-    try!(serialize_into(&mut stream, &data));
+/// Send Result package as response to a query, obeying the given limits.
+///
+/// # Failures
+/// Returns `Error::ResponseTooLarge` without writing anything if the
+/// response exceeds the maximum size. If the client does not read the
+/// response in time, an IO error of kind `TimedOut` is returned and the
+/// connection is unusable.
+pub fn send_response_package<W: Write>(
+    stream: &mut W,
+    data: ResultSet,
+    limits: &SendLimits,
+) -> Result<(), Error> {
+    // The length field counts the package type and the result
+    let len = 4 + try!(wire_format().serialized_size(&data));
+    if limits.max_response_size > 0 && len > limits.max_response_size {
+        return Err(Error::ResponseTooLarge);
+    }
+
+    let mut packet = PacketWriter::new(PkgType::Response);
+    try!(packet.add(&data));
+    let buf = packet.finish();
+
+    // Without a throttle the buffer is written in big chunks
+    let row_size = row_size(&data.columns);
+    let bytes_per_second = match (limits.rows_per_second, row_size) {
+        (0, _) | (_, 0) => None,
+        (rows, row_size) => Some(rows * row_size as u64),
+    };
+    // Only the rows count against the throttle, not the column definitions,
+    // the length and the checksum
+    let unthrottled = buf.len() - data.data.len();
+    write_throttled(
+        stream,
+        &buf,
+        unthrottled,
+        bytes_per_second,
+        limits.send_timeout,
+    )
+}
+
+/// Writes `buf` in chunks and fails if the stream did not accept everything
+/// within `timeout`. After the first `unthrottled` bytes, at most
+/// `bytes_per_second` are written per second.
+fn write_throttled<W: Write>(
+    stream: &mut W,
+    buf: &[u8],
+    unthrottled: usize,
+    bytes_per_second: Option<u64>,
+    timeout: Option<Duration>,
+) -> Result<(), Error> {
+    // Ten chunks per second keep the throttled stream smooth
+    let chunk_size = bytes_per_second
+        .map(|b| (b / 10).max(1) as usize)
+        .unwrap_or(SEND_CHUNK_SIZE);
+    let start = Instant::now();
+    let mut written = 0;
+    while written < buf.len() {
+        if let Some(timeout) = timeout {
+            if start.elapsed() > timeout {
+                return Err(
+                    io::Error::new(io::ErrorKind::TimedOut, "client reads too slowly").into(),
+                );
+            }
+        }
+        if let Some(bytes_per_second) = bytes_per_second {
+            let throttled = written.saturating_sub(unthrottled);
+            let due = Duration::from_secs_f64(throttled as f64 / bytes_per_second as f64);
+            if let Some(wait) = due.checked_sub(start.elapsed()) {
+                thread::sleep(wait);
+            }
+        }
+        let end = buf.len().min(unthrottled.max(written + chunk_size));
+        match stream.write(&buf[written..end]) {
+            Ok(0) => return Err(io::Error::new(io::ErrorKind::WriteZero, "stream closed").into()),
+            Ok(n) => written += n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    try!(stream.flush());
     Ok(())
 }
 
+/// Send a notification to a listening client.
+pub fn send_notification<W: Write>(
+    stream: &mut W,
+    notification: &Notification,
+) -> Result<(), Error> {
+    let mut packet = PacketWriter::new(PkgType::Notification);
+    try!(packet.add(notification));
+    packet.send(stream)
+}
+
+/// Pushes a `ShutdownWarning` to a client, see `process::warn_shutdown`.
+pub fn send_shutdown_warning<W: Write>(
+    stream: &mut W,
+    warning: &ShutdownWarning,
+) -> Result<(), Error> {
+    let mut packet = PacketWriter::new(PkgType::ShuttingDown);
+    try!(packet.add(warning));
+    packet.send(stream)
+}
+
+/// Streams a change of the replication log to a follower.
+pub fn send_change<W: Write>(stream: &mut W, change: &Change) -> Result<(), Error> {
+    let mut packet = PacketWriter::new(PkgType::Change);
+    try!(packet.add(change));
+    packet.send(stream)
+}
+
+/// Tells a follower how far the leader is, while nothing changes.
+pub fn send_heartbeat<W: Write>(stream: &mut W, heartbeat: &Heartbeat) -> Result<(), Error> {
+    let mut packet = PacketWriter::new(PkgType::Heartbeat);
+    try!(packet.add(heartbeat));
+    packet.send(stream)
+}
+
 // # Some information for the `net` working group:
 //
 // The net module is used by the `conn` module to receive commands from the
@@ -174,26 +603,42 @@ pub fn send_response_package<W: Write>(mut stream: &mut W, data: ResultSet) -> R
 //   receiving commands, sending answers, ...)
 //
 
+/// Writes a packet like a client would.
+#[cfg(test)]
+fn write_packet<T: Serialize>(stream: &mut Vec<u8>, pkg: PkgType, payload: &T) {
+    let mut packet = PacketWriter::new(pkg);
+    packet.add(payload).unwrap();
+    packet.send(stream).unwrap();
+}
+
 #[test]
 pub fn test_send_ok_packet() {
     let mut vec = Vec::new();
 
     let res = send_info_package(&mut vec, PkgType::Ok);
     assert_eq!(res.is_ok(), true);
-    assert_eq!(vec, vec![0, 0, 0, 4]);
+    let checksum = crc32fast::hash(&[0, 0, 0, 4]);
+    let mut expected = vec![0, 0, 0, 4, 0, 0, 0, 4];
+    expected.write_u32::<BigEndian>(checksum).unwrap();
+    assert_eq!(vec, expected);
 }
 
 #[test]
 pub fn test_send_error_packet() {
     let mut vec = Vec::new(); // stream to write into
                               // could not encode/ send package
-    let vec2 = vec![
+    let mut vec2 = vec![
+        0, 0, 0, 52, // length
         0, 0, 0, 3, // for error packet
         0, 2, // for kind of error
         0, 0, 0, 0, 0, 0, 0, 27, // for the size of the message string
         114, 101, 99, 101, 105, 118, 101, 100, 32, 117, 110, 101, 120, 112, 101, 99, 116, 101, 100,
-        32, 112, 97, 99, 107, 97, 103, 101,
-    ]; // string itself
+        32, 112, 97, 99, 107, 97, 103, 101, // string itself
+        0, 0, 0, // no line, column and hint
+        0, 0, 0, 0, 0, 0, 0, 0, // no other errors
+    ];
+    let checksum = crc32fast::hash(&vec2[4..]);
+    vec2.write_u32::<BigEndian>(checksum).unwrap();
     let err = Error::UnexpectedPkg;
 
     // test if the message is sent
@@ -202,6 +647,75 @@ pub fn test_send_error_packet() {
     assert_eq!(vec, vec2);
 }
 
+#[test]
+pub fn test_parse_error_position() {
+    use parse::Parser;
+
+    let query = "SELECT a\nFROM foo\nWHERE = 3";
+    let err = Parser::create(query).parse().unwrap_err();
+    let msg = ClientErrMsg::from_parse_error(&err, query);
+    assert_eq!(msg.code(), ClientErrMsg::PARSE_ERROR);
+    assert_eq!((msg.line, msg.column), (Some(3), Some(7)));
+    assert!(msg.to_string().contains("at line 3, column 7"));
+
+    // a query ending too early points behind its last token
+    let query = "SELECT a FROM";
+    let err = Parser::create(query).parse().unwrap_err();
+    let msg = ClientErrMsg::from_parse_error(&err, query);
+    assert_eq!((msg.line, msg.column), (Some(1), Some(14)));
+
+    // all statements of a script with syntax errors are reported
+    let query = "SELECT a FROM;\nUSE DATABASE x;\nDROP TABLE 'y';\nSELECT FROM foo";
+    let err = Parser::create(query)
+        .recovering()
+        .parse_statements()
+        .unwrap_err();
+    let msg = ClientErrMsg::from_parse_error(&err, query);
+    assert_eq!((msg.line, msg.column), (Some(1), Some(14)));
+    let others: Vec<_> = msg.others.iter().map(|m| (m.line, m.column)).collect();
+    assert_eq!(others, vec![(Some(3), Some(12)), (Some(4), Some(13))]);
+    assert!(msg
+        .to_string()
+        .contains("\nexpected a name at line 3, column 12"));
+}
+
+#[test]
+pub fn test_execution_error_codes() {
+    use query::ExecutionError;
+    use storage;
+
+    // the client reads the packet and takes the code from its payload
+    let received = |error: ExecutionError| {
+        let mut vec = Vec::new();
+        send_error_package(&mut vec, ClientErrMsg::from_execution_error(&error)).unwrap();
+        let packet = read_packet(&vec[..], MAX_PACKET_SIZE).unwrap();
+        assert!(packet.pkg == PkgType::Error);
+        let msg: ClientErrMsg = deserialize_from(packet.payload()).unwrap();
+        msg
+    };
+
+    let msg = received(ExecutionError::StorageError(
+        storage::Error::UniqueKeyValueExists("email".into()),
+    ));
+    assert_eq!(msg.code(), ClientErrMsg::UNIQUE_VIOLATION);
+    assert!(msg.msg.contains("'email'"));
+    let msg = received(ExecutionError::StorageError(
+        storage::Error::PrimaryKeyValueExists,
+    ));
+    assert_eq!(msg.code(), ClientErrMsg::UNIQUE_VIOLATION);
+    let msg = received(ExecutionError::RowIsReferenced("fk_owner".into()));
+    assert_eq!(msg.code(), ClientErrMsg::FOREIGN_KEY_VIOLATION);
+    assert!(msg.msg.contains("'fk_owner'"));
+    let msg = received(ExecutionError::CheckViolation("positive_price".into()));
+    assert_eq!(msg.code(), ClientErrMsg::CHECK_VIOLATION);
+    assert!(msg.msg.contains("'positive_price'"));
+    let msg = received(ExecutionError::NotNullViolation("name".into()));
+    assert_eq!(msg.code(), ClientErrMsg::NOT_NULL_VIOLATION);
+    assert!(msg.msg.contains("'name'"));
+    let msg = received(ExecutionError::DivisionByZero);
+    assert_eq!(msg.code(), ClientErrMsg::EXECUTION_ERROR);
+}
+
 #[test]
 pub fn test_read_commands() {
     // test if the commands are correctly decoded
@@ -209,23 +723,81 @@ pub fn test_read_commands() {
     let mut vec = Vec::new(); // stream to write into
 
     // write the command into the stream
-    let _ = serialize_into(&mut vec, &PkgType::Command);
-    let _ = serialize_into(&mut vec, &Command::Quit);
+    write_packet(&mut vec, PkgType::Command, &Command::Quit);
 
     // read the command from the stream for Command::Quit
-    let mut command_res = read_commands(&mut Cursor::new(vec));
+    let mut command_res = read_commands(&mut Cursor::new(vec), MAX_PACKET_SIZE);
     assert_eq!(command_res.is_ok(), true);
     assert_eq!(command_res.unwrap(), Command::Quit);
 
     let mut vec2 = Vec::new();
     // write the command into the stream
-    let _ = serialize_into(&mut vec2, &PkgType::Command);
-    let _ = serialize_into(&mut vec2, &Command::Query("select".into()));
+    write_packet(
+        &mut vec2,
+        PkgType::Command,
+        &Command::Query("select".into()),
+    );
 
     // read the command from the stream for Command::Query("select")
-    command_res = read_commands(&mut Cursor::new(vec2));
+    command_res = read_commands(&mut Cursor::new(vec2), MAX_PACKET_SIZE);
     assert_eq!(command_res.is_ok(), true);
     assert_eq!(command_res.unwrap(), Command::Query("select".into()));
+
+    let mut vec3 = Vec::new();
+    // write the command into the stream
+    write_packet(&mut vec3, PkgType::Command, &Command::Kill(42));
+
+    // read the command from the stream for Command::Kill(42)
+    command_res = read_commands(&mut Cursor::new(vec3), MAX_PACKET_SIZE);
+    assert_eq!(command_res.unwrap(), Command::Kill(42));
+
+    let mut vec4 = Vec::new();
+    write_packet(
+        &mut vec4,
+        PkgType::Command,
+        &Command::Listen("cache".into()),
+    );
+    command_res = read_commands(&mut Cursor::new(vec4), MAX_PACKET_SIZE);
+    assert_eq!(command_res.unwrap(), Command::Listen("cache".into()));
+
+    let mut vec5 = Vec::new();
+    write_packet(&mut vec5, PkgType::Command, &Command::Resume("0a1b".into()));
+    command_res = read_commands(&mut Cursor::new(vec5), MAX_PACKET_SIZE);
+    assert_eq!(command_res.unwrap(), Command::Resume("0a1b".into()));
+
+    let mut vec6 = Vec::new();
+    write_packet(&mut vec6, PkgType::Command, &Command::Dump("shop".into()));
+    command_res = read_commands(&mut Cursor::new(vec6), MAX_PACKET_SIZE);
+    assert_eq!(command_res.unwrap(), Command::Dump("shop".into()));
+
+    let copy = vec![
+        Command::CopyIn("orders".into()),
+        Command::CopyData(b"1,\"a\"\n".to_vec()),
+        Command::CopyDone,
+        Command::CopyFail("disk full".into()),
+    ];
+    let mut vec7 = Vec::new();
+    for cmd in &copy {
+        write_packet(&mut vec7, PkgType::Command, cmd);
+    }
+    let mut stream = Cursor::new(vec7);
+    for cmd in copy {
+        assert_eq!(read_commands(&mut stream, MAX_PACKET_SIZE).unwrap(), cmd);
+    }
+
+    let prepared = vec![
+        Command::Prepare("select * from foo".into()),
+        Command::Execute(3),
+        Command::Deallocate(3),
+    ];
+    let mut vec8 = Vec::new();
+    for cmd in &prepared {
+        write_packet(&mut vec8, PkgType::Command, cmd);
+    }
+    let mut stream = Cursor::new(vec8);
+    for cmd in prepared {
+        assert_eq!(read_commands(&mut stream, MAX_PACKET_SIZE).unwrap(), cmd);
+    }
 }
 
 #[test]
@@ -237,13 +809,220 @@ pub fn testlogin() {
     let login = Login {
         username: "elena".into(),
         password: "prakt".into(),
+        min_version: 1,
+        max_version: 2,
+        capabilities: Capabilities::TLS,
+        max_packet_size: 1024,
     };
-    let _ = serialize_into(&mut vec, &PkgType::Login);
-    let _ = serialize_into(&mut vec, &login);
+    write_packet(&mut vec, PkgType::Login, &login);
 
-    let login_res = read_login(&mut Cursor::new(vec)).unwrap();
+    let login_res = read_login(&mut Cursor::new(vec), MAX_PACKET_SIZE).unwrap();
 
     // test for equality
     assert_eq!(login_res.username, "elena");
     assert_eq!(login_res.password, "prakt");
+    assert_eq!(login_res.max_version, 2);
+}
+
+#[test]
+pub fn test_unexpected_pkg_is_drained() {
+    use std::io::Cursor;
+    let mut vec = Vec::new();
+
+    // a login where a command is expected, followed by a valid command
+    write_packet(&mut vec, PkgType::Login, &Login::default());
+    write_packet(&mut vec, PkgType::Command, &Command::Ping);
+
+    let mut cursor = Cursor::new(vec);
+    match read_commands(&mut cursor, MAX_PACKET_SIZE) {
+        Err(Error::UnexpectedPkg) => {}
+        other => panic!("expected UnexpectedPkg, got {:?}", other),
+    }
+    assert_eq!(
+        read_commands(&mut cursor, MAX_PACKET_SIZE).unwrap(),
+        Command::Ping
+    );
+}
+
+#[test]
+pub fn test_corrupt_packet() {
+    use std::io::Cursor;
+    let mut vec = Vec::new();
+    write_packet(&mut vec, PkgType::Command, &Command::Query("select".into()));
+    assert_eq!(
+        read_commands(&mut Cursor::new(vec.clone()), MAX_PACKET_SIZE).unwrap(),
+        Command::Query("select".into())
+    );
+
+    // a flipped bit in the query string
+    let pos = vec.len() - 6;
+    vec[pos] ^= 0x20;
+    match read_commands(&mut Cursor::new(vec), MAX_PACKET_SIZE) {
+        Err(Error::CorruptPacket) => {}
+        other => panic!("expected CorruptPacket, got {:?}", other),
+    }
+}
+
+#[test]
+pub fn test_large_response() {
+    use std::io::Cursor;
+    use storage::types::{Column, SqlType};
+
+    // 1000 rows, far more than 1 KiB
+    let data: Vec<u8> = (0..4000).map(|i| i as u8).collect();
+    let result = ResultSet {
+        data: data.clone(),
+        columns: vec![Column::new("a", SqlType::Int, false, "", false)],
+    };
+    let mut vec = Vec::new();
+    send_response_package(&mut vec, result, &SendLimits::default()).unwrap();
+
+    let packet = read_packet(Cursor::new(vec.clone()), MAX_PACKET_SIZE).unwrap();
+    assert!(packet.pkg == PkgType::Response);
+    let result: ResultSet = deserialize_from(packet.payload()).unwrap();
+    assert_eq!(result.data, data);
+
+    // the receiver allows smaller packets only
+    match read_packet(Cursor::new(vec), 1024) {
+        Err(Error::PacketTooLarge) => {}
+        Err(e) => panic!("expected PacketTooLarge, got {:?}", e),
+        Ok(_) => panic!("expected PacketTooLarge"),
+    }
+}
+
+#[test]
+pub fn test_greeting() {
+    use std::io::Cursor;
+    let mut vec = Vec::new();
+    let greet = Greeting::make_greeting(6, "hi".into(), server_capabilities(Some("shop")));
+    write_packet(&mut vec, PkgType::Greet, &greet);
+
+    let packet = read_packet(Cursor::new(vec), MAX_PACKET_SIZE).unwrap();
+    let greet: Greeting = deserialize_from(packet.payload()).unwrap();
+    assert!(packet.pkg == PkgType::Greet);
+    assert_eq!(greet.server.version, env!("CARGO_PKG_VERSION"));
+    assert_eq!(greet.server.default_database, Some("shop".into()));
+    assert!(greet.server.supports("notify"));
+    assert!(!greet.server.supports("MERGE"));
+}
+
+#[test]
+pub fn test_negotiation() {
+    let mut login = Login {
+        username: "elena".into(),
+        password: "prakt".into(),
+        min_version: 1,
+        max_version: 3,
+        capabilities: Capabilities::TLS.with(Capabilities::COMPRESSION),
+        max_packet_size: 1024,
+    };
+
+    // highest common version, common capabilities only, smaller packets
+    let n = Negotiation::choose(&login, 2, 2, Capabilities::COMPRESSION, 4096).unwrap();
+    assert_eq!(n.version, 2);
+    assert!(n.capabilities.contains(Capabilities::COMPRESSION));
+    assert!(!n.capabilities.contains(Capabilities::TLS));
+    assert_eq!(n.max_packet_size, 1024);
+
+    // no overlap
+    login.min_version = 3;
+    assert!(Negotiation::choose(&login, 1, 2, Capabilities::NONE, 4096).is_none());
+}
+
+#[test]
+pub fn test_timeout_and_disconnect_errors() {
+    use std::io::Cursor;
+
+    // reading from a closed stream
+    let err = read_commands(&mut Cursor::new(Vec::new()), MAX_PACKET_SIZE).unwrap_err();
+    assert!(err.is_disconnect());
+    assert!(!err.is_timeout());
+
+    let err = Error::Io(io::Error::new(io::ErrorKind::WouldBlock, "timeout"));
+    assert!(err.is_timeout());
+    assert!(!err.is_disconnect());
+}
+
+#[test]
+pub fn test_send_limits() {
+    use storage::types::{Column, SqlType};
+
+    // 100 rows of a single int column
+    let result = || ResultSet {
+        data: vec![0; 400],
+        columns: vec![Column::new("a", SqlType::Int, false, "", false)],
+    };
+
+    let mut vec = Vec::new();
+    let limits = SendLimits {
+        max_response_size: 256,
+        ..SendLimits::default()
+    };
+    match send_response_package(&mut vec, result(), &limits) {
+        Err(Error::ResponseTooLarge) => {}
+        r => panic!("unexpected result {:?}", r),
+    }
+    assert!(vec.is_empty());
+
+    let limits = SendLimits {
+        rows_per_second: 500,
+        ..SendLimits::default()
+    };
+    let start = Instant::now();
+    send_response_package(&mut vec, result(), &limits).unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(150));
+    assert!(vec.len() > 400);
+
+    // accepts one byte every 10 milliseconds
+    struct SlowClient;
+    impl Write for SlowClient {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            thread::sleep(Duration::from_millis(10));
+            Ok(buf.len().min(1))
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+    let limits = SendLimits {
+        send_timeout: Some(Duration::from_millis(50)),
+        ..SendLimits::default()
+    };
+    let err = send_response_package(&mut SlowClient, result(), &limits).unwrap_err();
+    assert!(err.is_timeout());
+}
+
+#[test]
+fn test_export_data_set() {
+    use parse::token::Lit;
+    use std::io::Cursor;
+    use storage::{Column, Rows, SqlType};
+
+    let columns = vec![
+        Column::new("id", SqlType::Int, false, "", true),
+        Column::new("name", SqlType::VarChar(16), true, "", false),
+        Column::new("ok", SqlType::Bool, false, "", false),
+    ];
+    let mut rows = Rows::new(Cursor::new(Vec::<u8>::new()), &columns);
+    let values = [
+        vec![Lit::Int(1), Lit::String("a, b".into()), Lit::Bool(1)],
+        vec![Lit::Int(2), Lit::Null, Lit::Bool(0)],
+    ];
+    for row in &values {
+        ::catalog::add_row(&mut rows, row).unwrap();
+    }
+    let mut data = preprocess(&rows.to_result_set().unwrap());
+
+    let mut csv = Vec::new();
+    data.write_csv(&mut csv).unwrap();
+    assert_eq!(
+        String::from_utf8(csv).unwrap(),
+        "id,name,ok\n1,\"a, b\",true\n2,,false\n"
+    );
+    let mut json = Vec::new();
+    data.write_json(&mut json).unwrap();
+    assert_eq!(
+        String::from_utf8(json).unwrap(),
+        "[{\"id\":1,\"name\":\"a, b\",\"ok\":true},{\"id\":2,\"name\":null,\"ok\":false}]"
+    );
 }
@@ -0,0 +1,759 @@
+//! MySQL compatibility listener
+//!
+//! Speaks enough of the MySQL client/server protocol to let MySQL clients
+//! and drivers send queries to uoSQL:
+//!
+//! - the `HandshakeV10` / `HandshakeResponse41` handshake
+//! - `COM_QUERY`, `COM_INIT_DB`, `COM_PING` and `COM_QUIT`
+//! - results as text resultset, other results as OK or ERR packet
+//!
+//! The user store only keeps salted SHA-256 hashes, which cannot be checked
+//! against a `mysql_native_password` scramble. The server therefore asks the
+//! client to switch to `mysql_clear_password`, which has to be enabled in
+//! the client (e.g. `mysql --enable-cleartext-plugin`). Only use this
+//! listener in trusted networks.
+//!
+//! Queries run through the same parser and executor as the native protocol
+//! and are subject to the same privileges and connection limits.
+//!
+//! Every packet starts with a three byte length (little endian) and a
+//! sequence number that is reset to 0 with every command of the client.
+//!
+
+use audit;
+use auth;
+use conn;
+use process;
+use query::ExecutionError;
+use storage;
+use storage::types::TEXT_SIZE;
+use storage::{ResultSet, SqlType};
+
+use super::types::{preprocess, ClientErrMsg};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use sha2::{Digest, Sha256};
+
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Like `try!`, but for `Option`
+macro_rules! try_opt {
+    ($e:expr) => {
+        match $e {
+            Some(v) => v,
+            None => return None,
+        }
+    };
+}
+
+const SERVER_VERSION: &'static str = "5.7.0-uosql";
+const NATIVE_PASSWORD: &'static str = "mysql_native_password";
+const CLEAR_PASSWORD: &'static str = "mysql_clear_password";
+const MAX_PACKET_SIZE: usize = 0xFF_FFFF;
+/// `utf8_general_ci` and `binary`
+const CHARSET_UTF8: u16 = 33;
+const CHARSET_BINARY: u16 = 63;
+const SERVER_STATUS_AUTOCOMMIT: u16 = 0x0002;
+
+// Capability flags
+const CLIENT_LONG_PASSWORD: u32 = 0x0000_0001;
+const CLIENT_LONG_FLAG: u32 = 0x0000_0004;
+const CLIENT_CONNECT_WITH_DB: u32 = 0x0000_0008;
+const CLIENT_PROTOCOL_41: u32 = 0x0000_0200;
+const CLIENT_TRANSACTIONS: u32 = 0x0000_2000;
+const CLIENT_SECURE_CONNECTION: u32 = 0x0000_8000;
+const CLIENT_PLUGIN_AUTH: u32 = 0x0008_0000;
+const CLIENT_PLUGIN_AUTH_LENENC_CLIENT_DATA: u32 = 0x0020_0000;
+const SERVER_CAPABILITIES: u32 = CLIENT_LONG_PASSWORD
+    | CLIENT_LONG_FLAG
+    | CLIENT_CONNECT_WITH_DB
+    | CLIENT_PROTOCOL_41
+    | CLIENT_TRANSACTIONS
+    | CLIENT_SECURE_CONNECTION
+    | CLIENT_PLUGIN_AUTH
+    | CLIENT_PLUGIN_AUTH_LENENC_CLIENT_DATA;
+
+// Commands
+const COM_QUIT: u8 = 0x01;
+const COM_INIT_DB: u8 = 0x02;
+const COM_QUERY: u8 = 0x03;
+const COM_PING: u8 = 0x0e;
+
+// Column types
+const MYSQL_TYPE_TINY: u8 = 0x01;
+const MYSQL_TYPE_LONG: u8 = 0x03;
+const MYSQL_TYPE_FLOAT: u8 = 0x04;
+const MYSQL_TYPE_DOUBLE: u8 = 0x05;
+const MYSQL_TYPE_LONGLONG: u8 = 0x08;
+const MYSQL_TYPE_DATE: u8 = 0x0a;
+const MYSQL_TYPE_TIME: u8 = 0x0b;
+const MYSQL_TYPE_DATETIME: u8 = 0x0c;
+const MYSQL_TYPE_NEWDECIMAL: u8 = 0xf6;
+const MYSQL_TYPE_BLOB: u8 = 0xfc;
+const MYSQL_TYPE_VAR_STRING: u8 = 0xfd;
+
+// Column flags
+const NOT_NULL_FLAG: u16 = 0x0001;
+const PRI_KEY_FLAG: u16 = 0x0002;
+
+// Error codes
+const ER_DB_CREATE_EXISTS: u16 = 1007;
+const ER_CON_COUNT_ERROR: u16 = 1040;
+const ER_HANDSHAKE_ERROR: u16 = 1043;
+const ER_ACCESS_DENIED_ERROR: u16 = 1045;
+const ER_UNKNOWN_COM_ERROR: u16 = 1047;
+const ER_BAD_NULL_ERROR: u16 = 1048;
+const ER_TABLE_EXISTS_ERROR: u16 = 1050;
+const ER_RECORD_FILE_FULL: u16 = 1114;
+const ER_DUP_ENTRY: u16 = 1062;
+const ER_CRASHED_ON_USAGE: u16 = 1194;
+const ER_CANNOT_ADD_FOREIGN: u16 = 1215;
+const ER_TRUNCATED_WRONG_VALUE_FOR_FIELD: u16 = 1366;
+const ER_ROW_IS_REFERENCED_2: u16 = 1451;
+const ER_NO_REFERENCED_ROW_2: u16 = 1452;
+const ER_PARSE_ERROR: u16 = 1064;
+const ER_UNKNOWN_ERROR: u16 = 1105;
+const ER_TOO_MANY_USER_CONNECTIONS: u16 = 1203;
+const ER_OPTION_PREVENTS_STATEMENT: u16 = 1290;
+const ER_LOCK_DEADLOCK: u16 = 1213;
+const ER_CHECK_CONSTRAINT_VIOLATED: u16 = 3819;
+
+/// A MySQL connection: a stream and the current sequence number
+struct PacketStream {
+    stream: TcpStream,
+    seq: u8,
+}
+
+impl PacketStream {
+    /// Reads the payload of the next packet.
+    fn read_packet(&mut self) -> io::Result<Vec<u8>> {
+        let mut payload = Vec::new();
+        loop {
+            let len = try!(self.stream.read_u24::<LittleEndian>()) as usize;
+            let seq = try!(self.stream.read_u8());
+            self.seq = seq.wrapping_add(1);
+            let start = payload.len();
+            payload.resize(start + len, 0);
+            try!(self.stream.read_exact(&mut payload[start..]));
+            // Payloads of the maximum size are continued in the next packet
+            if len < MAX_PACKET_SIZE {
+                return Ok(payload);
+            }
+        }
+    }
+
+    /// Writes `payload`, split into as many packets as necessary.
+    fn write_packet(&mut self, payload: &[u8]) -> io::Result<()> {
+        let mut chunks = payload.chunks(MAX_PACKET_SIZE);
+        let mut last_len = 0;
+        for chunk in &mut chunks {
+            try!(self.write_single(chunk));
+            last_len = chunk.len();
+        }
+        // A payload of exactly the maximum size needs an empty packet
+        if last_len == MAX_PACKET_SIZE || payload.is_empty() {
+            try!(self.write_single(&[]));
+        }
+        self.stream.flush()
+    }
+
+    fn write_single(&mut self, chunk: &[u8]) -> io::Result<()> {
+        let mut packet = Vec::with_capacity(chunk.len() + 4);
+        try!(packet.write_u24::<LittleEndian>(chunk.len() as u32));
+        packet.push(self.seq);
+        packet.extend_from_slice(chunk);
+        self.seq = self.seq.wrapping_add(1);
+        self.stream.write_all(&packet)
+    }
+
+    fn write_ok(&mut self) -> io::Result<()> {
+        let mut p = vec![0x00];
+        write_lenenc_int(&mut p, 0); // affected rows
+        write_lenenc_int(&mut p, 0); // last insert id
+        try!(p.write_u16::<LittleEndian>(SERVER_STATUS_AUTOCOMMIT));
+        try!(p.write_u16::<LittleEndian>(0)); // warnings
+        self.write_packet(&p)
+    }
+
+    fn write_eof(&mut self) -> io::Result<()> {
+        let mut p = vec![0xfe];
+        try!(p.write_u16::<LittleEndian>(0)); // warnings
+        try!(p.write_u16::<LittleEndian>(SERVER_STATUS_AUTOCOMMIT));
+        self.write_packet(&p)
+    }
+
+    fn write_err(&mut self, code: u16, msg: &str) -> io::Result<()> {
+        let mut p = vec![0xff];
+        try!(p.write_u16::<LittleEndian>(code));
+        p.push(b'#');
+        p.extend_from_slice(sql_state(code).as_bytes());
+        p.extend_from_slice(msg.as_bytes());
+        self.write_packet(&p)
+    }
+
+    /// Sends a text resultset, or an OK packet if the result has no columns.
+    fn write_result(&mut self, result: &ResultSet) -> io::Result<()> {
+        if result.columns.is_empty() {
+            return self.write_ok();
+        }
+
+        let mut p = Vec::new();
+        write_lenenc_int(&mut p, result.columns.len() as u64);
+        try!(self.write_packet(&p));
+
+        for column in &result.columns {
+            let (type_code, charset, length) = match column.sql_type {
+                SqlType::Int => (MYSQL_TYPE_LONG, CHARSET_BINARY, 11),
+                SqlType::Bool => (MYSQL_TYPE_TINY, CHARSET_BINARY, 1),
+                SqlType::Char(len) => (MYSQL_TYPE_VAR_STRING, CHARSET_UTF8, len as u32 * 3),
+                SqlType::VarChar(len) => (MYSQL_TYPE_VAR_STRING, CHARSET_UTF8, len as u32 * 3),
+                SqlType::Text => (MYSQL_TYPE_BLOB, CHARSET_UTF8, TEXT_SIZE as u32 * 3),
+                SqlType::Float => (MYSQL_TYPE_FLOAT, CHARSET_BINARY, 12),
+                SqlType::Double => (MYSQL_TYPE_DOUBLE, CHARSET_BINARY, 22),
+                SqlType::BigInt => (MYSQL_TYPE_LONGLONG, CHARSET_BINARY, 20),
+                SqlType::Date => (MYSQL_TYPE_DATE, CHARSET_BINARY, 10),
+                SqlType::Time => (MYSQL_TYPE_TIME, CHARSET_BINARY, 15),
+                SqlType::Timestamp => (MYSQL_TYPE_DATETIME, CHARSET_BINARY, 26),
+                // digits, point and sign
+                SqlType::Decimal(precision, _) => {
+                    (MYSQL_TYPE_NEWDECIMAL, CHARSET_BINARY, precision as u32 + 2)
+                }
+                SqlType::VarBinary(len) => (MYSQL_TYPE_BLOB, CHARSET_BINARY, len),
+                SqlType::Blob => (MYSQL_TYPE_BLOB, CHARSET_BINARY, u32::max_value()),
+            };
+            let decimals = match column.sql_type {
+                SqlType::Decimal(_, scale) => scale,
+                _ => 0,
+            };
+            let mut flags = 0;
+            if !column.allow_null {
+                flags |= NOT_NULL_FLAG;
+            }
+            if column.is_primary_key {
+                flags |= PRI_KEY_FLAG;
+            }
+
+            let mut p = Vec::new();
+            write_lenenc_str(&mut p, "def"); // catalog
+            write_lenenc_str(&mut p, ""); // schema
+            write_lenenc_str(&mut p, ""); // table
+            write_lenenc_str(&mut p, ""); // original table
+            write_lenenc_str(&mut p, &column.name);
+            write_lenenc_str(&mut p, &column.name); // original name
+            write_lenenc_int(&mut p, 0x0c); // length of the following fields
+            try!(p.write_u16::<LittleEndian>(charset));
+            try!(p.write_u32::<LittleEndian>(length));
+            p.push(type_code);
+            try!(p.write_u16::<LittleEndian>(flags));
+            p.push(decimals);
+            p.extend_from_slice(&[0, 0]);
+            try!(self.write_packet(&p));
+        }
+        try!(self.write_eof());
+
+        let mut data = preprocess(result);
+        while data.next() {
+            let mut p = Vec::new();
+            for idx in 0..data.get_col_cnt() {
+                let value = match data.get_type_by_idx(idx) {
+                    Some(SqlType::Int) => data.next_int_by_idx(idx).map(|i| i.to_string()),
+                    Some(SqlType::Bool) => {
+                        data.next_bool_by_idx(idx)
+                            .map(|b| if b { "1".into() } else { "0".into() })
+                    }
+                    // binary values are sent as they are
+                    Some(SqlType::VarBinary(_)) => {
+                        match data.next_bytes_by_idx(idx) {
+                            Some(b) => {
+                                write_lenenc_int(&mut p, b.len() as u64);
+                                p.extend_from_slice(&b);
+                            }
+                            None => p.push(0xfb),
+                        }
+                        continue;
+                    }
+                    _ => data.next_as_string_by_idx(idx),
+                };
+                match value {
+                    Some(v) => write_lenenc_str(&mut p, &v),
+                    None => p.push(0xfb), // NULL
+                }
+            }
+            try!(self.write_packet(&p));
+        }
+        self.write_eof()
+    }
+}
+
+/// Writes a length encoded integer.
+fn write_lenenc_int(buf: &mut Vec<u8>, n: u64) {
+    if n < 0xfb {
+        buf.push(n as u8);
+    } else if n <= 0xffff {
+        buf.push(0xfc);
+        let _ = buf.write_u16::<LittleEndian>(n as u16);
+    } else if n <= 0xff_ffff {
+        buf.push(0xfd);
+        let _ = buf.write_u24::<LittleEndian>(n as u32);
+    } else {
+        buf.push(0xfe);
+        let _ = buf.write_u64::<LittleEndian>(n);
+    }
+}
+
+/// Writes a length encoded string.
+fn write_lenenc_str(buf: &mut Vec<u8>, s: &str) {
+    write_lenenc_int(buf, s.len() as u64);
+    buf.extend_from_slice(s.as_bytes());
+}
+
+/// Returns the SQLSTATE sent with the given error code.
+fn sql_state(code: u16) -> &'static str {
+    match code {
+        ER_CON_COUNT_ERROR | ER_TOO_MANY_USER_CONNECTIONS => "08004",
+        ER_ACCESS_DENIED_ERROR => "28000",
+        ER_HANDSHAKE_ERROR => "08S01",
+        ER_PARSE_ERROR => "42000",
+        ER_LOCK_DEADLOCK => "40001",
+        ER_BAD_NULL_ERROR | ER_DUP_ENTRY | ER_ROW_IS_REFERENCED_2 | ER_NO_REFERENCED_ROW_2 => {
+            "23000"
+        }
+        _ => "HY000",
+    }
+}
+
+/// Reader for the fields of a packet sent by the client
+struct Payload<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Payload<'a> {
+    fn new(data: &'a [u8]) -> Payload<'a> {
+        Payload { data: data, pos: 0 }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    fn bytes(&mut self, n: usize) -> Option<&'a [u8]> {
+        if self.pos + n > self.data.len() {
+            return None;
+        }
+        let bytes = &self.data[self.pos..self.pos + n];
+        self.pos += n;
+        Some(bytes)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.bytes(1).map(|b| b[0])
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.bytes(4)
+            .map(|mut b| b.read_u32::<LittleEndian>().unwrap_or(0))
+    }
+
+    fn lenenc_int(&mut self) -> Option<u64> {
+        match try_opt!(self.u8()) {
+            0xfc => self
+                .bytes(2)
+                .map(|mut b| b.read_u16::<LittleEndian>().unwrap_or(0) as u64),
+            0xfd => self
+                .bytes(3)
+                .map(|mut b| b.read_u24::<LittleEndian>().unwrap_or(0) as u64),
+            0xfe => self
+                .bytes(8)
+                .map(|mut b| b.read_u64::<LittleEndian>().unwrap_or(0)),
+            n => Some(n as u64),
+        }
+    }
+
+    /// Reads a NUL terminated string, or the rest of the payload if there is
+    /// no terminator.
+    fn nul_str(&mut self) -> String {
+        let rest = &self.data[self.pos.min(self.data.len())..];
+        let len = rest.iter().position(|&b| b == 0).unwrap_or(rest.len());
+        self.pos += len + 1;
+        String::from_utf8_lossy(&rest[..len]).into_owned()
+    }
+
+    fn rest(&mut self) -> &'a [u8] {
+        let rest = &self.data[self.pos.min(self.data.len())..];
+        self.pos = self.data.len();
+        rest
+    }
+}
+
+/// The fields of the `HandshakeResponse41` packet we need
+#[derive(Debug)]
+struct HandshakeResponse {
+    username: String,
+    auth_response: Vec<u8>,
+    database: Option<String>,
+    plugin: String,
+}
+
+fn parse_handshake_response(data: &[u8]) -> Option<HandshakeResponse> {
+    let mut p = Payload::new(data);
+    let capabilities = try_opt!(p.u32());
+    if capabilities & CLIENT_PROTOCOL_41 == 0 {
+        return None;
+    }
+    try_opt!(p.bytes(4 + 1 + 23)); // max packet size, charset, filler
+    let username = p.nul_str();
+    let auth_response = if capabilities & CLIENT_PLUGIN_AUTH_LENENC_CLIENT_DATA != 0 {
+        let len = try_opt!(p.lenenc_int()) as usize;
+        try_opt!(p.bytes(len)).to_vec()
+    } else if capabilities & CLIENT_SECURE_CONNECTION != 0 {
+        let len = try_opt!(p.u8()) as usize;
+        try_opt!(p.bytes(len)).to_vec()
+    } else {
+        p.nul_str().into_bytes()
+    };
+    let database = if capabilities & CLIENT_CONNECT_WITH_DB != 0 && !p.is_empty() {
+        Some(p.nul_str()).filter(|d| !d.is_empty())
+    } else {
+        None
+    };
+    let plugin = if capabilities & CLIENT_PLUGIN_AUTH != 0 && !p.is_empty() {
+        p.nul_str()
+    } else {
+        NATIVE_PASSWORD.into()
+    };
+    Some(HandshakeResponse {
+        username: username,
+        auth_response: auth_response,
+        database: database,
+        plugin: plugin,
+    })
+}
+
+/// Returns 20 printable bytes sent as scramble in the handshake.
+fn make_scramble() -> Vec<u8> {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+    let mut hasher = Sha256::new();
+    hasher.update(nanos.to_string().as_bytes());
+    hasher.finalize()[..20]
+        .iter()
+        .map(|b| b % 94 + 33)
+        .collect()
+}
+
+/// Strips the terminating NUL the client sends with a clear text password.
+fn clear_password(data: &[u8]) -> String {
+    let data = match data.last() {
+        Some(&0) => &data[..data.len() - 1],
+        _ => data,
+    };
+    String::from_utf8_lossy(data).into_owned()
+}
+
+/// Performs the handshake. Returns the user name, the password and the
+/// database to select.
+fn handshake(s: &mut PacketStream) -> io::Result<Option<(String, String, Option<String>)>> {
+    let scramble = make_scramble();
+    let mut p = vec![10]; // protocol version
+    p.extend_from_slice(SERVER_VERSION.as_bytes());
+    p.push(0);
+    try!(p.write_u32::<LittleEndian>(0)); // connection id
+    p.extend_from_slice(&scramble[..8]);
+    p.push(0);
+    try!(p.write_u16::<LittleEndian>(SERVER_CAPABILITIES as u16));
+    p.push(CHARSET_UTF8 as u8);
+    try!(p.write_u16::<LittleEndian>(SERVER_STATUS_AUTOCOMMIT));
+    try!(p.write_u16::<LittleEndian>((SERVER_CAPABILITIES >> 16) as u16));
+    p.push(scramble.len() as u8 + 1);
+    p.extend_from_slice(&[0; 10]);
+    p.extend_from_slice(&scramble[8..]);
+    p.push(0);
+    p.extend_from_slice(NATIVE_PASSWORD.as_bytes());
+    p.push(0);
+    try!(s.write_packet(&p));
+
+    let response = match parse_handshake_response(&try!(s.read_packet())) {
+        Some(r) => r,
+        None => {
+            try!(s.write_err(ER_HANDSHAKE_ERROR, "Bad handshake"));
+            return Ok(None);
+        }
+    };
+
+    let password = if response.plugin == CLEAR_PASSWORD {
+        clear_password(&response.auth_response)
+    } else {
+        // Ask the client to send the password in clear text
+        let mut p = vec![0xfe];
+        p.extend_from_slice(CLEAR_PASSWORD.as_bytes());
+        p.push(0);
+        try!(s.write_packet(&p));
+        clear_password(&try!(s.read_packet()))
+    };
+    Ok(Some((response.username, password, response.database)))
+}
+
+/// Sends the outcome of a query to the client.
+fn send_query_result(
+    s: &mut PacketStream,
+    q: &str,
+    result: Result<ResultSet, ExecutionError>,
+) -> io::Result<()> {
+    match result {
+        Ok(r) => s.write_result(&r),
+        Err(ExecutionError::ParseError(e)) => {
+            let msg = ClientErrMsg::from_parse_error(&e, q).to_string();
+            s.write_err(ER_PARSE_ERROR, &msg.replace('\n', "; "))
+        }
+        Err(ExecutionError::NotNullViolation(column)) => s.write_err(
+            ER_BAD_NULL_ERROR,
+            &format!("Column '{}' cannot be null", column),
+        ),
+        Err(ExecutionError::ForeignKeyViolation(name)) => s.write_err(
+            ER_NO_REFERENCED_ROW_2,
+            &format!(
+                "Cannot add or update a child row: a foreign key constraint fails (`{}`)",
+                name
+            ),
+        ),
+        Err(ExecutionError::RowIsReferenced(name)) => s.write_err(
+            ER_ROW_IS_REFERENCED_2,
+            &format!(
+                "Cannot delete or update a parent row: a foreign key constraint fails (`{}`)",
+                name
+            ),
+        ),
+        Err(ExecutionError::InvalidForeignKey(name)) => s.write_err(
+            ER_CANNOT_ADD_FOREIGN,
+            &format!("Failed to add the foreign key constraint '{}'", name),
+        ),
+        Err(ExecutionError::CheckViolation(name)) => s.write_err(
+            ER_CHECK_CONSTRAINT_VIOLATED,
+            &format!("Check constraint '{}' is violated.", name),
+        ),
+        Err(ExecutionError::InvalidCsv(e)) => s.write_err(
+            ER_TRUNCATED_WRONG_VALUE_FOR_FIELD,
+            &format!("Invalid CSV at {}", e),
+        ),
+        Err(ExecutionError::StorageError(storage::Error::UniqueKeyValueExists(index))) => s
+            .write_err(
+                ER_DUP_ENTRY,
+                &format!("Duplicate entry for key '{}'", index),
+            ),
+        Err(ExecutionError::StorageError(storage::Error::PrimaryKeyValueExists)) => {
+            s.write_err(ER_DUP_ENTRY, "Duplicate entry for key 'PRIMARY'")
+        }
+        Err(ExecutionError::StorageError(storage::Error::ChecksumMismatch(addr))) => s.write_err(
+            ER_CRASHED_ON_USAGE,
+            &format!(
+                "Row at byte {} does not match its checksum and should be repaired",
+                addr
+            ),
+        ),
+        Err(ExecutionError::StorageError(storage::Error::Deadlock)) => s.write_err(
+            ER_LOCK_DEADLOCK,
+            "Deadlock found when trying to get lock; transaction was rolled back",
+        ),
+        Err(ExecutionError::StorageError(storage::Error::TableExists(name))) => s.write_err(
+            ER_TABLE_EXISTS_ERROR,
+            &format!("Table '{}' already exists", name),
+        ),
+        Err(ExecutionError::StorageError(storage::Error::DatabaseExists(name))) => s.write_err(
+            ER_DB_CREATE_EXISTS,
+            &format!("Can't create database '{}'; database exists", name),
+        ),
+        Err(ExecutionError::StorageError(storage::Error::DatabaseFull(name))) => s.write_err(
+            ER_RECORD_FILE_FULL,
+            &format!("Database '{}' has reached its maximum size", name),
+        ),
+        Err(ExecutionError::TooManyConnections(name)) => s.write_err(
+            ER_CON_COUNT_ERROR,
+            &format!("Too many connections to database '{}'", name),
+        ),
+        Err(ExecutionError::ReadOnly) => s.write_err(
+            ER_OPTION_PREVENTS_STATEMENT,
+            "The server is a read-only follower so it cannot execute this statement",
+        ),
+        Err(e) => s.write_err(ER_UNKNOWN_ERROR, &format!("{:?}", e)),
+    }
+}
+
+/// Handles one MySQL client connection until the client quits, the
+/// connection is killed or the client sends nothing for `idle_timeout`.
+pub fn handle(stream: TcpStream, limits: process::Limits, idle_timeout: Option<Duration>) {
+    let addr = stream
+        .peer_addr()
+        .map(|a| a.to_string())
+        .unwrap_or("???".into());
+    info!("Handling MySQL connection from {}", addr);
+
+    // A client that does not log in must not hold its worker for the whole
+    // idle timeout
+    if let Err(e) = stream.set_read_timeout(Some(conn::HANDSHAKE_TIMEOUT)) {
+        warn!("Cannot set handshake timeout: {:?}", e);
+    }
+    let registered_stream = stream.try_clone().ok().map(super::Stream::Plain);
+    let mut s = PacketStream {
+        stream: stream,
+        seq: 0,
+    };
+
+    let (name, password, database) = match handshake(&mut s) {
+        Ok(Some(login)) => login,
+        Ok(None) => {
+            error!("MySQL handshake failed. Connection closed.");
+            return;
+        }
+        Err(e) => {
+            error!("MySQL handshake failed: {:?}. Connection closed.", e);
+            return;
+        }
+    };
+
+    let mut user = match auth::find_user(&name, &password) {
+        Ok(u) => u,
+        Err(e) => {
+            audit::login_failed(&name, &addr, &format!("{:?}", e));
+            let msg = format!("Access denied for user '{}'", name);
+            let _ = s.write_err(ER_ACCESS_DENIED_ERROR, &msg);
+            error!("Authentication failed. Connection closed.");
+            return;
+        }
+    };
+    let process = match process::register(&user._name, &addr, registered_stream, limits) {
+        Ok(p) => p,
+        Err(e) => {
+            let (code, msg) = match e {
+                process::LimitError::TooManyConnections => {
+                    (ER_CON_COUNT_ERROR, "Too many connections")
+                }
+                process::LimitError::TooManyUserConnections => (
+                    ER_TOO_MANY_USER_CONNECTIONS,
+                    "User has too many active connections",
+                ),
+                process::LimitError::TooManyDatabaseConnections => {
+                    (ER_CON_COUNT_ERROR, "Too many connections to the database")
+                }
+            };
+            let _ = s.write_err(code, msg);
+            warn!("{:?} for user '{}'. Connection closed.", e, user._name);
+            return;
+        }
+    };
+    if let Err(e) = s.stream.set_read_timeout(idle_timeout) {
+        warn!("Cannot set idle timeout: {:?}", e);
+    }
+
+    let res = match database {
+        Some(db) => {
+            let q = format!("use database {}", db);
+            let result = conn::run_query(&q, &mut user, &process);
+            send_query_result(&mut s, &q, result)
+        }
+        None => s.write_ok(),
+    };
+    if let Err(e) = res {
+        error!("Failed to send packet: {:?}. Connection closed.", e);
+        return;
+    }
+
+    loop {
+        if process.is_killed() {
+            info!("Connection {} was killed. Connection closed.", process.id());
+            return;
+        }
+
+        let packet = match s.read_packet() {
+            Ok(p) => p,
+            Err(ref e)
+                if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut =>
+            {
+                info!(
+                    "Connection {} of user '{}' was idle for too long. Connection closed.",
+                    process.id(),
+                    user._name
+                );
+                return;
+            }
+            Err(_) => {
+                info!(
+                    "Connection {} of user '{}' was closed by the client.",
+                    process.id(),
+                    user._name
+                );
+                return;
+            }
+        };
+
+        let mut p = Payload::new(&packet);
+        let res = match p.u8() {
+            Some(COM_QUIT) => {
+                debug!("MySQL client disconnected properly.");
+                return;
+            }
+            Some(COM_PING) => s.write_ok(),
+            Some(COM_INIT_DB) => {
+                let q = format!("use database {}", String::from_utf8_lossy(p.rest()));
+                let result = conn::run_query(&q, &mut user, &process);
+                send_query_result(&mut s, &q, result)
+            }
+            Some(COM_QUERY) => {
+                let q = String::from_utf8_lossy(p.rest()).into_owned();
+                debug!("MySQL query received: {}", q);
+                let result = conn::run_query(&q, &mut user, &process);
+                send_query_result(&mut s, &q, result)
+            }
+            other => {
+                debug!("Unsupported MySQL command {:?}", other);
+                s.write_err(ER_UNKNOWN_COM_ERROR, "Unknown command")
+            }
+        };
+        if let Err(e) = res {
+            warn!("Failed to send packet: {:?}", e);
+        }
+    }
+}
+
+#[test]
+fn test_lenenc_int() {
+    let mut buf = Vec::new();
+    write_lenenc_int(&mut buf, 250);
+    write_lenenc_int(&mut buf, 251);
+    write_lenenc_int(&mut buf, 0x10000);
+    assert_eq!(buf, vec![250, 0xfc, 251, 0, 0xfd, 0, 0, 1]);
+
+    let mut p = Payload::new(&buf);
+    assert_eq!(p.lenenc_int(), Some(250));
+    assert_eq!(p.lenenc_int(), Some(251));
+    assert_eq!(p.lenenc_int(), Some(0x10000));
+    assert_eq!(p.lenenc_int(), None);
+}
+
+#[test]
+fn test_parse_handshake_response() {
+    let mut data = Vec::new();
+    let capabilities =
+        CLIENT_PROTOCOL_41 | CLIENT_SECURE_CONNECTION | CLIENT_CONNECT_WITH_DB | CLIENT_PLUGIN_AUTH;
+    data.write_u32::<LittleEndian>(capabilities).unwrap();
+    data.write_u32::<LittleEndian>(1 << 24).unwrap();
+    data.push(CHARSET_UTF8 as u8);
+    data.extend_from_slice(&[0; 23]);
+    data.extend_from_slice(b"elena\0");
+    data.extend_from_slice(b"\x06prakt\0");
+    data.extend_from_slice(b"shop\0");
+    data.extend_from_slice(b"mysql_clear_password\0");
+
+    let r = parse_handshake_response(&data).unwrap();
+    assert_eq!(r.username, "elena");
+    assert_eq!(clear_password(&r.auth_response), "prakt");
+    assert_eq!(r.database, Some("shop".into()));
+    assert_eq!(r.plugin, CLEAR_PASSWORD);
+
+    // pre 4.1 clients are not supported
+    assert!(parse_handshake_response(&[0; 32]).is_none());
+}
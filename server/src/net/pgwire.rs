@@ -0,0 +1,741 @@
+//! PostgreSQL compatibility listener
+//!
+//! Speaks the frontend/backend protocol version 3.0 of PostgreSQL, so that
+//! `psql` and other PostgreSQL clients can query uoSQL:
+//!
+//! - the startup message (SSL and GSS encryption requests are declined)
+//! - clear text password authentication
+//! - the simple query protocol: `RowDescription` and `DataRow` in text
+//!   format, followed by `CommandComplete`
+//!
+//! The extended query protocol is not supported. Its messages are answered
+//! with an error and skipped until the next `Sync`.
+//!
+//! Like the MySQL listener, the password is sent in clear text, because the
+//! user store only keeps salted hashes. Only use this listener in trusted
+//! networks.
+//!
+//! Every message except the startup message starts with a type byte,
+//! followed by its length (including itself) as big endian 32 bit integer.
+//!
+
+use audit;
+use auth;
+use conn;
+use parse::parser::ParseError;
+use process;
+use query::ExecutionError;
+use storage::{self, transaction};
+use storage::{ResultSet, SqlType};
+
+use super::types::preprocess;
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+const PROTOCOL_VERSION: i32 = 196608; // 3.0
+const SSL_REQUEST: i32 = 80877103;
+const GSSENC_REQUEST: i32 = 80877104;
+const CANCEL_REQUEST: i32 = 80877102;
+const SERVER_VERSION: &'static str = "10.0 (uoSQL)";
+/// Upper bound for the length of a single message
+const MAX_MESSAGE_LEN: usize = 64 * 1024 * 1024;
+
+// Type oids
+const BOOLOID: i32 = 16;
+const BYTEAOID: i32 = 17;
+const INT8OID: i32 = 20;
+const INT4OID: i32 = 23;
+const TEXTOID: i32 = 25;
+const FLOAT4OID: i32 = 700;
+const FLOAT8OID: i32 = 701;
+const DATEOID: i32 = 1082;
+const TIMEOID: i32 = 1083;
+const TIMESTAMPOID: i32 = 1114;
+const VARCHAROID: i32 = 1043;
+const NUMERICOID: i32 = 1700;
+
+// Error codes (SQLSTATE)
+const INVALID_PASSWORD: &'static str = "28P01";
+const TOO_MANY_CONNECTIONS: &'static str = "53300";
+const PROTOCOL_VIOLATION: &'static str = "08P01";
+const FEATURE_NOT_SUPPORTED: &'static str = "0A000";
+const SYNTAX_ERROR: &'static str = "42601";
+const INSUFFICIENT_PRIVILEGE: &'static str = "42501";
+const NOT_NULL_VIOLATION: &'static str = "23502";
+const UNIQUE_VIOLATION: &'static str = "23505";
+const FOREIGN_KEY_VIOLATION: &'static str = "23503";
+const INVALID_FOREIGN_KEY: &'static str = "42830";
+const CHECK_VIOLATION: &'static str = "23514";
+const BAD_COPY_FILE_FORMAT: &'static str = "22P04";
+const DEADLOCK_DETECTED: &'static str = "40P01";
+const DUPLICATE_TABLE: &'static str = "42P07";
+const DUPLICATE_DATABASE: &'static str = "42P04";
+const OBJECT_IN_USE: &'static str = "55006";
+const DISK_FULL: &'static str = "53100";
+const READ_ONLY_SQL_TRANSACTION: &'static str = "25006";
+const INTERNAL_ERROR: &'static str = "XX000";
+const DATA_CORRUPTED: &'static str = "XX001";
+
+/// Fields of an `ErrorResponse` or `NoticeResponse`
+struct ErrorFields<'a> {
+    severity: &'static str,
+    code: &'static str,
+    message: &'a str,
+    /// 1-based character position in the query
+    position: Option<usize>,
+    hint: Option<&'a str>,
+}
+
+/// A PostgreSQL connection
+struct MessageStream {
+    stream: TcpStream,
+}
+
+impl MessageStream {
+    /// Reads a message without type byte, only used for the startup message.
+    fn read_startup(&mut self) -> io::Result<Vec<u8>> {
+        let len = try!(self.stream.read_i32::<BigEndian>());
+        self.read_body(len)
+    }
+
+    /// Reads the next message and returns its type and body.
+    fn read_message(&mut self) -> io::Result<(u8, Vec<u8>)> {
+        let tag = try!(self.stream.read_u8());
+        let len = try!(self.stream.read_i32::<BigEndian>());
+        Ok((tag, try!(self.read_body(len))))
+    }
+
+    fn read_body(&mut self, len: i32) -> io::Result<Vec<u8>> {
+        if len < 4 || len as usize > MAX_MESSAGE_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "invalid message length",
+            ));
+        }
+        let mut body = vec![0; len as usize - 4];
+        try!(self.stream.read_exact(&mut body));
+        Ok(body)
+    }
+
+    fn write_message(&mut self, tag: u8, body: &[u8]) -> io::Result<()> {
+        let mut msg = Vec::with_capacity(body.len() + 5);
+        msg.push(tag);
+        try!(msg.write_i32::<BigEndian>(body.len() as i32 + 4));
+        msg.extend_from_slice(body);
+        self.stream.write_all(&msg)
+    }
+
+    fn write_auth_request(&mut self, code: i32) -> io::Result<()> {
+        let mut body = Vec::new();
+        try!(body.write_i32::<BigEndian>(code));
+        self.write_message(b'R', &body)
+    }
+
+    fn write_parameter_status(&mut self, name: &str, value: &str) -> io::Result<()> {
+        let mut body = Vec::new();
+        write_cstr(&mut body, name);
+        write_cstr(&mut body, value);
+        self.write_message(b'S', &body)
+    }
+
+    /// Reports whether the connection is idle or inside of a transaction.
+    fn write_ready_for_query(&mut self, in_transaction: bool) -> io::Result<()> {
+        let status = if in_transaction { b"T" } else { b"I" };
+        try!(self.write_message(b'Z', status));
+        self.stream.flush()
+    }
+
+    fn write_command_complete(&mut self, tag: &str) -> io::Result<()> {
+        let mut body = Vec::new();
+        write_cstr(&mut body, tag);
+        self.write_message(b'C', &body)
+    }
+
+    /// Sends an `ErrorResponse` (`tag == b'E'`) or `NoticeResponse`
+    /// (`tag == b'N'`).
+    fn write_error(&mut self, tag: u8, fields: &ErrorFields) -> io::Result<()> {
+        let mut body = Vec::new();
+        body.push(b'S');
+        write_cstr(&mut body, fields.severity);
+        body.push(b'V');
+        write_cstr(&mut body, fields.severity);
+        body.push(b'C');
+        write_cstr(&mut body, fields.code);
+        body.push(b'M');
+        write_cstr(&mut body, fields.message);
+        if let Some(position) = fields.position {
+            body.push(b'P');
+            write_cstr(&mut body, &position.to_string());
+        }
+        if let Some(hint) = fields.hint {
+            body.push(b'H');
+            write_cstr(&mut body, hint);
+        }
+        body.push(0);
+        self.write_message(tag, &body)
+    }
+
+    /// Sends a simple `ERROR` without position or hint.
+    fn write_simple_error(&mut self, code: &'static str, message: &str) -> io::Result<()> {
+        self.write_error(
+            b'E',
+            &ErrorFields {
+                severity: "ERROR",
+                code: code,
+                message: message,
+                position: None,
+                hint: None,
+            },
+        )
+    }
+
+    /// Sends the rows of a result, followed by `CommandComplete`.
+    fn write_result(&mut self, result: &ResultSet, q: &str) -> io::Result<()> {
+        if result.columns.is_empty() {
+            return self.write_command_complete(&command_tag(q));
+        }
+
+        let mut body = Vec::new();
+        try!(body.write_i16::<BigEndian>(result.columns.len() as i16));
+        for column in &result.columns {
+            let (oid, size, modifier) = match column.sql_type {
+                SqlType::Int => (INT4OID, 4, -1),
+                SqlType::Bool => (BOOLOID, 1, -1),
+                SqlType::Char(len) => (VARCHAROID, -1, len as i32 + 4),
+                SqlType::VarChar(len) => (VARCHAROID, -1, len as i32 + 4),
+                SqlType::Text => (TEXTOID, -1, -1),
+                SqlType::Float => (FLOAT4OID, 4, -1),
+                SqlType::Double => (FLOAT8OID, 8, -1),
+                SqlType::BigInt => (INT8OID, 8, -1),
+                SqlType::Date => (DATEOID, 4, -1),
+                SqlType::Time => (TIMEOID, 8, -1),
+                SqlType::Timestamp => (TIMESTAMPOID, 8, -1),
+                SqlType::VarBinary(_) | SqlType::Blob => (BYTEAOID, -1, -1),
+                SqlType::Decimal(precision, scale) => (
+                    NUMERICOID,
+                    -1,
+                    ((precision as i32) << 16 | scale as i32) + 4,
+                ),
+            };
+            write_cstr(&mut body, &column.name);
+            try!(body.write_i32::<BigEndian>(0)); // table oid
+            try!(body.write_i16::<BigEndian>(0)); // column number
+            try!(body.write_i32::<BigEndian>(oid));
+            try!(body.write_i16::<BigEndian>(size));
+            try!(body.write_i32::<BigEndian>(modifier));
+            try!(body.write_i16::<BigEndian>(0)); // text format
+        }
+        try!(self.write_message(b'T', &body));
+
+        let mut data = preprocess(result);
+        let mut count = 0;
+        while data.next() {
+            let mut body = Vec::new();
+            try!(body.write_i16::<BigEndian>(data.get_col_cnt() as i16));
+            for idx in 0..data.get_col_cnt() {
+                let value = match data.get_type_by_idx(idx) {
+                    Some(SqlType::Int) => data.next_int_by_idx(idx).map(|i| i.to_string()),
+                    Some(SqlType::Bool) => {
+                        data.next_bool_by_idx(idx)
+                            .map(|b| if b { "t".into() } else { "f".into() })
+                    }
+                    _ => data.next_as_string_by_idx(idx),
+                };
+                match value {
+                    Some(v) => {
+                        try!(body.write_i32::<BigEndian>(v.len() as i32));
+                        body.extend_from_slice(v.as_bytes());
+                    }
+                    None => try!(body.write_i32::<BigEndian>(-1)), // NULL
+                }
+            }
+            try!(self.write_message(b'D', &body));
+            count += 1;
+        }
+        self.write_command_complete(&format!("SELECT {}", count))
+    }
+}
+
+/// Writes a NUL terminated string.
+fn write_cstr(buf: &mut Vec<u8>, s: &str) {
+    buf.extend_from_slice(s.as_bytes());
+    buf.push(0);
+}
+
+/// Reads a NUL terminated string starting at `*pos` and moves `*pos` behind
+/// the terminator.
+fn read_cstr(data: &[u8], pos: &mut usize) -> String {
+    let rest = &data[(*pos).min(data.len())..];
+    let len = rest.iter().position(|&b| b == 0).unwrap_or(rest.len());
+    *pos += len + 1;
+    String::from_utf8_lossy(&rest[..len]).into_owned()
+}
+
+/// Returns the parameters of a startup message body (after the protocol
+/// version) as name-value pairs.
+fn startup_parameters(data: &[u8]) -> Vec<(String, String)> {
+    let mut params = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() && data[pos] != 0 {
+        let name = read_cstr(data, &mut pos);
+        let value = read_cstr(data, &mut pos);
+        params.push((name, value));
+    }
+    params
+}
+
+/// Splits a query string into statements at every `;` outside of string
+/// literals, quoted names and comments. Returns each statement with its
+/// byte offset in `q`; statements of nothing but whitespace and comments
+/// are skipped.
+fn split_statements(q: &str) -> Vec<(usize, &str)> {
+    let mut statements = Vec::new();
+    let mut start = 0;
+    // a quotation mark, or - in a line comment and * in a block comment
+    let mut quote = None;
+    let mut escaped = false;
+    let mut star = false;
+    let mut content = false;
+    for (i, c) in q.char_indices() {
+        let after_star = star;
+        star = false;
+        match (quote, c) {
+            _ if escaped => escaped = false,
+            (Some('\''), '\\') => escaped = true,
+            (None, '-') if q[i..].starts_with("--") => quote = Some('-'),
+            (None, '/') if q[i..].starts_with("/*") => {
+                quote = Some('*');
+                escaped = true;
+            }
+            (Some('-'), '\n') => quote = None,
+            (Some('*'), '/') if after_star => quote = None,
+            (Some('*'), '*') => star = true,
+            (Some('-'), _) | (Some('*'), _) => {}
+            (None, '\'') | (None, '"') | (None, '`') => {
+                quote = Some(c);
+                content = true;
+            }
+            (Some(open), _) if open == c => quote = None,
+            (None, ';') => {
+                if content {
+                    statements.push((start, &q[start..i]));
+                }
+                start = i + 1;
+                content = false;
+            }
+            (None, c) if !c.is_whitespace() => content = true,
+            _ => {}
+        }
+    }
+    if content {
+        statements.push((start, &q[start..]));
+    }
+    statements
+}
+
+/// Returns the `CommandComplete` tag for a statement without result rows.
+fn command_tag(q: &str) -> String {
+    let command = q.split_whitespace().next().unwrap_or("").to_uppercase();
+    match &command[..] {
+        // Only single rows can be inserted
+        "INSERT" => "INSERT 0 1".into(),
+        "TRUNCATE" => "TRUNCATE TABLE".into(),
+        _ => command,
+    }
+}
+
+/// Sends the outcome of a statement. `offset` is the position of the
+/// statement in the query string sent by the client.
+fn send_query_result(
+    s: &mut MessageStream,
+    q: &str,
+    offset: usize,
+    full_query: &str,
+    result: Result<ResultSet, ExecutionError>,
+) -> io::Result<()> {
+    match result {
+        Ok(r) => s.write_result(&r, q),
+        Err(ExecutionError::ParseError(e)) => {
+            let lo = match e.span() {
+                Some(span) => span.lo,
+                None if e == ParseError::UnexpectedEoq => q.trim_end().len(),
+                None => 0,
+            };
+            let position = full_query[..offset + lo].chars().count() + 1;
+            let message = e.to_string();
+            s.write_error(
+                b'E',
+                &ErrorFields {
+                    severity: "ERROR",
+                    code: SYNTAX_ERROR,
+                    message: &message,
+                    position: Some(position),
+                    hint: e.hint(),
+                },
+            )
+        }
+        Err(ExecutionError::AuthError(auth::AuthError::PermissionDenied)) => {
+            s.write_simple_error(INSUFFICIENT_PRIVILEGE, "permission denied")
+        }
+        Err(ExecutionError::NotNullViolation(column)) => {
+            let msg = format!(
+                "null value in column \"{}\" violates not-null constraint",
+                column
+            );
+            s.write_simple_error(NOT_NULL_VIOLATION, &msg)
+        }
+        Err(ExecutionError::ForeignKeyViolation(name)) => {
+            let msg = format!("insert violates foreign key constraint \"{}\"", name);
+            s.write_simple_error(FOREIGN_KEY_VIOLATION, &msg)
+        }
+        Err(ExecutionError::RowIsReferenced(name)) => {
+            let msg = format!("delete violates foreign key constraint \"{}\"", name);
+            s.write_simple_error(FOREIGN_KEY_VIOLATION, &msg)
+        }
+        Err(ExecutionError::InvalidForeignKey(name)) => {
+            let msg = format!("invalid foreign key constraint \"{}\"", name);
+            s.write_simple_error(INVALID_FOREIGN_KEY, &msg)
+        }
+        Err(ExecutionError::CheckViolation(name)) => {
+            let msg = format!("new row violates check constraint \"{}\"", name);
+            s.write_simple_error(CHECK_VIOLATION, &msg)
+        }
+        Err(ExecutionError::InvalidCsv(e)) => {
+            s.write_simple_error(BAD_COPY_FILE_FORMAT, &format!("invalid CSV at {}", e))
+        }
+        Err(ExecutionError::StorageError(storage::Error::UniqueKeyValueExists(index))) => {
+            let msg = format!(
+                "duplicate key value violates unique constraint \"{}\"",
+                index
+            );
+            s.write_simple_error(UNIQUE_VIOLATION, &msg)
+        }
+        Err(ExecutionError::StorageError(storage::Error::PrimaryKeyValueExists)) => s
+            .write_simple_error(
+                UNIQUE_VIOLATION,
+                "duplicate key value violates primary key constraint",
+            ),
+        Err(ExecutionError::StorageError(storage::Error::ChecksumMismatch(addr))) => {
+            let msg = format!("row at byte {} does not match its checksum", addr);
+            s.write_simple_error(DATA_CORRUPTED, &msg)
+        }
+        Err(ExecutionError::StorageError(storage::Error::Deadlock)) => s.write_simple_error(
+            DEADLOCK_DETECTED,
+            "deadlock detected, the transaction was rolled back",
+        ),
+        Err(ExecutionError::StorageError(storage::Error::TableExists(name))) => {
+            let msg = format!("relation \"{}\" already exists", name);
+            s.write_simple_error(DUPLICATE_TABLE, &msg)
+        }
+        Err(ExecutionError::StorageError(storage::Error::DatabaseExists(name))) => {
+            let msg = format!("database \"{}\" already exists", name);
+            s.write_simple_error(DUPLICATE_DATABASE, &msg)
+        }
+        Err(ExecutionError::DatabaseInUse(name)) => {
+            let msg = format!("database \"{}\" is being accessed by other users", name);
+            s.write_simple_error(OBJECT_IN_USE, &msg)
+        }
+        Err(ExecutionError::StorageError(storage::Error::DatabaseFull(name))) => {
+            let msg = format!("database \"{}\" reached its maximum size", name);
+            s.write_simple_error(DISK_FULL, &msg)
+        }
+        Err(ExecutionError::TooManyConnections(name)) => {
+            let msg = format!("too many connections for database \"{}\"", name);
+            s.write_simple_error(TOO_MANY_CONNECTIONS, &msg)
+        }
+        Err(ExecutionError::ReadOnly) => s.write_simple_error(
+            READ_ONLY_SQL_TRANSACTION,
+            "cannot execute this statement on a read-only follower",
+        ),
+        Err(e) => s.write_simple_error(INTERNAL_ERROR, &format!("{:?}", e)),
+    }
+}
+
+/// Reads the startup message. Declines encryption requests. Returns `None`
+/// if the client does not want to start a session.
+fn read_startup(s: &mut MessageStream) -> io::Result<Option<Vec<(String, String)>>> {
+    loop {
+        let body = try!(s.read_startup());
+        let mut version = &body[..body.len().min(4)];
+        let code = try!(version.read_i32::<BigEndian>());
+        match code {
+            SSL_REQUEST | GSSENC_REQUEST => {
+                try!(s.stream.write_all(b"N"));
+            }
+            CANCEL_REQUEST => return Ok(None),
+            PROTOCOL_VERSION => return Ok(Some(startup_parameters(&body[4..]))),
+            _ => {
+                let msg = format!("unsupported frontend protocol {}", code);
+                try!(s.write_simple_error(PROTOCOL_VIOLATION, &msg));
+                return Ok(None);
+            }
+        }
+    }
+}
+
+/// Handles one PostgreSQL client connection until the client terminates,
+/// the connection is killed or the client sends nothing for `idle_timeout`.
+pub fn handle(stream: TcpStream, limits: process::Limits, idle_timeout: Option<Duration>) {
+    let addr = stream
+        .peer_addr()
+        .map(|a| a.to_string())
+        .unwrap_or("???".into());
+    info!("Handling PostgreSQL connection from {}", addr);
+
+    // A client that does not log in must not hold its worker for the whole
+    // idle timeout
+    if let Err(e) = stream.set_read_timeout(Some(conn::HANDSHAKE_TIMEOUT)) {
+        warn!("Cannot set handshake timeout: {:?}", e);
+    }
+    let registered_stream = stream.try_clone().ok().map(super::Stream::Plain);
+    let mut s = MessageStream { stream: stream };
+
+    let params = match read_startup(&mut s) {
+        Ok(Some(p)) => p,
+        Ok(None) => return,
+        Err(e) => {
+            error!("PostgreSQL startup failed: {:?}. Connection closed.", e);
+            return;
+        }
+    };
+    let param = |name: &str| params.iter().find(|p| p.0 == name).map(|p| p.1.clone());
+    let name = param("user").unwrap_or_default();
+
+    // Ask for the password in clear text
+    let password = match s
+        .write_auth_request(3)
+        .and_then(|_| s.stream.flush())
+        .and_then(|_| s.read_message())
+    {
+        Ok((b'p', body)) => read_cstr(&body, &mut 0),
+        Ok(_) => {
+            let _ = s.write_simple_error(PROTOCOL_VIOLATION, "expected password message");
+            return;
+        }
+        Err(e) => {
+            error!("PostgreSQL authentication failed: {:?}", e);
+            return;
+        }
+    };
+
+    let mut user = match auth::find_user(&name, &password) {
+        Ok(u) => u,
+        Err(e) => {
+            audit::login_failed(&name, &addr, &format!("{:?}", e));
+            let msg = format!("password authentication failed for user \"{}\"", name);
+            let _ = s.write_error(
+                b'E',
+                &ErrorFields {
+                    severity: "FATAL",
+                    code: INVALID_PASSWORD,
+                    message: &msg,
+                    position: None,
+                    hint: None,
+                },
+            );
+            error!("Authentication failed. Connection closed.");
+            return;
+        }
+    };
+    let process = match process::register(&user._name, &addr, registered_stream, limits) {
+        Ok(p) => p,
+        Err(e) => {
+            let _ = s.write_error(
+                b'E',
+                &ErrorFields {
+                    severity: "FATAL",
+                    code: TOO_MANY_CONNECTIONS,
+                    message: "sorry, too many clients already",
+                    position: None,
+                    hint: None,
+                },
+            );
+            warn!("{:?} for user '{}'. Connection closed.", e, user._name);
+            return;
+        }
+    };
+    if let Err(e) = s.stream.set_read_timeout(idle_timeout) {
+        warn!("Cannot set idle timeout: {:?}", e);
+    }
+
+    let mut res = s.write_auth_request(0);
+    for &(name, value) in [
+        ("server_version", SERVER_VERSION),
+        ("server_encoding", "UTF8"),
+        ("client_encoding", "UTF8"),
+        ("DateStyle", "ISO, MDY"),
+        ("integer_datetimes", "on"),
+        ("standard_conforming_strings", "on"),
+    ]
+    .iter()
+    {
+        res = res.and_then(|_| s.write_parameter_status(name, value));
+    }
+
+    // Clients pick a database by default (e.g. the user name), so a missing
+    // database is reported as notice only
+    if let Some(db) = param("database") {
+        let q = format!("use database {}", db);
+        if let Err(e) = conn::run_query(&q, &mut user, &process) {
+            let msg = format!("cannot use database \"{}\": {:?}", db, e);
+            res = res.and_then(|_| {
+                s.write_error(
+                    b'N',
+                    &ErrorFields {
+                        severity: "NOTICE",
+                        code: INTERNAL_ERROR,
+                        message: &msg,
+                        position: None,
+                        hint: None,
+                    },
+                )
+            });
+        }
+    }
+
+    // Cancel requests are not supported, so no real key is needed
+    let mut key_data = Vec::new();
+    let _ = key_data.write_i32::<BigEndian>(process.id() as i32);
+    let _ = key_data.write_i32::<BigEndian>(0);
+    res = res
+        .and_then(|_| s.write_message(b'K', &key_data))
+        .and_then(|_| s.write_ready_for_query(false));
+    if let Err(e) = res {
+        error!("Failed to send message: {:?}. Connection closed.", e);
+        return;
+    }
+
+    // Set after an error in the extended query protocol
+    let mut skip_until_sync = false;
+    loop {
+        if process.is_killed() {
+            info!("Connection {} was killed. Connection closed.", process.id());
+            return;
+        }
+
+        let (tag, body) = match s.read_message() {
+            Ok(m) => m,
+            Err(ref e)
+                if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut =>
+            {
+                info!(
+                    "Connection {} of user '{}' was idle for too long. Connection closed.",
+                    process.id(),
+                    user._name
+                );
+                return;
+            }
+            Err(_) => {
+                info!(
+                    "Connection {} of user '{}' was closed by the client.",
+                    process.id(),
+                    user._name
+                );
+                return;
+            }
+        };
+
+        let in_transaction = || transaction::is_active(process.id());
+        let res = match tag {
+            // Terminate
+            b'X' => {
+                debug!("PostgreSQL client disconnected properly.");
+                return;
+            }
+            // Simple query
+            b'Q' => {
+                let q = read_cstr(&body, &mut 0);
+                debug!("PostgreSQL query received: {}", q);
+                let statements = split_statements(&q);
+                let mut res = if statements.is_empty() {
+                    s.write_message(b'I', &[]) // EmptyQueryResponse
+                } else {
+                    Ok(())
+                };
+                for (offset, statement) in statements {
+                    let result = conn::run_query(statement, &mut user, &process);
+                    let failed = result.is_err();
+                    res =
+                        res.and_then(|_| send_query_result(&mut s, statement, offset, &q, result));
+                    // The remaining statements are skipped after an error
+                    if failed {
+                        break;
+                    }
+                }
+                res.and_then(|_| s.write_ready_for_query(in_transaction()))
+            }
+            // Sync ends an extended query
+            b'S' => {
+                skip_until_sync = false;
+                s.write_ready_for_query(in_transaction())
+            }
+            _ if skip_until_sync => Ok(()),
+            // Flush
+            b'H' => s.stream.flush(),
+            // Parse, Bind, Describe, Execute, Close
+            b'P' | b'B' | b'D' | b'E' | b'C' => {
+                skip_until_sync = true;
+                s.write_simple_error(
+                    FEATURE_NOT_SUPPORTED,
+                    "extended query protocol is not supported",
+                )
+            }
+            other => {
+                debug!("Unsupported PostgreSQL message {:?}", other as char);
+                s.write_simple_error(PROTOCOL_VIOLATION, "unsupported message")
+                    .and_then(|_| s.write_ready_for_query(in_transaction()))
+            }
+        };
+        if let Err(e) = res {
+            warn!("Failed to send message: {:?}", e);
+        }
+    }
+}
+
+#[test]
+fn test_split_statements() {
+    let q = "select * from t; insert into t values (';', 1);\n;";
+    assert_eq!(
+        split_statements(q),
+        vec![
+            (0, "select * from t"),
+            (16, " insert into t values (';', 1)")
+        ]
+    );
+    assert!(split_statements(" ; /* ; */ -- ;").is_empty());
+    let q = "select 'it\\'s;' from `a;b`;select 1";
+    assert_eq!(
+        split_statements(q),
+        vec![(0, "select 'it\\'s;' from `a;b`"), (27, "select 1")]
+    );
+    let q = "select 1 -- it's one;\n/**/; select /* it's; */ 2";
+    assert_eq!(
+        split_statements(q),
+        vec![
+            (0, "select 1 -- it's one;\n/**/"),
+            (27, " select /* it's; */ 2")
+        ]
+    );
+}
+
+#[test]
+fn test_startup_parameters() {
+    let data = b"user\0elena\0database\0shop\0\0";
+    assert_eq!(
+        startup_parameters(data),
+        vec![
+            ("user".to_string(), "elena".to_string()),
+            ("database".to_string(), "shop".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn test_command_tag() {
+    assert_eq!(command_tag("  create table t (a int)"), "CREATE");
+    assert_eq!(command_tag("insert into t values (1)"), "INSERT 0 1");
+}
@@ -0,0 +1,433 @@
+//! TLS for the native protocol
+//!
+//! A client asks for TLS by sending `Command::StartTls` instead of its login.
+//! The server answers with `Ok` and both sides perform the TLS handshake on
+//! the same connection, the login and everything after it are encrypted.
+//! Without `tls_cert` the server answers with `Error::TlsUnavailable`.
+//!
+//! If `tls_ca` is configured, the server also asks the client for a
+//! certificate signed by one of these authorities. The rules of `cert_users`
+//! map a verified certificate to a user, who then logs in without a
+//! password, see `user_of`. Clients without a certificate log in by
+//! password as usual.
+
+use config;
+use openssl::error::ErrorStack;
+use openssl::nid::Nid;
+use openssl::ssl::{SslAcceptor, SslFiletype, SslMethod, SslStream, SslVerifyMode};
+use openssl::x509::{X509Name, X509VerifyResult, X509};
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// The acceptor built from the configuration, `None` without TLS
+static ACCEPTOR: Mutex<Option<Arc<SslAcceptor>>> = Mutex::new(None);
+/// Whether the acceptor asks clients for certificates
+static CLIENT_CERTIFICATES: AtomicBool = AtomicBool::new(false);
+
+/// A connection of the native protocol, encrypted or not.
+///
+/// An encrypted stream can be cloned like a `TcpStream`: all clones share
+/// the TLS session, which is locked for every read and write. Notifications
+/// are written into a clone while the connection waits for its next command,
+/// see `process::Handle::lock_writes`.
+pub enum Stream {
+    Plain(TcpStream),
+    /// The socket besides the session, for timeouts and shutting it down
+    Tls(TcpStream, Arc<Mutex<SslStream<TcpStream>>>),
+}
+
+impl Stream {
+    /// Returns the underlying socket.
+    pub fn tcp(&self) -> &TcpStream {
+        match self {
+            &Stream::Plain(ref tcp) => tcp,
+            &Stream::Tls(ref tcp, _) => tcp,
+        }
+    }
+
+    pub fn try_clone(&self) -> io::Result<Stream> {
+        Ok(match self {
+            &Stream::Plain(ref tcp) => Stream::Plain(try!(tcp.try_clone())),
+            &Stream::Tls(ref tcp, ref ssl) => Stream::Tls(try!(tcp.try_clone()), ssl.clone()),
+        })
+    }
+
+    /// Returns true if the stream is encrypted.
+    pub fn is_tls(&self) -> bool {
+        match self {
+            &Stream::Plain(_) => false,
+            &Stream::Tls(..) => true,
+        }
+    }
+
+    /// Returns true if decrypted data waits to be read. The socket does not
+    /// become readable for it anymore.
+    pub fn has_buffered(&self) -> bool {
+        match self {
+            &Stream::Plain(_) => false,
+            &Stream::Tls(_, ref ssl) => ssl.lock().unwrap().ssl().pending() > 0,
+        }
+    }
+
+    /// Returns the certificate of the client, if it sent one and the
+    /// server verified it.
+    pub fn peer_certificate(&self) -> Option<X509> {
+        match self {
+            &Stream::Plain(_) => None,
+            &Stream::Tls(_, ref ssl) => {
+                let ssl = ssl.lock().unwrap();
+                match ssl.ssl().verify_result() {
+                    X509VerifyResult::OK => ssl.ssl().peer_certificate(),
+                    _ => None,
+                }
+            }
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            &mut Stream::Plain(ref mut tcp) => tcp.read(buf),
+            &mut Stream::Tls(_, ref ssl) => ssl.lock().unwrap().read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            &mut Stream::Plain(ref mut tcp) => tcp.write(buf),
+            &mut Stream::Tls(_, ref ssl) => ssl.lock().unwrap().write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            &mut Stream::Plain(ref mut tcp) => tcp.flush(),
+            &mut Stream::Tls(_, ref ssl) => ssl.lock().unwrap().flush(),
+        }
+    }
+}
+
+/// Builds the acceptor for the certificate and private key of the server,
+/// both in PEM files. With the PEM file of authorities `ca`, clients are
+/// asked for a certificate signed by one of them.
+pub fn acceptor(cert: &str, key: &str, ca: Option<&str>) -> Result<SslAcceptor, ErrorStack> {
+    let mut builder = try!(SslAcceptor::mozilla_intermediate_v5(SslMethod::tls_server()));
+    try!(builder.set_certificate_chain_file(cert));
+    try!(builder.set_private_key_file(key, SslFiletype::PEM));
+    try!(builder.check_private_key());
+    // a ticket arriving after the handshake would look like a response to
+    // a client waiting for notifications
+    try!(builder.set_num_tickets(0));
+    if let Some(ca) = ca {
+        try!(builder.set_ca_file(ca));
+        builder.set_client_ca_list(try!(X509Name::load_client_ca_file(ca)));
+        // clients without a certificate log in by password
+        builder.set_verify(SslVerifyMode::PEER);
+    }
+    Ok(builder.build())
+}
+
+/// Enables TLS for the connections from now on, see `acceptor`.
+pub fn init(cert: &str, key: &str, ca: Option<&str>) -> Result<(), ErrorStack> {
+    let acceptor = try!(acceptor(cert, key, ca));
+    *ACCEPTOR.lock().unwrap() = Some(Arc::new(acceptor));
+    CLIENT_CERTIFICATES.store(ca.is_some(), Ordering::SeqCst);
+    Ok(())
+}
+
+/// Returns true if clients may ask for TLS.
+pub fn is_enabled() -> bool {
+    ACCEPTOR.lock().unwrap().is_some()
+}
+
+/// Returns true if clients may log in by certificate.
+pub fn verifies_clients() -> bool {
+    CLIENT_CERTIFICATES.load(Ordering::SeqCst)
+}
+
+/// Performs the handshake of the server on a connection that asked for
+/// TLS.
+pub fn accept(tcp: TcpStream) -> io::Result<Stream> {
+    let acceptor = match *ACCEPTOR.lock().unwrap() {
+        Some(ref acceptor) => acceptor.clone(),
+        None => return Err(io::Error::new(io::ErrorKind::Other, "TLS is not enabled")),
+    };
+    let socket = try!(tcp.try_clone());
+    match acceptor.accept(tcp) {
+        Ok(ssl) => Ok(Stream::Tls(socket, Arc::new(Mutex::new(ssl)))),
+        Err(e) => Err(io::Error::new(io::ErrorKind::Other, e.to_string())),
+    }
+}
+
+/// Returns the user the verified certificate of the client maps to by the
+/// rules of `cert_users`, `None` without certificate or matching rule.
+pub fn user_of(stream: &Stream) -> Option<String> {
+    let cert = match stream.peer_certificate() {
+        Some(cert) => cert,
+        None => return None,
+    };
+    map_certificate(&cert, &config::current().cert_users)
+}
+
+/// Returns the user of the first rule matching the certificate.
+pub fn map_certificate(cert: &X509, rules: &[CertRule]) -> Option<String> {
+    let common_names: Vec<String> = cert
+        .subject_name()
+        .entries_by_nid(Nid::COMMONNAME)
+        .filter_map(|entry| entry.data().as_utf8().ok())
+        .map(|name| name.to_string())
+        .collect();
+    let alt_names: Vec<String> = cert
+        .subject_alt_names()
+        .map(|names| {
+            names
+                .iter()
+                .filter_map(|name| name.dnsname().or(name.email()))
+                .map(|name| name.to_string())
+                .collect()
+        })
+        .unwrap_or_default();
+    for rule in rules {
+        let names = match rule.field {
+            CertField::CommonName => &common_names,
+            CertField::AltName => &alt_names,
+        };
+        if let Some(name) = names.iter().find(|name| matches(&rule.pattern, name)) {
+            return Some(match &*rule.user {
+                "*" => name.clone(),
+                user => user.to_string(),
+            });
+        }
+    }
+    None
+}
+
+/// Part of a certificate a `CertRule` looks at
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CertField {
+    /// The common name of the subject, `CN`
+    CommonName,
+    /// A DNS name or e-mail address of the subject alternative names, `SAN`
+    AltName,
+}
+
+/// Rule of `cert_users`, like `CN=alice:alice`, `SAN=*.ops.example.com:ops`
+/// or `CN=*:*`: a `*` in the pattern matches any text, the user `*` is the
+/// matching name itself.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CertRule {
+    pub field: CertField,
+    pub pattern: String,
+    pub user: String,
+}
+
+impl FromStr for CertRule {
+    type Err = String;
+
+    fn from_str(rule: &str) -> Result<CertRule, String> {
+        let (field, rest) = if rule.starts_with("CN=") {
+            (CertField::CommonName, &rule[3..])
+        } else if rule.starts_with("SAN=") {
+            (CertField::AltName, &rule[4..])
+        } else {
+            return Err(format!("starts with neither 'CN=' nor 'SAN=': '{}'", rule));
+        };
+        // the names of a certificate may contain colons, user names do not
+        match rest.rfind(':') {
+            Some(i) if i > 0 && i + 1 < rest.len() => Ok(CertRule {
+                field: field,
+                pattern: rest[..i].to_string(),
+                user: rest[i + 1..].to_string(),
+            }),
+            _ => Err(format!(
+                "has no pattern and user like 'CN=name:user': '{}'",
+                rule
+            )),
+        }
+    }
+}
+
+impl fmt::Display for CertRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let field = match self.field {
+            CertField::CommonName => "CN",
+            CertField::AltName => "SAN",
+        };
+        write!(f, "{}={}:{}", field, self.pattern, self.user)
+    }
+}
+
+/// Returns true if the name matches the pattern, in which `*` stands for
+/// any text.
+fn matches(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or("");
+    if !name.starts_with(first) {
+        return false;
+    }
+    let mut rest = &name[first.len()..];
+    let mut parts: Vec<_> = parts.collect();
+    let last = match parts.pop() {
+        Some(last) => last,
+        // no star at all
+        None => return rest.is_empty(),
+    };
+    for part in parts {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+#[cfg(test)]
+fn certificate(
+    name: &str,
+    issuer: Option<(&X509, &::openssl::pkey::PKey<::openssl::pkey::Private>)>,
+) -> (X509, ::openssl::pkey::PKey<::openssl::pkey::Private>) {
+    use openssl::asn1::Asn1Time;
+    use openssl::bn::BigNum;
+    use openssl::hash::MessageDigest;
+    use openssl::pkey::PKey;
+    use openssl::rsa::Rsa;
+    use openssl::x509::extension::{BasicConstraints, SubjectAlternativeName};
+    use openssl::x509::{X509Builder, X509NameBuilder};
+
+    let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+    let mut subject = X509NameBuilder::new().unwrap();
+    subject.append_entry_by_nid(Nid::COMMONNAME, name).unwrap();
+    let subject = subject.build();
+    let mut builder = X509Builder::new().unwrap();
+    builder.set_version(2).unwrap();
+    let serial = BigNum::from_u32(name.len() as u32 + 1)
+        .unwrap()
+        .to_asn1_integer()
+        .unwrap();
+    builder.set_serial_number(&serial).unwrap();
+    builder.set_subject_name(&subject).unwrap();
+    builder.set_pubkey(&key).unwrap();
+    builder
+        .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+        .unwrap();
+    builder
+        .set_not_after(&Asn1Time::days_from_now(1).unwrap())
+        .unwrap();
+    match issuer {
+        Some((cert, issuer_key)) => {
+            builder.set_issuer_name(cert.subject_name()).unwrap();
+            let alt_names = SubjectAlternativeName::new()
+                .dns(name)
+                .build(&builder.x509v3_context(Some(cert), None))
+                .unwrap();
+            builder.append_extension(alt_names).unwrap();
+            builder.sign(issuer_key, MessageDigest::sha256()).unwrap();
+        }
+        None => {
+            builder.set_issuer_name(&subject).unwrap();
+            builder
+                .append_extension(BasicConstraints::new().critical().ca().build().unwrap())
+                .unwrap();
+            builder.sign(&key, MessageDigest::sha256()).unwrap();
+        }
+    }
+    (builder.build(), key)
+}
+
+#[test]
+fn test_client_certificate_maps_to_user() {
+    use openssl::ssl::SslConnector;
+    use std::env;
+    use std::fs::{self, File};
+    use std::net::TcpListener;
+    use std::thread;
+
+    let (ca, ca_key) = certificate("uosql test authority", None);
+    let (server, server_key) = certificate("localhost", Some((&ca, &ca_key)));
+    let (client, client_key) = certificate("alice", Some((&ca, &ca_key)));
+
+    let dir = env::temp_dir().join(format!("uosql-tls-{}", ::std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let write = |name: &str, pem: Vec<u8>| {
+        let path = dir.join(name);
+        File::create(&path).unwrap().write_all(&pem).unwrap();
+        path.to_string_lossy().into_owned()
+    };
+    let ca_file = write("ca.pem", ca.to_pem().unwrap());
+    let cert_file = write("server.pem", server.to_pem().unwrap());
+    let key_file = write("server.key", server_key.private_key_to_pem_pkcs8().unwrap());
+    let acceptor = acceptor(&cert_file, &key_file, Some(&ca_file)).unwrap();
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let client_thread = thread::spawn(move || {
+        let mut connector = SslConnector::builder(SslMethod::tls_client()).unwrap();
+        connector.cert_store_mut().add_cert(ca).unwrap();
+        connector.set_certificate(&client).unwrap();
+        connector.set_private_key(&client_key).unwrap();
+        let tcp = TcpStream::connect(addr).unwrap();
+        let mut ssl = connector.build().connect("localhost", tcp).unwrap();
+        ssl.write_all(b"hello").unwrap();
+        ssl.flush().unwrap();
+    });
+    let (tcp, _) = listener.accept().unwrap();
+    let socket = tcp.try_clone().unwrap();
+    let ssl = acceptor.accept(tcp).unwrap();
+    let mut stream = Stream::Tls(socket, Arc::new(Mutex::new(ssl)));
+    let mut hello = [0; 5];
+    stream.read_exact(&mut hello).unwrap();
+    client_thread.join().unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(&hello, b"hello");
+    assert!(stream.is_tls());
+    let cert = stream.peer_certificate().unwrap();
+    let rules =
+        |rules: &[&str]| -> Vec<CertRule> { rules.iter().map(|r| r.parse().unwrap()).collect() };
+    assert_eq!(
+        map_certificate(&cert, &rules(&["CN=alice:alice"])),
+        Some("alice".into())
+    );
+    assert_eq!(
+        map_certificate(&cert, &rules(&["CN=bob:bob", "CN=*:*"])),
+        Some("alice".into())
+    );
+    assert_eq!(
+        map_certificate(&cert, &rules(&["SAN=ali*:ops"])),
+        Some("ops".into())
+    );
+    assert_eq!(
+        map_certificate(&cert, &rules(&["CN=bob:bob", "SAN=*.example.com:ops"])),
+        None
+    );
+}
+
+#[test]
+fn test_cert_rules() {
+    let rule: CertRule = "SAN=*.ops.example.com:ops".parse().unwrap();
+    assert_eq!(rule.field, CertField::AltName);
+    assert_eq!(rule.pattern, "*.ops.example.com");
+    assert_eq!(rule.user, "ops");
+    assert_eq!(rule.to_string(), "SAN=*.ops.example.com:ops");
+    let rule: CertRule = "CN=urn:a:b:carol".parse().unwrap();
+    assert_eq!((&*rule.pattern, &*rule.user), ("urn:a:b", "carol"));
+    assert!("OU=ops:ops".parse::<CertRule>().is_err());
+    assert!("CN=alice".parse::<CertRule>().is_err());
+    assert!("CN=alice:".parse::<CertRule>().is_err());
+
+    assert!(matches("alice", "alice"));
+    assert!(!matches("alice", "alice2"));
+    assert!(matches("*", ""));
+    assert!(matches("*.example.com", "db.example.com"));
+    assert!(!matches("*.example.com", "example.com"));
+    assert!(matches("a*c*e", "abcde"));
+    assert!(!matches("a*c*e", "abde"));
+    assert!(!matches("ab*ba", "aba"));
+}
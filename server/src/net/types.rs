@@ -1,8 +1,20 @@
 /// Because of cyclic references to modules we need to use super::Error to use
 /// the enum. Nightly Build supports using enums - so we can fix super::Error in
 /// about 3 months ;)
+use auth::AuthError;
+use csv;
+use parse::parser::ParseError;
+use parse::Span;
+use query::ExecutionError;
+use serde_json::{self, Map, Value};
+use std::cmp;
 use std::error::Error;
-use storage::types::FromSql;
+use std::fmt;
+use std::io;
+use storage;
+use storage::decimal;
+use storage::temporal;
+use storage::types::{self, FromSql};
 use storage::ResultSet;
 use storage::{Column, SqlType};
 
@@ -11,6 +23,7 @@ use serde::{Deserialize, Serialize};
 /// Representation of a ResultSet with its useful functions to get data.
 pub struct DataSet {
     data: Vec<Vec<Vec<u8>>>,
+    nulls: Vec<Vec<bool>>,
     columns: Vec<Column>,
     current_pos: usize,
     line_cnt: usize,
@@ -116,10 +129,16 @@ impl DataSet {
         }
     }
 
-    /// Return next data entry. next() has to be called first it initialize
-    /// the pointer
+    /// Return true if the next data entry is null. next() has to be called
+    /// first it initialize the pointer
+    pub fn next_is_null_by_idx(&self, idx: usize) -> bool {
+        idx < self.columns.len() && self.nulls[self.current_pos - 1][idx]
+    }
+
+    /// Return next data entry or None if it is null. next() has to be called
+    /// first it initialize the pointer
     pub fn next_int_by_idx(&mut self, idx: usize) -> Option<i32> {
-        if idx >= self.columns.len() {
+        if idx >= self.columns.len() || self.next_is_null_by_idx(idx) {
             //idx out of bounds
             None
         } else {
@@ -133,10 +152,10 @@ impl DataSet {
         }
     }
 
-    /// Return next data entry. next() has to be called first it initialize
-    /// the pointer
+    /// Return next data entry or None if it is null. next() has to be called
+    /// first it initialize the pointer
     pub fn next_bool_by_idx(&mut self, idx: usize) -> Option<bool> {
-        if idx >= self.columns.len() {
+        if idx >= self.columns.len() || self.next_is_null_by_idx(idx) {
             //idx out of bounds
             None
         } else {
@@ -150,29 +169,94 @@ impl DataSet {
         }
     }
 
-    /// Return next data entry. next() has to be called first it initialize
-    /// the pointer
+    /// Return next data entry or None if it is null. next() has to be called
+    /// first it initialize the pointer
     pub fn next_char_by_idx(&mut self, idx: usize) -> Option<String> {
-        if idx >= self.columns.len() {
+        if idx >= self.columns.len() || self.next_is_null_by_idx(idx) {
             //idx out of bounds
             None
         } else {
-            // find the first pos that does not contain '0' value
-            let mut pos = 0;
+            // without the padding or the length in front
             let data = &self.data[self.current_pos - 1][idx][..];
-            while pos < self.columns[idx].sql_type.size() as usize {
-                if data[pos] == 0 {
-                    break;
-                }
-                pos += 1;
-            }
-            match String::from_sql(&self.data[self.current_pos - 1][idx][0..pos]) {
+            match String::from_sql(self.columns[idx].sql_type.content(data)) {
                 Ok(val) => Some(val),
                 Err(_e) => None,
             }
         }
     }
 
+    /// Return next data entry or None if it is null. next() has to be called
+    /// first it initialize the pointer
+    pub fn next_bigint_by_idx(&mut self, idx: usize) -> Option<i64> {
+        self.next_value_by_idx(idx)
+    }
+
+    /// Return next data entry or None if it is null. next() has to be called
+    /// first it initialize the pointer
+    pub fn next_float_by_idx(&mut self, idx: usize) -> Option<f32> {
+        self.next_value_by_idx(idx)
+    }
+
+    /// Return next data entry or None if it is null. next() has to be called
+    /// first it initialize the pointer
+    pub fn next_double_by_idx(&mut self, idx: usize) -> Option<f64> {
+        self.next_value_by_idx(idx)
+    }
+
+    /// Return next data entry as `YYYY-MM-DD` or None if it is null.
+    /// next() has to be called first it initialize the pointer
+    pub fn next_date_by_idx(&mut self, idx: usize) -> Option<String> {
+        self.next_value_by_idx(idx).map(temporal::format_date)
+    }
+
+    /// Return next data entry as `HH:MM:SS[.ffffff]` or None if it is null.
+    /// next() has to be called first it initialize the pointer
+    pub fn next_time_by_idx(&mut self, idx: usize) -> Option<String> {
+        self.next_value_by_idx(idx).map(temporal::format_time)
+    }
+
+    /// Return next data entry as `YYYY-MM-DD HH:MM:SS[.ffffff]` or None if
+    /// it is null. next() has to be called first it initialize the pointer
+    pub fn next_timestamp_by_idx(&mut self, idx: usize) -> Option<String> {
+        self.next_value_by_idx(idx).map(temporal::format_timestamp)
+    }
+
+    /// Return next data entry or None if it is null. Values of BLOB columns
+    /// are sent as VARBINARY. next() has to be called first it initialize
+    /// the pointer
+    pub fn next_bytes_by_idx(&mut self, idx: usize) -> Option<Vec<u8>> {
+        match self.get_type_by_idx(idx) {
+            Some(SqlType::VarBinary(_)) if !self.next_is_null_by_idx(idx) => {
+                let sql_type = self.columns[idx].sql_type;
+                Some(
+                    sql_type
+                        .content(&self.data[self.current_pos - 1][idx])
+                        .to_vec(),
+                )
+            }
+            _ => None,
+        }
+    }
+
+    /// Return next data entry as a number like `-12.50` or None if it is
+    /// null. next() has to be called first it initialize the pointer
+    pub fn next_decimal_by_idx(&mut self, idx: usize) -> Option<String> {
+        match self.get_type_by_idx(idx) {
+            Some(SqlType::Decimal(_, scale)) => self
+                .next_value_by_idx(idx)
+                .map(|v| decimal::format(v, scale)),
+            _ => None,
+        }
+    }
+
+    fn next_value_by_idx<T: FromSql>(&self, idx: usize) -> Option<T> {
+        if idx >= self.columns.len() || self.next_is_null_by_idx(idx) {
+            None
+        } else {
+            T::from_sql(&self.data[self.current_pos - 1][idx][..]).ok()
+        }
+    }
+
     /// Return next data entry. next() has to be called first it initialize
     /// the pointer
     pub fn next_int_by_name(&mut self, name: String) -> Option<i32> {
@@ -200,12 +284,161 @@ impl DataSet {
         }
     }
 
+    /// Return next data entry. next() has to be called first it initialize
+    /// the pointer
+    pub fn next_bigint_by_name(&mut self, name: String) -> Option<i64> {
+        match self.get_col_idx(name) {
+            Some(idx) => self.next_bigint_by_idx(idx),
+            None => None,
+        }
+    }
+
+    /// Return next data entry. next() has to be called first it initialize
+    /// the pointer
+    pub fn next_float_by_name(&mut self, name: String) -> Option<f32> {
+        match self.get_col_idx(name) {
+            Some(idx) => self.next_float_by_idx(idx),
+            None => None,
+        }
+    }
+
+    /// Return next data entry. next() has to be called first it initialize
+    /// the pointer
+    pub fn next_double_by_name(&mut self, name: String) -> Option<f64> {
+        match self.get_col_idx(name) {
+            Some(idx) => self.next_double_by_idx(idx),
+            None => None,
+        }
+    }
+
+    /// Return next data entry. next() has to be called first it initialize
+    /// the pointer
+    pub fn next_date_by_name(&mut self, name: String) -> Option<String> {
+        match self.get_col_idx(name) {
+            Some(idx) => self.next_date_by_idx(idx),
+            None => None,
+        }
+    }
+
+    /// Return next data entry. next() has to be called first it initialize
+    /// the pointer
+    pub fn next_time_by_name(&mut self, name: String) -> Option<String> {
+        match self.get_col_idx(name) {
+            Some(idx) => self.next_time_by_idx(idx),
+            None => None,
+        }
+    }
+
+    /// Return next data entry. next() has to be called first it initialize
+    /// the pointer
+    pub fn next_timestamp_by_name(&mut self, name: String) -> Option<String> {
+        match self.get_col_idx(name) {
+            Some(idx) => self.next_timestamp_by_idx(idx),
+            None => None,
+        }
+    }
+
+    /// Return next data entry. next() has to be called first it initialize
+    /// the pointer
+    pub fn next_bytes_by_name(&mut self, name: String) -> Option<Vec<u8>> {
+        match self.get_col_idx(name) {
+            Some(idx) => self.next_bytes_by_idx(idx),
+            None => None,
+        }
+    }
+
+    /// Return next data entry. next() has to be called first it initialize
+    /// the pointer
+    pub fn next_decimal_by_name(&mut self, name: String) -> Option<String> {
+        match self.get_col_idx(name) {
+            Some(idx) => self.next_decimal_by_idx(idx),
+            None => None,
+        }
+    }
+
+    /// Returns the next data entry as text, whatever its type, or None if
+    /// it is null. next() has to be called first it initialize the pointer
+    pub fn next_as_string_by_idx(&mut self, idx: usize) -> Option<String> {
+        match self.get_type_by_idx(idx) {
+            Some(SqlType::Int) => self.next_int_by_idx(idx).map(|v| v.to_string()),
+            Some(SqlType::Bool) => self.next_bool_by_idx(idx).map(|v| v.to_string()),
+            Some(SqlType::BigInt) => self.next_bigint_by_idx(idx).map(|v| v.to_string()),
+            Some(SqlType::Float) => self.next_float_by_idx(idx).map(|v| v.to_string()),
+            Some(SqlType::Double) => self.next_double_by_idx(idx).map(|v| v.to_string()),
+            Some(SqlType::Date) => self.next_date_by_idx(idx),
+            Some(SqlType::Time) => self.next_time_by_idx(idx),
+            Some(SqlType::Timestamp) => self.next_timestamp_by_idx(idx),
+            Some(SqlType::Decimal(..)) => self.next_decimal_by_idx(idx),
+            // as hex digits behind `\x`, like PostgreSQL does
+            Some(SqlType::VarBinary(_)) => self.next_bytes_by_idx(idx).map(|b| {
+                let hex: Vec<String> = b.iter().map(|byte| format!("{:02x}", byte)).collect();
+                format!("\\x{}", hex.concat())
+            }),
+            Some(SqlType::Blob) => None,
+            Some(_) => self.next_char_by_idx(idx),
+            None => None,
+        }
+    }
+
     /// Set the data pointer before the first entry (pos = -1). next() has to be
     /// called first to start a new next... - loop
     pub fn first(&mut self) {
         self.current_pos = 0
     }
 
+    /// Return the number of lines.
+    pub fn get_line_cnt(&self) -> usize {
+        self.line_cnt
+    }
+
+    /// Set the data pointer before the line of the index, counted from 0, so
+    /// that next() moves to it. Used to show a page of the lines.
+    pub fn seek(&mut self, line: usize) {
+        self.current_pos = cmp::min(line, self.line_cnt)
+    }
+
+    /// Write all lines as CSV text, see `csv::record`: the column names,
+    /// then a record per line. Leaves the data pointer after the last entry.
+    pub fn write_csv<W: io::Write>(&mut self, out: &mut W) -> io::Result<()> {
+        let names: Vec<_> = self.columns.iter().map(|c| Some(c.name.clone())).collect();
+        try!(out.write_all(csv::record(&names).as_bytes()));
+        self.first();
+        while self.next() {
+            let fields: Vec<_> = (0..self.columns.len())
+                .map(|i| self.next_as_string_by_idx(i))
+                .collect();
+            try!(out.write_all(csv::record(&fields).as_bytes()));
+        }
+        Ok(())
+    }
+
+    /// Write all lines as a JSON array of objects, whose keys are the column
+    /// names. Numbers and booleans are those of JSON, other values strings.
+    /// Leaves the data pointer after the last entry.
+    pub fn write_json<W: io::Write>(&mut self, out: &mut W) -> io::Result<()> {
+        try!(out.write_all(b"["));
+        self.first();
+        while self.next() {
+            if self.current_pos > 1 {
+                try!(out.write_all(b","));
+            }
+            let mut object = Map::new();
+            for i in 0..self.columns.len() {
+                let value = match self.columns[i].sql_type {
+                    SqlType::Int => self.next_int_by_idx(i).map(Value::from),
+                    SqlType::BigInt => self.next_bigint_by_idx(i).map(Value::from),
+                    SqlType::Float => self.next_float_by_idx(i).map(|f| Value::from(f as f64)),
+                    SqlType::Double => self.next_double_by_idx(i).map(Value::from),
+                    SqlType::Bool => self.next_bool_by_idx(i).map(Value::from),
+                    _ => self.next_as_string_by_idx(i).map(Value::from),
+                };
+                object.insert(self.columns[i].name.clone(), value.unwrap_or(Value::Null));
+            }
+            try!(serde_json::to_writer(&mut *out, &object));
+        }
+        out.write_all(b"]")
+    }
+
     /// Set the data pointer after the last entry . previous() has to be called
     /// first to start a new backward loop
     pub fn last(&mut self) {
@@ -238,28 +471,30 @@ pub fn preprocess(data: &ResultSet) -> DataSet {
     let col_count = data.columns.len();
     let data_len = data.data.len();
     // get line length
-    let mut line_len = 0;
+    let line_len = types::row_size(&data.columns);
     let mut arr = Vec::<u32>::new();
     for i in 0..(col_count) {
-        line_len += data.columns[i].get_size();
         arr.push(data.columns[i].get_size());
     }
     // number of lines
     if line_len == 0 {
         return DataSet {
             data: Vec::new(),
+            nulls: Vec::new(),
             columns: data.columns.clone(),
             current_pos: 0,
             line_cnt: 0,
         };
     }
 
-    let line_count = data_len / line_len as usize;
+    let line_count = data_len / line_len;
     let mut process_data = Vec::new();
+    let mut process_nulls = Vec::new();
 
     // split data
     let mut pos = 0;
     for _i in 0..(line_count) {
+        let line = &data.data[pos..pos + line_len];
         let mut colvec = Vec::new();
         for j in 0..(col_count) {
             let mut linevec = Vec::<u8>::new();
@@ -269,12 +504,19 @@ pub fn preprocess(data: &ResultSet) -> DataSet {
             }
             colvec.push(linevec); // push the single data vec to column
         }
+        // skip the null bitmap following the values
+        let nullvec = (0..col_count)
+            .map(|j| types::is_null(&data.columns, line, j))
+            .collect();
+        pos += types::null_bitmap_size(&data.columns);
         process_data.push(colvec);
+        process_nulls.push(nullvec);
     }
     // println!("data = {:?}", data);
     // println!("process data = {:?}", process_data);
     DataSet {
         data: process_data,
+        nulls: process_nulls,
         columns: data.columns.clone(),
         current_pos: 0,
         line_cnt: line_count,
@@ -293,66 +535,398 @@ pub enum PkgType {
     Response,
     AccDenied,
     AccGranted,
+    /// Sent instead of `AccGranted` if a connection limit is reached
+    TooManyConnections,
+    /// Pushed to a connection listening on a channel, see `Command::Listen`.
+    /// May arrive at any time, also between a command and its response.
+    Notification,
+    /// Pushed to every connection once the server begins shutting down,
+    /// with a `ShutdownWarning`. May arrive at any time, like
+    /// `Notification`.
+    ShuttingDown,
+    /// Streamed to a follower after `Command::Replicate`, with a `Change`
+    Change,
+    /// Sent to a follower instead of a `Change` while nothing changes, with a
+    /// `Heartbeat`
+    Heartbeat,
 }
 
-/// Struct to send the kind of error and error message to the client
+/// Bitvector of optional protocol features.
+///
+/// Both sides announce what they support during the handshake. Only the
+/// intersection (see `Negotiation`) may be used on a connection.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Capabilities(pub u32);
+
+impl Capabilities {
+    pub const NONE: Capabilities = Capabilities(0);
+    pub const TLS: Capabilities = Capabilities(1 << 0);
+    pub const COMPRESSION: Capabilities = Capabilities(1 << 1);
+    pub const PREPARED_STATEMENTS: Capabilities = Capabilities(1 << 2);
+
+    /// Returns true if all features in `other` are set in `self`.
+    pub fn contains(&self, other: Capabilities) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Returns the features supported by both sides.
+    pub fn intersect(&self, other: Capabilities) -> Capabilities {
+        Capabilities(self.0 & other.0)
+    }
+
+    /// Returns the union of both feature sets.
+    pub fn with(&self, other: Capabilities) -> Capabilities {
+        Capabilities(self.0 | other.0)
+    }
+}
+
+/// Struct to send the kind of error and error message to the client.
+/// Syntax errors additionally carry the position in the query and may come
+/// with a hint how to fix the query.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ClientErrMsg {
     code: u16,
     pub msg: String,
+    /// 1-based line of the error in the query
+    pub line: Option<u32>,
+    /// 1-based column of the error in the query
+    pub column: Option<u32>,
+    pub hint: Option<String>,
+    /// The syntax errors of the statements after the one of this error in a
+    /// script, in their order
+    pub others: Vec<ClientErrMsg>,
 }
 
 /// Convert the possible Error to a serializable ClientErrMsg struct
 impl From<super::Error> for ClientErrMsg {
     fn from(error: super::Error) -> ClientErrMsg {
         match error {
-            super::Error::Io(_) => ClientErrMsg {
-                code: 0,
-                msg: error.description().into(),
-            },
-            super::Error::UnexpectedPkg => ClientErrMsg {
-                code: 2,
-                msg: error.description().into(),
-            },
-            super::Error::UnknownCmd => ClientErrMsg {
-                code: 3,
-                msg: error.description().into(),
-            },
-            super::Error::Bincode(_) => ClientErrMsg {
-                code: 4,
-                msg: error.description().into(),
-            },
-            super::Error::UnEoq(_) => ClientErrMsg {
-                code: 6,
-                msg: error.description().into(),
-            },
+            super::Error::Io(_) => ClientErrMsg::new(0, error.description()),
+            super::Error::UnexpectedPkg => ClientErrMsg::new(2, error.description()),
+            super::Error::UnknownCmd => ClientErrMsg::new(3, error.description()),
+            super::Error::Bincode(_) => ClientErrMsg::new(4, error.description()),
+            super::Error::UnEoq(ref e) => {
+                let mut msg = ClientErrMsg::new(ClientErrMsg::PARSE_ERROR, &e.to_string());
+                msg.hint = e.hint().map(|h| h.into());
+                msg
+            }
+            super::Error::ProtocolMismatch => {
+                ClientErrMsg::new(ClientErrMsg::PROTOCOL_MISMATCH, error.description())
+            }
+            super::Error::UnknownConnection => ClientErrMsg::new(8, error.description()),
+            super::Error::PermissionDenied => ClientErrMsg::new(9, error.description()),
+            super::Error::ResponseTooLarge => ClientErrMsg::new(10, error.description()),
+            super::Error::CorruptPacket => ClientErrMsg::new(11, error.description()),
+            super::Error::PacketTooLarge => ClientErrMsg::new(12, error.description()),
+            super::Error::UnknownStatement => ClientErrMsg::new(15, error.description()),
+            super::Error::ReplicationOff => ClientErrMsg::new(16, error.description()),
+            super::Error::UnknownLsn => ClientErrMsg::new(17, error.description()),
+            super::Error::TlsUnavailable => ClientErrMsg::new(22, error.description()),
         }
     }
 }
 
+impl ClientErrMsg {
+    /// Error code sent when the query could not be parsed.
+    pub const PARSE_ERROR: u16 = 6;
+    /// Error code sent when client and server share no protocol version.
+    pub const PROTOCOL_MISMATCH: u16 = 7;
+    /// Error code sent when a command that runs a statement failed.
+    pub const EXECUTION_ERROR: u16 = 13;
+    /// Error code sent when the transaction was rolled back to resolve a
+    /// deadlock.
+    pub const DEADLOCK: u16 = 14;
+    /// Error code sent when a row would duplicate the value of a primary
+    /// key or `UNIQUE` constraint.
+    pub const UNIQUE_VIOLATION: u16 = 18;
+    /// Error code sent when a row references no row, or is still referenced,
+    /// through a foreign key.
+    pub const FOREIGN_KEY_VIOLATION: u16 = 19;
+    /// Error code sent when a row violates a `CHECK` constraint.
+    pub const CHECK_VIOLATION: u16 = 20;
+    /// Error code sent when a `NOT NULL` column would be null.
+    pub const NOT_NULL_VIOLATION: u16 = 21;
+    /// Error code sent when the password has to be changed with `ALTER USER`
+    /// before anything else.
+    pub const PASSWORD_EXPIRED: u16 = 23;
+
+    fn new(code: u16, msg: &str) -> ClientErrMsg {
+        ClientErrMsg {
+            code: code,
+            msg: msg.into(),
+            line: None,
+            column: None,
+            hint: None,
+            others: Vec::new(),
+        }
+    }
+
+    /// Creates the message for a syntax error in `query`, including the
+    /// position of the error. Of the errors of several statements, the first
+    /// one makes the message and the others follow it.
+    pub fn from_parse_error(error: &ParseError, query: &str) -> ClientErrMsg {
+        if let &ParseError::Several(ref errors) = error {
+            let mut msg = ClientErrMsg::from_parse_error(&errors[0], query);
+            msg.others = errors[1..]
+                .iter()
+                .map(|e| ClientErrMsg::from_parse_error(e, query))
+                .collect();
+            return msg;
+        }
+        let mut msg = ClientErrMsg::new(ClientErrMsg::PARSE_ERROR, &error.to_string());
+        let position = match error.span() {
+            Some(span) => Some(span.line_column(query)),
+            // The query ended too early: point behind its last character
+            None if *error == ParseError::UnexpectedEoq => Some(
+                Span {
+                    lo: query.trim_end().len(),
+                    hi: query.trim_end().len(),
+                }
+                .line_column(query),
+            ),
+            None => None,
+        };
+        if let Some((line, column)) = position {
+            msg.line = Some(line);
+            msg.column = Some(column);
+        }
+        msg.hint = error.hint().map(|h| h.into());
+        msg
+    }
+
+    /// Converts the error a statement failed with. Constraint violations get
+    /// their own code and name the constraint.
+    pub fn from_execution_error(error: &ExecutionError) -> ClientErrMsg {
+        match error {
+            &ExecutionError::AuthError(AuthError::PermissionDenied) => {
+                super::Error::PermissionDenied.into()
+            }
+            &ExecutionError::AuthError(AuthError::PasswordExpired) => ClientErrMsg::new(
+                ClientErrMsg::PASSWORD_EXPIRED,
+                "the password has expired, change it with ALTER USER first",
+            ),
+            &ExecutionError::StorageError(storage::Error::Deadlock) => ClientErrMsg::new(
+                ClientErrMsg::DEADLOCK,
+                "deadlock detected, the transaction was rolled back",
+            ),
+            &ExecutionError::StorageError(storage::Error::UniqueKeyValueExists(ref index)) => {
+                ClientErrMsg::new(
+                    ClientErrMsg::UNIQUE_VIOLATION,
+                    &format!("duplicate value for unique constraint '{}'", index),
+                )
+            }
+            &ExecutionError::StorageError(storage::Error::PrimaryKeyValueExists) => {
+                ClientErrMsg::new(
+                    ClientErrMsg::UNIQUE_VIOLATION,
+                    "duplicate value for the primary key",
+                )
+            }
+            &ExecutionError::ForeignKeyViolation(ref name) => ClientErrMsg::new(
+                ClientErrMsg::FOREIGN_KEY_VIOLATION,
+                &format!("no referenced row for foreign key '{}'", name),
+            ),
+            &ExecutionError::RowIsReferenced(ref name) => ClientErrMsg::new(
+                ClientErrMsg::FOREIGN_KEY_VIOLATION,
+                &format!("row is still referenced through foreign key '{}'", name),
+            ),
+            &ExecutionError::CheckViolation(ref name) => ClientErrMsg::new(
+                ClientErrMsg::CHECK_VIOLATION,
+                &format!("row violates check constraint '{}'", name),
+            ),
+            &ExecutionError::NotNullViolation(ref column) => ClientErrMsg::new(
+                ClientErrMsg::NOT_NULL_VIOLATION,
+                &format!("column '{}' must not be null", column),
+            ),
+            _ => ClientErrMsg::new(ClientErrMsg::EXECUTION_ERROR, &format!("{:?}", error)),
+        }
+    }
+
+    /// Returns the numeric error code.
+    pub fn code(&self) -> u16 {
+        self.code
+    }
+}
+
+/// Renders the message, e.g. `unexpected token at line 2, column 5`,
+/// followed by the hint on a separate line
+impl fmt::Display for ClientErrMsg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "{}", self.msg));
+        if let (Some(line), Some(column)) = (self.line, self.column) {
+            try!(write!(f, " at line {}, column {}", line, column));
+        }
+        if let Some(ref hint) = self.hint {
+            try!(write!(f, "\nhint: {}", hint));
+        }
+        for other in &self.others {
+            try!(write!(f, "\n{}", other));
+        }
+        Ok(())
+    }
+}
+
 /// This is the first packet being sent by the server after the TCP connection
 /// is established.
 #[derive(Serialize, Deserialize)]
 pub struct Greeting {
     pub protocol_version: u8, // 1 byte
     pub message: String,      // n bytes
+    pub server: ServerCapabilities,
 }
 
 impl Greeting {
-    pub fn make_greeting(version: u8, msg: String) -> Greeting {
+    pub fn make_greeting(version: u8, msg: String, server: ServerCapabilities) -> Greeting {
         Greeting {
             protocol_version: version,
             message: msg,
+            server: server,
         }
     }
 }
 
+/// Describes the server software, so that clients can adapt to it.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ServerCapabilities {
+    /// Version of the server, e.g. `0.1.0`
+    pub version: String,
+    /// Commit the server was built from, empty if unknown
+    pub build_commit: String,
+    /// Supported statements and SQL features, e.g. `SELECT` or `NOTIFY`
+    pub sql_features: Vec<String>,
+    /// Database new connections start in, if the user may access it
+    pub default_database: Option<String>,
+    /// Accepted ways to log in, e.g. `password`
+    pub auth_methods: Vec<String>,
+}
+
+impl ServerCapabilities {
+    /// Returns true if the server supports the given SQL feature.
+    pub fn supports(&self, feature: &str) -> bool {
+        self.sql_features
+            .iter()
+            .any(|f| f.eq_ignore_ascii_case(feature))
+    }
+}
+
 /// The client responds with this packet to a `Greeting` packet, finishing the
-/// authentication handshake.
+/// authentication handshake. Besides the credentials it contains the range
+/// of protocol versions and the features the client supports.
 #[derive(Default, Serialize, Deserialize)]
 pub struct Login {
     pub username: String,
     pub password: String,
+    pub min_version: u8,
+    pub max_version: u8,
+    pub capabilities: Capabilities,
+    /// Longest packet the client accepts
+    pub max_packet_size: u32,
+}
+
+/// Sent by the server right after `AccGranted`: the protocol version and
+/// features both sides agreed on. Neither side may use a feature that is not
+/// contained in `capabilities`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Negotiation {
+    pub version: u8,
+    pub capabilities: Capabilities,
+    /// Longest packet either side may send
+    pub max_packet_size: u32,
+}
+
+impl Negotiation {
+    /// Picks the highest version in both ranges, the common features and
+    /// the smaller maximum packet size. Returns `None` if the version ranges
+    /// do not overlap.
+    pub fn choose(
+        login: &Login,
+        min_version: u8,
+        max_version: u8,
+        capabilities: Capabilities,
+        max_packet_size: u32,
+    ) -> Option<Negotiation> {
+        let version = std::cmp::min(login.max_version, max_version);
+        if version < login.min_version || version < min_version {
+            return None;
+        }
+        Some(Negotiation {
+            version: version,
+            capabilities: login.capabilities.intersect(capabilities),
+            max_packet_size: std::cmp::min(login.max_packet_size, max_packet_size),
+        })
+    }
+}
+
+/// Sent with `AccGranted`. If the connection drops, the client may reconnect
+/// and send `Command::Resume(token)` to continue the session with its
+/// current database instead of logging in again. Every token can be used
+/// once, the resumed session gets a new one.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SessionInfo {
+    /// The id shown by `SHOW PROCESSLIST`
+    pub id: u64,
+    pub token: String,
+}
+
+/// Sent by the server to all connections listening on `channel` when a
+/// client executes `NOTIFY channel, 'payload'`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Notification {
+    pub channel: String,
+    pub payload: String,
+}
+
+/// Sent by the server to all connections when it begins shutting down.
+/// Queries still running after the grace period are cancelled and the
+/// connections closed.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ShutdownWarning {
+    /// Seconds until then
+    pub grace_period: u64,
+}
+
+/// A change of the leader streamed to its followers, see `replication`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Change {
+    /// Position in the replication log, counting up from 1
+    pub lsn: u64,
+    /// Milliseconds since the Unix epoch when the change was logged
+    pub time: u64,
+    /// What a committed transaction did, in the order it did it
+    pub steps: Vec<Step>,
+}
+
+/// A part of a `Change`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Step {
+    /// A statement to run that changed something else than rows, and the
+    /// current database it ran in
+    Query {
+        database: Option<String>,
+        query: String,
+    },
+    /// A row inserted into a table, and the values of its BLOB columns kept
+    /// outside of the row, see `storage::blob::overflow_values`
+    Insert {
+        database: String,
+        table: String,
+        row: Vec<u8>,
+        blobs: Vec<Vec<u8>>,
+    },
+    /// A row deleted from a table
+    Delete {
+        database: String,
+        table: String,
+        row: Vec<u8>,
+    },
+}
+
+/// Tells a follower how far the leader is
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Heartbeat {
+    /// Position of the last change of the leader
+    pub lsn: u64,
+    /// Milliseconds since the Unix epoch
+    pub time: u64,
 }
 
 /// Sent by the client to the server.
@@ -365,6 +939,42 @@ pub enum Command {
     Quit,
     Ping,
     Query(String),
+    /// Cancel the running query of the connection with the given id (see
+    /// `SHOW PROCESSLIST`) and close that connection.
+    Kill(u64),
+    /// Receive a `Notification` for every `NOTIFY` on the given channel
+    Listen(String),
+    /// Sent instead of a `Login` to continue a dropped session, see
+    /// `SessionInfo`
+    Resume(String),
+    /// Dump the given database as SQL text, answered by a `Response` with a
+    /// chunk of the text in every row and then `Ok`, see `DUMP DATABASE`
+    Dump(String),
+    /// Insert CSV text into the given table, see `COPY`. The text follows
+    /// in `CopyData` commands, ended by `CopyDone` or, if the client gives
+    /// up, by `CopyFail` with the reason. Answered by a `Response` with the
+    /// number of rows inserted once the text ended.
+    CopyIn(String),
+    CopyData(Vec<u8>),
+    CopyDone,
+    CopyFail(String),
+    /// Parse a statement once to run it repeatedly by `Execute`, see
+    /// `cache`. Answered by a `Response` with the id of the statement, which
+    /// lives as long as the connection. Only valid if the capability
+    /// `PREPARED_STATEMENTS` was negotiated, like the two commands below.
+    Prepare(String),
+    /// Run the prepared statement with the given id, answered like `Query`
+    Execute(u64),
+    /// Forget the prepared statement with the given id, answered by `Ok`
+    Deallocate(u64),
+    /// Stream the changes from the given position of the replication log on,
+    /// answered by a `Change` packet for every change, or a `Heartbeat` while
+    /// there is none, until the connection is closed. Only allowed for the
+    /// administrator.
+    Replicate(u64),
+    /// Sent instead of a `Login` to encrypt the connection first, answered
+    /// by `Ok` before the TLS handshake, see `net::tls`
+    StartTls,
     // Shutdown,
     // Statistics,
 }
@@ -1,12 +1,38 @@
-use super::super::storage::SqlType;
+use super::super::storage::{Compression, EngineID, Privilege, ReferentialAction, SqlType};
 /// Top level type. Is returned by `parse`.
 use super::token;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 #[derive(Debug, Clone, PartialEq)]
 pub enum Query {
     Dummy, // For Compiling
     DefStmt(DefStmt),
     ManipulationStmt(ManipulationStmt),
+    ControlStmt(ControlStmt),
+    TransactionStmt(TransactionStmt),
+}
+
+impl Query {
+    /// Returns true if the statement changes the schema, the data or the
+    /// privileges.
+    pub fn is_change(&self) -> bool {
+        match self {
+            &Query::DefStmt(_) => true,
+            &Query::ControlStmt(ControlStmt::Grant(_))
+            | &Query::ControlStmt(ControlStmt::Revoke(_)) => true,
+            &Query::ManipulationStmt(ref stmt) => match stmt {
+                &ManipulationStmt::Insert(_)
+                | &ManipulationStmt::InsertSelect(_)
+                | &ManipulationStmt::Update(_)
+                | &ManipulationStmt::Delete(_)
+                | &ManipulationStmt::Copy(_)
+                | &ManipulationStmt::Restore(_)
+                | &ManipulationStmt::RepairTable(_) => true,
+                _ => false,
+            },
+            _ => false,
+        }
+    }
 }
 
 /// All Data Definition Statements
@@ -15,6 +41,9 @@ pub enum DefStmt {
     Create(CreateStmt),
     Alter(AltStmt),
     Drop(DropStmt),
+    Truncate(String),
+    /// Renames a database: the old and the new name
+    RenameDatabase(String, String),
 }
 
 /// All Data Manipulation Statements
@@ -22,10 +51,97 @@ pub enum DefStmt {
 pub enum ManipulationStmt {
     Update(UpdateStmt),
     Select(SelectStmt),
+    /// Selects combined by `UNION`, `INTERSECT` or `EXCEPT`
+    Compound(CompoundStmt),
     Insert(InsertStmt),
+    /// `INSERT ... SELECT`
+    InsertSelect(InsertSelectStmt),
     Delete(DeleteStmt),
     Use(UseStmt),
     Describe(String),
+    /// `EXPLAIN`, the plan of the select without executing it
+    Explain(CompoundStmt),
+    Show(ShowStmt),
+    Notify(NotifyStmt),
+    Vacuum(Option<String>),
+    Analyze(Option<String>),
+    CheckTable(String),
+    RepairTable(String),
+    Backup(BackupStmt),
+    /// Restores the database backed up to the path
+    Restore(String),
+    /// Writes the tables of the database as SQL text
+    Dump(String),
+    Copy(CopyStmt),
+}
+
+/// All Data Control Statements
+#[derive(Debug, Clone, PartialEq)]
+pub enum ControlStmt {
+    Grant(GrantStmt),
+    Revoke(GrantStmt),
+    Admin(AdminStmt),
+}
+
+/// Statements of the administrator about the server, like `ADMIN RELOAD`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AdminStmt {
+    /// Reads the configuration again, see `config::reload`
+    Reload,
+    /// Shuts the server down once running queries finished, see `shutdown`
+    Shutdown,
+}
+
+/// Transaction Control Statements
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TransactionStmt {
+    Begin,
+    Commit,
+    Rollback,
+}
+
+/// Information for granting and revoking privileges. `database == None`
+/// refers to the current database, `table == None` to all of its tables.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GrantStmt {
+    pub privileges: Vec<Privilege>,
+    pub database: Option<String>,
+    pub table: Option<String>,
+    pub user: String,
+}
+
+/// Split between showable content
+#[derive(Debug, Clone, PartialEq)]
+pub enum ShowStmt {
+    Grants(Option<String>),
+    Processlist,
+    Status,
+    /// Statistics of all analyzed tables or of one table
+    Stats(Option<String>),
+    TableStatus,
+    /// The definition of a view
+    CreateView(String),
+    /// The databases the user may use
+    Databases,
+    /// The size and connections of the databases the user may use
+    DatabaseStatus,
+    /// The tables and views of the current database the user may read
+    Tables,
+    /// The columns of a table, view or catalog table
+    Columns(String),
+    /// The storage engines of `ENGINE = ...`
+    Engines,
+    /// The threads serving the connections, see `pool`
+    Workers,
+    /// How far this server lags behind its leader, see `replication`
+    ReplicaStatus,
+    /// The events of the current database, see `scheduler`
+    Events,
+    /// What the query of a connection is doing, holds the id of the
+    /// connection
+    Progress(u64),
+    /// The damaged tables found at startup, see `storage::recovery`
+    Recovery,
 }
 
 /// Split between creatable content (only Tables yet)
@@ -34,13 +150,19 @@ pub enum CreateStmt {
     Table(CreateTableStmt),
     View(CreateViewStmt),
     Database(String),
+    User(UserStmt),
+    Index(CreateIndexStmt),
+    Function(CreateFunctionStmt),
+    Event(CreateEventStmt),
 }
 
 /// Split between alterable content (only Tables yet)
 #[derive(Debug, Clone, PartialEq)]
 pub enum AltStmt {
     Table(AlterTableStmt), //Column(String)
-                           //View(String)
+    //View(String)
+    User(UserStmt),
+    Database(AlterDatabaseStmt),
 }
 
 /// Split between drop-able content (only Tables yet)
@@ -48,7 +170,21 @@ pub enum AltStmt {
 pub enum DropStmt {
     Table(String),
     View(String),
-    Database(String),
+    Database(DropDatabaseStmt),
+    User(String),
+    Function(String),
+    Event(String),
+}
+
+/// Information for dropping a database
+#[derive(Debug, Clone, PartialEq)]
+pub struct DropDatabaseStmt {
+    pub name: String,
+    /// A missing database is no error
+    pub if_exists: bool,
+    /// Connections using the database are killed instead of making the
+    /// statement fail
+    pub force: bool,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -56,11 +192,60 @@ pub enum UseStmt {
     Database(String),
 }
 
+/// Information for sending a notification to all connections listening on
+/// `channel`
+#[derive(Debug, Clone, PartialEq)]
+pub struct NotifyStmt {
+    pub channel: String,
+    pub payload: String,
+}
+
+/// Information for backing up `database` to the directory `path`
+#[derive(Debug, Clone, PartialEq)]
+pub struct BackupStmt {
+    pub database: String,
+    pub path: String,
+}
+
+/// Information for inserting the rows of the CSV file `path` on the server
+/// into the table `tid`
+#[derive(Debug, Clone, PartialEq)]
+pub struct CopyStmt {
+    pub tid: String,
+    pub path: String,
+}
+
 /// Information for table creation
 #[derive(Debug, Clone, PartialEq)]
 pub struct CreateTableStmt {
     pub tid: String,
     pub cols: Vec<ColumnInfo>,
+    pub engine: EngineID,
+    pub compression: Compression,
+    /// the data file is encrypted, see `storage::encrypt`
+    pub encrypted: bool,
+    /// the table is dropped when the connection ends
+    pub temporary: bool,
+    pub uniques: Vec<Vec<String>>, // columns of each UNIQUE constraint
+    pub foreign_keys: Vec<ForeignKeyInfo>,
+    pub checks: Vec<Conditions>, // table CHECK constraints
+}
+
+/// Information for a FOREIGN KEY constraint of a new table
+#[derive(Debug, Clone, PartialEq)]
+pub struct ForeignKeyInfo {
+    pub cols: Vec<String>,
+    pub ref_tid: String,
+    pub ref_cols: Vec<String>, // empty for the primary key
+    pub on_delete: ReferentialAction,
+}
+
+/// Information for the creation of a secondary index on a single column
+#[derive(Debug, Clone, PartialEq)]
+pub struct CreateIndexStmt {
+    pub name: String,
+    pub tid: String,
+    pub col: String,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -68,6 +253,39 @@ pub struct CreateViewStmt {
     pub name: String,
     pub opt: bool, // OR REPLACE keyword
     pub sel: SelectStmt,
+    /// The select as written in the query
+    pub text: String,
+}
+
+/// Information for `CREATE FUNCTION name(param type, ...) RETURNS type AS
+/// 'expression'`
+#[derive(Debug, Clone, PartialEq)]
+pub struct CreateFunctionStmt {
+    pub name: String,
+    pub opt: bool, // OR REPLACE keyword
+    pub params: Vec<(String, SqlType)>,
+    pub returns: SqlType,
+    /// The expression of the parameters the function returns, as written
+    pub body: String,
+}
+
+/// Information for `CREATE EVENT name ON SCHEDULE EVERY n unit DO
+/// statement`
+#[derive(Debug, Clone, PartialEq)]
+pub struct CreateEventStmt {
+    pub name: String,
+    pub opt: bool, // OR REPLACE keyword
+    /// Seconds between two runs
+    pub every: u64,
+    /// The statement to run, as written
+    pub body: String,
+}
+
+/// Information for user creation and password changes
+#[derive(Debug, Clone, PartialEq)]
+pub struct UserStmt {
+    pub name: String,
+    pub password: String,
 }
 
 /// Information for column creation
@@ -79,6 +297,10 @@ pub struct ColumnInfo {
     pub auto_increment: bool,
     pub not_null: bool,
     pub comment: Option<String>,
+    /// Value of the column if an insert leaves it out
+    pub default: Option<token::Lit>,
+    /// Condition of a CHECK constraint of the column
+    pub check: Option<Conditions>,
 }
 
 /// Information for table alteration
@@ -88,12 +310,23 @@ pub struct AlterTableStmt {
     pub op: AlterOp,
 }
 
+/// New limits of a database, see `storage::DatabaseLimits`. None keeps the
+/// limit as it is, 0 lifts it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlterDatabaseStmt {
+    pub name: String,
+    pub max_size: Option<u64>,
+    pub max_connections: Option<u64>,
+}
+
 /// Possible operations for table alterations
 #[derive(Debug, Clone, PartialEq)]
 pub enum AlterOp {
     Add(ColumnInfo),
     Drop(String),
     Modify(ColumnInfo),
+    /// Renames the table
+    Rename(String),
 }
 
 /// Information for table update
@@ -110,7 +343,13 @@ pub struct UpdateStmt {
 pub struct SelectStmt {
     pub target: Vec<Target>,
     pub tid: Vec<String>,
+    /// Tables joined to those of `tid` by `JOIN ... ON`, in order
+    pub joins: Vec<Join>,
     pub alias: HashMap<String, String>,
+    /// The names the tables of `tid` and then of `joins` go by, their
+    /// aliases or else their own names. A table joined to itself is told
+    /// apart by them.
+    pub names: Vec<String>,
     pub cond: Option<Conditions>,
     //pub groupby: Option<GroupBy>,
     //pub orderby: Option<OrderBy>,
@@ -119,6 +358,39 @@ pub struct SelectStmt {
     pub limit: Option<Limit>,
 }
 
+/// Selects combined by set operations, like `SELECT ... UNION SELECT ...`
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompoundStmt {
+    pub select: SetExpr,
+    /// `ORDER BY` after the last select, for the whole result
+    pub order: Vec<Sort>,
+    /// `LIMIT` after the last select, for the whole result
+    pub limit: Option<Limit>,
+}
+
+/// A select or a set operation of two of them. `INTERSECT` binds closer
+/// than `UNION` and `EXCEPT`, which are applied from left to right.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SetExpr {
+    Select(SelectStmt),
+    Op(SetOp, Box<SetExpr>, Box<SetExpr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SetOp {
+    Union,
+    UnionAll,
+    Intersect,
+    Except,
+}
+
+/// A table joined by `[INNER] JOIN table ON condition`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Join {
+    pub tid: String,
+    pub cond: Conditions,
+}
+
 /// Information for data selection
 #[derive(Debug, Clone, PartialEq)]
 pub struct Target {
@@ -134,6 +406,44 @@ pub enum Col {
     Specified(String),
     // for example: table.* => select every column in table
     Every,
+    /// COUNT(*), the number of rows selected
+    Count,
+    /// An expression computed for every row, like `price * qty`
+    Expr(token::Lit),
+    /// A window function like `RANK() OVER (ORDER BY score DESC)`
+    Window(Window),
+}
+
+/// A window function with its window: the rows of a partition, those with
+/// equal values in the columns of `PARTITION BY`, in the order of `ORDER BY`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Window {
+    pub function: WindowFunction,
+    /// The columns of `PARTITION BY`, without an order
+    pub partition: Vec<Sort>,
+    pub order: Vec<Sort>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum WindowFunction {
+    RowNumber,
+    Rank,
+    Sum(token::Lit),
+    Avg(token::Lit),
+}
+
+impl WindowFunction {
+    /// Returns the function as a call, e.g. `SUM(price)`, which names its
+    /// column.
+    pub fn call(&self) -> token::Lit {
+        let (name, args) = match self {
+            &WindowFunction::RowNumber => ("row_number", vec![]),
+            &WindowFunction::Rank => ("rank", vec![]),
+            &WindowFunction::Sum(ref lit) => ("sum", vec![lit.clone()]),
+            &WindowFunction::Avg(ref lit) => ("avg", vec![lit.clone()]),
+        };
+        token::Lit::Call(name.into(), args)
+    }
 }
 
 /// Information for data output limiting
@@ -150,7 +460,33 @@ pub struct Limit {
 pub struct InsertStmt {
     pub tid: String,
     pub col: Vec<String>,
-    pub val: Vec<token::Lit>,
+    /// Values of the rows to insert, one vector for every row
+    pub val: Vec<Vec<token::Lit>>,
+    /// What happens to rows whose primary or unique key exists already,
+    /// None fails the statement
+    pub on_duplicate: Option<OnDuplicate>,
+}
+
+/// Handling of a row to insert whose key collides with an existing row
+#[derive(Debug, Clone, PartialEq)]
+pub enum OnDuplicate {
+    /// `INSERT IGNORE`, the row is skipped
+    Ignore,
+    /// `ON DUPLICATE KEY UPDATE`, the existing row is updated like by
+    /// `UPDATE ... SET`. `VALUES(col)` stands for a value of the row to insert.
+    Update(Vec<Condition>),
+}
+
+/// Information for inserting the rows of a select
+#[derive(Debug, Clone, PartialEq)]
+pub struct InsertSelectStmt {
+    pub tid: String,
+    /// Columns the values of the select are given for, all if empty
+    pub col: Vec<String>,
+    /// The select, a single one has no set operations
+    pub select: CompoundStmt,
+    /// Only `INSERT IGNORE`, `ON DUPLICATE KEY UPDATE` is not supported here
+    pub on_duplicate: Option<OnDuplicate>,
 }
 
 /// Information for data deletion
@@ -159,6 +495,8 @@ pub struct DeleteStmt {
     pub tid: String,
     pub alias: HashMap<String, String>,
     pub cond: Option<Conditions>,
+    /// Targets of `RETURNING`, the deleted rows are returned if not empty
+    pub returning: Vec<Target>,
 }
 
 /// Additional operations for ordering and limiting
@@ -171,15 +509,127 @@ pub enum SpecOps {
 }
 
 /// Conditions for managing AND/OR where-clauses
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Conditions {
     Leaf(Condition),
     And(Box<Conditions>, Box<Conditions>),
     Or(Box<Conditions>, Box<Conditions>),
+    /// A comparison of expressions, like `LENGTH(name) > 3`
+    Expr(token::Lit, CompType, token::Lit),
+    /// `lhs IN (values)`, or `NOT IN` if the flag is set. `IN (SELECT ...)`
+    /// has the subquery as its only value.
+    In(token::Lit, Vec<token::Lit>, bool),
+    /// `EXISTS (SELECT ...)`, or `NOT EXISTS` if the flag is set. Like
+    /// `Lit::Subquery` it is never stored.
+    #[serde(skip)]
+    Exists(Box<SelectStmt>, bool),
+}
+
+/// Most conjuncts `Conditions::conjuncts` distributes an OR into
+const MAX_CONJUNCTS: usize = 64;
+
+impl Conditions {
+    /// Returns the negated conditions. The NOT is pushed down to the
+    /// comparisons by De Morgan's laws, which hold for the unknown results
+    /// of null values, too.
+    pub fn negate(self) -> Conditions {
+        match self {
+            Conditions::Leaf(mut c) => {
+                c.op = c.op.negate();
+                Conditions::Leaf(c)
+            }
+            Conditions::And(a, b) => {
+                Conditions::Or(Box::new((*a).negate()), Box::new((*b).negate()))
+            }
+            Conditions::Or(a, b) => {
+                Conditions::And(Box::new((*a).negate()), Box::new((*b).negate()))
+            }
+            Conditions::Expr(lhs, op, rhs) => Conditions::Expr(lhs, op.negate(), rhs),
+            Conditions::In(lhs, values, negated) => Conditions::In(lhs, values, !negated),
+            Conditions::Exists(stmt, negated) => Conditions::Exists(stmt, !negated),
+        }
+    }
+
+    /// Returns the conditions in conjunctive normal form: conditions that
+    /// must all hold, none of them an AND. An OR of ANDs is distributed over
+    /// them unless that makes more than `MAX_CONJUNCTS` conjuncts, then it
+    /// stays a single one.
+    pub fn conjuncts(&self) -> Vec<Conditions> {
+        match self {
+            &Conditions::And(ref a, ref b) => {
+                let mut conjuncts = a.conjuncts();
+                conjuncts.extend(b.conjuncts());
+                conjuncts
+            }
+            &Conditions::Or(ref a, ref b) => {
+                let (left, right) = (a.conjuncts(), b.conjuncts());
+                if left.len() * right.len() > MAX_CONJUNCTS {
+                    return vec![self.clone()];
+                }
+                let mut conjuncts = Vec::new();
+                for l in &left {
+                    for r in &right {
+                        conjuncts.push(Conditions::Or(Box::new(l.clone()), Box::new(r.clone())));
+                    }
+                }
+                conjuncts
+            }
+            _ => vec![self.clone()],
+        }
+    }
+
+    /// Joins conditions by AND, None if there are none.
+    pub fn all<I>(conditions: I) -> Option<Conditions>
+    where
+        I: IntoIterator<Item = Conditions>,
+    {
+        conditions.into_iter().fold(None, |all, c| {
+            Some(match all {
+                Some(all) => Conditions::And(Box::new(all), Box::new(c)),
+                None => c,
+            })
+        })
+    }
+
+    /// Returns true if a condition contains a `(SELECT ...)`.
+    pub fn has_subquery(&self) -> bool {
+        match self {
+            &Conditions::Leaf(ref c) => match c.rhs {
+                CondType::Literal(ref lit) => lit.has_subquery(),
+                CondType::Word(_) => false,
+            },
+            &Conditions::And(ref a, ref b) | &Conditions::Or(ref a, ref b) => {
+                a.has_subquery() || b.has_subquery()
+            }
+            &Conditions::Expr(ref a, _, ref b) => a.has_subquery() || b.has_subquery(),
+            &Conditions::In(ref a, ref values, _) => {
+                a.has_subquery() || values.iter().any(token::Lit::has_subquery)
+            }
+            &Conditions::Exists(..) => true,
+        }
+    }
+
+    /// Returns true if a condition compares the column
+    pub fn uses_column(&self, name: &str) -> bool {
+        match self {
+            &Conditions::Leaf(ref c) => c.col == name || c.rhs == CondType::Word(name.into()),
+            &Conditions::And(ref a, ref b) | &Conditions::Or(ref a, ref b) => {
+                a.uses_column(name) || b.uses_column(name)
+            }
+            &Conditions::Expr(ref a, _, ref b) => {
+                a.columns().contains(&name) || b.columns().contains(&name)
+            }
+            &Conditions::In(ref a, ref values, _) => {
+                a.columns().contains(&name) || values.iter().any(|v| v.columns().contains(&name))
+            }
+            // never part of a check constraint
+            &Conditions::Exists(..) => false,
+        }
+    }
 }
 
 /// Information for the where-clause
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Condition {
     pub aliascol: Option<String>,
     pub col: String,
@@ -200,7 +650,7 @@ pub struct Sort {
 }
 
 /// Allowed operators for where-clause
-#[derive(Debug, Clone, PartialEq, Copy)]
+#[derive(Debug, Clone, PartialEq, Copy, Serialize, Deserialize)]
 pub enum CompType {
     Equ,
     NEqu,
@@ -208,6 +658,19 @@ pub enum CompType {
     SThan,
     GEThan,
     SEThan,
+    /// Full text search, all words of the rhs occur in the column
+    Match,
+    NMatch,
+    /// The column is null, the rhs is `Lit::Null`
+    IsNull,
+    IsNotNull,
+    /// The column matches a pattern with the wildcards `%` and `_`, see
+    /// `storage::pattern`
+    Like,
+    NLike,
+    /// A regular expression matches a part of the column
+    Regexp,
+    NRegexp,
 }
 
 impl CompType {
@@ -219,12 +682,31 @@ impl CompType {
             &CompType::SThan => CompType::GEThan,
             &CompType::GEThan => CompType::SThan,
             &CompType::SEThan => CompType::GThan,
+            &CompType::Match => CompType::NMatch,
+            &CompType::NMatch => CompType::Match,
+            &CompType::IsNull => CompType::IsNotNull,
+            &CompType::IsNotNull => CompType::IsNull,
+            &CompType::Like => CompType::NLike,
+            &CompType::NLike => CompType::Like,
+            &CompType::Regexp => CompType::NRegexp,
+            &CompType::NRegexp => CompType::Regexp,
+        }
+    }
+
+    /// Returns whether the rhs is a search term or a pattern, which is
+    /// compared as it is rather than as a value of the column.
+    pub fn takes_text(&self) -> bool {
+        match self {
+            &CompType::Match | &CompType::NMatch => true,
+            &CompType::Like | &CompType::NLike => true,
+            &CompType::Regexp | &CompType::NRegexp => true,
+            _ => false,
         }
     }
 }
 
 /// Allowed data types for where-clause
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum CondType {
     Literal(token::Lit),
     Word(String),
@@ -235,6 +717,7 @@ pub enum DataSrc {
     Int(i64),
     String(String),
     Bool(u8),
+    Null,
 }
 
 /// Possible values for "Order By" keyword
@@ -249,11 +732,13 @@ impl DataSrc {
     /// return is true for Int when Value no null
     /// return is true for String when String is not empty
     /// return is true for Bool when Bool is not null
+    /// return is false for Null
     pub fn is_true(&self) -> bool {
         match self {
             &DataSrc::Int(x) => x == 0,
             &DataSrc::String(ref x) => !x.is_empty(),
             &DataSrc::Bool(x) => x != 0,
+            &DataSrc::Null => false,
         }
     }
     /// static method to turn u8 into bool
@@ -1,3 +1,4 @@
+use super::super::storage::decimal;
 use super::token::{Lit, Token, TokenSpan};
 use super::Span;
 use std::iter::Iterator;
@@ -5,6 +6,7 @@ use std::str::Chars;
 
 /// A lexer with its associated query, a char iterator, and
 /// positions (last, current, next)
+#[derive(Clone)]
 pub struct Lexer<'a> {
     chs: Chars<'a>,
     last: Option<char>,
@@ -41,16 +43,11 @@ impl<'a> Lexer<'a> {
         // Advance last_pos to position of current char
         self.last_pos = self.curr_pos;
 
-        // Next position is current position plus the utf8 length
-        // of the next character
-        match self.next {
-            Some(c) => {
-                self.curr_pos = match self.curr_pos {
-                    Some(n) => Some(n + c.len_utf8()),
-                    None => Some(c.len_utf8()), // Start at pos 0
-                }
-            }
-            _ => {}
+        // The new current char starts where the last one ended. At the end of
+        // the query, curr_pos is the length of the query.
+        self.curr_pos = match (self.curr_pos, self.last) {
+            (Some(n), Some(c)) => Some(n + c.len_utf8()),
+            _ => Some(0), // Start at pos 0
         };
     }
 
@@ -96,19 +93,38 @@ impl<'a> Lexer<'a> {
         s
     }
 
+    /// Scan each new string LITERAL, a doubled quotation mark stands for
+    /// itself and a backslash starts an escape sequence, see `escape`
     fn scan_lit(&mut self) -> Result<String, LexError> {
+        let open = self.char_span();
         let mut s = String::new();
         self.bump(); // To first char of literal
         loop {
-            if self.curr.is_some() {
-                match self.curr.unwrap_or(' ') {
-                    c @ '\'' | c @ '"' => break,
-                    c @ _ => {
-                        s.push(c);
+            match self.curr {
+                None => return Err(LexError::UnclosedQuotationmark(open)),
+                Some('\'') if self.next == Some('\'') => {
+                    s.push('\'');
+                    self.bump();
+                }
+                Some('\'') => break,
+                Some('\\') => {
+                    let lo = self.char_span().lo;
+                    self.bump();
+                    let c = match self.curr {
+                        Some(c) => c,
+                        None => return Err(LexError::UnclosedQuotationmark(open)),
+                    };
+                    match escape(c) {
+                        Some(text) => s.push_str(text),
+                        None => {
+                            return Err(LexError::UnknownEscape(Span {
+                                lo: lo,
+                                hi: self.char_span().hi,
+                            }))
+                        }
                     }
                 }
-            } else {
-                return Err(LexError::UnclosedQuotationmark);
+                Some(c) => s.push(c),
             }
             self.bump();
         }
@@ -116,6 +132,71 @@ impl<'a> Lexer<'a> {
         Ok(s)
     }
 
+    /// Scan each new quoted NAME, in double quotes or backticks, which may
+    /// contain spaces or be a keyword. A doubled quotation mark stands for
+    /// itself, there are no escape sequences.
+    fn scan_quoted_word(&mut self) -> Result<String, LexError> {
+        let open = self.char_span();
+        let quote = self.curr;
+        let mut s = String::new();
+        self.bump(); // To first char of the name
+        loop {
+            match self.curr {
+                None => return Err(LexError::UnclosedQuotationmark(open)),
+                c if c == quote && self.next == quote => {
+                    s.push(quote.unwrap());
+                    self.bump();
+                }
+                c if c == quote => break,
+                // names become file names, see `Table::get_path`
+                Some(c) if c.is_control() || c == '.' || c == '/' || c == '\\' => {
+                    return Err(LexError::InvalidNameChar(self.char_span()))
+                }
+                Some(c) => s.push(c),
+            }
+            self.bump();
+        }
+        if s.is_empty() {
+            return Err(LexError::EmptyName(Span {
+                lo: open.lo,
+                hi: self.char_span().hi,
+            }));
+        }
+        self.bump();
+        Ok(s)
+    }
+
+    /// Skips a comment from -- to the end of the line
+    fn skip_line_comment(&mut self) {
+        while self.curr.is_some() && self.curr != Some('\n') {
+            self.bump();
+        }
+    }
+
+    /// Skips a comment from /* to */, which do not nest
+    fn skip_block_comment(&mut self) -> Result<(), LexError> {
+        let lo = self.char_span().lo;
+        self.dbump();
+        loop {
+            match (self.curr, self.next) {
+                (Some('*'), Some('/')) => break,
+                (Some(_), _) => self.bump(),
+                (None, _) => return Err(LexError::UnclosedComment(Span { lo: lo, hi: lo + 2 })),
+            }
+        }
+        self.dbump();
+        Ok(())
+    }
+
+    /// Returns the span of the current char
+    fn char_span(&self) -> Span {
+        let lo = self.curr_pos.unwrap_or(0);
+        Span {
+            lo: lo,
+            hi: lo + self.curr.map_or(0, char::len_utf8),
+        }
+    }
+
     /// Skips all the whitespaces
     fn skip_whitespace(&mut self) {
         while is_whitespace(self.curr.unwrap_or('x')) {
@@ -123,25 +204,40 @@ impl<'a> Lexer<'a> {
         }
     }
 
-    /// Returns next token that is not a whitespace
+    /// Returns next token that is not a whitespace or a comment
     pub fn next_real(&mut self) -> Result<Option<TokenSpan>, LexError> {
-        let tokspanop = try!(self.next());
-        let wspace = match tokspanop {
-            Some(ref tokspan) => match tokspan.tok {
-                Token::Whitespace => true,
-                _ => false,
-            },
-            _ => false,
-        };
-
-        if wspace {
-            self.next()
-        } else {
-            Ok(tokspanop)
+        loop {
+            match try!(self.next()) {
+                Some(TokenSpan {
+                    tok: Token::Whitespace,
+                    ..
+                }) => continue,
+                tokspanop => return Ok(tokspanop),
+            }
         }
     }
 }
 
+/// Returns the text an escape sequence in a string literal stands for,
+/// given by the char after the backslash. `\%` and `\_` keep their
+/// backslash, so that they still escape the wildcards of LIKE.
+fn escape(c: char) -> Option<&'static str> {
+    match c {
+        '0' => Some("\0"),
+        'b' => Some("\x08"),
+        'n' => Some("\n"),
+        'r' => Some("\r"),
+        't' => Some("\t"),
+        'Z' => Some("\x1a"),
+        '\\' => Some("\\"),
+        '\'' => Some("'"),
+        '"' => Some("\""),
+        '%' => Some("\\%"),
+        '_' => Some("\\_"),
+        _ => None,
+    }
+}
+
 /// Checks for whitespace/line break/tab
 fn is_whitespace(c: char) -> bool {
     match c {
@@ -180,6 +276,9 @@ impl<'a> Lexer<'a> {
                 let n = self.scan_nums();
                 if let Ok(i) = n.parse::<i64>() {
                     Token::Literal(Lit::Int(i))
+                } else if let Some((d, scale)) = decimal::parse(&n) {
+                    // numbers with a point are exact, unless too long
+                    Token::Literal(Lit::Decimal(d, scale))
                 } else {
                     if let Ok(f) = n.parse::<f64>() {
                         Token::Literal(Lit::Float(f))
@@ -189,6 +288,16 @@ impl<'a> Lexer<'a> {
                 }
             }
 
+            // Comments count as whitespace
+            '-' if nexchar == '-' => {
+                self.skip_line_comment();
+                Token::Whitespace
+            }
+            '/' if nexchar == '*' => {
+                try!(self.skip_block_comment());
+                Token::Whitespace
+            }
+
             // Semicolon
             ';' => {
                 self.bump();
@@ -232,11 +341,17 @@ impl<'a> Lexer<'a> {
             }
 
             // Literals
-            '\'' | '"' => {
+            '\'' => {
                 let l = try!(self.scan_lit());
                 Token::Literal(Lit::String(l))
             }
 
+            // Quoted names
+            '"' | '`' => {
+                let w = try!(self.scan_quoted_word());
+                Token::QuotedWord(w)
+            }
+
             // Equ
             '=' => {
                 self.bump();
@@ -291,6 +406,12 @@ impl<'a> Lexer<'a> {
                 Token::Div
             }
 
+            // Concat ||
+            '|' if nexchar == '|' => {
+                self.dbump();
+                Token::Concat
+            }
+
             // Mod
             '%' => {
                 self.bump();
@@ -329,5 +450,28 @@ impl<'a> Lexer<'a> {
 
 #[derive(PartialEq, Debug)]
 pub enum LexError {
-    UnclosedQuotationmark,
+    /// A string or quoted name without its closing quotation mark, the span
+    /// is the opening one
+    UnclosedQuotationmark(Span),
+    /// A backslash followed by a char that starts no escape sequence
+    UnknownEscape(Span),
+    /// A quoted name with nothing between the quotation marks
+    EmptyName(Span),
+    /// A char that cannot be part of a quoted name
+    InvalidNameChar(Span),
+    /// A comment without its closing */, the span is the opening /*
+    UnclosedComment(Span),
+}
+
+impl LexError {
+    /// Returns the part of the query that caused the error.
+    pub fn span(&self) -> &Span {
+        match self {
+            &LexError::UnclosedQuotationmark(ref s)
+            | &LexError::UnknownEscape(ref s)
+            | &LexError::EmptyName(ref s)
+            | &LexError::InvalidNameChar(ref s)
+            | &LexError::UnclosedComment(ref s) => s,
+        }
+    }
 }
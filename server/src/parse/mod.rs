@@ -12,14 +12,76 @@ pub mod token;
 pub use self::parser::Parser;
 
 /// Represents a substring in the query string in byte indices.
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Span {
     pub lo: usize,
     pub hi: usize,
 }
 
+impl Span {
+    /// Returns the 1-based line and column of the start of the span.
+    pub fn line_column(&self, query: &str) -> (u32, u32) {
+        let mut line = 1;
+        let mut column = 1;
+        for (i, c) in query.char_indices() {
+            if i >= self.lo {
+                break;
+            }
+            if c == '\n' {
+                line += 1;
+                column = 1;
+            } else {
+                column += 1;
+            }
+        }
+        (line, column)
+    }
+}
+
 /// Main function of this module: Takes a sql query as string and returns
 /// the parsed AST.
 pub fn parse(query: &str) -> Result<ast::Query, parser::ParseError> {
     Parser::create(query).parse()
 }
+
+/// Parses a script of statements separated by semicolons, reporting the
+/// syntax errors of all statements, see `Parser::parse_statements`.
+pub fn parse_statements(query: &str) -> Result<Vec<ast::Query>, parser::ParseError> {
+    Parser::create(query).recovering().parse_statements()
+}
+
+/// Splits a script of statements at the semicolons ending them and returns
+/// the text of every statement that is not empty, the statements
+/// `parse_statements` returns the ASTs of. The whole query is returned if
+/// it holds a token the lexer does not know.
+pub fn split_statements(query: &str) -> Vec<&str> {
+    let mut lexer = lex::Lexer::from_query(query);
+    let mut statements = Vec::new();
+    // span of the tokens of the statement so far
+    let mut tokens: Option<Span> = None;
+    loop {
+        match lexer.next_real() {
+            Ok(Some(token::TokenSpan {
+                tok: token::Token::Semi,
+                ..
+            })) => {
+                if let Some(span) = tokens.take() {
+                    statements.push(&query[span.lo..span.hi]);
+                }
+            }
+            Ok(Some(token)) => {
+                let lo = tokens.as_ref().map_or(token.span.lo, |span| span.lo);
+                tokens = Some(Span {
+                    lo: lo,
+                    hi: token.span.hi,
+                });
+            }
+            Ok(None) => break,
+            Err(_) => return vec![query],
+        }
+    }
+    if let Some(span) = tokens {
+        statements.push(&query[span.lo..span.hi]);
+    }
+    statements
+}
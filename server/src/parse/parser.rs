@@ -1,4 +1,7 @@
-use super::super::storage::SqlType;
+use super::super::storage::pattern as storage_pattern;
+use super::super::storage::{
+    decimal, temporal, Compression, EngineID, Privilege, ReferentialAction, SqlType,
+};
 use super::ast::*;
 use super::lex;
 use super::lex::Lexer;
@@ -6,6 +9,7 @@ use super::token::Token;
 use super::token::{Lit, TokenSpan};
 use super::Span;
 use std::collections::HashMap;
+use std::fmt;
 ///  Program for testing and playing with the parser
 ///
 use std::iter::Iterator;
@@ -15,15 +19,28 @@ use std::mem::swap;
 // Parser public functions
 // ===========================================================================
 
-// the parser needs a Lexer that iterates through the query
+// the parser needs a Lexer that iterates through the query, it is cloned to
+// go back to an earlier token
+#[derive(Clone)]
 pub struct Parser<'a> {
     lexiter: Lexer<'a>,
+    query: &'a str,
 
     last: Option<TokenSpan>,
     // the current token given by the lexer
     curr: Option<TokenSpan>,
     // next token
     peek: Option<TokenSpan>,
+    // whether operands may be columns of the row, see expect_expression
+    columns: bool,
+    // the semicolon ending the statement once the lexer reached it, the
+    // tokens after it are not read before the next statement starts
+    semicolon: Option<Span>,
+    // the end of the last token that was the current one
+    end: usize,
+    // whether a statement with a syntax error is skipped to parse the ones
+    // after it, see recovering
+    recover: bool,
 }
 
 impl<'a> Parser<'a> {
@@ -32,9 +49,14 @@ impl<'a> Parser<'a> {
         let l = Lexer::from_query(query);
         let mut p = Parser {
             lexiter: l,
+            query: query,
             last: None,
             curr: None,
             peek: None,
+            columns: false,
+            semicolon: None,
+            end: 0,
+            recover: false,
         };
         // Sets initial position of lexer and curr/peek
         p.bump();
@@ -42,8 +64,97 @@ impl<'a> Parser<'a> {
         p
     }
 
-    /// Parses the given query into an AST
+    /// Parses the given query into an AST, the query must be a single
+    /// statement
     pub fn parse(&mut self) -> Result<Query, ParseError> {
+        let mut queries = try!(self.parse_statements());
+        if queries.len() > 1 {
+            return Err(ParseError::InvalidEoq);
+        }
+        Ok(queries.remove(0))
+    }
+
+    /// Parses the query as a single expression, which may refer to columns,
+    /// like the body of a function.
+    pub fn parse_expression(&mut self) -> Result<Lit, ParseError> {
+        let lit = try!(self.expect_expression(None));
+        try!(self.bump());
+        if self.curr.is_some() || self.semicolon.is_some() {
+            return Err(ParseError::InvalidEoq);
+        }
+        Ok(lit)
+    }
+
+    /// Returns the parser in error-recovery mode: `parse_statements` skips
+    /// a statement with a syntax error up to the semicolon ending it and
+    /// goes on with the next one, to report the errors of all statements.
+    pub fn recovering(mut self) -> Parser<'a> {
+        self.recover = true;
+        self
+    }
+
+    /// Parses a script of statements separated by semicolons into their
+    /// ASTs, in the order of the script. Empty statements are skipped. In
+    /// error-recovery mode, syntax errors in more than one statement are
+    /// returned as `ParseError::Several`.
+    pub fn parse_statements(&mut self) -> Result<Vec<Query>, ParseError> {
+        let mut queries = Vec::new();
+        let mut errors = Vec::new();
+        loop {
+            if self.curr.is_some() {
+                match self.parse_statement() {
+                    Ok(query) => queries.push(query),
+                    Err(error) => {
+                        errors.push(self.statement_error(error));
+                        if !self.recover {
+                            break;
+                        }
+                        self.synchronize();
+                    }
+                }
+            }
+            if self.semicolon.is_none() {
+                break;
+            }
+            // starts over behind the semicolon
+            self.semicolon = None;
+            self.curr = None;
+            self.peek = None;
+            if let Err(error) = self.bump().and_then(|_| self.bump()) {
+                errors.push(error);
+                if !self.recover {
+                    break;
+                }
+                self.synchronize();
+            }
+        }
+        match errors.len() {
+            0 if queries.is_empty() => Err(ParseError::EmptyQueryError),
+            0 => Ok(queries),
+            1 => Err(errors.remove(0)),
+            _ => Err(ParseError::Several(errors)),
+        }
+    }
+
+    // returns the error of a statement, one ending too early at a semicolon
+    // is shown there
+    fn statement_error(&self, error: ParseError) -> ParseError {
+        match (error, &self.semicolon) {
+            (ParseError::UnexpectedEoq, &Some(ref span)) => ParseError::WrongToken(span.clone()),
+            (error, _) => error,
+        }
+    }
+
+    // skips the rest of a statement with a syntax error, errors of the lexer
+    // in it are not reported
+    fn synchronize(&mut self) {
+        while self.curr.is_some() || self.peek.is_some() {
+            let _ = self.bump();
+        }
+    }
+
+    // parses a statement, which ends like the query at a semicolon
+    fn parse_statement(&mut self) -> Result<Query, ParseError> {
         // deletes Whitespaces in the beginning of Query
 
         // first token is checked if it's a keyword using expect_keyword()
@@ -51,13 +162,44 @@ impl<'a> Parser<'a> {
             Keyword::Create,
             Keyword::Drop,
             Keyword::Alter,
+            Keyword::Truncate,
             Keyword::Use,
             Keyword::Delete,
             Keyword::Insert,
             Keyword::Describe,
+            Keyword::Explain,
             Keyword::Update,
             Keyword::Select,
+            Keyword::Grant,
+            Keyword::Revoke,
+            Keyword::Show,
+            Keyword::Notify,
+            Keyword::Vacuum,
+            Keyword::Analyze,
+            Keyword::Check,
+            Keyword::Repair,
+            Keyword::Backup,
+            Keyword::Restore,
+            Keyword::Dump,
+            Keyword::Copy,
+            Keyword::Load,
+            Keyword::Rename,
+            Keyword::Begin,
+            Keyword::Start,
+            Keyword::Commit,
+            Keyword::Rollback,
         ];
+        // ADMIN RELOAD|SHUTDOWN, admin is no keyword as it is the name of a user
+        if is_word(&self.curr, "admin") && self.peek.is_some() {
+            try!(self.bump());
+            let stmt = match self.curr {
+                Some(_) if is_word(&self.curr, "reload") => AdminStmt::Reload,
+                Some(_) if is_word(&self.curr, "shutdown") => AdminStmt::Shutdown,
+                Some(ref token) => return Err(ParseError::WrongToken(token.span.clone())),
+                None => return Err(ParseError::UnexpectedEoq),
+            };
+            return self.return_query_ast(Query::ControlStmt(ControlStmt::Admin(stmt)));
+        }
         let querytype = self.expect_keyword(keywords).map_err(|e| match e {
             ParseError::UnexpectedEoq => ParseError::EmptyQueryError,
             _ => e,
@@ -79,6 +221,29 @@ impl<'a> Parser<'a> {
                 let query = Query::DefStmt(DefStmt::Drop(try!(self.parse_drop_stmt())));
                 Ok(try!(self.return_query_ast(query)))
             }
+            // Truncate-Query
+            Keyword::Truncate => {
+                try!(self.bump());
+                // TRUNCATE TABLE is the same as TRUNCATE
+                if self.expect_keyword(&[Keyword::Table]).is_ok() {
+                    try!(self.bump());
+                }
+                let query = Query::DefStmt(DefStmt::Truncate(try!(self.expect_word(false))));
+                Ok(try!(self.return_query_ast(query)))
+            }
+            // Rename-Query
+            Keyword::Rename => {
+                try!(self.bump());
+                try!(self.expect_keyword(&[Keyword::Database]));
+                try!(self.bump());
+                let database = try!(self.expect_word(false));
+                try!(self.bump());
+                try!(self.expect_keyword(&[Keyword::To]));
+                try!(self.bump());
+                let new_name = try!(self.expect_word(false));
+                let query = Query::DefStmt(DefStmt::RenameDatabase(database, new_name));
+                Ok(try!(self.return_query_ast(query)))
+            }
             // Use-Query
             Keyword::Use => {
                 let query =
@@ -87,9 +252,7 @@ impl<'a> Parser<'a> {
             }
             // Insert-Query
             Keyword::Insert => {
-                let query = Query::ManipulationStmt(ManipulationStmt::Insert(try!(
-                    self.parse_insert_stmt()
-                )));
+                let query = Query::ManipulationStmt(try!(self.parse_insert_stmt()));
                 Ok(try!(self.return_query_ast(query)))
             }
             //Update-Query
@@ -114,14 +277,194 @@ impl<'a> Parser<'a> {
                 )));
                 Ok(try!(self.return_query_ast(query)))
             }
+            // Explain-Query
+            Keyword::Explain => {
+                try!(self.bump());
+                try!(self.expect_keyword(&[Keyword::Select]));
+                let query = Query::ManipulationStmt(ManipulationStmt::Explain(try!(
+                    self.parse_query_expr()
+                )));
+                Ok(try!(self.return_query_ast(query)))
+            }
             //Select-Query
             Keyword::Select => {
-                let query = Query::ManipulationStmt(ManipulationStmt::Select(try!(
-                    self.parse_select_stmt()
+                let query = Query::ManipulationStmt(try!(self.parse_compound_stmt()));
+                Ok(try!(self.return_query_ast(query)))
+            }
+            // Grant-Query
+            Keyword::Grant => {
+                let query = Query::ControlStmt(ControlStmt::Grant(try!(
+                    self.parse_grant_stmt(Keyword::To)
+                )));
+                Ok(try!(self.return_query_ast(query)))
+            }
+            // Revoke-Query
+            Keyword::Revoke => {
+                let query = Query::ControlStmt(ControlStmt::Revoke(try!(
+                    self.parse_grant_stmt(Keyword::From)
+                )));
+                Ok(try!(self.return_query_ast(query)))
+            }
+            // Show-Query
+            Keyword::Show => {
+                let query =
+                    Query::ManipulationStmt(ManipulationStmt::Show(try!(self.parse_show_stmt())));
+                Ok(try!(self.return_query_ast(query)))
+            }
+            // Vacuum-Query, without a table all tables are compacted
+            Keyword::Vacuum => {
+                let table = if self.peek.is_some() {
+                    try!(self.bump());
+                    Some(try!(self.expect_word(false)))
+                } else {
+                    None
+                };
+                let query = Query::ManipulationStmt(ManipulationStmt::Vacuum(table));
+                Ok(try!(self.return_query_ast(query)))
+            }
+            // Analyze-Query: ANALYZE [TABLE <name>], without a table all
+            // tables are analyzed
+            Keyword::Analyze => {
+                let table = if self.peek.is_some() {
+                    try!(self.bump());
+                    try!(self.expect_keyword(&[Keyword::Table]));
+                    try!(self.bump());
+                    Some(try!(self.expect_word(false)))
+                } else {
+                    None
+                };
+                let query = Query::ManipulationStmt(ManipulationStmt::Analyze(table));
+                Ok(try!(self.return_query_ast(query)))
+            }
+            // Check-Query: CHECK TABLE
+            Keyword::Check => {
+                try!(self.bump());
+                try!(self.expect_keyword(&[Keyword::Table]));
+                try!(self.bump());
+                let table = try!(self.expect_word(false));
+                let query = Query::ManipulationStmt(ManipulationStmt::CheckTable(table));
+                Ok(try!(self.return_query_ast(query)))
+            }
+            // Repair-Query: REPAIR TABLE
+            Keyword::Repair => {
+                try!(self.bump());
+                try!(self.expect_keyword(&[Keyword::Table]));
+                try!(self.bump());
+                let table = try!(self.expect_word(false));
+                let query = Query::ManipulationStmt(ManipulationStmt::RepairTable(table));
+                Ok(try!(self.return_query_ast(query)))
+            }
+            // Backup-Query: BACKUP DATABASE <name> TO '<path>'
+            Keyword::Backup => {
+                try!(self.bump());
+                try!(self.expect_keyword(&[Keyword::Database]));
+                try!(self.bump());
+                let database = try!(self.expect_word(false));
+                try!(self.bump());
+                try!(self.expect_keyword(&[Keyword::To]));
+                try!(self.bump());
+                let query = Query::ManipulationStmt(ManipulationStmt::Backup(BackupStmt {
+                    database: database,
+                    path: try!(self.expect_path()),
+                }));
+                Ok(try!(self.return_query_ast(query)))
+            }
+            // Restore-Query: RESTORE DATABASE FROM '<path>'
+            Keyword::Restore => {
+                try!(self.bump());
+                try!(self.expect_keyword(&[Keyword::Database]));
+                try!(self.bump());
+                try!(self.expect_keyword(&[Keyword::From]));
+                try!(self.bump());
+                let query =
+                    Query::ManipulationStmt(ManipulationStmt::Restore(try!(self.expect_path())));
+                Ok(try!(self.return_query_ast(query)))
+            }
+            // Dump-Query: DUMP DATABASE <name>
+            Keyword::Dump => {
+                try!(self.bump());
+                try!(self.expect_keyword(&[Keyword::Database]));
+                try!(self.bump());
+                let database = try!(self.expect_word(false));
+                let query = Query::ManipulationStmt(ManipulationStmt::Dump(database));
+                Ok(try!(self.return_query_ast(query)))
+            }
+            // Copy-Query: COPY <table> FROM '<path>'
+            Keyword::Copy => {
+                try!(self.bump());
+                let tid = try!(self.expect_word(false));
+                try!(self.bump());
+                try!(self.expect_keyword(&[Keyword::From]));
+                try!(self.bump());
+                let query = Query::ManipulationStmt(ManipulationStmt::Copy(CopyStmt {
+                    tid: tid,
+                    path: try!(self.expect_path()),
+                }));
+                Ok(try!(self.return_query_ast(query)))
+            }
+            // Load-Query: LOAD DATA INFILE '<path>' INTO TABLE <table>, the
+            // MySQL spelling of COPY
+            Keyword::Load => {
+                for word in &["data", "infile"] {
+                    try!(self.bump());
+                    if try!(self.expect_word(false)).to_lowercase() != *word {
+                        return Err(ParseError::DebugError(format!(
+                            "expected {}",
+                            word.to_uppercase()
+                        )));
+                    }
+                }
+                try!(self.bump());
+                let path = try!(self.expect_path());
+                try!(self.bump());
+                try!(self.expect_keyword(&[Keyword::Into]));
+                try!(self.bump());
+                try!(self.expect_keyword(&[Keyword::Table]));
+                try!(self.bump());
+                let query = Query::ManipulationStmt(ManipulationStmt::Copy(CopyStmt {
+                    tid: try!(self.expect_word(false)),
+                    path: path,
+                }));
+                Ok(try!(self.return_query_ast(query)))
+            }
+            // Notify-Query
+            Keyword::Notify => {
+                let query = Query::ManipulationStmt(ManipulationStmt::Notify(try!(
+                    self.parse_notify_stmt()
                 )));
                 Ok(try!(self.return_query_ast(query)))
             }
 
+            // Transaction-Query: BEGIN [TRANSACTION | WORK] or START TRANSACTION
+            Keyword::Begin => {
+                if self.check_next_keyword(&[Keyword::Transaction, Keyword::Work]) {
+                    try!(self.bump());
+                }
+                let query = Query::TransactionStmt(TransactionStmt::Begin);
+                Ok(try!(self.return_query_ast(query)))
+            }
+            Keyword::Start => {
+                try!(self.bump());
+                try!(self.expect_keyword(&[Keyword::Transaction]));
+                let query = Query::TransactionStmt(TransactionStmt::Begin);
+                Ok(try!(self.return_query_ast(query)))
+            }
+            // COMMIT [WORK] and ROLLBACK [WORK]
+            Keyword::Commit => {
+                if self.check_next_keyword(&[Keyword::Work]) {
+                    try!(self.bump());
+                }
+                let query = Query::TransactionStmt(TransactionStmt::Commit);
+                Ok(try!(self.return_query_ast(query)))
+            }
+            Keyword::Rollback => {
+                if self.check_next_keyword(&[Keyword::Work]) {
+                    try!(self.bump());
+                }
+                let query = Query::TransactionStmt(TransactionStmt::Rollback);
+                Ok(try!(self.return_query_ast(query)))
+            }
+
             // Unknown Error
             _ => Err(ParseError::UnknownError),
         }
@@ -144,14 +487,34 @@ impl<'a> Parser<'a> {
             view_check = true;
         }
 
+        // CREATE TEMPORARY TABLE
+        if self.expect_keyword(&[Keyword::Temporary]).is_ok() {
+            try!(self.bump());
+            try!(self.expect_keyword(&[Keyword::Table]));
+            return Ok(CreateStmt::Table(try!(self.parse_create_table_stmt(true))));
+        }
+
+        // CREATE FUNCTION, function is no keyword
+        if is_word(&self.curr, "function") {
+            let stmt = try!(self.parse_create_function_stmt(view_check));
+            return Ok(CreateStmt::Function(stmt));
+        }
+        // CREATE EVENT, event is no keyword either
+        if is_word(&self.curr, "event") {
+            let stmt = try!(self.parse_create_event_stmt(view_check));
+            return Ok(CreateStmt::Event(stmt));
+        }
+
         match try!(self.expect_keyword(&[
             Keyword::Table,
             Keyword::Database,
             Keyword::View,
+            Keyword::User,
+            Keyword::Index,
             Keyword::Or
         ])) {
             // Create the table subtree
-            Keyword::Table => Ok(CreateStmt::Table(try!(self.parse_create_table_stmt()))),
+            Keyword::Table => Ok(CreateStmt::Table(try!(self.parse_create_table_stmt(false)))),
             // Create Database subtree
             Keyword::Database => {
                 try!(self.bump());
@@ -165,18 +528,131 @@ impl<'a> Parser<'a> {
                 try!(self.expect_keyword(&[Keyword::As]));
                 try!(self.bump());
                 try!(self.expect_keyword(&[Keyword::Select]));
+                // the select is the rest of the statement
+                let start = self.curr.as_ref().unwrap().span.lo;
+                let sel = try!(self.parse_select_stmt());
                 Ok(CreateStmt::View(CreateViewStmt {
                     name: name,
                     opt: view_check,
-                    sel: try!(self.parse_select_stmt()),
+                    sel: sel,
+                    text: self.query[start..self.end].trim().to_string(),
                 }))
             }
+            // Create User subtree
+            Keyword::User => Ok(CreateStmt::User(try!(self.parse_user_stmt()))),
+            // Create Index subtree
+            Keyword::Index => Ok(CreateStmt::Index(try!(self.parse_create_index_stmt()))),
             _ => Err(ParseError::UnknownError),
         }
     }
 
-    // Parses the tokens fore the create table subtree
-    fn parse_create_table_stmt(&mut self) -> Result<CreateTableStmt, ParseError> {
+    // Parses the tokens for the create function subtree:
+    // <name> (<param> <type>, ...) RETURNS <type> AS '<expression>'
+    fn parse_create_function_stmt(
+        &mut self,
+        replace: bool,
+    ) -> Result<CreateFunctionStmt, ParseError> {
+        try!(self.bump());
+        // calls are lowercased like the names of built-in functions
+        let name = try!(self.expect_word(false)).to_lowercase();
+        try!(self.bump());
+        try!(self.expect_token(&[Token::ParenOp]));
+        let mut params = Vec::new();
+        if !self.check_next_token(&[Token::ParenCl]) {
+            loop {
+                try!(self.bump());
+                let param = try!(self.expect_word(false));
+                try!(self.bump());
+                params.push((param, try!(self.expect_datatype())));
+                if !self.check_next_token(&[Token::Comma]) {
+                    break;
+                }
+                try!(self.bump());
+            }
+        }
+        try!(self.bump());
+        try!(self.expect_token(&[Token::ParenCl]).map_err(|e| match e {
+            ParseError::WrongToken(span) => ParseError::MissingParenthesis(span),
+            _ => e,
+        }));
+        try!(self.bump());
+        // returns is no keyword either
+        try!(self.expect_plain_word("returns"));
+        try!(self.bump());
+        let returns = try!(self.expect_datatype());
+        try!(self.bump());
+        try!(self.expect_keyword(&[Keyword::As]));
+        try!(self.bump());
+        let body = match try!(self.expect_literal()) {
+            Lit::String(s) => s,
+            _ => return Err(ParseError::BodyIsNoString),
+        };
+        Ok(CreateFunctionStmt {
+            name: name,
+            opt: replace,
+            params: params,
+            returns: returns,
+            body: body,
+        })
+    }
+
+    // Parses the tokens for the create event subtree:
+    // <name> ON SCHEDULE EVERY <n> <unit> DO <statement>
+    fn parse_create_event_stmt(&mut self, replace: bool) -> Result<CreateEventStmt, ParseError> {
+        try!(self.bump());
+        let name = try!(self.expect_word(false));
+        try!(self.bump());
+        try!(self.expect_keyword(&[Keyword::On]));
+        try!(self.bump());
+        try!(self.expect_plain_word("schedule"));
+        try!(self.bump());
+        try!(self.expect_plain_word("every"));
+        try!(self.bump());
+        let every = try!(self.expect_interval());
+        try!(self.bump());
+        try!(self.expect_plain_word("do"));
+        try!(self.bump());
+        // the statement is the rest of the query, it ends with it
+        let start = match self.curr {
+            Some(ref token) => token.span.lo,
+            None => return Err(ParseError::UnexpectedEoq),
+        };
+        try!(self.parse_statement());
+        Ok(CreateEventStmt {
+            name: name,
+            opt: replace,
+            every: every,
+            body: self.query[start..self.end].trim().to_string(),
+        })
+    }
+
+    // Parses the tokens for the create index subtree: <name> ON <table> (<column>)
+    fn parse_create_index_stmt(&mut self) -> Result<CreateIndexStmt, ParseError> {
+        try!(self.bump());
+        let name = try!(self.expect_word(false));
+        try!(self.bump());
+        try!(self.expect_keyword(&[Keyword::On]));
+        try!(self.bump());
+        let tid = try!(self.expect_word(false));
+        try!(self.bump());
+        try!(self.expect_token(&[Token::ParenOp]));
+        try!(self.bump());
+        let col = try!(self.expect_word(true));
+        try!(self.bump());
+        try!(self.expect_token(&[Token::ParenCl]).map_err(|e| match e {
+            ParseError::WrongToken(span) => ParseError::MissingParenthesis(span),
+            _ => e,
+        }));
+        Ok(CreateIndexStmt {
+            name: name,
+            tid: tid,
+            col: col,
+        })
+    }
+
+    // Parses the tokens fore the create table subtree, temporary tables use
+    // the memory engine unless another one is given
+    fn parse_create_table_stmt(&mut self, temporary: bool) -> Result<CreateTableStmt, ParseError> {
         // Convention: Every method must use bump to
         // put the lexer to the position of the token the method needs
         try!(self.bump());
@@ -185,6 +661,17 @@ impl<'a> Parser<'a> {
         let mut table_info = CreateTableStmt {
             tid: try!(self.expect_word(false)),
             cols: Vec::<ColumnInfo>::new(),
+            engine: if temporary {
+                EngineID::Memory
+            } else {
+                EngineID::FlatFile
+            },
+            compression: Compression::None,
+            encrypted: false,
+            temporary: temporary,
+            uniques: Vec::new(),
+            foreign_keys: Vec::new(),
+            checks: Vec::new(),
         };
         try!(self.bump());
         // if there is a ParenOp token.....
@@ -193,21 +680,50 @@ impl<'a> Parser<'a> {
         }
         try!(self.expect_token(&[Token::ParenOp]));
         // ...call parse_create_column_vec to generate the column vector subtree
-        table_info.cols = try!(self.parse_create_column_vec());
+        try!(self.parse_create_column_vec(&mut table_info));
+        // optional table options in any order: ENGINE [=] <name>,
+        // COMPRESSION [=] <name> and ENCRYPTION [=] 'Y' | 'N'
+        let options = [Keyword::Engine, Keyword::Compression, Keyword::Encryption];
+        while self.check_next_keyword(&options) {
+            try!(self.bump());
+            let option = try!(self.expect_keyword(&options));
+            try!(self.bump());
+            if self.expect_token(&[Token::Equ]).is_ok() {
+                try!(self.bump());
+            }
+            match option {
+                Keyword::Engine => table_info.engine = try!(self.expect_engine()),
+                Keyword::Compression => table_info.compression = try!(self.expect_compression()),
+                _ => table_info.encrypted = try!(self.expect_encryption()),
+            }
+        }
         Ok(table_info)
     }
 
-    // Parses the tokens for the column vector subtree
-    fn parse_create_column_vec(&mut self) -> Result<Vec<ColumnInfo>, ParseError> {
+    // Parses the tokens for the column vector subtree and the UNIQUE
+    // constraints between the columns
+    fn parse_create_column_vec(
+        &mut self,
+        table_info: &mut CreateTableStmt,
+    ) -> Result<(), ParseError> {
         // Convention: Every method must use bump to
         // put the lexer to the position of the token the method needs
         try!(self.bump());
-        let mut colsvec = Vec::<ColumnInfo>::new();
 
         // fill the vector with content until ParenCl is the curr token
         while !self.expect_token(&[Token::ParenCl]).is_ok() {
-            // parsing the content for a single ColumnInfo
-            colsvec.push(try!(self.expect_column_info()));
+            if self.expect_keyword(&[Keyword::Unique]).is_ok() {
+                table_info
+                    .uniques
+                    .push(try!(self.parse_unique_constraint()));
+            } else if self.expect_keyword(&[Keyword::Foreign]).is_ok() {
+                table_info.foreign_keys.push(try!(self.parse_foreign_key()));
+            } else if self.expect_keyword(&[Keyword::Check]).is_ok() {
+                table_info.checks.push(try!(self.parse_check_constraint()));
+            } else {
+                // parsing the content for a single ColumnInfo
+                table_info.cols.push(try!(self.expect_column_info()));
+            }
             try!(self.bump());
             // Check if there is a Comma seperating two columns or a ParenCl
             // ending the vectorparsing
@@ -216,20 +732,165 @@ impl<'a> Parser<'a> {
                 _ => (),
             };
         }
-        Ok(colsvec)
+        Ok(())
+    }
+
+    // Parses the column list of a UNIQUE constraint: UNIQUE (<column>, ...)
+    fn parse_unique_constraint(&mut self) -> Result<Vec<String>, ParseError> {
+        try!(self.bump());
+        self.parse_column_list()
+    }
+
+    // Parses a FOREIGN KEY constraint:
+    // FOREIGN KEY (<column>, ...) REFERENCES <table> [(<column>, ...)]
+    // [ON DELETE RESTRICT | CASCADE | SET NULL]
+    fn parse_foreign_key(&mut self) -> Result<ForeignKeyInfo, ParseError> {
+        try!(self.bump());
+        try!(self.expect_keyword(&[Keyword::Key]));
+        try!(self.bump());
+        let cols = try!(self.parse_column_list());
+        try!(self.bump());
+        try!(self.expect_keyword(&[Keyword::References]));
+        try!(self.bump());
+        let ref_tid = try!(self.expect_word(false));
+        let mut ref_cols = Vec::new();
+        if self.check_next_token(&[Token::ParenOp]) {
+            try!(self.bump());
+            ref_cols = try!(self.parse_column_list());
+        }
+        let mut on_delete = ReferentialAction::Restrict;
+        if self.check_next_keyword(&[Keyword::On]) {
+            try!(self.bump());
+            try!(self.bump());
+            try!(self.expect_keyword(&[Keyword::Delete]));
+            try!(self.bump());
+            on_delete = match try!(self.expect_keyword(&[
+                Keyword::Restrict,
+                Keyword::Cascade,
+                Keyword::Set
+            ])) {
+                Keyword::Cascade => ReferentialAction::Cascade,
+                Keyword::Set => {
+                    try!(self.bump());
+                    try!(self.expect_keyword(&[Keyword::Null]));
+                    ReferentialAction::SetNull
+                }
+                _ => ReferentialAction::Restrict,
+            };
+        }
+        Ok(ForeignKeyInfo {
+            cols: cols,
+            ref_tid: ref_tid,
+            ref_cols: ref_cols,
+            on_delete: on_delete,
+        })
+    }
+
+    // Parses a CHECK constraint: CHECK (<condition>), ends on the closing
+    // parenthesis
+    fn parse_check_constraint(&mut self) -> Result<Conditions, ParseError> {
+        try!(self.bump());
+        try!(self.expect_token(&[Token::ParenOp]));
+        let cond = try!(self.parse_disjunction());
+        try!(self.bump());
+        try!(self.expect_token(&[Token::ParenCl]).map_err(|e| match e {
+            ParseError::WrongToken(span) => ParseError::MissingParenthesis(span),
+            _ => e,
+        }));
+        if cond.has_subquery() {
+            return Err(ParseError::DebugError(
+                "CHECK constraints cannot contain subqueries".into(),
+            ));
+        }
+        Ok(cond)
+    }
+
+    // Parses a list of columns in parentheses, ends on the closing one
+    fn parse_column_list(&mut self) -> Result<Vec<String>, ParseError> {
+        try!(self.expect_token(&[Token::ParenOp]));
+        try!(self.bump());
+        let mut columns = vec![try!(self.expect_word(true))];
+        try!(self.bump());
+        while try!(self.expect_token(&[Token::Comma, Token::ParenCl])) == Token::Comma {
+            try!(self.bump());
+            columns.push(try!(self.expect_word(true)));
+            try!(self.bump());
+        }
+        Ok(columns)
     }
 
     // Parses tokens for alter statement
     fn parse_alt_stmt(&mut self) -> Result<AltStmt, ParseError> {
         try!(self.bump());
-        match try!(self.expect_keyword(&[Keyword::Table])) {
+        match try!(self.expect_keyword(&[Keyword::Table, Keyword::User, Keyword::Database])) {
             Keyword::Table => Ok(AltStmt::Table(try!(self.parse_alter_table_stmt()))),
+            Keyword::User => Ok(AltStmt::User(try!(self.parse_user_stmt()))),
+            Keyword::Database => Ok(AltStmt::Database(try!(self.parse_alter_database_stmt()))),
 
             // Unknown parsing error
             _ => Err(ParseError::UnknownError),
         }
     }
 
+    // Parses the user name and the password clause of CREATE/ALTER USER:
+    // `<name> IDENTIFIED BY '<password>'` or `<name> PASSWORD '<password>'`
+    fn parse_user_stmt(&mut self) -> Result<UserStmt, ParseError> {
+        try!(self.bump());
+        let name = try!(self.expect_word(false));
+        try!(self.bump());
+        match try!(self.expect_keyword(&[Keyword::Identified, Keyword::Password])) {
+            Keyword::Identified => {
+                try!(self.bump());
+                try!(self.expect_keyword(&[Keyword::By]));
+            }
+            _ => (),
+        }
+        try!(self.bump());
+        let password = match try!(self.expect_literal()) {
+            Lit::String(s) => s,
+            _ => return Err(ParseError::PasswordIsNoString),
+        };
+        Ok(UserStmt {
+            name: name,
+            password: password,
+        })
+    }
+
+    // Parses the database and its new limits in any order, at least one:
+    // `<name> MAX_SIZE [=] <bytes> MAX_CONNECTIONS [=] <count>`
+    fn parse_alter_database_stmt(&mut self) -> Result<AlterDatabaseStmt, ParseError> {
+        try!(self.bump());
+        let mut stmt = AlterDatabaseStmt {
+            name: try!(self.expect_word(false)),
+            max_size: None,
+            max_connections: None,
+        };
+        // max_size and max_connections are no keywords
+        while is_word(&self.peek, "max_size") || is_word(&self.peek, "max_connections") {
+            try!(self.bump());
+            let size = is_word(&self.curr, "max_size");
+            try!(self.bump());
+            if self.expect_token(&[Token::Equ]).is_ok() {
+                try!(self.bump());
+            }
+            let value = match (try!(self.expect_number()), &self.curr) {
+                (Lit::Int(value), _) if value >= 0 => value as u64,
+                (_, &Some(ref token)) => return Err(ParseError::NotANumber(token.span.clone())),
+                (_, &None) => return Err(ParseError::UnexpectedEoq),
+            };
+            if size {
+                stmt.max_size = Some(value);
+            } else {
+                stmt.max_connections = Some(value);
+            }
+        }
+        if stmt.max_size.is_none() && stmt.max_connections.is_none() {
+            try!(self.bump());
+            try!(self.expect_plain_word("max_size"));
+        }
+        Ok(stmt)
+    }
+
     // Parses table to modify and subsequent operations
     fn parse_alter_table_stmt(&mut self) -> Result<AlterTableStmt, ParseError> {
         try!(self.bump());
@@ -244,9 +905,18 @@ impl<'a> Parser<'a> {
     // datatype if necessary
     fn parse_alter_op(&mut self) -> Result<AlterOp, ParseError> {
         try!(self.bump());
-        match try!(self.expect_keyword(&[Keyword::Add, Keyword::Drop, Keyword::Modify])) {
+        match try!(self.expect_keyword(&[
+            Keyword::Add,
+            Keyword::Drop,
+            Keyword::Modify,
+            Keyword::Rename
+        ])) {
             Keyword::Add => {
                 try!(self.bump());
+                // ADD COLUMN is the same as ADD
+                if self.expect_keyword(&[Keyword::Column]).is_ok() {
+                    try!(self.bump());
+                }
                 Ok(AlterOp::Add(try!(self.expect_column_info())))
             }
             Keyword::Drop => {
@@ -261,61 +931,324 @@ impl<'a> Parser<'a> {
                 try!(self.bump());
                 Ok(AlterOp::Modify(try!(self.expect_column_info())))
             }
-            _ => Err(ParseError::UnknownError),
-        }
-    }
-
-    // Parses the tokens for drop statement
-    fn parse_drop_stmt(&mut self) -> Result<DropStmt, ParseError> {
-        try!(self.bump());
-        match try!(self.expect_keyword(&[Keyword::Table, Keyword::Database, Keyword::View])) {
+            Keyword::Rename => {
+                try!(self.bump());
+                try!(self.expect_keyword(&[Keyword::To]));
+                try!(self.bump());
+                Ok(AlterOp::Rename(try!(self.expect_word(false))))
+            }
+            _ => Err(ParseError::UnknownError),
+        }
+    }
+
+    // Parses the tokens for drop statement
+    fn parse_drop_stmt(&mut self) -> Result<DropStmt, ParseError> {
+        try!(self.bump());
+        if is_word(&self.curr, "function") {
+            try!(self.bump());
+            return Ok(DropStmt::Function(
+                try!(self.expect_word(false)).to_lowercase(),
+            ));
+        }
+        if is_word(&self.curr, "event") {
+            try!(self.bump());
+            return Ok(DropStmt::Event(try!(self.expect_word(false))));
+        }
+        match try!(self.expect_keyword(&[
+            Keyword::Table,
+            Keyword::Database,
+            Keyword::View,
+            Keyword::User
+        ])) {
+            Keyword::Table => {
+                try!(self.bump());
+                Ok(DropStmt::Table(try!(self.expect_word(false))))
+            }
+            Keyword::Database => {
+                try!(self.bump());
+                let mut if_exists = false;
+                if self.expect_keyword(&[Keyword::If]).is_ok() {
+                    try!(self.bump());
+                    try!(self.expect_keyword(&[Keyword::Exists]));
+                    try!(self.bump());
+                    if_exists = true;
+                }
+                let name = try!(self.expect_word(false));
+                let force = self.check_next_keyword(&[Keyword::Force]);
+                if force {
+                    try!(self.bump());
+                }
+                Ok(DropStmt::Database(DropDatabaseStmt {
+                    name: name,
+                    if_exists: if_exists,
+                    force: force,
+                }))
+            }
+            Keyword::View => {
+                try!(self.bump());
+                Ok(DropStmt::View(try!(self.expect_word(false))))
+            }
+            Keyword::User => {
+                try!(self.bump());
+                Ok(DropStmt::User(try!(self.expect_word(false))))
+            }
+            _ => Err(ParseError::UnknownError),
+        }
+    }
+
+    // Parses the tokens for use statement
+    fn parse_use_stmt(&mut self) -> Result<UseStmt, ParseError> {
+        try!(self.bump());
+        match try!(self.expect_keyword(&[Keyword::Database])) {
+            Keyword::Database => {
+                try!(self.bump());
+                Ok(UseStmt::Database(try!(self.expect_word(false))))
+            }
+            _ => Err(ParseError::UnknownError),
+        }
+    }
+
+    // Parses the tokens for grant and revoke statements:
+    // `<privileges> ON <object> TO|FROM <user>`, where object is one of
+    // `*`, `<table>`, `<db>.*` or `<db>.<table>`
+    fn parse_grant_stmt(&mut self, user_keyword: Keyword) -> Result<GrantStmt, ParseError> {
+        let mut privileges = Vec::new();
+        let mut done = false;
+        // parsing the privilege list, at least one
+        while !done {
+            try!(self.bump());
+            match try!(self.expect_keyword(&[
+                Keyword::Select,
+                Keyword::Insert,
+                Keyword::Update,
+                Keyword::Delete,
+                Keyword::Create,
+                Keyword::Drop,
+                Keyword::All
+            ])) {
+                Keyword::Select => privileges.push(Privilege::Select),
+                Keyword::Insert => privileges.push(Privilege::Insert),
+                Keyword::Update => privileges.push(Privilege::Update),
+                Keyword::Delete => privileges.push(Privilege::Delete),
+                Keyword::Create => privileges.push(Privilege::Create),
+                Keyword::Drop => privileges.push(Privilege::Drop),
+                Keyword::All => privileges.extend(Privilege::all()),
+                _ => return Err(ParseError::UnknownError),
+            }
+            if self.check_next_token(&[Token::Comma]) {
+                try!(self.bump());
+            } else {
+                done = true;
+            }
+        }
+        try!(self.bump());
+        try!(self.expect_keyword(&[Keyword::On]));
+        try!(self.bump());
+        let (database, table) = if self.expect_token(&[Token::Star]).is_ok() {
+            (None, None)
+        } else {
+            let name = try!(self.expect_word(false));
+            if self.check_next_token(&[Token::Dot]) {
+                try!(self.bump());
+                try!(self.bump());
+                if self.expect_token(&[Token::Star]).is_ok() {
+                    (Some(name), None)
+                } else {
+                    (Some(name), Some(try!(self.expect_word(false))))
+                }
+            } else {
+                (None, Some(name))
+            }
+        };
+        try!(self.bump());
+        try!(self.expect_keyword(&[user_keyword]));
+        try!(self.bump());
+        Ok(GrantStmt {
+            privileges: privileges,
+            database: database,
+            table: table,
+            user: try!(self.expect_word(false)),
+        })
+    }
+
+    // Parses the tokens for show statement
+    fn parse_show_stmt(&mut self) -> Result<ShowStmt, ParseError> {
+        try!(self.bump());
+        // workers, replica, events, progress and recovery are no keywords,
+        // tables and columns may be named like them
+        if is_word(&self.curr, "workers") {
+            return Ok(ShowStmt::Workers);
+        }
+        if is_word(&self.curr, "recovery") {
+            return Ok(ShowStmt::Recovery);
+        }
+        if is_word(&self.curr, "progress") {
+            try!(self.bump());
+            try!(self.expect_keyword(&[Keyword::For]));
+            try!(self.bump());
+            return match (try!(self.expect_number()), &self.curr) {
+                (Lit::Int(id), _) => Ok(ShowStmt::Progress(id as u64)),
+                (_, &Some(ref token)) => Err(ParseError::NotANumber(token.span.clone())),
+                (_, &None) => Err(ParseError::UnexpectedEoq),
+            };
+        }
+        if is_word(&self.curr, "events") {
+            return Ok(ShowStmt::Events);
+        }
+        if is_word(&self.curr, "replica") {
+            try!(self.bump());
+            try!(self.expect_keyword(&[Keyword::Status]));
+            return Ok(ShowStmt::ReplicaStatus);
+        }
+        match try!(self.expect_keyword(&[
+            Keyword::Grants,
+            Keyword::Processlist,
+            Keyword::Status,
+            Keyword::Stats,
+            Keyword::Table,
+            Keyword::Database,
+            Keyword::Create,
+            Keyword::Databases,
+            Keyword::Tables,
+            Keyword::Columns,
+            Keyword::Engines,
+        ])) {
+            Keyword::Grants => {
+                if self.check_next_keyword(&[Keyword::For]) {
+                    try!(self.bump());
+                    try!(self.bump());
+                    Ok(ShowStmt::Grants(Some(try!(self.expect_word(false)))))
+                } else {
+                    Ok(ShowStmt::Grants(None))
+                }
+            }
+            Keyword::Processlist => Ok(ShowStmt::Processlist),
+            Keyword::Status => Ok(ShowStmt::Status),
+            Keyword::Stats => {
+                if self.check_next_keyword(&[Keyword::For]) {
+                    try!(self.bump());
+                    try!(self.bump());
+                    Ok(ShowStmt::Stats(Some(try!(self.expect_word(false)))))
+                } else {
+                    Ok(ShowStmt::Stats(None))
+                }
+            }
             Keyword::Table => {
                 try!(self.bump());
-                Ok(DropStmt::Table(try!(self.expect_word(false))))
+                try!(self.expect_keyword(&[Keyword::Status]));
+                Ok(ShowStmt::TableStatus)
             }
             Keyword::Database => {
                 try!(self.bump());
-                Ok(DropStmt::Database(try!(self.expect_word(false))))
+                try!(self.expect_keyword(&[Keyword::Status]));
+                Ok(ShowStmt::DatabaseStatus)
             }
-            Keyword::View => {
+            Keyword::Create => {
                 try!(self.bump());
-                Ok(DropStmt::View(try!(self.expect_word(false))))
+                try!(self.expect_keyword(&[Keyword::View]));
+                try!(self.bump());
+                Ok(ShowStmt::CreateView(try!(self.expect_word(false))))
+            }
+            Keyword::Databases => Ok(ShowStmt::Databases),
+            Keyword::Tables => Ok(ShowStmt::Tables),
+            Keyword::Engines => Ok(ShowStmt::Engines),
+            Keyword::Columns => {
+                try!(self.bump());
+                try!(self.expect_keyword(&[Keyword::From, Keyword::In]));
+                try!(self.bump());
+                let mut table = try!(self.expect_word(false));
+                // tables of another schema, like information_schema.tables
+                if self.check_next_token(&[Token::Dot]) {
+                    try!(self.bump());
+                    try!(self.bump());
+                    table = format!("{}.{}", table, try!(self.expect_word(true)));
+                }
+                Ok(ShowStmt::Columns(table))
             }
             _ => Err(ParseError::UnknownError),
         }
     }
 
-    // Parses the tokens for use statement
-    fn parse_use_stmt(&mut self) -> Result<UseStmt, ParseError> {
+    // Parses the tokens for notify statement, the payload is optional
+    fn parse_notify_stmt(&mut self) -> Result<NotifyStmt, ParseError> {
         try!(self.bump());
-        match try!(self.expect_keyword(&[Keyword::Database])) {
-            Keyword::Database => {
-                try!(self.bump());
-                Ok(UseStmt::Database(try!(self.expect_word(false))))
+        let channel = try!(self.expect_word(false));
+        let payload = if self.check_next_token(&[Token::Comma]) {
+            try!(self.bump());
+            try!(self.bump());
+            match try!(self.expect_literal()) {
+                Lit::String(s) => s,
+                _ => return Err(ParseError::PayloadIsNoString),
             }
-            _ => Err(ParseError::UnknownError),
+        } else {
+            String::new()
+        };
+        Ok(NotifyStmt {
+            channel: channel,
+            payload: payload,
+        })
+    }
+
+    // checks if the current token is a string and returns it as the path
+    // of a backup
+    fn expect_path(&mut self) -> Result<String, ParseError> {
+        match try!(self.expect_literal()) {
+            Lit::String(s) => Ok(s),
+            _ => Err(ParseError::PathIsNoString),
         }
     }
 
-    // Parses tokens for insert statement
-    fn parse_insert_stmt(&mut self) -> Result<InsertStmt, ParseError> {
+    // Parses tokens for insert statement, the rows are given as values or
+    // by a select
+    fn parse_insert_stmt(&mut self) -> Result<ManipulationStmt, ParseError> {
         try!(self.bump());
+        let mut on_duplicate = None;
+        if self.expect_keyword(&[Keyword::Ignore]).is_ok() {
+            on_duplicate = Some(OnDuplicate::Ignore);
+            try!(self.bump());
+        }
         match try!(self.expect_keyword(&[Keyword::Into])) {
             Keyword::Into => (),
             _ => return Err(ParseError::UnknownError),
         }
 
         try!(self.bump());
+        let tid = try!(self.expect_word(false));
+        let col = try!(self.parse_insert_stmt_detail());
+        if self.expect_keyword(&[Keyword::Select]).is_ok() {
+            return Ok(ManipulationStmt::InsertSelect(InsertSelectStmt {
+                tid: tid,
+                col: col,
+                select: try!(self.parse_query_expr()),
+                on_duplicate: on_duplicate,
+            }));
+        }
+        let val = try!(self.parse_insert_stmt_value());
+        if self.check_next_keyword(&[Keyword::On]) {
+            try!(self.bump());
+            try!(self.bump());
+            try!(self.expect_keyword(&[Keyword::Duplicate]));
+            try!(self.bump());
+            try!(self.expect_keyword(&[Keyword::Key]));
+            try!(self.bump());
+            try!(self.expect_keyword(&[Keyword::Update]));
+            on_duplicate = Some(OnDuplicate::Update(try!(self.parse_assignments())));
+            // the assignments end on the token after them
+            if self.curr.is_some() {
+                return Err(ParseError::InvalidEoq);
+            }
+        }
         let i = InsertStmt {
-            tid: try!(self.expect_word(false)),
-            col: try!(self.parse_insert_stmt_detail()),
-            val: try!(self.parse_insert_stmt_value()),
+            tid: tid,
+            col: col,
+            val: val,
+            on_duplicate: on_duplicate,
         };
 
-        if i.col.len() != 0 && i.col.len() != i.val.len() {
+        if i.col.len() != 0 && i.val.iter().any(|row| row.len() != i.col.len()) {
             return Err(ParseError::ColumnCountMissmatch);
         }
-        Ok(i)
+        Ok(ManipulationStmt::Insert(i))
     }
 
     // Parses columns for insert statement
@@ -355,13 +1288,24 @@ impl<'a> Parser<'a> {
         Ok(res_vec)
     }
 
-    // Parses i.val of parse_insert_stmt
-    fn parse_insert_stmt_value(&mut self) -> Result<Vec<Lit>, ParseError> {
-        let mut res_vec = Vec::<Lit>::new();
+    // Parses i.val of parse_insert_stmt: the rows after VALUES, separated
+    // by commas
+    fn parse_insert_stmt_value(&mut self) -> Result<Vec<Vec<Lit>>, ParseError> {
         match try!(self.expect_keyword(&[Keyword::Values])) {
             Keyword::Values => (),
             _ => return Err(ParseError::UnknownError),
         }
+        let mut rows = vec![try!(self.parse_insert_stmt_row())];
+        while self.check_next_token(&[Token::Comma]) {
+            try!(self.bump());
+            rows.push(try!(self.parse_insert_stmt_row()));
+        }
+        Ok(rows)
+    }
+
+    // Continuation of parse_insert_stmt_value, parses the values of a row
+    fn parse_insert_stmt_row(&mut self) -> Result<Vec<Lit>, ParseError> {
+        let mut res_vec = Vec::<Lit>::new();
         try!(self.bump());
         try!(self.expect_token(&[Token::ParenOp]));
         try!(self.bump());
@@ -394,9 +1338,24 @@ impl<'a> Parser<'a> {
         }
         try!(self.bump());
         try!(self.expect_keyword(&[Keyword::Set]));
+        let setvec = try!(self.parse_assignments());
+        Ok(UpdateStmt {
+            tid: tableid,
+            alias: aliasmap,
+            set: setvec,
+            conds: if self.expect_keyword(&[Keyword::Where]).is_ok() {
+                Some(try!(self.parse_where_part()))
+            } else {
+                None
+            },
+        })
+    }
+
+    // Parses the assignments col = expression of UPDATE ... SET, at least
+    // one. Starts on the token before them and ends on the token after them.
+    fn parse_assignments(&mut self) -> Result<Vec<Condition>, ParseError> {
         let mut setvec = Vec::new();
         let mut done = false;
-        //parsing optional update changes, at least one
         while !done {
             try!(self.bump());
             //parse optional alias
@@ -410,7 +1369,8 @@ impl<'a> Parser<'a> {
             try!(self.bump());
             try!(self.expect_token(&[Token::Equ]));
             try!(self.bump());
-            let value = try!(self.expect_literal());
+            // the value may be computed from the values of the row
+            let value = try!(self.expect_expression(None));
             try!(self.bump());
             setvec.push(Condition {
                 aliascol: alias,
@@ -423,16 +1383,7 @@ impl<'a> Parser<'a> {
                 done = true;
             }
         }
-        Ok(UpdateStmt {
-            tid: tableid,
-            alias: aliasmap,
-            set: setvec,
-            conds: if self.expect_keyword(&[Keyword::Where]).is_ok() {
-                Some(try!(self.parse_where_part()))
-            } else {
-                None
-            },
-        })
+        Ok(setvec)
     }
 
     // Parses the tokens for delete statement
@@ -442,7 +1393,7 @@ impl<'a> Parser<'a> {
         try!(self.bump());
         let tableid = try!(self.expect_word(false));
         let mut aliasmap = HashMap::new();
-        if !self.check_next_keyword(&[Keyword::Where]) {
+        if !self.check_next_keyword(&[Keyword::Where, Keyword::Returning]) {
             try!(self.bump());
             match self.expect_word(false) {
                 Err(ParseError::UnexpectedEoq) => (),
@@ -458,33 +1409,27 @@ impl<'a> Parser<'a> {
             Ok(Keyword::Where) => Some(try!(self.parse_where_part())),
             _ => None,
         };
+        let returning = if self.expect_keyword(&[Keyword::Returning]).is_ok() {
+            try!(self.parse_targets())
+        } else {
+            Vec::new()
+        };
         Ok(DeleteStmt {
             tid: tableid,
             alias: aliasmap,
             cond: conditiontree,
+            returning: returning,
         })
     }
 
-    // Parses the tokens for select statement
-    fn parse_select_stmt(&mut self) -> Result<SelectStmt, ParseError> {
+    // Parses the targets of a select or of RETURNING, at least one. Starts on
+    // the token before them and ends on the token after them.
+    fn parse_targets(&mut self) -> Result<Vec<Target>, ParseError> {
         let mut targetvec = Vec::new();
         let mut done = false;
-        // parsing optional targets, at least one
         while !done {
-            // optional table alias
             try!(self.bump());
-            let mut targetalias = None;
-            if self.check_next_token(&[Token::Dot]) {
-                targetalias = Some(try!(self.expect_word(false)));
-                try!(self.bump());
-                try!(self.bump());
-            };
-            // required target column
-            let targetcol = match self.expect_token(&[Token::Star]) {
-                Err(err) => Col::Specified(try!(self.expect_word(true))),
-                Ok(Token::Star) => Col::Every,
-                _ => return Err(ParseError::UnknownError),
-            };
+            let (targetalias, targetcol) = try!(self.parse_target());
             try!(self.bump());
             // optional target column rename
             let mut targetrename = None;
@@ -503,33 +1448,22 @@ impl<'a> Parser<'a> {
                 done = true;
             }
         }
+        Ok(targetvec)
+    }
+
+    // Parses the tokens for select statement
+    fn parse_select_stmt(&mut self) -> Result<SelectStmt, ParseError> {
+        let targetvec = try!(self.parse_targets());
         // parsing the from list, at least one table required
         try!(self.expect_keyword(&[Keyword::From]));
         let mut tidvec = Vec::new();
         let mut aliasmap = HashMap::new();
-        done = false;
+        let mut names = Vec::new();
+        let mut done = false;
         // parsing optional tables
         while !done {
             try!(self.bump());
-            let tableid = try!(self.expect_word(false));
-            if !self.check_next_keyword(&[
-                Keyword::Where,
-                Keyword::Limit,
-                Keyword::Group,
-                Keyword::Order,
-            ]) && !self.check_next_token(&[Token::Comma])
-            {
-                try!(self.bump());
-                match self.expect_word(false) {
-                    Err(ParseError::UnexpectedEoq) => (),
-                    Err(err) => return Err(err),
-                    Ok(s) => {
-                        aliasmap.insert(s.clone(), tableid.clone());
-                        ()
-                    }
-                }
-            }
-            tidvec.push(tableid);
+            tidvec.push(try!(self.parse_table_ref(&mut aliasmap, &mut names)));
             if !self.check_next_token(&[Token::Comma]) {
                 done = true;
                 try!(self.bump());
@@ -537,6 +1471,26 @@ impl<'a> Parser<'a> {
                 try!(self.bump());
             }
         }
+        // optional joined tables
+        let mut joins = Vec::new();
+        while self
+            .expect_keyword(&[Keyword::Join, Keyword::Inner])
+            .is_ok()
+        {
+            if self.expect_keyword(&[Keyword::Inner]).is_ok() {
+                try!(self.bump());
+                try!(self.expect_keyword(&[Keyword::Join]));
+            }
+            try!(self.bump());
+            let tableid = try!(self.parse_table_ref(&mut aliasmap, &mut names));
+            try!(self.bump());
+            try!(self.expect_keyword(&[Keyword::On]));
+            let cond = try!(self.parse_where_part());
+            joins.push(Join {
+                tid: tableid,
+                cond: cond,
+            });
+        }
         let mut conditions = None;
         let mut order_vec = Vec::new();
         // optional where statement
@@ -553,35 +1507,7 @@ impl<'a> Parser<'a> {
         }
         if self.expect_keyword(&[Keyword::Order]).is_ok() {
             try!(self.bump());
-            try!(self.expect_keyword(&[Keyword::By]));
-            let mut done = false;
-            while !done {
-                try!(self.bump());
-                let mut o_alias = None;
-                if self.check_next_token(&[Token::Dot]) {
-                    o_alias = Some(try!(self.expect_word(false)));
-                    try!(self.bump());
-                    try!(self.bump());
-                };
-                let o_col = try!(self.expect_word(true));
-                let mut o_order = Some(Order::Asc);
-                if self.check_next_keyword(&[Keyword::Asc]) {
-                    try!(self.bump());
-                } else if self.check_next_keyword(&[Keyword::Desc]) {
-                    o_order = Some(Order::Desc);
-                    try!(self.bump());
-                }
-                order_vec.push(Sort {
-                    alias: o_alias,
-                    col: o_col,
-                    order: o_order,
-                });
-                if !self.check_next_token(&[Token::Comma]) {
-                    done = true;
-                } else {
-                    try!(self.bump());
-                }
-            }
+            order_vec = try!(self.parse_sort_list());
             if self.check_next_keyword(&[Keyword::Limit]) {
                 try!(self.bump());
             }
@@ -614,7 +1540,9 @@ impl<'a> Parser<'a> {
         Ok(SelectStmt {
             target: targetvec,
             tid: tidvec,
+            joins: joins,
             alias: aliasmap,
+            names: names,
             cond: conditions,
             spec_op: None,
             order: order_vec,
@@ -622,15 +1550,306 @@ impl<'a> Parser<'a> {
         })
     }
 
+    // parses a select statement and the ones combined with it by UNION,
+    // INTERSECT or EXCEPT, starting on the first SELECT
+    // Parses a select or selects combined by set operations like
+    // parse_compound_stmt, a single select is wrapped too
+    fn parse_query_expr(&mut self) -> Result<CompoundStmt, ParseError> {
+        match try!(self.parse_compound_stmt()) {
+            ManipulationStmt::Compound(stmt) => Ok(stmt),
+            ManipulationStmt::Select(stmt) => Ok(CompoundStmt {
+                select: SetExpr::Select(stmt),
+                order: Vec::new(),
+                limit: None,
+            }),
+            _ => Err(ParseError::UnknownError),
+        }
+    }
+
+    fn parse_compound_stmt(&mut self) -> Result<ManipulationStmt, ParseError> {
+        let set_ops = &[Keyword::Union, Keyword::Intersect, Keyword::Except];
+        let mut selects = vec![try!(self.parse_select_stmt())];
+        let mut ops = Vec::new();
+        loop {
+            // a select ends on its last token or on the one after it
+            if self.expect_keyword(set_ops).is_err() && self.check_next_keyword(set_ops) {
+                try!(self.bump());
+            }
+            let op = match self.expect_keyword(set_ops) {
+                Ok(Keyword::Union) if self.check_next_keyword(&[Keyword::All]) => {
+                    try!(self.bump());
+                    SetOp::UnionAll
+                }
+                Ok(Keyword::Union) => SetOp::Union,
+                Ok(Keyword::Intersect) => SetOp::Intersect,
+                Ok(_) => SetOp::Except,
+                Err(_) => break,
+            };
+            {
+                let last = &selects[selects.len() - 1];
+                if !last.order.is_empty() || last.limit.is_some() {
+                    return Err(ParseError::DebugError(
+                        "ORDER BY and LIMIT must follow the last select".into(),
+                    ));
+                }
+            }
+            try!(self.bump());
+            try!(self.expect_keyword(&[Keyword::Select]));
+            selects.push(try!(self.parse_select_stmt()));
+            ops.push(op);
+        }
+        let mut last = selects.pop().unwrap();
+        if ops.is_empty() {
+            return Ok(ManipulationStmt::Select(last));
+        }
+        let mut order = Vec::new();
+        swap(&mut order, &mut last.order);
+        let limit = last.limit.take();
+        selects.push(last);
+
+        // intersections first, then the other operations from left to right
+        let mut selects = selects.into_iter().map(SetExpr::Select);
+        let mut terms = vec![selects.next().unwrap()];
+        let mut term_ops = Vec::new();
+        for (op, select) in ops.into_iter().zip(selects) {
+            if op == SetOp::Intersect {
+                let left = terms.pop().unwrap();
+                terms.push(SetExpr::Op(op, Box::new(left), Box::new(select)));
+            } else {
+                term_ops.push(op);
+                terms.push(select);
+            }
+        }
+        let mut terms = terms.into_iter();
+        let mut expr = terms.next().unwrap();
+        for (op, term) in term_ops.into_iter().zip(terms) {
+            expr = SetExpr::Op(op, Box::new(expr), Box::new(term));
+        }
+        Ok(ManipulationStmt::Compound(CompoundStmt {
+            select: expr,
+            order: order,
+            limit: limit,
+        }))
+    }
+
+    // parses a target of a select statement: a column with an optional
+    // table alias, all columns of a table, COUNT(*), an expression or a
+    // window function
+    fn parse_target(&mut self) -> Result<(Option<String>, Col), ParseError> {
+        if self.expect_token(&[Token::Star]).is_ok() {
+            return Ok((None, Col::Every));
+        }
+        let lo = self.curr.as_ref().map_or(0, |token| token.span.lo);
+        let word = match self.curr {
+            Some(TokenSpan {
+                tok: Token::Word(ref s),
+                ..
+            }) => Some(s.to_lowercase()),
+            _ => None,
+        };
+        let mut first = None;
+        if self.check_next_token(&[Token::Dot]) {
+            // optional table alias
+            let alias = try!(self.expect_word(false));
+            try!(self.bump());
+            try!(self.bump());
+            if self.expect_token(&[Token::Star]).is_ok() {
+                return Ok((Some(alias), Col::Every));
+            }
+            first = Some(Lit::Column(Some(alias), try!(self.expect_word(true))));
+        } else if word == Some("count".into()) && self.check_next_token(&[Token::ParenOp]) {
+            // count is no keyword, so that columns may still be named so
+            try!(self.bump());
+            try!(self.bump());
+            try!(self.expect_token(&[Token::Star]));
+            try!(self.bump());
+            try!(self.expect_token(&[Token::ParenCl]));
+            return Ok((None, Col::Count));
+        }
+        let lit = try!(self.expect_expression(first));
+        // over is no keyword either
+        if is_word(&self.peek, "over") {
+            let span = Span {
+                lo: lo,
+                hi: self.end,
+            };
+            return Ok((None, Col::Window(try!(self.parse_window(lit, span)))));
+        }
+        Ok(match lit {
+            Lit::Column(alias, column) => (alias, Col::Specified(column)),
+            lit => (None, Col::Expr(lit)),
+        })
+    }
+
+    // parses OVER (PARTITION BY ... ORDER BY ...) after the call of a window
+    // function, which spans `span`, starting on the last token of the call;
+    // stops on the closing parenthesis
+    fn parse_window(&mut self, call: Lit, span: Span) -> Result<Window, ParseError> {
+        let function = match call {
+            Lit::Call(ref name, ref args) => match (&name[..], &args[..]) {
+                ("row_number", &[]) => Some(WindowFunction::RowNumber),
+                ("rank", &[]) => Some(WindowFunction::Rank),
+                ("sum", &[ref arg]) => Some(WindowFunction::Sum(arg.clone())),
+                ("avg", &[ref arg]) => Some(WindowFunction::Avg(arg.clone())),
+                _ => None,
+            },
+            _ => None,
+        };
+        let function = try!(function.ok_or(ParseError::NotAWindowFunction(span)));
+        try!(self.bump());
+        try!(self.bump());
+        try!(self.expect_token(&[Token::ParenOp]));
+        try!(self.bump());
+        let mut partition = Vec::new();
+        // partition is no keyword, so that columns may still be named so
+        if is_word(&self.curr, "partition") {
+            try!(self.bump());
+            try!(self.expect_keyword(&[Keyword::By]));
+            loop {
+                try!(self.bump());
+                let (alias, col) = try!(self.parse_column_ref());
+                partition.push(Sort {
+                    alias: alias,
+                    col: col,
+                    order: None,
+                });
+                if !self.check_next_token(&[Token::Comma]) {
+                    break;
+                }
+                try!(self.bump());
+            }
+            try!(self.bump());
+        }
+        let mut order = Vec::new();
+        if self.expect_keyword(&[Keyword::Order]).is_ok() {
+            try!(self.bump());
+            order = try!(self.parse_sort_list());
+            try!(self.bump());
+        }
+        try!(self.expect_token(&[Token::ParenCl]).map_err(|e| match e {
+            ParseError::WrongToken(span) => ParseError::MissingParenthesis(span),
+            _ => e,
+        }));
+        Ok(Window {
+            function: function,
+            partition: partition,
+            order: order,
+        })
+    }
+
+    // parses a column with an optional table alias, stops on its last token
+    fn parse_column_ref(&mut self) -> Result<(Option<String>, String), ParseError> {
+        let mut alias = None;
+        if self.check_next_token(&[Token::Dot]) {
+            alias = Some(try!(self.expect_word(false)));
+            try!(self.bump());
+            try!(self.bump());
+        }
+        Ok((alias, try!(self.expect_word(true))))
+    }
+
+    // parses the columns of ORDER BY, each optionally followed by ASC or
+    // DESC, starting on BY; stops on the last token of the list
+    fn parse_sort_list(&mut self) -> Result<Vec<Sort>, ParseError> {
+        try!(self.expect_keyword(&[Keyword::By]));
+        let mut order_vec = Vec::new();
+        loop {
+            try!(self.bump());
+            let (o_alias, o_col) = try!(self.parse_column_ref());
+            let mut o_order = Some(Order::Asc);
+            if self.check_next_keyword(&[Keyword::Asc]) {
+                try!(self.bump());
+            } else if self.check_next_keyword(&[Keyword::Desc]) {
+                o_order = Some(Order::Desc);
+                try!(self.bump());
+            }
+            order_vec.push(Sort {
+                alias: o_alias,
+                col: o_col,
+                order: o_order,
+            });
+            if !self.check_next_token(&[Token::Comma]) {
+                break;
+            }
+            try!(self.bump());
+        }
+        Ok(order_vec)
+    }
+
+    // parses a table of the from list with its optional alias, stops on the
+    // last token of it
+    // parses a table of a select with its optional alias, `AS` may come
+    // before the alias. The name the table goes by is added to `names`.
+    fn parse_table_ref(
+        &mut self,
+        aliasmap: &mut HashMap<String, String>,
+        names: &mut Vec<String>,
+    ) -> Result<String, ParseError> {
+        let mut tableid = try!(self.expect_word(false));
+        // tables of another schema, like information_schema.tables
+        if self.check_next_token(&[Token::Dot]) {
+            try!(self.bump());
+            try!(self.bump());
+            tableid = format!("{}.{}", tableid, try!(self.expect_word(true)));
+        }
+        if !self.check_next_keyword(&[
+            Keyword::Where,
+            Keyword::Limit,
+            Keyword::Group,
+            Keyword::Order,
+            Keyword::Join,
+            Keyword::Inner,
+            Keyword::On,
+            Keyword::Union,
+            Keyword::Intersect,
+            Keyword::Except,
+        ]) && !self.check_next_token(&[Token::Comma, Token::ParenCl])
+        {
+            try!(self.bump());
+            if self.expect_keyword(&[Keyword::As]).is_ok() {
+                try!(self.bump());
+            }
+            match self.expect_word(false) {
+                Err(ParseError::UnexpectedEoq) => (),
+                Err(err) => return Err(err),
+                Ok(s) => {
+                    aliasmap.insert(s.clone(), tableid.clone());
+                    names.push(s);
+                    return Ok(tableid);
+                }
+            }
+        }
+        names.push(tableid.clone());
+        Ok(tableid)
+    }
+
     // ============================================================================
     // Utility Functions
     // ============================================================================
 
-    // sets next position for the lexer
+    // sets next position for the lexer, a semicolon ends the tokens of the
+    // statement
     fn bump(&mut self) -> Result<(), ParseError> {
         swap(&mut self.last, &mut self.curr); //  last = curr
         swap(&mut self.curr, &mut self.peek); //  curr = peek
-        self.peek = try!(self.lexiter.next_real());
+        if let Some(ref token) = self.curr {
+            self.end = token.span.hi;
+        }
+        self.peek = if self.semicolon.is_some() {
+            None
+        } else {
+            try!(self.lexiter.next_real())
+        };
+        if let Some(TokenSpan {
+            tok: Token::Semi,
+            ref span,
+        }) = self.peek
+        {
+            self.semicolon = Some(span.clone());
+        }
+        if self.semicolon.is_some() {
+            self.peek = None;
+        }
         Ok(())
     }
     // checks, if query is ended correctly. if yes -> returns query as ast
@@ -642,47 +1861,54 @@ impl<'a> Parser<'a> {
             Err(ParseError::InvalidEoq)
         }
     }
-    // parses the where part into Conditions type
+    // parses the where part into Conditions type and ends on the token after
+    // it. NOT binds closer than AND, which binds closer than OR.
     fn parse_where_part(&mut self) -> Result<Conditions, ParseError> {
-        let mut cond;
-        if self.check_next_token(&[Token::ParenOp]) {
+        let cond = try!(self.parse_disjunction());
+        try!(self.bump());
+        Ok(cond)
+    }
+    // parses conditions joined by OR, ends on their last token
+    fn parse_disjunction(&mut self) -> Result<Conditions, ParseError> {
+        let mut cond = try!(self.parse_conjunction());
+        while self.check_next_keyword(&[Keyword::Or]) {
             try!(self.bump());
-            cond = try!(self.parse_where_part());
-            try!(self.expect_token(&[Token::ParenCl]).map_err(|e| match e {
-                ParseError::WrongToken(span) => ParseError::MissingParenthesis(span),
-                _ => e,
-            }));
-            if self.check_next_keyword(&[Keyword::Or, Keyword::And]) {
-                try!(self.bump());
-                if self.expect_keyword(&[Keyword::Or]).is_ok() {
-                    cond = Conditions::Or(Box::new(cond), Box::new(try!(self.parse_where_part())));
-                } else if self.expect_keyword(&[Keyword::And]).is_ok() {
-                    cond = Conditions::And(Box::new(cond), Box::new(try!(self.parse_where_part())));
-                };
-            }
-        } else {
-            cond = Conditions::Leaf(try!(self.parse_condition()));
+            let rhs = try!(self.parse_conjunction());
+            cond = Conditions::Or(Box::new(cond), Box::new(rhs));
+        }
+        Ok(cond)
+    }
+    // parses conditions joined by AND
+    fn parse_conjunction(&mut self) -> Result<Conditions, ParseError> {
+        let mut cond = try!(self.parse_negation());
+        while self.check_next_keyword(&[Keyword::And]) {
             try!(self.bump());
-            while self.expect_keyword(&[Keyword::And, Keyword::Or]).is_ok() {
-                if self.expect_keyword(&[Keyword::Or]).is_ok() {
-                    cond = Conditions::Or(Box::new(cond), Box::new(try!(self.parse_where_part())));
-                } else {
-                    if self.check_next_token(&[Token::ParenOp]) {
-                        cond = Conditions::And(
-                            Box::new(cond),
-                            Box::new(try!(self.parse_where_part())),
-                        );
-                    } else {
-                        cond = Conditions::And(
-                            Box::new(cond),
-                            Box::new(Conditions::Leaf(try!(self.parse_condition()))),
-                        );
-                        try!(self.bump());
-                    };
-                };
+            let rhs = try!(self.parse_negation());
+            cond = Conditions::And(Box::new(cond), Box::new(rhs));
+        }
+        Ok(cond)
+    }
+    // parses a condition with any number of NOTs in front, which are pushed
+    // down to the comparisons, or conditions in parentheses
+    fn parse_negation(&mut self) -> Result<Conditions, ParseError> {
+        if self.check_next_keyword(&[Keyword::Not]) {
+            try!(self.bump());
+            return Ok(try!(self.parse_negation()).negate());
+        }
+        if self.check_next_token(&[Token::ParenOp]) {
+            // the parenthesis may also start an expression like (a + 1) > 2,
+            // which is parsed again as a condition
+            let start = self.clone();
+            try!(self.bump());
+            if let Ok(cond) = self.parse_disjunction() {
+                if self.check_next_token(&[Token::ParenCl]) {
+                    try!(self.bump());
+                    return Ok(cond);
+                }
             }
+            *self = start;
         }
-        Ok(cond)
+        self.parse_condition()
     }
 
     fn check_next_token(&self, checktoken: &[Token]) -> bool {
@@ -707,16 +1933,38 @@ impl<'a> Parser<'a> {
         }
     }
     // aprses a single condition
-    fn parse_condition(&mut self) -> Result<Condition, ParseError> {
+    fn parse_condition(&mut self) -> Result<Conditions, ParseError> {
         try!(self.bump());
-        let mut alias = None;
-        if self.check_next_token(&[Token::Dot]) {
-            alias = Some(try!(self.expect_word(false)));
-            try!(self.bump());
-            try!(self.bump());
-        };
-        let columnname = try!(self.expect_word(true));
+        if self.expect_keyword(&[Keyword::Match]).is_ok() {
+            return Ok(Conditions::Leaf(try!(self.parse_match_condition())));
+        }
+        if self.expect_keyword(&[Keyword::Exists]).is_ok() {
+            return self.parse_exists_condition();
+        }
+        let lhs = try!(self.expect_expression(None));
         try!(self.bump());
+        if self.expect_keyword(&[Keyword::Is]).is_ok() {
+            let operation = try!(self.parse_null_condition());
+            return Ok(comparison(lhs, operation, Lit::Null));
+        }
+        if self.expect_keyword(&[Keyword::Between]).is_ok() {
+            return self.parse_between_condition(lhs);
+        }
+        let negated = self.expect_keyword(&[Keyword::Not]).is_ok();
+        if negated {
+            try!(self.bump());
+        }
+        if self.expect_keyword(&[Keyword::In]).is_ok() {
+            return self.parse_in_condition(lhs, negated);
+        }
+        if negated
+            || self
+                .expect_keyword(&[Keyword::Like, Keyword::Regexp])
+                .is_ok()
+        {
+            let keyword = try!(self.expect_keyword(&[Keyword::Like, Keyword::Regexp]));
+            return self.parse_pattern_condition(lhs, keyword, negated);
+        }
         let operation = match try!(self.expect_token(&[
             Token::Equ,
             Token::GThan,
@@ -734,26 +1982,158 @@ impl<'a> Parser<'a> {
             _ => return Err(ParseError::UnknownError),
         };
         try!(self.bump());
-        let mut rhsalias = None;
-        let rhs = match self.expect_word(false) {
-            Ok(s) => {
-                if self.check_next_token(&[Token::Dot]) {
-                    rhsalias = Some(s);
-                    try!(self.bump());
-                    try!(self.bump());
+        let rhs = try!(self.expect_expression(None));
+        Ok(comparison(lhs, operation, rhs))
+    }
+    // parses the rest of the predicate lhs BETWEEN low AND high into
+    // lhs >= low AND lhs <= high
+    fn parse_between_condition(&mut self, lhs: Lit) -> Result<Conditions, ParseError> {
+        try!(self.bump());
+        let low = try!(self.expect_expression(None));
+        try!(self.bump());
+        try!(self.expect_keyword(&[Keyword::And]));
+        try!(self.bump());
+        let high = try!(self.expect_expression(None));
+        Ok(Conditions::And(
+            Box::new(comparison(lhs.clone(), CompType::GEThan, low)),
+            Box::new(comparison(lhs, CompType::SEThan, high)),
+        ))
+    }
+    // parses the rest of the predicate lhs [NOT] LIKE pattern [ESCAPE 'c'] or
+    // lhs [NOT] REGEXP pattern, patterns with ESCAPE are rewritten to escape
+    // with a backslash
+    fn parse_pattern_condition(
+        &mut self,
+        lhs: Lit,
+        keyword: Keyword,
+        negated: bool,
+    ) -> Result<Conditions, ParseError> {
+        try!(self.bump());
+        let mut pattern = try!(self.expect_expression(None));
+        // escape is no keyword, so that columns may still be named so
+        let escape = match self.peek {
+            Some(TokenSpan {
+                tok: Token::Word(ref s),
+                ..
+            }) => keyword == Keyword::Like && s.to_lowercase() == "escape",
+            _ => false,
+        };
+        if escape {
+            try!(self.bump());
+            try!(self.bump());
+            let escape = match try!(self.expect_literal()) {
+                Lit::String(ref s) if s.chars().count() == 1 => s.chars().next().unwrap(),
+                _ => return Err(ParseError::DebugError("ESCAPE needs one character".into())),
+            };
+            pattern = match pattern {
+                Lit::String(ref s) => Lit::String(storage_pattern::escape(s, escape)),
+                _ => {
+                    return Err(ParseError::DebugError(
+                        "ESCAPE needs a string pattern".into(),
+                    ))
                 }
-                CondType::Word(try!(self.expect_word(true)))
+            };
+        }
+        let operation = match (keyword, negated) {
+            (Keyword::Like, false) => CompType::Like,
+            (Keyword::Like, true) => CompType::NLike,
+            (_, false) => CompType::Regexp,
+            (_, true) => CompType::NRegexp,
+        };
+        Ok(comparison(lhs, operation, pattern))
+    }
+    // parses the rest of the predicate lhs [NOT] IN (value, ...) or
+    // lhs [NOT] IN (SELECT ...)
+    fn parse_in_condition(&mut self, lhs: Lit, negated: bool) -> Result<Conditions, ParseError> {
+        try!(self.bump());
+        try!(self.expect_token(&[Token::ParenOp]));
+        try!(self.bump());
+        if self.expect_keyword(&[Keyword::Select]).is_ok() {
+            let subquery = try!(self.parse_subquery());
+            return Ok(Conditions::In(lhs, vec![subquery], negated));
+        }
+        let mut values = Vec::new();
+        loop {
+            values.push(try!(self.expect_expression(None)));
+            try!(self.bump());
+            if self.expect_token(&[Token::Comma]).is_err() {
+                break;
             }
-            _ => CondType::Literal(try!(self.expect_literal())),
+            try!(self.bump());
+        }
+        try!(self.expect_token(&[Token::ParenCl]).map_err(|e| match e {
+            ParseError::WrongToken(span) => ParseError::MissingParenthesis(span),
+            _ => e,
+        }));
+        Ok(Conditions::In(lhs, values, negated))
+    }
+    // parses the rest of the predicate EXISTS (SELECT ...), a NOT in front is
+    // pushed down by parse_negation
+    fn parse_exists_condition(&mut self) -> Result<Conditions, ParseError> {
+        try!(self.bump());
+        try!(self.expect_token(&[Token::ParenOp]));
+        try!(self.bump());
+        try!(self.expect_keyword(&[Keyword::Select]));
+        match try!(self.parse_subquery()) {
+            Lit::Subquery(stmt) => Ok(Conditions::Exists(stmt, false)),
+            _ => Err(ParseError::UnknownError),
+        }
+    }
+    // parses a select statement in parentheses, starting on its SELECT and
+    // ending on the closing parenthesis
+    fn parse_subquery(&mut self) -> Result<Lit, ParseError> {
+        let stmt = try!(self.parse_select_stmt());
+        // the statement ends on its last token or the one after it
+        if self.expect_token(&[Token::ParenCl]).is_err() {
+            try!(self.bump());
+        }
+        try!(self.expect_token(&[Token::ParenCl]).map_err(|e| match e {
+            ParseError::WrongToken(span) => ParseError::MissingParenthesis(span),
+            _ => e,
+        }));
+        Ok(Lit::Subquery(Box::new(stmt)))
+    }
+    // parses the rest of the predicate IS [NOT] NULL
+    fn parse_null_condition(&mut self) -> Result<CompType, ParseError> {
+        try!(self.bump());
+        let mut operation = CompType::IsNull;
+        if self.expect_keyword(&[Keyword::Not]).is_ok() {
+            operation = CompType::IsNotNull;
+            try!(self.bump());
+        }
+        try!(self.expect_keyword(&[Keyword::Null]));
+        Ok(operation)
+    }
+    // parses the full text predicate MATCH(column, 'term')
+    fn parse_match_condition(&mut self) -> Result<Condition, ParseError> {
+        try!(self.bump());
+        try!(self.expect_token(&[Token::ParenOp]));
+        try!(self.bump());
+        let mut alias = None;
+        if self.check_next_token(&[Token::Dot]) {
+            alias = Some(try!(self.expect_word(false)));
+            try!(self.bump());
+            try!(self.bump());
         };
+        let columnname = try!(self.expect_word(true));
+        try!(self.bump());
+        try!(self.expect_token(&[Token::Comma]));
+        try!(self.bump());
+        let term = try!(self.expect_literal());
+        try!(self.bump());
+        try!(self.expect_token(&[Token::ParenCl]).map_err(|e| match e {
+            ParseError::WrongToken(span) => ParseError::MissingParenthesis(span),
+            _ => e,
+        }));
         Ok(Condition {
             aliascol: alias,
             col: columnname,
-            op: operation,
-            aliasrhs: rhsalias,
-            rhs: rhs,
+            op: CompType::Match,
+            aliasrhs: None,
+            rhs: CondType::Literal(term),
         })
     }
+
     // Utility function to parse metadata of columns
     fn expect_column_info(&mut self) -> Result<ColumnInfo, ParseError> {
         let column_id = try!(self.expect_word(true));
@@ -763,6 +2143,8 @@ impl<'a> Parser<'a> {
         let mut auto_increment = false;
         let mut not_null = false;
         let mut comment = None;
+        let mut default = None;
+        let mut check = None;
 
         while self.peek.is_some() && !self.check_next_token(&[Token::ParenCl, Token::Comma]) {
             if self.check_next_keyword(&[Keyword::Primary]) {
@@ -790,6 +2172,14 @@ impl<'a> Parser<'a> {
                     Lit::String(s) => s,
                     _ => return Err(ParseError::CommentIsNoString),
                 })
+            } else if self.check_next_keyword(&[Keyword::Default]) {
+                try!(self.bump());
+                try!(self.expect_keyword(&[Keyword::Default]));
+                try!(self.bump());
+                default = Some(try!(self.expect_literal()));
+            } else if self.check_next_keyword(&[Keyword::Check]) {
+                try!(self.bump());
+                check = Some(try!(self.parse_check_constraint()));
             } else {
                 break;
             }
@@ -802,8 +2192,113 @@ impl<'a> Parser<'a> {
             auto_increment: auto_increment,
             not_null: not_null,
             comment: comment,
+            default: default,
+            check: check,
         })
     }
+    // checks if the current token is the given word, which is no keyword
+    fn expect_plain_word(&self, word: &str) -> Result<(), ParseError> {
+        match self.curr {
+            None => Err(ParseError::UnexpectedEoq),
+            Some(ref token) if !is_word(&self.curr, word) => {
+                Err(ParseError::WrongToken(token.span.clone()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // parses a positive number and the unit after it, like 2 HOURS, into
+    // seconds, ends on the unit
+    fn expect_interval(&mut self) -> Result<u64, ParseError> {
+        let count = match (try!(self.expect_number()), &self.curr) {
+            (Lit::Int(count), _) if count > 0 => count as u64,
+            (_, &Some(ref token)) => return Err(ParseError::NotANumber(token.span.clone())),
+            (_, &None) => return Err(ParseError::UnexpectedEoq),
+        };
+        try!(self.bump());
+        let token = match self.curr {
+            None => return Err(ParseError::UnexpectedEoq),
+            Some(ref token) => token,
+        };
+        let word = match token.tok {
+            Token::Word(ref s) => s.to_lowercase(),
+            _ => String::new(),
+        };
+        let unit = match word.trim_end_matches('s') {
+            "second" => 1,
+            "minute" => 60,
+            "hour" => 60 * 60,
+            "day" => 24 * 60 * 60,
+            "week" => 7 * 24 * 60 * 60,
+            _ => return Err(ParseError::NotAnInterval(token.span.clone())),
+        };
+        Ok(count * unit)
+    }
+
+    // checks if the current token is the name of a storage engine
+    fn expect_engine(&self) -> Result<EngineID, ParseError> {
+        let token = match self.curr {
+            None => return Err(ParseError::UnexpectedEoq),
+            Some(ref token) => token,
+        };
+        let word = match token.tok {
+            Token::Word(ref s) => s.to_lowercase(),
+            _ => String::new(),
+        };
+        match &word[..] {
+            "flatfile" => Ok(EngineID::FlatFile),
+            "bstar" => Ok(EngineID::BStar),
+            "invertedindex" => Ok(EngineID::InvertedIndex),
+            "memory" => Ok(EngineID::Memory),
+            _ => Err(ParseError::NotAnEngine(Span {
+                lo: token.span.lo,
+                hi: token.span.hi,
+            })),
+        }
+    }
+
+    // checks if the current token names a compression, either as a word or
+    // as a string
+    fn expect_compression(&self) -> Result<Compression, ParseError> {
+        let token = match self.curr {
+            None => return Err(ParseError::UnexpectedEoq),
+            Some(ref token) => token,
+        };
+        let compression = match token.tok {
+            Token::Word(ref s) | Token::Literal(Lit::String(ref s)) => Compression::from_name(s),
+            _ => None,
+        };
+        compression.ok_or(ParseError::NotACompression(Span {
+            lo: token.span.lo,
+            hi: token.span.hi,
+        }))
+    }
+
+    // checks if the current token is Y or N, either as a word or as a
+    // string, and returns true for Y
+    fn expect_encryption(&self) -> Result<bool, ParseError> {
+        let token = match self.curr {
+            None => return Err(ParseError::UnexpectedEoq),
+            Some(ref token) => token,
+        };
+        match token.tok {
+            Token::Word(ref s) | Token::Literal(Lit::String(ref s))
+                if s.eq_ignore_ascii_case("y") =>
+            {
+                Ok(true)
+            }
+            Token::Word(ref s) | Token::Literal(Lit::String(ref s))
+                if s.eq_ignore_ascii_case("n") =>
+            {
+                Ok(false)
+            }
+            _ => Err(ParseError::NotAnEncryption(Span {
+                lo: token.span.lo,
+                hi: token.span.hi,
+            })),
+        }
+    }
+
     // checks if the current token is a datatype.
     // In case of e.g. char(x) checks if ( ,x and ) are the following
     // token and if x is correct size.
@@ -836,36 +2331,29 @@ impl<'a> Parser<'a> {
         // checks if token is a correct Datatype
         found_datatype = match &tmp_datatype[..] {
             "int" => SqlType::Int,
+            "bigint" => SqlType::BigInt,
             "bool" => SqlType::Bool,
             "boolean" => SqlType::Bool,
+            "float" | "real" => SqlType::Float,
+            "double" => SqlType::Double,
+            "text" => SqlType::Text,
+            "date" => SqlType::Date,
+            "time" => SqlType::Time,
+            "timestamp" | "datetime" => SqlType::Timestamp,
+            "blob" | "bytea" => SqlType::Blob,
+            "varbinary" => {
+                let length = try!(self.expect_length(u16::max_value() as i64, span_lo, span_hi));
+                SqlType::VarBinary(length as u32)
+            }
+            "decimal" | "numeric" => try!(self.expect_precision(span_lo, span_hi)),
             // checks if char is written in correct sql syntax
             "char" => {
-                try!(self.bump());
-                try!(self.expect_token(&[Token::ParenOp]));
-                try!(self.bump());
-                let length_lit = try!(self.expect_number());
-                try!(self.bump());
-                try!(self.expect_token(&[Token::ParenCl]));
-
-                let length = match length_lit {
-                    Lit::Int(i) => {
-                        if 0 <= i && i <= (u8::max_value() as i64) {
-                            i as u8
-                        } else {
-                            return Err(ParseError::DatatypeMissmatch(Span {
-                                lo: span_lo,
-                                hi: span_hi,
-                            }));
-                        }
-                    }
-                    _ => {
-                        return Err(ParseError::DatatypeMissmatch(Span {
-                            lo: span_lo,
-                            hi: span_hi,
-                        }))
-                    }
-                };
-                SqlType::Char(length)
+                let length = try!(self.expect_length(u8::max_value() as i64, span_lo, span_hi));
+                SqlType::Char(length as u8)
+            }
+            "varchar" => {
+                let length = try!(self.expect_length(u16::max_value() as i64, span_lo, span_hi));
+                SqlType::VarChar(length as u16)
             }
             _ => {
                 return Err(ParseError::NotADatatype(Span {
@@ -876,6 +2364,59 @@ impl<'a> Parser<'a> {
         };
         Ok(found_datatype)
     }
+    // parses the length (x) of a datatype, which must not exceed max
+    fn expect_length(
+        &mut self,
+        max: i64,
+        span_lo: usize,
+        span_hi: usize,
+    ) -> Result<i64, ParseError> {
+        try!(self.bump());
+        try!(self.expect_token(&[Token::ParenOp]));
+        try!(self.bump());
+        let length_lit = try!(self.expect_number());
+        try!(self.bump());
+        try!(self.expect_token(&[Token::ParenCl]));
+
+        match length_lit {
+            Lit::Int(i) if 0 <= i && i <= max => Ok(i),
+            _ => Err(ParseError::DatatypeMissmatch(Span {
+                lo: span_lo,
+                hi: span_hi,
+            })),
+        }
+    }
+    // parses the optional (precision[, scale]) of a decimal, which default
+    // to (10, 0)
+    fn expect_precision(&mut self, span_lo: usize, span_hi: usize) -> Result<SqlType, ParseError> {
+        if !self.check_next_token(&[Token::ParenOp]) {
+            return Ok(SqlType::Decimal(10, 0));
+        }
+        try!(self.bump());
+        try!(self.bump());
+        let precision = try!(self.expect_number());
+        let scale = if self.check_next_token(&[Token::Comma]) {
+            try!(self.bump());
+            try!(self.bump());
+            try!(self.expect_number())
+        } else {
+            Lit::Int(0)
+        };
+        try!(self.bump());
+        try!(self.expect_token(&[Token::ParenCl]));
+
+        match (precision, scale) {
+            (Lit::Int(p), Lit::Int(s))
+                if 1 <= p && p <= decimal::MAX_PRECISION as i64 && 0 <= s && s <= p =>
+            {
+                Ok(SqlType::Decimal(p as u8, s as u8))
+            }
+            _ => Err(ParseError::DatatypeMissmatch(Span {
+                lo: span_lo,
+                hi: span_hi,
+            })),
+        }
+    }
     // checks if the current token is a word
     fn expect_word(&self, allowkeyword: bool) -> Result<String, ParseError> {
         let found_word;
@@ -893,6 +2434,8 @@ impl<'a> Parser<'a> {
             // checks whether token is a word
             found_word = match token.tok {
                 Token::Word(ref s) => s,
+                // quoted names may be keywords
+                Token::QuotedWord(ref s) => return Ok(s.clone()),
                 _ => {
                     return Err(ParseError::NotAWord(Span {
                         lo: span_lo,
@@ -911,8 +2454,113 @@ impl<'a> Parser<'a> {
         }
     }
 
-    // checks if the current token is a word
-    fn expect_literal(&self) -> Result<Lit, ParseError> {
+    // checks if the current token is a literal: a constant, a typed
+    // constant like DATE '2024-01-01' or a function call like NOW()
+    fn expect_literal(&mut self) -> Result<Lit, ParseError> {
+        let first = try!(self.expect_operand());
+        self.expect_sum(first)
+    }
+    // parses an expression whose operands may also be columns of the row,
+    // like price * qty, `first` is its first operand if it is parsed already
+    fn expect_expression(&mut self, first: Option<Lit>) -> Result<Lit, ParseError> {
+        let columns = self.columns;
+        self.columns = true;
+        let result = match first {
+            Some(first) => self.expect_sum(first),
+            None => self.expect_literal(),
+        };
+        self.columns = columns;
+        result
+    }
+    // parses the rest of a sum like 1 + 2 or of a concatenation like
+    // 'a' || 'b' after its first operand
+    fn expect_sum(&mut self, first: Lit) -> Result<Lit, ParseError> {
+        let mut lit = try!(self.expect_product_from(first));
+        loop {
+            let op = if self.check_next_token(&[Token::Add]) {
+                "+"
+            } else if self.check_next_token(&[Token::Sub]) {
+                "-"
+            } else if self.check_next_token(&[Token::Concat]) {
+                "||"
+            } else {
+                return Ok(lit);
+            };
+            try!(self.bump());
+            try!(self.bump());
+            let rhs = try!(self.expect_product());
+            lit = Lit::Call(op.to_string(), vec![lit, rhs]);
+        }
+    }
+    // parses a product like 2 * 1.5, evaluated by the executor
+    fn expect_product(&mut self) -> Result<Lit, ParseError> {
+        let first = try!(self.expect_operand());
+        self.expect_product_from(first)
+    }
+    fn expect_product_from(&mut self, first: Lit) -> Result<Lit, ParseError> {
+        let mut lit = first;
+        loop {
+            let op = if self.check_next_token(&[Token::Star]) {
+                "*"
+            } else if self.check_next_token(&[Token::Div]) {
+                "/"
+            } else if self.check_next_token(&[Token::Mod]) {
+                "%"
+            } else {
+                return Ok(lit);
+            };
+            try!(self.bump());
+            try!(self.bump());
+            let rhs = try!(self.expect_operand());
+            lit = Lit::Call(op.to_string(), vec![lit, rhs]);
+        }
+    }
+    // checks if the current token is a single literal, a negated one or a
+    // parenthesized expression
+    fn expect_operand(&mut self) -> Result<Lit, ParseError> {
+        if self.expect_token(&[Token::Sub]).is_ok() {
+            try!(self.bump());
+            return Ok(match try!(self.expect_operand()) {
+                Lit::Int(i) => Lit::Int(-i),
+                Lit::Decimal(d, scale) => Lit::Decimal(-d, scale),
+                Lit::Float(f) => Lit::Float(-f),
+                lit => Lit::Call("-".to_string(), vec![Lit::Int(0), lit]),
+            });
+        }
+        if self.expect_token(&[Token::ParenOp]).is_ok() {
+            try!(self.bump());
+            if self.columns && self.expect_keyword(&[Keyword::Select]).is_ok() {
+                return self.parse_subquery();
+            }
+            let lit = try!(self.expect_literal());
+            try!(self.bump());
+            try!(self.expect_token(&[Token::ParenCl]).map_err(|e| match e {
+                ParseError::WrongToken(span) => ParseError::MissingParenthesis(span),
+                _ => e,
+            }));
+            return Ok(lit);
+        }
+        let word = match self.curr {
+            Some(TokenSpan {
+                tok: Token::Word(ref s),
+                ..
+            }) => Some(s.to_lowercase()),
+            _ => None,
+        };
+        if let Some(word) = word {
+            if self.check_next_token(&[Token::ParenOp]) {
+                return self.parse_call(word);
+            }
+            if self.check_next_string() {
+                return self.parse_typed_literal(&word);
+            }
+        }
+        if self.columns && self.check_next_token(&[Token::Dot]) {
+            let alias = try!(self.expect_word(false));
+            try!(self.bump());
+            try!(self.bump());
+            return Ok(Lit::Column(Some(alias), try!(self.expect_word(true))));
+        }
         let found_lit;
         let span_lo;
         let span_hi;
@@ -932,6 +2580,10 @@ impl<'a> Parser<'a> {
                         Lit::Bool(1)
                     } else if s.to_lowercase() == "false" {
                         Lit::Bool(0)
+                    } else if s.to_lowercase() == "null" {
+                        Lit::Null
+                    } else if self.columns {
+                        Lit::Column(None, s.clone())
                     } else {
                         return Err(ParseError::NotALiteral(Span {
                             lo: span_lo,
@@ -939,6 +2591,7 @@ impl<'a> Parser<'a> {
                         }));
                     }
                 }
+                Token::QuotedWord(ref s) if self.columns => Lit::Column(None, s.clone()),
                 Token::Literal(ref s) => s.clone(),
                 _ => {
                     return Err(ParseError::NotALiteral(Span {
@@ -950,6 +2603,63 @@ impl<'a> Parser<'a> {
         }
         Ok(found_lit)
     }
+    // parses the arguments of a function call name(arg, ...)
+    fn parse_call(&mut self, name: String) -> Result<Lit, ParseError> {
+        try!(self.bump());
+        let mut args = Vec::new();
+        if !self.check_next_token(&[Token::ParenCl]) {
+            loop {
+                try!(self.bump());
+                args.push(try!(self.expect_literal()));
+                if !self.check_next_token(&[Token::Comma]) {
+                    break;
+                }
+                try!(self.bump());
+            }
+        }
+        try!(self.bump());
+        try!(self.expect_token(&[Token::ParenCl]).map_err(|e| match e {
+            ParseError::WrongToken(span) => ParseError::MissingParenthesis(span),
+            _ => e,
+        }));
+        Ok(Lit::Call(name, args))
+    }
+    // parses the string of a typed constant like DATE '2024-01-01'
+    fn parse_typed_literal(&mut self, type_name: &str) -> Result<Lit, ParseError> {
+        let span_lo = match self.curr {
+            Some(ref token) => token.span.lo,
+            None => return Err(ParseError::UnexpectedEoq),
+        };
+        try!(self.bump());
+        let (text, span_hi) = match self.curr {
+            Some(TokenSpan {
+                tok: Token::Literal(Lit::String(ref s)),
+                ref span,
+            }) => (s.clone(), span.hi),
+            _ => return Err(ParseError::UnexpectedEoq),
+        };
+        let lit = match type_name {
+            "date" => temporal::parse_date(&text).map(Lit::Date),
+            "time" => temporal::parse_time(&text).map(Lit::Time),
+            "timestamp" => temporal::parse_timestamp(&text).map(Lit::Timestamp),
+            "x" => decode_hex(&text).map(Lit::Bytes),
+            _ => None,
+        };
+        lit.ok_or(ParseError::NotALiteral(Span {
+            lo: span_lo,
+            hi: span_hi,
+        }))
+    }
+    // checks if the next token is a string constant
+    fn check_next_string(&self) -> bool {
+        match self.peek {
+            Some(TokenSpan {
+                tok: Token::Literal(Lit::String(_)),
+                ..
+            }) => true,
+            _ => false,
+        }
+    }
     // checks if the current token is a number
     fn expect_number(&self) -> Result<Lit, ParseError> {
         let found_num;
@@ -968,6 +2678,7 @@ impl<'a> Parser<'a> {
             found_num = match token.tok {
                 Token::Literal(Lit::Int(s)) => Lit::Int(s),
                 Token::Literal(Lit::Float(s)) => Lit::Float(s),
+                Token::Literal(Lit::Decimal(d, scale)) => Lit::Decimal(d, scale),
                 _ => {
                     return Err(ParseError::NotANumber(Span {
                         lo: span_lo,
@@ -1043,14 +2754,57 @@ impl<'a> Parser<'a> {
     }
 }
 
+// builds the predicate lhs op rhs, a leaf if the lhs is a column and the rhs
+// a column or a constant
+fn comparison(lhs: Lit, op: CompType, rhs: Lit) -> Conditions {
+    let (alias, column) = match lhs {
+        Lit::Column(alias, column) => (alias, column),
+        lhs => return Conditions::Expr(lhs, op, rhs),
+    };
+    let (rhsalias, rhs) = match rhs {
+        // patterns of columns are compared as values of expressions
+        Lit::Column(..) if op.takes_text() => {
+            return Conditions::Expr(Lit::Column(alias, column), op, rhs)
+        }
+        Lit::Column(rhsalias, name) => (rhsalias, CondType::Word(name)),
+        ref rhs if rhs.columns().is_empty() => (None, CondType::Literal(rhs.clone())),
+        rhs => return Conditions::Expr(Lit::Column(alias, column), op, rhs),
+    };
+    Conditions::Leaf(Condition {
+        aliascol: alias,
+        col: column,
+        op: op,
+        aliasrhs: rhsalias,
+        rhs: rhs,
+    })
+}
+
+/// Returns true if the token is a word that is no keyword, like OVER.
+fn is_word(token: &Option<TokenSpan>, word: &str) -> bool {
+    match *token {
+        Some(TokenSpan {
+            tok: Token::Word(ref s),
+            ..
+        }) => s.eq_ignore_ascii_case(word),
+        _ => false,
+    }
+}
+
+/// Returns true if a word is a keyword, which is a name only when quoted.
+pub fn is_keyword(word: &str) -> bool {
+    keyword_from_string(word).is_some()
+}
+
 fn keyword_from_string(string: &str) -> Option<Keyword> {
     let tmp = string.to_lowercase();
     match &tmp[..] {
         "create" => Some(Keyword::Create),
         "drop" => Some(Keyword::Drop),
         "table" => Some(Keyword::Table),
+        "temporary" => Some(Keyword::Temporary),
         "view" => Some(Keyword::View),
         "alter" => Some(Keyword::Alter),
+        "truncate" => Some(Keyword::Truncate),
         "update" => Some(Keyword::Update),
         "select" => Some(Keyword::Select),
         "insert" => Some(Keyword::Insert),
@@ -1060,11 +2814,14 @@ fn keyword_from_string(string: &str) -> Option<Keyword> {
         "column" => Some(Keyword::Column),
         "database" => Some(Keyword::Database),
         "into" => Some(Keyword::Into),
+        "ignore" => Some(Keyword::Ignore),
+        "duplicate" => Some(Keyword::Duplicate),
         "use" => Some(Keyword::Use),
         "values" => Some(Keyword::Values),
         "from" => Some(Keyword::From),
         "where" => Some(Keyword::Where),
         "describe" => Some(Keyword::Describe),
+        "explain" => Some(Keyword::Explain),
         "and" => Some(Keyword::And),
         "or" => Some(Keyword::Or),
         "set" => Some(Keyword::Set),
@@ -1083,6 +2840,66 @@ fn keyword_from_string(string: &str) -> Option<Keyword> {
         "not" => Some(Keyword::Not),
         "null" => Some(Keyword::Null),
         "comment" => Some(Keyword::Comment),
+        "default" => Some(Keyword::Default),
+        "unique" => Some(Keyword::Unique),
+        "foreign" => Some(Keyword::Foreign),
+        "references" => Some(Keyword::References),
+        "restrict" => Some(Keyword::Restrict),
+        "cascade" => Some(Keyword::Cascade),
+        "check" => Some(Keyword::Check),
+        "user" => Some(Keyword::User),
+        "identified" => Some(Keyword::Identified),
+        "password" => Some(Keyword::Password),
+        "grant" => Some(Keyword::Grant),
+        "revoke" => Some(Keyword::Revoke),
+        "show" => Some(Keyword::Show),
+        "grants" => Some(Keyword::Grants),
+        "on" => Some(Keyword::On),
+        "to" => Some(Keyword::To),
+        "for" => Some(Keyword::For),
+        "all" => Some(Keyword::All),
+        "processlist" => Some(Keyword::Processlist),
+        "status" => Some(Keyword::Status),
+        "stats" => Some(Keyword::Stats),
+        "databases" => Some(Keyword::Databases),
+        "tables" => Some(Keyword::Tables),
+        "columns" => Some(Keyword::Columns),
+        "engines" => Some(Keyword::Engines),
+        "notify" => Some(Keyword::Notify),
+        "vacuum" => Some(Keyword::Vacuum),
+        "analyze" => Some(Keyword::Analyze),
+        "repair" => Some(Keyword::Repair),
+        "backup" => Some(Keyword::Backup),
+        "restore" => Some(Keyword::Restore),
+        "dump" => Some(Keyword::Dump),
+        "copy" => Some(Keyword::Copy),
+        "load" => Some(Keyword::Load),
+        "rename" => Some(Keyword::Rename),
+        "if" => Some(Keyword::If),
+        "exists" => Some(Keyword::Exists),
+        "force" => Some(Keyword::Force),
+        "engine" => Some(Keyword::Engine),
+        "compression" => Some(Keyword::Compression),
+        "encryption" => Some(Keyword::Encryption),
+        "match" => Some(Keyword::Match),
+        "is" => Some(Keyword::Is),
+        "between" => Some(Keyword::Between),
+        "like" => Some(Keyword::Like),
+        "regexp" => Some(Keyword::Regexp),
+        "in" => Some(Keyword::In),
+        "union" => Some(Keyword::Union),
+        "intersect" => Some(Keyword::Intersect),
+        "except" => Some(Keyword::Except),
+        "returning" => Some(Keyword::Returning),
+        "join" => Some(Keyword::Join),
+        "inner" => Some(Keyword::Inner),
+        "index" => Some(Keyword::Index),
+        "begin" => Some(Keyword::Begin),
+        "start" => Some(Keyword::Start),
+        "transaction" => Some(Keyword::Transaction),
+        "work" => Some(Keyword::Work),
+        "commit" => Some(Keyword::Commit),
+        "rollback" => Some(Keyword::Rollback),
         _ => None,
     }
 }
@@ -1099,8 +2916,29 @@ pub enum Keyword {
     Create,
     Drop,
     Alter,
+    Truncate,
     Use,
     Describe,
+    Explain,
+    Show,
+    Notify,
+    Vacuum,
+    Analyze,
+    Repair,
+    Backup,
+    Restore,
+    Dump,
+    Copy,
+    Load,
+    Rename,
+    // transaction control keywords
+    Begin,
+    Start,
+    Commit,
+    Rollback,
+    // data control keywords
+    Grant,
+    Revoke,
     // data manipulation keywords
     Select,
     Update,
@@ -1109,9 +2947,25 @@ pub enum Keyword {
     Set,
     // 2nd level keywords
     Table,
+    Temporary,
     Database,
     View,
     Column,
+    User,
+    Grants,
+    Processlist,
+    Status,
+    Stats,
+    Databases,
+    Tables,
+    Columns,
+    Engines,
+    Index,
+    Transaction,
+    Work,
+    If,
+    Exists,
+    Force,
     // 3rd level keywords
     From,
     Where,
@@ -1122,6 +2976,8 @@ pub enum Keyword {
     Modify,
     Add,
     Into,
+    Ignore,
+    Duplicate,
     Values,
     And,
     Or,
@@ -1136,6 +2992,34 @@ pub enum Keyword {
     Not,
     Null,
     Comment,
+    Default,
+    Unique,
+    Foreign,
+    References,
+    Restrict,
+    Cascade,
+    Check,
+    Identified,
+    Password,
+    On,
+    To,
+    For,
+    All,
+    Engine,
+    Compression,
+    Encryption,
+    Match,
+    Is,
+    Between,
+    Like,
+    Regexp,
+    In,
+    Join,
+    Inner,
+    Union,
+    Intersect,
+    Except,
+    Returning,
 }
 
 #[derive(Debug, PartialEq)]
@@ -1156,17 +3040,157 @@ pub enum ParseError {
     NotAToken(Span),
     NotAWord(Span),
     NotADatatype(Span),
+    NotAnEngine(Span),
+    NotACompression(Span),
+    NotAnEncryption(Span),
     NotANumber(Span),
     NotALiteral(Span),
+    NotAWindowFunction(Span),
+    NotAnInterval(Span),
     ColumnCountMissmatch,
     MissingParenthesis(Span),
     LimitError,
     ReservedKeyword(Span),
     CommentIsNoString,
+    PasswordIsNoString,
+    PayloadIsNoString,
+    PathIsNoString,
+    BodyIsNoString,
+    /// The syntax errors of several statements of a script, in their order
+    Several(Vec<ParseError>),
     //Used for debugging
     DebugError(String), // TODO: introduce good errors and think more about it
 }
 
+impl ParseError {
+    /// Returns the part of the query that caused the error, if known.
+    pub fn span(&self) -> Option<&Span> {
+        match self {
+            &ParseError::WrongKeyword(ref s)
+            | &ParseError::WrongToken(ref s)
+            | &ParseError::DatatypeMissmatch(ref s)
+            | &ParseError::NotAKeyword(ref s)
+            | &ParseError::NotAToken(ref s)
+            | &ParseError::NotAWord(ref s)
+            | &ParseError::NotADatatype(ref s)
+            | &ParseError::NotAnEngine(ref s)
+            | &ParseError::NotACompression(ref s)
+            | &ParseError::NotAnEncryption(ref s)
+            | &ParseError::NotANumber(ref s)
+            | &ParseError::NotALiteral(ref s)
+            | &ParseError::NotAWindowFunction(ref s)
+            | &ParseError::NotAnInterval(ref s)
+            | &ParseError::MissingParenthesis(ref s)
+            | &ParseError::ReservedKeyword(ref s) => Some(s),
+            &ParseError::LexError(ref e) => Some(e.span()),
+            &ParseError::Several(ref errors) => errors[0].span(),
+            _ => None,
+        }
+    }
+
+    /// Returns a suggestion how to fix the query, if there is one.
+    pub fn hint(&self) -> Option<&'static str> {
+        match self {
+            &ParseError::LexError(lex::LexError::UnclosedQuotationmark(_)) => {
+                Some("close the string or name with a matching quotation mark")
+            }
+            &ParseError::LexError(lex::LexError::UnclosedComment(_)) => {
+                Some("end the comment with */")
+            }
+            &ParseError::LexError(lex::LexError::UnknownEscape(_)) => {
+                Some("write a backslash in a string as \\\\")
+            }
+            &ParseError::LexError(lex::LexError::InvalidNameChar(_)) => {
+                Some("names cannot contain dots, slashes or control characters")
+            }
+            &ParseError::UnexpectedEoq => Some("the query is incomplete"),
+            &ParseError::DatatypeMissmatch(_) => {
+                Some("the length of a char column must be between 0 and 255")
+            }
+            &ParseError::NotADatatype(_) => Some("supported types are int, bool and char(n)"),
+            &ParseError::NotAnEngine(_) => {
+                Some("supported engines are flatfile, bstar, invertedindex and memory")
+            }
+            &ParseError::NotACompression(_) => {
+                Some("supported compressions are none, lz4 and zstd")
+            }
+            &ParseError::NotAnEncryption(_) => {
+                Some("a table is encrypted with 'Y' or not with 'N'")
+            }
+            &ParseError::NotAWindowFunction(_) => {
+                Some("supported window functions are row_number(), rank(), sum(x) and avg(x)")
+            }
+            &ParseError::NotAnInterval(_) => {
+                Some("supported units are seconds, minutes, hours, days and weeks")
+            }
+            &ParseError::MissingParenthesis(_) => Some("every '(' needs a matching ')'"),
+            &ParseError::ReservedKeyword(_) => {
+                Some("quote keywords used as names with double quotes or backticks")
+            }
+            &ParseError::ColumnCountMissmatch => {
+                Some("specify as many values as there are columns")
+            }
+            &ParseError::Several(ref errors) => errors[0].hint(),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let msg = match self {
+            &ParseError::LexError(lex::LexError::UnclosedQuotationmark(_)) => {
+                "unclosed quotation mark"
+            }
+            &ParseError::LexError(lex::LexError::UnknownEscape(_)) => "unknown escape sequence",
+            &ParseError::LexError(lex::LexError::EmptyName(_)) => "empty name",
+            &ParseError::LexError(lex::LexError::UnclosedComment(_)) => "unclosed comment",
+            &ParseError::LexError(lex::LexError::InvalidNameChar(_)) => "invalid character in name",
+            &ParseError::UnknownError => "unknown error",
+            &ParseError::EmptyQueryError => "empty query",
+            &ParseError::UnexpectedEoq => "unexpected end of query",
+            &ParseError::InvalidEoq => "expected end of query",
+            &ParseError::WrongKeyword(_) => "unexpected keyword",
+            &ParseError::WrongToken(_) => "unexpected token",
+            &ParseError::DatatypeMissmatch(_) => "invalid datatype",
+            &ParseError::NotAKeyword(_) => "expected a keyword",
+            &ParseError::NotAToken(_) => "expected a token",
+            &ParseError::NotAWord(_) => "expected a name",
+            &ParseError::NotADatatype(_) => "unknown datatype",
+            &ParseError::NotAnEngine(_) => "unknown storage engine",
+            &ParseError::NotACompression(_) => "unknown compression",
+            &ParseError::NotAnEncryption(_) => "expected 'Y' or 'N'",
+            &ParseError::NotANumber(_) => "expected a number",
+            &ParseError::NotALiteral(_) => "expected a value",
+            &ParseError::NotAWindowFunction(_) => "unknown window function",
+            &ParseError::NotAnInterval(_) => "unknown unit of time",
+            &ParseError::ColumnCountMissmatch => "number of columns and values differ",
+            &ParseError::MissingParenthesis(_) => "missing parenthesis",
+            &ParseError::LimitError => "limit expects integer values",
+            &ParseError::ReservedKeyword(_) => "reserved keyword",
+            &ParseError::CommentIsNoString => "comment must be a string",
+            &ParseError::PasswordIsNoString => "password must be a string",
+            &ParseError::PayloadIsNoString => "notification payload must be a string",
+            &ParseError::PathIsNoString => "backup path must be a string",
+            &ParseError::BodyIsNoString => "function body must be a string",
+            &ParseError::Several(ref errors) => return errors[0].fmt(f),
+            &ParseError::DebugError(ref s) => s,
+        };
+        msg.fmt(f)
+    }
+}
+
+/// Decodes pairs of hex digits into bytes.
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if s.len() % 2 != 0 || !s.is_ascii() {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).ok())
+        .collect()
+}
+
 impl From<lex::LexError> for ParseError {
     fn from(error: lex::LexError) -> ParseError {
         ParseError::LexError(error)
@@ -1,6 +1,6 @@
-use super::super::storage::SqlType;
+use super::super::storage::{Compression, EngineID, Privilege, ReferentialAction, SqlType};
 use super::ast::*;
-use super::lex::Lexer;
+use super::lex::{LexError, Lexer};
 use super::parser;
 use super::token::Token;
 use super::token::{Lit, TokenSpan};
@@ -23,7 +23,14 @@ fn test_create_table_empty() {
         Ok(Query::DefStmt(DefStmt::Create(CreateStmt::Table(
             CreateTableStmt {
                 tid: "foo".to_string(),
-                cols: Vec::<ColumnInfo>::new()
+                cols: Vec::<ColumnInfo>::new(),
+                engine: EngineID::FlatFile,
+                compression: Compression::None,
+                encrypted: false,
+                temporary: false,
+                uniques: Vec::new(),
+                foreign_keys: Vec::new(),
+                checks: Vec::new(),
             }
         ))))
     );
@@ -42,6 +49,8 @@ fn test_create_table_content() {
             auto_increment: false,
             not_null: false,
             comment: None,
+            default: None,
+            check: None,
         },
         ColumnInfo {
             cid: "LastName".to_string(),
@@ -50,6 +59,8 @@ fn test_create_table_content() {
             auto_increment: false,
             not_null: false,
             comment: None,
+            default: None,
+            check: None,
         },
     ];
 
@@ -57,7 +68,14 @@ fn test_create_table_content() {
         p.parse().unwrap(),
         Query::DefStmt(DefStmt::Create(CreateStmt::Table(CreateTableStmt {
             tid: "foo".to_string(),
-            cols: vec
+            cols: vec,
+            engine: EngineID::FlatFile,
+            compression: Compression::None,
+            encrypted: false,
+            temporary: false,
+            uniques: Vec::new(),
+            foreign_keys: Vec::new(),
+            checks: Vec::new(),
         })))
     )
 }
@@ -76,6 +94,8 @@ fn test_create_table_content_primary() {
             auto_increment: false,
             not_null: false,
             comment: None,
+            default: None,
+            check: None,
         },
         ColumnInfo {
             cid: "LastName".to_string(),
@@ -84,6 +104,8 @@ fn test_create_table_content_primary() {
             auto_increment: false,
             not_null: false,
             comment: None,
+            default: None,
+            check: None,
         },
     ];
 
@@ -91,7 +113,14 @@ fn test_create_table_content_primary() {
         p.parse().unwrap(),
         Query::DefStmt(DefStmt::Create(CreateStmt::Table(CreateTableStmt {
             tid: "foo".to_string(),
-            cols: vec
+            cols: vec,
+            engine: EngineID::FlatFile,
+            compression: Compression::None,
+            encrypted: false,
+            temporary: false,
+            uniques: Vec::new(),
+            foreign_keys: Vec::new(),
+            checks: Vec::new(),
         })))
     )
 }
@@ -110,17 +139,307 @@ fn test_create_table_full() {
         auto_increment: true,
         not_null: true,
         comment: Some("TEST".to_string()),
+        default: None,
+        check: None,
     }];
 
     assert_eq!(
         p.parse().unwrap(),
         Query::DefStmt(DefStmt::Create(CreateStmt::Table(CreateTableStmt {
             tid: "foo".to_string(),
-            cols: vec
+            cols: vec,
+            engine: EngineID::FlatFile,
+            compression: Compression::None,
+            encrypted: false,
+            temporary: false,
+            uniques: Vec::new(),
+            foreign_keys: Vec::new(),
+            checks: Vec::new(),
         })))
     )
 }
 
+#[test]
+fn test_create_table_default() {
+    let mut p = parser::Parser::create(
+        "create table foo (n int not null default 1, at timestamp default now())",
+    );
+
+    let vec = vec![
+        ColumnInfo {
+            cid: "n".to_string(),
+            datatype: SqlType::Int,
+            primary: false,
+            auto_increment: false,
+            not_null: true,
+            comment: None,
+            default: Some(Lit::Int(1)),
+            check: None,
+        },
+        ColumnInfo {
+            cid: "at".to_string(),
+            datatype: SqlType::Timestamp,
+            primary: false,
+            auto_increment: false,
+            not_null: false,
+            comment: None,
+            default: Some(Lit::Call("now".to_string(), Vec::new())),
+            check: None,
+        },
+    ];
+
+    assert_eq!(
+        p.parse().unwrap(),
+        Query::DefStmt(DefStmt::Create(CreateStmt::Table(CreateTableStmt {
+            tid: "foo".to_string(),
+            cols: vec,
+            engine: EngineID::FlatFile,
+            compression: Compression::None,
+            encrypted: false,
+            temporary: false,
+            uniques: Vec::new(),
+            foreign_keys: Vec::new(),
+            checks: Vec::new(),
+        })))
+    )
+}
+
+#[test]
+fn test_create_table_unique() {
+    let mut p = parser::Parser::create(
+        "create table foo (a int, unique (a), b int, UNIQUE(a, b)) engine = bstar",
+    );
+
+    match p.parse().unwrap() {
+        Query::DefStmt(DefStmt::Create(CreateStmt::Table(t))) => {
+            assert_eq!(t.cols.len(), 2);
+            assert_eq!(t.cols[1].cid, "b");
+            assert_eq!(
+                t.uniques,
+                vec![
+                    vec!["a".to_string()],
+                    vec!["a".to_string(), "b".to_string()],
+                ]
+            );
+            assert_eq!(t.engine, EngineID::BStar);
+        }
+        q => panic!("unexpected query {:?}", q),
+    }
+
+    let mut p = parser::Parser::create("create table foo (a int, unique ())");
+    assert!(p.parse().is_err());
+}
+
+#[test]
+fn test_create_table_foreign_key() {
+    let mut p = parser::Parser::create(
+        "create table foo (a int, b int, foreign key (a) references bar, \
+         FOREIGN KEY (a, b) REFERENCES baz (x, y) ON DELETE SET NULL, \
+         foreign key (b) references bar (id) on delete cascade)",
+    );
+
+    match p.parse().unwrap() {
+        Query::DefStmt(DefStmt::Create(CreateStmt::Table(t))) => {
+            assert_eq!(t.cols.len(), 2);
+            assert_eq!(
+                t.foreign_keys,
+                vec![
+                    ForeignKeyInfo {
+                        cols: vec!["a".to_string()],
+                        ref_tid: "bar".to_string(),
+                        ref_cols: Vec::new(),
+                        on_delete: ReferentialAction::Restrict,
+                    },
+                    ForeignKeyInfo {
+                        cols: vec!["a".to_string(), "b".to_string()],
+                        ref_tid: "baz".to_string(),
+                        ref_cols: vec!["x".to_string(), "y".to_string()],
+                        on_delete: ReferentialAction::SetNull,
+                    },
+                    ForeignKeyInfo {
+                        cols: vec!["b".to_string()],
+                        ref_tid: "bar".to_string(),
+                        ref_cols: vec!["id".to_string()],
+                        on_delete: ReferentialAction::Cascade,
+                    },
+                ]
+            );
+        }
+        q => panic!("unexpected query {:?}", q),
+    }
+
+    let mut p = parser::Parser::create(
+        "create table foo (a int, foreign key (a) references bar on update cascade)",
+    );
+    assert!(p.parse().is_err());
+}
+
+#[test]
+fn test_create_table_check() {
+    let mut p = parser::Parser::create(
+        "create table foo (a int check (a > 0), b int, check ((a < b) or b is null))",
+    );
+
+    match p.parse().unwrap() {
+        Query::DefStmt(DefStmt::Create(CreateStmt::Table(t))) => {
+            assert_eq!(t.cols.len(), 2);
+            assert_eq!(
+                t.cols[0].check,
+                Some(Conditions::Leaf(Condition {
+                    aliascol: None,
+                    col: "a".to_string(),
+                    op: CompType::GThan,
+                    aliasrhs: None,
+                    rhs: CondType::Literal(Lit::Int(0)),
+                }))
+            );
+            assert_eq!(t.cols[1].check, None);
+            assert_eq!(t.checks.len(), 1);
+            assert!(t.checks[0].uses_column("a"));
+            assert!(t.checks[0].uses_column("b"));
+        }
+        q => panic!("unexpected query {:?}", q),
+    }
+
+    let mut p = parser::Parser::create("create table foo (a int check a > 0)");
+    assert!(p.parse().is_err());
+}
+
+#[test]
+fn test_create_table_engine() {
+    let mut p = parser::Parser::create("create table foo (id int primary key) engine = BStar");
+
+    let vec = vec![ColumnInfo {
+        cid: "id".to_string(),
+        datatype: SqlType::Int,
+        primary: true,
+        auto_increment: false,
+        not_null: false,
+        comment: None,
+        default: None,
+        check: None,
+    }];
+
+    assert_eq!(
+        p.parse().unwrap(),
+        Query::DefStmt(DefStmt::Create(CreateStmt::Table(CreateTableStmt {
+            tid: "foo".to_string(),
+            cols: vec,
+            engine: EngineID::BStar,
+            compression: Compression::None,
+            encrypted: false,
+            temporary: false,
+            uniques: Vec::new(),
+            foreign_keys: Vec::new(),
+            checks: Vec::new(),
+        })))
+    );
+
+    let mut p = parser::Parser::create("create table foo (id int primary key) ENGINE flatfile");
+    match p.parse().unwrap() {
+        Query::DefStmt(DefStmt::Create(CreateStmt::Table(stmt))) => {
+            assert_eq!(stmt.engine, EngineID::FlatFile)
+        }
+        q => panic!("unexpected query {:?}", q),
+    }
+}
+
+#[test]
+fn test_create_table_compression() {
+    let mut p =
+        parser::Parser::create("create table foo (id int) compression = 'LZ4' engine flatfile");
+    match p.parse().unwrap() {
+        Query::DefStmt(DefStmt::Create(CreateStmt::Table(stmt))) => {
+            assert_eq!(stmt.compression, Compression::Lz4);
+            assert_eq!(stmt.engine, EngineID::FlatFile);
+        }
+        q => panic!("unexpected query {:?}", q),
+    }
+
+    let mut p = parser::Parser::create("create table foo (id int) compression zstd");
+    match p.parse().unwrap() {
+        Query::DefStmt(DefStmt::Create(CreateStmt::Table(stmt))) => {
+            assert_eq!(stmt.compression, Compression::Zstd);
+        }
+        q => panic!("unexpected query {:?}", q),
+    }
+
+    let mut p = parser::Parser::create("create table foo (id int) compression brotli");
+    let sol = parser::ParseError::NotACompression(Span { lo: 38, hi: 44 });
+    assert_eq!(p.parse(), Err(sol));
+}
+
+#[test]
+fn test_create_table_encryption() {
+    let mut p =
+        parser::Parser::create("create table foo (id int) encryption = 'Y' compression lz4");
+    match p.parse().unwrap() {
+        Query::DefStmt(DefStmt::Create(CreateStmt::Table(stmt))) => {
+            assert!(stmt.encrypted);
+            assert_eq!(stmt.compression, Compression::Lz4);
+        }
+        q => panic!("unexpected query {:?}", q),
+    }
+
+    let mut p = parser::Parser::create("create table foo (id int) encryption n");
+    match p.parse().unwrap() {
+        Query::DefStmt(DefStmt::Create(CreateStmt::Table(stmt))) => assert!(!stmt.encrypted),
+        q => panic!("unexpected query {:?}", q),
+    }
+
+    let mut p = parser::Parser::create("create table foo (id int) encryption maybe");
+    let sol = parser::ParseError::NotAnEncryption(Span { lo: 37, hi: 42 });
+    assert_eq!(p.parse(), Err(sol));
+}
+
+#[test]
+fn test_create_temporary_table() {
+    let mut p = parser::Parser::create("create temporary table foo (id int primary key)");
+    match p.parse().unwrap() {
+        Query::DefStmt(DefStmt::Create(CreateStmt::Table(stmt))) => {
+            assert!(stmt.temporary);
+            assert_eq!(stmt.engine, EngineID::Memory);
+        }
+        q => panic!("unexpected query {:?}", q),
+    }
+
+    let mut p = parser::Parser::create("create temporary database foo");
+    assert!(p.parse().is_err());
+}
+
+#[test]
+fn test_create_index() {
+    let mut p = parser::Parser::create("create index byname on foo (name)");
+
+    assert_eq!(
+        p.parse().unwrap(),
+        Query::DefStmt(DefStmt::Create(CreateStmt::Index(CreateIndexStmt {
+            name: "byname".to_string(),
+            tid: "foo".to_string(),
+            col: "name".to_string(),
+        })))
+    );
+}
+
+#[test]
+fn test_transaction_stmts() {
+    let begin = Query::TransactionStmt(TransactionStmt::Begin);
+    assert_eq!(parser::Parser::create("begin").parse().unwrap(), begin);
+    assert_eq!(parser::Parser::create("begin work").parse().unwrap(), begin);
+    assert_eq!(
+        parser::Parser::create("start transaction").parse().unwrap(),
+        begin
+    );
+    assert_eq!(
+        parser::Parser::create("commit work").parse().unwrap(),
+        Query::TransactionStmt(TransactionStmt::Commit)
+    );
+    assert_eq!(
+        parser::Parser::create("rollback").parse().unwrap(),
+        Query::TransactionStmt(TransactionStmt::Rollback)
+    );
+}
+
 #[test]
 fn test_create_database() {
     let mut p = parser::Parser::create("create database foo");
@@ -146,11 +465,21 @@ fn test_alter_table_add_column() {
                 auto_increment: false,
                 not_null: false,
                 comment: None,
+                default: None,
+                check: None,
             })
         })))
     );
 }
 
+#[test]
+fn test_alter_table_add_column_keyword() {
+    let mut p = parser::Parser::create("alter table foo add column bar int not null");
+    let mut q = parser::Parser::create("alter table foo add bar int not null");
+
+    assert_eq!(p.parse().unwrap(), q.parse().unwrap());
+}
+
 #[test]
 fn test_alter_table_add_column_primary() {
     let mut p = parser::Parser::create("alter table foo add bar inT primary key");
@@ -166,11 +495,67 @@ fn test_alter_table_add_column_primary() {
                 auto_increment: false,
                 not_null: false,
                 comment: None,
+                default: None,
+                check: None,
             })
         })))
     );
 }
 
+#[test]
+fn test_alter_table_add_column_types() {
+    let types = vec![
+        ("varchar(300)", SqlType::VarChar(300)),
+        ("text", SqlType::Text),
+        ("float", SqlType::Float),
+        ("double", SqlType::Double),
+        ("bigint", SqlType::BigInt),
+        ("date", SqlType::Date),
+        ("time", SqlType::Time),
+        ("datetime", SqlType::Timestamp),
+        ("decimal(12, 2)", SqlType::Decimal(12, 2)),
+        ("numeric(5)", SqlType::Decimal(5, 0)),
+        ("decimal", SqlType::Decimal(10, 0)),
+        ("blob", SqlType::Blob),
+        ("varbinary(16)", SqlType::VarBinary(16)),
+    ];
+    for (name, datatype) in types {
+        let query = format!("alter table foo add bar {}", name);
+        let mut p = parser::Parser::create(&query);
+
+        assert_eq!(
+            p.parse().unwrap(),
+            Query::DefStmt(DefStmt::Alter(AltStmt::Table(AlterTableStmt {
+                tid: "foo".to_string(),
+                op: AlterOp::Add(ColumnInfo {
+                    cid: "bar".to_string(),
+                    datatype: datatype,
+                    primary: false,
+                    auto_increment: false,
+                    not_null: false,
+                    comment: None,
+                    default: None,
+                    check: None,
+                })
+            })))
+        );
+    }
+}
+
+#[test]
+fn err_decimal_scale_too_large() {
+    let mut p = parser::Parser::create("alter table foo add bar decimal(4, 5)");
+
+    assert!(p.parse().is_err());
+}
+
+#[test]
+fn err_varchar_too_long() {
+    let mut p = parser::Parser::create("alter table foo add bar varchar(70000)");
+
+    assert!(p.parse().is_err());
+}
+
 #[test]
 fn test_alter_table_drop_column() {
     let mut p = parser::Parser::create("alter table foo drop column bar");
@@ -202,6 +587,8 @@ fn test_alter_table_modify() {
                 auto_increment: false,
                 not_null: false,
                 comment: None,
+                default: None,
+                check: None,
             })
         })))
     );
@@ -217,13 +604,43 @@ fn test_drop_table() {
     );
 }
 
+#[test]
+fn test_truncate_table() {
+    for query in &["truncate table foo", "TRUNCATE foo"] {
+        let mut p = parser::Parser::create(query);
+
+        assert_eq!(
+            p.parse().unwrap(),
+            Query::DefStmt(DefStmt::Truncate("foo".to_string()))
+        );
+    }
+
+    let mut p = parser::Parser::create("truncate table");
+    assert!(p.parse().is_err());
+}
+
 #[test]
 fn test_drop_database() {
     let mut p = parser::Parser::create("drop database foo");
 
     assert_eq!(
         p.parse().unwrap(),
-        Query::DefStmt(DefStmt::Drop(DropStmt::Database("foo".to_string())))
+        Query::DefStmt(DefStmt::Drop(DropStmt::Database(DropDatabaseStmt {
+            name: "foo".to_string(),
+            if_exists: false,
+            force: false,
+        })))
+    );
+
+    let mut p = parser::Parser::create("drop database if exists foo force");
+
+    assert_eq!(
+        p.parse().unwrap(),
+        Query::DefStmt(DefStmt::Drop(DropStmt::Database(DropDatabaseStmt {
+            name: "foo".to_string(),
+            if_exists: true,
+            force: true,
+        })))
     );
 }
 
@@ -237,6 +654,57 @@ fn test_drop_view() {
     );
 }
 
+#[test]
+fn test_statements_and_comments() {
+    let mut p = parser::Parser::create(
+        "-- it's a script\nuse database foo; /* drop it;\n */ drop table bar;;\n",
+    );
+
+    assert_eq!(
+        p.parse_statements().unwrap(),
+        vec![
+            Query::ManipulationStmt(ManipulationStmt::Use(UseStmt::Database("foo".to_string()))),
+            Query::DefStmt(DefStmt::Drop(DropStmt::Table("bar".to_string()))),
+        ]
+    );
+    assert_eq!(
+        super::split_statements(
+            "-- it's a script\nuse database foo; /* drop it;\n */ drop table bar;;\n"
+        ),
+        vec!["use database foo", "drop table bar"]
+    );
+    assert_eq!(
+        super::split_statements("insert into foo values ('a;b')"),
+        vec!["insert into foo values ('a;b')"]
+    );
+    // a single statement may end with a semicolon, but not be followed by another
+    assert!(parser::Parser::create("drop table foo; -- done")
+        .parse()
+        .is_ok());
+    assert_eq!(
+        parser::Parser::create("drop table foo; drop table bar").parse(),
+        Err(parser::ParseError::InvalidEoq)
+    );
+    assert_eq!(
+        parser::Parser::create(" ; -- nothing").parse_statements(),
+        Err(parser::ParseError::EmptyQueryError)
+    );
+    // the text of a view ends with its statement
+    match parser::Parser::create("create view v as select * from foo -- all\n; drop table foo")
+        .parse_statements()
+        .unwrap()[0]
+    {
+        Query::DefStmt(DefStmt::Create(CreateStmt::View(ref stmt))) => {
+            assert_eq!(stmt.text, "select * from foo")
+        }
+        ref q => panic!("unexpected query {:?}", q),
+    }
+    // a comment from -- on, also without a space
+    assert!(parser::Parser::create("drop table foo --bar")
+        .parse()
+        .is_ok());
+}
+
 #[test]
 fn test_use_database() {
     let mut p = parser::Parser::create("use database foo");
@@ -269,11 +737,12 @@ fn test_insert_1() {
         Query::ManipulationStmt(ManipulationStmt::Insert(InsertStmt {
             tid: "foo".to_string(),
             col: Vec::<String>::new(),
-            val: vec![
+            val: vec![vec![
                 Lit::String("peter".to_string()),
                 Lit::String("pan".to_string()),
                 Lit::Int(3)
-            ],
+            ]],
+            on_duplicate: None,
         }))
     );
 }
@@ -290,11 +759,12 @@ fn test_insert_2() {
         Query::ManipulationStmt(ManipulationStmt::Insert(InsertStmt {
             tid: "foo".to_string(),
             col: Vec::<String>::new(),
-            val: vec![
+            val: vec![vec![
                 Lit::String("peter".to_string()),
                 Lit::String("pan".to_string()),
                 Lit::Int(4)
-            ],
+            ]],
+            on_duplicate: None,
         }))
     );
 }
@@ -311,11 +781,12 @@ fn test_insert_3() {
         Query::ManipulationStmt(ManipulationStmt::Insert(InsertStmt {
             tid: "foo".to_string(),
             col: vec!["eins".to_string(), "zwei".to_string(), "drei".to_string()],
-            val: vec![
+            val: vec![vec![
                 Lit::String("peter".to_string()),
                 Lit::String("pan".to_string()),
                 Lit::Int(5)
-            ],
+            ]],
+            on_duplicate: None,
         }))
     );
 }
@@ -336,32 +807,110 @@ fn test_delete_row() {
                 aliasrhs: None,
                 rhs: CondType::Literal(Lit::String("peter".to_string())),
             })),
+            returning: Vec::new(),
         }))
     );
 }
 
 #[test]
-fn test_delete_full_with_table_alias() {
-    let mut p = parser::Parser::create("delete from foo bar");
-    let mut aliashm = HashMap::new();
-    aliashm.insert("bar".to_string(), "foo".to_string());
+fn test_delete_returning() {
+    let mut p = parser::Parser::create(
+        "delete from foo f where id < 3 or name = 'x' returning f.id, price * 2 as double, *",
+    );
+    let mut alias = HashMap::new();
+    alias.insert("f".to_string(), "foo".to_string());
 
     assert_eq!(
         p.parse().unwrap(),
         Query::ManipulationStmt(ManipulationStmt::Delete(DeleteStmt {
             tid: "foo".to_string(),
-            alias: aliashm,
-            cond: None,
+            alias: alias,
+            cond: Some(Conditions::Or(
+                Box::new(Conditions::Leaf(Condition {
+                    aliascol: None,
+                    col: "id".to_string(),
+                    op: CompType::SThan,
+                    aliasrhs: None,
+                    rhs: CondType::Literal(Lit::Int(3)),
+                })),
+                Box::new(Conditions::Leaf(Condition {
+                    aliascol: None,
+                    col: "name".to_string(),
+                    op: CompType::Equ,
+                    aliasrhs: None,
+                    rhs: CondType::Literal(Lit::String("x".to_string())),
+                })),
+            )),
+            returning: vec![
+                Target {
+                    alias: Some("f".to_string()),
+                    col: Col::Specified("id".to_string()),
+                    rename: None,
+                },
+                Target {
+                    alias: None,
+                    col: Col::Expr(Lit::Call(
+                        "*".into(),
+                        vec![Lit::Column(None, "price".into()), Lit::Int(2)],
+                    )),
+                    rename: Some("double".to_string()),
+                },
+                Target {
+                    alias: None,
+                    col: Col::Every,
+                    rename: None,
+                },
+            ],
         }))
     );
+
+    let mut p = parser::Parser::create("delete from foo returning id");
+    match p.parse().unwrap() {
+        Query::ManipulationStmt(ManipulationStmt::Delete(stmt)) => {
+            assert!(stmt.cond.is_none());
+            assert_eq!(stmt.returning.len(), 1);
+        }
+        _ => panic!("no delete"),
+    }
+    assert!(parser::Parser::create("delete from foo returning")
+        .parse()
+        .is_err());
 }
 
 #[test]
-fn test_select_full_with_table_alias() {
-    let mut p = parser::Parser::create("select * from foo bar");
-    let mut aliashm = HashMap::new();
-    aliashm.insert("bar".to_string(), "foo".to_string());
-    let selected_tables = vec!["foo".to_string()];
+fn test_delete_match() {
+    let mut p =
+        parser::Parser::create("delete from foo where MATCH(body, 'Rust parser') and id > 3");
+
+    assert_eq!(
+        p.parse().unwrap(),
+        Query::ManipulationStmt(ManipulationStmt::Delete(DeleteStmt {
+            tid: "foo".to_string(),
+            alias: HashMap::new(),
+            cond: Some(Conditions::And(
+                Box::new(Conditions::Leaf(Condition {
+                    aliascol: None,
+                    col: "body".to_string(),
+                    op: CompType::Match,
+                    aliasrhs: None,
+                    rhs: CondType::Literal(Lit::String("Rust parser".to_string())),
+                })),
+                Box::new(Conditions::Leaf(Condition {
+                    aliascol: None,
+                    col: "id".to_string(),
+                    op: CompType::GThan,
+                    aliasrhs: None,
+                    rhs: CondType::Literal(Lit::Int(3)),
+                })),
+            )),
+            returning: Vec::new(),
+        }))
+    );
+}
+
+#[test]
+fn test_select_between() {
+    let mut p = parser::Parser::create("select * from foo where id between 3 and 7 and x = 1");
 
     assert_eq!(
         p.parse().unwrap(),
@@ -371,9 +920,35 @@ fn test_select_full_with_table_alias() {
                 col: Col::Every,
                 rename: None,
             }],
-            tid: selected_tables,
-            alias: aliashm,
-            cond: None,
+            tid: vec!["foo".to_string()],
+            joins: Vec::new(),
+            alias: HashMap::new(),
+            names: vec!["foo".to_string()],
+            cond: Some(Conditions::And(
+                Box::new(Conditions::And(
+                    Box::new(Conditions::Leaf(Condition {
+                        aliascol: None,
+                        col: "id".to_string(),
+                        op: CompType::GEThan,
+                        aliasrhs: None,
+                        rhs: CondType::Literal(Lit::Int(3)),
+                    })),
+                    Box::new(Conditions::Leaf(Condition {
+                        aliascol: None,
+                        col: "id".to_string(),
+                        op: CompType::SEThan,
+                        aliasrhs: None,
+                        rhs: CondType::Literal(Lit::Int(7)),
+                    })),
+                )),
+                Box::new(Conditions::Leaf(Condition {
+                    aliascol: None,
+                    col: "x".to_string(),
+                    op: CompType::Equ,
+                    aliasrhs: None,
+                    rhs: CondType::Literal(Lit::Int(1)),
+                })),
+            )),
             spec_op: None,
             order: Vec::new(),
             limit: None,
@@ -382,22 +957,23 @@ fn test_select_full_with_table_alias() {
 }
 
 #[test]
-fn test_select_specific_column() {
-    let mut p = parser::Parser::create("select bar from foo bar");
-    let mut aliashm = HashMap::new();
-    aliashm.insert("bar".to_string(), "foo".to_string());
-    let selected_tables = vec!["foo".to_string()];
+fn test_select_qualified_table() {
+    let mut p = parser::Parser::create("select * from information_schema.tables t");
 
     assert_eq!(
         p.parse().unwrap(),
         Query::ManipulationStmt(ManipulationStmt::Select(SelectStmt {
             target: vec![Target {
                 alias: None,
-                col: Col::Specified("bar".to_string()),
+                col: Col::Every,
                 rename: None,
             }],
-            tid: selected_tables,
-            alias: aliashm,
+            tid: vec!["information_schema.tables".to_string()],
+            joins: Vec::new(),
+            alias: vec![("t".to_string(), "information_schema.tables".to_string())]
+                .into_iter()
+                .collect(),
+            names: vec!["t".to_string()],
             cond: None,
             spec_op: None,
             order: Vec::new(),
@@ -407,578 +983,2181 @@ fn test_select_specific_column() {
 }
 
 #[test]
-fn test_select_specific_columns() {
-    let mut p = parser::Parser::create("select bar_1, bar_2 from foo bar");
-    let mut aliashm = HashMap::new();
-    aliashm.insert("bar".to_string(), "foo".to_string());
-    let selected_tables = vec!["foo".to_string()];
+fn test_select_order_by_columns() {
+    let mut p = parser::Parser::create("select * from foo order by a desc, f.b, c asc");
+    match p.parse().unwrap() {
+        Query::ManipulationStmt(ManipulationStmt::Select(stmt)) => assert_eq!(
+            stmt.order,
+            vec![
+                Sort {
+                    alias: None,
+                    col: "a".to_string(),
+                    order: Some(Order::Desc),
+                },
+                Sort {
+                    alias: Some("f".to_string()),
+                    col: "b".to_string(),
+                    order: Some(Order::Asc),
+                },
+                Sort {
+                    alias: None,
+                    col: "c".to_string(),
+                    order: Some(Order::Asc),
+                },
+            ]
+        ),
+        query => panic!("not a select: {:?}", query),
+    }
+}
+
+#[test]
+fn test_select_join() {
+    let mut p = parser::Parser::create(
+        "select * from foo f inner join bar on f.id = bar.foo_id join baz as b on (b.x = 1) \
+         where f.id > 2",
+    );
 
     assert_eq!(
         p.parse().unwrap(),
         Query::ManipulationStmt(ManipulationStmt::Select(SelectStmt {
-            target: vec![
-                Target {
-                    alias: None,
-                    col: Col::Specified("bar_1".to_string()),
-                    rename: None,
+            target: vec![Target {
+                alias: None,
+                col: Col::Every,
+                rename: None,
+            }],
+            tid: vec!["foo".to_string()],
+            joins: vec![
+                Join {
+                    tid: "bar".to_string(),
+                    cond: Conditions::Leaf(Condition {
+                        aliascol: Some("f".to_string()),
+                        col: "id".to_string(),
+                        op: CompType::Equ,
+                        aliasrhs: Some("bar".to_string()),
+                        rhs: CondType::Word("foo_id".to_string()),
+                    }),
+                },
+                Join {
+                    tid: "baz".to_string(),
+                    cond: Conditions::Leaf(Condition {
+                        aliascol: Some("b".to_string()),
+                        col: "x".to_string(),
+                        op: CompType::Equ,
+                        aliasrhs: None,
+                        rhs: CondType::Literal(Lit::Int(1)),
+                    }),
                 },
-                Target {
-                    alias: None,
-                    col: Col::Specified("bar_2".to_string()),
-                    rename: None,
-                }
             ],
-            tid: selected_tables,
-            alias: aliashm,
-            cond: None,
+            alias: vec![
+                ("f".to_string(), "foo".to_string()),
+                ("b".to_string(), "baz".to_string()),
+            ]
+            .into_iter()
+            .collect(),
+            names: vec!["f".to_string(), "bar".to_string(), "b".to_string()],
+            cond: Some(Conditions::Leaf(Condition {
+                aliascol: Some("f".to_string()),
+                col: "id".to_string(),
+                op: CompType::GThan,
+                aliasrhs: None,
+                rhs: CondType::Literal(Lit::Int(2)),
+            })),
             spec_op: None,
             order: Vec::new(),
             limit: None,
         }))
     );
+
+    assert!(parser::Parser::create("select * from foo join bar")
+        .parse()
+        .is_err());
 }
 
 #[test]
-fn test_select_specific_columns_alias() {
-    let mut p = parser::Parser::create("select bar_1 as a2, bar_2 as a1 from foo bar");
-    let mut aliashm = HashMap::new();
-    aliashm.insert("bar".to_string(), "foo".to_string());
-    let selected_tables = vec!["foo".to_string()];
+fn test_select_self_join() {
+    let mut p = parser::Parser::create("select a.id from foo as a join foo b on a.id = b.parent");
+    match p.parse().unwrap() {
+        Query::ManipulationStmt(ManipulationStmt::Select(stmt)) => {
+            assert_eq!(stmt.tid, vec!["foo".to_string()]);
+            assert_eq!(stmt.joins[0].tid, "foo");
+            // every occurrence of the table goes by its own alias
+            assert_eq!(stmt.names, vec!["a".to_string(), "b".to_string()]);
+            assert_eq!(stmt.alias.get("a").map(|t| &t[..]), Some("foo"));
+        }
+        other => panic!("{:?}", other),
+    }
+}
+
+#[test]
+fn test_select_like() {
+    let cond = |query: &str| match parser::Parser::create(query).parse().unwrap() {
+        Query::ManipulationStmt(ManipulationStmt::Select(stmt)) => stmt.cond.unwrap(),
+        _ => panic!("no select statement"),
+    };
+    let leaf = |op, pattern: &str| {
+        Conditions::Leaf(Condition {
+            aliascol: None,
+            col: "name".to_string(),
+            op: op,
+            aliasrhs: None,
+            rhs: CondType::Literal(Lit::String(pattern.to_string())),
+        })
+    };
 
     assert_eq!(
-        p.parse().unwrap(),
-        Query::ManipulationStmt(ManipulationStmt::Select(SelectStmt {
-            target: vec![
-                Target {
-                    alias: None,
-                    col: Col::Specified("bar_1".to_string()),
-                    rename: Some("a2".to_string()),
-                },
-                Target {
-                    alias: None,
-                    col: Col::Specified("bar_2".to_string()),
-                    rename: Some("a1".to_string()),
-                }
-            ],
-            tid: selected_tables,
-            alias: aliashm,
-            cond: None,
-            spec_op: None,
-            order: Vec::new(),
-            limit: None,
-        }))
+        cond("select * from foo where name like 'a_%'"),
+        leaf(CompType::Like, "a_%")
+    );
+    // the pattern is rewritten to escape with a backslash
+    assert_eq!(
+        cond("select * from foo where name not like '10!%\\\\' escape '!'"),
+        leaf(CompType::NLike, "10\\%\\\\")
+    );
+    assert_eq!(
+        cond("select * from foo where name regexp '^a+'"),
+        leaf(CompType::Regexp, "^a+")
+    );
+    assert_eq!(
+        cond("select * from foo where upper(name) not regexp 'X'"),
+        Conditions::Expr(
+            Lit::Call("upper".into(), vec![Lit::Column(None, "name".into())]),
+            CompType::NRegexp,
+            Lit::String("X".into()),
+        )
+    );
+
+    assert!(
+        parser::Parser::create("select * from foo where name like 'a' escape 'ab'")
+            .parse()
+            .is_err()
+    );
+    assert!(
+        parser::Parser::create("select * from foo where name not = 'a'")
+            .parse()
+            .is_err()
     );
 }
 
 #[test]
-fn test_select_specific_columns_alias_dot() {
-    let mut p = parser::Parser::create("select a.bar_1, b.bar_2 from foo bar");
-    let mut aliashm = HashMap::new();
-    aliashm.insert("bar".to_string(), "foo".to_string());
-    let selected_tables = vec!["foo".to_string()];
+fn test_select_expressions() {
+    let mut p = parser::Parser::create(
+        "select price * qty as total, upper(p.name) || '!' from products p \
+         where length(p.name) > 3 and qty >= 2 * 5",
+    );
+    let column = |alias: Option<&str>, name: &str| Lit::Column(alias.map(Into::into), name.into());
+    let call = |name: &str, args| Lit::Call(name.into(), args);
 
     assert_eq!(
         p.parse().unwrap(),
         Query::ManipulationStmt(ManipulationStmt::Select(SelectStmt {
             target: vec![
                 Target {
-                    alias: Some("a".to_string()),
-                    col: Col::Specified("bar_1".to_string()),
-                    rename: None,
+                    alias: None,
+                    col: Col::Expr(call("*", vec![column(None, "price"), column(None, "qty")])),
+                    rename: Some("total".to_string()),
                 },
                 Target {
-                    alias: Some("b".to_string()),
-                    col: Col::Specified("bar_2".to_string()),
+                    alias: None,
+                    col: Col::Expr(call(
+                        "||",
+                        vec![
+                            call("upper", vec![column(Some("p"), "name")]),
+                            Lit::String("!".into()),
+                        ],
+                    )),
                     rename: None,
-                }
+                },
             ],
-            tid: selected_tables,
-            alias: aliashm,
-            cond: None,
+            tid: vec!["products".to_string()],
+            joins: Vec::new(),
+            alias: vec![("p".to_string(), "products".to_string())]
+                .into_iter()
+                .collect(),
+            names: vec!["p".to_string()],
+            cond: Some(Conditions::And(
+                Box::new(Conditions::Expr(
+                    call("length", vec![column(Some("p"), "name")]),
+                    CompType::GThan,
+                    Lit::Int(3),
+                )),
+                Box::new(Conditions::Leaf(Condition {
+                    aliascol: None,
+                    col: "qty".to_string(),
+                    op: CompType::GEThan,
+                    aliasrhs: None,
+                    rhs: CondType::Literal(call("*", vec![Lit::Int(2), Lit::Int(5)])),
+                })),
+            )),
             spec_op: None,
             order: Vec::new(),
             limit: None,
         }))
     );
+
+    // a column compared with an expression of columns
+    let mut p = parser::Parser::create("select * from foo where a < b + 1");
+    match p.parse().unwrap() {
+        Query::ManipulationStmt(ManipulationStmt::Select(stmt)) => assert_eq!(
+            stmt.cond,
+            Some(Conditions::Expr(
+                column(None, "a"),
+                CompType::SThan,
+                call("+", vec![column(None, "b"), Lit::Int(1)]),
+            ))
+        ),
+        _ => panic!("no select statement"),
+    }
+
+    // values of INSERT cannot refer to columns
+    assert!(parser::Parser::create("insert into foo values (a + 1)")
+        .parse()
+        .is_err());
 }
 
 #[test]
-fn test_select_full_where_clause() {
-    let mut p = parser::Parser::create(
-        "select * from foo bar where
-        fname = 'Eugene' and lname = 'peng' or
-        fname = 'peter' and lname = 'pan'",
+fn test_rename() {
+    let mut p = parser::Parser::create("alter table foo rename to bar");
+    assert_eq!(
+        p.parse().unwrap(),
+        Query::DefStmt(DefStmt::Alter(AltStmt::Table(AlterTableStmt {
+            tid: "foo".to_string(),
+            op: AlterOp::Rename("bar".to_string()),
+        })))
     );
-    let mut aliashm = HashMap::new();
-    aliashm.insert("bar".to_string(), "foo".to_string());
-    let selected_tables = vec!["foo".to_string()];
 
+    let mut p = parser::Parser::create("rename database foo to bar");
     assert_eq!(
         p.parse().unwrap(),
-        Query::ManipulationStmt(ManipulationStmt::Select(SelectStmt {
-            target: vec![Target {
-                alias: None,
-                col: Col::Every,
-                rename: None,
-            }],
-            tid: selected_tables,
-            alias: aliashm,
-            cond: Some(Conditions::Or(
-                Box::new(Conditions::And(
-                    Box::new(Conditions::Leaf(Condition {
-                        aliascol: None,
-                        col: "fname".to_string(),
-                        op: CompType::Equ,
-                        aliasrhs: None,
-                        rhs: CondType::Literal(Lit::String("Eugene".to_string())),
-                    })),
-                    Box::new(Conditions::Leaf(Condition {
-                        aliascol: None,
-                        col: "lname".to_string(),
-                        op: CompType::Equ,
-                        aliasrhs: None,
-                        rhs: CondType::Literal(Lit::String("peng".to_string())),
-                    }))
-                )),
-                Box::new(Conditions::And(
-                    Box::new(Conditions::Leaf(Condition {
-                        aliascol: None,
-                        col: "fname".to_string(),
-                        op: CompType::Equ,
-                        aliasrhs: None,
-                        rhs: CondType::Literal(Lit::String("peter".to_string())),
-                    })),
-                    Box::new(Conditions::Leaf(Condition {
-                        aliascol: None,
-                        col: "lname".to_string(),
-                        op: CompType::Equ,
-                        aliasrhs: None,
-                        rhs: CondType::Literal(Lit::String("pan".to_string())),
-                    }))
-                ))
-            )),
-            spec_op: None,
-            order: Vec::new(),
-            limit: None,
-        }))
+        Query::DefStmt(DefStmt::RenameDatabase(
+            "foo".to_string(),
+            "bar".to_string()
+        ))
     );
 }
 
-#[test] //to do: fix keyword limit as to prevent usage as alias for foo
-fn test_select_full_no_where_limit() {
-    let mut p = parser::Parser::create("select * from foo limit 30,3");
+#[test]
+fn test_insert_null() {
+    let mut p = parser::Parser::create("insert into foo values (1, NULL)");
 
     assert_eq!(
         p.parse().unwrap(),
-        Query::ManipulationStmt(ManipulationStmt::Select(SelectStmt {
-            target: vec![Target {
-                alias: None,
-                col: Col::Every,
-                rename: None,
-            }],
-            tid: vec!["foo".to_string()],
-            alias: HashMap::new(),
-            cond: None,
-            spec_op: None,
-            order: Vec::new(),
-            limit: Some(Limit {
-                count: Some(3),
-                offset: Some(30),
-            }),
+        Query::ManipulationStmt(ManipulationStmt::Insert(InsertStmt {
+            tid: "foo".to_string(),
+            col: Vec::<String>::new(),
+            val: vec![vec![Lit::Int(1), Lit::Null]],
+            on_duplicate: None,
         }))
     );
 }
 
 #[test]
-fn test_select_full_where_clause_limit() {
-    let mut p = parser::Parser::create(
-        "select * from foo bar where
-        fname = 'Eugene' and lname = 'peng' or
-        fname = 'peter' and lname = 'pan' limit 30,3",
-    );
-    let mut aliashm = HashMap::new();
-    aliashm.insert("bar".to_string(), "foo".to_string());
-    let selected_tables = vec!["foo".to_string()];
+fn test_delete_null() {
+    let mut p = parser::Parser::create("delete from foo where name is null or nick is not null");
 
     assert_eq!(
         p.parse().unwrap(),
-        Query::ManipulationStmt(ManipulationStmt::Select(SelectStmt {
-            target: vec![Target {
-                alias: None,
-                col: Col::Every,
-                rename: None,
-            }],
-            tid: selected_tables,
-            alias: aliashm,
+        Query::ManipulationStmt(ManipulationStmt::Delete(DeleteStmt {
+            tid: "foo".to_string(),
+            alias: HashMap::new(),
             cond: Some(Conditions::Or(
-                Box::new(Conditions::And(
-                    Box::new(Conditions::Leaf(Condition {
-                        aliascol: None,
-                        col: "fname".to_string(),
-                        op: CompType::Equ,
-                        aliasrhs: None,
-                        rhs: CondType::Literal(Lit::String("Eugene".to_string())),
-                    })),
-                    Box::new(Conditions::Leaf(Condition {
-                        aliascol: None,
-                        col: "lname".to_string(),
-                        op: CompType::Equ,
-                        aliasrhs: None,
-                        rhs: CondType::Literal(Lit::String("peng".to_string())),
-                    }))
-                )),
-                Box::new(Conditions::And(
-                    Box::new(Conditions::Leaf(Condition {
-                        aliascol: None,
-                        col: "fname".to_string(),
-                        op: CompType::Equ,
-                        aliasrhs: None,
-                        rhs: CondType::Literal(Lit::String("peter".to_string())),
-                    })),
-                    Box::new(Conditions::Leaf(Condition {
-                        aliascol: None,
-                        col: "lname".to_string(),
-                        op: CompType::Equ,
-                        aliasrhs: None,
-                        rhs: CondType::Literal(Lit::String("pan".to_string())),
-                    }))
-                ))
+                Box::new(Conditions::Leaf(Condition {
+                    aliascol: None,
+                    col: "name".to_string(),
+                    op: CompType::IsNull,
+                    aliasrhs: None,
+                    rhs: CondType::Literal(Lit::Null),
+                })),
+                Box::new(Conditions::Leaf(Condition {
+                    aliascol: None,
+                    col: "nick".to_string(),
+                    op: CompType::IsNotNull,
+                    aliasrhs: None,
+                    rhs: CondType::Literal(Lit::Null),
+                })),
             )),
-            spec_op: None,
-            order: Vec::new(),
-            limit: Some(Limit {
-                count: Some(3),
-                offset: Some(30),
-            }),
+            returning: Vec::new(),
         }))
     );
 }
 
 #[test]
-fn test_select_complete_1() {
+fn test_insert_temporal() {
     let mut p = parser::Parser::create(
-        "
-        select bar_1.column_1 as X, bar_2.column_2 as Y from foo bar_1, foo_2 bar_2
-        where bar_1.fname = 'Eugene' and bar_1.lname = 'peng'
-        or bar_2.fname = bar_1.fname and bar_2.lname = bar_1.lname limit 30,3",
+        "insert into foo values (DATE '1970-01-02', time '00:00:01', NOW())",
     );
-    let mut aliashm = HashMap::new();
-    aliashm.insert("bar_2".to_string(), "foo_2".to_string());
-    aliashm.insert("bar_1".to_string(), "foo".to_string());
-    let selected_tables = vec!["foo".to_string(), "foo_2".to_string()];
 
     assert_eq!(
         p.parse().unwrap(),
-        Query::ManipulationStmt(ManipulationStmt::Select(SelectStmt {
-            target: vec![
-                Target {
-                    alias: Some("bar_1".to_string()),
-                    col: Col::Specified("column_1".to_string()),
-                    rename: Some("X".to_string()),
-                },
-                Target {
-                    alias: Some("bar_2".to_string()),
-                    col: Col::Specified("column_2".to_string()),
-                    rename: Some("Y".to_string()),
-                }
-            ],
+        Query::ManipulationStmt(ManipulationStmt::Insert(InsertStmt {
+            tid: "foo".to_string(),
+            col: Vec::<String>::new(),
+            val: vec![vec![
+                Lit::Date(1),
+                Lit::Time(1_000_000),
+                Lit::Call("now".to_string(), Vec::new()),
+            ]],
+            on_duplicate: None,
+        }))
+    );
+}
+
+#[test]
+fn test_insert_arithmetic() {
+    let mut p = parser::Parser::create("insert into foo values (19.99 * (2 + 1), -0.50)");
+
+    assert_eq!(
+        p.parse().unwrap(),
+        Query::ManipulationStmt(ManipulationStmt::Insert(InsertStmt {
+            tid: "foo".to_string(),
+            col: Vec::<String>::new(),
+            val: vec![vec![
+                Lit::Call(
+                    "*".to_string(),
+                    vec![
+                        Lit::Decimal(1999, 2),
+                        Lit::Call("+".to_string(), vec![Lit::Int(2), Lit::Int(1)]),
+                    ],
+                ),
+                Lit::Decimal(-50, 2),
+            ]],
+            on_duplicate: None,
+        }))
+    );
+}
+
+#[test]
+fn test_insert_bytes() {
+    let mut p = parser::Parser::create("insert into foo values (X'00ff1A')");
+
+    assert_eq!(
+        p.parse().unwrap(),
+        Query::ManipulationStmt(ManipulationStmt::Insert(InsertStmt {
+            tid: "foo".to_string(),
+            col: Vec::<String>::new(),
+            val: vec![vec![Lit::Bytes(vec![0x00, 0xff, 0x1a])]],
+            on_duplicate: None,
+        }))
+    );
+}
+
+#[test]
+fn test_insert_doubled_quotes() {
+    let mut p = parser::Parser::create("insert into foo values ('it''s', 'say \"hi\"', '')");
+
+    assert_eq!(
+        p.parse().unwrap(),
+        Query::ManipulationStmt(ManipulationStmt::Insert(InsertStmt {
+            tid: "foo".to_string(),
+            col: Vec::<String>::new(),
+            val: vec![vec![
+                Lit::String("it's".into()),
+                Lit::String("say \"hi\"".into()),
+                Lit::String("".into()),
+            ]],
+            on_duplicate: None,
+        }))
+    );
+}
+
+#[test]
+fn test_insert_escapes() {
+    let mut p =
+        parser::Parser::create("insert into foo values ('a\\nb', 'it\\'s', '50\\%', 'c:\\\\x')");
+
+    match p.parse().unwrap() {
+        Query::ManipulationStmt(ManipulationStmt::Insert(stmt)) => assert_eq!(
+            stmt.val[0],
+            vec![
+                Lit::String("a\nb".into()),
+                Lit::String("it's".into()),
+                // still an escaped wildcard of LIKE
+                Lit::String("50\\%".into()),
+                Lit::String("c:\\x".into()),
+            ]
+        ),
+        q => panic!("unexpected query {:?}", q),
+    }
+}
+
+#[test]
+fn test_quoted_names() {
+    let mut p = parser::Parser::create(
+        "select \"order\", t.`my \"\"col`, `a``b` + 1 from \"my table\" t where \"select\" = 'x'",
+    );
+
+    let stmt = match p.parse().unwrap() {
+        Query::ManipulationStmt(ManipulationStmt::Select(stmt)) => stmt,
+        q => panic!("unexpected query {:?}", q),
+    };
+    assert_eq!(stmt.target[0].col, Col::Specified("order".into()));
+    assert_eq!(stmt.target[1].alias, Some("t".into()));
+    assert_eq!(stmt.target[1].col, Col::Specified("my \"\"col".into()));
+    assert_eq!(
+        stmt.target[2].col,
+        Col::Expr(Lit::Call(
+            "+".into(),
+            vec![Lit::Column(None, "a`b".into()), Lit::Int(1)]
+        ))
+    );
+    assert_eq!(stmt.tid, vec!["my table".to_string()]);
+    match stmt.cond {
+        Some(Conditions::Leaf(ref c)) => assert_eq!(c.col, "select"),
+        ref c => panic!("unexpected condition {:?}", c),
+    }
+}
+
+#[test]
+fn err_invalid_hex() {
+    let mut p = parser::Parser::create("insert into foo values (x'0g')");
+
+    assert!(p.parse().is_err());
+}
+
+#[test]
+fn test_delete_function_call() {
+    let mut p = parser::Parser::create("delete from foo where day < datediff(now(), '2024-01-01')");
+
+    assert_eq!(
+        p.parse().unwrap(),
+        Query::ManipulationStmt(ManipulationStmt::Delete(DeleteStmt {
+            tid: "foo".to_string(),
+            alias: HashMap::new(),
+            cond: Some(Conditions::Leaf(Condition {
+                aliascol: None,
+                col: "day".to_string(),
+                op: CompType::SThan,
+                aliasrhs: None,
+                rhs: CondType::Literal(Lit::Call(
+                    "datediff".to_string(),
+                    vec![
+                        Lit::Call("now".to_string(), Vec::new()),
+                        Lit::String("2024-01-01".to_string()),
+                    ],
+                )),
+            })),
+            returning: Vec::new(),
+        }))
+    );
+}
+
+#[test]
+fn err_invalid_date() {
+    let mut p = parser::Parser::create("insert into foo values (date '2023-02-29')");
+
+    assert!(p.parse().is_err());
+}
+
+#[test]
+fn err_is_without_null() {
+    let mut p = parser::Parser::create("delete from foo where name is 3");
+
+    assert!(p.parse().is_err());
+}
+
+#[test]
+fn test_delete_full_with_table_alias() {
+    let mut p = parser::Parser::create("delete from foo bar");
+    let mut aliashm = HashMap::new();
+    aliashm.insert("bar".to_string(), "foo".to_string());
+
+    assert_eq!(
+        p.parse().unwrap(),
+        Query::ManipulationStmt(ManipulationStmt::Delete(DeleteStmt {
+            tid: "foo".to_string(),
+            alias: aliashm,
+            cond: None,
+            returning: Vec::new(),
+        }))
+    );
+}
+
+#[test]
+fn test_select_full_with_table_alias() {
+    let mut p = parser::Parser::create("select * from foo bar");
+    let mut aliashm = HashMap::new();
+    aliashm.insert("bar".to_string(), "foo".to_string());
+    let selected_tables = vec!["foo".to_string()];
+
+    assert_eq!(
+        p.parse().unwrap(),
+        Query::ManipulationStmt(ManipulationStmt::Select(SelectStmt {
+            target: vec![Target {
+                alias: None,
+                col: Col::Every,
+                rename: None,
+            }],
             tid: selected_tables,
+            joins: Vec::new(),
             alias: aliashm,
-            cond: Some(Conditions::Or(
-                Box::new(Conditions::And(
-                    Box::new(Conditions::Leaf(Condition {
-                        aliascol: Some("bar_1".to_string()),
-                        col: "fname".to_string(),
-                        op: CompType::Equ,
-                        aliasrhs: None,
-                        rhs: CondType::Literal(Lit::String("Eugene".to_string())),
-                    })),
-                    Box::new(Conditions::Leaf(Condition {
-                        aliascol: Some("bar_1".to_string()),
-                        col: "lname".to_string(),
-                        op: CompType::Equ,
-                        aliasrhs: None,
-                        rhs: CondType::Literal(Lit::String("peng".to_string())),
-                    }))
-                )),
-                Box::new(Conditions::And(
-                    Box::new(Conditions::Leaf(Condition {
-                        aliascol: Some("bar_2".to_string()),
-                        col: "fname".to_string(),
-                        op: CompType::Equ,
-                        aliasrhs: Some("bar_1".to_string()),
-                        rhs: CondType::Word("fname".to_string()),
-                    })),
-                    Box::new(Conditions::Leaf(Condition {
-                        aliascol: Some("bar_2".to_string()),
-                        col: "lname".to_string(),
-                        op: CompType::Equ,
-                        aliasrhs: Some("bar_1".to_string()),
-                        rhs: CondType::Word("lname".to_string()),
-                    }))
-                ))
-            )),
+            names: vec!["bar".to_string()],
+            cond: None,
             spec_op: None,
             order: Vec::new(),
-            limit: Some(Limit {
-                count: Some(3),
-                offset: Some(30),
-            }),
+            limit: None,
         }))
     );
 }
 
 #[test]
-fn test_select_complete_2_with_order_by() {
-    let mut p = parser::Parser::create(
-        "
-        select bar_1.column_1 as X, bar_2.column_2 as Y from foo bar_1, foo_2 bar_2
-        where bar_1.fname = 'Eugene' and bar_1.lname = 'peng'
-        or bar_2.fname = bar_1.fname and bar_2.lname = bar_1.lname
-        order by bar_1.X limit 30,3",
+fn test_select_specific_column() {
+    let mut p = parser::Parser::create("select bar from foo bar");
+    let mut aliashm = HashMap::new();
+    aliashm.insert("bar".to_string(), "foo".to_string());
+    let selected_tables = vec!["foo".to_string()];
+
+    assert_eq!(
+        p.parse().unwrap(),
+        Query::ManipulationStmt(ManipulationStmt::Select(SelectStmt {
+            target: vec![Target {
+                alias: None,
+                col: Col::Specified("bar".to_string()),
+                rename: None,
+            }],
+            tid: selected_tables,
+            joins: Vec::new(),
+            alias: aliashm,
+            names: vec!["bar".to_string()],
+            cond: None,
+            spec_op: None,
+            order: Vec::new(),
+            limit: None,
+        }))
     );
+}
+
+#[test]
+fn test_select_specific_columns() {
+    let mut p = parser::Parser::create("select bar_1, bar_2 from foo bar");
     let mut aliashm = HashMap::new();
-    aliashm.insert("bar_2".to_string(), "foo_2".to_string());
-    aliashm.insert("bar_1".to_string(), "foo".to_string());
-    let selected_tables = vec!["foo".to_string(), "foo_2".to_string()];
+    aliashm.insert("bar".to_string(), "foo".to_string());
+    let selected_tables = vec!["foo".to_string()];
 
     assert_eq!(
         p.parse().unwrap(),
         Query::ManipulationStmt(ManipulationStmt::Select(SelectStmt {
             target: vec![
                 Target {
-                    alias: Some("bar_1".to_string()),
-                    col: Col::Specified("column_1".to_string()),
-                    rename: Some("X".to_string()),
+                    alias: None,
+                    col: Col::Specified("bar_1".to_string()),
+                    rename: None,
                 },
                 Target {
-                    alias: Some("bar_2".to_string()),
-                    col: Col::Specified("column_2".to_string()),
-                    rename: Some("Y".to_string()),
+                    alias: None,
+                    col: Col::Specified("bar_2".to_string()),
+                    rename: None,
                 }
             ],
             tid: selected_tables,
+            joins: Vec::new(),
             alias: aliashm,
-            cond: Some(Conditions::Or(
-                Box::new(Conditions::And(
-                    Box::new(Conditions::Leaf(Condition {
-                        aliascol: Some("bar_1".to_string()),
-                        col: "fname".to_string(),
-                        op: CompType::Equ,
-                        aliasrhs: None,
-                        rhs: CondType::Literal(Lit::String("Eugene".to_string())),
-                    })),
-                    Box::new(Conditions::Leaf(Condition {
-                        aliascol: Some("bar_1".to_string()),
-                        col: "lname".to_string(),
-                        op: CompType::Equ,
-                        aliasrhs: None,
-                        rhs: CondType::Literal(Lit::String("peng".to_string())),
-                    }))
-                )),
-                Box::new(Conditions::And(
-                    Box::new(Conditions::Leaf(Condition {
-                        aliascol: Some("bar_2".to_string()),
-                        col: "fname".to_string(),
-                        op: CompType::Equ,
-                        aliasrhs: Some("bar_1".to_string()),
-                        rhs: CondType::Word("fname".to_string()),
-                    })),
-                    Box::new(Conditions::Leaf(Condition {
-                        aliascol: Some("bar_2".to_string()),
-                        col: "lname".to_string(),
-                        op: CompType::Equ,
-                        aliasrhs: Some("bar_1".to_string()),
-                        rhs: CondType::Word("lname".to_string()),
-                    }))
-                ))
-            )),
+            names: vec!["bar".to_string()],
+            cond: None,
             spec_op: None,
-            order: vec![Sort {
-                alias: Some("bar_1".to_string()),
-                col: "X".to_string(),
-                order: Some(Order::Asc),
-            }],
-            limit: Some(Limit {
-                count: Some(3),
-                offset: Some(30),
-            }),
+            order: Vec::new(),
+            limit: None,
         }))
     );
 }
 
 #[test]
-fn test_create_view_1() {
-    let mut p = parser::Parser::create("create view foo as select * from bar");
+fn test_select_specific_columns_alias() {
+    let mut p = parser::Parser::create("select bar_1 as a2, bar_2 as a1 from foo bar");
+    let mut aliashm = HashMap::new();
+    aliashm.insert("bar".to_string(), "foo".to_string());
+    let selected_tables = vec!["foo".to_string()];
+
+    assert_eq!(
+        p.parse().unwrap(),
+        Query::ManipulationStmt(ManipulationStmt::Select(SelectStmt {
+            target: vec![
+                Target {
+                    alias: None,
+                    col: Col::Specified("bar_1".to_string()),
+                    rename: Some("a2".to_string()),
+                },
+                Target {
+                    alias: None,
+                    col: Col::Specified("bar_2".to_string()),
+                    rename: Some("a1".to_string()),
+                }
+            ],
+            tid: selected_tables,
+            joins: Vec::new(),
+            alias: aliashm,
+            names: vec!["bar".to_string()],
+            cond: None,
+            spec_op: None,
+            order: Vec::new(),
+            limit: None,
+        }))
+    );
+}
+
+#[test]
+fn test_select_specific_columns_alias_dot() {
+    let mut p = parser::Parser::create("select a.bar_1, b.bar_2 from foo bar");
+    let mut aliashm = HashMap::new();
+    aliashm.insert("bar".to_string(), "foo".to_string());
+    let selected_tables = vec!["foo".to_string()];
+
+    assert_eq!(
+        p.parse().unwrap(),
+        Query::ManipulationStmt(ManipulationStmt::Select(SelectStmt {
+            target: vec![
+                Target {
+                    alias: Some("a".to_string()),
+                    col: Col::Specified("bar_1".to_string()),
+                    rename: None,
+                },
+                Target {
+                    alias: Some("b".to_string()),
+                    col: Col::Specified("bar_2".to_string()),
+                    rename: None,
+                }
+            ],
+            tid: selected_tables,
+            joins: Vec::new(),
+            alias: aliashm,
+            names: vec!["bar".to_string()],
+            cond: None,
+            spec_op: None,
+            order: Vec::new(),
+            limit: None,
+        }))
+    );
+}
+
+#[test]
+fn test_select_full_where_clause() {
+    let mut p = parser::Parser::create(
+        "select * from foo bar where
+        fname = 'Eugene' and lname = 'peng' or
+        fname = 'peter' and lname = 'pan'",
+    );
+    let mut aliashm = HashMap::new();
+    aliashm.insert("bar".to_string(), "foo".to_string());
+    let selected_tables = vec!["foo".to_string()];
+
+    assert_eq!(
+        p.parse().unwrap(),
+        Query::ManipulationStmt(ManipulationStmt::Select(SelectStmt {
+            target: vec![Target {
+                alias: None,
+                col: Col::Every,
+                rename: None,
+            }],
+            tid: selected_tables,
+            joins: Vec::new(),
+            alias: aliashm,
+            names: vec!["bar".to_string()],
+            cond: Some(Conditions::Or(
+                Box::new(Conditions::And(
+                    Box::new(Conditions::Leaf(Condition {
+                        aliascol: None,
+                        col: "fname".to_string(),
+                        op: CompType::Equ,
+                        aliasrhs: None,
+                        rhs: CondType::Literal(Lit::String("Eugene".to_string())),
+                    })),
+                    Box::new(Conditions::Leaf(Condition {
+                        aliascol: None,
+                        col: "lname".to_string(),
+                        op: CompType::Equ,
+                        aliasrhs: None,
+                        rhs: CondType::Literal(Lit::String("peng".to_string())),
+                    }))
+                )),
+                Box::new(Conditions::And(
+                    Box::new(Conditions::Leaf(Condition {
+                        aliascol: None,
+                        col: "fname".to_string(),
+                        op: CompType::Equ,
+                        aliasrhs: None,
+                        rhs: CondType::Literal(Lit::String("peter".to_string())),
+                    })),
+                    Box::new(Conditions::Leaf(Condition {
+                        aliascol: None,
+                        col: "lname".to_string(),
+                        op: CompType::Equ,
+                        aliasrhs: None,
+                        rhs: CondType::Literal(Lit::String("pan".to_string())),
+                    }))
+                ))
+            )),
+            spec_op: None,
+            order: Vec::new(),
+            limit: None,
+        }))
+    );
+}
+
+#[test] //to do: fix keyword limit as to prevent usage as alias for foo
+fn test_select_full_no_where_limit() {
+    let mut p = parser::Parser::create("select * from foo limit 30,3");
+
+    assert_eq!(
+        p.parse().unwrap(),
+        Query::ManipulationStmt(ManipulationStmt::Select(SelectStmt {
+            target: vec![Target {
+                alias: None,
+                col: Col::Every,
+                rename: None,
+            }],
+            tid: vec!["foo".to_string()],
+            joins: Vec::new(),
+            alias: HashMap::new(),
+            names: vec!["foo".to_string()],
+            cond: None,
+            spec_op: None,
+            order: Vec::new(),
+            limit: Some(Limit {
+                count: Some(3),
+                offset: Some(30),
+            }),
+        }))
+    );
+}
+
+#[test]
+fn test_select_full_where_clause_limit() {
+    let mut p = parser::Parser::create(
+        "select * from foo bar where
+        fname = 'Eugene' and lname = 'peng' or
+        fname = 'peter' and lname = 'pan' limit 30,3",
+    );
+    let mut aliashm = HashMap::new();
+    aliashm.insert("bar".to_string(), "foo".to_string());
+    let selected_tables = vec!["foo".to_string()];
+
+    assert_eq!(
+        p.parse().unwrap(),
+        Query::ManipulationStmt(ManipulationStmt::Select(SelectStmt {
+            target: vec![Target {
+                alias: None,
+                col: Col::Every,
+                rename: None,
+            }],
+            tid: selected_tables,
+            joins: Vec::new(),
+            alias: aliashm,
+            names: vec!["bar".to_string()],
+            cond: Some(Conditions::Or(
+                Box::new(Conditions::And(
+                    Box::new(Conditions::Leaf(Condition {
+                        aliascol: None,
+                        col: "fname".to_string(),
+                        op: CompType::Equ,
+                        aliasrhs: None,
+                        rhs: CondType::Literal(Lit::String("Eugene".to_string())),
+                    })),
+                    Box::new(Conditions::Leaf(Condition {
+                        aliascol: None,
+                        col: "lname".to_string(),
+                        op: CompType::Equ,
+                        aliasrhs: None,
+                        rhs: CondType::Literal(Lit::String("peng".to_string())),
+                    }))
+                )),
+                Box::new(Conditions::And(
+                    Box::new(Conditions::Leaf(Condition {
+                        aliascol: None,
+                        col: "fname".to_string(),
+                        op: CompType::Equ,
+                        aliasrhs: None,
+                        rhs: CondType::Literal(Lit::String("peter".to_string())),
+                    })),
+                    Box::new(Conditions::Leaf(Condition {
+                        aliascol: None,
+                        col: "lname".to_string(),
+                        op: CompType::Equ,
+                        aliasrhs: None,
+                        rhs: CondType::Literal(Lit::String("pan".to_string())),
+                    }))
+                ))
+            )),
+            spec_op: None,
+            order: Vec::new(),
+            limit: Some(Limit {
+                count: Some(3),
+                offset: Some(30),
+            }),
+        }))
+    );
+}
+
+#[test]
+fn test_select_complete_1() {
+    let mut p = parser::Parser::create(
+        "
+        select bar_1.column_1 as X, bar_2.column_2 as Y from foo bar_1, foo_2 bar_2
+        where bar_1.fname = 'Eugene' and bar_1.lname = 'peng'
+        or bar_2.fname = bar_1.fname and bar_2.lname = bar_1.lname limit 30,3",
+    );
+    let mut aliashm = HashMap::new();
+    aliashm.insert("bar_2".to_string(), "foo_2".to_string());
+    aliashm.insert("bar_1".to_string(), "foo".to_string());
+    let selected_tables = vec!["foo".to_string(), "foo_2".to_string()];
+
+    assert_eq!(
+        p.parse().unwrap(),
+        Query::ManipulationStmt(ManipulationStmt::Select(SelectStmt {
+            target: vec![
+                Target {
+                    alias: Some("bar_1".to_string()),
+                    col: Col::Specified("column_1".to_string()),
+                    rename: Some("X".to_string()),
+                },
+                Target {
+                    alias: Some("bar_2".to_string()),
+                    col: Col::Specified("column_2".to_string()),
+                    rename: Some("Y".to_string()),
+                }
+            ],
+            tid: selected_tables,
+            joins: Vec::new(),
+            alias: aliashm,
+            names: vec!["bar_1".to_string(), "bar_2".to_string()],
+            cond: Some(Conditions::Or(
+                Box::new(Conditions::And(
+                    Box::new(Conditions::Leaf(Condition {
+                        aliascol: Some("bar_1".to_string()),
+                        col: "fname".to_string(),
+                        op: CompType::Equ,
+                        aliasrhs: None,
+                        rhs: CondType::Literal(Lit::String("Eugene".to_string())),
+                    })),
+                    Box::new(Conditions::Leaf(Condition {
+                        aliascol: Some("bar_1".to_string()),
+                        col: "lname".to_string(),
+                        op: CompType::Equ,
+                        aliasrhs: None,
+                        rhs: CondType::Literal(Lit::String("peng".to_string())),
+                    }))
+                )),
+                Box::new(Conditions::And(
+                    Box::new(Conditions::Leaf(Condition {
+                        aliascol: Some("bar_2".to_string()),
+                        col: "fname".to_string(),
+                        op: CompType::Equ,
+                        aliasrhs: Some("bar_1".to_string()),
+                        rhs: CondType::Word("fname".to_string()),
+                    })),
+                    Box::new(Conditions::Leaf(Condition {
+                        aliascol: Some("bar_2".to_string()),
+                        col: "lname".to_string(),
+                        op: CompType::Equ,
+                        aliasrhs: Some("bar_1".to_string()),
+                        rhs: CondType::Word("lname".to_string()),
+                    }))
+                ))
+            )),
+            spec_op: None,
+            order: Vec::new(),
+            limit: Some(Limit {
+                count: Some(3),
+                offset: Some(30),
+            }),
+        }))
+    );
+}
+
+#[test]
+fn test_select_complete_2_with_order_by() {
+    let mut p = parser::Parser::create(
+        "
+        select bar_1.column_1 as X, bar_2.column_2 as Y from foo bar_1, foo_2 bar_2
+        where bar_1.fname = 'Eugene' and bar_1.lname = 'peng'
+        or bar_2.fname = bar_1.fname and bar_2.lname = bar_1.lname
+        order by bar_1.X limit 30,3",
+    );
+    let mut aliashm = HashMap::new();
+    aliashm.insert("bar_2".to_string(), "foo_2".to_string());
+    aliashm.insert("bar_1".to_string(), "foo".to_string());
+    let selected_tables = vec!["foo".to_string(), "foo_2".to_string()];
+
+    assert_eq!(
+        p.parse().unwrap(),
+        Query::ManipulationStmt(ManipulationStmt::Select(SelectStmt {
+            target: vec![
+                Target {
+                    alias: Some("bar_1".to_string()),
+                    col: Col::Specified("column_1".to_string()),
+                    rename: Some("X".to_string()),
+                },
+                Target {
+                    alias: Some("bar_2".to_string()),
+                    col: Col::Specified("column_2".to_string()),
+                    rename: Some("Y".to_string()),
+                }
+            ],
+            tid: selected_tables,
+            joins: Vec::new(),
+            alias: aliashm,
+            names: vec!["bar_1".to_string(), "bar_2".to_string()],
+            cond: Some(Conditions::Or(
+                Box::new(Conditions::And(
+                    Box::new(Conditions::Leaf(Condition {
+                        aliascol: Some("bar_1".to_string()),
+                        col: "fname".to_string(),
+                        op: CompType::Equ,
+                        aliasrhs: None,
+                        rhs: CondType::Literal(Lit::String("Eugene".to_string())),
+                    })),
+                    Box::new(Conditions::Leaf(Condition {
+                        aliascol: Some("bar_1".to_string()),
+                        col: "lname".to_string(),
+                        op: CompType::Equ,
+                        aliasrhs: None,
+                        rhs: CondType::Literal(Lit::String("peng".to_string())),
+                    }))
+                )),
+                Box::new(Conditions::And(
+                    Box::new(Conditions::Leaf(Condition {
+                        aliascol: Some("bar_2".to_string()),
+                        col: "fname".to_string(),
+                        op: CompType::Equ,
+                        aliasrhs: Some("bar_1".to_string()),
+                        rhs: CondType::Word("fname".to_string()),
+                    })),
+                    Box::new(Conditions::Leaf(Condition {
+                        aliascol: Some("bar_2".to_string()),
+                        col: "lname".to_string(),
+                        op: CompType::Equ,
+                        aliasrhs: Some("bar_1".to_string()),
+                        rhs: CondType::Word("lname".to_string()),
+                    }))
+                ))
+            )),
+            spec_op: None,
+            order: vec![Sort {
+                alias: Some("bar_1".to_string()),
+                col: "X".to_string(),
+                order: Some(Order::Asc),
+            }],
+            limit: Some(Limit {
+                count: Some(3),
+                offset: Some(30),
+            }),
+        }))
+    );
+}
+
+#[test]
+fn test_create_view_1() {
+    let mut p = parser::Parser::create("create view foo as select * from bar ");
+
+    assert_eq!(
+        p.parse().unwrap(),
+        Query::DefStmt(DefStmt::Create(CreateStmt::View(CreateViewStmt {
+            name: "foo".to_string(),
+            opt: false,
+            sel: SelectStmt {
+                target: vec![Target {
+                    alias: None,
+                    col: Col::Every,
+                    rename: None,
+                }],
+                tid: vec!["bar".to_string()],
+                joins: Vec::new(),
+                alias: HashMap::new(),
+                names: vec!["bar".to_string()],
+                cond: None,
+                spec_op: None,
+                order: Vec::new(),
+                limit: None,
+            },
+            text: "select * from bar".to_string(),
+        })))
+    );
+}
+
+#[test]
+fn test_create_view_2() {
+    let mut p = parser::Parser::create("create or replace view foo as select * from bar");
+
+    assert_eq!(
+        p.parse().unwrap(),
+        Query::DefStmt(DefStmt::Create(CreateStmt::View(CreateViewStmt {
+            name: "foo".to_string(),
+            opt: true,
+            sel: SelectStmt {
+                target: vec![Target {
+                    alias: None,
+                    col: Col::Every,
+                    rename: None,
+                }],
+                tid: vec!["bar".to_string()],
+                joins: Vec::new(),
+                alias: HashMap::new(),
+                names: vec!["bar".to_string()],
+                cond: None,
+                spec_op: None,
+                order: Vec::new(),
+                limit: None,
+            },
+            text: "select * from bar".to_string(),
+        })))
+    );
+    assert_eq!(
+        parser::Parser::create("show create view foo")
+            .parse()
+            .unwrap(),
+        Query::ManipulationStmt(ManipulationStmt::Show(ShowStmt::CreateView("foo".into())))
+    );
+}
+
+#[test]
+fn test_admin_reload() {
+    assert_eq!(
+        parser::Parser::create("ADMIN reload").parse().unwrap(),
+        Query::ControlStmt(ControlStmt::Admin(AdminStmt::Reload))
+    );
+    assert_eq!(
+        parser::Parser::create("admin shutdown").parse().unwrap(),
+        Query::ControlStmt(ControlStmt::Admin(AdminStmt::Shutdown))
+    );
+    assert!(parser::Parser::create("admin restart").parse().is_err());
+    // admin stays the name of a user
+    assert!(parser::Parser::create("show grants for admin")
+        .parse()
+        .is_ok());
+}
+
+#[test]
+fn test_create_function() {
+    let mut p = parser::Parser::create(
+        "create or replace function Twice(x int, y varchar(8)) returns int as 'x * 2'",
+    );
+
+    assert_eq!(
+        p.parse().unwrap(),
+        Query::DefStmt(DefStmt::Create(CreateStmt::Function(CreateFunctionStmt {
+            name: "twice".to_string(),
+            opt: true,
+            params: vec![
+                ("x".to_string(), SqlType::Int),
+                ("y".to_string(), SqlType::VarChar(8)),
+            ],
+            returns: SqlType::Int,
+            body: "x * 2".to_string(),
+        })))
+    );
+    match parser::Parser::create("create function one() returns int as '1'")
+        .parse()
+        .unwrap()
+    {
+        Query::DefStmt(DefStmt::Create(CreateStmt::Function(ref stmt))) => {
+            assert!(stmt.params.is_empty() && !stmt.opt)
+        }
+        ref q => panic!("unexpected query {:?}", q),
+    }
+    assert_eq!(
+        parser::Parser::create("drop function Twice")
+            .parse()
+            .unwrap(),
+        Query::DefStmt(DefStmt::Drop(DropStmt::Function("twice".into())))
+    );
+    assert_eq!(
+        parser::Parser::create("create function f(x int) returns int as 42").parse(),
+        Err(parser::ParseError::BodyIsNoString)
+    );
+    assert!(parser::Parser::create("create function f(x int) as 'x'")
+        .parse()
+        .is_err());
+    // function and returns are no keywords
+    assert!(parser::Parser::create("select function, returns from foo")
+        .parse()
+        .is_ok());
+
+    assert_eq!(
+        parser::Parser::create("2 * (x + 1)").parse_expression(),
+        Ok(Lit::Call(
+            "*".into(),
+            vec![
+                Lit::Int(2),
+                Lit::Call("+".into(), vec![Lit::Column(None, "x".into()), Lit::Int(1)]),
+            ]
+        ))
+    );
+    assert_eq!(
+        parser::Parser::create("x; y").parse_expression(),
+        Err(parser::ParseError::InvalidEoq)
+    );
+}
+
+#[test]
+fn test_create_event() {
+    let mut p = parser::Parser::create(
+        "create or replace event Cleanup on schedule every 1 day do \
+         delete from logs where ts < 10",
+    );
+
+    assert_eq!(
+        p.parse().unwrap(),
+        Query::DefStmt(DefStmt::Create(CreateStmt::Event(CreateEventStmt {
+            name: "Cleanup".to_string(),
+            opt: true,
+            every: 24 * 60 * 60,
+            body: "delete from logs where ts < 10".to_string(),
+        })))
+    );
+    match parser::Parser::create("create event e on schedule every 90 seconds do select * from t")
+        .parse()
+        .unwrap()
+    {
+        Query::DefStmt(DefStmt::Create(CreateStmt::Event(ref stmt))) => {
+            assert_eq!((stmt.every, stmt.opt), (90, false))
+        }
+        ref q => panic!("unexpected query {:?}", q),
+    }
+    assert_eq!(
+        parser::Parser::create("drop event Cleanup")
+            .parse()
+            .unwrap(),
+        Query::DefStmt(DefStmt::Drop(DropStmt::Event("Cleanup".into())))
+    );
+    assert_eq!(
+        parser::Parser::create("show events").parse().unwrap(),
+        Query::ManipulationStmt(ManipulationStmt::Show(ShowStmt::Events))
+    );
+    match parser::Parser::create("create event e on schedule every 1 fortnight do select * from t")
+        .parse()
+    {
+        Err(parser::ParseError::NotAnInterval(_)) => {}
+        ref q => panic!("unexpected result {:?}", q),
+    }
+    assert!(
+        parser::Parser::create("create event e on schedule every 0 days do select * from t")
+            .parse()
+            .is_err()
+    );
+    assert!(
+        parser::Parser::create("create event e on schedule every 1 day")
+            .parse()
+            .is_err()
+    );
+    // event and events are no keywords
+    assert!(parser::Parser::create("select event, events from foo")
+        .parse()
+        .is_ok());
+}
+
+#[test]
+fn test_update_full_with_table_alias() {
+    let mut p = parser::Parser::create("update foo bar set bar_1 = 1 where bar.bar_2 > 'pleb'");
+    let mut aliashm = HashMap::new();
+    aliashm.insert("bar".to_string(), "foo".to_string());
+    let set_vec = vec![Condition {
+        aliascol: None,
+        col: "bar_1".to_string(),
+        op: CompType::Equ,
+        aliasrhs: None,
+        rhs: CondType::Literal(Lit::Int(1)),
+    }];
+
+    assert_eq!(
+        p.parse().unwrap(),
+        Query::ManipulationStmt(ManipulationStmt::Update(UpdateStmt {
+            tid: "foo".to_string(),
+            alias: aliashm,
+            set: set_vec,
+            conds: Some(Conditions::Leaf(Condition {
+                aliascol: Some("bar".to_string()),
+                col: "bar_2".to_string(),
+                op: CompType::GThan,
+                aliasrhs: None,
+                rhs: CondType::Literal(Lit::String("pleb".to_string())),
+            }))
+        }))
+    );
+}
+
+#[test]
+fn test_update_expressions() {
+    let mut p = parser::Parser::create(
+        "update stock set qty = qty - 1, note = note || '!' where qty > 0 and not id = 3",
+    );
+
+    match p.parse().unwrap() {
+        Query::ManipulationStmt(ManipulationStmt::Update(stmt)) => {
+            let column = |name: &str| Lit::Column(None, name.into());
+            assert_eq!(
+                stmt.set[0].rhs,
+                CondType::Literal(Lit::Call("-".into(), vec![column("qty"), Lit::Int(1)]))
+            );
+            assert_eq!(
+                stmt.set[1].rhs,
+                CondType::Literal(Lit::Call(
+                    "||".into(),
+                    vec![column("note"), Lit::String("!".into())]
+                ))
+            );
+            match stmt.conds {
+                Some(Conditions::And(_, _)) => (),
+                other => panic!("{:?}", other),
+            }
+        }
+        other => panic!("{:?}", other),
+    }
+}
+
+#[test]
+fn test_mult_where_blocks_3_param() {
+    let mut p = parser::Parser::create(
+        "delete from foo where lname = 'peng' or
+        fname = 'peter' and lname = 'pan'",
+    );
+
+    assert_eq!(
+        p.parse().unwrap(),
+        Query::ManipulationStmt(ManipulationStmt::Delete(DeleteStmt {
+            tid: "foo".to_string(),
+            alias: HashMap::new(),
+            cond: Some(Conditions::Or(
+                Box::new(Conditions::Leaf(Condition {
+                    aliascol: None,
+                    col: "lname".to_string(),
+                    op: CompType::Equ,
+                    aliasrhs: None,
+                    rhs: CondType::Literal(Lit::String("peng".to_string())),
+                })),
+                Box::new(Conditions::And(
+                    Box::new(Conditions::Leaf(Condition {
+                        aliascol: None,
+                        col: "fname".to_string(),
+                        op: CompType::Equ,
+                        aliasrhs: None,
+                        rhs: CondType::Literal(Lit::String("peter".to_string())),
+                    })),
+                    Box::new(Conditions::Leaf(Condition {
+                        aliascol: None,
+                        col: "lname".to_string(),
+                        op: CompType::Equ,
+                        aliasrhs: None,
+                        rhs: CondType::Literal(Lit::String("pan".to_string())),
+                    }))
+                ))
+            )),
+            returning: Vec::new(),
+        }))
+    );
+}
+
+#[test]
+fn test_mult_where_blocks_priority_4_param() {
+    let mut p = parser::Parser::create(
+        "delete from foo where
+        fname = 'Eugene' and lname = 'peng' or
+        fname = 'peter' and lname = 'pan'",
+    );
+
+    assert_eq!(
+        p.parse().unwrap(),
+        Query::ManipulationStmt(ManipulationStmt::Delete(DeleteStmt {
+            tid: "foo".to_string(),
+            alias: HashMap::new(),
+            cond: Some(Conditions::Or(
+                Box::new(Conditions::And(
+                    Box::new(Conditions::Leaf(Condition {
+                        aliascol: None,
+                        col: "fname".to_string(),
+                        op: CompType::Equ,
+                        aliasrhs: None,
+                        rhs: CondType::Literal(Lit::String("Eugene".to_string())),
+                    })),
+                    Box::new(Conditions::Leaf(Condition {
+                        aliascol: None,
+                        col: "lname".to_string(),
+                        op: CompType::Equ,
+                        aliasrhs: None,
+                        rhs: CondType::Literal(Lit::String("peng".to_string())),
+                    }))
+                )),
+                Box::new(Conditions::And(
+                    Box::new(Conditions::Leaf(Condition {
+                        aliascol: None,
+                        col: "fname".to_string(),
+                        op: CompType::Equ,
+                        aliasrhs: None,
+                        rhs: CondType::Literal(Lit::String("peter".to_string())),
+                    })),
+                    Box::new(Conditions::Leaf(Condition {
+                        aliascol: None,
+                        col: "lname".to_string(),
+                        op: CompType::Equ,
+                        aliasrhs: None,
+                        rhs: CondType::Literal(Lit::String("pan".to_string())),
+                    }))
+                ))
+            )),
+            returning: Vec::new(),
+        }))
+    );
+}
+
+#[test]
+fn test_where_parentheses_and_not() {
+    let cond = |query: &str| match parser::Parser::create(query).parse().unwrap() {
+        Query::ManipulationStmt(ManipulationStmt::Select(stmt)) => stmt.cond.unwrap(),
+        _ => panic!("no select statement"),
+    };
+    let leaf = |col: &str, op, value| {
+        Conditions::Leaf(Condition {
+            aliascol: None,
+            col: col.to_string(),
+            op: op,
+            aliasrhs: None,
+            rhs: CondType::Literal(Lit::Int(value)),
+        })
+    };
+    let and = |a, b| Conditions::And(Box::new(a), Box::new(b));
+    let or = |a, b| Conditions::Or(Box::new(a), Box::new(b));
+
+    assert_eq!(
+        cond("select * from foo where a = 1 or b = 2 and c = 3"),
+        or(
+            leaf("a", CompType::Equ, 1),
+            and(leaf("b", CompType::Equ, 2), leaf("c", CompType::Equ, 3))
+        )
+    );
+    assert_eq!(
+        cond("select * from foo where (a = 1 or b = 2) and c = 3 order by a"),
+        and(
+            or(leaf("a", CompType::Equ, 1), leaf("b", CompType::Equ, 2)),
+            leaf("c", CompType::Equ, 3)
+        )
+    );
+    assert_eq!(
+        cond("select * from foo where ((a = 1)) and not (b = 2 or not c > 3)"),
+        and(
+            leaf("a", CompType::Equ, 1),
+            and(leaf("b", CompType::NEqu, 2), leaf("c", CompType::GThan, 3))
+        )
+    );
+    // the parenthesis starts an expression, not a condition
+    assert_eq!(
+        cond("select * from foo where (a + 1) * 2 > 4"),
+        Conditions::Expr(
+            Lit::Call(
+                "*".into(),
+                vec![
+                    Lit::Call("+".into(), vec![Lit::Column(None, "a".into()), Lit::Int(1)]),
+                    Lit::Int(2),
+                ],
+            ),
+            CompType::GThan,
+            Lit::Int(4),
+        )
+    );
+
+    assert!(
+        parser::Parser::create("select * from foo where (a = 1 or b = 2")
+            .parse()
+            .is_err()
+    );
+    assert!(parser::Parser::create("select * from foo where a = 1 and")
+        .parse()
+        .is_err());
+}
+
+#[test]
+fn test_select_in_and_subqueries() {
+    let select = |query: &str| match parser::Parser::create(query).parse().unwrap() {
+        Query::ManipulationStmt(ManipulationStmt::Select(stmt)) => stmt,
+        _ => panic!("no select statement"),
+    };
+    let column = |name: &str| Lit::Column(None, name.into());
+
+    assert_eq!(
+        select("select * from foo where a not in (1, 2 + 3)").cond,
+        Some(Conditions::In(
+            column("a"),
+            vec![
+                Lit::Int(1),
+                Lit::Call("+".into(), vec![Lit::Int(2), Lit::Int(3)]),
+            ],
+            true,
+        ))
+    );
+    // NOT in front negates the IN
+    assert_eq!(
+        select("select * from foo where not a in (1)").cond,
+        Some(Conditions::In(column("a"), vec![Lit::Int(1)], true))
+    );
+
+    let inner = select("select b from bar");
+    assert_eq!(
+        select("select * from foo where a in (select b from bar)").cond,
+        Some(Conditions::In(
+            column("a"),
+            vec![Lit::Subquery(Box::new(inner.clone()))],
+            false,
+        ))
+    );
+    let stmt = select("select (select b from bar), a from foo where a > (select b from bar)");
+    assert_eq!(
+        stmt.target[0].col,
+        Col::Expr(Lit::Subquery(Box::new(inner.clone())))
+    );
+    assert_eq!(
+        stmt.cond,
+        Some(Conditions::Leaf(Condition {
+            aliascol: None,
+            col: "a".to_string(),
+            op: CompType::GThan,
+            aliasrhs: None,
+            rhs: CondType::Literal(Lit::Subquery(Box::new(inner))),
+        }))
+    );
+    // the subquery may end on its last token or on the one after
+    assert_eq!(
+        select("select * from foo where a in (select b from bar where c = 1 limit 2) order by a")
+            .order
+            .len(),
+        1
+    );
+
+    assert!(
+        parser::Parser::create("select * from foo where a in (select b from bar")
+            .parse()
+            .is_err()
+    );
+    assert!(
+        parser::Parser::create("create table foo (a int, check (a in (select b from bar)))")
+            .parse()
+            .is_err()
+    );
+
+    // NOT in front of EXISTS negates it
+    let inner = select("select * from bar where bar.b = foo.a");
+    assert_eq!(
+        select("select * from foo where exists (select * from bar where bar.b = foo.a)").cond,
+        Some(Conditions::Exists(Box::new(inner.clone()), false))
+    );
+    let stmt = select("select * from foo where a = 1 and not exists (select * from bar)");
+    assert_eq!(
+        stmt.cond,
+        Some(Conditions::And(
+            Box::new(Conditions::Leaf(Condition {
+                aliascol: None,
+                col: "a".to_string(),
+                op: CompType::Equ,
+                aliasrhs: None,
+                rhs: CondType::Literal(Lit::Int(1)),
+            })),
+            Box::new(Conditions::Exists(
+                Box::new(select("select * from bar")),
+                true
+            )),
+        ))
+    );
+    assert!(
+        parser::Parser::create("select * from foo where exists select * from bar")
+            .parse()
+            .is_err()
+    );
+}
+
+#[test]
+fn test_conjuncts() {
+    let leaf = |col: &str| {
+        Conditions::Leaf(Condition {
+            aliascol: None,
+            col: col.to_string(),
+            op: CompType::Equ,
+            aliasrhs: None,
+            rhs: CondType::Literal(Lit::Int(1)),
+        })
+    };
+    let and = |a, b| Conditions::And(Box::new(a), Box::new(b));
+    let or = |a, b| Conditions::Or(Box::new(a), Box::new(b));
+
+    // a or (b and c) is (a or b) and (a or c)
+    let cond = and(or(leaf("a"), and(leaf("b"), leaf("c"))), leaf("d"));
+    assert_eq!(
+        cond.conjuncts(),
+        vec![
+            or(leaf("a"), leaf("b")),
+            or(leaf("a"), leaf("c")),
+            leaf("d")
+        ]
+    );
+    assert_eq!(
+        Conditions::all(cond.conjuncts()).unwrap().conjuncts(),
+        cond.conjuncts()
+    );
+    assert_eq!(Conditions::all(Vec::new()), None);
+
+    // distributing would make too many conjuncts
+    let mut wide = leaf("a");
+    for _ in 0..10 {
+        wide = and(wide, leaf("a"));
+    }
+    let cond = or(wide.clone(), wide);
+    assert_eq!(cond.conjuncts(), vec![cond.clone()]);
+}
+
+#[test]
+fn test_create_user() {
+    let mut p = parser::Parser::create("create user bob identified by 'secret'");
+
+    assert_eq!(
+        p.parse().unwrap(),
+        Query::DefStmt(DefStmt::Create(CreateStmt::User(UserStmt {
+            name: "bob".to_string(),
+            password: "secret".to_string(),
+        })))
+    );
+}
+
+#[test]
+fn test_alter_user_password() {
+    let mut p = parser::Parser::create("alter user bob password 'geheim'");
+
+    assert_eq!(
+        p.parse().unwrap(),
+        Query::DefStmt(DefStmt::Alter(AltStmt::User(UserStmt {
+            name: "bob".to_string(),
+            password: "geheim".to_string(),
+        })))
+    );
+}
+
+#[test]
+fn test_alter_database_limits() {
+    let mut p = parser::Parser::create("alter database shop max_connections 5 max_size = 1048576");
+
+    assert_eq!(
+        p.parse().unwrap(),
+        Query::DefStmt(DefStmt::Alter(AltStmt::Database(AlterDatabaseStmt {
+            name: "shop".to_string(),
+            max_size: Some(1048576),
+            max_connections: Some(5),
+        })))
+    );
+
+    let mut p = parser::Parser::create("alter database shop max_size 0");
+    assert_eq!(
+        p.parse().unwrap(),
+        Query::DefStmt(DefStmt::Alter(AltStmt::Database(AlterDatabaseStmt {
+            name: "shop".to_string(),
+            max_size: Some(0),
+            max_connections: None,
+        })))
+    );
+
+    // at least one limit
+    assert!(parser::Parser::create("alter database shop")
+        .parse()
+        .is_err());
+    assert!(parser::Parser::create("alter database shop max_size -1")
+        .parse()
+        .is_err());
+
+    let mut p = parser::Parser::create("show database status");
+    assert_eq!(
+        p.parse().unwrap(),
+        Query::ManipulationStmt(ManipulationStmt::Show(ShowStmt::DatabaseStatus))
+    );
+}
+
+#[test]
+fn test_drop_user() {
+    let mut p = parser::Parser::create("drop user bob");
+
+    assert_eq!(
+        p.parse().unwrap(),
+        Query::DefStmt(DefStmt::Drop(DropStmt::User("bob".to_string())))
+    );
+}
+
+#[test]
+fn test_grant_table() {
+    let mut p = parser::Parser::create("grant select, insert on shop.orders to bob");
+
+    assert_eq!(
+        p.parse().unwrap(),
+        Query::ControlStmt(ControlStmt::Grant(GrantStmt {
+            privileges: vec![Privilege::Select, Privilege::Insert],
+            database: Some("shop".to_string()),
+            table: Some("orders".to_string()),
+            user: "bob".to_string(),
+        }))
+    );
+}
+
+#[test]
+fn test_grant_all_database() {
+    let mut p = parser::Parser::create("GRANT ALL ON shop.* TO bob");
+
+    assert_eq!(
+        p.parse().unwrap(),
+        Query::ControlStmt(ControlStmt::Grant(GrantStmt {
+            privileges: Privilege::all(),
+            database: Some("shop".to_string()),
+            table: None,
+            user: "bob".to_string(),
+        }))
+    );
+}
+
+#[test]
+fn test_revoke_current_database() {
+    let mut p = parser::Parser::create("revoke drop on * from bob");
+
+    assert_eq!(
+        p.parse().unwrap(),
+        Query::ControlStmt(ControlStmt::Revoke(GrantStmt {
+            privileges: vec![Privilege::Drop],
+            database: None,
+            table: None,
+            user: "bob".to_string(),
+        }))
+    );
+}
+
+#[test]
+fn test_show_grants() {
+    let mut p = parser::Parser::create("show grants");
+    assert_eq!(
+        p.parse().unwrap(),
+        Query::ManipulationStmt(ManipulationStmt::Show(ShowStmt::Grants(None)))
+    );
+
+    let mut p = parser::Parser::create("show grants for bob");
+    assert_eq!(
+        p.parse().unwrap(),
+        Query::ManipulationStmt(ManipulationStmt::Show(ShowStmt::Grants(Some(
+            "bob".to_string()
+        ))))
+    );
+}
+
+#[test]
+fn test_show_processlist() {
+    let mut p = parser::Parser::create("SHOW PROCESSLIST");
+
+    assert_eq!(
+        p.parse().unwrap(),
+        Query::ManipulationStmt(ManipulationStmt::Show(ShowStmt::Processlist))
+    );
+}
+
+#[test]
+fn test_show_progress() {
+    assert_eq!(
+        parser::Parser::create("SHOW PROGRESS FOR 42")
+            .parse()
+            .unwrap(),
+        Query::ManipulationStmt(ManipulationStmt::Show(ShowStmt::Progress(42)))
+    );
+    match parser::Parser::create("show progress for bob").parse() {
+        Err(parser::ParseError::NotANumber(_)) => {}
+        ref q => panic!("unexpected result {:?}", q),
+    }
+    assert!(parser::Parser::create("show progress").parse().is_err());
+    // progress is no keyword
+    assert!(parser::Parser::create("select progress from jobs")
+        .parse()
+        .is_ok());
+}
+
+#[test]
+fn test_show_workers() {
+    assert_eq!(
+        parser::Parser::create("show workers").parse().unwrap(),
+        Query::ManipulationStmt(ManipulationStmt::Show(ShowStmt::Workers))
+    );
+    assert!(parser::Parser::create("show workers now").parse().is_err());
+}
+
+#[test]
+fn test_show_replica_status() {
+    assert_eq!(
+        parser::Parser::create("SHOW REPLICA STATUS")
+            .parse()
+            .unwrap(),
+        Query::ManipulationStmt(ManipulationStmt::Show(ShowStmt::ReplicaStatus))
+    );
+    assert!(parser::Parser::create("show replica").parse().is_err());
+}
+
+#[test]
+fn test_show_status() {
+    let mut p = parser::Parser::create("show status");
+
+    assert_eq!(
+        p.parse().unwrap(),
+        Query::ManipulationStmt(ManipulationStmt::Show(ShowStmt::Status))
+    );
+}
+
+#[test]
+fn test_show_recovery() {
+    let mut p = parser::Parser::create("show recovery");
+
+    assert_eq!(
+        p.parse().unwrap(),
+        Query::ManipulationStmt(ManipulationStmt::Show(ShowStmt::Recovery))
+    );
+}
+
+#[test]
+fn test_show_table_status() {
+    let mut p = parser::Parser::create("show table status");
+
+    assert_eq!(
+        p.parse().unwrap(),
+        Query::ManipulationStmt(ManipulationStmt::Show(ShowStmt::TableStatus))
+    );
+}
+
+#[test]
+fn test_window_functions() {
+    let targets = |query: &str| match parser::Parser::create(query).parse().unwrap() {
+        Query::ManipulationStmt(ManipulationStmt::Select(stmt)) => stmt.target,
+        other => panic!("{:?}", other),
+    };
+    let sort = |alias: Option<&str>, col: &str, order| Sort {
+        alias: alias.map(Into::into),
+        col: col.into(),
+        order: order,
+    };
+    let t = targets(
+        "select name, rank() over (partition by e.dept, year order by salary desc) as r, \
+         sum(salary * 2) over (order by id) from emp e",
+    );
+    assert_eq!(t[0].col, Col::Specified("name".into()));
+    assert_eq!(
+        t[1].col,
+        Col::Window(Window {
+            function: WindowFunction::Rank,
+            partition: vec![sort(Some("e"), "dept", None), sort(None, "year", None)],
+            order: vec![sort(None, "salary", Some(Order::Desc))],
+        })
+    );
+    assert_eq!(t[1].rename, Some("r".into()));
+    assert_eq!(
+        t[2].col,
+        Col::Window(Window {
+            function: WindowFunction::Sum(Lit::Call(
+                "*".into(),
+                vec![Lit::Column(None, "salary".into()), Lit::Int(2)]
+            )),
+            partition: vec![],
+            order: vec![sort(None, "id", Some(Order::Asc))],
+        })
+    );
+    assert_eq!(
+        targets("select row_number() over () from emp")[0].col,
+        Col::Window(Window {
+            function: WindowFunction::RowNumber,
+            partition: vec![],
+            order: vec![],
+        })
+    );
+    // over and partition are no keywords
+    assert_eq!(
+        targets("select over, partition from emp")[1].col,
+        Col::Specified("partition".into())
+    );
+
+    match parser::Parser::create("select upper(name) over () from emp").parse() {
+        Err(parser::ParseError::NotAWindowFunction(span)) => {
+            assert_eq!((span.lo, span.hi), (7, 18))
+        }
+        other => panic!("{:?}", other),
+    }
+    assert!(
+        parser::Parser::create("select rank() over (order by a from emp")
+            .parse()
+            .is_err()
+    );
+}
+
+#[test]
+fn test_show_lists() {
+    let show = |query: &str| match parser::Parser::create(query).parse().unwrap() {
+        Query::ManipulationStmt(ManipulationStmt::Show(stmt)) => stmt,
+        other => panic!("{:?}", other),
+    };
+    assert_eq!(show("show databases"), ShowStmt::Databases);
+    assert_eq!(show("SHOW TABLES"), ShowStmt::Tables);
+    assert_eq!(show("show engines"), ShowStmt::Engines);
+    assert_eq!(
+        show("show columns from foo"),
+        ShowStmt::Columns("foo".into())
+    );
+    assert_eq!(
+        show("show columns in information_schema.tables"),
+        ShowStmt::Columns("information_schema.tables".into())
+    );
+    assert!(parser::Parser::create("show columns foo").parse().is_err());
+}
+
+#[test]
+fn test_notify() {
+    let mut p = parser::Parser::create("notify cache, 'users'");
+
+    assert_eq!(
+        p.parse().unwrap(),
+        Query::ManipulationStmt(ManipulationStmt::Notify(NotifyStmt {
+            channel: "cache".into(),
+            payload: "users".into(),
+        }))
+    );
+
+    let mut p = parser::Parser::create("notify cache");
+
+    assert_eq!(
+        p.parse().unwrap(),
+        Query::ManipulationStmt(ManipulationStmt::Notify(NotifyStmt {
+            channel: "cache".into(),
+            payload: "".into(),
+        }))
+    );
+}
+
+#[test]
+fn test_vacuum() {
+    let mut p = parser::Parser::create("vacuum foo");
+
+    assert_eq!(
+        p.parse().unwrap(),
+        Query::ManipulationStmt(ManipulationStmt::Vacuum(Some("foo".into())))
+    );
+
+    let mut p = parser::Parser::create("VACUUM");
+
+    assert_eq!(
+        p.parse().unwrap(),
+        Query::ManipulationStmt(ManipulationStmt::Vacuum(None))
+    );
+
+    let mut p = parser::Parser::create("vacuum foo bar");
+    assert!(p.parse().is_err());
+}
+
+#[test]
+fn test_check_and_repair_table() {
+    let mut p = parser::Parser::create("check table foo");
+
+    assert_eq!(
+        p.parse().unwrap(),
+        Query::ManipulationStmt(ManipulationStmt::CheckTable("foo".into()))
+    );
+
+    let mut p = parser::Parser::create("REPAIR TABLE foo");
+
+    assert_eq!(
+        p.parse().unwrap(),
+        Query::ManipulationStmt(ManipulationStmt::RepairTable("foo".into()))
+    );
+
+    let mut p = parser::Parser::create("check foo");
+    assert!(p.parse().is_err());
+
+    let mut p = parser::Parser::create("repair table");
+    assert!(p.parse().is_err());
+}
+
+#[test]
+fn test_analyze_and_show_stats() {
+    let mut p = parser::Parser::create("analyze table foo");
+
+    assert_eq!(
+        p.parse().unwrap(),
+        Query::ManipulationStmt(ManipulationStmt::Analyze(Some("foo".into())))
+    );
+
+    let mut p = parser::Parser::create("ANALYZE");
+
+    assert_eq!(
+        p.parse().unwrap(),
+        Query::ManipulationStmt(ManipulationStmt::Analyze(None))
+    );
+
+    let mut p = parser::Parser::create("show stats for foo");
+
+    assert_eq!(
+        p.parse().unwrap(),
+        Query::ManipulationStmt(ManipulationStmt::Show(ShowStmt::Stats(Some("foo".into()))))
+    );
+
+    let mut p = parser::Parser::create("analyze foo");
+    assert!(p.parse().is_err());
+}
+
+#[test]
+fn test_backup_and_restore() {
+    let mut p = parser::Parser::create("backup database shop to 'backups/shop'");
+
+    assert_eq!(
+        p.parse().unwrap(),
+        Query::ManipulationStmt(ManipulationStmt::Backup(BackupStmt {
+            database: "shop".into(),
+            path: "backups/shop".into(),
+        }))
+    );
+
+    let mut p = parser::Parser::create("RESTORE DATABASE FROM 'backups/shop'");
+
+    assert_eq!(
+        p.parse().unwrap(),
+        Query::ManipulationStmt(ManipulationStmt::Restore("backups/shop".into()))
+    );
+}
+
+#[test]
+fn test_dump() {
+    let mut p = parser::Parser::create("dump database shop");
 
     assert_eq!(
         p.parse().unwrap(),
-        Query::DefStmt(DefStmt::Create(CreateStmt::View(CreateViewStmt {
-            name: "foo".to_string(),
-            opt: false,
-            sel: SelectStmt {
-                target: vec![Target {
-                    alias: None,
-                    col: Col::Every,
-                    rename: None,
-                }],
-                tid: vec!["bar".to_string()],
-                alias: HashMap::new(),
-                cond: None,
-                spec_op: None,
-                order: Vec::new(),
-                limit: None,
-            },
-        })))
+        Query::ManipulationStmt(ManipulationStmt::Dump("shop".into()))
     );
 }
 
 #[test]
-fn test_create_view_2() {
-    let mut p = parser::Parser::create("create or replace view foo as select * from bar");
+fn test_select_count() {
+    let mut p = parser::Parser::create("select COUNT(*) as n from orders");
+
+    match p.parse().unwrap() {
+        Query::ManipulationStmt(ManipulationStmt::Select(stmt)) => {
+            assert_eq!(
+                stmt.target,
+                vec![Target {
+                    alias: None,
+                    col: Col::Count,
+                    rename: Some("n".into()),
+                }]
+            );
+            assert_eq!(stmt.tid, vec!["orders".to_string()]);
+        }
+        other => panic!("{:?}", other),
+    }
+    // count may still name a column
+    assert!(parser::Parser::create("select count from orders")
+        .parse()
+        .is_ok());
+    assert!(parser::Parser::create("select sum(*) from orders")
+        .parse()
+        .is_err());
+}
+
+#[test]
+fn test_copy() {
+    let mut p = parser::Parser::create("copy orders from '/tmp/orders.csv'");
 
     assert_eq!(
         p.parse().unwrap(),
-        Query::DefStmt(DefStmt::Create(CreateStmt::View(CreateViewStmt {
-            name: "foo".to_string(),
-            opt: true,
-            sel: SelectStmt {
-                target: vec![Target {
-                    alias: None,
-                    col: Col::Every,
-                    rename: None,
-                }],
-                tid: vec!["bar".to_string()],
-                alias: HashMap::new(),
-                cond: None,
-                spec_op: None,
-                order: Vec::new(),
-                limit: None,
-            },
-        })))
+        Query::ManipulationStmt(ManipulationStmt::Copy(CopyStmt {
+            tid: "orders".into(),
+            path: "/tmp/orders.csv".into(),
+        }))
+    );
+
+    let mut p = parser::Parser::create("load data infile '/tmp/orders.csv' into table orders");
+    assert_eq!(
+        p.parse().unwrap(),
+        Query::ManipulationStmt(ManipulationStmt::Copy(CopyStmt {
+            tid: "orders".into(),
+            path: "/tmp/orders.csv".into(),
+        }))
+    );
+    assert!(
+        parser::Parser::create("load infile '/tmp/orders.csv' into table orders")
+            .parse()
+            .is_err()
     );
 }
 
 #[test]
-fn test_update_full_with_table_alias() {
-    let mut p = parser::Parser::create("update foo bar set bar_1 = 1 where bar.bar_2 > 'pleb'");
-    let mut aliashm = HashMap::new();
-    aliashm.insert("bar".to_string(), "foo".to_string());
-    let set_vec = vec![Condition {
-        aliascol: None,
-        col: "bar_1".to_string(),
-        op: CompType::Equ,
-        aliasrhs: None,
-        rhs: CondType::Literal(Lit::Int(1)),
-    }];
+fn test_insert_rows() {
+    let mut p = parser::Parser::create("insert into foo (a, b) values (1, 'x'), (2, null)");
 
     assert_eq!(
         p.parse().unwrap(),
-        Query::ManipulationStmt(ManipulationStmt::Update(UpdateStmt {
-            tid: "foo".to_string(),
-            alias: aliashm,
-            set: set_vec,
-            conds: Some(Conditions::Leaf(Condition {
-                aliascol: Some("bar".to_string()),
-                col: "bar_2".to_string(),
-                op: CompType::GThan,
-                aliasrhs: None,
-                rhs: CondType::Literal(Lit::String("pleb".to_string())),
-            }))
+        Query::ManipulationStmt(ManipulationStmt::Insert(InsertStmt {
+            tid: "foo".into(),
+            col: vec!["a".into(), "b".into()],
+            val: vec![
+                vec![Lit::Int(1), Lit::String("x".into())],
+                vec![Lit::Int(2), Lit::Null],
+            ],
+            on_duplicate: None,
         }))
     );
+    assert!(
+        parser::Parser::create("insert into foo (a, b) values (1, 'x'), (2)")
+            .parse()
+            .is_err()
+    );
+    assert!(parser::Parser::create("insert into foo values (1), ")
+        .parse()
+        .is_err());
 }
 
 #[test]
-fn test_mult_where_blocks_3_param() {
+fn test_insert_select() {
+    let mut p = parser::Parser::create("insert into foo (a, b) select c, d from bar where c > 1");
+
+    let select = match parser::Parser::create("select c, d from bar where c > 1")
+        .parse()
+        .unwrap()
+    {
+        Query::ManipulationStmt(ManipulationStmt::Select(stmt)) => stmt,
+        other => panic!("{:?}", other),
+    };
+    assert_eq!(
+        p.parse().unwrap(),
+        Query::ManipulationStmt(ManipulationStmt::InsertSelect(InsertSelectStmt {
+            tid: "foo".into(),
+            col: vec!["a".into(), "b".into()],
+            select: CompoundStmt {
+                select: SetExpr::Select(select),
+                order: Vec::new(),
+                limit: None,
+            },
+            on_duplicate: None,
+        }))
+    );
+    match parser::Parser::create("insert into foo select a from bar union select a from baz")
+        .parse()
+        .unwrap()
+    {
+        Query::ManipulationStmt(ManipulationStmt::InsertSelect(stmt)) => {
+            assert!(stmt.col.is_empty());
+            match stmt.select.select {
+                SetExpr::Op(SetOp::Union, _, _) => (),
+                other => panic!("{:?}", other),
+            }
+        }
+        other => panic!("{:?}", other),
+    }
+}
+
+#[test]
+fn test_insert_on_duplicate() {
     let mut p = parser::Parser::create(
-        "delete from foo where lname = 'peng' or
-        fname = 'peter' and lname = 'pan'",
+        "insert into foo (id, n) values (1, 2) on duplicate key update n = n + values(n), m = 0",
     );
 
     assert_eq!(
         p.parse().unwrap(),
-        Query::ManipulationStmt(ManipulationStmt::Delete(DeleteStmt {
-            tid: "foo".to_string(),
-            alias: HashMap::new(),
-            cond: Some(Conditions::Or(
-                Box::new(Conditions::Leaf(Condition {
+        Query::ManipulationStmt(ManipulationStmt::Insert(InsertStmt {
+            tid: "foo".into(),
+            col: vec!["id".into(), "n".into()],
+            val: vec![vec![Lit::Int(1), Lit::Int(2)]],
+            on_duplicate: Some(OnDuplicate::Update(vec![
+                Condition {
                     aliascol: None,
-                    col: "lname".to_string(),
+                    col: "n".into(),
                     op: CompType::Equ,
                     aliasrhs: None,
-                    rhs: CondType::Literal(Lit::String("peng".to_string())),
-                })),
-                Box::new(Conditions::And(
-                    Box::new(Conditions::Leaf(Condition {
-                        aliascol: None,
-                        col: "fname".to_string(),
-                        op: CompType::Equ,
-                        aliasrhs: None,
-                        rhs: CondType::Literal(Lit::String("peter".to_string())),
-                    })),
-                    Box::new(Conditions::Leaf(Condition {
-                        aliascol: None,
-                        col: "lname".to_string(),
-                        op: CompType::Equ,
-                        aliasrhs: None,
-                        rhs: CondType::Literal(Lit::String("pan".to_string())),
-                    }))
-                ))
-            ))
+                    rhs: CondType::Literal(Lit::Call(
+                        "+".into(),
+                        vec![
+                            Lit::Column(None, "n".into()),
+                            Lit::Call("values".into(), vec![Lit::Column(None, "n".into())]),
+                        ],
+                    )),
+                },
+                Condition {
+                    aliascol: None,
+                    col: "m".into(),
+                    op: CompType::Equ,
+                    aliasrhs: None,
+                    rhs: CondType::Literal(Lit::Int(0)),
+                },
+            ])),
         }))
     );
+
+    match parser::Parser::create("insert ignore into foo values (1), (2)")
+        .parse()
+        .unwrap()
+    {
+        Query::ManipulationStmt(ManipulationStmt::Insert(stmt)) => {
+            assert_eq!(stmt.on_duplicate, Some(OnDuplicate::Ignore));
+            assert_eq!(stmt.val.len(), 2);
+        }
+        other => panic!("{:?}", other),
+    }
+    match parser::Parser::create("insert ignore into foo select a from bar")
+        .parse()
+        .unwrap()
+    {
+        Query::ManipulationStmt(ManipulationStmt::InsertSelect(stmt)) => {
+            assert_eq!(stmt.on_duplicate, Some(OnDuplicate::Ignore))
+        }
+        other => panic!("{:?}", other),
+    }
+    assert!(
+        parser::Parser::create("insert into foo values (1) on duplicate key update n = 1 x")
+            .parse()
+            .is_err()
+    );
+    assert!(
+        parser::Parser::create("insert into foo values (1) on duplicate update n = 1")
+            .parse()
+            .is_err()
+    );
 }
 
 #[test]
-fn test_mult_where_blocks_priority_4_param() {
+fn test_set_operations() {
     let mut p = parser::Parser::create(
-        "delete from foo where
-        fname = 'Eugene' and lname = 'peng' or
-        fname = 'peter' and lname = 'pan'",
+        "select a from foo union all select a from bar intersect select b from baz \
+         except select c from qux order by a desc limit 5",
     );
 
+    let select = |col: &str, tid: &str| match parser::Parser::create(&format!(
+        "select {} from {}",
+        col, tid
+    ))
+    .parse()
+    .unwrap()
+    {
+        Query::ManipulationStmt(ManipulationStmt::Select(stmt)) => SetExpr::Select(stmt),
+        other => panic!("{:?}", other),
+    };
+    // INTERSECT binds tighter than UNION and EXCEPT
+    let intersection = SetExpr::Op(
+        SetOp::Intersect,
+        Box::new(select("a", "bar")),
+        Box::new(select("b", "baz")),
+    );
+    let union = SetExpr::Op(
+        SetOp::UnionAll,
+        Box::new(select("a", "foo")),
+        Box::new(intersection),
+    );
     assert_eq!(
         p.parse().unwrap(),
-        Query::ManipulationStmt(ManipulationStmt::Delete(DeleteStmt {
-            tid: "foo".to_string(),
-            alias: HashMap::new(),
-            cond: Some(Conditions::Or(
-                Box::new(Conditions::And(
-                    Box::new(Conditions::Leaf(Condition {
-                        aliascol: None,
-                        col: "fname".to_string(),
-                        op: CompType::Equ,
-                        aliasrhs: None,
-                        rhs: CondType::Literal(Lit::String("Eugene".to_string())),
-                    })),
-                    Box::new(Conditions::Leaf(Condition {
-                        aliascol: None,
-                        col: "lname".to_string(),
-                        op: CompType::Equ,
-                        aliasrhs: None,
-                        rhs: CondType::Literal(Lit::String("peng".to_string())),
-                    }))
-                )),
-                Box::new(Conditions::And(
-                    Box::new(Conditions::Leaf(Condition {
-                        aliascol: None,
-                        col: "fname".to_string(),
-                        op: CompType::Equ,
-                        aliasrhs: None,
-                        rhs: CondType::Literal(Lit::String("peter".to_string())),
-                    })),
-                    Box::new(Conditions::Leaf(Condition {
-                        aliascol: None,
-                        col: "lname".to_string(),
-                        op: CompType::Equ,
-                        aliasrhs: None,
-                        rhs: CondType::Literal(Lit::String("pan".to_string())),
-                    }))
-                ))
-            ))
+        Query::ManipulationStmt(ManipulationStmt::Compound(CompoundStmt {
+            select: SetExpr::Op(SetOp::Except, Box::new(union), Box::new(select("c", "qux"))),
+            order: vec![Sort {
+                alias: None,
+                col: "a".into(),
+                order: Some(Order::Desc),
+            }],
+            limit: Some(Limit {
+                count: Some(5),
+                offset: None,
+            }),
         }))
     );
+
+    // ORDER BY and LIMIT belong to the whole statement
+    assert!(
+        parser::Parser::create("select a from foo limit 1 union select a from bar")
+            .parse()
+            .is_err()
+    );
+    assert!(parser::Parser::create("select a from foo union a from bar")
+        .parse()
+        .is_err());
 }
 
 /*#[test]
@@ -1005,7 +3184,7 @@ fn err_create_table_error1() {
 #[test]
 fn err_create_keyword1() {
     let mut p = parser::Parser::create("   table create");
-    let sol = parser::ParseError::WrongKeyword(Span { lo: 5, hi: 10 });
+    let sol = parser::ParseError::WrongKeyword(Span { lo: 3, hi: 8 });
 
     assert_eq!(p.parse(), Err(sol));
 }
@@ -1014,7 +3193,7 @@ fn err_create_keyword1() {
 #[test]
 fn err_create_wrong_token_1() {
     let mut p = parser::Parser::create("create table Studenten )");
-    let sol = parser::ParseError::WrongToken(Span { lo: 24, hi: 24 });
+    let sol = parser::ParseError::WrongToken(Span { lo: 23, hi: 24 });
 
     assert_eq!(p.parse(), Err(sol));
 }
@@ -1022,7 +3201,7 @@ fn err_create_wrong_token_1() {
 #[test]
 fn err_create_wrong_token_2() {
     let mut p = parser::Parser::create("create table studenten (asd int(");
-    let sol = parser::ParseError::WrongToken(Span { lo: 32, hi: 32 });
+    let sol = parser::ParseError::WrongToken(Span { lo: 31, hi: 32 });
 
     assert_eq!(p.parse(), Err(sol));
 }
@@ -1030,7 +3209,44 @@ fn err_create_wrong_token_2() {
 #[test]
 fn err_create_wrong_token_3() {
     let mut p = parser::Parser::create("create table studenten (asd asd)");
-    let sol = parser::ParseError::NotADatatype(Span { lo: 30, hi: 32 });
+    let sol = parser::ParseError::NotADatatype(Span { lo: 28, hi: 31 });
+
+    assert_eq!(p.parse(), Err(sol));
+}
+
+#[test]
+fn err_create_unknown_engine() {
+    let mut p = parser::Parser::create("create table t (a int) engine = heap");
+    let sol = parser::ParseError::NotAnEngine(Span { lo: 32, hi: 36 });
+
+    assert_eq!(p.parse(), Err(sol));
+}
+
+#[test]
+fn err_match_missing_parenthesis() {
+    let mut p = parser::Parser::create("delete from foo where match(body, 'x' and");
+    let sol = parser::ParseError::MissingParenthesis(Span { lo: 38, hi: 41 });
+
+    assert_eq!(p.parse(), Err(sol));
+}
+
+#[test]
+fn err_create_index_missing_parenthesis() {
+    let mut p = parser::Parser::create("create index i on foo (name");
+    let sol = parser::ParseError::UnexpectedEoq;
+
+    assert_eq!(p.parse(), Err(sol));
+
+    let mut p = parser::Parser::create("create index i on foo (name, id)");
+    let sol = parser::ParseError::MissingParenthesis(Span { lo: 27, hi: 28 });
+
+    assert_eq!(p.parse(), Err(sol));
+}
+
+#[test]
+fn err_start_without_transaction() {
+    let mut p = parser::Parser::create("start work");
+    let sol = parser::ParseError::WrongKeyword(Span { lo: 6, hi: 10 });
 
     assert_eq!(p.parse(), Err(sol));
 }
@@ -1038,7 +3254,7 @@ fn err_create_wrong_token_3() {
 #[test]
 fn err_create_missing_parenthesis() {
     let mut p = parser::Parser::create("create table studenten asd int)");
-    let sol = parser::ParseError::WrongToken(Span { lo: 25, hi: 28 });
+    let sol = parser::ParseError::WrongToken(Span { lo: 23, hi: 26 });
 
     assert_eq!(p.parse(), Err(sol));
 }
@@ -1046,7 +3262,7 @@ fn err_create_missing_parenthesis() {
 #[test]
 fn err_create_not_a_keyword_1() {
     let mut p = parser::Parser::create("hallo table studenten");
-    let sol = parser::ParseError::NotAKeyword(Span { lo: 2, hi: 7 });
+    let sol = parser::ParseError::NotAKeyword(Span { lo: 0, hi: 5 });
 
     assert_eq!(p.parse(), Err(sol));
 }
@@ -1054,7 +3270,7 @@ fn err_create_not_a_keyword_1() {
 #[test]
 fn err_create_not_a_keyword_2() {
     let mut p = parser::Parser::create("create asd Studenten");
-    let sol = parser::ParseError::NotAKeyword(Span { lo: 9, hi: 12 });
+    let sol = parser::ParseError::NotAKeyword(Span { lo: 7, hi: 10 });
 
     assert_eq!(p.parse(), Err(sol));
 }
@@ -1078,7 +3294,7 @@ fn err_create_invalid_eoq_2() {
 #[test]
 fn err_describe() {
     let mut p = parser::Parser::create("describe ,");
-    let sol = parser::ParseError::NotAWord(Span { lo: 10, hi: 10 });
+    let sol = parser::ParseError::NotAWord(Span { lo: 9, hi: 10 });
 
     assert_eq!(p.parse(), Err(sol));
 }
@@ -1086,7 +3302,7 @@ fn err_describe() {
 #[test]
 fn err_describe_2() {
     let mut p = parser::Parser::create("describe table");
-    let sol = parser::ParseError::ReservedKeyword(Span { lo: 11, hi: 14 });
+    let sol = parser::ParseError::ReservedKeyword(Span { lo: 9, hi: 14 });
 
     assert_eq!(p.parse(), Err(sol));
 }
@@ -1094,7 +3310,7 @@ fn err_describe_2() {
 #[test]
 fn err_alter_1() {
     let mut p = parser::Parser::create("alter table table add bar int");
-    let sol = parser::ParseError::ReservedKeyword(Span { lo: 14, hi: 19 });
+    let sol = parser::ParseError::ReservedKeyword(Span { lo: 12, hi: 17 });
 
     assert_eq!(p.parse(), Err(sol));
 }
@@ -1102,7 +3318,7 @@ fn err_alter_1() {
 #[test]
 fn err_alter_2() {
     let mut p = parser::Parser::create("alter table foo add bar foo");
-    let sol = parser::ParseError::NotADatatype(Span { lo: 26, hi: 27 });
+    let sol = parser::ParseError::NotADatatype(Span { lo: 24, hi: 27 });
 
     assert_eq!(p.parse(), Err(sol));
 }
@@ -1110,7 +3326,7 @@ fn err_alter_2() {
 #[test]
 fn err_alter_3() {
     let mut p = parser::Parser::create("alter table foo drop bar_1");
-    let sol = parser::ParseError::NotAKeyword(Span { lo: 23, hi: 26 });
+    let sol = parser::ParseError::NotAKeyword(Span { lo: 21, hi: 26 });
 
     assert_eq!(p.parse(), Err(sol));
 }
@@ -1118,7 +3334,7 @@ fn err_alter_3() {
 #[test]
 fn err_alter_5() {
     let mut p = parser::Parser::create("alter table foo add (bar int");
-    let sol = parser::ParseError::NotAWord(Span { lo: 22, hi: 23 });
+    let sol = parser::ParseError::NotAWord(Span { lo: 20, hi: 21 });
 
     assert_eq!(p.parse(), Err(sol));
 }
@@ -1126,7 +3342,7 @@ fn err_alter_5() {
 #[test]
 fn err_alter_6() {
     let mut p = parser::Parser::create("alter table foo drop column (");
-    let sol = parser::ParseError::NotAWord(Span { lo: 29, hi: 29 });
+    let sol = parser::ParseError::NotAWord(Span { lo: 28, hi: 29 });
 
     assert_eq!(p.parse(), Err(sol));
 }
@@ -1134,7 +3350,7 @@ fn err_alter_6() {
 #[test]
 fn err_alter_8() {
     let mut p = parser::Parser::create("alter table foo modify asd");
-    let sol = parser::ParseError::NotAKeyword(Span { lo: 25, hi: 26 });
+    let sol = parser::ParseError::NotAKeyword(Span { lo: 23, hi: 26 });
 
     assert_eq!(p.parse(), Err(sol));
 }
@@ -1142,7 +3358,7 @@ fn err_alter_8() {
 #[test]
 fn err_alter_9() {
     let mut p = parser::Parser::create("alter table foo modify column bar asd");
-    let sol = parser::ParseError::NotADatatype(Span { lo: 36, hi: 37 });
+    let sol = parser::ParseError::NotADatatype(Span { lo: 34, hi: 37 });
 
     assert_eq!(p.parse(), Err(sol));
 }
@@ -1158,7 +3374,7 @@ fn err_alter_10() {
 #[test]
 fn err_use_1() {
     let mut p = parser::Parser::create("use table foo");
-    let sol = parser::ParseError::WrongKeyword(Span { lo: 6, hi: 11 });
+    let sol = parser::ParseError::WrongKeyword(Span { lo: 4, hi: 9 });
 
     assert_eq!(p.parse(), Err(sol));
 }
@@ -1166,7 +3382,7 @@ fn err_use_1() {
 #[test]
 fn err_use_2() {
     let mut p = parser::Parser::create("use database use");
-    let sol = parser::ParseError::ReservedKeyword(Span { lo: 15, hi: 16 });
+    let sol = parser::ParseError::ReservedKeyword(Span { lo: 13, hi: 16 });
 
     assert_eq!(p.parse(), Err(sol));
 }
@@ -1174,7 +3390,7 @@ fn err_use_2() {
 #[test]
 fn err_use_3() {
     let mut p = parser::Parser::create("use database 1");
-    let sol = parser::ParseError::NotAWord(Span { lo: 14, hi: 14 });
+    let sol = parser::ParseError::NotAWord(Span { lo: 13, hi: 14 });
 
     assert_eq!(p.parse(), Err(sol));
 }
@@ -1182,7 +3398,7 @@ fn err_use_3() {
 #[test]
 fn err_drop_1() {
     let mut p = parser::Parser::create("drop foo");
-    let sol = parser::ParseError::NotAKeyword(Span { lo: 7, hi: 8 });
+    let sol = parser::ParseError::NotAKeyword(Span { lo: 5, hi: 8 });
 
     assert_eq!(p.parse(), Err(sol));
 }
@@ -1190,7 +3406,7 @@ fn err_drop_1() {
 #[test]
 fn err_drop_2() {
     let mut p = parser::Parser::create("drop table table");
-    let sol = parser::ParseError::ReservedKeyword(Span { lo: 13, hi: 16 });
+    let sol = parser::ParseError::ReservedKeyword(Span { lo: 11, hi: 16 });
 
     assert_eq!(p.parse(), Err(sol));
 }
@@ -1198,7 +3414,7 @@ fn err_drop_2() {
 #[test]
 fn err_drop_3() {
     let mut p = parser::Parser::create("drop table ]");
-    let sol = parser::ParseError::NotAWord(Span { lo: 12, hi: 12 });
+    let sol = parser::ParseError::NotAWord(Span { lo: 11, hi: 12 });
 
     assert_eq!(p.parse(), Err(sol));
 }
@@ -1206,7 +3422,7 @@ fn err_drop_3() {
 #[test]
 fn err_insert_1() {
     let mut p = parser::Parser::create("insert a");
-    let sol = parser::ParseError::NotAKeyword(Span { lo: 8, hi: 8 });
+    let sol = parser::ParseError::NotAKeyword(Span { lo: 7, hi: 8 });
 
     assert_eq!(p.parse(), Err(sol));
 }
@@ -1214,7 +3430,7 @@ fn err_insert_1() {
 #[test]
 fn err_insert_2() {
     let mut p = parser::Parser::create("insert into into");
-    let sol = parser::ParseError::ReservedKeyword(Span { lo: 14, hi: 16 });
+    let sol = parser::ParseError::ReservedKeyword(Span { lo: 12, hi: 16 });
 
     assert_eq!(p.parse(), Err(sol));
 }
@@ -1222,7 +3438,7 @@ fn err_insert_2() {
 #[test]
 fn err_insert_3() {
     let mut p = parser::Parser::create("insert into foo bar ('⊂(▀¯▀⊂)', 420, 'lel'");
-    let sol = parser::ParseError::NotAKeyword(Span { lo: 18, hi: 21 });
+    let sol = parser::ParseError::NotAKeyword(Span { lo: 16, hi: 19 });
 
     assert_eq!(p.parse(), Err(sol));
 }
@@ -1234,3 +3450,129 @@ fn err_insert_4() {
 
     assert_eq!(p.parse(), Err(sol));
 }
+
+#[test]
+fn err_recovery() {
+    let script = "drop table; use database foo; select * from 'x; drop table bar";
+    let first = || parser::ParseError::WrongToken(Span { lo: 10, hi: 11 });
+
+    assert_eq!(
+        parser::Parser::create(script).parse_statements(),
+        Err(first())
+    );
+    // the unclosed string swallows the rest of the script
+    assert_eq!(
+        parser::Parser::create(script)
+            .recovering()
+            .parse_statements(),
+        Err(parser::ParseError::Several(vec![
+            first(),
+            parser::ParseError::LexError(LexError::UnclosedQuotationmark(Span { lo: 44, hi: 45 })),
+        ]))
+    );
+    assert_eq!(
+        parser::Parser::create("drop table foo; drop table bar")
+            .recovering()
+            .parse_statements()
+            .map(|queries| queries.len()),
+        Ok(2)
+    );
+}
+
+#[test]
+fn err_quotes() {
+    let err = |q: &str| parser::Parser::create(q).parse().unwrap_err();
+
+    assert_eq!(
+        err("select * from foo where a = 'x"),
+        parser::ParseError::LexError(LexError::UnclosedQuotationmark(Span { lo: 28, hi: 29 }))
+    );
+    assert_eq!(
+        err("select * from foo where a = 'x\\qy'"),
+        parser::ParseError::LexError(LexError::UnknownEscape(Span { lo: 30, hi: 32 }))
+    );
+    assert_eq!(
+        err("select * from `foo` where a = 'x\\"),
+        parser::ParseError::LexError(LexError::UnclosedQuotationmark(Span { lo: 30, hi: 31 }))
+    );
+    assert_eq!(
+        err("create table \"a/b\" (id int)"),
+        parser::ParseError::LexError(LexError::InvalidNameChar(Span { lo: 15, hi: 16 }))
+    );
+    assert_eq!(
+        err("select * from foo /* where"),
+        parser::ParseError::LexError(LexError::UnclosedComment(Span { lo: 18, hi: 20 }))
+    );
+    assert_eq!(
+        err("drop table ``"),
+        parser::ParseError::LexError(LexError::EmptyName(Span { lo: 11, hi: 13 }))
+    );
+}
+
+#[test]
+fn err_create_user_password_no_string() {
+    let mut p = parser::Parser::create("create user bob identified by 42");
+    let sol = parser::ParseError::PasswordIsNoString;
+
+    assert_eq!(p.parse(), Err(sol));
+}
+
+#[test]
+fn err_grant_missing_user() {
+    let mut p = parser::Parser::create("grant select on foo");
+    let sol = parser::ParseError::UnexpectedEoq;
+
+    assert_eq!(p.parse(), Err(sol));
+}
+
+#[test]
+fn err_notify_payload_no_string() {
+    let mut p = parser::Parser::create("notify cache, 42");
+    let sol = parser::ParseError::PayloadIsNoString;
+
+    assert_eq!(p.parse(), Err(sol));
+}
+
+#[test]
+fn err_backup_path_no_string() {
+    let mut p = parser::Parser::create("backup database shop to 42");
+    let sol = parser::ParseError::PathIsNoString;
+
+    assert_eq!(p.parse(), Err(sol));
+}
+
+#[test]
+fn test_explain() {
+    match parser::Parser::create("explain select a from foo where a > 1 order by a limit 3")
+        .parse()
+        .unwrap()
+    {
+        Query::ManipulationStmt(ManipulationStmt::Explain(stmt)) => {
+            assert!(stmt.order.is_empty());
+            match stmt.select {
+                SetExpr::Select(select) => {
+                    assert_eq!(select.tid, vec!["foo".to_string()]);
+                    assert!(select.cond.is_some());
+                    assert_eq!(select.order.len(), 1);
+                    assert!(select.limit.is_some());
+                }
+                other => panic!("{:?}", other),
+            }
+        }
+        other => panic!("{:?}", other),
+    }
+    match parser::Parser::create("explain select a from foo union select a from bar")
+        .parse()
+        .unwrap()
+    {
+        Query::ManipulationStmt(ManipulationStmt::Explain(stmt)) => match stmt.select {
+            SetExpr::Op(SetOp::Union, _, _) => (),
+            other => panic!("{:?}", other),
+        },
+        other => panic!("{:?}", other),
+    }
+    assert!(parser::Parser::create("explain delete from foo")
+        .parse()
+        .is_err());
+    assert!(parser::Parser::create("explain").parse().is_err());
+}
@@ -1,19 +1,44 @@
 use super::Span;
 use parse::ast::*;
+use storage::decimal;
 use storage::SqlType;
+
+use serde::{Deserialize, Serialize};
 /// A token with it's associated Span in the source code
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct TokenSpan {
     pub tok: Token,
     pub span: Span,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Lit {
     String(String),
     Int(i64),
     Float(f64),
+    /// A number with a point, its digits and the number of digits after
+    /// the point, see `storage::decimal`
+    Decimal(i64, u8),
     Bool(u8),
+    /// `X'0A1B'`, bytes given as hex digits
+    Bytes(Vec<u8>),
+    /// The missing value of a nullable column
+    Null,
+    /// `DATE 'YYYY-MM-DD'`, days since 1970-01-01
+    Date(i32),
+    /// `TIME 'HH:MM:SS'`, microseconds since midnight
+    Time(i64),
+    /// `TIMESTAMP 'YYYY-MM-DD HH:MM:SS'`, microseconds since 1970-01-01
+    Timestamp(i64),
+    /// A function call like `NOW()`, evaluated by the executor
+    Call(String, Vec<Lit>),
+    /// A column of the row, with an optional table alias, in expressions of
+    /// `SELECT` and `WHERE` like `price * qty`
+    Column(Option<String>, String),
+    /// `(SELECT ...)`, replaced by its result before the statement around it
+    /// is executed. It is never stored, like in a check constraint.
+    #[serde(skip)]
+    Subquery(Box<SelectStmt>),
 }
 
 impl Lit {
@@ -22,7 +47,32 @@ impl Lit {
             &Lit::String(ref s) => DataSrc::String(s.clone()),
             &Lit::Int(ref i) => DataSrc::Int(i.clone()),
             &Lit::Float(ref f) => DataSrc::String(f.to_string()),
+            &Lit::Decimal(d, scale) => DataSrc::String(decimal::format(d, scale)),
             &Lit::Bool(ref b) => DataSrc::Bool(b.clone()),
+            &Lit::Bytes(ref b) => DataSrc::String(String::from_utf8_lossy(b).into_owned()),
+            &Lit::Null => DataSrc::Null,
+            &Lit::Date(d) => DataSrc::Int(d as i64),
+            &Lit::Time(t) | &Lit::Timestamp(t) => DataSrc::Int(t),
+            // calls have no value before the executor evaluated them
+            &Lit::Call(..) | &Lit::Column(..) | &Lit::Subquery(_) => DataSrc::Null,
+        }
+    }
+
+    /// Returns the names of the columns an expression refers to.
+    pub fn columns(&self) -> Vec<&str> {
+        match self {
+            &Lit::Column(_, ref name) => vec![name],
+            &Lit::Call(_, ref args) => args.iter().flat_map(|a| a.columns()).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Returns true if the expression contains a `(SELECT ...)`.
+    pub fn has_subquery(&self) -> bool {
+        match self {
+            &Lit::Subquery(_) => true,
+            &Lit::Call(_, ref args) => args.iter().any(Lit::has_subquery),
+            _ => false,
         }
     }
 
@@ -30,8 +80,16 @@ impl Lit {
         match self {
             &Lit::String(_) => SqlType::Char(0),
             &Lit::Int(_) => SqlType::Int,
-            &Lit::Float(_) => SqlType::Char(0),
+            &Lit::Float(_) => SqlType::Double,
+            &Lit::Decimal(_, scale) => SqlType::Decimal(decimal::MAX_PRECISION, scale),
             &Lit::Bool(_) => SqlType::Bool,
+            &Lit::Bytes(ref b) => SqlType::VarBinary(b.len() as u32),
+            // null has no type of its own, callers check for it first
+            &Lit::Null => SqlType::Char(0),
+            &Lit::Date(_) => SqlType::Date,
+            &Lit::Time(_) => SqlType::Time,
+            &Lit::Timestamp(_) => SqlType::Timestamp,
+            &Lit::Call(..) | &Lit::Column(..) | &Lit::Subquery(_) => SqlType::Char(0),
         }
     }
 }
@@ -40,6 +98,8 @@ impl Lit {
 #[derive(Debug, Clone, PartialEq)]
 pub enum Token {
     Word(String),
+    /// A name in double quotes or backticks, never a keyword
+    QuotedWord(String),
 
     // detects literals
     Literal(Lit),
@@ -66,6 +126,8 @@ pub enum Token {
     Sub,
     Div,
     Mod,
+    /// `||`, concatenates strings
+    Concat,
 
     // sensitive Wildcard/Mult, eval in parser
     Star,
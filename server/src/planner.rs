@@ -0,0 +1,241 @@
+//! Cost-based planning of selects
+//!
+//! Before a select is executed, the executor decides how to read its
+//! tables by the estimates of this module: whether a table is read through
+//! an index or by a full scan, which conditions are checked on the rows of
+//! a single table before they are joined, and in which order the tables
+//! are joined. The estimates come from the statistics of `ANALYZE`, see
+//! `storage::stats`; without them the tables are joined in the order of
+//! the statement and an index is used wherever one fits.
+//!
+//! Costs count rows read and compared: a row of a full scan costs
+//! `SCAN_ROW_COST`, one found through an index `INDEX_ROW_COST`, as an
+//! index reads the rows one by one. A join costs its inputs and its result
+//! if it joins on equal columns, like a hash join, and every pair of rows
+//! otherwise, like a nested loop.
+
+/// Cost of reading a row in a full scan
+pub const SCAN_ROW_COST: f64 = 1.0;
+/// Cost of reading a row found through an index
+pub const INDEX_ROW_COST: f64 = 3.0;
+/// Share of the rows assumed for a condition the statistics do not cover
+pub const DEFAULT_SHARE: f64 = 1.0 / 3.0;
+/// Most tables whose join orders are all compared, more are joined in the
+/// order of the statement
+pub const MAX_REORDERED: usize = 6;
+
+/// Estimated number of rows and cost of a part of a plan
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Estimate {
+    pub rows: f64,
+    pub cost: f64,
+}
+
+impl Estimate {
+    /// Returns the estimate of a full scan of rows.
+    pub fn scan(rows: f64) -> Estimate {
+        Estimate {
+            rows: rows,
+            cost: rows * SCAN_ROW_COST,
+        }
+    }
+
+    /// Returns the estimate of reading a share of rows through an index.
+    pub fn lookup(rows: f64, share: f64) -> Estimate {
+        let rows = rows * share;
+        Estimate {
+            rows: rows,
+            cost: rows * INDEX_ROW_COST,
+        }
+    }
+
+    /// Returns the estimate of the rows fulfilling a condition.
+    pub fn filter(self, share: f64) -> Estimate {
+        Estimate {
+            rows: self.rows * share,
+            cost: self.cost,
+        }
+    }
+
+    /// Returns the estimate of sorting the rows.
+    pub fn sort(self) -> Estimate {
+        Estimate {
+            rows: self.rows,
+            cost: self.cost + self.rows * self.rows.max(2.0).log2(),
+        }
+    }
+}
+
+/// Returns whether reading a share of the rows of a table through an index
+/// is cheaper than a full scan. An unknown share is taken to pay off.
+pub fn pays_off(share: Option<f64>) -> bool {
+    share.map_or(true, |share| share * INDEX_ROW_COST < SCAN_ROW_COST)
+}
+
+/// Chooses the cheapest of the indexes a table may be read through, given
+/// by the shares of its rows they read, or None for a full scan. Indexes
+/// with an unknown share are only chosen, the first of them, if no share is
+/// known.
+pub fn choose_access(shares: &[Option<f64>]) -> Option<usize> {
+    let known = shares
+        .iter()
+        .enumerate()
+        .filter_map(|(i, share)| share.map(|share| (i, share)))
+        .fold(None, |best: Option<(usize, f64)>, (i, share)| match best {
+            Some((_, least)) if least <= share => best,
+            _ => Some((i, share)),
+        });
+    match known {
+        Some((i, share)) if pays_off(Some(share)) => Some(i),
+        Some(_) => None,
+        None => shares.iter().position(Option::is_none),
+    }
+}
+
+/// A conjunct of the conditions joining tables
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Predicate {
+    /// The tables it refers to, bit `i` stands for the table at `i`
+    pub tables: u32,
+    /// Estimated share of the pairs of rows fulfilling it
+    pub share: f64,
+    /// Whether it compares columns for equality
+    pub equi: bool,
+}
+
+/// Returns the estimate of joining the rows of `outer`, made of the tables
+/// in `joined`, with those of the table at `inner`.
+pub fn join(
+    outer: Estimate,
+    joined: u32,
+    input: Estimate,
+    inner: usize,
+    predicates: &[Predicate],
+) -> Estimate {
+    let all = joined | 1 << inner;
+    let applied = predicates
+        .iter()
+        .filter(|p| p.tables & all == p.tables && p.tables & 1 << inner != 0);
+    let mut share = 1.0;
+    let mut equi = false;
+    for predicate in applied {
+        share *= predicate.share;
+        equi |= predicate.equi;
+    }
+    let rows = outer.rows * input.rows * share;
+    let compared = if equi {
+        outer.rows + input.rows
+    } else {
+        outer.rows * input.rows
+    };
+    Estimate {
+        rows: rows,
+        cost: outer.cost + input.cost + compared + rows,
+    }
+}
+
+/// Chooses the cheapest order of joining tables one after another, given
+/// by the estimates of reading them and the predicates joining them.
+/// Returns the positions of the tables in that order and the estimates of
+/// joining the tables up to every one of them. Of orders costing the same,
+/// the one closest to the order of the statement is chosen.
+pub fn join_order(inputs: &[Estimate], predicates: &[Predicate]) -> (Vec<usize>, Vec<Estimate>) {
+    let mut best = (0..inputs.len()).collect::<Vec<_>>();
+    if inputs.len() <= MAX_REORDERED {
+        let mut order = Vec::with_capacity(inputs.len());
+        let mut cheapest = None;
+        search(inputs, predicates, &mut order, None, &mut cheapest);
+        if let Some((order, _)) = cheapest {
+            best = order;
+        }
+    }
+    let estimates = estimates(inputs, predicates, &best);
+    (best, estimates)
+}
+
+/// Returns the estimates of joining tables in an order.
+pub fn estimates(inputs: &[Estimate], predicates: &[Predicate], order: &[usize]) -> Vec<Estimate> {
+    let mut estimates: Vec<Estimate> = Vec::with_capacity(order.len());
+    let mut joined = 0;
+    for &i in order {
+        let estimate = match estimates.last() {
+            Some(&outer) => join(outer, joined, inputs[i], i, predicates),
+            None => inputs[i],
+        };
+        estimates.push(estimate);
+        joined |= 1 << i;
+    }
+    estimates
+}
+
+/// Extends a partial order of joins by every table not in it yet, keeping
+/// the cheapest complete order. Orders costing more than it are dropped.
+fn search(
+    inputs: &[Estimate],
+    predicates: &[Predicate],
+    order: &mut Vec<usize>,
+    outer: Option<Estimate>,
+    cheapest: &mut Option<(Vec<usize>, f64)>,
+) {
+    if let (Some(outer), &Some((_, cost))) = (outer, &*cheapest) {
+        if outer.cost >= cost {
+            return;
+        }
+    }
+    if order.len() == inputs.len() {
+        *cheapest = Some((order.clone(), outer.map_or(0.0, |outer| outer.cost)));
+        return;
+    }
+    let joined = order.iter().fold(0, |joined, &i| joined | 1 << i);
+    for i in 0..inputs.len() {
+        if joined & 1 << i != 0 {
+            continue;
+        }
+        let estimate = match outer {
+            Some(outer) => join(outer, joined, inputs[i], i, predicates),
+            None => inputs[i],
+        };
+        order.push(i);
+        search(inputs, predicates, order, Some(estimate), cheapest);
+        order.pop();
+    }
+}
+
+#[test]
+fn test_choose_access() {
+    assert_eq!(choose_access(&[]), None);
+    assert_eq!(choose_access(&[None, None]), Some(0));
+    assert_eq!(choose_access(&[None, Some(0.2), Some(0.01)]), Some(2));
+    assert_eq!(choose_access(&[Some(0.5), None]), None);
+    assert!(pays_off(Some(0.3)));
+    assert!(!pays_off(Some(0.4)));
+}
+
+#[test]
+fn test_join_order() {
+    // the table without a predicate is joined last instead of first, as
+    // its cross product is larger than the result of the other join
+    let inputs = [
+        Estimate::scan(10.0).filter(0.5),
+        Estimate::scan(10000.0),
+        Estimate::scan(1000.0),
+    ];
+    let predicates = [Predicate {
+        tables: 0b110,
+        share: 0.0001,
+        equi: true,
+    }];
+    let (order, estimates) = join_order(&inputs, &predicates);
+    assert_eq!(order, vec![1, 2, 0]);
+    assert_eq!(estimates.len(), 3);
+    assert_eq!(estimates[1].rows, 1000.0);
+    assert_eq!(estimates[2].rows, 5000.0);
+    assert_eq!(
+        estimates[2].cost,
+        10000.0 + 1000.0 + 11000.0 + 1000.0 + 10.0 + 5000.0 + 5000.0
+    );
+
+    // equal costs keep the order of the statement
+    let inputs = [Estimate::scan(5.0), Estimate::scan(5.0)];
+    assert_eq!(join_order(&inputs, &[]).0, vec![0, 1]);
+}
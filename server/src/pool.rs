@@ -0,0 +1,407 @@
+//! Worker pool serving the connections of the native protocol
+//!
+//! A connection has no thread of its own. The poller, a single thread,
+//! accepts and greets connections and waits for idle ones to send their
+//! login or next command, without blocking on any of them (see `mio`).
+//! Whatever is to be done for a connection, its login or answering a
+//! command, is a job for one of the `workers` threads of the configuration.
+//! A connection that does not log in within `conn::HANDSHAKE_TIMEOUT` is
+//! closed, so silent connections cannot keep the workers from others.
+//! Between two commands a connection is parked at the poller again, so
+//! thousands of mostly idle connections need no more than these threads. A
+//! long query keeps its worker busy, the jobs of other connections are
+//! taken by the other workers meanwhile.
+//!
+//! All workers take their jobs from one queue, the first idle worker runs
+//! the next job. A job that panics only closes its connection, the worker
+//! goes on with the next one. `SHOW WORKERS` shows the workers and the
+//! queue, see `status`.
+//!
+//! The connections of the compatibility protocols are jobs as well, see
+//! `submit`: each keeps a worker until it ends.
+
+use conn::{self, Session};
+use std::collections::VecDeque;
+use std::net::TcpStream;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+
+#[cfg(unix)]
+use config;
+#[cfg(unix)]
+use mio::unix::SourceFd;
+#[cfg(unix)]
+use mio::{Events, Interest, Poll, Token, Waker};
+#[cfg(unix)]
+use shutdown;
+#[cfg(unix)]
+use std::collections::HashMap;
+#[cfg(unix)]
+use std::io;
+#[cfg(unix)]
+use std::net::TcpListener;
+#[cfg(unix)]
+use std::os::unix::io::AsRawFd;
+#[cfg(unix)]
+use std::thread;
+#[cfg(unix)]
+use std::time::{Duration, Instant};
+
+/// Time between looking for connections that were idle for too long
+#[cfg(unix)]
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+#[cfg(unix)]
+const LISTENER: Token = Token(0);
+#[cfg(unix)]
+const WAKER: Token = Token(1);
+/// Token of the first parked connection, the others count up from it
+#[cfg(unix)]
+const FIRST_SESSION: usize = 2;
+
+/// The workers of the pool, empty until `serve` starts them
+static WORKERS: Mutex<Vec<Arc<Worker>>> = Mutex::new(Vec::new());
+
+/// Jobs waiting for a worker, the oldest first
+static QUEUE: Mutex<VecDeque<Job>> = Mutex::new(VecDeque::new());
+
+/// Signalled when a job is queued
+static QUEUED: Condvar = Condvar::new();
+
+/// Most jobs that waited at once
+static MAX_QUEUE_DEPTH: AtomicUsize = AtomicUsize::new(0);
+
+/// Something to be done for a connection
+enum Job {
+    /// Login of a greeted connection that sent something
+    Open(TcpStream),
+    /// A connection that sent a command
+    Serve(Session),
+    /// A connection of a compatibility protocol, from its login to its end
+    Run(Box<dyn FnOnce() + Send>),
+}
+
+struct Worker {
+    busy: AtomicBool,
+    jobs: AtomicU64,
+}
+
+/// Snapshot of a worker, as shown by `SHOW WORKERS`
+#[derive(Clone, Debug, PartialEq)]
+pub struct WorkerStatus {
+    pub id: usize,
+    /// Whether the worker runs a job
+    pub busy: bool,
+    /// Jobs waiting for any worker
+    pub queue_depth: usize,
+    /// Most jobs that waited for a worker at once
+    pub max_queue_depth: usize,
+    /// Jobs the worker started
+    pub jobs: u64,
+}
+
+/// Returns the state of all workers ordered by id.
+pub fn status() -> Vec<WorkerStatus> {
+    let queue_depth = QUEUE.lock().unwrap().len();
+    let workers = WORKERS.lock().unwrap();
+    workers
+        .iter()
+        .enumerate()
+        .map(|(id, worker)| WorkerStatus {
+            id: id,
+            busy: worker.busy.load(Ordering::SeqCst),
+            queue_depth: queue_depth,
+            max_queue_depth: MAX_QUEUE_DEPTH.load(Ordering::SeqCst),
+            jobs: worker.jobs.load(Ordering::SeqCst),
+        })
+        .collect()
+}
+
+/// Connections handed back by the workers, to be parked by the poller
+#[cfg(unix)]
+struct Returned {
+    sessions: Mutex<Vec<Session>>,
+    waker: Waker,
+}
+
+/// Accepts the connections of `listener` and serves them by `workers`
+/// threads. Only returns if polling fails.
+#[cfg(unix)]
+pub fn serve(
+    listener: TcpListener,
+    workers: usize,
+    default_database: Option<&'static str>,
+) -> io::Result<()> {
+    let mut poll = try!(Poll::new());
+    try!(listener.set_nonblocking(true));
+    try!(poll.registry().register(
+        &mut SourceFd(&listener.as_raw_fd()),
+        LISTENER,
+        Interest::READABLE
+    ));
+    let returned = Arc::new(Returned {
+        sessions: Mutex::new(Vec::new()),
+        waker: try!(Waker::new(poll.registry(), WAKER)),
+    });
+    for id in 0..workers {
+        let worker = Arc::new(Worker {
+            busy: AtomicBool::new(false),
+            jobs: AtomicU64::new(0),
+        });
+        WORKERS.lock().unwrap().push(worker.clone());
+        let returned = returned.clone();
+        try!(thread::Builder::new()
+            .name(format!("worker-{}", id))
+            .spawn(move || work(&worker, &returned, default_database)));
+    }
+    info!("Serving connections by {} workers", workers);
+
+    let mut parked: HashMap<Token, Session> = HashMap::new();
+    // Greeted connections waiting for the login, since when
+    let mut greeted: HashMap<Token, (TcpStream, Instant)> = HashMap::new();
+    let mut next_token = FIRST_SESSION;
+    let mut events = Events::with_capacity(1024);
+    loop {
+        match poll.poll(&mut events, Some(IDLE_CHECK_INTERVAL)) {
+            Ok(_) => {}
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+        for event in events.iter() {
+            match event.token() {
+                LISTENER => {
+                    for stream in accept(&listener, default_database) {
+                        let token = Token(next_token);
+                        next_token = next_token.wrapping_add(1).max(FIRST_SESSION);
+                        try!(poll.registry().register(
+                            &mut SourceFd(&stream.as_raw_fd()),
+                            token,
+                            Interest::READABLE
+                        ));
+                        greeted.insert(token, (stream, Instant::now()));
+                    }
+                }
+                // the parking below takes the returned connections
+                WAKER => {}
+                token => {
+                    if let Some((stream, _)) = greeted.remove(&token) {
+                        let fd = stream.as_raw_fd();
+                        try!(poll.registry().deregister(&mut SourceFd(&fd)));
+                        dispatch(Job::Open(stream));
+                    } else if let Some(session) = parked.remove(&token) {
+                        let fd = session.stream().as_raw_fd();
+                        try!(poll.registry().deregister(&mut SourceFd(&fd)));
+                        dispatch(Job::Serve(session));
+                    }
+                }
+            }
+        }
+
+        // Park the connections until they send their next command
+        let sessions: Vec<_> = returned.sessions.lock().unwrap().drain(..).collect();
+        for session in sessions {
+            // a TLS session may have read the next command already
+            if session.has_buffered() {
+                dispatch(Job::Serve(session));
+                continue;
+            }
+            let token = Token(next_token);
+            next_token = next_token.wrapping_add(1).max(FIRST_SESSION);
+            let fd = session.stream().as_raw_fd();
+            try!(poll
+                .registry()
+                .register(&mut SourceFd(&fd), token, Interest::READABLE));
+            parked.insert(token, session);
+        }
+
+        let timed_out: Vec<_> = parked
+            .iter()
+            .filter(|&(_, session)| session.timed_out())
+            .map(|(&token, _)| token)
+            .collect();
+        for token in timed_out {
+            if let Some(session) = parked.remove(&token) {
+                let fd = session.stream().as_raw_fd();
+                try!(poll.registry().deregister(&mut SourceFd(&fd)));
+                session.close_idle();
+            }
+        }
+
+        let silent: Vec<_> = greeted
+            .iter()
+            .filter(|&(_, &(_, since))| since.elapsed() >= conn::HANDSHAKE_TIMEOUT)
+            .map(|(&token, _)| token)
+            .collect();
+        for token in silent {
+            if let Some((stream, _)) = greeted.remove(&token) {
+                try!(poll
+                    .registry()
+                    .deregister(&mut SourceFd(&stream.as_raw_fd())));
+                info!(
+                    "Connection from {} did not log in in time. Connection closed.",
+                    stream
+                        .peer_addr()
+                        .map(|a| a.to_string())
+                        .unwrap_or("???".into())
+                );
+            }
+        }
+    }
+}
+
+/// Accepts and greets all pending connections. Returns the greeted ones.
+#[cfg(unix)]
+fn accept(listener: &TcpListener, default_database: Option<&str>) -> Vec<TcpStream> {
+    let mut greeted = Vec::new();
+    loop {
+        match listener.accept() {
+            // The server shuts down, the stream is closed when dropped
+            Ok(_) if shutdown::is_shutting_down() => {
+                debug!("Refusing connection, the server shuts down");
+            }
+            Ok((mut stream, _)) => {
+                // the workers read and write blocking, some systems pass on
+                // the mode of the listener
+                if let Err(e) = stream.set_nonblocking(false) {
+                    warn!("Failed to accept incoming connection: {:?}", e);
+                    continue;
+                }
+                // the greeting fits into the empty send buffer
+                if conn::greet(
+                    &mut stream,
+                    &config::current().send_limits(),
+                    default_database,
+                ) {
+                    greeted.push(stream);
+                }
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return greeted,
+            Err(e) => {
+                warn!("Failed to accept incoming connection: {:?}", e);
+                return greeted;
+            }
+        }
+    }
+}
+
+/// Queues a job for the next idle worker.
+#[cfg(unix)]
+fn dispatch(job: Job) {
+    let mut queue = QUEUE.lock().unwrap();
+    queue.push_back(job);
+    MAX_QUEUE_DEPTH.fetch_max(queue.len(), Ordering::SeqCst);
+    QUEUED.notify_one();
+}
+
+/// Runs `handler` for a connection of a compatibility protocol by a worker.
+/// The connection keeps the worker until it ends.
+#[cfg(unix)]
+pub fn submit<F>(handler: F)
+where
+    F: FnOnce() + Send + 'static,
+{
+    dispatch(Job::Run(Box::new(handler)));
+}
+
+/// Runs the jobs of the queue, forever.
+#[cfg(unix)]
+fn work(worker: &Worker, returned: &Returned, default_database: Option<&'static str>) {
+    loop {
+        let job = {
+            let mut queue = QUEUE.lock().unwrap();
+            loop {
+                match queue.pop_front() {
+                    Some(job) => break job,
+                    None => queue = QUEUED.wait(queue).unwrap(),
+                }
+            }
+        };
+        worker.busy.store(true, Ordering::SeqCst);
+        worker.jobs.fetch_add(1, Ordering::SeqCst);
+        // the connection of a job that panics is dropped, which closes it
+        let session = match panic::catch_unwind(AssertUnwindSafe(|| run(job, default_database))) {
+            Ok(session) => session,
+            Err(_) => {
+                error!("A job of the worker pool panicked. Connection closed.");
+                None
+            }
+        };
+        worker.busy.store(false, Ordering::SeqCst);
+        if let Some(session) = session {
+            returned.sessions.lock().unwrap().push(session);
+            if let Err(e) = returned.waker.wake() {
+                error!("Cannot hand a connection back to the poller: {}", e);
+            }
+        }
+    }
+}
+
+/// Runs a job, returns the connection to be parked if it stays open.
+#[cfg(unix)]
+fn run(job: Job, default_database: Option<&'static str>) -> Option<Session> {
+    match job {
+        // Limits and timeouts are those of the current configuration,
+        // which a reload may change, when a connection logs in
+        Job::Open(stream) => {
+            let config = config::current();
+            conn::login(
+                stream,
+                config.limits(),
+                config.idle_timeout(),
+                config.send_limits(),
+                default_database,
+            )
+        }
+        Job::Serve(session) => session.serve(),
+        Job::Run(handler) => {
+            handler();
+            None
+        }
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn test_silent_connections_do_not_block_logins() {
+    use net::types::{Command, PkgType};
+    use net::{self, PacketWriter};
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || serve(listener, 2, None));
+
+    // as many connections as workers never answer the greeting
+    let silent: Vec<_> = (0..2).map(|_| TcpStream::connect(addr).unwrap()).collect();
+    let mut client = TcpStream::connect(addr).unwrap();
+    client
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let greeting = net::read_packet(&mut client, net::MAX_PACKET_SIZE).unwrap();
+    assert!(greeting.pkg == PkgType::Greet);
+
+    // the next connection is still answered long before the handshake
+    // timeout of the silent ones
+    let mut packet = PacketWriter::new(PkgType::Command);
+    packet.add(&Command::Resume("unknown".into())).unwrap();
+    packet.send(&mut client).unwrap();
+    let answer = net::read_packet(&mut client, net::MAX_PACKET_SIZE).unwrap();
+    assert!(answer.pkg == PkgType::AccDenied);
+    drop(silent);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_panicking_jobs_keep_the_workers() {
+    use std::sync::mpsc;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    thread::spawn(move || serve(listener, 1, None));
+
+    let (done, finished) = mpsc::channel();
+    for i in 0..3 {
+        submit(move || panic!("job {} fails", i));
+    }
+    submit(move || done.send(()).unwrap());
+    finished.recv_timeout(Duration::from_secs(5)).unwrap();
+}
@@ -0,0 +1,670 @@
+//! Registry of all active connections
+//!
+//! Every connection registers itself after a successful login and is
+//! assigned a unique id. The registry is used by `SHOW PROCESSLIST` and by
+//! `Command::Kill`: killing a connection sets its `CancelToken`, which the
+//! query executor polls between calls into the storage engine, and shuts
+//! down its socket so that an idle connection is closed as well.
+//!
+//! While a query runs, the executor counts the rows it processed and names
+//! the table it works on in the `Progress` of the connection, which
+//! `SHOW PROGRESS FOR` shows.
+//!
+//! Registering fails if the configured connection `Limits` are exceeded.
+//! Some counters are kept for `SHOW STATUS`.
+//!
+//! Connections may listen on channels (`Command::Listen`). `NOTIFY` writes
+//! a notification package directly into the stream of every listener. To
+//! keep packages from interleaving, a connection has to hold its write lock
+//! (`Handle::lock_writes`) while it sends a package itself.
+//!
+//! Every connection gets a secret session token. If the connection drops,
+//! its `Session` is kept for `RESUME_WINDOW`, so that the client can
+//! reconnect with the token and continue where it stopped. The resumed
+//! connection keeps the id of the dropped one, and with it the open
+//! transaction and the temporary tables, which stay locked and kept
+//! meanwhile. Once the window expired, a background thread rolls the
+//! transaction back and drops the tables (`spawn_expiry`). Connections that
+//! end otherwise do so right away.
+//!
+
+use audit;
+use net;
+use net::types::{Negotiation, Notification, ShutdownWarning};
+use replication;
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::Read;
+use std::net::{Shutdown, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use storage::{memory, transaction};
+
+/// How long the session of a dropped connection can be resumed
+pub const RESUME_WINDOW: Duration = Duration::from_secs(5 * 60);
+/// How often suspended sessions are checked for an expired window
+const EXPIRY_INTERVAL: Duration = Duration::from_secs(1);
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+static REGISTRY: Mutex<Vec<Entry>> = Mutex::new(Vec::new());
+static SUSPENDED: Mutex<Vec<Suspended>> = Mutex::new(Vec::new());
+
+static TOTAL_CONNECTIONS: AtomicU64 = AtomicU64::new(0);
+static REJECTED_CONNECTIONS: AtomicU64 = AtomicU64::new(0);
+static MAX_USED_CONNECTIONS: AtomicU64 = AtomicU64::new(0);
+
+/// Maximum number of simultaneous connections, `0` means unlimited
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Limits {
+    pub max_connections: usize,
+    pub max_user_connections: usize,
+}
+
+/// Counters describing the connection history of the server
+#[derive(Clone, Copy, Debug)]
+pub struct Status {
+    /// Currently open connections
+    pub connections: u64,
+    /// Highest number of simultaneously open connections
+    pub max_used_connections: u64,
+    /// Connections accepted since startup
+    pub total_connections: u64,
+    /// Connections refused because a limit was reached
+    pub rejected_connections: u64,
+}
+
+/// Shared flag telling a running query to stop as soon as possible.
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// Snapshot of a connection, as shown by `SHOW PROCESSLIST`
+#[derive(Clone, Debug)]
+pub struct Process {
+    pub id: u64,
+    pub user: String,
+    pub host: String,
+    pub database: Option<String>,
+    /// The query that is currently executed, if any
+    pub query: Option<String>,
+    /// Start of the current query or, if idle, of the connection
+    pub since: Instant,
+    /// Rows the current or last query processed
+    pub rows: u64,
+    /// The table the current or last query worked on last
+    pub table: Option<String>,
+}
+
+/// Progress of the query of a connection, shared with its executor
+#[derive(Clone, Debug, Default)]
+pub struct Progress {
+    rows: Arc<AtomicU64>,
+    table: Arc<Mutex<Option<String>>>,
+}
+
+impl Progress {
+    /// Counts a row the query processed.
+    pub fn add_row(&self) {
+        self.rows.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Notes the table the query works on now.
+    pub fn set_table(&self, table: &str) {
+        let mut current = self.table.lock().unwrap();
+        if current.as_ref().map(|t| &t[..]) != Some(table) {
+            *current = Some(table.to_string());
+        }
+    }
+
+    pub fn rows(&self) -> u64 {
+        self.rows.load(Ordering::Relaxed)
+    }
+
+    pub fn table(&self) -> Option<String> {
+        self.table.lock().unwrap().clone()
+    }
+
+    fn reset(&self) {
+        self.rows.store(0, Ordering::Relaxed);
+        *self.table.lock().unwrap() = None;
+    }
+}
+
+/// State of a dropped connection that can be resumed
+#[derive(Clone, Debug)]
+pub struct Session {
+    /// Id of the dropped connection, which the resumed one keeps
+    pub id: u64,
+    pub user: String,
+    pub database: Option<String>,
+    /// Channels the connection listened on
+    pub channels: Vec<String>,
+    pub negotiation: Negotiation,
+}
+
+struct Suspended {
+    token: String,
+    session: Session,
+    since: Instant,
+}
+
+struct Entry {
+    process: Process,
+    cancel: CancelToken,
+    progress: Progress,
+    stream: Option<TcpStream>,
+    /// Stream used to push notifications, locked for every package
+    writer: Arc<Mutex<Option<net::Stream>>>,
+    /// Channels this connection listens on
+    channels: Vec<String>,
+    /// Whether the client understands `PkgType::ShuttingDown`
+    warn_on_shutdown: bool,
+}
+
+/// Returned by `register` and `enter_database` if a connection limit is
+/// reached
+#[derive(Debug, PartialEq)]
+pub enum LimitError {
+    TooManyConnections,
+    TooManyUserConnections,
+    /// The database has as many connections as its `max_connections`
+    TooManyDatabaseConnections,
+}
+
+/// Errors that may occur when killing a connection
+#[derive(Debug, PartialEq)]
+pub enum KillError {
+    UnknownConnection,
+    PermissionDenied,
+}
+
+/// Handle of a registered connection. The connection is removed from the
+/// registry when the handle is dropped.
+pub struct Handle {
+    id: u64,
+    cancel: CancelToken,
+    writer: Arc<Mutex<Option<net::Stream>>>,
+    token: String,
+    /// Set by `suspend`: the transaction and the temporary tables are kept
+    suspended: bool,
+}
+
+impl Handle {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Returns the secret the client needs to resume this session.
+    pub fn session_token(&self) -> &str {
+        &self.token
+    }
+
+    /// Unregisters the dropped connection, but keeps its session for
+    /// `RESUME_WINDOW`, see `resume`.
+    pub fn suspend(mut self, database: Option<String>, negotiation: Negotiation) {
+        let mut session = Session {
+            id: self.id,
+            user: String::new(),
+            database: database,
+            channels: Vec::new(),
+            negotiation: negotiation,
+        };
+        self.update(|e| {
+            session.user = e.process.user.clone();
+            session.channels = e.channels.clone();
+        });
+        debug!("suspended session of connection {}", self.id);
+        SUSPENDED.lock().unwrap().push(Suspended {
+            token: self.token.clone(),
+            session: session,
+            since: Instant::now(),
+        });
+        self.suspended = true;
+    }
+
+    /// Returns the token the executor has to poll for this connection.
+    pub fn cancel_token(&self) -> &CancelToken {
+        &self.cancel
+    }
+
+    /// Returns true if the connection was killed.
+    pub fn is_killed(&self) -> bool {
+        self.cancel.is_cancelled()
+    }
+
+    /// Marks the start (`Some`) or the end (`None`) of a query. Its progress
+    /// is kept until the next query starts.
+    pub fn set_query(&self, query: Option<&str>) {
+        self.update(|e| {
+            if query.is_some() {
+                e.progress.reset();
+            }
+            e.process.query = query.map(|q| q.to_string());
+            e.process.since = Instant::now();
+        });
+    }
+
+    /// Updates the database shown for this connection.
+    pub fn set_database(&self, database: Option<&str>) {
+        self.update(|e| e.process.database = database.map(|d| d.to_string()));
+    }
+
+    /// Returns the database of this connection, which another connection may
+    /// have renamed, see `rename_database`.
+    pub fn database(&self) -> Option<String> {
+        let mut database = None;
+        self.update(|e| database = e.process.database.clone());
+        database
+    }
+
+    /// Starts listening on `channel`.
+    pub fn listen(&self, channel: &str) {
+        self.update(|e| {
+            if !e.channels.iter().any(|c| c == channel) {
+                e.channels.push(channel.to_string());
+            }
+        });
+    }
+
+    /// Lets the connection be warned by a `PkgType::ShuttingDown` package
+    /// once the server shuts down, see `warn_shutdown`. Only clients of the
+    /// native protocol understand it.
+    pub fn warn_on_shutdown(&self) {
+        self.update(|e| e.warn_on_shutdown = true);
+    }
+
+    /// Must be held while sending a package, so that no notification is
+    /// written into the middle of it.
+    pub fn lock_writes(&self) -> MutexGuard<Option<net::Stream>> {
+        self.writer.lock().unwrap()
+    }
+
+    fn update<F: FnOnce(&mut Entry)>(&self, f: F) {
+        let mut registry = REGISTRY.lock().unwrap();
+        if let Some(entry) = registry.iter_mut().find(|e| e.process.id == self.id) {
+            f(entry);
+        }
+    }
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        let process = {
+            let mut registry = REGISTRY.lock().unwrap();
+            registry
+                .iter()
+                .position(|e| e.process.id == self.id)
+                .map(|i| registry.remove(i).process)
+        };
+        if let Some(p) = process {
+            audit::logout(p.id, &p.user, &p.host);
+        }
+        if !self.suspended {
+            transaction::abort(self.id);
+            memory::forget(self.id);
+        }
+        replication::forget(self.id);
+    }
+}
+
+/// Adds a connection to the registry. `stream` is shut down when the
+/// connection is killed.
+///
+/// # Failures
+/// Fails without registering if the connection would exceed one of the
+/// given limits.
+pub fn register(
+    user: &str,
+    host: &str,
+    stream: Option<net::Stream>,
+    limits: Limits,
+) -> Result<Handle, LimitError> {
+    add(None, user, host, stream, limits)
+}
+
+/// Adds the connection resuming a session to the registry, under the id of
+/// the dropped connection, see `register`.
+pub fn register_resumed(
+    session: &Session,
+    host: &str,
+    stream: Option<net::Stream>,
+    limits: Limits,
+) -> Result<Handle, LimitError> {
+    add(Some(session.id), &session.user, host, stream, limits)
+}
+
+fn add(
+    id: Option<u64>,
+    user: &str,
+    host: &str,
+    stream: Option<net::Stream>,
+    limits: Limits,
+) -> Result<Handle, LimitError> {
+    let mut registry = REGISTRY.lock().unwrap();
+    if limits.max_connections > 0 && registry.len() >= limits.max_connections {
+        REJECTED_CONNECTIONS.fetch_add(1, Ordering::SeqCst);
+        return Err(LimitError::TooManyConnections);
+    }
+    let user_connections = registry.iter().filter(|e| e.process.user == user).count();
+    if limits.max_user_connections > 0 && user_connections >= limits.max_user_connections {
+        REJECTED_CONNECTIONS.fetch_add(1, Ordering::SeqCst);
+        return Err(LimitError::TooManyUserConnections);
+    }
+
+    let id = id.unwrap_or_else(|| NEXT_ID.fetch_add(1, Ordering::SeqCst));
+    let cancel = CancelToken::new();
+    let socket = stream.as_ref().and_then(|s| s.tcp().try_clone().ok());
+    let writer = Arc::new(Mutex::new(stream));
+    let token = new_token(id);
+    registry.push(Entry {
+        process: Process {
+            id: id,
+            user: user.to_string(),
+            host: host.to_string(),
+            database: None,
+            query: None,
+            since: Instant::now(),
+            rows: 0,
+            table: None,
+        },
+        cancel: cancel.clone(),
+        progress: Progress::default(),
+        stream: socket,
+        writer: writer.clone(),
+        channels: Vec::new(),
+        warn_on_shutdown: false,
+    });
+    TOTAL_CONNECTIONS.fetch_add(1, Ordering::SeqCst);
+    MAX_USED_CONNECTIONS.fetch_max(registry.len() as u64, Ordering::SeqCst);
+    debug!("registered connection {} of user '{}'", id, user);
+    drop(registry);
+    audit::login(id, user, host);
+    Ok(Handle {
+        id: id,
+        cancel: cancel,
+        writer: writer,
+        token: token,
+        suspended: false,
+    })
+}
+
+/// Returns a random token of 32 hex digits.
+fn new_token(id: u64) -> String {
+    let mut bytes = [0; 16];
+    let random = File::open("/dev/urandom").and_then(|mut f| f.read_exact(&mut bytes));
+    if random.is_err() {
+        warn!("Cannot read /dev/urandom, session tokens are guessable");
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or(0);
+        let mut hasher = Sha256::new();
+        hasher.update(format!("{}-{}", nanos, id).as_bytes());
+        bytes.copy_from_slice(&hasher.finalize()[..16]);
+    }
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Takes the suspended session with the given token. Every session can be
+/// resumed once and only within `RESUME_WINDOW`. A session that is not
+/// registered again by `register_resumed` has to be ended by `discard`.
+pub fn resume(token: &str) -> Option<Session> {
+    expire(RESUME_WINDOW);
+    let mut suspended = SUSPENDED.lock().unwrap();
+    let pos = suspended.iter().position(|s| s.token == token);
+    pos.map(|pos| suspended.remove(pos).session)
+}
+
+/// Rolls back the transaction of a session that is not resumed after all
+/// and drops its temporary tables.
+pub fn discard(session: &Session) {
+    transaction::abort(session.id);
+    memory::forget(session.id);
+}
+
+/// Discards the sessions suspended for longer than `window`.
+fn expire(window: Duration) {
+    let expired: Vec<Suspended> = {
+        let mut suspended = SUSPENDED.lock().unwrap();
+        let (expired, kept) = suspended
+            .drain(..)
+            .partition(|s| s.since.elapsed() >= window);
+        *suspended = kept;
+        expired
+    };
+    for s in expired {
+        debug!("session of connection {} expired", s.session.id);
+        discard(&s.session);
+    }
+}
+
+/// Discards all suspended sessions, when the server shuts down.
+pub fn discard_suspended() {
+    expire(Duration::from_secs(0));
+}
+
+/// Starts the thread which discards the sessions that were not resumed
+/// within `RESUME_WINDOW`.
+pub fn spawn_expiry() {
+    thread::spawn(|| loop {
+        thread::sleep(EXPIRY_INTERVAL);
+        expire(RESUME_WINDOW);
+    });
+}
+
+/// Makes `database` the database of connection `id`, unless `max` other
+/// connections use it already, 0 is no limit. Counting and switching happen
+/// at once, so concurrent connections cannot exceed the limit together.
+pub fn enter_database(id: u64, database: &str, max: u64) -> Result<(), LimitError> {
+    let mut registry = REGISTRY.lock().unwrap();
+    let using = registry
+        .iter()
+        .filter(|e| {
+            e.process.id != id && e.process.database.as_ref().map(|d| &d[..]) == Some(database)
+        })
+        .count() as u64;
+    if max > 0 && using >= max {
+        return Err(LimitError::TooManyDatabaseConnections);
+    }
+    if let Some(entry) = registry.iter_mut().find(|e| e.process.id == id) {
+        entry.process.database = Some(database.to_string());
+    }
+    Ok(())
+}
+
+/// Moves all connections and suspended sessions using a renamed database
+/// to its new name. A connection picks up the name before its next query.
+pub fn rename_database(database: &str, new_name: &str) {
+    for entry in REGISTRY.lock().unwrap().iter_mut() {
+        if entry.process.database.as_ref().map(|d| &d[..]) == Some(database) {
+            entry.process.database = Some(new_name.to_string());
+        }
+    }
+    for suspended in SUSPENDED.lock().unwrap().iter_mut() {
+        if suspended.session.database.as_ref().map(|d| &d[..]) == Some(database) {
+            suspended.session.database = Some(new_name.to_string());
+        }
+    }
+}
+
+/// Returns the current connection counters.
+pub fn status() -> Status {
+    let connections = REGISTRY.lock().unwrap().len() as u64;
+    Status {
+        connections: connections,
+        max_used_connections: MAX_USED_CONNECTIONS.load(Ordering::SeqCst),
+        total_connections: TOTAL_CONNECTIONS.load(Ordering::SeqCst),
+        rejected_connections: REJECTED_CONNECTIONS.load(Ordering::SeqCst),
+    }
+}
+
+/// Returns all active connections ordered by id.
+pub fn list() -> Vec<Process> {
+    let registry = REGISTRY.lock().unwrap();
+    registry.iter().map(snapshot).collect()
+}
+
+/// Returns the connection of the id, if it is active.
+pub fn get(id: u64) -> Option<Process> {
+    let registry = REGISTRY.lock().unwrap();
+    registry.iter().find(|e| e.process.id == id).map(snapshot)
+}
+
+fn snapshot(entry: &Entry) -> Process {
+    let mut process = entry.process.clone();
+    process.rows = entry.progress.rows();
+    process.table = entry.progress.table();
+    process
+}
+
+/// Returns the progress the executor of connection `id` has to update, if
+/// the connection is registered.
+pub fn progress(id: u64) -> Option<Progress> {
+    let registry = REGISTRY.lock().unwrap();
+    registry
+        .iter()
+        .find(|e| e.process.id == id)
+        .map(|e| e.progress.clone())
+}
+
+/// Cancels the running query of connection `id` and closes the connection.
+/// Only the administrator may kill connections of other users.
+pub fn kill(id: u64, user: &str, is_admin: bool) -> Result<(), KillError> {
+    let registry = REGISTRY.lock().unwrap();
+    let entry = match registry.iter().find(|e| e.process.id == id) {
+        Some(e) => e,
+        None => return Err(KillError::UnknownConnection),
+    };
+    if !is_admin && entry.process.user != user {
+        return Err(KillError::PermissionDenied);
+    }
+    entry.cancel.cancel();
+    if let Some(ref stream) = entry.stream {
+        // Unblocks a connection waiting for its next command. A running
+        // query notices the cancellation first and fails to send its result.
+        let _ = stream.shutdown(Shutdown::Read);
+    }
+    info!("connection {} killed by user '{}'", id, user);
+    Ok(())
+}
+
+/// Sends a notification to every connection listening on `channel` and
+/// returns the number of connections it was delivered to.
+pub fn notify(channel: &str, payload: &str) -> usize {
+    // Writing may block, so the registry must not be locked meanwhile
+    let writers: Vec<_> = REGISTRY
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|e| e.channels.iter().any(|c| c == channel))
+        .map(|e| e.writer.clone())
+        .collect();
+    let notification = Notification {
+        channel: channel.to_string(),
+        payload: payload.to_string(),
+    };
+    let mut delivered = 0;
+    for writer in writers {
+        if let Some(ref mut stream) = *writer.lock().unwrap() {
+            if net::send_notification(stream, &notification).is_ok() {
+                delivered += 1;
+            }
+        }
+    }
+    debug!("notified {} listeners on channel '{}'", delivered, channel);
+    delivered
+}
+
+/// Sends a `ShutdownWarning` to every connection that understands it and
+/// returns the number of connections it was delivered to.
+pub fn warn_shutdown(warning: &ShutdownWarning) -> usize {
+    let writers: Vec<_> = REGISTRY
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|e| e.warn_on_shutdown)
+        .map(|e| e.writer.clone())
+        .collect();
+    let mut delivered = 0;
+    for writer in writers {
+        if let Some(ref mut stream) = *writer.lock().unwrap() {
+            if net::send_shutdown_warning(stream, warning).is_ok() {
+                delivered += 1;
+            }
+        }
+    }
+    delivered
+}
+
+/// Cancels the running queries of all connections and closes them, like
+/// `kill`.
+pub fn kill_all() {
+    for entry in REGISTRY.lock().unwrap().iter() {
+        entry.cancel.cancel();
+        if let Some(ref stream) = entry.stream {
+            let _ = stream.shutdown(Shutdown::Read);
+        }
+    }
+}
+
+#[test]
+fn test_resume_keeps_transaction() {
+    let negotiation = Negotiation {
+        version: net::PROTOCOL_VERSION,
+        capabilities: net::types::Capabilities::NONE,
+        max_packet_size: 1024,
+    };
+    let handle = register("alice", "127.0.0.1:1", None, Limits::default()).unwrap();
+    let id = handle.id();
+    let token = handle.session_token().to_string();
+    transaction::begin(id).unwrap();
+    handle.suspend(Some("shop".into()), negotiation);
+    assert!(transaction::is_active(id));
+    assert!(!list().iter().any(|p| p.id == id));
+
+    let session = resume(&token).unwrap();
+    assert_eq!((session.id, &*session.user), (id, "alice"));
+    assert!(resume(&token).is_none());
+    let handle = register_resumed(&session, "127.0.0.1:2", None, Limits::default()).unwrap();
+    assert_eq!(handle.id(), id);
+    assert_ne!(handle.session_token(), token);
+    assert!(transaction::is_active(id));
+    transaction::commit(id).unwrap();
+    drop(handle);
+    assert!(!transaction::is_active(id));
+
+    // sessions not resumed in time are rolled back, as are connections that
+    // end without being suspended
+    let handle = register("bob", "127.0.0.1:3", None, Limits::default()).unwrap();
+    let id = handle.id();
+    let token = handle.session_token().to_string();
+    transaction::begin(id).unwrap();
+    handle.suspend(None, negotiation);
+    expire(RESUME_WINDOW);
+    assert!(transaction::is_active(id));
+    expire(Duration::from_secs(0));
+    assert!(!transaction::is_active(id));
+    assert!(resume(&token).is_none());
+
+    // a connection that ends without being suspended rolls back at once
+    let handle = register("bob", "127.0.0.1:4", None, Limits::default()).unwrap();
+    let id = handle.id();
+    transaction::begin(id).unwrap();
+    drop(handle);
+    assert!(!transaction::is_active(id));
+}
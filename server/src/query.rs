@@ -5,38 +5,190 @@
 //!
 
 use super::auth;
+use super::catalog::{self, Catalog};
+use super::config;
+use super::csv;
+use super::dump;
+use super::explain::{self, Plan};
+use super::function;
+use super::join;
+use super::metrics;
 use super::parse::ast::*;
-use super::parse::parser::ParseError;
+use super::parse::parser::{ParseError, Parser};
+use super::parse::token::Lit;
+use super::planner::{self, Estimate};
+use super::pool;
+use super::process::{self, CancelToken, Progress};
+use super::replication;
+use super::scheduler;
+use super::setop;
+use super::shutdown;
+use super::sort::{self, SortKey};
+use super::spill;
 
 use super::storage;
-use super::storage::types::SqlType;
-use super::storage::{Column, Database, Engine, EngineID, ResultSet, Rows, Table};
+use super::storage::backup;
+use super::storage::count;
+use super::storage::decimal;
+use super::storage::lock::{self, Mode};
+use super::storage::memory;
+use super::storage::pattern;
+use super::storage::recovery;
+use super::storage::repair;
+use super::storage::stats;
+use super::storage::temporal;
+use super::storage::transaction;
+use super::storage::types::{self, SqlType};
+use super::storage::vacuum;
+use super::storage::{Check, Damage, EngineID, ForeignKey, ReferentialAction};
+use super::storage::{Column, Database, Engine, Privilege, ResultSet, Rows, Table, Versioned};
+use super::storage::{Event, Function, View};
+use super::window;
 
-use std::collections::HashMap;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
 
-use std::io::Cursor;
+use std::fs::File;
+use std::io::{Cursor, Read};
+use std::mem;
+use std::ops::Bound;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Number of rows `COPY` hands to the storage engine at once
+const COPY_BATCH: usize = 1000;
+/// Size of the blocks `COPY` reads its file in
+const COPY_CHUNK: usize = 64 * 1024;
+
+/// How long `DROP DATABASE ... FORCE` waits for killed connections to end
+const DISCONNECT_TIMEOUT: Duration = Duration::from_secs(5);
 
 pub struct Executor<'a> {
     pub user: &'a mut auth::User,
+    /// Polled between storage engine calls, see `process::kill`
+    pub cancel: &'a CancelToken,
+    /// Id of the connection, identifies its transaction
+    pub connection: u64,
+    /// Snapshot and transaction of the statement
+    pub statement: transaction::Statement,
+    /// Rows processed and the table worked on, see `SHOW PROGRESS FOR`
+    progress: Progress,
+    /// Views being expanded, a view must not refer to itself
+    views: Vec<String>,
 }
 
 pub fn execute_from_ast<'a>(
-    query: Query,
+    mut query: Query,
     user: &'a mut auth::User,
+    cancel: &'a CancelToken,
+    connection: u64,
 ) -> Result<ResultSet, ExecutionError> {
-    let mut executor = Executor::new(user);
+    try!(check_password_expired(user, Some(&query)));
+    let mut executor = Executor::new(user, cancel, connection);
 
-    let res = match query {
-        Query::ManipulationStmt(stmt) => executor.execute_manipulation_stmt(stmt),
-        Query::DefStmt(stmt) => executor.execute_def_stmt(stmt),
-        _ => return Err(ExecutionError::ParseError(ParseError::UnknownError)),
+    let res = match executor.expand_functions(&mut query) {
+        Ok(()) => match query {
+            Query::ManipulationStmt(stmt) => executor.execute_manipulation_stmt(stmt),
+            Query::DefStmt(stmt) => executor.execute_def_stmt(stmt),
+            Query::ControlStmt(stmt) => executor.execute_control_stmt(stmt),
+            Query::TransactionStmt(stmt) => executor.execute_transaction_stmt(stmt),
+            _ => return Err(ExecutionError::ParseError(ParseError::UnknownError)),
+        },
+        Err(e) => Err(e),
     };
+    try!(executor.statement.end(res.is_ok()));
+    Ok(try!(try!(res).to_result_set()))
+}
+
+/// Executes `DUMP DATABASE` without collecting the text: `write` gets every
+/// chunk of it as soon as it is complete.
+pub fn dump_database<'a, F>(
+    database: &str,
+    user: &'a mut auth::User,
+    cancel: &'a CancelToken,
+    connection: u64,
+    mut write: F,
+) -> Result<(), ExecutionError>
+where
+    F: FnMut(&str) -> Result<(), ExecutionError>,
+{
+    try!(check_password_expired(user, None));
+    let mut executor = Executor::new(user, cancel, connection);
+    let res = executor.dump(database, &mut write);
+    try!(executor.statement.end(res.is_ok()));
+    res
+}
+
+/// Executes a `COPY` of CSV text that the client sends: `next_chunk`
+/// returns the chunks of the text and None once it ends. Returns the number
+/// of rows inserted like `COPY ... FROM` does.
+pub fn copy_in<'a, F>(
+    table: &str,
+    user: &'a mut auth::User,
+    cancel: &'a CancelToken,
+    connection: u64,
+    mut next_chunk: F,
+) -> Result<ResultSet, ExecutionError>
+where
+    F: FnMut() -> Result<Option<Vec<u8>>, ExecutionError>,
+{
+    try!(check_password_expired(user, None));
+    let mut executor = Executor::new(user, cancel, connection);
+    let res = executor
+        .copy(table, &mut next_chunk)
+        .and_then(inserted_count);
+    try!(executor.statement.end(res.is_ok()));
     Ok(try!(try!(res).to_result_set()))
 }
 
+/// Fails with `AuthError::PasswordExpired` if the password of the user has
+/// expired, unless the statement changes it.
+fn check_password_expired(user: &auth::User, query: Option<&Query>) -> Result<(), ExecutionError> {
+    if !auth::password_expired(&user._name) {
+        return Ok(());
+    }
+    match query {
+        Some(&Query::DefStmt(DefStmt::Alter(AltStmt::User(ref stmt))))
+            if stmt.name == user._name =>
+        {
+            Ok(())
+        }
+        _ => Err(ExecutionError::AuthError(auth::AuthError::PasswordExpired)),
+    }
+}
+
 impl<'a> Executor<'a> {
-    pub fn new(user: &'a mut auth::User) -> Executor<'a> {
-        Executor { user: user }
+    pub fn new(user: &'a mut auth::User, cancel: &'a CancelToken, connection: u64) -> Executor<'a> {
+        Executor {
+            user: user,
+            cancel: cancel,
+            connection: connection,
+            statement: transaction::start_statement(connection),
+            progress: process::progress(connection).unwrap_or_default(),
+            views: Vec::new(),
+        }
+    }
+
+    /// Replaces the calls of the functions of the current database in a
+    /// statement by their bodies, see `function`.
+    fn expand_functions(&self, query: &mut Query) -> Result<(), ExecutionError> {
+        let functions = match self.user._currentDatabase {
+            Some(ref base) => try!(base.load_meta_data()).functions,
+            None => return Ok(()),
+        };
+        Ok(try!(function::expand_query(query, &functions)))
+    }
+
+    fn execute_transaction_stmt(
+        &mut self,
+        stmt: TransactionStmt,
+    ) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
+        match stmt {
+            TransactionStmt::Begin => try!(transaction::begin(self.connection)),
+            TransactionStmt::Commit => try!(transaction::commit(self.connection)),
+            TransactionStmt::Rollback => try!(transaction::rollback(self.connection)),
+        }
+        Ok(generate_rows_dummy())
     }
 
     fn execute_manipulation_stmt(
@@ -46,9 +198,26 @@ impl<'a> Executor<'a> {
         match query {
             ManipulationStmt::Use(stmt) => self.execute_use_stmt(stmt),
             ManipulationStmt::Insert(stmt) => self.execute_insert_stmt(stmt),
+            ManipulationStmt::InsertSelect(stmt) => self.execute_insert_select_stmt(stmt),
             ManipulationStmt::Describe(stmt) => self.execute_describe_stmt(stmt),
+            ManipulationStmt::Explain(stmt) => self.execute_explain_stmt(stmt),
             ManipulationStmt::Select(stmt) => self.execute_select_stmt(stmt),
+            ManipulationStmt::Compound(stmt) => self.execute_compound_stmt(stmt),
+            ManipulationStmt::Update(stmt) => self.execute_update_stmt(stmt),
             ManipulationStmt::Delete(stmt) => self.execute_delete_stmt(stmt),
+            ManipulationStmt::Show(stmt) => self.execute_show_stmt(stmt),
+            ManipulationStmt::Vacuum(table) => self.execute_vacuum_stmt(table),
+            ManipulationStmt::Analyze(table) => self.execute_analyze_stmt(table),
+            ManipulationStmt::CheckTable(table) => self.execute_check_table_stmt(table),
+            ManipulationStmt::RepairTable(table) => self.execute_repair_table_stmt(table),
+            ManipulationStmt::Backup(stmt) => self.execute_backup_stmt(stmt),
+            ManipulationStmt::Restore(path) => self.execute_restore_stmt(path),
+            ManipulationStmt::Dump(database) => self.execute_dump_stmt(database),
+            ManipulationStmt::Copy(stmt) => self.execute_copy_stmt(stmt),
+            ManipulationStmt::Notify(stmt) => {
+                process::notify(&stmt.channel, &stmt.payload);
+                Ok(generate_rows_dummy())
+            }
             _ => Err(ExecutionError::DebugError(
                 "Feature not implemented yet!".into(),
             )),
@@ -63,699 +232,6612 @@ impl<'a> Executor<'a> {
             DefStmt::Create(stmt) => self.execute_create_stmt(stmt),
             DefStmt::Drop(stmt) => self.execute_drop_stmt(stmt),
             DefStmt::Alter(stmt) => self.execute_alt_stmt(stmt),
+            DefStmt::Truncate(table) => self.execute_truncate_stmt(table),
+            DefStmt::RenameDatabase(database, new_name) => {
+                self.execute_rename_database(database, new_name)
+            }
         }
     }
 
-    fn execute_use_stmt(
+    fn execute_control_stmt(
         &mut self,
-        query: UseStmt,
+        query: ControlStmt,
+    ) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
+        // Only the administrator hands out privileges and runs the server
+        try!(self.require_admin());
+        let (stmt, grant) = match query {
+            ControlStmt::Grant(stmt) => (stmt, true),
+            ControlStmt::Revoke(stmt) => (stmt, false),
+            ControlStmt::Admin(AdminStmt::Reload) => return self.execute_admin_reload(),
+            ControlStmt::Admin(AdminStmt::Shutdown) => {
+                shutdown::begin("ADMIN SHUTDOWN");
+                return Ok(generate_rows_dummy());
+            }
+        };
+        if !try!(auth::user_exists(&stmt.user)) {
+            return Err(ExecutionError::AuthError(auth::AuthError::UserNotFound));
+        }
+        let loaded;
+        let base = match stmt.database {
+            Some(ref name) => {
+                loaded = try!(Database::load(name));
+                &loaded
+            }
+            None => try!(self.get_own_database()),
+        };
+        let table = stmt.table.as_ref().map(|t| &t[..]);
+        if let Some(t) = table {
+            // Fails if the table does not exist
+            try!(base.load_table(t));
+        }
+        let mut meta = try!(base.load_meta_data());
+        if grant {
+            meta.grant(&stmt.user, table, &stmt.privileges);
+        } else {
+            meta.revoke(&stmt.user, table, &stmt.privileges);
+        }
+        try!(base.save_meta_data(&meta));
+        Ok(generate_rows_dummy())
+    }
+
+    fn execute_show_stmt(
+        &mut self,
+        query: ShowStmt,
     ) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
         match query {
-            UseStmt::Database(querybase) => {
-                self.user._currentDatabase = Some(try!(Database::load(&querybase)));
-                Ok(generate_rows_dummy())
+            ShowStmt::Processlist => self.execute_show_processlist(),
+            ShowStmt::Status => self.execute_show_status(),
+            ShowStmt::TableStatus => self.execute_show_table_status(),
+            ShowStmt::Stats(table) => self.execute_show_stats(table),
+            ShowStmt::CreateView(name) => self.execute_show_create_view(&name),
+            ShowStmt::Databases => self.execute_show_databases(),
+            ShowStmt::DatabaseStatus => self.execute_show_database_status(),
+            ShowStmt::Tables => self.execute_show_tables(),
+            ShowStmt::Columns(name) => self.execute_show_columns(&name),
+            ShowStmt::Engines => self.execute_show_engines(),
+            ShowStmt::Workers => self.execute_show_workers(),
+            ShowStmt::ReplicaStatus => self.execute_show_replica_status(),
+            ShowStmt::Events => self.execute_show_events(),
+            ShowStmt::Progress(id) => self.execute_show_progress(id),
+            ShowStmt::Recovery => {
+                // the findings name tables of all databases
+                try!(self.require_admin());
+                self.execute_show_recovery()
+            }
+            ShowStmt::Grants(user) => {
+                let name = user.unwrap_or(self.user._name.clone());
+                // Everybody may look at their own privileges
+                if name != self.user._name {
+                    try!(self.require_admin());
+                }
+                self.execute_show_grants(&name)
             }
         }
     }
 
-    fn execute_insert_stmt(
+    fn execute_vacuum_stmt(
         &mut self,
-        stmt: InsertStmt,
+        table: Option<String>,
     ) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
-        let table = try!(self.get_table(&stmt.tid));
+        let base = try!(self.get_own_database());
+        let names = match table {
+            Some(name) => {
+                // compacting rewrites the table without changing it
+                try!(self.require_privilege(Some(&name), Privilege::Select));
+                try!(self.require_privilege(Some(&name), Privilege::Insert));
+                vec![name]
+            }
+            None => {
+                // tables the user may not compact are left out
+                let mut names = Vec::new();
+                for name in try!(base.list_tables()) {
+                    if try!(auth::has_privilege(
+                        self.user,
+                        &base,
+                        Some(&name),
+                        Privilege::Select
+                    )) && try!(auth::has_privilege(
+                        self.user,
+                        &base,
+                        Some(&name),
+                        Privilege::Insert
+                    )) {
+                        names.push(name);
+                    }
+                }
+                names
+            }
+        };
 
-        if !stmt.col.is_empty() {
-            return Err(ExecutionError::DebugError(
-                "Not implemented:
-            Insert just some values into some columns.
-            Use insert into table values (_,....) instead"
-                    .into(),
-            ));
+        let columns = vec![
+            Column::new("table", SqlType::Char(64), false, "table", true),
+            Column::new("rows", SqlType::BigInt, false, "rows kept", false),
+            Column::new(
+                "removed",
+                SqlType::BigInt,
+                false,
+                "deleted rows removed",
+                false,
+            ),
+            Column::new(
+                "size_before",
+                SqlType::BigInt,
+                false,
+                "bytes of the data file before",
+                false,
+            ),
+            Column::new(
+                "size_after",
+                SqlType::BigInt,
+                false,
+                "bytes of the data file after",
+                false,
+            ),
+        ];
+        let mut rows = Rows::new(Cursor::new(Vec::<u8>::new()), &columns);
+        for name in names {
+            try!(self.check_cancelled());
+            let report = try!(vacuum::vacuum(try!(base.load_table(&name))));
+            let values = [
+                Lit::String(name),
+                Lit::Int(report.rows as i64),
+                Lit::Int(report.removed as i64),
+                Lit::Int(report.size_before as i64),
+                Lit::Int(report.size_after as i64),
+            ];
+            let mut row = Vec::<u8>::new();
+            for (column, value) in columns.iter().zip(values.iter()) {
+                try!(column.sql_type.encode_into(&mut row, value));
+            }
+            try!(rows.add_row(&row));
         }
+        Ok(rows)
+    }
 
-        let mut writevec = Vec::<u8>::new();
-        {
-            let columns = table.columns();
-            let insertvalues = stmt.val;
-            if insertvalues.len() != columns.len() {
-                return Err(ExecutionError::InsertMissmatch);
+    fn execute_analyze_stmt(
+        &mut self,
+        table: Option<String>,
+    ) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
+        let base = try!(self.get_own_database());
+        let names = match table {
+            Some(name) => {
+                try!(self.require_privilege(Some(&name), Privilege::Select));
+                // the statistics are kept in the database
+                if try!(self.get_table(&name)).connection().is_some() {
+                    return Err(storage::Error::NoImplementation.into());
+                }
+                vec![name]
             }
+            None => {
+                // tables the user may not read are left out
+                let mut names = Vec::new();
+                for name in try!(base.list_tables()) {
+                    if try!(auth::has_privilege(
+                        self.user,
+                        &base,
+                        Some(&name),
+                        Privilege::Select
+                    )) {
+                        names.push(name);
+                    }
+                }
+                names
+            }
+        };
 
-            let mut index = 0;
-
-            for column in table.columns() {
-                info!("inserting at {:?}", writevec.len());
-                info!("This is the insertvalue: {:?}", insertvalues[index]);
-                column
-                    .sql_type
-                    .encode_into(&mut writevec, &insertvalues[index]);
-                index += 1;
+        let columns = vec![
+            Column::new("table", SqlType::Char(64), false, "table", true),
+            Column::new("rows", SqlType::BigInt, false, "rows analyzed", false),
+        ];
+        let mut rows = Rows::new(Cursor::new(Vec::<u8>::new()), &columns);
+        let mut gathered = Vec::new();
+        for name in names {
+            try!(self.check_cancelled());
+            let mut table_rows = try!(self.get_rows(&name));
+            gathered.push(try!(stats::collect(
+                &name,
+                &mut table_rows,
+                temporal::now()
+            )));
+        }
+        let mut meta = try!(base.load_meta_data());
+        for table_stats in gathered {
+            let values = [
+                Lit::String(table_stats.table.clone()),
+                Lit::Int(table_stats.rows as i64),
+            ];
+            let mut row = Vec::<u8>::new();
+            for (column, value) in columns.iter().zip(values.iter()) {
+                try!(column.sql_type.encode_into(&mut row, value));
             }
+            try!(rows.add_row(&row));
+            meta.set_stats(table_stats);
         }
-        let mut engine = table.create_engine();
-        info!("handing data vector {:?} to storage engine", writevec);
-        try!(engine.insert_row(&writevec));
-        Ok(generate_rows_dummy())
+        try!(base.save_meta_data(&meta));
+        Ok(rows)
     }
 
-    fn execute_select_stmt(
+    fn execute_check_table_stmt(
         &mut self,
-        mut stmt: SelectStmt,
+        name: String,
     ) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
-        let masterrow: Rows<Cursor<Vec<u8>>>;
+        try!(self.require_privilege(Some(&name), Privilege::Select));
+        let damages = try!(repair::check(&try!(self.get_table(&name))));
+        let mut messages: Vec<_> = damages
+            .iter()
+            .map(|damage| {
+                let text = match damage {
+                    &Damage::Checksum(addr) => {
+                        format!("row at byte {} does not match its checksum", addr)
+                    }
+                    &Damage::Header(addr) => format!("row at byte {} has a broken header", addr),
+                    &Damage::Truncated(addr) => {
+                        format!("file ends within the row at byte {}", addr)
+                    }
+                };
+                ("error", text)
+            })
+            .collect();
+        let status = if damages.is_empty() { "OK" } else { "Corrupt" };
+        messages.push(("status", status.into()));
+        table_messages(&name, "check", &messages)
+    }
 
-        let mut left = try!(self.get_rows(&stmt.tid[0]));
+    fn execute_repair_table_stmt(
+        &mut self,
+        name: String,
+    ) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
+        // repairing rewrites the table and may lose rows
+        try!(self.require_privilege(Some(&name), Privilege::Insert));
+        try!(self.require_privilege(Some(&name), Privilege::Delete));
+        try!(self.before_write(&name));
+        let report = try!(repair::repair(try!(self.get_table(&name))));
+        let mut messages = vec![("info", format!("{} rows kept", report.rows))];
+        if report.lost > 0 {
+            let text = format!("{} damaged rows left out", report.lost);
+            messages.push(("warning", text));
+        }
+        messages.push(("status", "OK".into()));
+        table_messages(&name, "repair", &messages)
+    }
 
-        let mut name_column_map = HashMap::<String, HashMap<String, usize>>::new();
-        let mut column_index_map = HashMap::<String, usize>::new();
-        let mut column_tablename_map = HashMap::<String, String>::new();
-        let mut columnindex: usize = 0;
-        for column in left.columns.clone() {
-            column_tablename_map.insert(column.name.clone(), stmt.tid[0].clone());
-            column_index_map.insert(column.name.into(), columnindex);
-            columnindex += 1;
+    fn execute_backup_stmt(
+        &mut self,
+        stmt: BackupStmt,
+    ) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
+        // backups hold all tables and are written anywhere on the server
+        try!(self.require_admin());
+        // the snapshot must not hold uncommitted changes
+        if transaction::is_active(self.connection) {
+            return Err(storage::Error::TransactionActive.into());
         }
-        name_column_map.insert(stmt.tid[0].clone(), column_index_map);
-        stmt.alias.insert(stmt.tid[0].clone(), stmt.tid[0].clone());
+        let base = try!(Database::load(&stmt.database));
+        let manifest = try!(backup::backup(&base, &self.statement, &stmt.path));
+        backup_rows(&manifest, &stmt.path)
+    }
 
-        // create a very huge cross product from all tables and some hashmaputilities
-        for i in 1..stmt.tid.len() {
-            let right = try!(self.get_rows(&stmt.tid[i]));
+    fn execute_restore_stmt(
+        &mut self,
+        path: String,
+    ) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
+        try!(self.require_admin());
+        let manifest = try!(backup::restore(&path));
+        backup_rows(&manifest, &path)
+    }
 
-            column_index_map = HashMap::<String, usize>::new();
-            for column in right.columns.clone() {
-                column_tablename_map.insert(column.name.clone(), stmt.tid[i].clone());
-                column_index_map.insert(column.name.into(), columnindex);
-                columnindex += 1;
+    /// Returns the chunks of the text as rows of a single column.
+    fn execute_dump_stmt(
+        &mut self,
+        database: String,
+    ) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
+        let mut chunks = Vec::new();
+        try!(self.dump(&database, &mut |chunk: &str| {
+            chunks.push(chunk.to_string());
+            Ok(())
+        }));
+        Ok(try!(dump::rows(&chunks)))
+    }
+
+    /// Writes the tables of a database as seen by the snapshot of the
+    /// statement as SQL text, see `dump`. The user must be allowed to read
+    /// all of them.
+    fn dump<F>(&mut self, database: &str, write: &mut F) -> Result<(), ExecutionError>
+    where
+        F: FnMut(&str) -> Result<(), ExecutionError>,
+    {
+        let base = try!(Database::load(database));
+        let mut tables = Vec::new();
+        for name in try!(base.list_tables()) {
+            if !try!(auth::has_privilege(
+                self.user,
+                &base,
+                Some(&name),
+                Privilege::Select
+            )) {
+                return Err(ExecutionError::AuthError(auth::AuthError::PermissionDenied));
             }
-            name_column_map.insert(stmt.tid[i].clone(), column_index_map);
-            stmt.alias.insert(stmt.tid[i].clone(), stmt.tid[i].clone());
-            let tmp = try!(self.cross_rows(left, right));
-            left = tmp;
+            tables.push(try!(base.load_table(&name)));
         }
-        masterrow = left;
-
-        // compute conditions
-        let mut whereresult = if stmt.cond.is_some() {
-            println!("Wherestmt {:?}", stmt.cond);
-            try!(self.execute_where(
-                masterrow,
-                (&stmt.alias, &column_tablename_map, &name_column_map),
-                &stmt.cond.unwrap(),
-                false,
-                Where::Select
-            ))
-        } else {
-            masterrow
-        };
-
-        // the string will be but in front of the original rows name.
-        // if bool = false. if bool = true the original columnname will be
-        // overwritten
-        let mut indextargets: Vec<((String, bool), usize)> = Vec::new();
-        for target in stmt.target {
-            let rename = if target.rename.is_some() {
-                let tmp = target.clone();
-                tmp.rename.unwrap().clone()
-            } else {
-                "".into()
-            };
 
-            match target.col {
-                Col::Every => {
-                    if target.alias.is_some() {
-                        let mut targetclone = target.clone();
-                        let tablename = stmt.alias.get(&targetclone.alias.unwrap());
-                        if tablename.is_none() {
-                            return Err(ExecutionError::UnknownAlias);
-                        }
-                        let columntoindex = name_column_map.get(tablename.unwrap()).unwrap();
-                        for index in columntoindex.values() {
-                            targetclone = target.clone();
-                            let append = if target.rename.is_some() {
-                                (rename.clone(), true)
-                            } else {
-                                (format!("{}.", targetclone.alias.unwrap()), false)
-                            };
-                            indextargets.push((append, index.clone()));
-                        }
-                    } else {
-                        for i in 0..(whereresult.columns.len()) {
-                            let append = if target.rename.is_some() {
-                                (rename.clone(), true)
+        let mut chunks = dump::Chunks::new(write);
+        try!(chunks.push(&format!("-- dump of database {}", database)));
+        for table in dump::creation_order(&tables) {
+            try!(self.check_cancelled());
+            try!(chunks.push(&dump::create_table(table)));
+            for index in dump::create_indexes(table) {
+                try!(chunks.push(&index));
+            }
+            let engine = self.versioned(try!(base.load_table(&table.name)));
+            let mut rows = try!(engine.full_scan());
+            try!(rows.reset_pos());
+            let mut row = Vec::new();
+            loop {
+                try!(self.next_row());
+                row.clear();
+                match rows.next_row(&mut row) {
+                    Ok(_) => (),
+                    Err(storage::Error::EndOfFile) => break,
+                    Err(e) => return Err(e.into()),
+                }
+                let mut values = Vec::with_capacity(table.columns().len());
+                for (i, column) in table.columns().iter().enumerate() {
+                    let value = match try!(rows.get_nullable_value(&row, i)) {
+                        None => Lit::Null,
+                        Some(slot) => {
+                            if column.sql_type == SqlType::Blob {
+                                let mut bytes = Vec::new();
+                                let mut reader = try!(engine.read_blob(&slot));
+                                try!(reader.read_to_end(&mut bytes).map_err(storage::Error::Io));
+                                Lit::Bytes(bytes)
                             } else {
-                                ("".into(), false)
-                            };
-                            indextargets.push((append, i));
+                                try!(dump::value(&column.sql_type, &slot))
+                            }
                         }
-                    }
-                }
-                Col::Specified(column) => {
-                    let tablename = if target.alias.is_some() {
-                        stmt.alias.get(&target.alias.unwrap())
-                    } else {
-                        column_tablename_map.get(&column)
                     };
-                    if tablename.is_none() {
-                        return Err(ExecutionError::UnknownColumn);
-                    }
-                    let columntoindex = name_column_map.get(tablename.unwrap()).unwrap();
-                    let column = columntoindex.get(&column);
-                    if column.is_none() {
-                        return Err(ExecutionError::UnknownColumn);
-                    }
-                    let append = if target.rename.is_some() {
-                        (rename.clone(), true)
-                    } else {
-                        (format!("{}.", tablename.unwrap().clone()), false)
-                    };
-                    indextargets.push((append, column.unwrap().clone()));
+                    values.push(value);
                 }
+                try!(chunks.push(&dump::insert(&table.name, &values)));
             }
         }
-
-        try!(whereresult.reset_pos());
-        let mut columnvec: Vec<Column> = Vec::new();
-        for index in indextargets.clone() {
-            whereresult.columns[index.1].name = if (index.0).1 {
-                (index.0).0
-            } else {
-                format!("{}{}", (index.0).0, whereresult.columns[index.1].name)
-            };
-
-            columnvec.push(whereresult.columns[index.1].clone());
+        // views may call the functions
+        let meta = try!(base.load_meta_data());
+        for function in &meta.functions {
+            try!(chunks.push(&dump::create_function(function)));
+        }
+        for view in &meta.views {
+            try!(chunks.push(&dump::create_view(view)));
         }
+        for event in &meta.events {
+            try!(chunks.push(&dump::create_event(event)));
+        }
+        chunks.finish()
+    }
 
-        let cursor = Cursor::new(Vec::<u8>::new());
-        let mut resultrows = Rows::<Cursor<Vec<u8>>>::new(cursor, &columnvec);
+    /// Reads the CSV file from the server, which only admins may do, and
+    /// returns the number of rows inserted.
+    fn execute_copy_stmt(
+        &mut self,
+        stmt: CopyStmt,
+    ) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
+        try!(self.require_admin());
+        let mut file = try!(File::open(&stmt.path).map_err(storage::Error::Io));
+        let inserted = try!(self.copy(&stmt.tid, &mut || {
+            let mut chunk = vec![0; COPY_CHUNK];
+            let len = try!(file.read(&mut chunk).map_err(storage::Error::Io));
+            chunk.truncate(len);
+            Ok(if len == 0 { None } else { Some(chunk) })
+        }));
+        inserted_count(inserted)
+    }
 
-        // TODO: implement skiprow for Rows!!!
-        // TODO: use less function calls of unwrap!!
-        let mut limitcount = (false, 0);
-        if stmt.limit.is_some() {
-            let limit = stmt.limit.unwrap();
-            limitcount = (true, limit.count.unwrap().clone());
-            if limit.offset.is_some() {
-                for _i in 0..limit.offset.unwrap() {
-                    let mut skiprow = Vec::<u8>::new();
-                    match whereresult.next_row(&mut skiprow) {
-                        Ok(_) => (),
-                        Err(_) => break,
-                    };
+    /// Inserts the records of CSV text into a table, see `csv`. A record
+    /// holds a field for every column of the table, in their order. The
+    /// rows are handed to the storage engine in batches.
+    fn copy<F>(&mut self, table: &str, next_chunk: &mut F) -> Result<u64, ExecutionError>
+    where
+        F: FnMut() -> Result<Option<Vec<u8>>, ExecutionError>,
+    {
+        try!(self.require_privilege(Some(table), Privilege::Insert));
+        let table = try!(self.get_table(table));
+        let mut engine = self.versioned(table);
+        let mut reader = csv::Reader::new();
+        let mut records = Vec::new();
+        let mut batch = Vec::with_capacity(COPY_BATCH);
+        let mut inserted = 0;
+        let mut done = false;
+        while !done {
+            try!(self.check_cancelled());
+            let read = match try!(next_chunk()) {
+                Some(chunk) => reader.read(&chunk, &mut records),
+                None => {
+                    done = true;
+                    reader.finish(&mut records)
+                }
+            };
+            try!(read.map_err(ExecutionError::InvalidCsv));
+            for record in records.drain(..) {
+                batch.push(try!(self.copied_row(&*engine, record)));
+                if batch.len() == COPY_BATCH || done {
+                    try!(self.check_cancelled());
+                    inserted += try!(engine.insert_rows(&slices(&batch)));
+                    batch.clear();
                 }
             }
         }
+        if !batch.is_empty() {
+            inserted += try!(engine.insert_rows(&slices(&batch)));
+        }
+        Ok(inserted)
+    }
 
-        // TODO: Errormanagement!!!
-        loop {
-            if limitcount.0 && limitcount.1 == 0 {
-                break;
-            }
-            let mut originalrow = Vec::<u8>::new();
-            let res = whereresult.next_row(&mut originalrow);
-            match res {
-                Ok(_) => (),
-                Err(_) => break,
+    /// Returns the row of a CSV record for the table of the engine.
+    fn copied_row(
+        &self,
+        engine: &dyn Engine,
+        record: csv::Record,
+    ) -> Result<Vec<u8>, ExecutionError> {
+        let columns = engine.table().columns();
+        let invalid = |reason: String| {
+            ExecutionError::InvalidCsv(csv::Error {
+                line: record.line,
+                reason: reason,
+            })
+        };
+        if record.fields.len() != columns.len() {
+            let reason = format!(
+                "{} fields for {} columns",
+                record.fields.len(),
+                columns.len()
+            );
+            return Err(invalid(reason));
+        }
+        let mut values = Vec::with_capacity(columns.len());
+        for (column, field) in columns.iter().zip(record.fields.iter()) {
+            values.push(match field {
+                &None => Lit::Null,
+                &Some(ref field) => match csv::value(&column.sql_type, field) {
+                    Some(value) => value,
+                    None => return Err(invalid(format!("invalid value for {}", column.name))),
+                },
+            });
+        }
+        self.encode_new_row(engine, &values)
+    }
+
+    fn execute_show_processlist(&self) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
+        let columns = vec![
+            Column::new("id", SqlType::Int, false, "connection id", true),
+            Column::new("user", SqlType::Char(32), false, "user", false),
+            Column::new("host", SqlType::Char(48), false, "client address", false),
+            Column::new(
+                "database",
+                SqlType::Char(64),
+                false,
+                "current database",
+                false,
+            ),
+            Column::new(
+                "time",
+                SqlType::Int,
+                false,
+                "seconds in current state",
+                false,
+            ),
+            Column::new("query", SqlType::Char(128), false, "running query", false),
+            Column::new("rows", SqlType::BigInt, false, "rows processed", false),
+        ];
+        let mut rows = Rows::new(Cursor::new(Vec::<u8>::new()), &columns);
+        for p in process::list() {
+            // Everybody may see their own connections
+            if !self.user.is_admin() && p.user != self.user._name {
+                continue;
             }
-            let mut toinsert = Vec::<u8>::new();
-            for index in indextargets.clone() {
-                toinsert.extend(try!(whereresult.get_value(&originalrow, index.1)).into_iter());
+            let values = [
+                Lit::Int(p.id as i64),
+                Lit::String(p.user),
+                Lit::String(p.host),
+                Lit::String(p.database.unwrap_or("".into())),
+                Lit::Int(p.since.elapsed().as_secs() as i64),
+                Lit::String(p.query.unwrap_or("".into())),
+                Lit::Int(p.rows as i64),
+            ];
+            let mut row = Vec::<u8>::new();
+            for (column, value) in columns.iter().zip(values.iter()) {
+                try!(column.sql_type.encode_into(&mut row, value));
             }
-            resultrows.add_row(&toinsert);
-            limitcount.1 -= 1;
+            try!(rows.add_row(&row));
         }
-
-        Ok(resultrows)
+        Ok(rows)
     }
 
-    fn execute_where<'b>(
-        &self,
-        mut tableset: Rows<Cursor<Vec<u8>>>,
-        infos: (
+    /// Shows what the query of a connection is doing: how long it runs, how
+    /// many rows it processed and which table it works on. Like `KILL`, only
+    /// the administrator may look at connections of other users.
+    fn execute_show_progress(&self, id: u64) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
+        let p = match process::get(id) {
+            Some(p) => p,
+            None => return Err(ExecutionError::UnknownConnection(id)),
+        };
+        if !self.user.is_admin() && p.user != self.user._name {
+            return Err(ExecutionError::AuthError(auth::AuthError::PermissionDenied));
+        }
+        let columns = vec![
+            Column::new("id", SqlType::Int, false, "connection id", true),
+            Column::new("user", SqlType::Char(32), false, "user", false),
+            Column::new("state", SqlType::Char(16), false, "running or idle", false),
+            Column::new(
+                "time",
+                SqlType::Int,
+                false,
+                "seconds in current state",
+                false,
+            ),
+            Column::new(
+                "rows",
+                SqlType::BigInt,
+                false,
+                "rows processed by the current or last query",
+                false,
+            ),
+            Column::new(
+                "rows_per_second",
+                SqlType::Double,
+                false,
+                "rows processed per second",
+                false,
+            ),
+            Column::new(
+                "table",
+                SqlType::Char(64),
+                false,
+                "table worked on last",
+                false,
+            ),
+            Column::new("query", SqlType::Char(128), false, "running query", false),
+        ];
+        let mut rows = Rows::new(Cursor::new(Vec::<u8>::new()), &columns);
+        let elapsed = p.since.elapsed();
+        let rate = if p.query.is_some() && elapsed.as_secs_f64() > 0.0 {
+            p.rows as f64 / elapsed.as_secs_f64()
+        } else {
+            0.0
+        };
+        let values = [
+            Lit::Int(p.id as i64),
+            Lit::String(p.user),
+            Lit::String(if p.query.is_some() { "running" } else { "idle" }.into()),
+            Lit::Int(elapsed.as_secs() as i64),
+            Lit::Int(p.rows as i64),
+            Lit::Float(rate),
+            Lit::String(p.table.unwrap_or("".into())),
+            Lit::String(p.query.unwrap_or("".into())),
+        ];
+        let mut row = Vec::<u8>::new();
+        for (column, value) in columns.iter().zip(values.iter()) {
+            try!(column.sql_type.encode_into(&mut row, value));
+        }
+        try!(rows.add_row(&row));
+        Ok(rows)
+    }
+
+    fn execute_show_workers(&self) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
+        let columns = vec![
+            Column::new("id", SqlType::Int, false, "worker id", true),
+            Column::new("busy", SqlType::Bool, false, "runs a job", false),
+            Column::new("queue_depth", SqlType::Int, false, "jobs waiting", false),
+            Column::new(
+                "max_queue_depth",
+                SqlType::Int,
+                false,
+                "most jobs waiting at once",
+                false,
+            ),
+            Column::new("jobs", SqlType::BigInt, false, "jobs started", false),
+        ];
+        let mut rows = Rows::new(Cursor::new(Vec::<u8>::new()), &columns);
+        for w in pool::status() {
+            let values = [
+                Lit::Int(w.id as i64),
+                Lit::Bool(w.busy as u8),
+                Lit::Int(w.queue_depth as i64),
+                Lit::Int(w.max_queue_depth as i64),
+                Lit::Int(w.jobs as i64),
+            ];
+            let mut row = Vec::<u8>::new();
+            for (column, value) in columns.iter().zip(values.iter()) {
+                try!(column.sql_type.encode_into(&mut row, value));
+            }
+            try!(rows.add_row(&row));
+        }
+        Ok(rows)
+    }
+
+    /// Returns how far this server lags behind its leader, no rows if it
+    /// follows none.
+    /// Lists the events of the current database, when they run next and
+    /// how they ran last, see `scheduler`.
+    fn execute_show_events(&self) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
+        let base = try!(self.get_own_database());
+        let columns = vec![
+            Column::new("name", SqlType::Char(64), false, "event name", false),
+            Column::new("definer", SqlType::Char(64), false, "runs as user", false),
+            Column::new(
+                "every",
+                SqlType::BigInt,
+                false,
+                "interval in seconds",
+                false,
+            ),
+            Column::new(
+                "statement",
+                SqlType::Char(128),
+                false,
+                "what it runs",
+                false,
+            ),
+            Column::new("next_run", SqlType::Timestamp, false, "next run", false),
+            Column::new(
+                "last_run",
+                SqlType::Timestamp,
+                true,
+                "last run, null if not run since startup",
+                false,
+            ),
+            Column::new(
+                "last_error",
+                SqlType::Char(128),
+                true,
+                "why the last run failed",
+                false,
+            ),
+        ];
+        let mut rows = Rows::new(Cursor::new(Vec::<u8>::new()), &columns);
+        let now = scheduler::now();
+        for event in try!(base.load_meta_data()).events {
+            let last = scheduler::last_run(&base.name, &event.name);
+            let values = [
+                Lit::String(event.name.clone()),
+                Lit::String(event.definer.clone()),
+                Lit::Int(event.every as i64),
+                Lit::String(event.body.clone()),
+                Lit::Timestamp(scheduler::next_run(&event, now) as i64 * 1_000_000),
+                last.as_ref()
+                    .map_or(Lit::Null, |r| Lit::Timestamp(r.time as i64 * 1_000_000)),
+                last.and_then(|r| r.error).map_or(Lit::Null, Lit::String),
+            ];
+            let mut encoded = Vec::new();
+            for (column, value) in columns.iter().zip(values.iter()) {
+                encoded.push(match value {
+                    &Lit::Null => None,
+                    value => {
+                        let mut data = Vec::new();
+                        try!(column.sql_type.encode_into(&mut data, value));
+                        Some(data)
+                    }
+                });
+            }
+            try!(rows.add_row(&try!(types::encode_row(&columns, &encoded))));
+        }
+        Ok(rows)
+    }
+
+    fn execute_show_replica_status(&self) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
+        let columns = vec![
+            Column::new("leader", SqlType::Char(64), false, "leader address", false),
+            Column::new("state", SqlType::Char(16), false, "connection state", false),
+            Column::new(
+                "applied_lsn",
+                SqlType::BigInt,
+                false,
+                "last change applied",
+                false,
+            ),
+            Column::new(
+                "leader_lsn",
+                SqlType::BigInt,
+                false,
+                "last change of the leader",
+                false,
+            ),
+            Column::new(
+                "lag_changes",
+                SqlType::BigInt,
+                false,
+                "changes not applied",
+                false,
+            ),
+            Column::new(
+                "lag_seconds",
+                SqlType::BigInt,
+                false,
+                "age of the last change applied",
+                false,
+            ),
+            Column::new(
+                "last_error",
+                SqlType::Char(128),
+                false,
+                "why it failed",
+                false,
+            ),
+        ];
+        let mut rows = Rows::new(Cursor::new(Vec::<u8>::new()), &columns);
+        if let Some(status) = replication::status() {
+            let values = [
+                Lit::String(status.leader.clone()),
+                Lit::String(status.state.into()),
+                Lit::Int(status.applied_lsn as i64),
+                Lit::Int(status.leader_lsn as i64),
+                Lit::Int(status.lag_changes() as i64),
+                Lit::Int(status.lag_seconds() as i64),
+                Lit::String(status.last_error.clone().unwrap_or("".into())),
+            ];
+            let mut row = Vec::<u8>::new();
+            for (column, value) in columns.iter().zip(values.iter()) {
+                try!(column.sql_type.encode_into(&mut row, value));
+            }
+            try!(rows.add_row(&row));
+        }
+        Ok(rows)
+    }
+
+    fn execute_show_recovery(&self) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
+        let columns = vec![
+            Column::new("database", SqlType::Char(64), false, "database", false),
+            Column::new("table", SqlType::Char(64), false, "table", false),
+            Column::new("problem", SqlType::Text, false, "what was wrong", false),
+            Column::new(
+                "action",
+                SqlType::Char(16),
+                false,
+                "what was done about it",
+                false,
+            ),
+            Column::new("detail", SqlType::Text, false, "outcome", false),
+        ];
+        let mut rows = Rows::new(Cursor::new(Vec::<u8>::new()), &columns);
+        for finding in recovery::findings() {
+            let values = [
+                Lit::String(finding.database),
+                Lit::String(finding.table),
+                Lit::String(finding.problem),
+                Lit::String(finding.action.name().into()),
+                Lit::String(finding.detail),
+            ];
+            let mut row = Vec::<u8>::new();
+            for (column, value) in columns.iter().zip(values.iter()) {
+                try!(column.sql_type.encode_into(&mut row, value));
+            }
+            try!(rows.add_row(&row));
+        }
+        Ok(rows)
+    }
+
+    /// Reloads the configuration, returns the settings that changed and
+    /// whether they were applied or need a restart.
+    fn execute_admin_reload(&self) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
+        let reload = match config::reload() {
+            Ok(reload) => reload,
+            Err(e) => return Err(ExecutionError::InvalidConfig(e.to_string())),
+        };
+        let columns = vec![
+            Column::new("setting", SqlType::Char(32), false, "setting", true),
+            Column::new(
+                "status",
+                SqlType::Char(16),
+                false,
+                "applied or restart",
+                false,
+            ),
+        ];
+        let changes = reload
+            .applied
+            .iter()
+            .map(|setting| (setting, "applied"))
+            .chain(reload.restart.iter().map(|setting| (setting, "restart")));
+        let mut rows = Rows::new(Cursor::new(Vec::<u8>::new()), &columns);
+        for (setting, status) in changes {
+            let mut row = Vec::<u8>::new();
+            try!(columns[0]
+                .sql_type
+                .encode_into(&mut row, &Lit::String(setting.to_string())));
+            try!(columns[1]
+                .sql_type
+                .encode_into(&mut row, &Lit::String(status.into())));
+            try!(rows.add_row(&row));
+        }
+        Ok(rows)
+    }
+
+    fn execute_show_status(&self) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
+        let columns = vec![
+            Column::new("name", SqlType::Char(32), false, "counter", true),
+            Column::new("value", SqlType::Int, false, "current value", false),
+        ];
+        let mut rows = Rows::new(Cursor::new(Vec::<u8>::new()), &columns);
+        for (name, value) in metrics::status() {
+            let mut row = Vec::<u8>::new();
+            try!(columns[0]
+                .sql_type
+                .encode_into(&mut row, &Lit::String(name)));
+            try!(columns[1]
+                .sql_type
+                .encode_into(&mut row, &Lit::Int(value as i64)));
+            try!(rows.add_row(&row));
+        }
+        Ok(rows)
+    }
+
+    fn execute_show_table_status(&self) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
+        let base = try!(self.get_own_database());
+        let columns = vec![
+            Column::new("table", SqlType::Char(64), false, "table", true),
+            Column::new("engine", SqlType::Char(16), false, "storage engine", false),
+            Column::new(
+                "compression",
+                SqlType::Char(8),
+                false,
+                "compression of the data file",
+                false,
+            ),
+            Column::new(
+                "encrypted",
+                SqlType::Bool,
+                false,
+                "whether the data file is encrypted",
+                false,
+            ),
+            Column::new(
+                "data_length",
+                SqlType::BigInt,
+                false,
+                "bytes of row data",
+                false,
+            ),
+            Column::new(
+                "stored_length",
+                SqlType::BigInt,
+                false,
+                "bytes of the data file",
+                false,
+            ),
+            Column::new(
+                "ratio",
+                SqlType::Double,
+                false,
+                "data length per stored byte",
+                false,
+            ),
+            Column::new(
+                "rows",
+                SqlType::BigInt,
+                true,
+                "rows in the data file, null if not counted",
+                false,
+            ),
+        ];
+        let mut rows = Rows::new(Cursor::new(Vec::<u8>::new()), &columns);
+        for name in try!(base.list_tables()) {
+            // tables the user may not read are left out
+            if !try!(auth::has_privilege(
+                self.user,
+                &base,
+                Some(&name),
+                Privilege::Select
+            )) {
+                continue;
+            }
+            let table = try!(base.load_table(&name));
+            let (raw, stored) = try!(table.data_sizes());
+            let ratio = if stored == 0 {
+                1.0
+            } else {
+                raw as f64 / stored as f64
+            };
+            let counted = if table.engine_id() == EngineID::FlatFile {
+                try!(count::read(&table))
+            } else {
+                None
+            };
+            let values = [
+                Lit::String(name),
+                Lit::String(table.engine_id().name().into()),
+                Lit::String(table.meta_data.compression.name().into()),
+                Lit::Bool(table.meta_data.encrypted as u8),
+                Lit::Int(raw as i64),
+                Lit::Int(stored as i64),
+                Lit::Float(ratio),
+                counted.map_or(Lit::Null, |c| Lit::Int(c as i64)),
+            ];
+            let mut encoded = Vec::new();
+            for (column, value) in columns.iter().zip(values.iter()) {
+                encoded.push(match value {
+                    &Lit::Null => None,
+                    value => {
+                        let mut data = Vec::new();
+                        try!(column.sql_type.encode_into(&mut data, value));
+                        Some(data)
+                    }
+                });
+            }
+            try!(rows.add_row(&try!(types::encode_row(&columns, &encoded))));
+        }
+        Ok(rows)
+    }
+
+    fn execute_show_stats(
+        &self,
+        table: Option<String>,
+    ) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
+        if let Some(ref name) = table {
+            try!(self.require_privilege(Some(name), Privilege::Select));
+        }
+        let base = try!(self.get_own_database());
+        let columns = vec![
+            Column::new("table", SqlType::Char(64), false, "table", false),
+            Column::new("column", SqlType::Char(64), false, "column", false),
+            Column::new("rows", SqlType::BigInt, false, "rows of the table", false),
+            Column::new("nulls", SqlType::BigInt, false, "null values", false),
+            Column::new("distinct", SqlType::BigInt, false, "distinct values", false),
+            Column::new("min", SqlType::VarChar(255), true, "smallest value", false),
+            Column::new("max", SqlType::VarChar(255), true, "largest value", false),
+            Column::new(
+                "buckets",
+                SqlType::Int,
+                false,
+                "buckets of the histogram",
+                false,
+            ),
+            Column::new(
+                "analyzed",
+                SqlType::Timestamp,
+                false,
+                "time of the last ANALYZE",
+                false,
+            ),
+        ];
+        let mut rows = Rows::new(Cursor::new(Vec::<u8>::new()), &columns);
+        let meta = try!(base.load_meta_data());
+        for table_stats in &meta.stats {
+            if table.is_some() && table.as_ref() != Some(&table_stats.table) {
+                continue;
+            }
+            // tables the user may not read are left out
+            if table.is_none()
+                && !try!(auth::has_privilege(
+                    self.user,
+                    &base,
+                    Some(&table_stats.table),
+                    Privilege::Select
+                ))
+            {
+                continue;
+            }
+            let sql_types: HashMap<_, _> = match base.load_table(&table_stats.table) {
+                Ok(t) => t
+                    .columns()
+                    .iter()
+                    .map(|c| (c.name.clone(), c.sql_type))
+                    .collect(),
+                Err(_) => continue,
+            };
+            for column_stats in &table_stats.columns {
+                let sql_type = match sql_types.get(&column_stats.name) {
+                    Some(&sql_type) => sql_type,
+                    None => continue,
+                };
+                let mut bounds = Vec::new();
+                for value in [&column_stats.min, &column_stats.max].iter() {
+                    bounds.push(match **value {
+                        Some(ref value) => {
+                            let mut text = try!(format_value(sql_type, value));
+                            // VARCHAR(255) holds at most 255 bytes
+                            while text.len() > 255 {
+                                text.pop();
+                            }
+                            Some(Lit::String(text))
+                        }
+                        None => None,
+                    });
+                }
+                let values = [
+                    Some(Lit::String(table_stats.table.clone())),
+                    Some(Lit::String(column_stats.name.clone())),
+                    Some(Lit::Int(table_stats.rows as i64)),
+                    Some(Lit::Int(column_stats.nulls as i64)),
+                    Some(Lit::Int(column_stats.distinct as i64)),
+                    bounds[0].take(),
+                    bounds[1].take(),
+                    Some(Lit::Int(column_stats.histogram.len() as i64)),
+                    Some(Lit::Timestamp(table_stats.analyzed)),
+                ];
+                let mut encoded = Vec::new();
+                for (column, value) in columns.iter().zip(values.iter()) {
+                    encoded.push(match value {
+                        &Some(ref value) => {
+                            let mut data = Vec::new();
+                            try!(column.sql_type.encode_into(&mut data, value));
+                            Some(data)
+                        }
+                        &None => None,
+                    });
+                }
+                try!(rows.add_values(&encoded));
+            }
+        }
+        Ok(rows)
+    }
+
+    fn execute_show_grants(&self, name: &str) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
+        let columns = vec![
+            Column::new("database", SqlType::Char(64), false, "database", false),
+            Column::new("table", SqlType::Char(64), false, "table or *", false),
+            Column::new(
+                "privileges",
+                SqlType::Char(64),
+                false,
+                "granted privileges",
+                false,
+            ),
+        ];
+        let mut rows = Rows::new(Cursor::new(Vec::<u8>::new()), &columns);
+        for dbname in try!(Database::list()) {
+            let base = try!(Database::load(&dbname));
+            let meta = try!(base.load_meta_data());
+            for grant in meta.grants.iter().filter(|g| g.user == name) {
+                let privileges: Vec<_> = grant.privileges.iter().map(|p| p.name()).collect();
+                let values = [
+                    Lit::String(dbname.clone()),
+                    Lit::String(grant.table.clone().unwrap_or("*".into())),
+                    Lit::String(privileges.join(", ")),
+                ];
+                let mut row = Vec::<u8>::new();
+                for (column, value) in columns.iter().zip(values.iter()) {
+                    try!(column.sql_type.encode_into(&mut row, value));
+                }
+                try!(rows.add_row(&row));
+            }
+        }
+        Ok(rows)
+    }
+
+    fn execute_show_create_view(
+        &self,
+        name: &str,
+    ) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
+        try!(self.require_privilege(Some(name), Privilege::Select));
+        let view = match try!(self.view(name)) {
+            Some(view) => view,
+            None => return Err(ExecutionError::UnknownView(name.into())),
+        };
+        let columns = vec![
+            Column::new("view", SqlType::Char(64), false, "view", false),
+            Column::new(
+                "create_view",
+                SqlType::Text,
+                false,
+                "statement creating the view",
+                false,
+            ),
+        ];
+        let mut rows = Rows::new(Cursor::new(Vec::<u8>::new()), &columns);
+        let values = [
+            Lit::String(view.name.clone()),
+            Lit::String(format!("CREATE VIEW {} AS {}", view.name, view.definition)),
+        ];
+        let mut row = Vec::<u8>::new();
+        for (column, value) in columns.iter().zip(values.iter()) {
+            try!(column.sql_type.encode_into(&mut row, value));
+        }
+        try!(rows.add_row(&row));
+        Ok(rows)
+    }
+
+    /// Lists the databases the user may use.
+    fn execute_show_databases(&self) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
+        let columns = vec![Column::new(
+            "database",
+            SqlType::VarChar(64),
+            false,
+            "name of the database",
+            true,
+        )];
+        let mut rows = Rows::new(Cursor::new(Vec::<u8>::new()), &columns);
+        let mut names = try!(Database::list());
+        names.sort();
+        for name in names {
+            let base = try!(Database::load(&name));
+            if try!(auth::has_any_privilege(self.user, &base)) {
+                try!(catalog::add_row(&mut rows, &[Lit::String(name)]));
+            }
+        }
+        Ok(rows)
+    }
+
+    /// Lists the size and the connections of the databases the user may use
+    /// next to their limits, see `DatabaseLimits`.
+    fn execute_show_database_status(&self) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
+        let columns = vec![
+            Column::new(
+                "database",
+                SqlType::VarChar(64),
+                false,
+                "name of the database",
+                true,
+            ),
+            Column::new("size", SqlType::BigInt, false, "bytes of its files", false),
+            Column::new(
+                "max_size",
+                SqlType::BigInt,
+                false,
+                "bytes it may take, 0 for no limit",
+                false,
+            ),
+            Column::new(
+                "connections",
+                SqlType::BigInt,
+                false,
+                "connections using it",
+                false,
+            ),
+            Column::new(
+                "max_connections",
+                SqlType::BigInt,
+                false,
+                "connections that may use it, 0 for no limit",
+                false,
+            ),
+        ];
+        let mut rows = Rows::new(Cursor::new(Vec::<u8>::new()), &columns);
+        let processes = process::list();
+        for name in try!(Database::list()) {
+            let base = try!(Database::load(&name));
+            if !try!(auth::has_any_privilege(self.user, &base)) {
+                continue;
+            }
+            let limits = try!(base.load_meta_data()).limits;
+            let connections = processes
+                .iter()
+                .filter(|p| p.database.as_ref() == Some(&name))
+                .count();
+            let values = [
+                Lit::String(name.clone()),
+                Lit::Int(try!(base.size()) as i64),
+                Lit::Int(limits.max_size as i64),
+                Lit::Int(connections as i64),
+                Lit::Int(limits.max_connections as i64),
+            ];
+            try!(catalog::add_row(&mut rows, &values));
+        }
+        Ok(rows)
+    }
+
+    /// Lists the tables and views of the current database the user may
+    /// read.
+    fn execute_show_tables(&self) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
+        let base = try!(self.get_own_database());
+        let columns = vec![
+            Column::new(
+                "table",
+                SqlType::VarChar(64),
+                false,
+                "name of the table or view",
+                true,
+            ),
+            Column::new("type", SqlType::VarChar(8), false, "table or view", false),
+            Column::new(
+                "engine",
+                SqlType::VarChar(16),
+                true,
+                "storage engine, null for a view",
+                false,
+            ),
+        ];
+        let mut rows = Rows::new(Cursor::new(Vec::<u8>::new()), &columns);
+        let mut names = try!(base.list_tables());
+        names.sort();
+        for name in names {
+            if !try!(auth::has_privilege(
+                self.user,
+                &base,
+                Some(&name),
+                Privilege::Select
+            )) {
+                continue;
+            }
+            let engine = try!(base.load_table(&name)).engine_id();
+            try!(catalog::add_row(
+                &mut rows,
+                &[
+                    Lit::String(name),
+                    Lit::String("table".into()),
+                    Lit::String(engine.name().into()),
+                ]
+            ));
+        }
+        let mut views = try!(base.load_meta_data()).views;
+        views.sort_by(|a, b| a.name.cmp(&b.name));
+        for view in views {
+            if !try!(auth::has_privilege(
+                self.user,
+                &base,
+                Some(&view.name),
+                Privilege::Select
+            )) {
+                continue;
+            }
+            try!(catalog::add_row(
+                &mut rows,
+                &[
+                    Lit::String(view.name),
+                    Lit::String("view".into()),
+                    Lit::Null,
+                ]
+            ));
+        }
+        Ok(rows)
+    }
+
+    /// Lists the columns of a table, view or catalog table, like
+    /// `information_schema.columns` does for tables.
+    fn execute_show_columns(
+        &mut self,
+        name: &str,
+    ) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
+        // the catalog only describes what the user may read
+        if Catalog::from_name(name).is_none() {
+            try!(self.require_privilege(Some(name), Privilege::Select));
+        }
+        let shown = try!(self.source_columns(name));
+        let columns = vec![
+            Column::new(
+                "column",
+                SqlType::VarChar(64),
+                false,
+                "name of the column",
+                true,
+            ),
+            Column::new(
+                "type",
+                SqlType::VarChar(64),
+                false,
+                "type as written in CREATE TABLE",
+                false,
+            ),
+            Column::new(
+                "nullable",
+                SqlType::Bool,
+                false,
+                "whether the column allows null",
+                false,
+            ),
+            Column::new(
+                "primary_key",
+                SqlType::Bool,
+                false,
+                "whether the column is part of the primary key",
+                false,
+            ),
+            Column::new(
+                "default",
+                SqlType::Text,
+                true,
+                "default value as written in SQL, null if there is none",
+                false,
+            ),
+            Column::new(
+                "description",
+                SqlType::Text,
+                false,
+                "comment of the column",
+                false,
+            ),
+        ];
+        let mut rows = Rows::new(Cursor::new(Vec::<u8>::new()), &columns);
+        for column in shown {
+            try!(catalog::add_row(
+                &mut rows,
+                &[
+                    Lit::String(column.name),
+                    Lit::String(dump::type_name(&column.sql_type)),
+                    Lit::Bool(column.allow_null as u8),
+                    Lit::Bool(column.is_primary_key as u8),
+                    column
+                        .default
+                        .as_ref()
+                        .map_or(Lit::Null, |d| Lit::String(dump::literal(d))),
+                    Lit::String(column.description),
+                ]
+            ));
+        }
+        Ok(rows)
+    }
+
+    /// Lists the storage engines a table may be created with.
+    fn execute_show_engines(&self) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
+        let columns = vec![
+            Column::new(
+                "engine",
+                SqlType::VarChar(16),
+                false,
+                "storage engine",
+                true,
+            ),
+            Column::new(
+                "default",
+                SqlType::Bool,
+                false,
+                "whether tables are created with it unless ENGINE is given",
+                false,
+            ),
+            Column::new(
+                "description",
+                SqlType::Text,
+                false,
+                "how the engine keeps the rows",
+                false,
+            ),
+        ];
+        let mut rows = Rows::new(Cursor::new(Vec::<u8>::new()), &columns);
+        for engine in EngineID::all() {
+            try!(catalog::add_row(
+                &mut rows,
+                &[
+                    Lit::String(engine.name().into()),
+                    Lit::Bool((engine == EngineID::FlatFile) as u8),
+                    Lit::String(engine.description().into()),
+                ]
+            ));
+        }
+        Ok(rows)
+    }
+
+    fn execute_use_stmt(
+        &mut self,
+        query: UseStmt,
+    ) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
+        match query {
+            UseStmt::Database(querybase) => {
+                let base = try!(Database::load(&querybase));
+                if !try!(auth::has_any_privilege(self.user, &base)) {
+                    return Err(ExecutionError::AuthError(auth::AuthError::PermissionDenied));
+                }
+                let max = try!(base.load_meta_data()).limits.max_connections;
+                if process::enter_database(self.connection, &base.name, max).is_err() {
+                    return Err(ExecutionError::TooManyConnections(base.name));
+                }
+                self.user._currentDatabase = Some(base);
+                Ok(generate_rows_dummy())
+            }
+        }
+    }
+
+    fn execute_insert_stmt(
+        &mut self,
+        mut stmt: InsertStmt,
+    ) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
+        try!(self.require_privilege(Some(&stmt.tid), Privilege::Insert));
+        if let Some(on_duplicate) = stmt.on_duplicate.take() {
+            let columns = try!(self.get_table(&stmt.tid)).columns().to_vec();
+            let mut rows = Vec::with_capacity(stmt.val.len());
+            for row in &stmt.val {
+                rows.push(try!(inserted_values(&columns, &stmt.col, row)));
+            }
+            return self.insert_or_update(&stmt.tid, rows, &on_duplicate);
+        }
+        let table = try!(self.get_table(&stmt.tid));
+        let mut engine = self.versioned(table);
+
+        // all rows are handed to the storage engine at once
+        let mut writevecs = Vec::with_capacity(stmt.val.len());
+        for row in &stmt.val {
+            let insertvalues = try!(inserted_values(engine.table().columns(), &stmt.col, row));
+            writevecs.push(try!(self.encode_new_row(&*engine, &insertvalues)));
+        }
+        info!("handing {} rows to storage engine", writevecs.len());
+        let inserted = try!(engine.insert_rows(&slices(&writevecs)));
+        inserted_count(inserted)
+    }
+
+    /// Inserts the rows of a select, handed to the storage engine in batches
+    /// like those of `COPY`. The select is executed before the first row is
+    /// inserted, so it does not see the rows it inserts. Returns the number
+    /// of rows inserted.
+    fn execute_insert_select_stmt(
+        &mut self,
+        stmt: InsertSelectStmt,
+    ) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
+        try!(self.require_privilege(Some(&stmt.tid), Privilege::Insert));
+        let mut rows = try!(self.execute_compound_stmt(stmt.select));
+        let table = try!(self.get_table(&stmt.tid));
+        let mut engine = self.versioned(table);
+        let expected = match stmt.col.len() {
+            0 => engine.table().columns().len(),
+            len => len,
+        };
+        if rows.columns.len() != expected {
+            return Err(ExecutionError::InsertMissmatch);
+        }
+
+        try!(rows.reset_pos());
+        // rows colliding with existing ones are handled one by one
+        let mut values_kept = Vec::new();
+        let mut batch = Vec::with_capacity(COPY_BATCH);
+        let mut inserted = 0;
+        let mut row = Vec::new();
+        loop {
+            try!(self.next_row());
+            row.clear();
+            match rows.next_row(&mut row) {
+                Ok(_) => (),
+                Err(storage::Error::EndOfFile) => break,
+                Err(e) => return Err(e.into()),
+            }
+            let mut values = Vec::with_capacity(rows.columns.len());
+            for (i, column) in rows.columns.iter().enumerate() {
+                values.push(match try!(rows.get_nullable_value(&row, i)) {
+                    Some(value) => try!(dump::value(&column.sql_type, &value)),
+                    None => Lit::Null,
+                });
+            }
+            let values = try!(inserted_values(
+                engine.table().columns(),
+                &stmt.col,
+                &values
+            ));
+            if stmt.on_duplicate.is_some() {
+                values_kept.push(values);
+                continue;
+            }
+            batch.push(try!(self.encode_new_row(&*engine, &values)));
+            if batch.len() == COPY_BATCH {
+                inserted += try!(engine.insert_rows(&slices(&batch)));
+                batch.clear();
+            }
+        }
+        if !batch.is_empty() {
+            inserted += try!(engine.insert_rows(&slices(&batch)));
+        }
+        drop(engine);
+        if let Some(ref on_duplicate) = stmt.on_duplicate {
+            return self.insert_or_update(&stmt.tid, values_kept, on_duplicate);
+        }
+        inserted_count(inserted)
+    }
+
+    /// Inserts rows of the values of all columns of a table one by one. A
+    /// row whose primary or unique key exists already is skipped or updates
+    /// the existing row instead. Returns the numbers of inserted and updated
+    /// rows, a row updated to the values it had counts as neither.
+    fn insert_or_update(
+        &mut self,
+        table: &str,
+        rows: Vec<Vec<Lit>>,
+        on_duplicate: &OnDuplicate,
+    ) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
+        let columns = try!(self.get_table(table)).columns().to_vec();
+        let position = |alias: &Option<String>, name: &String| {
+            if alias.as_ref().map_or(false, |a| a != table) {
+                return Err(ExecutionError::UnknownAlias);
+            }
+            columns
+                .iter()
+                .position(|c| &c.name == name)
+                .ok_or(ExecutionError::UnknownColumn)
+        };
+        let mut assignments = Vec::new();
+        if let &OnDuplicate::Update(ref set) = on_duplicate {
+            try!(self.require_privilege(Some(table), Privilege::Update));
+            for set in set {
+                let index = try!(position(&set.aliascol, &set.col));
+                let mut lit = match set.rhs {
+                    CondType::Literal(ref lit) => lit.clone(),
+                    CondType::Word(ref word) => Lit::Column(None, word.clone()),
+                };
+                try!(self.materialize(&mut lit));
+                assignments.push((index, lit));
+            }
+        }
+
+        let (mut inserted, mut updated) = (0, 0);
+        for values in rows {
+            try!(self.next_row());
+            let new_row = {
+                let engine = try!(self.get_engine(table));
+                try!(self.encode_new_row(&*engine, &values))
+            };
+            let old = match try!(self.duplicate_row(table, &new_row)) {
+                Some(old) => old,
+                None => {
+                    let mut engine = try!(self.get_engine(table));
+                    try!(engine.insert_row(&new_row));
+                    inserted += 1;
+                    continue;
+                }
+            };
+            if assignments.is_empty() {
+                continue;
+            }
+
+            let updated_row = {
+                let engine = try!(self.get_engine(table));
+                let value = |alias: &Option<String>, name: &String| {
+                    column_value(&columns, &old, try!(position(alias, name)))
+                };
+                let mut new_values = row_values(&columns, &old);
+                for &(index, ref lit) in &assignments {
+                    let lit = try!(proposed_values(lit, &columns, &values));
+                    let new_value = try!(evaluate_with(&lit, &value));
+                    new_values[index] =
+                        try!(self.encode_value(&*engine, &columns[index], &new_value));
+                }
+                let row = try!(types::encode_row(&columns, &new_values));
+                try!(check_constraints(engine.table(), &row));
+                try!(self.check_references(engine.table(), &row));
+                row
+            };
+            if updated_row == old {
+                continue;
+            }
+            try!(self.check_key_changes(table, &columns, &[old.clone()], &[updated_row.clone()]));
+            try!(self.remove_rows(table, &[old]));
+            let mut engine = try!(self.get_engine(table));
+            try!(engine.insert_row(&updated_row));
+            updated += 1;
+        }
+
+        let columns = vec![
+            Column::new("inserted", SqlType::BigInt, false, "inserted rows", false),
+            Column::new("updated", SqlType::BigInt, false, "updated rows", false),
+        ];
+        let mut result = Rows::new(Cursor::new(Vec::<u8>::new()), &columns);
+        let mut row = Vec::<u8>::new();
+        for (column, value) in columns.iter().zip(&[inserted, updated]) {
+            try!(column.sql_type.encode_into(&mut row, &Lit::Int(*value)));
+        }
+        try!(result.add_row(&row));
+        Ok(result)
+    }
+
+    /// Returns the row of the values of all columns of the table of the
+    /// engine, in their order. BLOB values are stored right away. Fails if
+    /// the row violates a constraint.
+    fn encode_new_row(
+        &self,
+        engine: &dyn Engine,
+        values: &[Lit],
+    ) -> Result<Vec<u8>, ExecutionError> {
+        let columns = engine.table().columns();
+        let mut encoded = Vec::new();
+        for (column, value) in columns.iter().zip(values.iter()) {
+            info!("This is the insertvalue: {:?}", value);
+            encoded.push(try!(self.encode_value(engine, column, value)));
+        }
+        let row = try!(storage::types::encode_row(columns, &encoded));
+        try!(check_constraints(engine.table(), &row));
+        try!(self.check_references(engine.table(), &row));
+        Ok(row)
+    }
+
+    /// Returns the bytes of a new value of a column of the table of the
+    /// engine, None for null. BLOB values are stored right away.
+    fn encode_value(
+        &self,
+        engine: &dyn Engine,
+        column: &Column,
+        value: &Lit,
+    ) -> Result<Option<Vec<u8>>, ExecutionError> {
+        if let &Lit::Null = value {
+            if !column.allow_null {
+                return Err(ExecutionError::NotNullViolation(column.name.clone()));
+            }
+            return Ok(None);
+        }
+        if column.sql_type == SqlType::Blob {
+            let bytes = match value {
+                &Lit::Bytes(ref b) => &b[..],
+                &Lit::String(ref s) => s.as_bytes(),
+                _ => return Err(ExecutionError::StorageError(storage::Error::InvalidType)),
+            };
+            return Ok(Some(try!(engine.write_blob(&mut Cursor::new(bytes)))));
+        }
+        let mut data = Vec::<u8>::new();
+        try!(column.sql_type.encode_into(&mut data, value));
+        Ok(Some(data))
+    }
+
+    /// Answers `SELECT COUNT(*)`, which must be the only target. Counting
+    /// all rows of a single table needs no scan, see `stored_count`.
+    fn execute_count_stmt(
+        &mut self,
+        mut stmt: SelectStmt,
+    ) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
+        if stmt.target.len() != 1 {
+            return Err(ExecutionError::DebugError(
+                "COUNT(*) must be the only target".into(),
+            ));
+        }
+        let name = stmt.target[0].rename.take().unwrap_or("count".into());
+        let stored = if stmt.cond.is_none() && stmt.tid.len() == 1 && stmt.joins.is_empty() {
+            try!(self.stored_count(&stmt.tid[0]))
+        } else {
+            None
+        };
+        let count = match stored {
+            Some(count) => count,
+            None => {
+                stmt.target[0].col = Col::Every;
+                stmt.order.clear();
+                stmt.limit = None;
+                try!(try!(self.execute_select_stmt(stmt)).count_rows()).0
+            }
+        };
+        let columns = vec![Column::new(
+            &name,
+            SqlType::BigInt,
+            false,
+            "number of rows",
+            false,
+        )];
+        let mut rows = Rows::new(Cursor::new(Vec::<u8>::new()), &columns);
+        let mut row = Vec::new();
+        try!(SqlType::BigInt.encode_into(&mut row, &Lit::Int(count as i64)));
+        try!(rows.add_row(&row));
+        Ok(rows)
+    }
+
+    /// Returns the number of rows of a table kept by the flat file engine,
+    /// if the snapshot of the statement sees all rows of its data file. A
+    /// count that is out of date is replaced by counting the rows of the
+    /// file.
+    fn stored_count(&self, name: &str) -> Result<Option<u64>, ExecutionError> {
+        if Catalog::from_name(name).is_some() || try!(self.view(name)).is_some() {
+            return Ok(None);
+        }
+        let table = try!(self.get_table(name));
+        if table.connection().is_some() || table.engine_id() != EngineID::FlatFile {
+            return Ok(None);
+        }
+        // writes record their changes before they write the rows
+        let database = &table.database().name;
+        let _writing = lock::latch(database, &table.name);
+        if !self
+            .statement
+            .invisible_changes(database, &table.name)
+            .is_empty()
+        {
+            return Ok(None);
+        }
+        if let Some(count) = try!(count::read(&table)) {
+            return Ok(Some(count));
+        }
+        let (count, _) = try!(table.count_rows());
+        try!(count::write(&table, count));
+        Ok(Some(count))
+    }
+
+    /// Checks that the user may read the tables of a select.
+    fn require_select(&self, stmt: &SelectStmt) -> Result<(), ExecutionError> {
+        for tid in stmt.tid.iter().chain(stmt.joins.iter().map(|j| &j.tid)) {
+            // the catalog only describes what the user may read
+            if Catalog::from_name(tid).is_none() {
+                try!(self.require_privilege(Some(tid), Privilege::Select));
+            }
+        }
+        Ok(())
+    }
+
+    fn execute_select_stmt(
+        &mut self,
+        mut stmt: SelectStmt,
+    ) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
+        try!(self.require_select(&stmt));
+        try!(self.materialize_select(&mut stmt));
+        if stmt.target.iter().any(|t| t.col == Col::Count) {
+            return self.execute_count_stmt(stmt);
+        }
+        // checked after the other conditions, row by row
+        let correlated = split_correlated(&mut stmt);
+        let masterrow: Rows<Cursor<Vec<u8>>>;
+
+        // the tables in the order of the statement, kept by SELECT *
+        let statement_tables: Vec<String> = stmt
+            .tid
+            .iter()
+            .chain(stmt.joins.iter().map(|join| &join.tid))
+            .cloned()
+            .collect();
+        // the name every table goes by, its alias or its own name; columns
+        // are looked up by it, so that a table joined to itself is read for
+        // every alias
+        let names: Vec<String> = statement_tables
+            .iter()
+            .enumerate()
+            .map(|(i, table)| stmt.names.get(i).unwrap_or(table).clone())
+            .collect();
+        let name_of = |position: usize, table: &String| {
+            // tables are joined in another order only if none repeats
+            if statement_tables[position] == *table {
+                names[position].clone()
+            } else {
+                let i = statement_tables.iter().position(|t| t == table);
+                names[i.unwrap()].clone()
+            }
+        };
+        // conditions on a single table resolve aliases to tables
+        let table_alias = stmt.alias.clone();
+        let joined = !stmt.joins.is_empty();
+        let mut ordered = false;
+        let (first, mut left, others, mut filters) = if statement_tables.len() > 1 {
+            let mut plan = try!(self.plan_joins(&mut stmt));
+            let mut tables = plan.tables.into_iter();
+            let first = tables.next().unwrap().0;
+            let filter = plan.filters.remove(&first);
+            let left = try!(self.filtered_rows(&first, filter, &table_alias));
+            (first, left, tables.collect(), plan.filters)
+        } else {
+            let pushed_down = match try!(self.range_pushdown(&stmt)) {
+                Some((rows, is_ordered)) => {
+                    ordered = is_ordered;
+                    Some((rows, None))
+                }
+                None => try!(self.lookup_pushdown(&stmt)),
+            };
+            let left = match pushed_down {
+                Some((rows, rest)) => {
+                    stmt.cond = rest;
+                    rows
+                }
+                None => try!(self.select_rows(&stmt.tid[0])),
+            };
+            (stmt.tid[0].clone(), left, Vec::new(), HashMap::new())
+        };
+        // conditions on the joined rows resolve aliases, and the names of
+        // tables not joined to themselves, to the names the tables go by
+        for (name, table) in names.iter().zip(&statement_tables) {
+            stmt.alias.insert(name.clone(), name.clone());
+            if statement_tables.iter().filter(|t| *t == table).count() == 1 {
+                stmt.alias.entry(table.clone()).or_insert(name.clone());
+            }
+        }
+
+        let mut name_column_map = HashMap::<String, HashMap<String, usize>>::new();
+        let mut column_index_map = HashMap::<String, usize>::new();
+        let mut column_tablename_map = HashMap::<String, String>::new();
+        // the name of the table of every column of the cross product
+        let mut column_tables = Vec::new();
+        let mut columnindex: usize = 0;
+        let first_name = name_of(0, &first);
+        for column in left.columns.clone() {
+            column_tables.push(first_name.clone());
+            column_tablename_map.insert(column.name.clone(), first_name.clone());
+            column_index_map.insert(column.name.into(), columnindex);
+            columnindex += 1;
+        }
+        name_column_map.insert(first_name.clone(), column_index_map);
+        let mut name_tables = HashMap::new();
+        name_tables.insert(first_name, first);
+
+        // create a very huge cross product from all tables and some hashmaputilities,
+        // joined tables are only combined with the rows fulfilling their condition
+        let count = others.len();
+        for (i, (tid, cond)) in others.into_iter().enumerate() {
+            let filter = filters.remove(&tid);
+            let right = try!(self.filtered_rows(&tid, filter, &table_alias));
+
+            let name = name_of(i + 1, &tid);
+            column_index_map = HashMap::<String, usize>::new();
+            for column in right.columns.clone() {
+                column_tables.push(name.clone());
+                column_tablename_map.insert(column.name.clone(), name.clone());
+                column_index_map.insert(column.name.into(), columnindex);
+                columnindex += 1;
+            }
+            name_column_map.insert(name.clone(), column_index_map);
+            name_tables.insert(name, tid);
+            left = match cond {
+                Some(cond) => {
+                    let infos = (&stmt.alias, &column_tablename_map, &name_column_map);
+                    // a sort-merge join of the last table may spare sorting
+                    // the result, which a WHERE condition with OR mixes up
+                    let order = match stmt.order.first() {
+                        Some(sort)
+                            if stmt.order.len() == 1
+                                && sort.order != Some(Order::Desc)
+                                && i + 1 == count
+                                && !stmt.cond.as_ref().map_or(false, has_or) =>
+                        {
+                            resolve_column(infos, &sort.alias, &sort.col).ok()
+                        }
+                        _ => None,
+                    };
+                    let (rows, is_ordered) = try!(self.join_rows(left, right, infos, &cond, order));
+                    ordered = is_ordered;
+                    rows
+                }
+                None => {
+                    ordered = false;
+                    try!(self.cross_rows(left, right))
+                }
+            };
+        }
+        masterrow = left;
+
+        // compute conditions
+        let mut whereresult = if stmt.cond.is_some() {
+            println!("Wherestmt {:?}", stmt.cond);
+            try!(self.execute_where(
+                masterrow,
+                (&stmt.alias, &column_tablename_map, &name_column_map),
+                &stmt.cond.unwrap(),
+                false,
+                Where::Select
+            ))
+        } else {
+            masterrow
+        };
+        if !correlated.is_empty() {
+            whereresult = try!(self.correlated_where(
+                whereresult,
+                (&stmt.alias, &column_tablename_map, &name_column_map),
+                &correlated
+            ));
+        }
+        let windows: Vec<Window> = stmt
+            .target
+            .iter()
+            .filter_map(|target| match target.col {
+                Col::Window(ref window) => Some(window.clone()),
+                _ => None,
+            })
+            .collect();
+        // window functions sort the rows themselves
+        if !stmt.order.is_empty() && !ordered && windows.is_empty() {
+            whereresult = try!(self.sort_rows(
+                whereresult,
+                (&stmt.alias, &column_tablename_map, &name_column_map),
+                &stmt.order
+            ));
+        }
+
+        // expressions of the targets become columns after those of the
+        // tables, followed by the arguments of window functions that are no
+        // columns and by the values of the window functions
+        let mut expressions: Vec<Lit> = stmt
+            .target
+            .iter()
+            .filter_map(|target| match target.col {
+                Col::Expr(ref lit) => Some(lit.clone()),
+                _ => None,
+            })
+            .collect();
+        let arguments = column_tables.len() + expressions.len();
+        expressions.extend(windows.iter().filter_map(window_argument));
+        if !expressions.is_empty() {
+            whereresult = try!(self.compute_expressions(
+                whereresult,
+                (&stmt.alias, &column_tablename_map, &name_column_map),
+                &expressions
+            ));
+        }
+        if !windows.is_empty() {
+            let infos = (&stmt.alias, &column_tablename_map, &name_column_map);
+            whereresult = try!(self.compute_windows(whereresult, infos, &windows, arguments));
+            if !stmt.order.is_empty() {
+                whereresult = try!(self.sort_rows(whereresult, infos, &stmt.order));
+            }
+        }
+        let mut expressionindex = column_tables.len();
+        let mut windowindex = column_tables.len() + expressions.len();
+
+        // the string will be but in front of the original rows name.
+        // if bool = false. if bool = true the original columnname will be
+        // overwritten
+        let mut indextargets: Vec<((String, bool), usize)> = Vec::new();
+        for target in stmt.target {
+            let rename = if target.rename.is_some() {
+                let tmp = target.clone();
+                tmp.rename.unwrap().clone()
+            } else {
+                "".into()
+            };
+
+            match target.col {
+                Col::Every => {
+                    if target.alias.is_some() {
+                        let mut targetclone = target.clone();
+                        let tablename = stmt.alias.get(&targetclone.alias.unwrap());
+                        if tablename.is_none() {
+                            return Err(ExecutionError::UnknownAlias);
+                        }
+                        let columntoindex = name_column_map.get(tablename.unwrap()).unwrap();
+                        // in the order of the columns of the table
+                        let mut indexes: Vec<usize> = columntoindex.values().cloned().collect();
+                        indexes.sort();
+                        for index in indexes {
+                            targetclone = target.clone();
+                            let append = if target.rename.is_some() {
+                                (rename.clone(), true)
+                            } else {
+                                (format!("{}.", targetclone.alias.unwrap()), false)
+                            };
+                            indextargets.push((append, index.clone()));
+                        }
+                    } else {
+                        // in the order of the tables in the statement
+                        let mut every: Vec<usize> = (0..column_tables.len()).collect();
+                        every.sort_by_key(|&i| names.iter().position(|t| t == &column_tables[i]));
+                        for i in every {
+                            let append = if target.rename.is_some() {
+                                (rename.clone(), true)
+                            } else if joined {
+                                // joined tables may share column names
+                                (format!("{}.", column_tables[i]), false)
+                            } else {
+                                ("".into(), false)
+                            };
+                            indextargets.push((append, i));
+                        }
+                    }
+                }
+                Col::Specified(column) => {
+                    let tablename = if target.alias.is_some() {
+                        stmt.alias.get(&target.alias.unwrap())
+                    } else {
+                        column_tablename_map.get(&column)
+                    };
+                    if tablename.is_none() {
+                        return Err(ExecutionError::UnknownColumn);
+                    }
+                    let columntoindex = name_column_map.get(tablename.unwrap()).unwrap();
+                    let column = columntoindex.get(&column);
+                    if column.is_none() {
+                        return Err(ExecutionError::UnknownColumn);
+                    }
+                    let append = if target.rename.is_some() {
+                        (rename.clone(), true)
+                    } else {
+                        (format!("{}.", tablename.unwrap().clone()), false)
+                    };
+                    indextargets.push((append, column.unwrap().clone()));
+                }
+                Col::Expr(lit) => {
+                    let name = target.rename.unwrap_or_else(|| dump::literal(&lit));
+                    indextargets.push(((name, true), expressionindex));
+                    expressionindex += 1;
+                }
+                Col::Window(window) => {
+                    let name = target
+                        .rename
+                        .unwrap_or_else(|| dump::literal(&window.function.call()));
+                    indextargets.push(((name, true), windowindex));
+                    windowindex += 1;
+                }
+                // answered by execute_count_stmt
+                Col::Count => return Err(ExecutionError::DebugError("COUNT(*)".into())),
+            }
+        }
+
+        try!(whereresult.reset_pos());
+        let mut columnvec: Vec<Column> = Vec::new();
+        for index in indextargets.clone() {
+            whereresult.columns[index.1].name = if (index.0).1 {
+                (index.0).0
+            } else {
+                format!("{}{}", (index.0).0, whereresult.columns[index.1].name)
+            };
+
+            columnvec.push(whereresult.columns[index.1].clone());
+        }
+
+        // values of BLOB columns are read through the engines of their
+        // tables and returned as VARBINARY
+        let mut blob_engines = HashMap::new();
+        for (i, index) in indextargets.iter().enumerate() {
+            if columnvec[i].sql_type == SqlType::Blob {
+                let table = &name_tables[&column_tables[index.1]];
+                blob_engines.insert(i, try!(self.get_engine(table)));
+            }
+        }
+        let mut selected = Vec::new();
+
+        // TODO: implement skiprow for Rows!!!
+        // TODO: use less function calls of unwrap!!
+        let mut limitcount = (false, 0);
+        if stmt.limit.is_some() {
+            let limit = stmt.limit.unwrap();
+            limitcount = (true, limit.count.unwrap().clone());
+            if limit.offset.is_some() {
+                for _i in 0..limit.offset.unwrap() {
+                    let mut skiprow = Vec::<u8>::new();
+                    match whereresult.next_row(&mut skiprow) {
+                        Ok(_) => (),
+                        Err(_) => break,
+                    };
+                }
+            }
+        }
+
+        // TODO: Errormanagement!!!
+        loop {
+            try!(self.next_row());
+            if limitcount.0 && limitcount.1 == 0 {
+                break;
+            }
+            let mut originalrow = Vec::<u8>::new();
+            let res = whereresult.next_row(&mut originalrow);
+            match res {
+                Ok(_) => (),
+                Err(_) => break,
+            }
+            let mut toinsert = Vec::new();
+            for (i, index) in indextargets.iter().enumerate() {
+                let mut value = try!(whereresult.get_nullable_value(&originalrow, index.1));
+                if let Some(engine) = blob_engines.get(&i) {
+                    value = match value {
+                        Some(slot) => {
+                            let mut bytes = Vec::new();
+                            let mut reader = try!(engine.read_blob(&slot));
+                            try!(reader.read_to_end(&mut bytes).map_err(storage::Error::Io));
+                            Some(bytes)
+                        }
+                        None => None,
+                    };
+                }
+                toinsert.push(value);
+            }
+            selected.push(toinsert);
+            limitcount.1 -= 1;
+        }
+
+        for &i in blob_engines.keys() {
+            let longest = selected
+                .iter()
+                .filter_map(|row| row[i].as_ref())
+                .map(|bytes| bytes.len())
+                .max()
+                .unwrap_or(0);
+            let sql_type = SqlType::VarBinary(longest as u32);
+            columnvec[i].sql_type = sql_type;
+            for row in &mut selected {
+                if let Some(bytes) = row[i].take() {
+                    let mut data = Vec::new();
+                    try!(sql_type.encode_into(&mut data, &Lit::Bytes(bytes)));
+                    row[i] = Some(data);
+                }
+            }
+        }
+
+        let cursor = Cursor::new(Vec::<u8>::new());
+        let mut resultrows = Rows::<Cursor<Vec<u8>>>::new(cursor, &columnvec);
+        for row in selected {
+            try!(resultrows.add_values(&row));
+        }
+        Ok(resultrows)
+    }
+
+    /// Executes selects combined by set operations, then sorts and limits
+    /// their result.
+    fn execute_compound_stmt(
+        &mut self,
+        stmt: CompoundStmt,
+    ) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
+        let mut rows = try!(self.execute_set_expr(stmt.select));
+        if !stmt.order.is_empty() {
+            let mut keys = Vec::new();
+            for sort in &stmt.order {
+                keys.push(SortKey {
+                    column: try!(result_column(&rows.columns, sort)),
+                    descending: sort.order == Some(Order::Desc),
+                });
+            }
+            rows = try!(sort::sort(rows, &keys, spill::memory_budget(), || {
+                self.check_cancelled()
+            }));
+        }
+        let limit = match stmt.limit {
+            Some(limit) => limit,
+            None => return Ok(rows),
+        };
+        try!(rows.reset_pos());
+        let mut limited = Rows::new(Cursor::new(Vec::new()), &rows.columns);
+        let mut row = Vec::new();
+        for _ in 0..limit.offset.unwrap_or(0) {
+            if rows.next_row(&mut row).is_err() {
+                return Ok(limited);
+            }
+        }
+        let mut count = limit.count;
+        while count != Some(0) {
+            try!(self.check_cancelled());
+            row.clear();
+            if rows.next_row(&mut row).is_err() {
+                break;
+            }
+            try!(limited.add_row(&row));
+            count = count.map(|c| c - 1);
+        }
+        Ok(limited)
+    }
+
+    fn execute_set_expr(&mut self, expr: SetExpr) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
+        match expr {
+            SetExpr::Select(stmt) => self.execute_select_stmt(stmt),
+            SetExpr::Op(op, left, right) => {
+                let left = try!(self.execute_set_expr(*left));
+                let right = try!(self.execute_set_expr(*right));
+                let columns = match setop::result_columns(&left.columns, &right.columns) {
+                    Some(columns) => columns,
+                    None => return Err(ExecutionError::IncompatibleSelects),
+                };
+                setop::combine(op, &columns, left, right, || self.check_cancelled())
+            }
+        }
+    }
+
+    /// Plans how the tables of a select with more than one table are joined,
+    /// see `planner`. The conjuncts of its condition on a single table are
+    /// taken from the statement, they are checked on the rows of the table
+    /// before joining them. If every table has statistics, the tables are
+    /// joined in the cheapest order, each by the conjuncts of the conditions
+    /// of the statement on it and the tables before.
+    fn plan_joins(&mut self, stmt: &mut SelectStmt) -> Result<JoinPlan, ExecutionError> {
+        let mut plan = JoinPlan {
+            tables: stmt.tid.iter().map(|tid| (tid.clone(), None)).collect(),
+            filters: HashMap::new(),
+            estimates: None,
+        };
+        plan.tables
+            .extend(stmt.joins.drain(..).map(|join| (join.tid, Some(join.cond))));
+        let names: Vec<String> = plan.tables.iter().map(|t| t.0.clone()).collect();
+        // the columns of a view are only known from its rows, and tables
+        // joined to themselves are told apart by their aliases only
+        for (i, name) in names.iter().enumerate() {
+            if names[..i].contains(name) || try!(self.view(name)).is_some() {
+                return Ok(plan);
+            }
+        }
+        let mut tables = Vec::new();
+        for name in &names {
+            tables.push((name.clone(), try!(self.source_columns(name))));
+        }
+        let cond = stmt.cond.take();
+        let tables_of = |cond: &Conditions| condition_tables(cond, &tables, &stmt.alias);
+
+        let mut filters = vec![Vec::new(); names.len()];
+        let mut rest = Vec::new();
+        for conjunct in cond.map_or_else(Vec::new, and_parts) {
+            match tables_of(&conjunct) {
+                Some(bits) if bits.count_ones() == 1 => {
+                    filters[bits.trailing_zeros() as usize].push(conjunct)
+                }
+                _ => rest.push(conjunct),
+            }
+        }
+
+        let meta = try!(try!(self.get_own_database()).load_meta_data());
+        let stats: Vec<_> = names.iter().map(|name| meta.stats(name)).collect();
+        let resolved = plan.tables.iter().all(|table| match table.1 {
+            Some(ref cond) => and_parts(cond.clone())
+                .iter()
+                .all(|c| tables_of(c).map_or(false, |bits| bits != 0)),
+            None => true,
+        });
+        if resolved && stats.iter().all(Option::is_some) {
+            // the conjuncts of all conditions on more than one table join
+            // the tables, whatever their order
+            let mut joining = Vec::new();
+            let unjoined = mem::replace(&mut rest, Vec::new());
+            for conjunct in plan
+                .tables
+                .iter_mut()
+                .filter_map(|table| table.1.take())
+                .flat_map(and_parts)
+                .chain(unjoined)
+            {
+                match tables_of(&conjunct) {
+                    Some(bits) if bits.count_ones() == 1 => {
+                        filters[bits.trailing_zeros() as usize].push(conjunct)
+                    }
+                    Some(bits) if bits != 0 => joining.push((bits, conjunct)),
+                    _ => rest.push(conjunct),
+                }
+            }
+
+            let mut inputs = Vec::new();
+            for (i, &(_, ref columns)) in tables.iter().enumerate() {
+                let stats = stats[i].unwrap();
+                let share = Conditions::all(filters[i].iter().cloned()).map(|cond| {
+                    estimate_share(stats, columns, &cond).unwrap_or(planner::DEFAULT_SHARE)
+                });
+                inputs.push(Estimate::scan(stats.rows as f64).filter(share.unwrap_or(1.0)));
+            }
+            let predicates: Vec<_> = joining
+                .iter()
+                .map(|&(bits, ref cond)| {
+                    let distinct = |alias: &Option<String>, name: &str| {
+                        column_table(&tables, &stmt.alias, alias, name).and_then(|i| {
+                            let stats = stats[i].unwrap();
+                            stats
+                                .columns
+                                .iter()
+                                .find(|c| c.name == name)
+                                .map(|c| c.distinct)
+                        })
+                    };
+                    let equi = match cond {
+                        &Conditions::Leaf(ref c) if c.op == CompType::Equ => match c.rhs {
+                            CondType::Word(ref other) => {
+                                Some((distinct(&c.aliascol, &c.col), distinct(&c.aliasrhs, other)))
+                            }
+                            _ => None,
+                        },
+                        _ => None,
+                    };
+                    let share = match equi {
+                        // every value of the side with fewer distinct ones is
+                        // expected to be found on the other side
+                        Some((Some(a), Some(b))) => 1.0 / a.max(b).max(1) as f64,
+                        _ => planner::DEFAULT_SHARE,
+                    };
+                    planner::Predicate {
+                        tables: bits,
+                        share: share,
+                        equi: equi.is_some(),
+                    }
+                })
+                .collect();
+
+            let (order, estimates) = planner::join_order(&inputs, &predicates);
+            let mut joined = 0;
+            plan.tables = order
+                .iter()
+                .map(|&i| {
+                    let all = joined | 1 << i;
+                    let (now, later) = joining
+                        .drain(..)
+                        .partition::<Vec<_>, _>(|&(bits, _)| bits & all == bits);
+                    joining = later;
+                    joined = all;
+                    let cond = Conditions::all(now.into_iter().map(|(_, cond)| cond));
+                    (names[i].clone(), cond)
+                })
+                .collect();
+            let inputs = order.iter().map(|&i| inputs[i]).collect();
+            plan.estimates = Some((inputs, estimates));
+        }
+
+        for (name, filter) in names.into_iter().zip(filters) {
+            if let Some(filter) = Conditions::all(filter) {
+                plan.filters.insert(name, filter);
+            }
+        }
+        stmt.cond = Conditions::all(rest);
+        Ok(plan)
+    }
+
+    /// Returns the rows of a table, view or catalog table fulfilling a
+    /// condition, read through an index if one fits, see `lookup_pushdown`.
+    fn filtered_rows(
+        &mut self,
+        name: &str,
+        cond: Option<Conditions>,
+        alias: &HashMap<String, String>,
+    ) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
+        if cond.is_none() {
+            return self.select_rows(name);
+        }
+        let stmt = table_select(name, cond, alias);
+        let (rows, rest) = match try!(self.lookup_pushdown(&stmt)) {
+            Some(found) => found,
+            None => (try!(self.select_rows(name)), stmt.cond),
+        };
+        let rest = match rest {
+            Some(rest) => rest,
+            None => return Ok(rows),
+        };
+        let mut alias = stmt.alias;
+        alias.insert(name.to_string(), name.to_string());
+        let mut column_index_map = HashMap::<String, usize>::new();
+        let mut column_tablename_map = HashMap::<String, String>::new();
+        for (i, column) in rows.columns.iter().enumerate() {
+            column_tablename_map.insert(column.name.clone(), name.to_string());
+            column_index_map.insert(column.name.clone(), i);
+        }
+        let mut name_column_map = HashMap::<String, HashMap<String, usize>>::new();
+        name_column_map.insert(name.to_string(), column_index_map);
+        let infos = (&alias, &column_tablename_map, &name_column_map);
+        self.execute_where(rows, infos, &rest, false, Where::Select)
+    }
+
+    /// Answers `EXPLAIN` with the plan of a select, see `explain`.
+    fn execute_explain_stmt(
+        &mut self,
+        stmt: CompoundStmt,
+    ) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
+        let plan = try!(self.explain_compound(stmt));
+        Ok(try!(plan.rows()))
+    }
+
+    fn explain_compound(&mut self, stmt: CompoundStmt) -> Result<Plan, ExecutionError> {
+        let mut plan = try!(self.explain_set_expr(stmt.select));
+        if !stmt.order.is_empty() {
+            plan = explain::sort(plan, &stmt.order);
+        }
+        Ok(match stmt.limit {
+            Some(ref limit) => explain::limit(plan, limit),
+            None => plan,
+        })
+    }
+
+    fn explain_set_expr(&mut self, expr: SetExpr) -> Result<Plan, ExecutionError> {
+        let (op, left, right) = match expr {
+            SetExpr::Select(stmt) => return self.explain_select(stmt),
+            SetExpr::Op(op, left, right) => (
+                op,
+                try!(self.explain_set_expr(*left)),
+                try!(self.explain_set_expr(*right)),
+            ),
+        };
+        let both = match (left.rows, right.rows) {
+            (Some(a), Some(b)) => Some((a, b)),
+            _ => None,
+        };
+        let (operator, rows) = match op {
+            SetOp::UnionAll => ("union all", both.map(|(a, b)| a + b)),
+            SetOp::Union => ("union", both.map(|(a, b)| a + b)),
+            SetOp::Intersect => ("intersect", both.map(|(a, b)| a.min(b))),
+            SetOp::Except => ("except", left.rows),
+        };
+        Ok(Plan::new(operator, String::new(), rows)
+            .child(left)
+            .child(right))
+    }
+
+    /// Returns the plan of a select, made by the decisions of
+    /// `execute_select_stmt`. Its subqueries are executed though, as their
+    /// results decide whether an index is used, except correlated ones.
+    fn explain_select(&mut self, mut stmt: SelectStmt) -> Result<Plan, ExecutionError> {
+        try!(self.require_select(&stmt));
+        try!(self.materialize_select(&mut stmt));
+        if stmt.target.iter().any(|t| t.col == Col::Count) {
+            if stmt.target.len() != 1 {
+                return Err(ExecutionError::DebugError(
+                    "COUNT(*) must be the only target".into(),
+                ));
+            }
+            if stmt.cond.is_none()
+                && stmt.tid.len() == 1
+                && stmt.joins.is_empty()
+                && try!(self.stored_count(&stmt.tid[0])).is_some()
+            {
+                return Ok(Plan::new("stored count", String::new(), Some(1)).table(&stmt.tid[0]));
+            }
+            stmt.target[0].col = Col::Every;
+            stmt.order.clear();
+            stmt.limit = None;
+            let plan = try!(self.explain_select(stmt));
+            return Ok(Plan::new("count", String::new(), Some(1)).child(plan));
+        }
+
+        let correlated = split_correlated(&mut stmt);
+        let mut ordered = false;
+        let joined = stmt.tid.len() > 1 || !stmt.joins.is_empty();
+        let mut plan = if joined {
+            try!(self.explain_joins(&mut stmt, &mut ordered))
+        } else if let Some(scan) = try!(self.range_scan(&stmt)) {
+            let table = &stmt.tid[0];
+            let all = try!(self.estimated_rows(table));
+            let share = match (scan.share, &stmt.cond) {
+                (Some(share), _) => Some(share),
+                (None, &None) => Some(1.0),
+                (None, &Some(_)) => None,
+            };
+            let mut detail = try!(self.index_name(table, scan.column));
+            if let Some(cond) = stmt.cond.take() {
+                detail = format!("{}: {}", detail, dump::conditions(&cond));
+            }
+            if scan.descending.is_some() {
+                ordered = true;
+                detail.push_str(", in index order");
+            }
+            Plan::new("range scan", detail, explain::share(all, share))
+                .table(table)
+                .cost(lookup_cost(all, share))
+        } else if let Some(lookup) = try!(self.index_lookup(&stmt)) {
+            let plan = try!(self.explain_lookup(&stmt.tid[0], &lookup));
+            stmt.cond = lookup.rest;
+            plan
+        } else {
+            try!(self.explain_source(&stmt.tid[0]))
+        };
+
+        if let Some(cond) = stmt.cond {
+            let rows = if joined {
+                None
+            } else {
+                explain::share(plan.rows, try!(self.condition_share(&stmt.tid[0], &cond)))
+            };
+            plan = Plan::new("filter", dump::conditions(&cond), rows).child(plan);
+        }
+        if let Some(cond) = Conditions::all(correlated) {
+            let detail = format!("{}, for every row", dump::conditions(&cond));
+            plan = Plan::new("correlated filter", detail, None).child(plan);
+        }
+        for target in &stmt.target {
+            if let Col::Window(ref window) = target.col {
+                plan = explain::window(plan, window);
+                ordered = false;
+            }
+        }
+        if !stmt.order.is_empty() && !ordered {
+            plan = explain::sort(plan, &stmt.order);
+        }
+        if let Some(ref limit) = stmt.limit {
+            plan = explain::limit(plan, limit);
+        }
+        Ok(plan)
+    }
+
+    /// Returns the plan joining the tables of a select, see `plan_joins`,
+    /// and sets whether its rows are ordered by `ORDER BY` already.
+    fn explain_joins(
+        &mut self,
+        stmt: &mut SelectStmt,
+        ordered: &mut bool,
+    ) -> Result<Plan, ExecutionError> {
+        let mut joins = try!(self.plan_joins(stmt));
+        let estimates = joins.estimates.take();
+        let mut name_column_map = HashMap::<String, HashMap<String, usize>>::new();
+        let mut column_tablename_map = HashMap::<String, String>::new();
+        let mut columns = Vec::new();
+        let count = joins.tables.len() - 1;
+        let mut joined: Option<Plan> = None;
+        for (i, (tid, cond)) in joins.tables.into_iter().enumerate() {
+            let split = columns.len();
+            let mut column_index_map = HashMap::<String, usize>::new();
+            for column in try!(self.source_columns(&tid)) {
+                column_tablename_map.insert(column.name.clone(), tid.clone());
+                column_index_map.insert(column.name.clone(), columns.len());
+                columns.push(column);
+            }
+            name_column_map.insert(tid.clone(), column_index_map);
+            stmt.alias.insert(tid.clone(), tid.clone());
+
+            let filter = joins.filters.remove(&tid);
+            let mut right = try!(self.explain_input(&tid, filter, &stmt.alias));
+            if let Some((ref inputs, _)) = estimates {
+                right.rows = Some(inputs[i].rows.ceil() as u64);
+            }
+            let outer = match joined.take() {
+                Some(outer) => outer,
+                None => {
+                    joined = Some(right);
+                    continue;
+                }
+            };
+            let (operator, equi, detail) = match cond {
+                Some(cond) => {
+                    let infos = (&stmt.alias, &column_tablename_map, &name_column_map);
+                    let position =
+                        |alias: &Option<String>, name: &String| resolve_column(infos, alias, name);
+                    let keys = equi_keys(&columns, split, &cond, &position);
+                    // like in execute_select_stmt
+                    let order = match stmt.order.first() {
+                        Some(sort)
+                            if stmt.order.len() == 1
+                                && sort.order != Some(Order::Desc)
+                                && i == count
+                                && !stmt.cond.as_ref().map_or(false, has_or) =>
+                        {
+                            resolve_column(infos, &sort.alias, &sort.col).ok()
+                        }
+                        _ => None,
+                    };
+                    let ordered_by_key = match (order, keys.first()) {
+                        (Some(i), Some(&(l, r))) => i == l || i == split + r,
+                        _ => false,
+                    };
+                    let method = join::choose(
+                        explain::count(outer.rows),
+                        explain::count(right.rows),
+                        !keys.is_empty(),
+                        ordered_by_key,
+                    );
+                    *ordered = method == join::Method::SortMerge && ordered_by_key;
+                    let operator = match method {
+                        join::Method::NestedLoop => "nested loop join",
+                        join::Method::Hash => "hash join",
+                        join::Method::SortMerge => "sort-merge join",
+                    };
+                    (operator, !keys.is_empty(), dump::conditions(&cond))
+                }
+                None => {
+                    *ordered = false;
+                    ("cross join", false, String::new())
+                }
+            };
+            let plan = match estimates {
+                Some((_, ref steps)) => {
+                    Plan::new(operator, detail, Some(steps[i].rows.ceil() as u64))
+                        .cost(Some(steps[i].cost))
+                }
+                None => {
+                    let rows = explain::join_rows(outer.rows, right.rows, equi);
+                    Plan::new(operator, detail, rows)
+                }
+            };
+            joined = Some(plan.child(outer).child(right));
+        }
+        Ok(joined.unwrap())
+    }
+
+    /// Returns the plan reading the rows of a table fulfilling a condition
+    /// before they are joined, see `filtered_rows`.
+    fn explain_input(
+        &mut self,
+        name: &str,
+        cond: Option<Conditions>,
+        alias: &HashMap<String, String>,
+    ) -> Result<Plan, ExecutionError> {
+        if cond.is_none() {
+            return self.explain_source(name);
+        }
+        let stmt = table_select(name, cond, alias);
+        let (plan, rest) = match try!(self.index_lookup(&stmt)) {
+            Some(lookup) => (try!(self.explain_lookup(name, &lookup)), lookup.rest),
+            None => (try!(self.explain_source(name)), stmt.cond),
+        };
+        Ok(match rest {
+            Some(rest) => {
+                let rows = explain::share(plan.rows, try!(self.condition_share(name, &rest)));
+                Plan::new("filter", dump::conditions(&rest), rows).child(plan)
+            }
+            None => plan,
+        })
+    }
+
+    /// Returns the plan reading the rows of a table found by the storage
+    /// engine, see `lookup_pushdown`.
+    fn explain_lookup(&self, table: &str, lookup: &IndexLookup) -> Result<Plan, ExecutionError> {
+        let all = try!(self.estimated_rows(table));
+        let rows = explain::share(all, lookup.share);
+        let cond = dump::conditions(&Conditions::Leaf(lookup.cond.clone()));
+        let plan = if lookup.op == CompType::Match || lookup.op == CompType::Like {
+            // without an inverted index the engine reads all rows
+            let detail = if try!(self.get_table(table)).engine_id() == EngineID::InvertedIndex {
+                format!("inverted index: {}", cond)
+            } else {
+                cond
+            };
+            Plan::new("text search", detail, rows)
+        } else {
+            let index = try!(self.index_name(table, lookup.column));
+            Plan::new("index lookup", format!("{}: {}", index, cond), rows)
+                .cost(lookup_cost(all, lookup.share))
+        };
+        Ok(plan.table(table))
+    }
+
+    /// Returns the plan reading all rows of a table, view or catalog table.
+    fn explain_source(&mut self, name: &str) -> Result<Plan, ExecutionError> {
+        if Catalog::from_name(name).is_some() {
+            return Ok(Plan::new("catalog", String::new(), None).table(name));
+        }
+        if let Some(view) = try!(self.view(name)) {
+            let stmt = try!(self.view_select(&view));
+            self.views.push(view.name);
+            let res = self.explain_select(stmt);
+            self.views.pop();
+            let plan = try!(res);
+            return Ok(Plan::new("view", String::new(), plan.rows)
+                .table(name)
+                .child(plan));
+        }
+        let engine = try!(self.get_table(name)).engine_id();
+        let rows = try!(self.estimated_rows(name));
+        Ok(
+            Plan::new("full scan", format!("{} engine", engine.name()), rows)
+                .table(name)
+                .cost(rows.map(|rows| Estimate::scan(rows as f64).cost)),
+        )
+    }
+
+    /// Returns the columns of the rows of a table, view or catalog table.
+    /// Those of a view are only known from its rows, so it is executed.
+    fn source_columns(&mut self, name: &str) -> Result<Vec<Column>, ExecutionError> {
+        if let Some(catalog) = Catalog::from_name(name) {
+            return Ok(catalog.columns());
+        }
+        if try!(self.view(name)).is_some() {
+            return Ok(try!(self.select_rows(name)).columns);
+        }
+        Ok(try!(self.get_table(name)).columns().to_vec())
+    }
+
+    /// Returns the number of rows of a table in its statistics or kept by
+    /// the flat file engine, None if there is neither.
+    fn estimated_rows(&self, name: &str) -> Result<Option<u64>, ExecutionError> {
+        if Catalog::from_name(name).is_some() || try!(self.view(name)).is_some() {
+            return Ok(None);
+        }
+        let meta = try!(try!(self.get_own_database()).load_meta_data());
+        if let Some(stats) = meta.stats(name) {
+            return Ok(Some(stats.rows));
+        }
+        let table = try!(self.get_table(name));
+        if table.connection().is_some() || table.engine_id() != EngineID::FlatFile {
+            return Ok(None);
+        }
+        Ok(try!(count::read(&table)))
+    }
+
+    /// Estimates the share of the rows of a table fulfilling a condition by
+    /// the statistics of the table, None if it cannot be estimated.
+    fn condition_share(
+        &self,
+        name: &str,
+        cond: &Conditions,
+    ) -> Result<Option<f64>, ExecutionError> {
+        if Catalog::from_name(name).is_some() || try!(self.view(name)).is_some() {
+            return Ok(None);
+        }
+        let meta = try!(try!(self.get_own_database()).load_meta_data());
+        let table = try!(self.get_table(name));
+        Ok(meta
+            .stats(name)
+            .and_then(|stats| estimate_share(stats, table.columns(), cond)))
+    }
+
+    /// Describes the secondary index on a single column of a table.
+    fn index_name(&self, name: &str, column: usize) -> Result<String, ExecutionError> {
+        let table = try!(self.get_table(name));
+        let column = &table.columns()[column].name;
+        Ok(table
+            .indexes()
+            .iter()
+            .find(|i| i.columns.len() == 1 && &i.columns[0] == column)
+            .map_or_else(
+                || format!("index on {}", column),
+                |i| format!("index {} on {}", i.name, column),
+            ))
+    }
+
+    fn execute_where<'b>(
+        &self,
+        mut tableset: Rows<Cursor<Vec<u8>>>,
+        infos: (
+            &HashMap<String, String>,
+            &HashMap<String, String>,
+            &HashMap<String, HashMap<String, usize>>,
+        ),
+        conditions: &Conditions,
+        negate: bool,
+        wheretype: Where,
+    ) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
+        match conditions {
+            &Conditions::And(ref c1, ref c2) => {
+                if wheretype == Where::Select {
+                    let leftside =
+                        try!(self.execute_where(tableset, infos, c1, false, wheretype.clone()));
+                    self.execute_where(leftside, infos, c2, false, wheretype)
+                } else {
+                    // IMPLEMENT!!! Needs a custom merge function
+                    let mut rightresult = try!(self.execute_where(
+                        try!(tableset.full_scan()),
+                        infos,
+                        c1,
+                        true,
+                        Where::Select
+                    ));
+                    try!(self.execute_where(tableset, infos, c2, false, wheretype.clone()));
+                    let mut engine = try!(self.get_engine(&wheretype.unwrap()));
+                    try!(rightresult.reset_pos());
+                    loop {
+                        try!(self.check_cancelled());
+                        let mut rightrow = Vec::<u8>::new();
+                        let outerres = rightresult.next_row(&mut rightrow);
+                        match outerres {
+                            Ok(_) => (),
+                            Err(_) => break,
+                        }
+                        engine.insert_row(&rightrow);
+                    }
+
+                    Ok(rightresult)
+                }
+            }
+
+            &Conditions::Or(ref c1, ref c2) => {
+                // When changing to the EFFECTIVE PLAN:
+                // REMEMBER CHANGING HERE TOO! (TODO)
+                if wheretype == Where::Select {
+                    let tableset2 = try!(tableset.full_scan());
+                    let leftside =
+                        try!(self.execute_where(tableset, infos, c1, false, wheretype.clone()));
+                    let rightside =
+                        try!(self.execute_where(tableset2, infos, c2, false, wheretype));
+                    self.merge_rows(leftside, rightside)
+                } else {
+                    try!(self.execute_where(
+                        try!(tableset.full_scan()),
+                        infos,
+                        c1,
+                        false,
+                        wheretype.clone()
+                    ));
+                    self.execute_where(tableset, infos, c2, false, wheretype)
+                }
+            }
+
+            &Conditions::Expr(..) | &Conditions::In(..) | &Conditions::Exists(..) => {
+                let condition = if negate {
+                    conditions.clone().negate()
+                } else {
+                    conditions.clone()
+                };
+                let position =
+                    |alias: &Option<String>, name: &String| resolve_column(infos, alias, name);
+                let cursor = Cursor::new(Vec::<u8>::new());
+                let mut selected = Rows::new(cursor, &tableset.columns);
+                try!(tableset.reset_pos());
+                loop {
+                    try!(self.next_row());
+                    let mut row = Vec::new();
+                    match tableset.next_row(&mut row) {
+                        Ok(_) => (),
+                        Err(storage::Error::EndOfFile) => break,
+                        Err(e) => return Err(e.into()),
+                    }
+                    let columns = &tableset.columns;
+                    if try!(evaluate_condition(columns, &row, &condition, &position)) == Some(true)
+                    {
+                        try!(selected.add_row(&row));
+                    }
+                }
+                match wheretype {
+                    Where::Select => Ok(selected),
+                    // the engine only deletes by comparing a column
+                    Where::Delete(ref table) => {
+                        try!(self.delete_rows(table, try!(read_rows(selected)), &mut Vec::new()));
+                        Ok(generate_rows_dummy())
+                    }
+                }
+            }
+
+            // TODO: SO MUCH REDUNDANT CODE!!!!!!!11111
+            //       remove whenever there is time.
+            &Conditions::Leaf(ref c) => {
+                let tablename = if c.aliascol.is_some() {
+                    match infos.0.get(&c.clone().aliascol.unwrap()) {
+                        Some(x) => x,
+                        None => return Err(ExecutionError::UnknownAlias),
+                    }
+                } else {
+                    match infos.1.get(&c.col) {
+                        Some(x) => x,
+                        None => return Err(ExecutionError::UnknownColumn),
+                    }
+                };
+                let columntoindex = infos.2.get(tablename).unwrap();
+                let column = columntoindex.get(&c.col);
+                if column.is_none() {
+                    return Err(ExecutionError::UnknownColumn);
+                }
+                let index = column.unwrap().clone();
+
+                match c.rhs {
+                    CondType::Word(ref column) => {
+                        let tablename2 = if c.aliasrhs.is_some() {
+                            match infos.0.get(&c.clone().aliasrhs.unwrap()) {
+                                Some(x) => x,
+                                None => return Err(ExecutionError::UnknownAlias),
+                            }
+                        } else {
+                            match infos.1.get(column) {
+                                Some(x) => x,
+                                None => return Err(ExecutionError::UnknownColumn),
+                            }
+                        };
+                        let columntoindex2 = infos.2.get(tablename2).unwrap();
+                        let column2 = columntoindex2.get(column);
+                        if column2.is_none() {
+                            return Err(ExecutionError::UnknownColumn);
+                        }
+                        let index2 = column2.unwrap().clone();
+                        let operator = if negate { c.op.negate() } else { c.op };
+                        if tableset.columns[index].sql_type != tableset.columns[index2].sql_type {
+                            return Err(ExecutionError::CompareDatatypeMissmatch);
+                        }
+                        if wheretype == Where::Select {
+                            Ok(try!(tableset.lookup(
+                                index,
+                                (&Vec::<u8>::new(), Some(index2)),
+                                operator
+                            )))
+                        } else {
+                            let engine = try!(self.get_engine(&wheretype.unwrap()));
+                            try!(engine.delete(index, (&Vec::<u8>::new(), Some(index2)), operator));
+                            Ok(generate_rows_dummy())
+                        }
+                    }
+
+                    CondType::Literal(Lit::Null) => {
+                        let operator = if negate { c.op.negate() } else { c.op };
+                        match operator {
+                            CompType::IsNull | CompType::IsNotNull => {}
+                            // a comparison with null is never true
+                            _ if wheretype == Where::Select => {
+                                let cursor = Cursor::new(Vec::<u8>::new());
+                                return Ok(Rows::new(cursor, &tableset.columns));
+                            }
+                            _ => return Ok(generate_rows_dummy()),
+                        }
+                        if wheretype == Where::Select {
+                            Ok(try!(tableset.lookup(index, (&[], None), operator)))
+                        } else {
+                            let engine = try!(self.get_engine(&wheretype.unwrap()));
+                            try!(engine.delete(index, (&[], None), operator));
+                            Ok(generate_rows_dummy())
+                        }
+                    }
+
+                    CondType::Literal(ref lit) => {
+                        let lit = &try!(evaluate(lit));
+                        // Error handling: if wrong compare type is giving => Missmatch error
+                        let sql_type = tableset.columns[index].sql_type;
+                        if !sql_type.accepts(lit) || (c.op.takes_text() && !sql_type.is_text()) {
+                            return Err(ExecutionError::CompareDatatypeMissmatch);
+                        }
+                        // TODO: use get_column methods!!
+                        let mut comparedata = Vec::<u8>::new();
+                        match (c.op, lit) {
+                            // the search term must not be cut to the column size
+                            (op, &Lit::String(ref term)) if op.takes_text() => {
+                                comparedata.extend_from_slice(term.as_bytes())
+                            }
+                            _ => {
+                                try!(tableset.columns[index]
+                                    .sql_type
+                                    .encode_into(&mut comparedata, lit));
+                            }
+                        }
+                        let operator = if negate { c.op.negate() } else { c.op };
+                        if wheretype == Where::Select {
+                            Ok(try!(tableset.lookup(index, (&comparedata, None), operator)))
+                        } else {
+                            let engine = try!(self.get_engine(&wheretype.unwrap()));
+                            engine.delete(index, (&comparedata, None), operator);
+                            Ok(generate_rows_dummy())
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn execute_delete_stmt(
+        &mut self,
+        mut query: DeleteStmt,
+    ) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
+        try!(self.require_privilege(Some(&query.tid), Privilege::Delete));
+        if let Some(ref mut cond) = query.cond {
+            try!(self.materialize_condition(cond));
+        }
+        let table = try!(self.get_rows(&query.tid));
+        let mut name_column_map = HashMap::<String, HashMap<String, usize>>::new();
+        let mut column_index_map = HashMap::<String, usize>::new();
+        let mut column_tablename_map = HashMap::<String, String>::new();
+        let mut columnindex: usize = 0;
+        for column in table.columns.clone() {
+            column_tablename_map.insert(column.name.clone(), query.tid.clone());
+            column_index_map.insert(column.name.into(), columnindex);
+            columnindex += 1;
+        }
+
+        name_column_map.insert(query.tid.clone(), column_index_map);
+        query.alias.insert(query.tid.clone(), query.tid.clone());
+
+        // the rows to return are computed before they are gone
+        let returning = if query.returning.is_empty() {
+            None
+        } else {
+            let infos = (&query.alias, &column_tablename_map, &name_column_map);
+            let rows = try!(self.get_rows(&query.tid));
+            let matched = match query.cond {
+                Some(ref cond) => try!(self.execute_where(rows, infos, cond, false, Where::Select)),
+                None => rows,
+            };
+            Some(try!(self.returning_rows(matched, infos, &query.returning)))
+        };
+
+        // referencing rows have to be found, so the rows are deleted one
+        // by one
+        if !try!(self.referencing(&query.tid)).is_empty() {
+            let rows = match query.cond {
+                Some(ref cond) => try!(self.execute_where(
+                    table,
+                    (&query.alias, &column_tablename_map, &name_column_map),
+                    cond,
+                    false,
+                    Where::Select
+                )),
+                None => table,
+            };
+            try!(self.delete_rows(&query.tid, try!(read_rows(rows)), &mut Vec::new()));
+            return Ok(returning.unwrap_or_else(generate_rows_dummy));
+        }
+
+        if query.cond.is_some() {
+            try!(self.execute_where(
+                table,
+                (&query.alias, &column_tablename_map, &name_column_map),
+                &query.cond.unwrap(),
+                false,
+                Where::Delete(query.tid)
+            ));
+        } else {
+            let mut engine = try!(self.get_engine(&query.tid));
+            // Todo: Storage: enable full table reset!!
+            try!(engine.reset());
+        }
+
+        Ok(returning.unwrap_or_else(generate_rows_dummy))
+    }
+
+    /// Returns the targets of `RETURNING` for the rows of a single table.
+    /// Columns keep their names unless renamed.
+    fn returning_rows(
+        &mut self,
+        rows: Rows<Cursor<Vec<u8>>>,
+        infos: (
+            &HashMap<String, String>,
+            &HashMap<String, String>,
+            &HashMap<String, HashMap<String, usize>>,
+        ),
+        targets: &[Target],
+    ) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
+        let table_columns = rows.columns.len();
+        let expressions: Vec<Lit> = targets
+            .iter()
+            .filter_map(|target| match target.col {
+                Col::Expr(ref lit) => Some(lit.clone()),
+                _ => None,
+            })
+            .collect();
+        let mut rows = try!(self.compute_expressions(rows, infos, &expressions));
+
+        let mut indices = Vec::new();
+        let mut columns = Vec::new();
+        let mut expressionindex = table_columns;
+        for target in targets {
+            let index = match target.col {
+                Col::Every => {
+                    if let Some(ref alias) = target.alias {
+                        if !infos.0.contains_key(alias) {
+                            return Err(ExecutionError::UnknownAlias);
+                        }
+                    }
+                    for i in 0..table_columns {
+                        indices.push(i);
+                        columns.push(rows.columns[i].clone());
+                    }
+                    continue;
+                }
+                Col::Specified(ref name) => try!(resolve_column(infos, &target.alias, name)),
+                Col::Expr(_) => {
+                    expressionindex += 1;
+                    expressionindex - 1
+                }
+                Col::Count => return Err(ExecutionError::DebugError("COUNT(*)".into())),
+                Col::Window(_) => {
+                    return Err(ExecutionError::DebugError(
+                        "window functions in RETURNING".into(),
+                    ))
+                }
+            };
+            let mut column = rows.columns[index].clone();
+            if let Some(ref rename) = target.rename {
+                column.name = rename.clone();
+            }
+            indices.push(index);
+            columns.push(column);
+        }
+        if columns.iter().any(|c| c.sql_type == SqlType::Blob) {
+            return Err(ExecutionError::DebugError(
+                "RETURNING of BLOB columns".into(),
+            ));
+        }
+
+        let mut result = Rows::new(Cursor::new(Vec::new()), &columns);
+        try!(rows.reset_pos());
+        loop {
+            let mut row = Vec::new();
+            match rows.next_row(&mut row) {
+                Ok(_) => (),
+                Err(storage::Error::EndOfFile) => break,
+                Err(e) => return Err(e.into()),
+            }
+            let values = row_values(&rows.columns, &row);
+            let selected: Vec<_> = indices.iter().map(|&i| values[i].clone()).collect();
+            try!(result.add_values(&selected));
+        }
+        Ok(result)
+    }
+
+    /// Executes an `UPDATE`: the values of the `SET` expressions are
+    /// computed from the old values of every row fulfilling the condition,
+    /// then the old rows are replaced by the new ones. A value other rows
+    /// reference must not change.
+    fn execute_update_stmt(
+        &mut self,
+        mut stmt: UpdateStmt,
+    ) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
+        try!(self.require_privilege(Some(&stmt.tid), Privilege::Update));
+        if let Some(ref mut cond) = stmt.conds {
+            try!(self.materialize_condition(cond));
+        }
+        for set in &mut stmt.set {
+            if let CondType::Literal(ref mut lit) = set.rhs {
+                try!(self.materialize(lit));
+            }
+        }
+        let rows = try!(self.get_rows(&stmt.tid));
+        let columns = rows.columns.clone();
+        let mut column_index_map = HashMap::<String, usize>::new();
+        let mut column_tablename_map = HashMap::<String, String>::new();
+        for (i, column) in columns.iter().enumerate() {
+            column_tablename_map.insert(column.name.clone(), stmt.tid.clone());
+            column_index_map.insert(column.name.clone(), i);
+        }
+        let mut name_column_map = HashMap::<String, HashMap<String, usize>>::new();
+        name_column_map.insert(stmt.tid.clone(), column_index_map);
+        stmt.alias.insert(stmt.tid.clone(), stmt.tid.clone());
+        let infos = (&stmt.alias, &column_tablename_map, &name_column_map);
+
+        // the columns to set and their expressions
+        let mut assignments = Vec::new();
+        for set in &stmt.set {
+            let index = try!(resolve_column(infos, &set.aliascol, &set.col));
+            let lit = match set.rhs {
+                CondType::Literal(ref lit) => lit.clone(),
+                CondType::Word(ref word) => Lit::Column(None, word.clone()),
+            };
+            assignments.push((index, lit));
+        }
+
+        let matched = match stmt.conds {
+            Some(ref cond) => try!(self.execute_where(rows, infos, cond, false, Where::Select)),
+            None => rows,
+        };
+        let old_rows = try!(read_rows(matched));
+        let mut new_rows = Vec::with_capacity(old_rows.len());
+        {
+            let engine = try!(self.get_engine(&stmt.tid));
+            for row in &old_rows {
+                try!(self.next_row());
+                let value = |alias: &Option<String>, name: &String| {
+                    let index = try!(resolve_column(infos, alias, name));
+                    column_value(&columns, row, index)
+                };
+                let mut values = row_values(&columns, row);
+                for &(index, ref lit) in &assignments {
+                    let new_value = try!(evaluate_with(lit, &value));
+                    values[index] = try!(self.encode_value(&*engine, &columns[index], &new_value));
+                }
+                let new_row = try!(types::encode_row(&columns, &values));
+                try!(check_constraints(engine.table(), &new_row));
+                try!(self.check_references(engine.table(), &new_row));
+                new_rows.push(new_row);
+            }
+        }
+
+        try!(self.check_key_changes(&stmt.tid, &columns, &old_rows, &new_rows));
+        try!(self.remove_rows(&stmt.tid, &old_rows));
+        let mut engine = try!(self.get_engine(&stmt.tid));
+        try!(engine.insert_rows(&slices(&new_rows)));
+        Ok(generate_rows_dummy())
+    }
+
+    fn execute_describe_stmt(
+        &mut self,
+        query: String,
+    ) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
+        try!(self.require_privilege(Some(&query), Privilege::Select));
+        let table = try!(self.get_table(&query));
+        let columns = table.columns();
+        let mut columnvec = Vec::new();
+
+        columnvec.extend(columns.iter().cloned());
+        Ok(Rows::new(Cursor::new(Vec::<u8>::new()), &columnvec))
+    }
+
+    fn execute_create_stmt(
+        &mut self,
+        query: CreateStmt,
+    ) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
+        match query {
+            CreateStmt::Database(s) => {
+                let base = try!(Database::create(&s));
+                // The creator owns the new database
+                if !self.user.is_admin() {
+                    let mut meta = try!(base.load_meta_data());
+                    meta.grant(&self.user._name, None, &Privilege::all());
+                    try!(base.save_meta_data(&meta));
+                }
+                self.user._currentDatabase = Some(base);
+                Ok(generate_rows_dummy())
+            }
+            CreateStmt::Table(stmt) => self.execute_create_table_stmt(stmt),
+            CreateStmt::Index(stmt) => self.execute_create_index_stmt(stmt),
+            CreateStmt::View(stmt) => self.execute_create_view_stmt(stmt),
+            CreateStmt::Function(stmt) => self.execute_create_function_stmt(stmt),
+            CreateStmt::Event(stmt) => {
+                try!(self.require_privilege(None, Privilege::Create));
+                let base = try!(self.get_own_database());
+                let mut meta = try!(base.load_meta_data());
+                if !stmt.opt && meta.event(&stmt.name).is_some() {
+                    return Err(ExecutionError::EventExists(stmt.name));
+                }
+                // the statement must at least be one of the database
+                try!(Parser::create(&stmt.body).parse());
+                meta.set_event(Event {
+                    name: stmt.name,
+                    every: stmt.every,
+                    starts: scheduler::now(),
+                    definer: self.user._name.clone(),
+                    body: stmt.body,
+                });
+                try!(base.save_meta_data(&meta));
+                Ok(generate_rows_dummy())
+            }
+            CreateStmt::User(stmt) => {
+                try!(self.require_admin());
+                try!(auth::create_user(&stmt.name, &stmt.password));
+                Ok(generate_rows_dummy())
+            }
+        }
+    }
+
+    /// Creates a view. Its select is executed once, so that a view refers
+    /// only to tables and views that exist.
+    fn execute_create_view_stmt(
+        &mut self,
+        stmt: CreateViewStmt,
+    ) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
+        try!(self.require_privilege(Some(&stmt.name), Privilege::Create));
+        {
+            let base = try!(self.get_own_database());
+            let exists = base.load_table(&stmt.name).is_ok()
+                || base
+                    .load_temporary_table(self.connection, &stmt.name)
+                    .is_some();
+            if exists || (!stmt.opt && try!(self.view(&stmt.name)).is_some()) {
+                return Err(storage::Error::TableExists(stmt.name).into());
+            }
+        }
+        self.views.push(stmt.name.clone());
+        let res = self.execute_select_stmt(stmt.sel);
+        self.views.pop();
+        try!(res);
+
+        let base = try!(self.get_own_database());
+        let mut meta = try!(base.load_meta_data());
+        meta.set_view(View {
+            name: stmt.name,
+            definition: stmt.text,
+        });
+        try!(base.save_meta_data(&meta));
+        Ok(generate_rows_dummy())
+    }
+
+    /// Creates a function. Its body is evaluated once with a value of every
+    /// parameter, so that it refers only to its parameters and to functions
+    /// that exist, and returns values of its result type.
+    fn execute_create_function_stmt(
+        &mut self,
+        stmt: CreateFunctionStmt,
+    ) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
+        try!(self.require_privilege(None, Privilege::Create));
+        let base = try!(self.get_own_database());
+        let mut meta = try!(base.load_meta_data());
+        if function::BUILT_IN.contains(&&stmt.name[..])
+            || (!stmt.opt && meta.function(&stmt.name).is_some())
+        {
+            return Err(ExecutionError::FunctionExists(stmt.name));
+        }
+        let function = Function {
+            name: stmt.name,
+            params: stmt.params,
+            returns: stmt.returns,
+            body: stmt.body,
+        };
+        // a replaced function must not end up calling itself
+        let mut functions = meta.functions.clone();
+        functions.retain(|f| f.name != function.name);
+        functions.push(function.clone());
+        let mut body = try!(function::body(&function));
+        try!(function::expand(&mut body, &functions));
+
+        let mut values = HashMap::new();
+        for &(ref param, sql_type) in &function.params {
+            let zeros = vec![0; sql_type.size() as usize];
+            values.insert(param.clone(), try!(sql_type.decode_from(&mut &zeros[..])));
+        }
+        let value = evaluate_with(&body, &|alias: &Option<String>, name: &String| match (
+            alias,
+            values.get(name),
+        ) {
+            (&None, Some(value)) => Ok(value.clone()),
+            _ => Err(ExecutionError::UnknownColumn),
+        });
+        match value {
+            // the type of the result is unknown
+            Ok(Lit::Null) | Err(ExecutionError::DivisionByZero) => (),
+            Err(ExecutionError::NumericOverflow) => (),
+            Ok(value) => {
+                try!(function
+                    .returns
+                    .encode_into(&mut Vec::new(), &value)
+                    .map_err(|_| ExecutionError::CompareDatatypeMissmatch));
+            }
+            Err(e) => return Err(e),
+        }
+        meta.set_function(function);
+        try!(base.save_meta_data(&meta));
+        Ok(generate_rows_dummy())
+    }
+
+    fn execute_create_table_stmt(
+        &mut self,
+        query: CreateTableStmt,
+    ) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
+        try!(self.require_privilege(Some(&query.tid), Privilege::Create));
+        if try!(self.view(&query.tid)).is_some() {
+            return Err(storage::Error::TableExists(query.tid).into());
+        }
+        if !query.temporary {
+            try!(self.before_write(&query.tid));
+        }
+        let base = try!(self.get_own_database());
+        let mut checks = Vec::new();
+        for c in &query.cols {
+            if let Some(ref condition) = c.check {
+                let name = format!("{}_{}_check", query.tid, c.cid);
+                checks.push((name, condition.clone()));
+            }
+        }
+        for condition in query.checks {
+            checks.push((format!("{}_check", query.tid), condition));
+        }
+        let tmp_vec: Vec<_> = query
+            .cols
+            .into_iter()
+            .map(|c| Column {
+                name: c.cid,
+                sql_type: c.datatype,
+                // primary keys identify rows, they are never null
+                allow_null: !c.not_null && !c.primary,
+                description: "this is a column".to_string(),
+                is_primary_key: c.primary,
+                default: c.default,
+            })
+            .collect();
+        for column in &tmp_vec {
+            try!(check_default(column));
+        }
+        let mut table = if query.temporary {
+            // a temporary table must not hide a table of the database, nor
+            // replace a temporary table
+            if base.load_table(&query.tid).is_ok()
+                || base
+                    .load_temporary_table(self.connection, &query.tid)
+                    .is_some()
+            {
+                return Err(storage::Error::TableExists(query.tid).into());
+            }
+            if query.engine != EngineID::Memory {
+                return Err(storage::Error::NoImplementation.into());
+            }
+            Table::temporary(&base, &query.tid, tmp_vec, self.connection)
+        } else {
+            Table::new(&base, &query.tid, tmp_vec, query.engine)
+        };
+        table.meta_data.compression = query.compression;
+        table.meta_data.encrypted = query.encrypted;
+        // every UNIQUE constraint is kept by a unique index, named like
+        // PostgreSQL does
+        for columns in &query.uniques {
+            let name = format!("{}_{}_key", query.tid, columns.join("_"));
+            try!(table.add_index(&name, columns, true));
+        }
+        for info in query.foreign_keys {
+            let foreign_key = try!(self.foreign_key(&table, info));
+            try!(table.add_foreign_key(foreign_key));
+        }
+        for (name, condition) in checks {
+            try!(add_check(&mut table, &name, condition));
+        }
+        try!(table.save());
+        let mut engine = table.create_engine();
+        if let Err(e) = engine.create_table() {
+            // the engine rejected the table, e.g. BSTAR without a primary key
+            let _ = engine.table().delete();
+            return Err(e.into());
+        }
+        if !query.uniques.is_empty() {
+            try!(engine.rebuild_indexes());
+        }
+        Ok(generate_rows_dummy())
+    }
+
+    fn execute_create_index_stmt(
+        &mut self,
+        query: CreateIndexStmt,
+    ) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
+        try!(self.require_privilege(Some(&query.tid), Privilege::Create));
+        try!(self.before_write(&query.tid));
+        let mut table = try!(self.get_table(&query.tid));
+        try!(table.add_index(&query.name, &[query.col], false));
+        try!(table.save());
+        let mut engine = table.create_engine();
+        try!(engine.rebuild_indexes());
+        Ok(generate_rows_dummy())
+    }
+
+    fn execute_drop_stmt(
+        &mut self,
+        query: DropStmt,
+    ) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
+        match query {
+            DropStmt::Table(s) => {
+                try!(self.require_privilege(Some(&s), Privilege::Drop));
+                // a temporary table is dropped before the table it hides
+                let base = try!(self.get_own_database());
+                if let Some(table) = base.load_temporary_table(self.connection, &s) {
+                    try!(table.delete());
+                    return Ok(generate_rows_dummy());
+                }
+                try!(self.before_write(&s));
+                for (child, foreign_key) in try!(self.referencing(&s)) {
+                    if child != s {
+                        return Err(ExecutionError::RowIsReferenced(foreign_key.name));
+                    }
+                }
+                let base = try!(self.get_own_database());
+                let table = try!(base.load_table(&s));
+                try!(table.delete());
+                let mut meta = try!(base.load_meta_data());
+                if meta.forget_table(&s) {
+                    try!(base.save_meta_data(&meta));
+                }
+                Ok(generate_rows_dummy())
+            }
+            DropStmt::View(name) => {
+                try!(self.require_privilege(Some(&name), Privilege::Drop));
+                let base = try!(self.get_own_database());
+                let mut meta = try!(base.load_meta_data());
+                if !meta.remove_view(&name) {
+                    return Err(ExecutionError::UnknownView(name));
+                }
+                meta.forget_table(&name);
+                try!(base.save_meta_data(&meta));
+                Ok(generate_rows_dummy())
+            }
+            DropStmt::Function(name) => {
+                try!(self.require_privilege(None, Privilege::Drop));
+                let base = try!(self.get_own_database());
+                let mut meta = try!(base.load_meta_data());
+                if !meta.remove_function(&name) {
+                    return Err(ExecutionError::UnknownFunction(name));
+                }
+                try!(base.save_meta_data(&meta));
+                Ok(generate_rows_dummy())
+            }
+            DropStmt::Event(name) => {
+                try!(self.require_privilege(None, Privilege::Drop));
+                let base = try!(self.get_own_database());
+                let mut meta = try!(base.load_meta_data());
+                if !meta.remove_event(&name) {
+                    return Err(ExecutionError::UnknownEvent(name));
+                }
+                try!(base.save_meta_data(&meta));
+                Ok(generate_rows_dummy())
+            }
+            DropStmt::Database(stmt) => self.execute_drop_database_stmt(stmt),
+            DropStmt::User(name) => {
+                try!(self.require_admin());
+                try!(auth::drop_user(&name));
+                // Privileges of removed users must not be inherited by a new
+                // account with the same name
+                for dbname in try!(Database::list()) {
+                    let base = try!(Database::load(&dbname));
+                    let mut meta = try!(base.load_meta_data());
+                    if meta.forget_user(&name) {
+                        try!(base.save_meta_data(&meta));
+                    }
+                }
+                Ok(generate_rows_dummy())
+            }
+        }
+    }
+
+    /// Drops a database. Fails while other connections use it as their
+    /// current database, unless `FORCE` is given: they are killed then.
+    fn execute_drop_database_stmt(
+        &mut self,
+        stmt: DropDatabaseStmt,
+    ) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
+        let base = match Database::load(&stmt.name) {
+            Ok(base) => base,
+            Err(_) if stmt.if_exists => return Ok(generate_rows_dummy()),
+            Err(e) => return Err(e.into()),
+        };
+        if !try!(auth::has_privilege(self.user, &base, None, Privilege::Drop)) {
+            return Err(ExecutionError::AuthError(auth::AuthError::PermissionDenied));
+        }
+        let others = self.connections_using(&stmt.name);
+        if !others.is_empty() {
+            if !stmt.force {
+                return Err(ExecutionError::DatabaseInUse(stmt.name));
+            }
+            // only the administrator may kill connections of other users
+            if !self.user.is_admin() && others.iter().any(|p| p.user != self.user._name) {
+                return Err(ExecutionError::AuthError(auth::AuthError::PermissionDenied));
+            }
+            warn!(
+                "killing connections {:?} to drop database {:?}",
+                others.iter().map(|p| p.id).collect::<Vec<_>>(),
+                stmt.name
+            );
+            for process in &others {
+                // a connection may have ended meanwhile
+                let _ = process::kill(process.id, &self.user._name, self.user.is_admin());
+            }
+            // killed connections roll back their transactions when they end
+            let start = Instant::now();
+            while !self.connections_using(&stmt.name).is_empty() {
+                try!(self.check_cancelled());
+                if start.elapsed() > DISCONNECT_TIMEOUT {
+                    return Err(ExecutionError::DatabaseInUse(stmt.name));
+                }
+                thread::sleep(Duration::from_millis(50));
+            }
+        }
+        try!(lock::without_locks(&stmt.name, || base.delete()));
+        info!(
+            "user {:?} dropped database {:?}",
+            self.user._name, stmt.name
+        );
+        let dropped_current = match self.user._currentDatabase {
+            Some(ref base) => base.name == stmt.name,
+            None => false,
+        };
+        if dropped_current {
+            self.user._currentDatabase = None;
+        }
+        Ok(generate_rows_dummy())
+    }
+
+    /// Returns the other connections whose current database is `database`.
+    fn connections_using(&self, database: &str) -> Vec<process::Process> {
+        process::list()
+            .into_iter()
+            .filter(|p| {
+                p.id != self.connection && p.database.as_ref().map(|d| &d[..]) == Some(database)
+            })
+            .collect()
+    }
+
+    fn execute_truncate_stmt(
+        &mut self,
+        table: String,
+    ) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
+        // Emptying the files of a table is as final as dropping it
+        try!(self.require_privilege(Some(&table), Privilege::Drop));
+        try!(self.before_write(&table));
+        for (child, foreign_key) in try!(self.referencing(&table)) {
+            if child != table {
+                return Err(ExecutionError::RowIsReferenced(foreign_key.name));
+            }
+        }
+        try!(try!(self.get_table(&table)).truncate());
+        try!(self.forget_stats(&table));
+        Ok(generate_rows_dummy())
+    }
+
+    fn execute_alt_stmt(
+        &mut self,
+        query: AltStmt,
+    ) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
+        match query {
+            AltStmt::Table(stmt) => self.execute_alt_table_stmt(stmt),
+            AltStmt::Database(stmt) => self.execute_alt_database_stmt(stmt),
+            AltStmt::User(stmt) => {
+                // Everybody may change their own password
+                if stmt.name != self.user._name {
+                    try!(self.require_admin());
+                }
+                try!(auth::alter_user_password(&stmt.name, &stmt.password));
+                Ok(generate_rows_dummy())
+            }
+        }
+    }
+
+    /// Renames a table. Inside of `BEGIN`, `ROLLBACK` restores the files of
+    /// the table and of the tables referencing it, but not its grants and
+    /// statistics.
+    fn execute_rename_table(
+        &mut self,
+        table: &str,
+        new_name: &str,
+    ) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
+        // the table is created anew under the new name
+        try!(self.require_privilege(Some(new_name), Privilege::Create));
+        let base = try!(self.get_own_database());
+        if base
+            .load_temporary_table(self.connection, new_name)
+            .is_some()
+        {
+            return Err(storage::Error::TableExists(new_name.to_string()).into());
+        }
+        if base.load_temporary_table(self.connection, table).is_some() {
+            // a temporary table must not hide a table of the database
+            if base.load_table(new_name).is_ok() {
+                return Err(storage::Error::TableExists(new_name.to_string()).into());
+            }
+            memory::rename(self.connection, &base.name, table, new_name);
+            return Ok(generate_rows_dummy());
+        }
+        try!(self.before_write(table));
+        try!(self.before_write(new_name));
+        for (child, _) in try!(self.referencing(table)) {
+            if child != table {
+                try!(self.before_write(&child));
+            }
+        }
+        try!(base.load_table(table));
+        try!(base.rename_table(table, new_name));
+        Ok(generate_rows_dummy())
+    }
+
+    /// Renames a database, which no transaction may use meanwhile. The
+    /// connections using it and their temporary tables move along.
+    fn execute_rename_database(
+        &mut self,
+        database: String,
+        new_name: String,
+    ) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
+        let mut base = try!(Database::load(&database));
+        if !try!(auth::has_privilege(self.user, &base, None, Privilege::Drop)) {
+            return Err(ExecutionError::AuthError(auth::AuthError::PermissionDenied));
+        }
+        try!(lock::without_locks(&database, || {
+            try!(base.rename(&new_name));
+            memory::rename_database(&database, &new_name);
+            process::rename_database(&database, &new_name);
+            Ok(())
+        }));
+        if let Some(ref mut current) = self.user._currentDatabase {
+            if current.name == database {
+                current.name = new_name;
+            }
+        }
+        Ok(generate_rows_dummy())
+    }
+
+    /// Changes the limits of a database, the limits left out keep their
+    /// values. Connections and data beyond a lowered limit stay, only new
+    /// ones are refused.
+    fn execute_alt_database_stmt(
+        &mut self,
+        stmt: AlterDatabaseStmt,
+    ) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
+        // the limits protect the other databases, whose users cannot be
+        // asked
+        try!(self.require_admin());
+        let base = try!(Database::load(&stmt.name));
+        let mut meta_data = try!(base.load_meta_data());
+        if let Some(max_size) = stmt.max_size {
+            meta_data.limits.max_size = max_size;
+        }
+        if let Some(max_connections) = stmt.max_connections {
+            meta_data.limits.max_connections = max_connections;
+        }
+        try!(base.save_meta_data(&meta_data));
+        Ok(generate_rows_dummy())
+    }
+
+    fn execute_alt_table_stmt(
+        &mut self,
+        stmt: AlterTableStmt,
+    ) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
+        // Changing the layout of a table counts as (re)creating it
+        try!(self.require_privilege(Some(&stmt.tid), Privilege::Create));
+        if let AlterOp::Rename(ref new_name) = stmt.op {
+            return self.execute_rename_table(&stmt.tid, new_name);
+        }
+        try!(self.before_write(&stmt.tid));
+        let old = try!(self.get_table(&stmt.tid));
+        let mut table = try!(self.get_table(&stmt.tid));
+        // the column of the old row each column of the new row comes from,
+        // or the value of a new column
+        let mut sources: Vec<Result<usize, Option<Vec<u8>>>> =
+            (0..old.columns().len()).map(Ok).collect();
+        match stmt.op {
+            AlterOp::Add(columninfo) => {
+                let comment = if columninfo.comment.is_some() {
+                    columninfo.comment.unwrap()
+                } else {
+                    "".into()
+                };
+
+                let mut column = Column::new(
+                    &columninfo.cid,
+                    columninfo.datatype,
+                    !columninfo.not_null,
+                    &comment,
+                    columninfo.primary,
+                );
+                column.default = columninfo.default;
+                try!(check_default(&column));
+                // existing rows get the default or null
+                let value = match column.default {
+                    Some(ref lit) => try!(evaluate(lit)),
+                    None => Lit::Null,
+                };
+                sources.push(Err(match value {
+                    Lit::Null => None,
+                    Lit::Bytes(ref b) if column.sql_type == SqlType::Blob => {
+                        let engine = try!(self.get_engine(&stmt.tid));
+                        Some(try!(engine.write_blob(&mut Cursor::new(b))))
+                    }
+                    Lit::String(ref s) if column.sql_type == SqlType::Blob => {
+                        let engine = try!(self.get_engine(&stmt.tid));
+                        Some(try!(engine.write_blob(&mut Cursor::new(s.as_bytes()))))
+                    }
+                    value => {
+                        let mut data = Vec::new();
+                        try!(column.sql_type.encode_into(&mut data, &value));
+                        Some(data)
+                    }
+                }));
+                try!(table.add_column(
+                    &column.name,
+                    column.sql_type,
+                    column.allow_null,
+                    &column.description,
+                    column.is_primary_key,
+                    column.default,
+                ));
+                if let Some(condition) = columninfo.check {
+                    let name = format!("{}_{}_check", stmt.tid, columninfo.cid);
+                    try!(add_check(&mut table, &name, condition));
+                }
+            }
+            AlterOp::Drop(column) => {
+                for (_, foreign_key) in try!(self.referencing(&stmt.tid)) {
+                    if foreign_key.ref_columns.contains(&column) {
+                        return Err(ExecutionError::RowIsReferenced(foreign_key.name));
+                    }
+                }
+                let index = match old.columns().iter().position(|c| c.name == column) {
+                    Some(i) => i,
+                    None => return Err(ExecutionError::UnknownColumn),
+                };
+                try!(table.remove_column(&column));
+                sources.remove(index);
+            }
+            AlterOp::Modify(columninfo) => {
+                let comment = if columninfo.comment.is_some() {
+                    columninfo.comment.unwrap()
+                } else {
+                    "".into()
+                };
+                let column = Column {
+                    name: columninfo.cid.clone(),
+                    sql_type: columninfo.datatype,
+                    is_primary_key: columninfo.primary,
+                    allow_null: !columninfo.not_null,
+                    description: comment,
+                    default: columninfo.default,
+                };
+                try!(check_default(&column));
+                try!(table.modify_column(column));
+                // a new check of the column replaces the old one
+                if let Some(condition) = columninfo.check {
+                    let name = format!("{}_{}_check", stmt.tid, columninfo.cid);
+                    table.remove_check(&name);
+                    try!(add_check(&mut table, &name, condition));
+                }
+            }
+            // answered by execute_rename_table
+            AlterOp::Rename(_) => return Err(ExecutionError::DebugError("RENAME".into())),
+        }
+
+        // the rows are converted into the new layout, checking the
+        // constraints of the new columns
+        try!(old.rewrite(&table, |row| {
+            let mut values = Vec::new();
+            for (column, source) in table.columns().iter().zip(&sources) {
+                let value = match source {
+                    &Ok(i) => {
+                        let value = if types::is_null(old.columns(), row, i) {
+                            None
+                        } else {
+                            Some(&row[types::column_range(old.columns(), i)])
+                        };
+                        try!(convert_value(&old.columns()[i], column, value))
+                    }
+                    &Err(ref value) => value.clone(),
+                };
+                if value.is_none() && !column.allow_null {
+                    return Err(ExecutionError::NotNullViolation(column.name.clone()));
+                }
+                values.push(value);
+            }
+            let row = try!(types::encode_row(table.columns(), &values));
+            try!(check_constraints(&table, &row));
+            Ok(row)
+        }));
+        try!(self.forget_stats(&stmt.tid));
+        Ok(generate_rows_dummy())
+    }
+
+    /// Fails unless the current user is the administrator.
+    fn require_admin(&self) -> Result<(), ExecutionError> {
+        if self.user.is_admin() {
+            Ok(())
+        } else {
+            Err(ExecutionError::AuthError(auth::AuthError::PermissionDenied))
+        }
+    }
+
+    /// Fails unless the current user holds `privilege` on the given table of
+    /// the current database.
+    fn require_privilege(
+        &self,
+        table: Option<&str>,
+        privilege: Privilege,
+    ) -> Result<(), ExecutionError> {
+        let base = try!(self.get_own_database());
+        if try!(auth::has_privilege(self.user, base, table, privilege)) {
+            Ok(())
+        } else {
+            Err(ExecutionError::AuthError(auth::AuthError::PermissionDenied))
+        }
+    }
+
+    fn get_own_database(&self) -> Result<&Database, ExecutionError> {
+        match self.user._currentDatabase {
+            Some(ref base) => Ok(base),
+            None => Err(ExecutionError::NoDatabaseSelected),
+        }
+    }
+
+    /// Must be called before the schema of a table changes: locks the table
+    /// exclusively, so that an open transaction can undo the change, see
+    /// `storage::transaction`.
+    fn before_write(&self, table: &str) -> Result<(), ExecutionError> {
+        let base = try!(self.get_own_database());
+        // temporary tables are not restored on rollback
+        if base.load_temporary_table(self.connection, table).is_some() {
+            return Ok(());
+        }
+        try!(self.lock_table(&base.name, table, Mode::Exclusive));
+        Ok(try!(transaction::before_write(
+            self.connection,
+            &base.name,
+            table
+        )))
+    }
+
+    /// Removes the statistics of a table whose rows or columns changed
+    /// wholesale.
+    fn forget_stats(&self, table: &str) -> Result<(), ExecutionError> {
+        let base = try!(self.get_own_database());
+        let mut meta = try!(base.load_meta_data());
+        if meta.forget_stats(table) {
+            try!(base.save_meta_data(&meta));
+        }
+        Ok(())
+    }
+
+    /// Fails if the query was cancelled. Must be called between storage
+    /// engine calls and in every loop over rows.
+    fn check_cancelled(&self) -> Result<(), ExecutionError> {
+        if self.cancel.is_cancelled() {
+            Err(ExecutionError::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Counts a row for the progress of the query, see `process::Progress`,
+    /// and fails if the query was cancelled. Must be called in every loop
+    /// over the rows a statement reads or writes.
+    fn next_row(&self) -> Result<(), ExecutionError> {
+        self.progress.add_row();
+        self.check_cancelled()
+    }
+
+    fn get_table(&self, table: &str) -> Result<Table, ExecutionError> {
+        try!(self.check_cancelled());
+        self.progress.set_table(table);
+        let dbase = try!(self.get_own_database());
+        // temporary tables hide the tables of the database
+        if let Some(temporary) = dbase.load_temporary_table(self.connection, table) {
+            return Ok(temporary);
+        }
+        try!(self.lock_table(&dbase.name, table, Mode::Shared));
+        Ok(try!(dbase.load_table(table)))
+    }
+
+    /// Locks a table for the transaction of the statement, see
+    /// `storage::lock`. Waiting for the lock ends when the statement is
+    /// cancelled.
+    fn lock_table(&self, database: &str, table: &str, mode: Mode) -> Result<(), ExecutionError> {
+        let cancel = self.cancel;
+        let locked = try!(self
+            .statement
+            .lock(database, table, mode, || cancel.is_cancelled()));
+        if locked {
+            Ok(())
+        } else {
+            Err(ExecutionError::Cancelled)
+        }
+    }
+
+    fn get_engine<'b>(&'b self, table: &str) -> Result<Box<dyn Engine + 'b>, ExecutionError> {
+        let table = try!(self.get_table(table));
+        Ok(self.versioned(table))
+    }
+
+    /// Creates the engine of a table, reading the snapshot of the statement.
+    /// Rows of temporary tables are not versioned.
+    fn versioned<'b>(&'b self, table: Table<'b>) -> Box<dyn Engine + 'b> {
+        if table.connection().is_some() {
+            return table.create_engine();
+        }
+        Box::new(Versioned::new(table.create_engine(), &self.statement))
+    }
+
+    /// Checks a foreign key of a new table, which may reference the table
+    /// itself, and returns it as it is stored.
+    fn foreign_key(
+        &self,
+        table: &Table,
+        info: ForeignKeyInfo,
+    ) -> Result<ForeignKey, ExecutionError> {
+        let name = format!("{}_{}_fkey", table.name, info.cols.join("_"));
+        let loaded;
+        let parent = if info.ref_tid == table.name {
+            table
+        } else {
+            loaded = try!(self.get_table(&info.ref_tid));
+            &loaded
+        };
+        // rows of other connections could not reference rows of a
+        // temporary table
+        if parent.connection().is_some() {
+            return Err(ExecutionError::InvalidForeignKey(name));
+        }
+        let primary_key: Vec<String> = parent
+            .columns()
+            .iter()
+            .filter(|c| c.is_primary_key)
+            .map(|c| c.name.clone())
+            .collect();
+        let ref_cols = if info.ref_cols.is_empty() {
+            primary_key.clone()
+        } else {
+            info.ref_cols
+        };
+        if ref_cols.len() != info.cols.len() {
+            return Err(ExecutionError::InvalidForeignKey(name));
+        }
+        // the referenced columns have to identify a row
+        let sorted = |columns: &[String]| {
+            let mut columns = columns.to_vec();
+            columns.sort();
+            columns
+        };
+        if sorted(&ref_cols) != sorted(&primary_key)
+            && !parent
+                .indexes()
+                .iter()
+                .any(|i| i.unique && sorted(&i.columns) == sorted(&ref_cols))
+        {
+            return Err(ExecutionError::InvalidForeignKey(name));
+        }
+        for (col, ref_col) in info.cols.iter().zip(ref_cols.iter()) {
+            let column = match table.columns().iter().find(|c| &c.name == col) {
+                Some(c) => c,
+                None => return Err(ExecutionError::UnknownColumn),
+            };
+            let ref_column = match parent.columns().iter().find(|c| &c.name == ref_col) {
+                Some(c) => c,
+                None => return Err(ExecutionError::UnknownColumn),
+            };
+            if column.sql_type != ref_column.sql_type || column.sql_type == SqlType::Blob {
+                return Err(ExecutionError::CompareDatatypeMissmatch);
+            }
+            if info.on_delete == ReferentialAction::SetNull && !column.allow_null {
+                return Err(ExecutionError::InvalidForeignKey(name));
+            }
+        }
+        Ok(ForeignKey {
+            name: name,
+            columns: info.cols,
+            ref_table: info.ref_tid,
+            ref_columns: ref_cols,
+            on_delete: info.on_delete,
+        })
+    }
+
+    /// Returns the foreign keys referencing a table, with the names of the
+    /// tables they belong to.
+    fn referencing(&self, table: &str) -> Result<Vec<(String, ForeignKey)>, ExecutionError> {
+        let base = try!(self.get_own_database());
+        let mut found = Vec::new();
+        for name in try!(base.list_tables()) {
+            let other = try!(base.load_table(&name));
+            for foreign_key in other.foreign_keys() {
+                if foreign_key.ref_table == table {
+                    found.push((name.clone(), foreign_key.clone()));
+                }
+            }
+        }
+        Ok(found)
+    }
+
+    /// Fails with `ForeignKeyViolation` unless every foreign key of a new row
+    /// of the table is null or references a row.
+    fn check_references(&self, table: &Table, row: &[u8]) -> Result<(), ExecutionError> {
+        let columns = table.columns();
+        for foreign_key in table.foreign_keys() {
+            let values = match try!(key_values(columns, row, &foreign_key.columns)) {
+                Some(values) => values,
+                None => continue,
+            };
+            // a row may reference itself
+            if foreign_key.ref_table == table.name
+                && try!(key_values(columns, row, &foreign_key.ref_columns)) == Some(values.clone())
+            {
+                continue;
+            }
+            let found = try!(self.rows_with_values(
+                &foreign_key.ref_table,
+                &foreign_key.ref_columns,
+                &values
+            ));
+            if found.is_empty() {
+                return Err(ExecutionError::ForeignKeyViolation(
+                    foreign_key.name.clone(),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Fails if rows of a table are replaced by rows with other values of a
+    /// key other rows reference.
+    fn check_key_changes(
+        &self,
+        table: &str,
+        columns: &[Column],
+        old_rows: &[Vec<u8>],
+        new_rows: &[Vec<u8>],
+    ) -> Result<(), ExecutionError> {
+        for (child, foreign_key) in try!(self.referencing(table)) {
+            for (old, new) in old_rows.iter().zip(new_rows) {
+                let values = match try!(key_values(columns, old, &foreign_key.ref_columns)) {
+                    Some(values) => values,
+                    None => continue,
+                };
+                if try!(key_values(columns, new, &foreign_key.ref_columns)) == Some(values.clone())
+                {
+                    continue;
+                }
+                if !try!(self.rows_with_values(&child, &foreign_key.columns, &values)).is_empty() {
+                    return Err(ExecutionError::RowIsReferenced(foreign_key.name));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns a row of a table with the same values of the primary key or
+    /// of a unique index as the given row, if there is one.
+    fn duplicate_row(&self, table: &str, row: &[u8]) -> Result<Option<Vec<u8>>, ExecutionError> {
+        let (columns, mut keys) = {
+            let table = try!(self.get_table(table));
+            let keys: Vec<Vec<String>> = table
+                .indexes()
+                .iter()
+                .filter(|index| index.unique)
+                .map(|index| index.columns.clone())
+                .collect();
+            (table.columns().to_vec(), keys)
+        };
+        let primary_key: Vec<String> = columns
+            .iter()
+            .filter(|c| c.is_primary_key)
+            .map(|c| c.name.clone())
+            .collect();
+        if !primary_key.is_empty() {
+            keys.insert(0, primary_key);
+        }
+        for key in keys {
+            // null values never collide
+            let values = match try!(key_values(&columns, row, &key)) {
+                Some(values) => values,
+                None => continue,
+            };
+            let found = try!(self.rows_with_values(table, &key, &values));
+            if let Some(found) = found.into_iter().next() {
+                return Ok(Some(found));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns the rows of a table whose columns hold the given values.
+    fn rows_with_values(
+        &self,
+        table: &str,
+        columns: &[String],
+        values: &[Vec<u8>],
+    ) -> Result<Vec<Vec<u8>>, ExecutionError> {
+        let engine = try!(self.get_engine(table));
+        let all = engine.table().columns();
+        let mut indexes = Vec::new();
+        for name in columns {
+            match all.iter().position(|c| &c.name == name) {
+                Some(i) => indexes.push(i),
+                None => return Err(ExecutionError::UnknownColumn),
+            }
+        }
+        let rows = try!(engine.lookup(indexes[0], (&values[0], None), CompType::Equ));
+        let mut found = try!(read_rows(rows));
+        found.retain(|row| {
+            indexes.iter().zip(values).all(|(&i, value)| {
+                !types::is_null(all, row, i) && &row[types::column_range(all, i)] == &value[..]
+            })
+        });
+        Ok(found)
+    }
+
+    /// Deletes rows of a table after applying the ON DELETE actions of the
+    /// foreign keys referencing them. `deleted` holds the rows deleted by the
+    /// statement so far, so that cascades through cycles of tables end.
+    fn delete_rows(
+        &self,
+        table: &str,
+        mut rows: Vec<Vec<u8>>,
+        deleted: &mut Vec<(String, Vec<u8>)>,
+    ) -> Result<(), ExecutionError> {
+        rows.retain(|row| !deleted.contains(&(table.to_string(), row.clone())));
+        if rows.is_empty() {
+            return Ok(());
+        }
+        deleted.extend(rows.iter().map(|row| (table.to_string(), row.clone())));
+        let columns = try!(self.get_table(table)).columns().to_vec();
+        for (child, foreign_key) in try!(self.referencing(table)) {
+            for row in &rows {
+                try!(self.check_cancelled());
+                let values = match try!(key_values(&columns, row, &foreign_key.ref_columns)) {
+                    Some(values) => values,
+                    None => continue,
+                };
+                let mut children =
+                    try!(self.rows_with_values(&child, &foreign_key.columns, &values));
+                children.retain(|r| !deleted.contains(&(child.clone(), r.clone())));
+                if children.is_empty() {
+                    continue;
+                }
+                match foreign_key.on_delete {
+                    ReferentialAction::Restrict => {
+                        return Err(ExecutionError::RowIsReferenced(foreign_key.name))
+                    }
+                    ReferentialAction::Cascade => try!(self.delete_rows(&child, children, deleted)),
+                    ReferentialAction::SetNull => {
+                        try!(self.remove_rows(&child, &children));
+                        let mut engine = try!(self.get_engine(&child));
+                        let child_columns = engine.table().columns().to_vec();
+                        for mut r in children {
+                            for name in &foreign_key.columns {
+                                let i = match child_columns.iter().position(|c| &c.name == name) {
+                                    Some(i) => i,
+                                    None => return Err(ExecutionError::UnknownColumn),
+                                };
+                                for b in &mut r[types::column_range(&child_columns, i)] {
+                                    *b = 0;
+                                }
+                                types::set_null(&child_columns, &mut r, i, true);
+                            }
+                            try!(engine.insert_row(&r));
+                        }
+                    }
+                }
+            }
+        }
+        self.remove_rows(table, &rows)
+    }
+
+    /// Deletes exactly the given rows of a table. The engines delete by a
+    /// condition, so rows sharing the value of the first primary key column
+    /// are put back, like `mvcc::revert` does.
+    fn remove_rows(&self, table: &str, rows: &[Vec<u8>]) -> Result<(), ExecutionError> {
+        let mut engine = try!(self.get_engine(table));
+        let columns = engine.table().columns().to_vec();
+        let key_column = match columns.iter().position(|c| c.is_primary_key) {
+            Some(i) => i,
+            None => {
+                return Err(ExecutionError::StorageError(
+                    storage::Error::FoundNoPrimaryKey,
+                ))
+            }
+        };
+        for row in rows {
+            try!(self.next_row());
+            let value = row[types::column_range(&columns, key_column)].to_vec();
+            let same_key = try!(read_rows(try!(engine.lookup(
+                key_column,
+                (&value, None),
+                CompType::Equ
+            ))));
+            if !same_key.contains(row) {
+                continue;
+            }
+            try!(engine.delete(key_column, (&value, None), CompType::Equ));
+            for other in same_key.iter().filter(|r| !rows.contains(r)) {
+                try!(engine.insert_row(other));
+            }
+        }
+        Ok(())
+    }
+
+    /// Answers a query on a single table ordered by a column with a secondary
+    /// index, or with a range condition like `BETWEEN` on such a column,
+    /// through a range scan of the storage engine, see `range_scan`. Returns
+    /// the rows, which also fulfill the condition, and whether they are
+    /// ordered already. Returns None if the query has to be answered
+    /// otherwise.
+    fn range_pushdown(
+        &self,
+        stmt: &SelectStmt,
+    ) -> Result<Option<(Rows<Cursor<Vec<u8>>>, bool)>, ExecutionError> {
+        let scan = match try!(self.range_scan(stmt)) {
+            Some(scan) => scan,
+            None => return Ok(None),
+        };
+        let engine = try!(self.get_engine(&stmt.tid[0]));
+        let rows = try!(engine.scan_range(
+            scan.column,
+            borrow_bound(&scan.bounds[0]),
+            borrow_bound(&scan.bounds[1])
+        ));
+        let mut rows = match scan.descending {
+            Some(descending) => {
+                let columns = rows.columns.clone();
+                let mut all = try!(read_rows(rows));
+                // nulls come after all values
+                if scan.nulls {
+                    let nulls = try!(engine.lookup(scan.column, (&[], None), CompType::IsNull));
+                    all.extend(try!(read_rows(nulls)));
+                }
+                if descending {
+                    all.reverse();
+                }
+                try!(to_rows(&columns, all))
+            }
+            None => rows,
+        };
+        try!(rows.reset_pos());
+        Ok(Some((rows, scan.descending.is_some())))
+    }
+
+    /// Decides whether a query on a single table is answered by a range scan
+    /// of a secondary index, which reads the rows in the order of the index.
+    /// Returns None if the query has to be answered otherwise.
+    fn range_scan(&self, stmt: &SelectStmt) -> Result<Option<RangeScan>, ExecutionError> {
+        if stmt.tid.len() != 1
+            || !stmt.joins.is_empty()
+            || stmt.order.len() > 1
+            || Catalog::from_name(&stmt.tid[0]).is_some()
+            || try!(self.view(&stmt.tid[0])).is_some()
+        {
+            return Ok(None);
+        }
+        let table = &stmt.tid[0];
+        let own = |alias: &Option<String>| match alias {
+            &Some(ref alias) => alias == table || stmt.alias.get(alias) == Some(table),
+            &None => true,
+        };
+        let range = match stmt.cond {
+            Some(ref cond) => match range_condition(cond) {
+                Some(range) => Some(range),
+                None => return Ok(None),
+            },
+            None => None,
+        };
+        if let Some(ref range) = range {
+            if !own(range.alias) {
+                return Ok(None);
+            }
+        }
+        let engine = try!(self.get_engine(table));
+        let indexed = |column: &str| {
+            engine
+                .table()
+                .indexes()
+                .iter()
+                .any(|i| i.columns.len() == 1 && i.columns[0] == column)
+        };
+        let sort = stmt.order.first();
+        let column = match (sort, &range) {
+            (Some(sort), _) if !own(&sort.alias) || !indexed(&sort.col) => return Ok(None),
+            (Some(sort), &Some(ref range)) if range.col != sort.col => return Ok(None),
+            (Some(sort), _) => sort.col.clone(),
+            // single comparisons are left to lookup_pushdown
+            (None, &Some(ref range)) if range.lower.is_some() && range.upper.is_some() => {
+                if !indexed(range.col) {
+                    return Ok(None);
+                }
+                range.col.to_string()
+            }
+            (None, _) => return Ok(None),
+        };
+        let (index, sql_type, allow_null) = {
+            let columns = engine.table().columns();
+            match columns.iter().position(|c| c.name == column) {
+                Some(i) => (i, columns[i].sql_type, columns[i].allow_null),
+                None => return Ok(None),
+            }
+        };
+
+        let (lower, upper) = match range {
+            Some(ref range) => (range.lower, range.upper),
+            None => (None, None),
+        };
+        let mut bounds = Vec::new();
+        for bound in &[lower, upper] {
+            bounds.push(match bound {
+                &Some((op, lit)) => {
+                    let mut data = Vec::new();
+                    // a mismatching literal is reported by execute_where
+                    if sql_type
+                        .encode_into(&mut data, &try!(evaluate(lit)))
+                        .is_err()
+                    {
+                        return Ok(None);
+                    }
+                    match op {
+                        CompType::GThan | CompType::SThan => Bound::Excluded(data),
+                        _ => Bound::Included(data),
+                    }
+                }
+                &None => Bound::Unbounded,
+            });
+        }
+        let mut share = None;
+        if sort.is_none() {
+            // like a lookup, the range scan reads the rows one by one
+            let meta = try!(try!(self.get_own_database()).load_meta_data());
+            // the shares of the rows above the lower and below the upper
+            // bound overlap in the range
+            share = meta.stats(table).and_then(|s| {
+                let mut share = -1.0;
+                for (bound, &(op, _)) in bounds.iter().zip(&[lower.unwrap(), upper.unwrap()]) {
+                    let value = match bound {
+                        &Bound::Included(ref v) | &Bound::Excluded(ref v) => v,
+                        &Bound::Unbounded => return None,
+                    };
+                    match s.selectivity(&column, sql_type, op, value) {
+                        Some(selectivity) => share += selectivity,
+                        None => return None,
+                    }
+                }
+                Some(share)
+            });
+            if !planner::pays_off(share) {
+                return Ok(None);
+            }
+        }
+
+        Ok(Some(RangeScan {
+            column: index,
+            bounds: bounds,
+            descending: sort.map(|sort| sort.order == Some(Order::Desc)),
+            nulls: sort.is_some() && range.is_none() && allow_null,
+            share: share,
+        }))
+    }
+
+    /// Executes the subqueries of the targets and conditions of a statement
+    /// once and replaces them by their results. Targets with a subquery are
+    /// named after their expression. Correlated subqueries of the targets
+    /// and the WHERE condition, those referring to columns of the statement,
+    /// are kept to be executed for every row, see `correlated_where`, unless
+    /// their conjunct is decorrelated, see `decorrelate`.
+    fn materialize_select(&mut self, stmt: &mut SelectStmt) -> Result<(), ExecutionError> {
+        let mut known = HashMap::new();
+        let scope = try!(self.scope(stmt, &mut known));
+        for target in &mut stmt.target {
+            if let Col::Expr(ref mut lit) = target.col {
+                if lit.has_subquery() {
+                    if target.rename.is_none() {
+                        target.rename = Some(dump::literal(lit));
+                    }
+                    let mut probe = lit.clone();
+                    let subqueries = literal_subqueries(&mut probe);
+                    if !try!(self.correlated(subqueries, &scope, &mut known)) {
+                        try!(self.materialize(lit));
+                    }
+                }
+            }
+        }
+        for join in &mut stmt.joins {
+            try!(self.materialize_condition(&mut join.cond));
+        }
+        if !stmt.cond.as_ref().map_or(false, Conditions::has_subquery) {
+            return Ok(());
+        }
+        let mut parts = Vec::new();
+        for mut part in and_parts(stmt.cond.take().unwrap()) {
+            if part.has_subquery() {
+                let mut probe = part.clone();
+                let subqueries = condition_subqueries(&mut probe);
+                if !try!(self.correlated(subqueries, &scope, &mut known)) {
+                    try!(self.materialize_condition(&mut part));
+                } else if let Some(decorrelated) = try!(self.decorrelate(&part, &scope, &mut known))
+                {
+                    part = decorrelated;
+                }
+            }
+            parts.push(part);
+        }
+        stmt.cond = Conditions::all(parts);
+        Ok(())
+    }
+
+    /// Returns the scope of a select, see `Scope`. The columns of its tables
+    /// are taken from `known` or added to it.
+    fn scope(
+        &mut self,
+        stmt: &SelectStmt,
+        known: &mut HashMap<String, Vec<Column>>,
+    ) -> Result<Scope, ExecutionError> {
+        let mut scope = Scope {
+            qualifiers: stmt.alias.keys().cloned().collect(),
+            columns: HashSet::new(),
+        };
+        for tid in stmt
+            .tid
+            .iter()
+            .chain(stmt.joins.iter().map(|join| &join.tid))
+        {
+            if !known.contains_key(tid) {
+                let columns = try!(self.source_columns(tid));
+                known.insert(tid.clone(), columns);
+            }
+            scope.qualifiers.insert(tid.clone());
+            scope
+                .columns
+                .extend(known[tid].iter().map(|column| column.name.clone()));
+        }
+        Ok(scope)
+    }
+
+    /// Returns whether subqueries are correlated with the select of `scope`,
+    /// whether they refer to its columns.
+    fn correlated(
+        &mut self,
+        subqueries: Vec<&mut SelectStmt>,
+        scope: &Scope,
+        known: &mut HashMap<String, Vec<Column>>,
+    ) -> Result<bool, ExecutionError> {
+        let mut correlated = false;
+        for stmt in subqueries {
+            try!(self.bind_outer(stmt, known, &mut |alias, name| {
+                correlated |= scope.contains(alias, name);
+                Ok(None)
+            }));
+        }
+        Ok(correlated)
+    }
+
+    /// Calls `bind` for every column a subquery refers to that belongs to
+    /// none of its tables, and replaces the column by the value `bind`
+    /// returns, if any. The columns of subqueries nested in it count if they
+    /// belong to neither.
+    fn bind_outer(
+        &mut self,
+        stmt: &mut SelectStmt,
+        known: &mut HashMap<String, Vec<Column>>,
+        bind: &mut dyn FnMut(&Option<String>, &String) -> Result<Option<Lit>, ExecutionError>,
+    ) -> Result<(), ExecutionError> {
+        let scope = try!(self.scope(stmt, known));
+        let mut outer = |alias: &Option<String>, name: &String| {
+            if scope.contains(alias, name) {
+                Ok(None)
+            } else {
+                bind(alias, name)
+            }
+        };
+        for target in &mut stmt.target {
+            if let Col::Expr(ref mut lit) = target.col {
+                try!(self.bind_literal(lit, known, &mut outer));
+            }
+        }
+        for join in &mut stmt.joins {
+            try!(self.bind_condition(&mut join.cond, known, &mut outer));
+        }
+        if let Some(ref mut cond) = stmt.cond {
+            try!(self.bind_condition(cond, known, &mut outer));
+        }
+        Ok(())
+    }
+
+    /// Replaces the columns of an expression by the values `bind` returns
+    /// for them, see `bind_outer`.
+    fn bind_literal(
+        &mut self,
+        lit: &mut Lit,
+        known: &mut HashMap<String, Vec<Column>>,
+        bind: &mut dyn FnMut(&Option<String>, &String) -> Result<Option<Lit>, ExecutionError>,
+    ) -> Result<(), ExecutionError> {
+        let value = match *lit {
+            Lit::Column(ref alias, ref name) => try!(bind(alias, name)),
+            Lit::Call(_, ref mut args) => {
+                for arg in args.iter_mut() {
+                    try!(self.bind_literal(arg, known, bind));
+                }
+                None
+            }
+            Lit::Subquery(ref mut stmt) => {
+                try!(self.bind_outer(stmt, known, bind));
+                None
+            }
+            _ => None,
+        };
+        if let Some(value) = value {
+            *lit = value;
+        }
+        Ok(())
+    }
+
+    /// Replaces the columns of a condition by the values `bind` returns for
+    /// them, see `bind_outer`. A comparison of a replaced column becomes one
+    /// of expressions.
+    fn bind_condition(
+        &mut self,
+        cond: &mut Conditions,
+        known: &mut HashMap<String, Vec<Column>>,
+        bind: &mut dyn FnMut(&Option<String>, &String) -> Result<Option<Lit>, ExecutionError>,
+    ) -> Result<(), ExecutionError> {
+        let bound = match *cond {
+            Conditions::And(ref mut a, ref mut b) | Conditions::Or(ref mut a, ref mut b) => {
+                try!(self.bind_condition(a, known, bind));
+                return self.bind_condition(b, known, bind);
+            }
+            Conditions::Expr(ref mut lhs, _, ref mut rhs) => {
+                try!(self.bind_literal(lhs, known, bind));
+                return self.bind_literal(rhs, known, bind);
+            }
+            Conditions::In(ref mut lhs, ref mut values, _) => {
+                try!(self.bind_literal(lhs, known, bind));
+                for value in values.iter_mut() {
+                    try!(self.bind_literal(value, known, bind));
+                }
+                return Ok(());
+            }
+            Conditions::Exists(ref mut stmt, _) => return self.bind_outer(stmt, known, bind),
+            Conditions::Leaf(ref c) => {
+                let mut rhs = match c.rhs {
+                    CondType::Word(ref name) => Lit::Column(c.aliasrhs.clone(), name.clone()),
+                    CondType::Literal(ref lit) => lit.clone(),
+                };
+                try!(self.bind_literal(&mut rhs, known, bind));
+                match try!(bind(&c.aliascol, &c.col)) {
+                    Some(lhs) => Conditions::Expr(lhs, c.op, rhs),
+                    None => {
+                        let (aliasrhs, rhs) = match rhs {
+                            Lit::Column(alias, name) => (alias, CondType::Word(name)),
+                            rhs => (None, CondType::Literal(rhs)),
+                        };
+                        Conditions::Leaf(Condition {
+                            aliascol: c.aliascol.clone(),
+                            col: c.col.clone(),
+                            op: c.op,
+                            aliasrhs: aliasrhs,
+                            rhs: rhs,
+                        })
+                    }
+                }
+            }
+        };
+        *cond = bound;
+        Ok(())
+    }
+
+    /// Turns `[NOT] EXISTS (SELECT ... FROM t WHERE t.a = outer.b AND ...)`
+    /// into a semi-join on the values of `t.a`, `outer.b IN (values)`, or an
+    /// anti-join, `outer.b NOT IN (values) OR outer.b IS NULL`, so that the
+    /// subquery is executed once instead of for every row. Both are the same
+    /// if the subquery reads a single table without `LIMIT` or `COUNT(*)`
+    /// and refers to the outer select by that equality only, as null values
+    /// are never equal. Returns None for any other condition.
+    fn decorrelate(
+        &mut self,
+        cond: &Conditions,
+        scope: &Scope,
+        known: &mut HashMap<String, Vec<Column>>,
+    ) -> Result<Option<Conditions>, ExecutionError> {
+        let (stmt, negated) = match *cond {
+            Conditions::Exists(ref stmt, negated) => (stmt, negated),
+            _ => return Ok(None),
+        };
+        if stmt.tid.len() != 1
+            || !stmt.joins.is_empty()
+            || stmt.limit.is_some()
+            || stmt.spec_op.is_some()
+            || stmt.target.iter().any(|t| t.col == Col::Count)
+        {
+            return Ok(None);
+        }
+        let inner = try!(self.scope(stmt, known));
+        let mut key = None;
+        let mut rest = Vec::new();
+        for part in stmt.cond.clone().map_or(Vec::new(), and_parts) {
+            if key.is_none() {
+                key = correlation(&part, &inner, scope);
+                if key.is_some() {
+                    continue;
+                }
+            }
+            rest.push(part);
+        }
+        let ((inner_alias, inner_col), (outer_alias, outer_col)) = match key {
+            Some(key) => key,
+            None => return Ok(None),
+        };
+        let mut select = (**stmt).clone();
+        select.target = vec![Target {
+            alias: inner_alias,
+            col: Col::Specified(inner_col),
+            rename: None,
+        }];
+        select.cond = Conditions::all(rest);
+        select.order.clear();
+        if try!(self.correlated(vec![&mut select.clone()], scope, known)) {
+            return Ok(None);
+        }
+        let mut values = try!(self.subquery_values(select));
+        let mut seen = HashSet::new();
+        values.retain(|value| *value != Lit::Null && seen.insert(dump::literal(value)));
+        let column = Lit::Column(outer_alias.clone(), outer_col.clone());
+        if !negated {
+            return Ok(Some(Conditions::In(column, values, false)));
+        }
+        let null = Condition {
+            aliascol: outer_alias,
+            col: outer_col,
+            op: CompType::IsNull,
+            aliasrhs: None,
+            rhs: CondType::Literal(Lit::Null),
+        };
+        Ok(Some(Conditions::Or(
+            Box::new(Conditions::In(column, values, true)),
+            Box::new(Conditions::Leaf(null)),
+        )))
+    }
+
+    /// Keeps the rows fulfilling conditions with correlated subqueries. The
+    /// subqueries are executed again for every row, with the columns of the
+    /// outer select they refer to replaced by the values of the row.
+    fn correlated_where(
+        &mut self,
+        mut rows: Rows<Cursor<Vec<u8>>>,
+        infos: (
+            &HashMap<String, String>,
+            &HashMap<String, String>,
+            &HashMap<String, HashMap<String, usize>>,
+        ),
+        conditions: &[Conditions],
+    ) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
+        let position = |alias: &Option<String>, name: &String| resolve_column(infos, alias, name);
+        let mut known = HashMap::new();
+        let mut selected = Rows::new(Cursor::new(Vec::new()), &rows.columns);
+        try!(rows.reset_pos());
+        loop {
+            try!(self.next_row());
+            let mut row = Vec::new();
+            match rows.next_row(&mut row) {
+                Ok(_) => (),
+                Err(storage::Error::EndOfFile) => break,
+                Err(e) => return Err(e.into()),
+            }
+            let mut holds = true;
+            for condition in conditions {
+                let mut condition = condition.clone();
+                let subqueries = condition_subqueries(&mut condition);
+                try!(self.correlate(subqueries, &rows.columns, &row, infos, &mut known));
+                try!(self.materialize_condition(&mut condition));
+                let columns = &rows.columns;
+                if try!(evaluate_condition(columns, &row, &condition, &position)) != Some(true) {
+                    holds = false;
+                    break;
+                }
+            }
+            if holds {
+                try!(selected.add_row(&row));
+            }
+        }
+        Ok(selected)
+    }
+
+    /// Replaces the columns of the outer select that subqueries refer to by
+    /// their values in a row of it, see `bind_outer`.
+    fn correlate(
+        &mut self,
+        subqueries: Vec<&mut SelectStmt>,
+        columns: &[Column],
+        row: &[u8],
+        infos: (
+            &HashMap<String, String>,
+            &HashMap<String, String>,
+            &HashMap<String, HashMap<String, usize>>,
+        ),
+        known: &mut HashMap<String, Vec<Column>>,
+    ) -> Result<(), ExecutionError> {
+        for stmt in subqueries {
+            try!(self.bind_outer(stmt, known, &mut |alias, name| {
+                match resolve_column(infos, alias, name) {
+                    Ok(index) => column_value(columns, row, index).map(Some),
+                    Err(_) => Ok(None),
+                }
+            }));
+        }
+        Ok(())
+    }
+
+    /// Replaces the subqueries of a condition by their results, those of
+    /// `IN (SELECT ...)` by all values of their column.
+    fn materialize_condition(&mut self, cond: &mut Conditions) -> Result<(), ExecutionError> {
+        match *cond {
+            Conditions::And(ref mut a, ref mut b) | Conditions::Or(ref mut a, ref mut b) => {
+                try!(self.materialize_condition(a));
+                self.materialize_condition(b)
+            }
+            Conditions::Leaf(ref mut c) => match c.rhs {
+                CondType::Literal(ref mut lit) => self.materialize(lit),
+                CondType::Word(_) => Ok(()),
+            },
+            Conditions::Expr(ref mut lhs, _, ref mut rhs) => {
+                try!(self.materialize(lhs));
+                self.materialize(rhs)
+            }
+            Conditions::Exists(..) => {
+                let exists = try!(self.materialize_exists(cond));
+                *cond = exists;
+                Ok(())
+            }
+            Conditions::In(ref mut lhs, ref mut values, _) => {
+                try!(self.materialize(lhs));
+                let subquery = match values.first() {
+                    Some(&Lit::Subquery(ref stmt)) if values.len() == 1 => Some((**stmt).clone()),
+                    _ => None,
+                };
+                match subquery {
+                    Some(stmt) => *values = try!(self.subquery_values(stmt)),
+                    None => {
+                        for value in values.iter_mut() {
+                            try!(self.materialize(value));
+                        }
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Executes the subquery of `[NOT] EXISTS` up to its first row and
+    /// returns a condition always or never holding, like `1 = 1`.
+    fn materialize_exists(&mut self, cond: &Conditions) -> Result<Conditions, ExecutionError> {
+        let (mut stmt, negated) = match *cond {
+            Conditions::Exists(ref stmt, negated) => ((**stmt).clone(), negated),
+            _ => return Ok(cond.clone()),
+        };
+        if stmt.limit.is_none() {
+            stmt.limit = Some(Limit {
+                count: Some(1),
+                offset: None,
+            });
+        }
+        let found = try!(try!(self.execute_select_stmt(stmt)).count_rows()).0 > 0;
+        let holds = if found != negated { 1 } else { 0 };
+        Ok(Conditions::Expr(
+            Lit::Int(1),
+            CompType::Equ,
+            Lit::Int(holds),
+        ))
+    }
+
+    /// Replaces the subqueries of an expression by their single value, null
+    /// if they return no row.
+    fn materialize(&mut self, lit: &mut Lit) -> Result<(), ExecutionError> {
+        let value = match *lit {
+            Lit::Subquery(ref stmt) => {
+                let mut values = try!(self.subquery_values((**stmt).clone()));
+                if values.len() > 1 {
+                    return Err(ExecutionError::InvalidSubquery);
+                }
+                values.pop().unwrap_or(Lit::Null)
+            }
+            Lit::Call(_, ref mut args) => {
+                for arg in args.iter_mut() {
+                    try!(self.materialize(arg));
+                }
+                return Ok(());
+            }
+            _ => return Ok(()),
+        };
+        *lit = value;
+        Ok(())
+    }
+
+    /// Executes a subquery and returns the values of its single column.
+    fn subquery_values(&mut self, stmt: SelectStmt) -> Result<Vec<Lit>, ExecutionError> {
+        let rows = try!(self.execute_select_stmt(stmt));
+        if rows.columns.len() != 1 {
+            return Err(ExecutionError::InvalidSubquery);
+        }
+        let columns = rows.columns.clone();
+        let mut values = Vec::new();
+        for row in try!(read_rows(rows)) {
+            values.push(try!(column_value(&columns, &row, 0)));
+        }
+        Ok(values)
+    }
+
+    /// Answers a conjunct of the condition of a query on a single table
+    /// through the storage engine, see `index_lookup`. Returns the rows found
+    /// and the other conjuncts, which still have to be checked row by row,
+    /// or None if no conjunct can be answered.
+    fn lookup_pushdown(
+        &self,
+        stmt: &SelectStmt,
+    ) -> Result<Option<(Rows<Cursor<Vec<u8>>>, Option<Conditions>)>, ExecutionError> {
+        let lookup = match try!(self.index_lookup(stmt)) {
+            Some(lookup) => lookup,
+            None => return Ok(None),
+        };
+        let engine = try!(self.get_engine(&stmt.tid[0]));
+        let mut rows = try!(engine.lookup(lookup.column, (&lookup.value, None), lookup.op));
+        try!(rows.reset_pos());
+        Ok(Some((rows, lookup.rest)))
+    }
+
+    /// Decides which conjunct of the condition of a query on a single table
+    /// is answered through the storage engine, see `lookup_leaf`: the one
+    /// reading the fewest rows, see `planner::choose_access`. Returns None if
+    /// every conjunct has to be checked row by row.
+    fn index_lookup(&self, stmt: &SelectStmt) -> Result<Option<IndexLookup>, ExecutionError> {
+        if stmt.tid.len() != 1
+            || !stmt.joins.is_empty()
+            || Catalog::from_name(&stmt.tid[0]).is_some()
+            || try!(self.view(&stmt.tid[0])).is_some()
+        {
+            return Ok(None);
+        }
+        let mut conjuncts = match stmt.cond {
+            Some(ref cond) => cond.conjuncts(),
+            None => return Ok(None),
+        };
+        let mut candidates = Vec::new();
+        for (i, conjunct) in conjuncts.iter().enumerate() {
+            if let &Conditions::Leaf(ref c) = conjunct {
+                if let Some(lookup) = try!(self.lookup_leaf(stmt, c)) {
+                    candidates.push((i, lookup));
+                }
+            }
+        }
+        let shares: Vec<_> = candidates.iter().map(|c| c.1.share).collect();
+        let (i, mut lookup) = match planner::choose_access(&shares) {
+            Some(chosen) => candidates.swap_remove(chosen),
+            None => return Ok(None),
+        };
+        conjuncts.remove(i);
+        lookup.rest = Conditions::all(conjuncts);
+        Ok(Some(lookup))
+    }
+
+    /// Decides whether a comparison of a column is answered through the
+    /// storage engine, which may use an index for it: the postings of an
+    /// inverted index for `MATCH` and `LIKE`, or a secondary index on the
+    /// column. Returns None if the comparison has to be checked row by row.
+    fn lookup_leaf(
+        &self,
+        stmt: &SelectStmt,
+        cond: &Condition,
+    ) -> Result<Option<IndexLookup>, ExecutionError> {
+        let lit = match cond.rhs {
+            CondType::Literal(ref lit) => try!(evaluate(lit)),
+            _ => return Ok(None),
+        };
+        let lit = &lit;
+        if let Some(ref alias) = cond.aliascol {
+            if alias != &stmt.tid[0] && stmt.alias.get(alias) != Some(&stmt.tid[0]) {
+                return Ok(None);
+            }
+        }
+        let engine = try!(self.get_engine(&stmt.tid[0]));
+        let (index, sql_type, comparedata) = {
+            let table = engine.table();
+            let index = match table.columns().iter().position(|col| col.name == cond.col) {
+                Some(i) => i,
+                None => return Ok(None),
+            };
+            let sql_type = table.columns()[index].sql_type;
+            let mut comparedata = Vec::<u8>::new();
+            if cond.op == CompType::Match || cond.op == CompType::Like {
+                match lit {
+                    &Lit::String(ref term) if sql_type.is_text() => {
+                        comparedata.extend_from_slice(term.as_bytes())
+                    }
+                    _ => return Ok(None),
+                }
+            } else {
+                let indexes = table.indexes();
+                if !indexes
+                    .iter()
+                    .any(|i| i.columns.len() == 1 && i.columns[0] == cond.col)
+                {
+                    return Ok(None);
+                }
+                // a mismatching literal is reported by execute_where
+                if sql_type.encode_into(&mut comparedata, lit).is_err() {
+                    return Ok(None);
+                }
+            }
+            (index, sql_type, comparedata)
+        };
+        let mut share = None;
+        if !cond.op.takes_text() {
+            let meta = try!(try!(self.get_own_database()).load_meta_data());
+            share = meta
+                .stats(&stmt.tid[0])
+                .and_then(|s| s.selectivity(&cond.col, sql_type, cond.op, &comparedata));
+        }
+        Ok(Some(IndexLookup {
+            column: index,
+            cond: cond.clone(),
+            op: cond.op,
+            value: comparedata,
+            rest: None,
+            share: share,
+        }))
+    }
+
+    /// Sorts rows by the columns of `ORDER BY`, see `sort`. Large results
+    /// are sorted on disk.
+    fn sort_rows(
+        &self,
+        rows: Rows<Cursor<Vec<u8>>>,
+        infos: (
             &HashMap<String, String>,
             &HashMap<String, String>,
             &HashMap<String, HashMap<String, usize>>,
         ),
-        conditions: &Conditions,
-        negate: bool,
-        wheretype: Where,
+        order: &[Sort],
     ) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
-        match conditions {
-            &Conditions::And(ref c1, ref c2) => {
-                if wheretype == Where::Select {
-                    let leftside =
-                        try!(self.execute_where(tableset, infos, c1, false, wheretype.clone()));
-                    self.execute_where(leftside, infos, c2, false, wheretype)
+        let mut keys = Vec::new();
+        for sort in order {
+            keys.push(SortKey {
+                column: try!(resolve_column(infos, &sort.alias, &sort.col)),
+                descending: sort.order == Some(Order::Desc),
+            });
+        }
+        sort::sort(rows, &keys, spill::memory_budget(), || {
+            self.check_cancelled()
+        })
+    }
+
+    /// Returns the view of the given name in the current database, if there
+    /// is one.
+    fn view(&self, name: &str) -> Result<Option<View>, ExecutionError> {
+        let base = try!(self.get_own_database());
+        Ok(try!(base.load_meta_data()).view(name).cloned())
+    }
+
+    /// Returns the rows of a table or view a select reads. A view is
+    /// expanded into its select, which is executed first; the columns it
+    /// returns are named without their tables, like those of a table.
+    fn select_rows(&mut self, name: &str) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
+        let view = match Catalog::from_name(name) {
+            Some(_) => None,
+            None => try!(self.view(name)),
+        };
+        let view = match view {
+            Some(view) => view,
+            None => return self.get_rows(name),
+        };
+        let stmt = try!(self.view_select(&view));
+        let prefixes: Vec<String> = stmt
+            .tid
+            .iter()
+            .chain(stmt.joins.iter().map(|j| &j.tid))
+            .chain(stmt.alias.keys())
+            .map(|table| format!("{}.", table))
+            .collect();
+        self.views.push(view.name);
+        let res = self.execute_select_stmt(stmt);
+        self.views.pop();
+        let mut rows = try!(res);
+        for column in &mut rows.columns {
+            let table = prefixes.iter().find(|p| column.name.starts_with(&p[..]));
+            if let Some(prefix) = table {
+                column.name = column.name[prefix.len()..].to_string();
+            }
+        }
+        try!(rows.reset_pos());
+        Ok(rows)
+    }
+
+    /// Returns the select of a view, which must not be expanded already.
+    fn view_select(&self, view: &View) -> Result<SelectStmt, ExecutionError> {
+        if self.views.contains(&view.name) {
+            return Err(ExecutionError::RecursiveView(view.name.clone()));
+        }
+        match try!(Parser::create(&view.definition).parse()) {
+            Query::ManipulationStmt(ManipulationStmt::Select(mut stmt)) => {
+                let base = try!(self.get_own_database());
+                let functions = try!(base.load_meta_data()).functions;
+                try!(function::expand_select(&mut stmt, &functions));
+                Ok(stmt)
+            }
+            _ => Err(ExecutionError::UnknownView(view.name.clone())),
+        }
+    }
+
+    fn get_rows(&self, table: &str) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
+        if let Some(catalog) = Catalog::from_name(table) {
+            return self.catalog_rows(catalog);
+        }
+        let engine = try!(self.get_engine(table));
+        let mut rows = try!(engine.full_scan());
+        try!(rows.reset_pos());
+        Ok(rows)
+    }
+
+    /// Returns the rows of a table of `information_schema`. Only the tables
+    /// the user may read are described, and only an administrator sees the
+    /// accounts of other users.
+    fn catalog_rows(&self, catalog: Catalog) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
+        let base = try!(self.get_own_database());
+        let mut tables = Vec::new();
+        if catalog != Catalog::Users {
+            for name in try!(base.list_tables()) {
+                if try!(auth::has_privilege(
+                    self.user,
+                    base,
+                    Some(&name),
+                    Privilege::Select
+                )) {
+                    tables.push(try!(base.load_table(&name)));
+                }
+            }
+        }
+        let users = if self.user.is_admin() {
+            try!(auth::list_users())
+        } else {
+            vec![self.user._name.clone()]
+        };
+        let mut rows = try!(catalog.rows(&tables, &users));
+        try!(rows.reset_pos());
+        Ok(rows)
+    }
+
+    fn merge_rows(
+        &self,
+        mut left: Rows<Cursor<Vec<u8>>>,
+        mut right: Rows<Cursor<Vec<u8>>>,
+    ) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
+        try!(right.reset_pos());
+        loop {
+            try!(self.check_cancelled());
+            try!(left.reset_pos());
+            let mut valid = true;
+
+            let mut rightrow = Vec::<u8>::new();
+            let outerres = right.next_row(&mut rightrow);
+            match outerres {
+                Ok(_) => (),
+                Err(_) => break,
+            }
+            loop {
+                let mut leftrow = Vec::<u8>::new();
+                let outerres = left.next_row(&mut leftrow);
+                match outerres {
+                    Ok(_) => (),
+                    Err(_) => break,
+                }
+                let mut primarykeys = 0;
+                let mut equalprimarykeys = 0;
+                for index in 0..left.columns.len() {
+                    if left.columns[index].is_primary_key {
+                        primarykeys += 1;
+                        let leftval = try!(left.get_value(&leftrow, index));
+                        let rightval = try!(right.get_value(&rightrow, index));
+                        if leftval == rightval {
+                            equalprimarykeys += 1;
+                        }
+                    }
+                }
+                if primarykeys == equalprimarykeys {
+                    valid = false;
+                }
+            }
+            if valid {
+                left.add_row(&rightrow);
+            }
+        }
+
+        Ok(left)
+    }
+
+    /// Joins two sets of rows: each row of `left` is combined with every row
+    /// of `right` for which the condition is true. Equalities of a column of
+    /// either side make up the key of a hash join or a sort-merge join, see
+    /// `join::choose`. If `order` is the index of a column of the result,
+    /// returns whether the rows are ordered by it.
+    fn join_rows(
+        &self,
+        left: Rows<Cursor<Vec<u8>>>,
+        right: Rows<Cursor<Vec<u8>>>,
+        infos: (
+            &HashMap<String, String>,
+            &HashMap<String, String>,
+            &HashMap<String, HashMap<String, usize>>,
+        ),
+        condition: &Conditions,
+        order: Option<usize>,
+    ) -> Result<(Rows<Cursor<Vec<u8>>>, bool), ExecutionError> {
+        let position = |alias: &Option<String>, name: &String| resolve_column(infos, alias, name);
+        let (left_columns, right_columns) = (left.columns.clone(), right.columns.clone());
+        let mut columns = left_columns.clone();
+        columns.extend(right_columns.iter().cloned());
+        let split = left_columns.len();
+        let keys = equi_keys(&columns, split, condition, &position);
+
+        let left_rows = try!(read_rows(left));
+        let right_rows = try!(read_rows(right));
+        let ordered_by_key = match (order, keys.first()) {
+            (Some(i), Some(&(l, r))) => i == l || i == split + r,
+            _ => false,
+        };
+        let method = join::choose(
+            left_rows.len(),
+            right_rows.len(),
+            !keys.is_empty(),
+            ordered_by_key,
+        );
+        info!(
+            "joining {} with {} rows by {:?}",
+            left_rows.len(),
+            right_rows.len(),
+            method
+        );
+
+        let mut rows = Rows::new(Cursor::new(Vec::<u8>::new()), &columns);
+        {
+            // the whole condition is checked for the pairs of equal keys too
+            let mut emit = |leftrow: &[u8], rightrow: &[u8]| -> Result<(), ExecutionError> {
+                try!(self.check_cancelled());
+                let mut values = row_values(&left_columns, leftrow);
+                values.extend(row_values(&right_columns, rightrow));
+                let row = try!(types::encode_row(&columns, &values));
+                if try!(evaluate_condition(&columns, &row, condition, &position)) == Some(true) {
+                    try!(rows.add_row(&row));
+                }
+                Ok(())
+            };
+            let input = |columns, rows, keys: Vec<usize>| join::Input {
+                columns: columns,
+                rows: rows,
+                keys: keys,
+            };
+            let (left_keys, right_keys) = keys.iter().cloned().unzip();
+            match method {
+                join::Method::NestedLoop => {
+                    for leftrow in &left_rows {
+                        for rightrow in &right_rows {
+                            try!(emit(leftrow, rightrow));
+                        }
+                    }
+                }
+                join::Method::Hash => try!(join::hash_join(
+                    input(&left_columns, left_rows, left_keys),
+                    input(&right_columns, right_rows, right_keys),
+                    spill::memory_budget(),
+                    emit
+                )),
+                join::Method::SortMerge => try!(join::merge_join(
+                    input(&left_columns, left_rows, left_keys),
+                    input(&right_columns, right_rows, right_keys),
+                    emit
+                )),
+            }
+        }
+        Ok((rows, method == join::Method::SortMerge && ordered_by_key))
+    }
+
+    /// Appends a column for every expression to the rows, holding its value
+    /// for the row. The type of a column is that of the values: strings
+    /// become VARCHAR as long as the longest one, numbers with a point
+    /// DECIMAL with the largest scale.
+    fn compute_expressions(
+        &mut self,
+        mut rows: Rows<Cursor<Vec<u8>>>,
+        infos: (
+            &HashMap<String, String>,
+            &HashMap<String, String>,
+            &HashMap<String, HashMap<String, usize>>,
+        ),
+        expressions: &[Lit],
+    ) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
+        let mut columns = rows.columns.clone();
+        let mut computed = Vec::new();
+        let mut known = HashMap::new();
+        try!(rows.reset_pos());
+        loop {
+            try!(self.check_cancelled());
+            let mut row = Vec::new();
+            match rows.next_row(&mut row) {
+                Ok(_) => (),
+                Err(storage::Error::EndOfFile) => break,
+                Err(e) => return Err(e.into()),
+            }
+            let mut values = Vec::new();
+            for lit in expressions {
+                // correlated subqueries are executed for every row
+                let bound = if lit.has_subquery() {
+                    let mut bound = lit.clone();
+                    let subqueries = literal_subqueries(&mut bound);
+                    try!(self.correlate(subqueries, &rows.columns, &row, infos, &mut known));
+                    try!(self.materialize(&mut bound));
+                    Some(bound)
                 } else {
-                    // IMPLEMENT!!! Needs a custom merge function
-                    let mut rightresult = try!(self.execute_where(
-                        try!(tableset.full_scan()),
-                        infos,
-                        c1,
-                        true,
-                        Where::Select
-                    ));
-                    try!(self.execute_where(tableset, infos, c2, false, wheretype.clone()));
-                    let mut engine = try!(self.get_engine(&wheretype.unwrap()));
-                    try!(rightresult.reset_pos());
-                    loop {
-                        let mut rightrow = Vec::<u8>::new();
-                        let outerres = rightresult.next_row(&mut rightrow);
-                        match outerres {
-                            Ok(_) => (),
-                            Err(_) => break,
+                    None
+                };
+                let value = |alias: &Option<String>, name: &String| {
+                    let index = try!(resolve_column(infos, alias, name));
+                    column_value(&rows.columns, &row, index)
+                };
+                values.push(try!(evaluate_with(bound.as_ref().unwrap_or(lit), &value)));
+            }
+            computed.push((row, values));
+        }
+
+        let mut types = Vec::new();
+        for (i, lit) in expressions.iter().enumerate() {
+            let sql_type = try!(expression_type(computed.iter().map(|r| &r.1[i])));
+            columns.push(Column::new(&dump::literal(lit), sql_type, true, "", false));
+            types.push(sql_type);
+        }
+        let mut result = Rows::new(Cursor::new(Vec::new()), &columns);
+        for (row, values) in computed {
+            let mut all = row_values(&rows.columns, &row);
+            for (value, sql_type) in values.iter().zip(&types) {
+                all.push(match *value {
+                    Lit::Null => None,
+                    ref value => {
+                        let mut data = Vec::new();
+                        try!(sql_type.encode_into(&mut data, value));
+                        Some(data)
+                    }
+                });
+            }
+            try!(result.add_values(&all));
+        }
+        Ok(result)
+    }
+
+    /// Appends a column with the values of every window function to the
+    /// rows, see `window`. The arguments that are no columns were computed
+    /// as the columns from `arguments` on, see `window_argument`.
+    fn compute_windows(
+        &self,
+        mut rows: Rows<Cursor<Vec<u8>>>,
+        infos: (
+            &HashMap<String, String>,
+            &HashMap<String, String>,
+            &HashMap<String, HashMap<String, usize>>,
+        ),
+        windows: &[Window],
+        mut arguments: usize,
+    ) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
+        for spec in windows {
+            let mut argument = |lit: &Lit| match *lit {
+                Lit::Column(ref alias, ref name) => resolve_column(infos, alias, name),
+                _ => {
+                    arguments += 1;
+                    Ok(arguments - 1)
+                }
+            };
+            let function = match spec.function {
+                WindowFunction::RowNumber => window::Function::RowNumber,
+                WindowFunction::Rank => window::Function::Rank,
+                WindowFunction::Sum(ref lit) => window::Function::Sum(try!(argument(lit))),
+                WindowFunction::Avg(ref lit) => window::Function::Avg(try!(argument(lit))),
+            };
+            let mut partition = Vec::new();
+            for sort in &spec.partition {
+                partition.push(try!(resolve_column(infos, &sort.alias, &sort.col)));
+            }
+            let mut order = Vec::new();
+            for sort in &spec.order {
+                order.push(SortKey {
+                    column: try!(resolve_column(infos, &sort.alias, &sort.col)),
+                    descending: sort.order == Some(Order::Desc),
+                });
+            }
+            let window = window::Window {
+                function: function,
+                partition: partition,
+                order: order,
+            };
+            let name = dump::literal(&spec.function.call());
+            rows = try!(window::apply(
+                rows,
+                &window,
+                &name,
+                |a, b| calculate("+", a, b),
+                || self.check_cancelled()
+            ));
+        }
+        Ok(rows)
+    }
+
+    fn cross_rows(
+        &self,
+        mut left: Rows<Cursor<Vec<u8>>>,
+        mut right: Rows<Cursor<Vec<u8>>>,
+    ) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
+        try!(left.reset_pos());
+        try!(right.reset_pos());
+        let mut columnvec = left.columns.clone();
+        let mut appendvec = right.columns.clone();
+
+        for _i in 0..appendvec.len() {
+            let _appendlength = appendvec.len();
+            columnvec.push(appendvec.remove(0));
+        }
+        let cursor = Cursor::new(Vec::<u8>::new());
+        let mut rows = Rows::<Cursor<Vec<u8>>>::new(cursor, &columnvec);
+
+        loop {
+            try!(self.next_row());
+            let mut insertingrow = Vec::<u8>::new();
+            let outerres = left.next_row(&mut insertingrow);
+
+            match outerres {
+                Ok(_) => (),
+                Err(_) => break,
+            }
+            loop {
+                let mut rightrow = Vec::<u8>::new();
+                let innerres = right.next_row(&mut rightrow);
+                match innerres {
+                    Ok(_) => {
+                        // both rows may have a null bitmap, the joined
+                        // row gets one covering all columns
+                        let mut values = Vec::new();
+                        for i in 0..left.columns.len() {
+                            values.push(try!(left.get_nullable_value(&insertingrow, i)));
                         }
-                        engine.insert_row(&rightrow);
+                        for i in 0..right.columns.len() {
+                            values.push(try!(right.get_nullable_value(&rightrow, i)));
+                        }
+                        try!(rows.add_values(&values));
+                    }
+                    Err(_) => {
+                        right.reset_pos();
+                        break;
                     }
+                }
+            }
+        }
 
-                    Ok(rightresult)
+        Ok(rows)
+    }
+}
+
+/// Replaces a function call by its result, other literals stay as they are.
+fn evaluate(lit: &Lit) -> Result<Lit, ExecutionError> {
+    evaluate_with(lit, &|_: &Option<String>, _: &String| {
+        Err(ExecutionError::UnknownColumn)
+    })
+}
+
+/// Evaluates an expression like `evaluate`, `column` returns the value of a
+/// column of the row from its optional table alias and name. Functions of
+/// null values are null.
+fn evaluate_with<F>(lit: &Lit, column: &F) -> Result<Lit, ExecutionError>
+where
+    F: Fn(&Option<String>, &String) -> Result<Lit, ExecutionError>,
+{
+    let (name, args) = match lit {
+        &Lit::Call(ref name, ref args) => (name, args),
+        &Lit::Column(ref alias, ref name) => return column(alias, name),
+        // subqueries are replaced by their results before execution
+        &Lit::Subquery(_) => return Err(ExecutionError::InvalidSubquery),
+        _ => return Ok(lit.clone()),
+    };
+    let mut values = Vec::new();
+    for arg in args {
+        values.push(try!(evaluate_with(arg, column)));
+    }
+    if values.iter().any(|v| *v == Lit::Null) {
+        return Ok(Lit::Null);
+    }
+    let now = temporal::now();
+    let result = match (&name[..], &values[..]) {
+        ("now", &[]) | ("current_timestamp", &[]) => Lit::Timestamp(now),
+        ("current_date", &[]) | ("curdate", &[]) => Lit::Date(temporal::split_timestamp(now).0),
+        ("current_time", &[]) | ("curtime", &[]) => Lit::Time(temporal::split_timestamp(now).1),
+        ("datediff", &[ref a, ref b]) => match (days(a), days(b)) {
+            (Some(a), Some(b)) => Lit::Int(a as i64 - b as i64),
+            _ => return Err(ExecutionError::CompareDatatypeMissmatch),
+        },
+        (op, &[ref a, ref b]) if op.len() == 1 && "+-*/%".contains(op) => {
+            try!(calculate(op, a, b))
+        }
+        ("||", _) | ("concat", _) => {
+            let mut s = String::new();
+            for value in &values {
+                s.push_str(&try!(
+                    to_text(value).ok_or(ExecutionError::CompareDatatypeMissmatch)
+                ));
+            }
+            Lit::String(s)
+        }
+        ("upper", &[Lit::String(ref s)]) => Lit::String(s.to_uppercase()),
+        ("lower", &[Lit::String(ref s)]) => Lit::String(s.to_lowercase()),
+        ("length", &[Lit::String(ref s)]) => Lit::Int(s.chars().count() as i64),
+        ("substr", &[Lit::String(ref s), Lit::Int(start)])
+        | ("substring", &[Lit::String(ref s), Lit::Int(start)]) => {
+            Lit::String(substr(s, start, None))
+        }
+        ("substr", &[Lit::String(ref s), Lit::Int(start), Lit::Int(len)])
+        | ("substring", &[Lit::String(ref s), Lit::Int(start), Lit::Int(len)]) => {
+            Lit::String(substr(s, start, Some(len)))
+        }
+        ("abs", &[Lit::Int(i)]) => {
+            Lit::Int(try!(i.checked_abs().ok_or(ExecutionError::NumericOverflow)))
+        }
+        ("abs", &[Lit::Decimal(d, scale)]) => Lit::Decimal(
+            try!(d.checked_abs().ok_or(ExecutionError::NumericOverflow)),
+            scale,
+        ),
+        ("abs", &[Lit::Float(f)]) => Lit::Float(f.abs()),
+        ("round", &[ref x]) => try!(round(x, 0)),
+        ("round", &[ref x, Lit::Int(digits)]) => try!(round(x, digits)),
+        ("upper", _)
+        | ("lower", _)
+        | ("length", _)
+        | ("substr", _)
+        | ("substring", _)
+        | ("abs", _)
+        | ("round", _) => return Err(ExecutionError::CompareDatatypeMissmatch),
+        _ => return Err(ExecutionError::UnknownFunction(name.clone())),
+    };
+    Ok(result)
+}
+
+/// Returns the type of a column holding the values of an expression, see
+/// `compute_expressions`. Integers and numbers with a point may mix.
+fn expression_type<'a, I>(values: I) -> Result<SqlType, ExecutionError>
+where
+    I: Iterator<Item = &'a Lit>,
+{
+    let mut result: Option<SqlType> = None;
+    for value in values {
+        let sql_type = match value {
+            &Lit::Null => continue,
+            &Lit::Int(_) => SqlType::BigInt,
+            &Lit::Float(_) => SqlType::Double,
+            &Lit::Decimal(_, scale) => SqlType::Decimal(decimal::MAX_PRECISION, scale),
+            &Lit::Bool(_) => SqlType::Bool,
+            &Lit::String(ref s) => SqlType::VarChar(s.len().min(u16::max_value() as usize) as u16),
+            &Lit::Bytes(ref b) => SqlType::VarBinary(b.len() as u32),
+            &Lit::Date(_) => SqlType::Date,
+            &Lit::Time(_) => SqlType::Time,
+            &Lit::Timestamp(_) => SqlType::Timestamp,
+            &Lit::Call(..) | &Lit::Column(..) | &Lit::Subquery(_) => {
+                return Err(ExecutionError::UnknownColumn)
+            }
+        };
+        result = Some(match (result, sql_type) {
+            (None, t) => t,
+            (Some(SqlType::VarChar(a)), SqlType::VarChar(b)) => SqlType::VarChar(a.max(b)),
+            (Some(SqlType::VarBinary(a)), SqlType::VarBinary(b)) => SqlType::VarBinary(a.max(b)),
+            (Some(SqlType::Double), SqlType::BigInt)
+            | (Some(SqlType::Double), SqlType::Decimal(..))
+            | (Some(SqlType::BigInt), SqlType::Double)
+            | (Some(SqlType::Decimal(..)), SqlType::Double) => SqlType::Double,
+            (Some(SqlType::Decimal(p, a)), SqlType::Decimal(_, b)) => SqlType::Decimal(p, a.max(b)),
+            (Some(SqlType::Decimal(p, a)), SqlType::BigInt)
+            | (Some(SqlType::BigInt), SqlType::Decimal(p, a)) => SqlType::Decimal(p, a),
+            (Some(a), b) => {
+                if a != b {
+                    return Err(ExecutionError::CompareDatatypeMissmatch);
                 }
+                a
             }
+        });
+    }
+    // an expression that is always null
+    Ok(result.unwrap_or(SqlType::VarChar(0)))
+}
 
-            &Conditions::Or(ref c1, ref c2) => {
-                // When changing to the EFFECTIVE PLAN:
-                // REMEMBER CHANGING HERE TOO! (TODO)
-                if wheretype == Where::Select {
-                    let tableset2 = try!(tableset.full_scan());
-                    let leftside =
-                        try!(self.execute_where(tableset, infos, c1, false, wheretype.clone()));
-                    let rightside =
-                        try!(self.execute_where(tableset2, infos, c2, false, wheretype));
-                    self.merge_rows(leftside, rightside)
-                } else {
-                    try!(self.execute_where(
-                        try!(tableset.full_scan()),
-                        infos,
-                        c1,
-                        false,
-                        wheretype.clone()
-                    ));
-                    self.execute_where(tableset, infos, c2, false, wheretype)
+/// Returns a string or a number as text, for concatenation.
+fn to_text(lit: &Lit) -> Option<String> {
+    match lit {
+        &Lit::String(ref s) => Some(s.clone()),
+        &Lit::Int(i) => Some(i.to_string()),
+        &Lit::Decimal(d, scale) => Some(decimal::format(d, scale)),
+        &Lit::Float(f) => Some(f.to_string()),
+        _ => None,
+    }
+}
+
+/// Returns `len` characters of a string from the position `start`, the
+/// first character is at position 1. Positions before it count, too.
+fn substr(s: &str, start: i64, len: Option<i64>) -> String {
+    let end = len.map(|len| start.saturating_add(len.max(0)));
+    let start = start.max(1);
+    let count = end.map_or(i64::max_value(), |end| end - start).max(0);
+    s.chars()
+        .skip((start - 1) as usize)
+        .take(count as usize)
+        .collect()
+}
+
+/// Rounds a number to `digits` digits after the point, halves away from
+/// zero. Negative digits round to tens, hundreds and so on.
+fn round(x: &Lit, digits: i64) -> Result<Lit, ExecutionError> {
+    let digits = digits.max(-18).min(18);
+    if let &Lit::Float(f) = x {
+        let factor = 10f64.powi(digits as i32);
+        return Ok(Lit::Float((f * factor).round() / factor));
+    }
+    let (value, scale) = match to_decimal(x) {
+        Some(value) => value,
+        None => return Err(ExecutionError::CompareDatatypeMissmatch),
+    };
+    if digits >= scale as i64 {
+        return Ok(x.clone());
+    }
+    let divisor = 10i64.pow((scale as i64 - digits) as u32);
+    let mut rounded = value / divisor;
+    if (value % divisor).abs() * 2 >= divisor {
+        rounded += value.signum();
+    }
+    let result = if digits < 0 {
+        rounded.checked_mul(10i64.pow(-digits as u32))
+    } else {
+        Some(rounded)
+    };
+    let result = try!(result.ok_or(ExecutionError::NumericOverflow));
+    Ok(match x {
+        &Lit::Int(_) => Lit::Int(result),
+        _ => Lit::Decimal(result, digits.max(0) as u8),
+    })
+}
+
+/// Compares two values of an expression, None if either is null.
+fn compare_lits(a: &Lit, b: &Lit) -> Result<Option<Ordering>, ExecutionError> {
+    let ordering = match (a, b) {
+        (&Lit::Null, _) | (_, &Lit::Null) => return Ok(None),
+        (&Lit::Float(_), _) | (_, &Lit::Float(_)) => match (to_f64(a), to_f64(b)) {
+            (Some(x), Some(y)) => x.partial_cmp(&y),
+            _ => None,
+        },
+        (&Lit::String(ref x), &Lit::String(ref y)) => Some(x.cmp(y)),
+        (&Lit::Bool(x), &Lit::Bool(y)) => Some((x != 0).cmp(&(y != 0))),
+        (&Lit::Bytes(ref x), &Lit::Bytes(ref y)) => Some(x.cmp(y)),
+        (&Lit::Time(x), &Lit::Time(y)) => Some(x.cmp(&y)),
+        (&Lit::Date(_), _) | (&Lit::Timestamp(_), _) => match (microseconds(a), microseconds(b)) {
+            (Some(x), Some(y)) => Some(x.cmp(&y)),
+            _ => None,
+        },
+        _ => match (to_decimal(a), to_decimal(b)) {
+            (Some(x), Some(y)) => {
+                let scale = x.1.max(y.1);
+                match (
+                    decimal::rescale(x.0, x.1, scale),
+                    decimal::rescale(y.0, y.1, scale),
+                ) {
+                    (Some(x), Some(y)) => Some(x.cmp(&y)),
+                    // a value too large to rescale is larger than the other
+                    _ => Some(to_f64(a).partial_cmp(&to_f64(b)).unwrap_or(Ordering::Equal)),
                 }
             }
+            _ => None,
+        },
+    };
+    match ordering {
+        Some(ordering) => Ok(Some(ordering)),
+        None => Err(ExecutionError::CompareDatatypeMissmatch),
+    }
+}
 
-            // TODO: SO MUCH REDUNDANT CODE!!!!!!!11111
-            //       remove whenever there is time.
-            &Conditions::Leaf(ref c) => {
-                let tablename = if c.aliascol.is_some() {
-                    match infos.0.get(&c.clone().aliascol.unwrap()) {
-                        Some(x) => x,
-                        None => return Err(ExecutionError::UnknownAlias),
-                    }
-                } else {
-                    match infos.1.get(&c.col) {
-                        Some(x) => x,
-                        None => return Err(ExecutionError::UnknownColumn),
-                    }
-                };
-                let columntoindex = infos.2.get(tablename).unwrap();
-                let column = columntoindex.get(&c.col);
-                if column.is_none() {
-                    return Err(ExecutionError::UnknownColumn);
-                }
-                let index = column.unwrap().clone();
+/// Returns the microseconds since 1970-01-01 of a DATE or a TIMESTAMP.
+fn microseconds(lit: &Lit) -> Option<i64> {
+    match lit {
+        &Lit::Date(d) => Some(temporal::date_to_timestamp(d)),
+        &Lit::Timestamp(t) => Some(t),
+        _ => None,
+    }
+}
 
-                match c.rhs {
-                    CondType::Word(ref column) => {
-                        let tablename2 = if c.aliasrhs.is_some() {
-                            match infos.0.get(&c.clone().aliasrhs.unwrap()) {
-                                Some(x) => x,
-                                None => return Err(ExecutionError::UnknownAlias),
-                            }
-                        } else {
-                            match infos.1.get(column) {
-                                Some(x) => x,
-                                None => return Err(ExecutionError::UnknownColumn),
-                            }
-                        };
-                        let columntoindex2 = infos.2.get(tablename2).unwrap();
-                        let column2 = columntoindex2.get(column);
-                        if column2.is_none() {
-                            return Err(ExecutionError::UnknownColumn);
-                        }
-                        let index2 = column2.unwrap().clone();
-                        let operator = if negate { c.op.negate() } else { c.op };
-                        if tableset.columns[index].sql_type != tableset.columns[index2].sql_type {
-                            return Err(ExecutionError::CompareDatatypeMissmatch);
-                        }
-                        if wheretype == Where::Select {
-                            Ok(try!(tableset.lookup(
-                                index,
-                                (&Vec::<u8>::new(), Some(index2)),
-                                operator
-                            )))
-                        } else {
-                            let engine = try!(self.get_engine(&wheretype.unwrap()));
-                            try!(engine.delete(index, (&Vec::<u8>::new(), Some(index2)), operator));
-                            Ok(generate_rows_dummy())
-                        }
-                    }
+/// Returns the value of a column of a row for an expression.
+fn column_value(columns: &[Column], row: &[u8], i: usize) -> Result<Lit, ExecutionError> {
+    if types::is_null(columns, row, i) {
+        return Ok(Lit::Null);
+    }
+    let sql_type = &columns[i].sql_type;
+    let value = &row[types::column_range(columns, i)];
+    if sql_type.is_text() {
+        let text = String::from_utf8_lossy(sql_type.content(value)).into_owned();
+        return Ok(Lit::String(text));
+    }
+    // BLOB values are not in the row
+    Ok(try!(sql_type.decode_from(&mut &value[..])))
+}
 
-                    CondType::Literal(ref lit) => {
-                        // Error handling: if wrong compare type is giving => Missmatch error
-                        match tableset.columns[index].sql_type {
-                            SqlType::Char(_c) => {
-                                if lit.sqltype() != SqlType::Char(0) {
-                                    return Err(ExecutionError::CompareDatatypeMissmatch);
-                                }
-                            }
-                            _ => {
-                                if tableset.columns[index].sql_type.clone() != lit.sqltype() {
-                                    return Err(ExecutionError::CompareDatatypeMissmatch);
-                                }
-                            }
-                        }
-                        // TODO: use get_column methods!!
-                        let mut comparedata = Vec::<u8>::new();
-                        try!(tableset.columns[index]
-                            .sql_type
-                            .encode_into(&mut comparedata, lit));
-                        let operator = if negate { c.op.negate() } else { c.op };
-                        if wheretype == Where::Select {
-                            Ok(try!(tableset.lookup(index, (&comparedata, None), operator)))
-                        } else {
-                            let engine = try!(self.get_engine(&wheretype.unwrap()));
-                            engine.delete(index, (&comparedata, None), operator);
-                            Ok(generate_rows_dummy())
-                        }
-                    }
-                }
+/// Applies an arithmetic operator to two numbers. Integers stay integers,
+/// decimals are calculated exactly and only floats make the result a float.
+fn calculate(op: &str, a: &Lit, b: &Lit) -> Result<Lit, ExecutionError> {
+    let result = match (a, b) {
+        (&Lit::Int(x), &Lit::Int(y)) => {
+            let result = match op {
+                "+" => x.checked_add(y),
+                "-" => x.checked_sub(y),
+                "*" => x.checked_mul(y),
+                _ if y == 0 => return Err(ExecutionError::DivisionByZero),
+                "/" => x.checked_div(y),
+                _ => x.checked_rem(y),
+            };
+            result.map(Lit::Int)
+        }
+        (&Lit::Float(_), _) | (_, &Lit::Float(_)) => {
+            let (x, y) = match (to_f64(a), to_f64(b)) {
+                (Some(x), Some(y)) => (x, y),
+                _ => return Err(ExecutionError::CompareDatatypeMissmatch),
+            };
+            match op {
+                "+" => Some(Lit::Float(x + y)),
+                "-" => Some(Lit::Float(x - y)),
+                "*" => Some(Lit::Float(x * y)),
+                _ if y == 0.0 => return Err(ExecutionError::DivisionByZero),
+                "/" => Some(Lit::Float(x / y)),
+                _ => Some(Lit::Float(x % y)),
             }
         }
+        _ => {
+            let (x, y) = match (to_decimal(a), to_decimal(b)) {
+                (Some(x), Some(y)) => (x, y),
+                _ => return Err(ExecutionError::CompareDatatypeMissmatch),
+            };
+            let result = match op {
+                "+" => decimal::add(x, y),
+                "-" => decimal::sub(x, y),
+                "*" => decimal::mul(x, y),
+                _ if y.0 == 0 => return Err(ExecutionError::DivisionByZero),
+                "/" => decimal::div(x, y),
+                _ => decimal::rem(x, y),
+            };
+            result.map(|(d, scale)| Lit::Decimal(d, scale))
+        }
+    };
+    result.ok_or(ExecutionError::NumericOverflow)
+}
+
+fn to_decimal(lit: &Lit) -> Option<(i64, u8)> {
+    match lit {
+        &Lit::Int(i) => Some((i, 0)),
+        &Lit::Decimal(d, scale) => Some((d, scale)),
+        _ => None,
     }
+}
 
-    fn execute_delete_stmt(
-        &mut self,
-        mut query: DeleteStmt,
-    ) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
-        let table = try!(self.get_rows(&query.tid));
-        let mut name_column_map = HashMap::<String, HashMap<String, usize>>::new();
-        let mut column_index_map = HashMap::<String, usize>::new();
-        let mut column_tablename_map = HashMap::<String, String>::new();
-        let mut columnindex: usize = 0;
-        for column in table.columns.clone() {
-            column_tablename_map.insert(column.name.clone(), query.tid.clone());
-            column_index_map.insert(column.name.into(), columnindex);
-            columnindex += 1;
+fn to_f64(lit: &Lit) -> Option<f64> {
+    match lit {
+        &Lit::Int(i) => Some(i as f64),
+        &Lit::Decimal(d, scale) => Some(decimal::to_f64(d, scale)),
+        &Lit::Float(f) => Some(f),
+        _ => None,
+    }
+}
+
+/// Checks that the default of a column fits into it. Defaults calling a
+/// function like NOW() are checked with the current result.
+fn check_default(column: &Column) -> Result<(), ExecutionError> {
+    let value = match column.default {
+        Some(ref lit) => try!(evaluate(lit)),
+        None => return Ok(()),
+    };
+    match value {
+        Lit::Null if !column.allow_null => {
+            Err(ExecutionError::NotNullViolation(column.name.clone()))
+        }
+        Lit::Null => Ok(()),
+        // long blobs are only stored on insert
+        Lit::Bytes(_) | Lit::String(_) if column.sql_type == SqlType::Blob => Ok(()),
+        _ => {
+            try!(column.sql_type.encode_into(&mut Vec::new(), &value));
+            Ok(())
         }
+    }
+}
 
-        name_column_map.insert(query.tid.clone(), column_index_map);
-        query.alias.insert(query.tid.clone(), query.tid.clone());
+/// Checks a check constraint against the columns of a table and adds it
+/// under the first free one of the names `name`, `name1`, `name2`, ...
+fn add_check(table: &mut Table, name: &str, condition: Conditions) -> Result<(), ExecutionError> {
+    // unknown columns and literals not fitting them show up on any row
+    let row = vec![0; types::row_size(table.columns())];
+    try!(check_condition(table.columns(), &row, &condition));
+    let mut free = name.to_string();
+    let mut n = 0;
+    while table.checks().iter().any(|c| c.name == free) {
+        n += 1;
+        free = format!("{}{}", name, n);
+    }
+    try!(table.add_check(Check {
+        name: free,
+        condition: condition,
+    }));
+    Ok(())
+}
 
-        if query.cond.is_some() {
-            try!(self.execute_where(
-                table,
-                (&query.alias, &column_tablename_map, &name_column_map),
-                &query.cond.unwrap(),
-                false,
-                Where::Delete(query.tid)
-            ));
-        } else {
-            let mut engine = try!(self.get_engine(&query.tid));
-            // Todo: Storage: enable full table reset!!
-            try!(engine.reset());
+/// Returns the index of a column of joined tables from its optional table
+/// alias and its name.
+fn resolve_column(
+    infos: (
+        &HashMap<String, String>,
+        &HashMap<String, String>,
+        &HashMap<String, HashMap<String, usize>>,
+    ),
+    alias: &Option<String>,
+    name: &String,
+) -> Result<usize, ExecutionError> {
+    let (alias_map, column_tablename_map, name_column_map) = infos;
+    let table = match alias {
+        &Some(ref alias) => alias_map.get(alias),
+        &None => column_tablename_map.get(name),
+    };
+    match table
+        .and_then(|t| name_column_map.get(t))
+        .and_then(|c| c.get(name))
+    {
+        Some(&index) => Ok(index),
+        None => Err(ExecutionError::UnknownColumn),
+    }
+}
+
+/// Returns the index of a column of a set operation's result to sort by.
+/// Result columns are named like `table.col` or by their rename; a name
+/// without table matches either if no other column does.
+fn result_column(columns: &[Column], sort: &Sort) -> Result<usize, ExecutionError> {
+    let name = match sort.alias {
+        Some(ref alias) => format!("{}.{}", alias, sort.col),
+        None => sort.col.clone(),
+    };
+    if let Some(index) = columns.iter().position(|c| c.name == name) {
+        return Ok(index);
+    }
+    let suffix = format!(".{}", name);
+    let mut matching = columns
+        .iter()
+        .enumerate()
+        .filter(|&(_, c)| c.name.ends_with(&suffix));
+    match (matching.next(), matching.next()) {
+        (Some((index, _)), None) => Ok(index),
+        _ => Err(ExecutionError::UnknownColumn),
+    }
+}
+
+/// Returns the pairs of columns, one of each side of a join, that the
+/// condition requires to be equal. The columns of the right side start at
+/// `split`, their indexes are returned relative to it.
+fn equi_keys<F>(
+    columns: &[Column],
+    split: usize,
+    condition: &Conditions,
+    position: &F,
+) -> Vec<(usize, usize)>
+where
+    F: Fn(&Option<String>, &String) -> Result<usize, ExecutionError>,
+{
+    let c = match condition {
+        &Conditions::And(ref a, ref b) => {
+            let mut keys = equi_keys(columns, split, a, position);
+            keys.extend(equi_keys(columns, split, b, position));
+            return keys;
         }
+        &Conditions::Or(..)
+        | &Conditions::Expr(..)
+        | &Conditions::In(..)
+        | &Conditions::Exists(..) => return Vec::new(),
+        &Conditions::Leaf(ref c) => c,
+    };
+    let other = match (c.op, &c.rhs) {
+        (CompType::Equ, &CondType::Word(ref other)) => other,
+        _ => return Vec::new(),
+    };
+    let (a, b) = match (position(&c.aliascol, &c.col), position(&c.aliasrhs, other)) {
+        (Ok(a), Ok(b)) => (a.min(b), a.max(b)),
+        _ => return Vec::new(),
+    };
+    if a >= split || b < split || columns[a].sql_type != columns[b].sql_type {
+        return Vec::new();
+    }
+    // BLOB values are not in the row
+    if columns[a].sql_type == SqlType::Blob {
+        return Vec::new();
+    }
+    vec![(a, b - split)]
+}
 
-        Ok(generate_rows_dummy())
+/// Returns the estimated cost of reading a share of the rows of a table
+/// through an index, see `planner`.
+fn lookup_cost(rows: Option<u64>, share: Option<f64>) -> Option<f64> {
+    match (rows, share) {
+        (Some(rows), Some(share)) => Some(Estimate::lookup(rows as f64, share).cost),
+        _ => None,
     }
+}
 
-    fn execute_describe_stmt(
-        &mut self,
-        query: String,
-    ) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
-        let table = try!(self.get_table(&query));
-        let columns = table.columns();
-        let mut columnvec = Vec::new();
+/// Returns a select of the rows of a single table fulfilling a condition.
+fn table_select(
+    name: &str,
+    cond: Option<Conditions>,
+    alias: &HashMap<String, String>,
+) -> SelectStmt {
+    SelectStmt {
+        target: Vec::new(),
+        tid: vec![name.to_string()],
+        joins: Vec::new(),
+        alias: alias.clone(),
+        names: vec![name.to_string()],
+        cond: cond,
+        spec_op: None,
+        order: Vec::new(),
+        limit: None,
+    }
+}
 
-        columnvec.extend(columns.iter().cloned());
-        Ok(Rows::new(Cursor::new(Vec::<u8>::new()), &columnvec))
+/// Takes the conjuncts with correlated subqueries from the condition of a
+/// select, which are the ones with subqueries left by
+/// `Executor::materialize_select`.
+fn split_correlated(stmt: &mut SelectStmt) -> Vec<Conditions> {
+    if !stmt.cond.as_ref().map_or(false, Conditions::has_subquery) {
+        return Vec::new();
     }
+    let (correlated, rest): (Vec<_>, Vec<_>) = and_parts(stmt.cond.take().unwrap())
+        .into_iter()
+        .partition(Conditions::has_subquery);
+    stmt.cond = Conditions::all(rest);
+    correlated
+}
 
-    fn execute_create_stmt(
-        &mut self,
-        query: CreateStmt,
-    ) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
-        match query {
-            CreateStmt::Database(s) => {
-                self.user._currentDatabase = Some(try!(Database::create(&s)));
-                Ok(generate_rows_dummy())
+/// Returns the subqueries of a condition, not those nested in them.
+fn condition_subqueries(cond: &mut Conditions) -> Vec<&mut SelectStmt> {
+    match *cond {
+        Conditions::And(ref mut a, ref mut b) | Conditions::Or(ref mut a, ref mut b) => {
+            let mut subqueries = condition_subqueries(a);
+            subqueries.extend(condition_subqueries(b));
+            subqueries
+        }
+        Conditions::Leaf(ref mut c) => match c.rhs {
+            CondType::Literal(ref mut lit) => literal_subqueries(lit),
+            CondType::Word(_) => Vec::new(),
+        },
+        Conditions::Expr(ref mut lhs, _, ref mut rhs) => {
+            let mut subqueries = literal_subqueries(lhs);
+            subqueries.extend(literal_subqueries(rhs));
+            subqueries
+        }
+        Conditions::In(ref mut lhs, ref mut values, _) => {
+            let mut subqueries = literal_subqueries(lhs);
+            for value in values.iter_mut() {
+                subqueries.extend(literal_subqueries(value));
             }
-            CreateStmt::Table(stmt) => self.execute_create_table_stmt(stmt),
-            _ => Err(ExecutionError::DebugError("to_do".into())),
+            subqueries
         }
+        Conditions::Exists(ref mut stmt, _) => vec![&mut **stmt],
     }
+}
 
-    fn execute_create_table_stmt(
-        &mut self,
-        query: CreateTableStmt,
-    ) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
-        let base = try!(self.get_own_database());
-        let tmp_vec: Vec<_> = query
-            .cols
-            .into_iter()
-            .map(|c| Column {
-                name: c.cid,
-                sql_type: c.datatype,
-                allow_null: false,
-                description: "this is a column".to_string(),
-                is_primary_key: c.primary,
-            })
-            .collect();
-        let table = try!(base.create_table(&query.tid, tmp_vec, EngineID::FlatFile));
-        let mut engine = table.create_engine();
-        engine.create_table();
-        Ok(generate_rows_dummy())
+/// Returns the subqueries of an expression, see `condition_subqueries`.
+fn literal_subqueries(lit: &mut Lit) -> Vec<&mut SelectStmt> {
+    match *lit {
+        Lit::Subquery(ref mut stmt) => vec![&mut **stmt],
+        Lit::Call(_, ref mut args) => args.iter_mut().flat_map(literal_subqueries).collect(),
+        _ => Vec::new(),
     }
+}
 
-    fn execute_drop_stmt(
-        &mut self,
-        query: DropStmt,
-    ) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
-        match query {
-            DropStmt::Table(s) => {
-                let base = try!(self.get_own_database());
-                let table = try!(base.load_table(&s));
-                try!(table.delete());
-                Ok(generate_rows_dummy())
+/// Returns the columns of `inner = outer`, a comparison of a column of the
+/// scope of a subquery with one of the select around it, as the inner and
+/// the outer column, see `Executor::decorrelate`.
+fn correlation(
+    cond: &Conditions,
+    inner: &Scope,
+    outer: &Scope,
+) -> Option<((Option<String>, String), (Option<String>, String))> {
+    let c = match *cond {
+        Conditions::Leaf(ref c) if c.op == CompType::Equ => c,
+        _ => return None,
+    };
+    let lhs = (c.aliascol.clone(), c.col.clone());
+    let rhs = match c.rhs {
+        CondType::Word(ref other) => (c.aliasrhs.clone(), other.clone()),
+        CondType::Literal(_) => return None,
+    };
+    let local = |column: &(Option<String>, String)| inner.contains(&column.0, &column.1);
+    let outside =
+        |column: &(Option<String>, String)| !local(column) && outer.contains(&column.0, &column.1);
+    if local(&lhs) && outside(&rhs) {
+        Some((lhs, rhs))
+    } else if outside(&lhs) && local(&rhs) {
+        Some((rhs, lhs))
+    } else {
+        None
+    }
+}
+
+/// Returns the conjuncts of a condition, unlike `Conditions::conjuncts`
+/// without distributing an OR over them.
+fn and_parts(cond: Conditions) -> Vec<Conditions> {
+    match cond {
+        Conditions::And(a, b) => {
+            let mut parts = and_parts(*a);
+            parts.extend(and_parts(*b));
+            parts
+        }
+        other => vec![other],
+    }
+}
+
+/// Returns the position of the table, among tables with their columns, a
+/// column belongs to, or None if it is not found in exactly one of them.
+fn column_table(
+    tables: &[(String, Vec<Column>)],
+    alias_map: &HashMap<String, String>,
+    alias: &Option<String>,
+    name: &str,
+) -> Option<usize> {
+    let mut found = tables
+        .iter()
+        .enumerate()
+        .filter(|&(_, &(ref table, ref columns))| {
+            let named = match alias {
+                &Some(ref alias) => alias_map.get(alias).unwrap_or(alias) == table,
+                &None => true,
+            };
+            named && columns.iter().any(|c| c.name == name)
+        })
+        .map(|(i, _)| i);
+    match (found.next(), found.next()) {
+        (Some(i), None) => Some(i),
+        _ => None,
+    }
+}
+
+/// Returns the tables whose columns a condition refers to, bit `i` standing
+/// for the table at `i`, or None if a column does not belong to exactly one
+/// of them, see `column_table`.
+fn condition_tables(
+    cond: &Conditions,
+    tables: &[(String, Vec<Column>)],
+    alias_map: &HashMap<String, String>,
+) -> Option<u32> {
+    let column = |alias: &Option<String>, name: &str| {
+        column_table(tables, alias_map, alias, name).map(|i| 1 << i)
+    };
+    let literal = |lit: &Lit| literal_tables(lit, tables, alias_map);
+    match cond {
+        &Conditions::And(ref a, ref b) | &Conditions::Or(ref a, ref b) => both(
+            condition_tables(a, tables, alias_map),
+            condition_tables(b, tables, alias_map),
+        ),
+        &Conditions::Leaf(ref c) => both(
+            column(&c.aliascol, &c.col),
+            match c.rhs {
+                CondType::Word(ref other) => column(&c.aliasrhs, other),
+                CondType::Literal(ref lit) => literal(lit),
+            },
+        ),
+        &Conditions::Expr(ref lhs, _, ref rhs) => both(literal(lhs), literal(rhs)),
+        &Conditions::In(ref lhs, ref values, _) => values
+            .iter()
+            .fold(literal(lhs), |bits, value| both(bits, literal(value))),
+        &Conditions::Exists(..) => None,
+    }
+}
+
+/// Returns the tables whose columns an expression refers to, see
+/// `condition_tables`.
+fn literal_tables(
+    lit: &Lit,
+    tables: &[(String, Vec<Column>)],
+    alias_map: &HashMap<String, String>,
+) -> Option<u32> {
+    match lit {
+        &Lit::Column(ref alias, ref name) => {
+            column_table(tables, alias_map, alias, name).map(|i| 1 << i)
+        }
+        &Lit::Call(_, ref args) => args.iter().fold(Some(0), |bits, arg| {
+            both(bits, literal_tables(arg, tables, alias_map))
+        }),
+        &Lit::Subquery(_) => None,
+        _ => Some(0),
+    }
+}
+
+/// Returns the tables of two parts of a condition, see `condition_tables`.
+fn both(a: Option<u32>, b: Option<u32>) -> Option<u32> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a | b),
+        _ => None,
+    }
+}
+
+/// Estimates the share of the rows fulfilling a condition by the statistics
+/// of their table: conjuncts and disjuncts are taken to be independent.
+fn estimate_share(stats: &stats::TableStats, columns: &[Column], cond: &Conditions) -> Option<f64> {
+    match cond {
+        &Conditions::And(ref a, ref b) => {
+            match (
+                estimate_share(stats, columns, a),
+                estimate_share(stats, columns, b),
+            ) {
+                (Some(a), Some(b)) => Some(a * b),
+                _ => None,
             }
-            DropStmt::Database(s) => {
-                let base = try!(Database::load(&s));
-                try!(base.delete());
-                let mut baseinuse = false;
-                match self.user._currentDatabase {
-                    Some(ref base) => {
-                        if base.name == s {
-                            baseinuse = true;
-                        };
-                        ()
-                    }
-                    None => (),
-                };
-                if baseinuse {
-                    self.user._currentDatabase = None;
-                };
-                Ok(generate_rows_dummy())
+        }
+        &Conditions::Or(ref a, ref b) => {
+            match (
+                estimate_share(stats, columns, a),
+                estimate_share(stats, columns, b),
+            ) {
+                (Some(a), Some(b)) => Some(a + b - a * b),
+                _ => None,
+            }
+        }
+        &Conditions::Leaf(ref c) => {
+            let lit = match c.rhs {
+                CondType::Literal(ref lit) => match evaluate(lit) {
+                    Ok(lit) => lit,
+                    Err(_) => return None,
+                },
+                _ => return None,
+            };
+            let column = match columns.iter().find(|column| column.name == c.col) {
+                Some(column) => column,
+                None => return None,
+            };
+            let mut value = Vec::new();
+            if column.sql_type.encode_into(&mut value, &lit).is_err() {
+                return None;
             }
-            _ => Err(ExecutionError::DebugError("to_do".into())),
+            stats.selectivity(&c.col, column.sql_type, c.op, &value)
         }
+        &Conditions::Expr(..) | &Conditions::In(..) | &Conditions::Exists(..) => None,
     }
+}
 
-    fn execute_alt_stmt(
-        &mut self,
-        query: AltStmt,
-    ) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
-        match query {
-            AltStmt::Table(stmt) => self.execute_alt_table_stmt(stmt),
-        }
+/// Returns whether a condition contains an `OR`.
+fn has_or(condition: &Conditions) -> bool {
+    match condition {
+        &Conditions::And(ref a, ref b) => has_or(a) || has_or(b),
+        &Conditions::Or(..) => true,
+        &Conditions::Leaf(_)
+        | &Conditions::Expr(..)
+        | &Conditions::In(..)
+        | &Conditions::Exists(..) => false,
     }
+}
 
-    fn execute_alt_table_stmt(
-        &mut self,
-        stmt: AlterTableStmt,
-    ) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
-        let _table = try!(self.get_table(&stmt.tid));
-        match stmt.op {
-            AlterOp::Add(columninfo) => {
-                let mut table = try!(self.get_table(&stmt.tid));
-                // Todo: no fullscan necessary!
-                let mut rows = try!(self.get_rows(&stmt.tid));
-                if !try!(rows.is_empty()) {
-                    return Err(ExecutionError::TableNotEmpty);
-                }
+/// Returns the values of the columns of a row, None stands for null.
+/// Returns the argument of a window function if it is computed before the
+/// function, as it is no column.
+fn window_argument(window: &Window) -> Option<Lit> {
+    match window.function {
+        WindowFunction::Sum(ref lit) | WindowFunction::Avg(ref lit) => match *lit {
+            Lit::Column(..) => None,
+            ref lit => Some(lit.clone()),
+        },
+        _ => None,
+    }
+}
 
-                let comment = if columninfo.comment.is_some() {
-                    columninfo.comment.unwrap()
-                } else {
-                    "".into()
-                };
+fn row_values(columns: &[Column], row: &[u8]) -> Vec<Option<Vec<u8>>> {
+    (0..columns.len())
+        .map(|i| {
+            if types::is_null(columns, row, i) {
+                None
+            } else {
+                Some(row[types::column_range(columns, i)].to_vec())
+            }
+        })
+        .collect()
+}
 
-                table.add_column(
-                    &columninfo.cid,
-                    columninfo.datatype,
-                    !columninfo.not_null,
-                    &comment,
-                    columninfo.primary,
-                );
-                try!(table.save());
-                Ok(generate_rows_dummy())
+/// Fails with `CheckViolation` if a check constraint of the table is false
+/// for a new row.
+fn check_constraints(table: &Table, row: &[u8]) -> Result<(), ExecutionError> {
+    for check in table.checks() {
+        if try!(check_condition(table.columns(), row, &check.condition)) == Some(false) {
+            return Err(ExecutionError::CheckViolation(check.name.clone()));
+        }
+    }
+    Ok(())
+}
+
+/// Evaluates a condition for a row. Returns None if the result is unknown
+/// because a compared value is null, which is no violation of a check.
+fn check_condition(
+    columns: &[Column],
+    row: &[u8],
+    condition: &Conditions,
+) -> Result<Option<bool>, ExecutionError> {
+    let position =
+        |_: &Option<String>, name: &String| match columns.iter().position(|c| &c.name == name) {
+            Some(i) => Ok(i),
+            None => Err(ExecutionError::UnknownColumn),
+        };
+    evaluate_condition(columns, row, condition, &position)
+}
+
+/// Evaluates a condition for a row like `check_condition`, `position`
+/// returns the index of a column from its optional table alias and name.
+fn evaluate_condition<F>(
+    columns: &[Column],
+    row: &[u8],
+    condition: &Conditions,
+    position: &F,
+) -> Result<Option<bool>, ExecutionError>
+where
+    F: Fn(&Option<String>, &String) -> Result<usize, ExecutionError>,
+{
+    let c = match condition {
+        &Conditions::And(ref a, ref b) => {
+            let a = try!(evaluate_condition(columns, row, a, position));
+            if a == Some(false) {
+                return Ok(a);
             }
-            AlterOp::Drop(column) => {
-                let mut table = try!(self.get_table(&stmt.tid));
-                // Todo: no fullscan necessary!
-                let mut rows = try!(self.get_rows(&stmt.tid));
-                if !try!(rows.is_empty()) {
-                    return Err(ExecutionError::TableNotEmpty);
-                }
-                table.remove_column(&column);
-                try!(table.save());
-                Ok(generate_rows_dummy())
+            let b = try!(evaluate_condition(columns, row, b, position));
+            return Ok(match (a, b) {
+                (_, Some(false)) => Some(false),
+                (Some(true), Some(true)) => Some(true),
+                _ => None,
+            });
+        }
+        &Conditions::Or(ref a, ref b) => {
+            let a = try!(evaluate_condition(columns, row, a, position));
+            if a == Some(true) {
+                return Ok(a);
             }
-            AlterOp::Modify(columninfo) => {
-                let mut table = try!(self.get_table(&stmt.tid));
-                {
-                    let columns = &mut table.meta_data.columns;
-                    let comment = if columninfo.comment.is_some() {
-                        columninfo.comment.unwrap()
-                    } else {
-                        "".into()
-                    };
-
-                    for index in 0..columns.len() {
-                        if columns[index].name == columninfo.cid {
-                            columns[index] = Column {
-                                name: columninfo.cid.clone(),
-                                sql_type: columninfo.datatype,
-                                is_primary_key: columninfo.primary,
-                                allow_null: !columninfo.not_null,
-                                description: comment.clone(),
+            let b = try!(evaluate_condition(columns, row, b, position));
+            return Ok(match (a, b) {
+                (_, Some(true)) => Some(true),
+                (Some(false), Some(false)) => Some(false),
+                _ => None,
+            });
+        }
+        &Conditions::Expr(ref lhs, op, ref rhs) => {
+            let value = |alias: &Option<String>, name: &String| {
+                column_value(columns, row, try!(position(alias, name)))
+            };
+            let lhs = try!(evaluate_with(lhs, &value));
+            let rhs = try!(evaluate_with(rhs, &value));
+            return Ok(match op {
+                CompType::IsNull => Some(lhs == Lit::Null),
+                CompType::IsNotNull => Some(lhs != Lit::Null),
+                CompType::Like | CompType::NLike | CompType::Regexp | CompType::NRegexp => {
+                    match (&lhs, &rhs) {
+                        (&Lit::String(ref text), &Lit::String(ref pattern)) => {
+                            let matches = match op {
+                                CompType::Like | CompType::NLike => pattern::like(text, pattern),
+                                _ => try!(pattern::regexp(text, pattern)),
                             };
+                            Some(matches == (op == CompType::Like || op == CompType::Regexp))
                         }
+                        (&Lit::Null, _) | (_, &Lit::Null) => None,
+                        _ => return Err(ExecutionError::CompareDatatypeMissmatch),
                     }
                 }
-                //println!("{:?}",table);
-                try!(table.save());
-                Ok(generate_rows_dummy())
+                CompType::Match | CompType::NMatch => {
+                    return Err(ExecutionError::CompareDatatypeMissmatch)
+                }
+                _ => try!(compare_lits(&lhs, &rhs)).map(|ordering| match op {
+                    CompType::Equ => ordering == Ordering::Equal,
+                    CompType::NEqu => ordering != Ordering::Equal,
+                    CompType::GThan => ordering == Ordering::Greater,
+                    CompType::SThan => ordering == Ordering::Less,
+                    CompType::GEThan => ordering != Ordering::Less,
+                    _ => ordering != Ordering::Greater,
+                }),
+            });
+        }
+        &Conditions::In(ref lhs, ref values, negated) => {
+            let value = |alias: &Option<String>, name: &String| {
+                column_value(columns, row, try!(position(alias, name)))
+            };
+            let lhs = try!(evaluate_with(lhs, &value));
+            // like comparisons with each value, ORed
+            let mut found = Some(false);
+            for v in values {
+                match try!(compare_lits(&lhs, &try!(evaluate_with(v, &value)))) {
+                    Some(Ordering::Equal) => {
+                        found = Some(true);
+                        break;
+                    }
+                    Some(_) => {}
+                    None => found = None,
+                }
+            }
+            return Ok(found.map(|found| found != negated));
+        }
+        // replaced by its result before, see `Executor::materialize_exists`
+        &Conditions::Exists(..) => return Err(ExecutionError::InvalidSubquery),
+        &Conditions::Leaf(ref c) => c,
+    };
+    let index = try!(position(&c.aliascol, &c.col));
+    let sql_type = &columns[index].sql_type;
+    let mut comparedata = Vec::new();
+    let other = match c.rhs {
+        CondType::Word(ref name) => {
+            let other = try!(position(&c.aliasrhs, name));
+            if *sql_type != columns[other].sql_type {
+                return Err(ExecutionError::CompareDatatypeMissmatch);
+            }
+            Some(other)
+        }
+        CondType::Literal(ref lit) => {
+            match (c.op, try!(evaluate(lit))) {
+                (CompType::IsNull, _) | (CompType::IsNotNull, _) => {}
+                (_, Lit::Null) => return Ok(None),
+                (op, ref lit)
+                    if !sql_type.accepts(lit) || (op.takes_text() && !sql_type.is_text()) =>
+                {
+                    return Err(ExecutionError::CompareDatatypeMissmatch)
+                }
+                (op, Lit::String(ref term)) if op.takes_text() => {
+                    comparedata.extend_from_slice(term.as_bytes())
+                }
+                (_, ref lit) => {
+                    try!(sql_type.encode_into(&mut comparedata, lit));
+                }
             }
+            None
         }
+    };
+    match c.op {
+        CompType::IsNull | CompType::IsNotNull => {}
+        _ => {
+            let null = |i| types::is_null(columns, row, i);
+            if null(index) || other.map_or(false, &null) {
+                return Ok(None);
+            }
+        }
+    }
+    Ok(Some(try!(types::fulfills(
+        columns,
+        row,
+        index,
+        (&comparedata, other),
+        c.op
+    ))))
+}
+
+/// Converts the value of a column into the type of another one, None stands
+/// for null.
+fn convert_value(
+    from: &Column,
+    to: &Column,
+    value: Option<&[u8]>,
+) -> Result<Option<Vec<u8>>, ExecutionError> {
+    let value = match value {
+        Some(value) => value,
+        None => return Ok(None),
+    };
+    if from.sql_type == to.sql_type {
+        return Ok(Some(value.to_vec()));
+    }
+    // BLOB values are not in the row
+    if from.sql_type == SqlType::Blob || to.sql_type == SqlType::Blob {
+        return Err(storage::Error::InvalidType.into());
     }
+    let lit = if from.sql_type.is_text() {
+        Lit::String(String::from_utf8_lossy(from.sql_type.content(value)).into_owned())
+    } else {
+        try!(from.sql_type.decode_from(&mut &value[..]))
+    };
+    let mut data = Vec::new();
+    try!(to.sql_type.encode_into(&mut data, &lit));
+    Ok(Some(data))
+}
 
-    fn get_own_database(&self) -> Result<&Database, ExecutionError> {
-        match self.user._currentDatabase {
-            Some(ref base) => Ok(base),
-            None => Err(ExecutionError::NoDatabaseSelected),
+/// Returns the date of a DATE, a TIMESTAMP or a string holding either.
+fn days(lit: &Lit) -> Option<i32> {
+    match lit {
+        &Lit::Date(date) => Some(date),
+        &Lit::Timestamp(timestamp) => Some(temporal::split_timestamp(timestamp).0),
+        &Lit::String(ref s) => temporal::parse_timestamp(s).map(|t| temporal::split_timestamp(t).0),
+        _ => None,
+    }
+}
+
+/// Returns the values of some columns of a row, or None if one of them is
+/// null.
+fn key_values(
+    columns: &[Column],
+    row: &[u8],
+    names: &[String],
+) -> Result<Option<Vec<Vec<u8>>>, ExecutionError> {
+    let mut values = Vec::new();
+    for name in names {
+        let i = match columns.iter().position(|c| &c.name == name) {
+            Some(i) => i,
+            None => return Err(ExecutionError::UnknownColumn),
+        };
+        if types::is_null(columns, row, i) {
+            return Ok(None);
         }
+        values.push(row[types::column_range(columns, i)].to_vec());
     }
+    Ok(Some(values))
+}
 
-    fn get_table(&self, table: &str) -> Result<Table, ExecutionError> {
-        let dbase = try!(self.get_own_database());
-        Ok(try!(dbase.load_table(table)))
+fn read_rows(mut rows: Rows<Cursor<Vec<u8>>>) -> Result<Vec<Vec<u8>>, ExecutionError> {
+    try!(rows.reset_pos());
+    let mut all = Vec::new();
+    loop {
+        let mut row = Vec::new();
+        match rows.next_row(&mut row) {
+            Ok(_) => all.push(row),
+            Err(storage::Error::EndOfFile) => break,
+            Err(e) => return Err(e.into()),
+        }
     }
+    Ok(all)
+}
 
-    fn get_engine<'b>(&'b self, table: &str) -> Result<Box<dyn Engine + 'b>, ExecutionError> {
-        let table = try!(self.get_table(table));
-        Ok(table.create_engine())
+fn to_rows(
+    columns: &[Column],
+    data: Vec<Vec<u8>>,
+) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
+    let mut rows = Rows::new(Cursor::new(Vec::new()), columns);
+    for row in data {
+        try!(rows.add_row(&row));
     }
+    Ok(rows)
+}
 
-    fn get_rows(&self, table: &str) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
-        let engine = try!(self.get_engine(table));
-        let mut rows = try!(engine.full_scan());
-        try!(rows.reset_pos());
-        Ok(rows)
+/// The columns of the tables a select reads and the names it refers to the
+/// tables by. A column of a subquery is resolved in the scope of the
+/// subquery first, then in those of the selects around it.
+struct Scope {
+    qualifiers: HashSet<String>,
+    columns: HashSet<String>,
+}
+
+impl Scope {
+    /// Returns whether a column, with an optional table name or alias,
+    /// belongs to the scope.
+    fn contains(&self, alias: &Option<String>, name: &str) -> bool {
+        match *alias {
+            Some(ref alias) => self.qualifiers.contains(alias),
+            None => self.columns.contains(name),
+        }
     }
+}
 
-    fn merge_rows(
-        &self,
-        mut left: Rows<Cursor<Vec<u8>>>,
-        mut right: Rows<Cursor<Vec<u8>>>,
-    ) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
-        try!(right.reset_pos());
-        loop {
-            try!(left.reset_pos());
-            let mut valid = true;
+/// How the tables of a select are joined, see `Executor::plan_joins`
+struct JoinPlan {
+    /// The tables in the order they are joined, with the condition joining
+    /// each to the tables before it
+    tables: Vec<(String, Option<Conditions>)>,
+    /// Conditions on the rows of a single table, checked before the join
+    filters: HashMap<String, Conditions>,
+    /// If the tables have statistics, in the order of `tables` the
+    /// estimates of reading each and of joining the tables up to it
+    estimates: Option<(Vec<Estimate>, Vec<Estimate>)>,
+}
 
-            let mut rightrow = Vec::<u8>::new();
-            let outerres = right.next_row(&mut rightrow);
-            match outerres {
-                Ok(_) => (),
-                Err(_) => break,
+/// A range scan of a secondary index answering a query on a single table,
+/// see `Executor::range_scan`
+struct RangeScan {
+    /// Index of the indexed column in the table
+    column: usize,
+    /// Lower and upper bound of the values
+    bounds: Vec<Bound<Vec<u8>>>,
+    /// Some if the rows are read for `ORDER BY` the column, whether the
+    /// order is descending
+    descending: Option<bool>,
+    /// Whether the rows with a null value, which the scan skips, are added
+    nulls: bool,
+    /// Share of the rows the statistics expect in the range, if known
+    share: Option<f64>,
+}
+
+/// A comparison of a column answered through the storage engine, see
+/// `Executor::index_lookup`
+struct IndexLookup {
+    /// Index of the compared column in the table
+    column: usize,
+    /// The comparison
+    cond: Condition,
+    op: CompType,
+    /// The encoded value, the term for `MATCH` and `LIKE`
+    value: Vec<u8>,
+    /// The other conjuncts of the condition
+    rest: Option<Conditions>,
+    /// Share of the rows the statistics expect, if known
+    share: Option<f64>,
+}
+
+/// A condition that bounds the values of a column from one or both sides
+struct RangeCondition<'a> {
+    alias: &'a Option<String>,
+    col: &'a str,
+    lower: Option<(CompType, &'a Lit)>,
+    upper: Option<(CompType, &'a Lit)>,
+}
+
+/// Returns the range of a condition that compares a column with a literal,
+/// or of two such conditions on the same column joined by `AND`, as
+/// `BETWEEN` is parsed into.
+fn range_condition(cond: &Conditions) -> Option<RangeCondition> {
+    match cond {
+        &Conditions::Leaf(ref c) => {
+            let lit = match c.rhs {
+                CondType::Literal(ref lit) => lit,
+                _ => return None,
+            };
+            let (lower, upper) = match c.op {
+                CompType::GThan | CompType::GEThan => (Some((c.op, lit)), None),
+                CompType::SThan | CompType::SEThan => (None, Some((c.op, lit))),
+                _ => return None,
+            };
+            Some(RangeCondition {
+                alias: &c.aliascol,
+                col: &c.col,
+                lower: lower,
+                upper: upper,
+            })
+        }
+        &Conditions::And(ref a, ref b) => {
+            let (a, b) = match (range_condition(a), range_condition(b)) {
+                (Some(a), Some(b)) => (a, b),
+                _ => return None,
+            };
+            if a.alias != b.alias || a.col != b.col {
+                return None;
             }
-            loop {
-                let mut leftrow = Vec::<u8>::new();
-                let outerres = left.next_row(&mut leftrow);
-                match outerres {
-                    Ok(_) => (),
-                    Err(_) => break,
+            let (lower, upper) = match (a.lower, a.upper, b.lower, b.upper) {
+                (Some(lower), None, None, Some(upper)) | (None, Some(upper), Some(lower), None) => {
+                    (lower, upper)
                 }
-                let mut primarykeys = 0;
-                let mut equalprimarykeys = 0;
-                for index in 0..left.columns.len() {
-                    if left.columns[index].is_primary_key {
-                        primarykeys += 1;
-                        let leftval = try!(left.get_value(&leftrow, index));
-                        let rightval = try!(right.get_value(&rightrow, index));
-                        if leftval == rightval {
-                            equalprimarykeys += 1;
-                        }
-                    }
-                }
-                if primarykeys == equalprimarykeys {
-                    valid = false;
-                }
-            }
-            if valid {
-                left.add_row(&rightrow);
-            }
+                _ => return None,
+            };
+            Some(RangeCondition {
+                alias: a.alias,
+                col: a.col,
+                lower: Some(lower),
+                upper: Some(upper),
+            })
         }
+        _ => None,
+    }
+}
 
-        Ok(left)
+fn borrow_bound(bound: &Bound<Vec<u8>>) -> Bound<&[u8]> {
+    match bound {
+        &Bound::Included(ref value) => Bound::Included(value),
+        &Bound::Excluded(ref value) => Bound::Excluded(value),
+        &Bound::Unbounded => Bound::Unbounded,
     }
+}
 
-    fn cross_rows(
-        &self,
-        mut left: Rows<Cursor<Vec<u8>>>,
-        mut right: Rows<Cursor<Vec<u8>>>,
-    ) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
-        try!(left.reset_pos());
-        try!(right.reset_pos());
-        let mut columnvec = left.columns.clone();
-        let mut appendvec = right.columns.clone();
+/// Returns the result of `BACKUP DATABASE` and `RESTORE DATABASE`
+fn backup_rows(
+    manifest: &backup::Manifest,
+    path: &str,
+) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
+    let columns = vec![
+        Column::new("database", SqlType::Char(64), false, "database", false),
+        Column::new(
+            "path",
+            SqlType::VarChar(255),
+            false,
+            "backup directory",
+            false,
+        ),
+        Column::new("files", SqlType::BigInt, false, "files", false),
+        Column::new("bytes", SqlType::BigInt, false, "size of the files", false),
+        Column::new(
+            "taken",
+            SqlType::Timestamp,
+            false,
+            "time of the backup",
+            false,
+        ),
+        Column::new(
+            "next_xid",
+            SqlType::BigInt,
+            false,
+            "first transaction not in the backup",
+            false,
+        ),
+    ];
+    let values = [
+        Lit::String(manifest.database.clone()),
+        Lit::String(path.into()),
+        Lit::Int(manifest.files.len() as i64),
+        Lit::Int(manifest.size() as i64),
+        Lit::Timestamp(manifest.taken),
+        Lit::Int(manifest.next_xid as i64),
+    ];
+    let mut rows = Rows::new(Cursor::new(Vec::<u8>::new()), &columns);
+    let mut row = Vec::<u8>::new();
+    for (column, value) in columns.iter().zip(values.iter()) {
+        try!(column.sql_type.encode_into(&mut row, value));
+    }
+    try!(rows.add_row(&row));
+    Ok(rows)
+}
 
-        for _i in 0..appendvec.len() {
-            let _appendlength = appendvec.len();
-            columnvec.push(appendvec.remove(0));
+/// Returns the values of a row of an `INSERT` in the order of the columns of
+/// the table, given for the named columns or for all of them. Left out
+/// columns get their default or null.
+/// Replaces `VALUES(col)` in an expression of `ON DUPLICATE KEY UPDATE` by
+/// the value of the column in the row that was to be inserted.
+fn proposed_values(lit: &Lit, columns: &[Column], values: &[Lit]) -> Result<Lit, ExecutionError> {
+    match lit {
+        &Lit::Call(ref name, ref args) if name == "values" => match &args[..] {
+            &[Lit::Column(None, ref column)] => {
+                match columns.iter().position(|c| &c.name == column) {
+                    Some(i) => Ok(values[i].clone()),
+                    None => Err(ExecutionError::UnknownColumn),
+                }
+            }
+            _ => Err(ExecutionError::UnknownColumn),
+        },
+        &Lit::Call(ref name, ref args) => {
+            let mut replaced = Vec::with_capacity(args.len());
+            for arg in args {
+                replaced.push(try!(proposed_values(arg, columns, values)));
+            }
+            Ok(Lit::Call(name.clone(), replaced))
         }
-        let cursor = Cursor::new(Vec::<u8>::new());
-        let mut rows = Rows::<Cursor<Vec<u8>>>::new(cursor, &columnvec);
-
-        loop {
-            let mut insertingrow = Vec::<u8>::new();
-            let outerres = left.next_row(&mut insertingrow);
+        _ => Ok(lit.clone()),
+    }
+}
 
-            match outerres {
-                Ok(_) => (),
-                Err(_) => break,
-            }
-            loop {
-                let mut datasrc = Vec::<u8>::new();
-                for i in 0..insertingrow.len() {
-                    datasrc.push(insertingrow[i]);
-                }
-                let innerres = right.next_row(&mut datasrc);
-                match innerres {
-                    Ok(_) => {
-                        try!(rows.add_row(&datasrc));
-                        ()
-                    }
-                    Err(_) => {
-                        right.reset_pos();
-                        break;
-                    }
-                }
+fn inserted_values(
+    columns: &[Column],
+    names: &[String],
+    row: &[Lit],
+) -> Result<Vec<Lit>, ExecutionError> {
+    let mut given: Vec<Option<&Lit>> = vec![None; columns.len()];
+    if names.is_empty() {
+        if row.len() != columns.len() {
+            return Err(ExecutionError::InsertMissmatch);
+        }
+        for (i, value) in row.iter().enumerate() {
+            given[i] = Some(value);
+        }
+    } else {
+        for (name, value) in names.iter().zip(row.iter()) {
+            match columns.iter().position(|c| &c.name == name) {
+                Some(i) if given[i].is_none() => given[i] = Some(value),
+                Some(_) => return Err(ExecutionError::InsertMissmatch),
+                None => return Err(ExecutionError::UnknownColumn),
             }
         }
+    }
+    let mut values = Vec::new();
+    for (column, value) in columns.iter().zip(given) {
+        values.push(match value.or(column.default.as_ref()) {
+            Some(value) => try!(evaluate(value)),
+            None => Lit::Null,
+        });
+    }
+    Ok(values)
+}
 
-        Ok(rows)
+/// Borrows encoded rows for `Engine::insert_rows`.
+fn slices(rows: &[Vec<u8>]) -> Vec<&[u8]> {
+    rows.iter().map(|r| &r[..]).collect()
+}
+
+/// Returns the result of COPY and INSERT: a row with the number of rows
+/// inserted.
+fn inserted_count(inserted: u64) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
+    let columns = vec![Column::new(
+        "rows",
+        SqlType::BigInt,
+        false,
+        "rows inserted",
+        false,
+    )];
+    let mut rows = Rows::new(Cursor::new(Vec::<u8>::new()), &columns);
+    let mut row = Vec::new();
+    try!(SqlType::BigInt.encode_into(&mut row, &Lit::Int(inserted as i64)));
+    try!(rows.add_row(&row));
+    Ok(rows)
+}
+
+/// Returns the result of CHECK TABLE or REPAIR TABLE: a row for every
+/// message, whose type is "error", "warning", "info" or "status".
+fn table_messages(
+    table: &str,
+    op: &str,
+    messages: &[(&str, String)],
+) -> Result<Rows<Cursor<Vec<u8>>>, ExecutionError> {
+    let columns = vec![
+        Column::new("table", SqlType::Char(64), false, "table", false),
+        Column::new("op", SqlType::Char(8), false, "statement", false),
+        Column::new(
+            "msg_type",
+            SqlType::Char(8),
+            false,
+            "type of message",
+            false,
+        ),
+        Column::new("msg_text", SqlType::VarChar(255), false, "message", false),
+    ];
+    let mut rows = Rows::new(Cursor::new(Vec::<u8>::new()), &columns);
+    for &(msg_type, ref text) in messages {
+        let values = [
+            Lit::String(table.into()),
+            Lit::String(op.into()),
+            Lit::String(msg_type.into()),
+            Lit::String(text.clone()),
+        ];
+        let mut row = Vec::<u8>::new();
+        for (column, value) in columns.iter().zip(values.iter()) {
+            try!(column.sql_type.encode_into(&mut row, value));
+        }
+        try!(rows.add_row(&row));
     }
+    Ok(rows)
+}
+
+/// Returns the text of an encoded value.
+fn format_value(sql_type: SqlType, value: &[u8]) -> Result<String, ExecutionError> {
+    let text = match try!(sql_type.decode_from(&mut Cursor::new(value))) {
+        // CHAR values are padded with zeros
+        Lit::String(s) => s.trim_end_matches('\0').to_string(),
+        Lit::Int(i) => i.to_string(),
+        Lit::Float(f) => f.to_string(),
+        Lit::Decimal(d, scale) => decimal::format(d, scale),
+        Lit::Bool(b) => (b != 0).to_string(),
+        Lit::Bytes(bytes) => bytes.iter().map(|b| format!("{:02x}", b)).collect(),
+        Lit::Date(d) => temporal::format_date(d),
+        Lit::Time(t) => temporal::format_time(t),
+        Lit::Timestamp(t) => temporal::format_timestamp(t),
+        Lit::Null | Lit::Call(..) | Lit::Column(..) | Lit::Subquery(_) => String::new(),
+    };
+    Ok(text)
 }
 
 fn generate_rows_dummy() -> Rows<Cursor<Vec<u8>>> {
@@ -769,6 +6851,7 @@ fn generate_rows_dummy() -> Rows<Cursor<Vec<u8>>> {
 pub enum ExecutionError {
     ParseError(ParseError),
     StorageError(storage::Error),
+    AuthError(auth::AuthError),
     UnknownError,
     NoDatabaseSelected,
     InsertMissmatch,
@@ -776,7 +6859,56 @@ pub enum ExecutionError {
     UnknownAlias,
     UnknownColumn,
     CompareDatatypeMissmatch,
-    TableNotEmpty,
+    UnknownFunction(String),
+    /// A NOT NULL column would be null, holds the name of the column
+    NotNullViolation(String),
+    /// A row references no row, holds the name of the foreign key
+    ForeignKeyViolation(String),
+    /// A row or column is still referenced, holds the name of the foreign key
+    RowIsReferenced(String),
+    /// The referenced columns do not fit a foreign key, holds its name
+    InvalidForeignKey(String),
+    /// A row violates a check constraint, holds the name of the constraint
+    CheckViolation(String),
+    /// A subquery returns more than one column, or more than one row where
+    /// a single value is expected
+    InvalidSubquery,
+    /// No view of the name exists, holds the name
+    UnknownView(String),
+    /// A view refers to itself, holds its name
+    RecursiveView(String),
+    /// A function of the name exists already or is built in, holds the name
+    FunctionExists(String),
+    /// A function calls itself, holds its name
+    RecursiveFunction(String),
+    /// An event of the name exists already, holds the name
+    EventExists(String),
+    /// No event of the name exists, holds the name
+    UnknownEvent(String),
+    /// A function is called with another number of arguments than it has
+    /// parameters, holds its name
+    FunctionArguments(String),
+    /// The selects of a set operation differ in their number of columns or
+    /// in the types of a column
+    IncompatibleSelects,
+    /// CSV text given to `COPY` cannot be read or holds a value that does
+    /// not fit its column
+    InvalidCsv(csv::Error),
+    DivisionByZero,
+    NumericOverflow,
+    Cancelled,
+    /// Other connections use the database, holds its name
+    DatabaseInUse(String),
+    /// The configuration cannot be reloaded, holds why
+    InvalidConfig(String),
+    /// The statement would change something on a follower, see
+    /// `replication`
+    ReadOnly,
+    /// No connection of the id is active, holds the id
+    UnknownConnection(u64),
+    /// The database has as many connections as its `max_connections`,
+    /// holds its name
+    TooManyConnections(String),
 }
 
 impl From<ParseError> for ExecutionError {
@@ -785,6 +6917,22 @@ impl From<ParseError> for ExecutionError {
     }
 }
 
+impl From<function::Error> for ExecutionError {
+    fn from(error: function::Error) -> ExecutionError {
+        match error {
+            function::Error::Recursive(name) => ExecutionError::RecursiveFunction(name),
+            function::Error::Arguments(name) => ExecutionError::FunctionArguments(name),
+            function::Error::Parse(error) => ExecutionError::ParseError(error),
+        }
+    }
+}
+
+impl From<auth::AuthError> for ExecutionError {
+    fn from(error: auth::AuthError) -> ExecutionError {
+        ExecutionError::AuthError(error)
+    }
+}
+
 impl From<storage::Error> for ExecutionError {
     fn from(error: storage::Error) -> ExecutionError {
         ExecutionError::StorageError(error)
@@ -805,3 +6953,164 @@ impl Where {
         }
     }
 }
+
+#[test]
+fn test_join_rows() {
+    let rows = |columns: &[Column], values: &[&[i64]]| {
+        let mut rows = Rows::new(Cursor::new(Vec::<u8>::new()), columns);
+        for row in values {
+            let mut encoded = Vec::new();
+            for value in row.iter() {
+                let mut data = Vec::new();
+                SqlType::Int
+                    .encode_into(&mut data, &Lit::Int(*value))
+                    .unwrap();
+                encoded.push(Some(data));
+            }
+            rows.add_values(&encoded).unwrap();
+        }
+        rows
+    };
+    let int = |name: &str| Column::new(name, SqlType::Int, false, "", false);
+    let left = rows(&[int("id")], &[&[1], &[2], &[3]]);
+    let right = rows(
+        &[int("id"), int("a")],
+        &[&[10, 1], &[11, 3], &[12, 3], &[13, 9]],
+    );
+
+    let mut alias = HashMap::new();
+    alias.insert("a".to_string(), "foo".to_string());
+    alias.insert("bar".to_string(), "bar".to_string());
+    let mut tables = HashMap::new();
+    tables.insert("id".to_string(), "bar".to_string());
+    tables.insert("a".to_string(), "bar".to_string());
+    let mut columns = HashMap::new();
+    columns.insert(
+        "foo".to_string(),
+        vec![("id".to_string(), 0)].into_iter().collect(),
+    );
+    columns.insert(
+        "bar".to_string(),
+        vec![("id".to_string(), 1), ("a".to_string(), 2)]
+            .into_iter()
+            .collect(),
+    );
+    let condition = Conditions::Leaf(Condition {
+        aliascol: Some("a".to_string()),
+        col: "id".to_string(),
+        op: CompType::Equ,
+        aliasrhs: None,
+        rhs: CondType::Word("a".to_string()),
+    });
+
+    let mut user = auth::User {
+        _name: "test".to_string(),
+        _currentDatabase: None,
+    };
+    let cancel = CancelToken::new();
+    let executor = Executor::new(&mut user, &cancel, 0);
+    let (mut joined, _) = executor
+        .join_rows(left, right, (&alias, &tables, &columns), &condition, None)
+        .unwrap();
+    assert_eq!(joined.columns.len(), 3);
+
+    let mut pairs = Vec::new();
+    for row in read_rows(joined.full_scan().unwrap()).unwrap() {
+        let value = |i| {
+            SqlType::Int
+                .decode_from(&mut &joined.get_value(&row, i).unwrap()[..])
+                .unwrap()
+        };
+        pairs.push((value(0), value(1)));
+    }
+    assert_eq!(
+        pairs,
+        vec![
+            (Lit::Int(1), Lit::Int(10)),
+            (Lit::Int(3), Lit::Int(11)),
+            (Lit::Int(3), Lit::Int(12)),
+        ]
+    );
+}
+
+#[test]
+fn test_evaluate_expressions() {
+    let call = |name: &str, args| Lit::Call(name.into(), args);
+    let string = |s: &str| Lit::String(s.into());
+    let value = |lit| evaluate(&lit).unwrap();
+
+    assert_eq!(value(call("upper", vec![string("abc")])), string("ABC"));
+    assert_eq!(value(call("length", vec![string("äbc")])), Lit::Int(3));
+    assert_eq!(
+        value(call("||", vec![string("a"), Lit::Int(1)])),
+        string("a1")
+    );
+    let substr = |start, len| {
+        call(
+            "substr",
+            vec![string("hello"), Lit::Int(start), Lit::Int(len)],
+        )
+    };
+    assert_eq!(value(substr(2, 3)), string("ell"));
+    assert_eq!(value(substr(0, 2)), string("h"));
+    assert_eq!(value(call("abs", vec![Lit::Int(-3)])), Lit::Int(3));
+    assert_eq!(
+        value(call("round", vec![Lit::Decimal(12345, 2), Lit::Int(1)])),
+        Lit::Decimal(1235, 1)
+    );
+    assert_eq!(
+        value(call("round", vec![Lit::Int(-125), Lit::Int(-1)])),
+        Lit::Int(-130)
+    );
+    // functions of null are null
+    assert_eq!(value(call("lower", vec![Lit::Null])), Lit::Null);
+
+    assert!(evaluate(&call("upper", vec![Lit::Int(1)])).is_err());
+    assert!(evaluate(&Lit::Column(None, "a".into())).is_err());
+
+    assert_eq!(
+        compare_lits(&Lit::Int(2), &Lit::Decimal(25, 1)).unwrap(),
+        Some(Ordering::Less)
+    );
+    assert_eq!(compare_lits(&string("b"), &Lit::Null).unwrap(), None);
+    assert!(compare_lits(&string("b"), &Lit::Int(1)).is_err());
+}
+
+#[test]
+fn test_in_condition() {
+    let columns = vec![Column::new("x", SqlType::Int, true, "", false)];
+    let row = |value: Option<i64>| {
+        let encoded = value.map(|v| {
+            let mut data = Vec::new();
+            SqlType::Int.encode_into(&mut data, &Lit::Int(v)).unwrap();
+            data
+        });
+        types::encode_row(&columns, &[encoded]).unwrap()
+    };
+    let within = |value, values: Vec<Lit>, negated| {
+        let cond = Conditions::In(Lit::Column(None, "x".into()), values, negated);
+        check_condition(&columns, &row(value), &cond).unwrap()
+    };
+
+    assert_eq!(
+        within(Some(2), vec![Lit::Int(1), Lit::Int(2)], false),
+        Some(true)
+    );
+    assert_eq!(
+        within(Some(3), vec![Lit::Int(1), Lit::Int(2)], false),
+        Some(false)
+    );
+    assert_eq!(
+        within(Some(3), vec![Lit::Int(1), Lit::Int(2)], true),
+        Some(true)
+    );
+    assert_eq!(within(Some(3), Vec::new(), false), Some(false));
+    // like x = 1 OR x = NULL, which is unknown unless x is 1
+    assert_eq!(
+        within(Some(1), vec![Lit::Int(1), Lit::Null], true),
+        Some(false)
+    );
+    assert_eq!(within(Some(3), vec![Lit::Int(1), Lit::Null], false), None);
+    assert_eq!(within(Some(3), vec![Lit::Int(1), Lit::Null], true), None);
+    assert_eq!(within(None, vec![Lit::Int(1)], false), None);
+}
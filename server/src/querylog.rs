@@ -0,0 +1,210 @@
+//! General query log
+//!
+//! If the configuration has a `query_log`, every statement is appended to
+//! that file, relative to the data directory, once it finished: when it
+//! started, the connection and its user and database, the SQL text, how
+//! long it took and whether it failed. Unlike the log of the server, which
+//! is meant for reading, the lines are either plain text or, with
+//! `query_log_format = "json"`, a JSON object each, for log pipelines:
+//!
+//! ```text
+//! 2026-10-16T16:12:52.639Z 1 admin shop 0.532ms OK select * from orders
+//! {"time":"2026-10-16T16:12:52.639Z","connection":1,"user":"admin",...}
+//! ```
+//!
+//! Both settings take effect on a reload, the file is opened again then.
+
+use serde::Serialize;
+use serde_json;
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The file statements are logged to and their format, `None` disables the
+/// log
+static LOG: Mutex<Option<(String, Format, File)>> = Mutex::new(None);
+
+/// Format of the lines of the query log
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Text,
+    Json,
+}
+
+impl Format {
+    /// Returns the format of the name used by the configuration, `text` or
+    /// `json`.
+    pub fn from_name(name: &str) -> Option<Format> {
+        match name {
+            "text" => Some(Format::Text),
+            "json" => Some(Format::Json),
+            _ => None,
+        }
+    }
+}
+
+/// A statement that finished
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entry<'a> {
+    pub connection: u64,
+    pub user: &'a str,
+    pub database: Option<&'a str>,
+    pub sql: &'a str,
+    pub started: SystemTime,
+    pub duration: Duration,
+    /// Why the statement failed, `None` if it succeeded
+    pub error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct JsonEntry<'a> {
+    time: String,
+    connection: u64,
+    user: &'a str,
+    database: Option<&'a str>,
+    sql: &'a str,
+    duration_ms: f64,
+    status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: &'a Option<String>,
+}
+
+impl<'a> Entry<'a> {
+    /// Returns the line of the entry in the format, without a line break.
+    pub fn line(&self, format: Format) -> String {
+        let time = timestamp(self.started);
+        let duration_ms = self.duration.as_micros() as f64 / 1000.0;
+        match format {
+            Format::Text => {
+                let status = match self.error {
+                    Some(ref e) => format!("ERROR({})", e),
+                    None => "OK".into(),
+                };
+                format!(
+                    "{} {} {} {} {}ms {} {}",
+                    time,
+                    self.connection,
+                    self.user,
+                    self.database.unwrap_or("-"),
+                    duration_ms,
+                    status,
+                    // one line per statement
+                    self.sql.split_whitespace().collect::<Vec<_>>().join(" ")
+                )
+            }
+            Format::Json => {
+                let entry = JsonEntry {
+                    time: time,
+                    connection: self.connection,
+                    user: self.user,
+                    database: self.database,
+                    sql: self.sql,
+                    duration_ms: duration_ms,
+                    status: if self.error.is_some() { "error" } else { "ok" },
+                    error: &self.error,
+                };
+                serde_json::to_string(&entry).unwrap_or_default()
+            }
+        }
+    }
+}
+
+/// Logs the statements from now on to the file in the format, or stops
+/// logging them. The file is only opened again if it changed.
+pub fn configure(file: Option<&str>, format: Format) -> io::Result<()> {
+    let mut log = LOG.lock().unwrap();
+    let reopen = match (&*log, file) {
+        (&Some((ref current, _, _)), Some(file)) => current != file,
+        _ => true,
+    };
+    *log = match file {
+        Some(file) if reopen => {
+            let opened = try!(OpenOptions::new().append(true).create(true).open(file));
+            info!("Logging statements to '{}'", file);
+            Some((file.into(), format, opened))
+        }
+        Some(_) => log.take().map(|(file, _, opened)| (file, format, opened)),
+        None => None,
+    };
+    Ok(())
+}
+
+/// Appends the statement to the query log, if there is one.
+pub fn record(entry: &Entry) {
+    let mut log = LOG.lock().unwrap();
+    if let Some((_, format, ref mut file)) = *log {
+        let line = entry.line(format) + "\n";
+        if let Err(e) = file.write_all(line.as_bytes()) {
+            error!("Cannot write the query log: {}", e);
+        }
+    }
+}
+
+/// Returns the time in UTC as in RFC 3339, in milliseconds, like
+/// `2026-10-16T16:12:52.639Z`.
+pub fn timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (days, secs_of_day) = (secs / 86400, secs % 86400);
+
+    // civil date of the days since 1970-01-01, by the algorithm of Howard
+    // Hinnant
+    let z = days as i64 + 719468;
+    let era = z / 146097;
+    let day_of_era = z - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+#[test]
+fn test_query_log() {
+    assert_eq!(timestamp(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+    let leap_day = UNIX_EPOCH + Duration::from_millis(951_827_696_789);
+    assert_eq!(timestamp(leap_day), "2000-02-29T12:34:56.789Z");
+
+    let mut entry = Entry {
+        connection: 7,
+        user: "admin",
+        database: None,
+        sql: "select *\n  from t",
+        started: leap_day,
+        duration: Duration::from_micros(1500),
+        error: None,
+    };
+    assert_eq!(
+        entry.line(Format::Text),
+        "2000-02-29T12:34:56.789Z 7 admin - 1.5ms OK select * from t"
+    );
+    entry.database = Some("shop");
+    entry.error = Some("NoDatabaseSelected".into());
+    let json: serde_json::Value = serde_json::from_str(&entry.line(Format::Json)).unwrap();
+    assert_eq!(json["time"], "2000-02-29T12:34:56.789Z");
+    assert_eq!(json["database"], "shop");
+    assert_eq!(json["sql"], "select *\n  from t");
+    assert_eq!(json["duration_ms"], 1.5);
+    assert_eq!(json["status"], "error");
+    assert_eq!(json["error"], "NoDatabaseSelected");
+    assert_eq!(Format::from_name("yaml"), None);
+}
@@ -0,0 +1,921 @@
+//! Replication of the changes of a leader to read-only followers
+//!
+//! A server with a `replication_log` is a leader: every transaction that
+//! changed the schema, the data or the privileges is appended to that file,
+//! relative to the data directory, as a `Change` numbered by its log
+//! sequence number (LSN). A transaction is logged when it commits, while it
+//! still holds its locks (`transaction::on_end`), so transactions writing
+//! the same rows or tables are logged in the order they wrote them. Rolled
+//! back transactions are left out. If the server has an encryption key, the
+//! lines of the log are sealed with it (`encrypt::seal_line`), as they hold
+//! the rows of encrypted tables.
+//!
+//! A change holds the rows the transaction inserted and deleted, taken from
+//! its undo log: `NOW()` or the file read by `COPY` give the same rows on
+//! the followers as on the leader. Statements that change something else
+//! than rows, like `CREATE TABLE` or `GRANT` (`Query::is_change`), are
+//! logged by their text in their place among the rows, and run again.
+//!
+//! A server with `replicate_from` is a follower. It logs in to the leader as
+//! the administrator with the `replication_password`, sends
+//! `Command::Replicate` with the LSN following the last change it applied
+//! and applies every change streamed back, see `net`: it runs the
+//! statements and replaces the rows with the primary keys of the changed
+//! ones (`mvcc::replay`). The LSN of the last change applied is kept in
+//! `replica.lsn` in the data directory, changes up to it are skipped. A
+//! change applied right before the follower crashed is applied again after
+//! a restart: its rows come out the same, its statements run again and
+//! mostly fail, which is logged. If the connection fails, the follower connects again after
+//! `RETRY_INTERVAL`. Clients may query a follower, but statements changing
+//! anything fail with `ExecutionError::ReadOnly`. `SHOW REPLICA STATUS`
+//! tells how far the follower lags behind.
+//!
+//! The replication has its limits:
+//!
+//! - a follower has to start with the data the leader had before the first
+//!   change of its log, restored from a backup for example
+//! - statements logged by their text run on the follower, e.g. the rows of
+//!   `CREATE TABLE ... AS SELECT` are selected there and `RESTORE` reads
+//!   its files there
+//! - the rows of a change are applied in one transaction, but its
+//!   statements each in their own
+//!
+//! The replication log is never truncated.
+
+use auth;
+use conn;
+use net::types::{Capabilities, Change, ClientErrMsg, Command, Heartbeat, Login, PkgType, Step};
+use net::{self, PacketWriter};
+use parse::ast::{ManipulationStmt, Query};
+use process;
+use serde::Deserialize;
+use serde_json;
+use shutdown;
+use storage::encrypt;
+use storage::lock::Mode;
+use storage::transaction::{self, Statement};
+use storage::{self, blob, mvcc, Engine, SqlType, Versioned};
+
+use aes_gcm::Aes256Gcm;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How long a leader waits for a change before it sends a `Heartbeat`
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+/// How long a follower waits before it connects to its leader again
+pub const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+/// File of a follower holding the LSN of the last change it applied
+const APPLIED_FILE: &'static str = "replica.lsn";
+/// Authenticated with every sealed line of the log
+const LINE_AAD: &'static [u8] = b"replication log";
+
+/// The replication log of a leader, `None` if this server keeps none
+static LOG: Mutex<Option<Log>> = Mutex::new(None);
+/// Notified whenever a change was appended to the log
+static APPENDED: Condvar = Condvar::new();
+/// The statements logged by their text of the open transactions, by
+/// connection
+static PENDING: Mutex<Vec<(u64, Vec<Pending>)>> = Mutex::new(Vec::new());
+
+/// The state of a follower, `None` if this server follows no leader
+static FOLLOWER: Mutex<Option<ReplicaStatus>> = Mutex::new(None);
+/// The connection that applies the changes of the leader, `0` if none
+static APPLIER: AtomicU64 = AtomicU64::new(0);
+
+/// The replication log, one line of JSON for every `Change`
+struct Log {
+    path: PathBuf,
+    file: File,
+    last_lsn: u64,
+    /// Seals the lines, the encryption key of the server
+    cipher: Option<Aes256Gcm>,
+}
+
+/// The position of a line of the log, which is read also from lines of
+/// older versions of `Change`
+#[derive(Deserialize)]
+struct Position {
+    lsn: u64,
+}
+
+/// Returns the JSON of a line of the log. Lines written before the server
+/// had an encryption key are not sealed.
+fn unseal(cipher: Option<&Aes256Gcm>, line: &str) -> Option<String> {
+    match cipher {
+        Some(cipher) if !line.starts_with('{') => encrypt::unseal_line(cipher, LINE_AAD, line),
+        _ => Some(line.into()),
+    }
+}
+
+impl Log {
+    /// Opens the log, the LSNs go on after the last change in the file.
+    fn open(path: &Path, cipher: Option<Aes256Gcm>) -> io::Result<Log> {
+        let text = match fs::read(path) {
+            Ok(text) => String::from_utf8_lossy(&text).into_owned(),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+        let last_lsn = text
+            .lines()
+            .filter_map(|line| unseal(cipher.as_ref(), line))
+            .filter_map(|line| serde_json::from_str::<Position>(&line).ok())
+            .last()
+            .map_or(0, |position| position.lsn);
+        let mut file = try!(OpenOptions::new().append(true).create(true).open(path));
+        // a line cut off by a crash must not swallow the next change
+        if !text.is_empty() && !text.ends_with('\n') {
+            try!(file.write_all(b"\n"));
+        }
+        Ok(Log {
+            path: path.into(),
+            file: file,
+            last_lsn: last_lsn,
+            cipher: cipher,
+        })
+    }
+
+    /// Appends the steps as the next change and returns it.
+    fn append(&mut self, steps: Vec<Step>) -> io::Result<Change> {
+        let change = Change {
+            lsn: self.last_lsn + 1,
+            time: now(),
+            steps: steps,
+        };
+        let mut line = try!(serde_json::to_string(&change));
+        if let Some(ref cipher) = self.cipher {
+            line = encrypt::seal_line(cipher, LINE_AAD, &line);
+        }
+        try!(self.file.write_all((line + "\n").as_bytes()));
+        self.last_lsn = change.lsn;
+        Ok(change)
+    }
+}
+
+/// A statement of an open transaction that is logged by its text, see
+/// `before`
+#[derive(Clone, Debug, PartialEq)]
+struct Pending {
+    /// Current database when the statement started
+    database: Option<String>,
+    query: String,
+    /// Number of rows the transaction changed before the statement
+    rows_before: usize,
+    /// Number of rows the transaction changed after the statement, `None`
+    /// while it runs
+    rows_after: Option<usize>,
+}
+
+/// Keeps the changes in the replication log at the path from now on.
+pub fn open(path: &Path) -> io::Result<()> {
+    let log = try!(Log::open(path, encrypt::cipher()));
+    info!(
+        "Logging changes for replication to '{}', last change {}",
+        path.display(),
+        log.last_lsn
+    );
+    *LOG.lock().unwrap() = Some(log);
+    transaction::on_end(ended);
+    Ok(())
+}
+
+/// Returns true if this server keeps a replication log.
+pub fn is_logging() -> bool {
+    LOG.lock().unwrap().is_some()
+}
+
+/// Returns true if the statement is logged by its text: it changes
+/// something, but not only rows.
+fn by_text(query: &Query) -> bool {
+    match query {
+        &Query::ManipulationStmt(ManipulationStmt::Insert(_))
+        | &Query::ManipulationStmt(ManipulationStmt::InsertSelect(_))
+        | &Query::ManipulationStmt(ManipulationStmt::Update(_))
+        | &Query::ManipulationStmt(ManipulationStmt::Delete(_))
+        | &Query::ManipulationStmt(ManipulationStmt::Copy(_)) => false,
+        query => query.is_change(),
+    }
+}
+
+/// Must be called before a statement runs, with its text and the current
+/// database. A statement that is logged by its text is noted for the
+/// transaction of the connection, see `after`.
+pub fn before(connection: u64, query: &Query, text: &str, database: Option<&str>) {
+    if !by_text(query) || !is_logging() {
+        return;
+    }
+    let statement = Pending {
+        database: database.map(|d| d.into()),
+        query: text.into(),
+        rows_before: transaction::recorded(connection),
+        rows_after: None,
+    };
+    let mut pending = PENDING.lock().unwrap();
+    match pending.iter().position(|&(id, _)| id == connection) {
+        Some(i) => pending[i].1.push(statement),
+        None => pending.push((connection, vec![statement])),
+    }
+}
+
+/// Must be called after a statement ran. A statement noted by `before`
+/// that failed is forgotten, as it did not end its transaction.
+pub fn after(connection: u64, success: bool) {
+    let mut pending = PENDING.lock().unwrap();
+    let i = match pending.iter().position(|&(id, _)| id == connection) {
+        Some(i) => i,
+        None => return,
+    };
+    let running = pending[i]
+        .1
+        .last()
+        .map_or(false, |s| s.rows_after.is_none());
+    if running && success {
+        let last = pending[i].1.len() - 1;
+        pending[i].1[last].rows_after = Some(transaction::recorded(connection));
+    } else if running {
+        pending[i].1.pop();
+    }
+    if pending[i].1.is_empty() {
+        pending.remove(i);
+    }
+}
+
+/// Logs a committed transaction, see `transaction::on_end`.
+fn ended(connection: u64, committed: bool, rows: &[(&str, &str, &transaction::Change)]) {
+    let statements = {
+        let mut pending = PENDING.lock().unwrap();
+        match pending.iter().position(|&(id, _)| id == connection) {
+            Some(i) => pending.remove(i).1,
+            None => Vec::new(),
+        }
+    };
+    if !committed || (statements.is_empty() && rows.is_empty()) {
+        return;
+    }
+    let mut steps = merge(statements, rows);
+    add_blobs(&mut steps);
+    let mut log = LOG.lock().unwrap();
+    if let Some(ref mut log) = *log {
+        match log.append(steps) {
+            Ok(_) => APPENDED.notify_all(),
+            Err(e) => error!("Cannot write the replication log: {}", e),
+        }
+    }
+}
+
+/// Returns the steps of a transaction: the rows it changed, with the
+/// statements logged by their text in between. The rows a statement
+/// changed are left out, running it again changes them.
+fn merge(statements: Vec<Pending>, rows: &[(&str, &str, &transaction::Change)]) -> Vec<Step> {
+    let row_step = |&(database, table, change): &(&str, &str, &transaction::Change)| match change {
+        &transaction::Change::Inserted(ref row) => Step::Insert {
+            database: database.into(),
+            table: table.into(),
+            row: row.clone(),
+            blobs: Vec::new(),
+        },
+        &transaction::Change::Deleted(ref row) => Step::Delete {
+            database: database.into(),
+            table: table.into(),
+            row: row.clone(),
+        },
+    };
+    let mut steps = Vec::new();
+    let mut next = 0;
+    for statement in statements {
+        let before = statement.rows_before.min(rows.len());
+        if next < before {
+            steps.extend(rows[next..before].iter().map(&row_step));
+            next = before;
+        }
+        steps.push(Step::Query {
+            database: statement.database,
+            query: statement.query,
+        });
+        next = next.max(statement.rows_after.unwrap_or(rows.len()).min(rows.len()));
+    }
+    steps.extend(rows[next..].iter().map(&row_step));
+    steps
+}
+
+/// Adds the values of the BLOB columns kept outside of the inserted rows,
+/// the follower keeps them in a file of its own.
+fn add_blobs(steps: &mut [Step]) {
+    let mut start = 0;
+    while start < steps.len() {
+        let names = match row_table(&steps[start]) {
+            Some((database, table)) if is_insert(&steps[start]) => {
+                (database.to_string(), table.to_string())
+            }
+            _ => {
+                start += 1;
+                continue;
+            }
+        };
+        let end = start
+            + steps[start..]
+                .iter()
+                .take_while(|s| is_insert(s) && row_table(s) == Some((&names.0[..], &names.1[..])))
+                .count();
+        let base = storage::Database::load(&names.0);
+        let table = base.as_ref().ok().and_then(|b| b.load_table(&names.1).ok());
+        if let Some(table) = table {
+            if table.columns().iter().any(|c| c.sql_type == SqlType::Blob) {
+                for step in &mut steps[start..end] {
+                    if let &mut Step::Insert {
+                        ref row,
+                        ref mut blobs,
+                        ..
+                    } = step
+                    {
+                        match blob::overflow_values(&table, row) {
+                            Ok(values) => *blobs = values,
+                            Err(e) => error!("Cannot read a BLOB to replicate: {:?}", e),
+                        }
+                    }
+                }
+            }
+        }
+        start = end;
+    }
+}
+
+/// Returns the database and the table of a step changing a row.
+fn row_table(step: &Step) -> Option<(&str, &str)> {
+    match step {
+        &Step::Insert {
+            ref database,
+            ref table,
+            ..
+        }
+        | &Step::Delete {
+            ref database,
+            ref table,
+            ..
+        } => Some((database, table)),
+        &Step::Query { .. } => None,
+    }
+}
+
+fn is_insert(step: &Step) -> bool {
+    match step {
+        &Step::Insert { .. } => true,
+        _ => false,
+    }
+}
+
+/// Drops the statements of the open transaction of a closed connection.
+pub fn forget(connection: u64) {
+    PENDING.lock().unwrap().retain(|&(id, _)| id != connection);
+}
+
+/// Streams the changes from the LSN `from` on to a follower, answering its
+/// `Command::Replicate`, until the connection is killed or the follower
+/// stops reading.
+pub fn stream(stream: &mut net::Stream, from: u64, process: &process::Handle) {
+    let (path, last_lsn, cipher) = match *LOG.lock().unwrap() {
+        Some(ref log) => (log.path.clone(), log.last_lsn, log.cipher.clone()),
+        None => return refuse(stream, net::Error::ReplicationOff, process),
+    };
+    if from == 0 || from > last_lsn + 1 {
+        return refuse(stream, net::Error::UnknownLsn, process);
+    }
+    let mut reader = match File::open(&path) {
+        Ok(file) => BufReader::new(file),
+        Err(e) => {
+            error!("Cannot read the replication log: {}", e);
+            return refuse(stream, net::Error::ReplicationOff, process);
+        }
+    };
+    info!(
+        "Connection {} replicates the changes from {} on",
+        process.id(),
+        from
+    );
+
+    // LSN of the last change read from the file
+    let mut read = 0;
+    let mut line = String::new();
+    while !process.is_killed() {
+        match reader.read_line(&mut line) {
+            // the last line may not be written completely yet
+            Ok(_) if line.ends_with('\n') => {
+                let text = unseal(cipher.as_ref(), &line).unwrap_or_default();
+                line.clear();
+                let lsn = serde_json::from_str::<Position>(&text).map(|p| p.lsn);
+                if let Ok(lsn) = lsn {
+                    read = lsn;
+                }
+                let change = match lsn {
+                    Ok(lsn) if lsn >= from => Some(serde_json::from_str::<Change>(&text)),
+                    _ => None,
+                };
+                match change {
+                    Some(Ok(change)) => {
+                        let _writing = process.lock_writes();
+                        if net::send_change(stream, &change).is_err() {
+                            break;
+                        }
+                    }
+                    Some(Err(e)) => warn!("Skipping change {} of an older version: {}", read, e),
+                    None => {}
+                }
+                continue;
+            }
+            Ok(_) => {}
+            Err(e) => {
+                error!("Cannot read the replication log: {}", e);
+                break;
+            }
+        }
+
+        let log = LOG.lock().unwrap();
+        let last_lsn = log.as_ref().map_or(0, |log| log.last_lsn);
+        if last_lsn > read {
+            continue;
+        }
+        let (log, waited) = APPENDED.wait_timeout(log, HEARTBEAT_INTERVAL).unwrap();
+        if waited.timed_out() {
+            let heartbeat = Heartbeat {
+                lsn: log.as_ref().map_or(0, |log| log.last_lsn),
+                time: now(),
+            };
+            drop(log);
+            let _writing = process.lock_writes();
+            if net::send_heartbeat(stream, &heartbeat).is_err() {
+                break;
+            }
+        }
+    }
+    info!("Connection {} stopped replicating.", process.id());
+}
+
+/// Answers `Command::Replicate` with the error.
+fn refuse(stream: &mut net::Stream, error: net::Error, process: &process::Handle) {
+    let _writing = process.lock_writes();
+    if net::send_error_package(stream, error.into()).is_err() {
+        warn!("Failed to send packet.");
+    }
+}
+
+/// How far a follower is, shown by `SHOW REPLICA STATUS`
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReplicaStatus {
+    /// Address of the leader
+    pub leader: String,
+    /// `connecting`, `streaming` or `disconnected`
+    pub state: &'static str,
+    /// LSN of the last change applied
+    pub applied_lsn: u64,
+    /// LSN of the last change of the leader, as far as known
+    pub leader_lsn: u64,
+    /// Milliseconds since the Unix epoch when the leader logged the last
+    /// change applied, `0` if unknown
+    pub applied_time: u64,
+    /// Why the connection to the leader failed the last time
+    pub last_error: Option<String>,
+}
+
+impl ReplicaStatus {
+    /// Returns the number of changes not applied yet.
+    pub fn lag_changes(&self) -> u64 {
+        self.leader_lsn.saturating_sub(self.applied_lsn)
+    }
+
+    /// Returns the seconds since the leader logged the last change applied,
+    /// while there are changes left to apply.
+    pub fn lag_seconds(&self) -> u64 {
+        if self.lag_changes() == 0 || self.applied_time == 0 {
+            return 0;
+        }
+        now().saturating_sub(self.applied_time) / 1000
+    }
+}
+
+/// Returns the state of this server as a follower, `None` if it follows no
+/// leader.
+pub fn status() -> Option<ReplicaStatus> {
+    FOLLOWER.lock().unwrap().clone()
+}
+
+/// Returns true if the connection may not change anything, as this server
+/// is a follower and the connection does not apply the changes of the
+/// leader.
+pub fn is_read_only(connection: u64) -> bool {
+    FOLLOWER.lock().unwrap().is_some() && APPLIER.load(Ordering::SeqCst) != connection
+}
+
+/// Why a follower lost its leader
+#[derive(Debug)]
+enum Error {
+    Net(net::Error),
+    /// The leader does not accept the password
+    AccessDenied,
+    /// The leader answered with an error, holds its message
+    Refused(String),
+}
+
+impl From<net::Error> for Error {
+    fn from(error: net::Error) -> Error {
+        Error::Net(error)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Error {
+        Error::Net(error.into())
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &Error::Net(ref e) => write!(f, "{}", e),
+            &Error::AccessDenied => write!(f, "the leader denied access"),
+            &Error::Refused(ref msg) => write!(f, "the leader refused: {}", msg),
+        }
+    }
+}
+
+/// Makes this server a follower of the leader at the address, see the
+/// module documentation. Returns at once, the changes are applied by a
+/// thread of their own.
+pub fn spawn_follower(leader: &str, password: &str) {
+    let applied_lsn = fs::read_to_string(APPLIED_FILE)
+        .ok()
+        .and_then(|text| text.trim().parse().ok())
+        .unwrap_or(0);
+    *FOLLOWER.lock().unwrap() = Some(ReplicaStatus {
+        leader: leader.into(),
+        state: "connecting",
+        applied_lsn: applied_lsn,
+        leader_lsn: applied_lsn,
+        applied_time: 0,
+        last_error: None,
+    });
+    info!(
+        "Following the leader at {}, last change applied {}",
+        leader, applied_lsn
+    );
+    let (leader, password) = (leader.to_string(), password.to_string());
+    thread::spawn(move || {
+        while !shutdown::is_shutting_down() {
+            let error = follow(&leader, &password);
+            if shutdown::is_shutting_down() {
+                break;
+            }
+            warn!("Lost the leader at {}: {}", leader, error);
+            update(|status| {
+                status.state = "disconnected";
+                status.last_error = Some(error.to_string());
+            });
+            thread::sleep(RETRY_INTERVAL);
+            update(|status| status.state = "connecting");
+        }
+    });
+}
+
+/// Changes the state of the follower.
+fn update<F: FnOnce(&mut ReplicaStatus)>(change: F) {
+    if let Some(ref mut status) = *FOLLOWER.lock().unwrap() {
+        change(status);
+    }
+}
+
+/// Connects to the leader and applies its changes until the connection
+/// fails, returns why.
+fn follow(leader: &str, password: &str) -> Error {
+    let applied_lsn = status().map_or(0, |status| status.applied_lsn);
+    let mut stream = match connect(leader, password, applied_lsn + 1) {
+        Ok(stream) => stream,
+        Err(e) => return e,
+    };
+    let process = match process::register(
+        auth::ADMIN_NAME,
+        "replication",
+        None,
+        process::Limits::default(),
+    ) {
+        Ok(process) => process,
+        Err(e) => return Error::Refused(format!("{:?}", e)),
+    };
+    APPLIER.store(process.id(), Ordering::SeqCst);
+    update(|status| status.state = "streaming");
+    info!("Streaming the changes of the leader at {}", leader);
+    let error = loop {
+        if process.is_killed() {
+            break Error::Refused("the server is shutting down".into());
+        }
+        let packet = match net::read_packet(&mut stream, net::MAX_PACKET_SIZE) {
+            Ok(packet) => packet,
+            Err(e) => break e.into(),
+        };
+        match packet.pkg {
+            PkgType::Change => match net::deserialize_from(packet.payload()) {
+                Ok(change) => apply(&change, &process),
+                Err(e) => break net::Error::from(e).into(),
+            },
+            PkgType::Heartbeat => match net::deserialize_from::<_, Heartbeat>(packet.payload()) {
+                Ok(heartbeat) => update(|status| status.leader_lsn = heartbeat.lsn),
+                Err(e) => break net::Error::from(e).into(),
+            },
+            PkgType::Error => {
+                let msg = net::deserialize_from::<_, ClientErrMsg>(packet.payload())
+                    .map(|e| e.msg)
+                    .unwrap_or_default();
+                break Error::Refused(msg);
+            }
+            // sent to every connection, nothing a follower has to know
+            PkgType::Notification | PkgType::ShuttingDown => {}
+            _ => break net::Error::UnexpectedPkg.into(),
+        }
+    };
+    APPLIER.store(0, Ordering::SeqCst);
+    error
+}
+
+/// Logs in to the leader and asks it for the changes from the LSN `from`
+/// on.
+fn connect(leader: &str, password: &str, from: u64) -> Result<TcpStream, Error> {
+    let mut stream = try!(TcpStream::connect(leader));
+    // the leader sends a heartbeat at least every interval
+    try!(stream.set_read_timeout(Some(HEARTBEAT_INTERVAL * 5)));
+    let greeting = try!(net::read_packet(&mut stream, net::MAX_PACKET_SIZE));
+    if greeting.pkg != PkgType::Greet {
+        return Err(net::Error::UnexpectedPkg.into());
+    }
+
+    let login = Login {
+        username: auth::ADMIN_NAME.into(),
+        password: password.into(),
+        min_version: net::MIN_PROTOCOL_VERSION,
+        max_version: net::PROTOCOL_VERSION,
+        capabilities: Capabilities::NONE,
+        max_packet_size: net::MAX_PACKET_SIZE,
+    };
+    let mut packet = PacketWriter::new(PkgType::Login);
+    try!(packet.add(&login));
+    try!(packet.send(&mut stream));
+    let answer = try!(net::read_packet(&mut stream, net::MAX_PACKET_SIZE));
+    match answer.pkg {
+        PkgType::AccGranted => {}
+        PkgType::AccDenied => return Err(Error::AccessDenied),
+        PkgType::Error => {
+            let error: ClientErrMsg =
+                try!(net::deserialize_from(answer.payload()).map_err(net::Error::from));
+            return Err(Error::Refused(error.msg));
+        }
+        _ => return Err(net::Error::UnexpectedPkg.into()),
+    }
+
+    let mut packet = PacketWriter::new(PkgType::Command);
+    try!(packet.add(&Command::Replicate(from)));
+    try!(packet.send(&mut stream));
+    Ok(stream)
+}
+
+/// Applies the steps of a change as the administrator and remembers it as
+/// applied, unless it was applied before. A step failing is logged, as the
+/// follower cannot do anything about it.
+fn apply(change: &Change, process: &process::Handle) {
+    if change.lsn <= status().map_or(0, |status| status.applied_lsn) {
+        debug!(
+            "Skipping change {} of the leader, applied before",
+            change.lsn
+        );
+        return;
+    }
+    let mut user = auth::User {
+        _name: auth::ADMIN_NAME.into(),
+        _currentDatabase: None,
+    };
+    let mut start = 0;
+    while start < change.steps.len() {
+        let end = match change.steps[start] {
+            Step::Query {
+                ref database,
+                ref query,
+            } => {
+                user._currentDatabase = database
+                    .as_ref()
+                    .and_then(|d| storage::Database::load(d).ok());
+                process.set_database(database.as_ref().map(|d| &d[..]));
+                if let Err(e) = conn::run_query(query, &mut user, process) {
+                    warn!("A statement of change {} failed: {:?}", change.lsn, e);
+                }
+                start + 1
+            }
+            _ => {
+                let rows = change.steps[start..]
+                    .iter()
+                    .take_while(|s| row_table(s).is_some())
+                    .count();
+                if let Err(e) = apply_rows(&change.steps[start..start + rows], process.id()) {
+                    warn!("The rows of change {} failed: {:?}", change.lsn, e);
+                }
+                start + rows
+            }
+        };
+        start = end;
+    }
+
+    let written = fs::write(APPLIED_FILE.to_string() + ".tmp", change.lsn.to_string())
+        .and_then(|_| fs::rename(APPLIED_FILE.to_string() + ".tmp", APPLIED_FILE));
+    if let Err(e) = written {
+        error!("Cannot remember the change applied: {}", e);
+    }
+    update(|status| {
+        status.applied_lsn = change.lsn;
+        status.leader_lsn = status.leader_lsn.max(change.lsn);
+        status.applied_time = change.time;
+    });
+}
+
+/// Applies steps changing rows in a transaction of the connection.
+fn apply_rows(steps: &[Step], connection: u64) -> Result<(), storage::Error> {
+    let mut statement = transaction::start_statement(connection);
+    let result = replay_rows(steps, &statement);
+    try!(statement.end(result.is_ok()));
+    result
+}
+
+/// Replaces the rows with the primary keys of the rows of the steps, see
+/// `mvcc::replay`.
+fn replay_rows(mut steps: &[Step], statement: &Statement) -> Result<(), storage::Error> {
+    while let Some((database, table)) = steps.first().and_then(row_table) {
+        let count = steps
+            .iter()
+            .take_while(|s| row_table(s) == Some((database, table)))
+            .count();
+        let base = try!(storage::Database::load(database));
+        let table = try!(base.load_table(table));
+        try!(statement.lock(&base.name, &table.name, Mode::Shared, || false));
+        let mut engine = Versioned::new(table.create_engine(), statement);
+        for step in &steps[..count] {
+            let change = match step {
+                &Step::Insert {
+                    ref row, ref blobs, ..
+                } => {
+                    let mut row = row.clone();
+                    try!(blob::store_overflow_values(engine.table(), &mut row, blobs));
+                    transaction::Change::Inserted(row)
+                }
+                &Step::Delete { ref row, .. } => transaction::Change::Deleted(row.clone()),
+                &Step::Query { .. } => continue,
+            };
+            try!(mvcc::replay(&mut engine, &change));
+        }
+        steps = &steps[count..];
+    }
+    Ok(())
+}
+
+/// Returns the milliseconds since the Unix epoch.
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|t| t.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[test]
+fn test_replication_log() {
+    let path = Path::new("test_replication.log");
+    let _ = fs::remove_file(path);
+    let query = |text: &str| Step::Query {
+        database: None,
+        query: text.into(),
+    };
+    {
+        let mut log = Log::open(path, None).unwrap();
+        assert_eq!(log.last_lsn, 0);
+        let change = log.append(vec![query("create database d")]).unwrap();
+        assert_eq!(change.lsn, 1);
+        let row = Step::Delete {
+            database: "d".into(),
+            table: "t".into(),
+            row: vec![1, 2],
+        };
+        log.append(vec![row]).unwrap();
+    }
+    // a line cut off by a crash is skipped
+    OpenOptions::new()
+        .append(true)
+        .open(path)
+        .unwrap()
+        .write_all(b"{\"lsn\":3,")
+        .unwrap();
+    let mut log = Log::open(path, None).unwrap();
+    assert_eq!(log.last_lsn, 2);
+    assert_eq!(log.append(vec![]).unwrap().lsn, 3);
+    // the LSNs go on after a change of an older version
+    log.file
+        .write_all(b"{\"lsn\":4,\"time\":0,\"queries\":[]}\n")
+        .unwrap();
+    assert_eq!(Log::open(path, None).unwrap().last_lsn, 4);
+    let text = fs::read_to_string(path).unwrap();
+    let changes: Vec<Change> = text
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect();
+    assert_eq!(
+        changes.iter().map(|c| c.lsn).collect::<Vec<_>>(),
+        vec![1, 2, 3]
+    );
+    assert_eq!(changes[0].steps, vec![query("create database d")]);
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_replication_steps() {
+    use storage::transaction::Change::{Deleted, Inserted};
+
+    let (a, b, c) = (Inserted(vec![1]), Deleted(vec![2]), Inserted(vec![3]));
+    let rows = vec![("shop", "t", &a), ("shop", "t", &b), ("shop", "u", &c)];
+    let statement = |text: &str, before, after| Pending {
+        database: Some("shop".into()),
+        query: text.into(),
+        rows_before: before,
+        rows_after: after,
+    };
+    let query = |text: &str| Step::Query {
+        database: Some("shop".into()),
+        query: text.into(),
+    };
+    let insert = |table: &str, row| Step::Insert {
+        database: "shop".into(),
+        table: table.into(),
+        row: vec![row],
+        blobs: vec![],
+    };
+    let delete = Step::Delete {
+        database: "shop".into(),
+        table: "t".into(),
+        row: vec![2],
+    };
+
+    // rows only
+    assert_eq!(
+        merge(vec![], &rows),
+        vec![insert("t", 1), delete.clone(), insert("u", 3)]
+    );
+    // statements in their place, the rows they changed left out
+    assert_eq!(
+        merge(
+            vec![
+                statement("create table u (id int primary key)", 1, Some(1)),
+                statement("alter table t add v int", 1, Some(2)),
+            ],
+            &rows
+        ),
+        vec![
+            insert("t", 1),
+            query("create table u (id int primary key)"),
+            query("alter table t add v int"),
+            insert("u", 3),
+        ]
+    );
+    // the statement running when the transaction ended changed the rest
+    assert_eq!(
+        merge(vec![statement("truncate t", 2, None)], &rows),
+        vec![insert("t", 1), delete, query("truncate t")]
+    );
+}
+
+#[test]
+fn test_sealed_replication_log() {
+    use aes_gcm::aead::KeyInit;
+
+    let path = Path::new("test_sealed_replication.log");
+    let _ = fs::remove_file(path);
+    let cipher = Aes256Gcm::new_from_slice(&[7; encrypt::KEY_SIZE]).unwrap();
+    let step = Step::Insert {
+        database: "shop".into(),
+        table: "t".into(),
+        row: b"secret row".to_vec(),
+        blobs: vec![],
+    };
+    {
+        let mut log = Log::open(path, Some(cipher.clone())).unwrap();
+        log.append(vec![step.clone()]).unwrap();
+        log.append(vec![]).unwrap();
+    }
+    let text = fs::read_to_string(path).unwrap();
+    assert!(!text.contains("shop"));
+    let row: Vec<String> = b"secret".iter().map(|b| b.to_string()).collect();
+    assert!(!text.contains(&row.join(",")));
+    assert_eq!(Log::open(path, Some(cipher.clone())).unwrap().last_lsn, 2);
+
+    let first = unseal(Some(&cipher), text.lines().next().unwrap()).unwrap();
+    let change: Change = serde_json::from_str(&first).unwrap();
+    assert_eq!(change.steps, vec![step]);
+    // without the key the lines cannot be read
+    assert_eq!(Log::open(path, None).unwrap().last_lsn, 0);
+    fs::remove_file(path).unwrap();
+}
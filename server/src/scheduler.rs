@@ -0,0 +1,174 @@
+//! Scheduler of the events of `CREATE EVENT`
+//!
+//! A thread looks at the events of all databases every `TICK` and runs the
+//! ones that are due, one after another. An event runs every `every`
+//! seconds, counted from when it was created, as the user who created it
+//! and in its database, like a connection of its own: `SHOW PROCESSLIST`
+//! shows it with the host `scheduler` while it runs. Runs missed while the
+//! server was down or another event was running are not made up for, the
+//! event just runs once at its next time.
+//!
+//! When each event ran the last time and how it failed is only kept in
+//! memory, for `SHOW EVENTS`. A follower runs no events, it gets their
+//! changes from its leader, see `replication`.
+
+use auth;
+use conn;
+use process;
+use replication;
+use shutdown;
+use storage::{Database, Event};
+
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How often the scheduler looks for events that are due
+pub const TICK: Duration = Duration::from_secs(1);
+
+/// The last run of every event that ran since the server started
+static RUNS: Mutex<Vec<Run>> = Mutex::new(Vec::new());
+
+/// The last run of an event
+#[derive(Clone, Debug, PartialEq)]
+pub struct Run {
+    pub database: String,
+    pub event: String,
+    /// Seconds since the Unix epoch when it started
+    pub time: u64,
+    /// Why it failed, `None` if it succeeded
+    pub error: Option<String>,
+}
+
+/// Returns the first time the event runs after the given time, in seconds
+/// since the Unix epoch.
+pub fn next_run(event: &Event, after: u64) -> u64 {
+    if after < event.starts {
+        return event.starts + event.every;
+    }
+    event.starts + ((after - event.starts) / event.every + 1) * event.every
+}
+
+/// Returns the last run of the event of the database, if it ran since the
+/// server started.
+pub fn last_run(database: &str, event: &str) -> Option<Run> {
+    RUNS.lock()
+        .unwrap()
+        .iter()
+        .find(|r| r.database == database && r.event == event)
+        .cloned()
+}
+
+/// Starts the thread running the events until the server shuts down.
+pub fn spawn() {
+    thread::spawn(|| {
+        let mut checked = now();
+        while !shutdown::is_shutting_down() {
+            thread::sleep(TICK);
+            // the changes of the events come from the leader
+            if replication::status().is_some() {
+                continue;
+            }
+            let time = now();
+            for (database, event) in due(checked, time) {
+                run(&database, &event);
+            }
+            checked = time;
+        }
+    });
+}
+
+/// Returns the events of all databases that are due to run after the time
+/// `checked` up to `time`.
+fn due(checked: u64, time: u64) -> Vec<(String, Event)> {
+    let databases = match Database::list() {
+        Ok(databases) => databases,
+        Err(e) => {
+            warn!("Cannot list the databases for their events: {:?}", e);
+            return Vec::new();
+        }
+    };
+    let mut due = Vec::new();
+    for name in databases {
+        let meta = match Database::load(&name).and_then(|base| base.load_meta_data()) {
+            Ok(meta) => meta,
+            Err(_) => continue,
+        };
+        for event in meta.events {
+            if next_run(&event, checked) <= time {
+                due.push((name.clone(), event));
+            }
+        }
+    }
+    due
+}
+
+/// Runs the statement of the event and remembers the run.
+fn run(database: &str, event: &Event) {
+    debug!("Running event '{}' of database '{}'", event.name, database);
+    let time = now();
+    let error = execute(database, event).err();
+    if let Some(ref e) = error {
+        warn!(
+            "Event '{}' of database '{}' failed: {}",
+            event.name, database, e
+        );
+    }
+    let mut runs = RUNS.lock().unwrap();
+    runs.retain(|r| r.database != database || r.event != event.name);
+    runs.push(Run {
+        database: database.into(),
+        event: event.name.clone(),
+        time: time,
+        error: error,
+    });
+}
+
+/// Executes the statement of the event as its definer.
+fn execute(database: &str, event: &Event) -> Result<(), String> {
+    // the privileges of a dropped user are gone, but not their events
+    if !auth::user_exists(&event.definer).unwrap_or(false) {
+        return Err(format!("the definer '{}' does not exist", event.definer));
+    }
+    let base = try!(Database::load(database).map_err(|e| format!("{:?}", e)));
+    let process = try!(process::register(
+        &event.definer,
+        "scheduler",
+        None,
+        process::Limits::default()
+    )
+    .map_err(|e| format!("{:?}", e)));
+    process.set_database(Some(database));
+    let mut user = auth::User {
+        _name: event.definer.clone(),
+        _currentDatabase: Some(base),
+    };
+    conn::run_query(&event.body, &mut user, &process)
+        .map(|_| ())
+        .map_err(|e| format!("{:?}", e))
+}
+
+/// Returns the seconds since the Unix epoch.
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|t| t.as_secs())
+        .unwrap_or(0)
+}
+
+#[test]
+fn test_next_run() {
+    let event = Event {
+        name: "cleanup".into(),
+        every: 60,
+        starts: 1000,
+        definer: "admin".into(),
+        body: "delete from logs".into(),
+    };
+    assert_eq!(next_run(&event, 0), 1060);
+    assert_eq!(next_run(&event, 1000), 1060);
+    assert_eq!(next_run(&event, 1059), 1060);
+    assert_eq!(next_run(&event, 1060), 1120);
+    // runs missed in between are skipped
+    assert_eq!(next_run(&event, 5000), 5020);
+}
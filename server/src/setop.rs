@@ -0,0 +1,352 @@
+//! Set operations of selects
+//!
+//! `UNION`, `INTERSECT` and `EXCEPT` combine the rows of two selects with
+//! the same number of columns. The columns of both sides need compatible
+//! types, see `common_type`, values are converted to the common one. The
+//! columns of the result are named like those of the left side.
+//!
+//! `UNION ALL` appends the rows of the right side to those of the left one.
+//! The other operations return every row only once: they sort their inputs
+//! by all columns, see `sort`, so that large ones are sorted on disk, and
+//! walk through them side by side. Their result is ordered by the columns.
+//! Null values are equal to each other here, unlike in comparisons.
+
+use parse::ast::SetOp;
+use parse::token::Lit;
+use sort::{self, Key, SortKey};
+use spill;
+use storage::decimal;
+use storage::types;
+use storage::{Column, Error, Rows, SqlType};
+
+use std::cmp::Ordering;
+use std::io::Cursor;
+
+/// Returns the columns of the result of a set operation, or None if the
+/// sides differ in their number of columns or the types of a column do not
+/// fit together.
+pub fn result_columns(left: &[Column], right: &[Column]) -> Option<Vec<Column>> {
+    if left.len() != right.len() {
+        return None;
+    }
+    left.iter()
+        .zip(right)
+        .map(|(l, r)| {
+            common_type(l.sql_type, r.sql_type).map(|sql_type| {
+                let mut column = l.clone();
+                column.sql_type = sql_type;
+                column.allow_null = l.allow_null || r.allow_null;
+                column.is_primary_key = false;
+                column
+            })
+        })
+        .collect()
+}
+
+/// Returns a type holding the values of both types: the longer of two texts
+/// or binaries, a decimal for integers and decimals, a double for other
+/// numbers and a timestamp for dates and timestamps. None if there is none.
+pub fn common_type(a: SqlType, b: SqlType) -> Option<SqlType> {
+    if a == b {
+        return Some(a);
+    }
+    if a.is_text() && b.is_text() {
+        return Some(match (text_length(a), text_length(b)) {
+            (Some(x), Some(y)) => SqlType::VarChar(x.max(y)),
+            _ => SqlType::Text,
+        });
+    }
+    Some(match (a, b) {
+        (SqlType::VarBinary(x), SqlType::VarBinary(y)) => SqlType::VarBinary(x.max(y)),
+        (SqlType::Date, SqlType::Timestamp) | (SqlType::Timestamp, SqlType::Date) => {
+            SqlType::Timestamp
+        }
+        (SqlType::Int, SqlType::BigInt) | (SqlType::BigInt, SqlType::Int) => SqlType::BigInt,
+        _ => match (digits(a), digits(b)) {
+            (Some((x_int, x_scale)), Some((y_int, y_scale))) => {
+                let scale = x_scale.max(y_scale);
+                let precision = (x_int.max(y_int) + scale).min(decimal::MAX_PRECISION);
+                SqlType::Decimal(precision, scale)
+            }
+            // floats take any number
+            _ if is_number(a) && is_number(b) => SqlType::Double,
+            _ => return None,
+        },
+    })
+}
+
+/// Returns the maximum length of a text type in bytes, None for TEXT.
+fn text_length(sql_type: SqlType) -> Option<u16> {
+    match sql_type {
+        SqlType::Char(len) => Some(len as u16),
+        SqlType::VarChar(len) => Some(len),
+        _ => None,
+    }
+}
+
+/// Returns the digits before and after the point of an exact number type.
+fn digits(sql_type: SqlType) -> Option<(u8, u8)> {
+    match sql_type {
+        SqlType::Int => Some((10, 0)),
+        SqlType::BigInt => Some((19, 0)),
+        SqlType::Decimal(precision, scale) => Some((precision - scale, scale)),
+        _ => None,
+    }
+}
+
+fn is_number(sql_type: SqlType) -> bool {
+    digits(sql_type).is_some() || sql_type == SqlType::Float || sql_type == SqlType::Double
+}
+
+/// Combines the rows of two selects into rows of `columns`, as returned by
+/// `result_columns`. `check` is called for every row, e.g. to stop the
+/// statement if it is cancelled.
+pub fn combine<E, F>(
+    op: SetOp,
+    columns: &[Column],
+    left: Rows<Cursor<Vec<u8>>>,
+    right: Rows<Cursor<Vec<u8>>>,
+    mut check: F,
+) -> Result<Rows<Cursor<Vec<u8>>>, E>
+where
+    E: From<Error>,
+    F: FnMut() -> Result<(), E>,
+{
+    let mut result = Rows::new(Cursor::new(Vec::new()), columns);
+    let keys: Vec<_> = (0..columns.len())
+        .map(|i| SortKey {
+            column: i,
+            descending: false,
+        })
+        .collect();
+    if op == SetOp::UnionAll || op == SetOp::Union {
+        try!(append(&mut result, left, &mut check));
+        try!(append(&mut result, right, &mut check));
+        if op == SetOp::UnionAll {
+            return Ok(result);
+        }
+        let sorted = try!(sort::sort(
+            result,
+            &keys,
+            spill::memory_budget(),
+            &mut check
+        ));
+        let mut rows = try!(Distinct::new(sorted, &keys));
+        let mut distinct = Rows::new(Cursor::new(Vec::new()), columns);
+        while let Some((_, row)) = try!(rows.next()) {
+            try!(check());
+            try!(distinct.add_row(&row));
+        }
+        return Ok(distinct);
+    }
+
+    let mut sides = Vec::new();
+    for input in vec![left, right] {
+        let mut converted = Rows::new(Cursor::new(Vec::new()), columns);
+        try!(append(&mut converted, input, &mut check));
+        let sorted = try!(sort::sort(
+            converted,
+            &keys,
+            spill::memory_budget(),
+            &mut check
+        ));
+        sides.push(try!(Distinct::new(sorted, &keys)));
+    }
+    let mut right = sides.pop().unwrap();
+    let mut left = sides.pop().unwrap();
+    let mut a = try!(left.next());
+    let mut b = try!(right.next());
+    loop {
+        try!(check());
+        let ordering = match (&a, &b) {
+            (&None, _) => break,
+            (&Some(_), &None) => Ordering::Less,
+            (&Some((ref x, _)), &Some((ref y, _))) => sort::compare(&keys, x, y),
+        };
+        if ordering != Ordering::Greater {
+            // a row of both sides for INTERSECT, one only of the left for
+            // EXCEPT
+            if (ordering == Ordering::Equal) == (op == SetOp::Intersect) {
+                try!(result.add_row(&a.as_ref().unwrap().1));
+            }
+            a = try!(left.next());
+        }
+        if ordering != Ordering::Less {
+            b = try!(right.next());
+        }
+    }
+    Ok(result)
+}
+
+/// Appends the rows of a select to `result`, with their values converted to
+/// the types of its columns.
+fn append<E, F>(
+    result: &mut Rows<Cursor<Vec<u8>>>,
+    mut input: Rows<Cursor<Vec<u8>>>,
+    check: &mut F,
+) -> Result<(), E>
+where
+    E: From<Error>,
+    F: FnMut() -> Result<(), E>,
+{
+    let columns = result.columns.clone();
+    try!(input.reset_pos());
+    loop {
+        try!(check());
+        let mut row = Vec::new();
+        match input.next_row(&mut row) {
+            Ok(_) => (),
+            Err(Error::EndOfFile) => break,
+            Err(e) => return Err(e.into()),
+        }
+        let mut values = Vec::with_capacity(columns.len());
+        for (i, column) in columns.iter().enumerate() {
+            values.push(try!(convert(&input.columns, &row, i, column.sql_type)));
+        }
+        try!(result.add_values(&values));
+    }
+    Ok(())
+}
+
+/// Returns the value of a column of a row as a value of another type, None
+/// if it is null.
+fn convert(
+    columns: &[Column],
+    row: &[u8],
+    i: usize,
+    sql_type: SqlType,
+) -> Result<Option<Vec<u8>>, Error> {
+    if types::is_null(columns, row, i) {
+        return Ok(None);
+    }
+    let value = &row[types::column_range(columns, i)];
+    let from = columns[i].sql_type;
+    if from == sql_type {
+        return Ok(Some(value.to_vec()));
+    }
+    let lit = if from.is_text() {
+        let text = String::from_utf8_lossy(from.content(value)).into_owned();
+        Lit::String(text)
+    } else {
+        try!(from.decode_from(&mut &value[..]))
+    };
+    let mut data = Vec::new();
+    try!(sql_type.encode_into(&mut data, &lit));
+    Ok(Some(data))
+}
+
+/// Reads sorted rows with their keys, skipping those equal to the row
+/// before.
+struct Distinct<'a> {
+    rows: Rows<Cursor<Vec<u8>>>,
+    keys: &'a [SortKey],
+    last: Option<Key>,
+}
+
+impl<'a> Distinct<'a> {
+    fn new(mut rows: Rows<Cursor<Vec<u8>>>, keys: &'a [SortKey]) -> Result<Distinct<'a>, Error> {
+        try!(rows.reset_pos());
+        Ok(Distinct {
+            rows: rows,
+            keys: keys,
+            last: None,
+        })
+    }
+
+    fn next(&mut self) -> Result<Option<(Key, Vec<u8>)>, Error> {
+        loop {
+            let mut row = Vec::new();
+            match self.rows.next_row(&mut row) {
+                Ok(_) => (),
+                Err(Error::EndOfFile) => return Ok(None),
+                Err(e) => return Err(e),
+            }
+            let key = sort::sort_key(&self.rows.columns, self.keys, &row);
+            if self.last.as_ref() != Some(&key) {
+                self.last = Some(key.clone());
+                return Ok(Some((key, row)));
+            }
+        }
+    }
+}
+
+#[test]
+fn test_common_type() {
+    assert_eq!(
+        common_type(SqlType::Char(10), SqlType::VarChar(5)),
+        Some(SqlType::VarChar(10))
+    );
+    assert_eq!(
+        common_type(SqlType::VarChar(5), SqlType::Text),
+        Some(SqlType::Text)
+    );
+    assert_eq!(
+        common_type(SqlType::Int, SqlType::BigInt),
+        Some(SqlType::BigInt)
+    );
+    assert_eq!(
+        common_type(SqlType::Int, SqlType::Decimal(5, 2)),
+        Some(SqlType::Decimal(12, 2))
+    );
+    assert_eq!(
+        common_type(SqlType::Float, SqlType::Int),
+        Some(SqlType::Double)
+    );
+    assert_eq!(
+        common_type(SqlType::Date, SqlType::Timestamp),
+        Some(SqlType::Timestamp)
+    );
+    assert_eq!(common_type(SqlType::Int, SqlType::VarChar(5)), None);
+    assert_eq!(common_type(SqlType::Bool, SqlType::Int), None);
+}
+
+#[test]
+fn test_combine() {
+    let rows = |sql_type: SqlType, values: &[Option<i64>]| {
+        let columns = vec![Column::new("a", sql_type, true, "", false)];
+        let mut rows = Rows::new(Cursor::new(Vec::new()), &columns);
+        for value in values {
+            let encoded = value.map(|v| {
+                let mut data = Vec::new();
+                sql_type.encode_into(&mut data, &Lit::Int(v)).unwrap();
+                data
+            });
+            rows.add_values(&[encoded]).unwrap();
+        }
+        rows
+    };
+    let values = |mut rows: Rows<Cursor<Vec<u8>>>| {
+        rows.reset_pos().unwrap();
+        let mut all = Vec::new();
+        let mut row = Vec::new();
+        while rows.next_row(&mut row).is_ok() {
+            all.push(match rows.get_nullable_value(&row, 0).unwrap() {
+                Some(v) => match rows.columns[0].sql_type.decode_from(&mut &v[..]).unwrap() {
+                    Lit::Int(i) => Some(i),
+                    _ => panic!("no integer"),
+                },
+                None => None,
+            });
+            row.clear();
+        }
+        all
+    };
+    let left = &[Some(3), Some(1), None, Some(3), Some(2)];
+    let right = &[Some(2), None, Some(4)];
+    let combined = |op| {
+        let (left, right) = (rows(SqlType::Int, left), rows(SqlType::BigInt, right));
+        let columns = result_columns(&left.columns, &right.columns).unwrap();
+        assert_eq!(columns[0].sql_type, SqlType::BigInt);
+        let no_check = || -> Result<(), Error> { Ok(()) };
+        values(combine(op, &columns, left, right, no_check).unwrap())
+    };
+
+    assert_eq!(combined(SetOp::UnionAll).len(), 8);
+    // nulls are equal and come last
+    assert_eq!(
+        combined(SetOp::Union),
+        vec![Some(1), Some(2), Some(3), Some(4), None]
+    );
+    assert_eq!(combined(SetOp::Intersect), vec![Some(2), None]);
+    assert_eq!(combined(SetOp::Except), vec![Some(1), Some(3)]);
+}
@@ -0,0 +1,92 @@
+//! Graceful shutdown of the server
+//!
+//! `SIGTERM`, `SIGINT` and `ADMIN SHUTDOWN` shut the server down, see
+//! `begin`. From then on the listeners close every new connection right
+//! away. The connections of the native protocol are warned by a
+//! `PkgType::ShuttingDown` package, and running queries get the
+//! `shutdown_timeout` of the configuration to finish. The queries still
+//! running then are cancelled and all connections are closed, like by
+//! `KILL`.
+//!
+//! There is no write-ahead log to flush: statements write their changes to
+//! the tables themselves, and the undo logs of open transactions only live
+//! in memory. A closed connection rolls its transaction back, so the server
+//! waits for the connections to close before it exits, at most
+//! `CLOSE_TIMEOUT`, and then rolls back those of the suspended sessions.
+//! A second signal exits at once.
+
+use config;
+use net::types::ShutdownWarning;
+use process;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Time the connections get to close once they were killed
+pub const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Time between looking for running queries and open connections
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+static SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+
+/// Returns true once the server shuts down.
+pub fn is_shutting_down() -> bool {
+    SHUTTING_DOWN.load(Ordering::SeqCst)
+}
+
+/// Shuts the server down in the background and exits the process at the
+/// end. Returns false if the server already shuts down.
+pub fn begin(reason: &str) -> bool {
+    if SHUTTING_DOWN.swap(true, Ordering::SeqCst) {
+        return false;
+    }
+    let grace_period = config::current().shutdown_timeout();
+    info!(
+        "Shutting down on {}, running queries have {} s to finish",
+        reason,
+        grace_period.as_secs()
+    );
+    thread::spawn(move || {
+        drain(grace_period);
+        info!("Shutdown complete");
+        ::std::process::exit(0);
+    });
+    true
+}
+
+/// Warns the connections, waits for the running queries and closes the
+/// connections.
+fn drain(grace_period: Duration) {
+    let warned = process::warn_shutdown(&ShutdownWarning {
+        grace_period: grace_period.as_secs(),
+    });
+    debug!("warned {} connections of the shutdown", warned);
+
+    let idle = || process::list().iter().all(|p| p.query.is_none());
+    if !wait(grace_period, idle) {
+        warn!("Cancelling the queries still running");
+    }
+    process::kill_all();
+    if !wait(CLOSE_TIMEOUT, || process::status().connections == 0) {
+        warn!(
+            "{} connections did not close in time, their transactions are not rolled back",
+            process::status().connections
+        );
+    }
+    process::discard_suspended();
+}
+
+/// Waits until `done` returns true, at most `timeout`. Returns false on a
+/// timeout.
+fn wait<F: Fn() -> bool>(timeout: Duration, done: F) -> bool {
+    let start = Instant::now();
+    while !done() {
+        if start.elapsed() >= timeout {
+            return false;
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+    true
+}
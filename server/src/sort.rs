@@ -0,0 +1,237 @@
+//! Sorting rows for `ORDER BY`
+//!
+//! Rows are sorted by the values of one or more columns, each ascending or
+//! descending. Null values come after all others when ascending and before
+//! them when descending. Rows of equal values keep their order.
+//!
+//! Results that fit into the memory budget are sorted in memory. Larger
+//! ones are sorted by an external merge sort: whenever the rows read take
+//! more than the budget, they are sorted and written to a temporary file as
+//! a run, see `spill`. The runs are then merged by repeatedly taking the
+//! smallest of their first rows.
+
+use spill::{SpillFile, SpillRows};
+use storage::types::{self, row_size};
+use storage::{Column, Error, Rows};
+
+use std::cmp::Ordering;
+use std::io::{Cursor, Write};
+
+/// A column to sort by
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SortKey {
+    pub column: usize,
+    pub descending: bool,
+}
+
+/// The values of the sort keys of a row in the form of `SqlType::sort_key`,
+/// None stands for null
+pub type Key = Vec<Option<Vec<u8>>>;
+
+/// Sorts rows by their keys within the memory budget. `check` is called for
+/// every row, e.g. to stop the sort of a cancelled statement.
+pub fn sort<E, F>(
+    mut rows: Rows<Cursor<Vec<u8>>>,
+    keys: &[SortKey],
+    budget: usize,
+    mut check: F,
+) -> Result<Rows<Cursor<Vec<u8>>>, E>
+where
+    E: From<Error>,
+    F: FnMut() -> Result<(), E>,
+{
+    let columns = rows.columns.clone();
+    let size = row_size(&columns);
+    let mut run = Vec::new();
+    let mut run_size = 0;
+    let mut runs = Vec::new();
+    try!(rows.reset_pos());
+    loop {
+        try!(check());
+        let mut row = Vec::new();
+        match rows.next_row(&mut row) {
+            Ok(_) => (),
+            Err(Error::EndOfFile) => break,
+            Err(e) => return Err(e.into()),
+        }
+        let key = sort_key(&columns, keys, &row);
+        let key_size: usize = key.iter().filter_map(|v| v.as_ref()).map(Vec::len).sum();
+        run_size += size + key_size;
+        run.push((key, row));
+        if run_size > budget {
+            runs.push(try!(write_run(&mut run, keys)));
+            run_size = 0;
+        }
+    }
+    // all rows are in the runs or in memory now
+    drop(rows);
+
+    let mut sorted = Rows::new(Cursor::new(Vec::new()), &columns);
+    if runs.is_empty() {
+        sort_run(&mut run, keys);
+        for (_, row) in run {
+            try!(sorted.add_row(&row));
+        }
+        return Ok(sorted);
+    }
+    if !run.is_empty() {
+        runs.push(try!(write_run(&mut run, keys)));
+    }
+    info!("merging {} sorted runs", runs.len());
+
+    let mut readers = Vec::new();
+    let mut heads = Vec::new();
+    for file in &runs {
+        let mut reader = try!(file.rows(size));
+        heads.push(try!(next_entry(&mut reader, &columns, keys)));
+        readers.push(reader);
+    }
+    loop {
+        try!(check());
+        // the earliest run wins among equal rows, so the sort stays stable
+        let mut smallest: Option<usize> = None;
+        for (i, head) in heads.iter().enumerate() {
+            let key = match *head {
+                Some((ref key, _)) => key,
+                None => continue,
+            };
+            let less = match smallest {
+                Some(s) => compare(keys, key, &heads[s].as_ref().unwrap().0) == Ordering::Less,
+                None => true,
+            };
+            if less {
+                smallest = Some(i);
+            }
+        }
+        let i = match smallest {
+            Some(i) => i,
+            None => break,
+        };
+        let next = try!(next_entry(&mut readers[i], &columns, keys));
+        let (_, row) = heads[i].take().unwrap();
+        try!(sorted.add_row(&row));
+        heads[i] = next;
+    }
+    Ok(sorted)
+}
+
+/// Compares the keys of two rows.
+pub fn compare(keys: &[SortKey], a: &Key, b: &Key) -> Ordering {
+    for (i, key) in keys.iter().enumerate() {
+        let ordering = match (&a[i], &b[i]) {
+            (&Some(ref x), &Some(ref y)) => x.cmp(y),
+            (&Some(_), &None) => Ordering::Less,
+            (&None, &Some(_)) => Ordering::Greater,
+            (&None, &None) => Ordering::Equal,
+        };
+        if ordering != Ordering::Equal {
+            return if key.descending {
+                ordering.reverse()
+            } else {
+                ordering
+            };
+        }
+    }
+    Ordering::Equal
+}
+
+/// Returns the key of a row, the values of its key columns.
+pub fn sort_key(columns: &[Column], keys: &[SortKey], row: &[u8]) -> Key {
+    keys.iter()
+        .map(|key| {
+            let i = key.column;
+            if types::is_null(columns, row, i) {
+                None
+            } else {
+                let value = &row[types::column_range(columns, i)];
+                Some(columns[i].sql_type.sort_key(value))
+            }
+        })
+        .collect()
+}
+
+fn sort_run(run: &mut Vec<(Key, Vec<u8>)>, keys: &[SortKey]) {
+    run.sort_by(|a, b| compare(keys, &a.0, &b.0));
+}
+
+/// Sorts the rows read so far and writes them to a run, leaving `run`
+/// empty.
+fn write_run(run: &mut Vec<(Key, Vec<u8>)>, keys: &[SortKey]) -> Result<SpillFile, Error> {
+    sort_run(run, keys);
+    let (file, mut writer) = try!(SpillFile::create("sort"));
+    for (_, row) in run.drain(..) {
+        try!(writer.write_all(&row));
+    }
+    try!(writer.flush());
+    Ok(file)
+}
+
+fn next_entry(
+    reader: &mut SpillRows,
+    columns: &[Column],
+    keys: &[SortKey],
+) -> Result<Option<(Key, Vec<u8>)>, Error> {
+    Ok(try!(reader.next_row()).map(|row| (sort_key(columns, keys, &row), row)))
+}
+
+#[test]
+fn test_external_sort() {
+    use parse::token::Lit;
+    use storage::SqlType;
+
+    let columns = vec![
+        Column::new("a", SqlType::Int, true, "", false),
+        Column::new("b", SqlType::Int, false, "", false),
+    ];
+    let mut rows = Rows::new(Cursor::new(Vec::new()), &columns);
+    let int = |v: i64| {
+        let mut data = Vec::new();
+        SqlType::Int.encode_into(&mut data, &Lit::Int(v)).unwrap();
+        data
+    };
+    for i in 0..200 {
+        let a = if i % 7 == 0 { None } else { Some(int(i % 5)) };
+        rows.add_values(&[a, Some(int(i))]).unwrap();
+    }
+    let keys = [
+        SortKey {
+            column: 0,
+            descending: false,
+        },
+        SortKey {
+            column: 1,
+            descending: true,
+        },
+    ];
+    let read = |mut rows: Rows<Cursor<Vec<u8>>>| {
+        rows.reset_pos().unwrap();
+        let mut all = Vec::new();
+        let mut row = Vec::new();
+        while rows.next_row(&mut row).is_ok() {
+            all.push(sort_key(&columns, &keys, &row));
+            row.clear();
+        }
+        all
+    };
+    let no_check = || -> Result<(), Error> { Ok(()) };
+
+    let in_memory = read(
+        sort(
+            rows.full_scan().unwrap(),
+            &keys,
+            ::spill::DEFAULT_MEMORY_BUDGET,
+            no_check,
+        )
+        .unwrap(),
+    );
+    assert_eq!(in_memory.len(), 200);
+    assert!(in_memory
+        .windows(2)
+        .all(|w| compare(&keys, &w[0], &w[1]) != Ordering::Greater));
+    // nulls come last
+    assert_eq!(in_memory[199][0], None);
+
+    // a budget of a few rows makes many runs
+    let external = read(sort(rows, &keys, 100, no_check).unwrap());
+    assert_eq!(external, in_memory);
+}
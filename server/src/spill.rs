@@ -0,0 +1,269 @@
+//! Temporary files of operators exceeding their memory budget
+//!
+//! Joins and sorts of many rows write some of them to files in the
+//! temporary directory of the system and read them back later, see `join`
+//! and `sort`. The rows are written one after another without any framing,
+//! all rows of a file have the same size. A file is removed when its
+//! `SpillFile` is dropped, so that failed statements leave nothing behind.
+//!
+//! If the server has an encryption key, rows of encrypted tables may be
+//! among them, so the rows are sealed in chunks of `CHUNK_SIZE` bytes
+//! instead, see `storage::encrypt`: the length of the sealed chunk and the
+//! sealed chunk, which also covers its number.
+//!
+//! Every sort and hash join has the same budget, `memory_budget`, which the
+//! configuration of the server sets, see `Config::memory_budget`.
+
+use aes_gcm::Aes256Gcm;
+use byteorder::{BigEndian, ByteOrder, ReadBytesExt, WriteBytesExt};
+use storage::{encrypt, Error};
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Cursor, Read, Write};
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Bytes the rows of an operator may take before they are written to a
+/// file, unless the configuration sets another budget
+pub const DEFAULT_MEMORY_BUDGET: usize = 16 * 1024 * 1024;
+/// Bytes of rows sealed together in the files of a server with an
+/// encryption key
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Tells apart the files of statements running at the same time
+static FILES: AtomicUsize = AtomicUsize::new(0);
+
+static MEMORY_BUDGET: AtomicUsize = AtomicUsize::new(DEFAULT_MEMORY_BUDGET);
+
+/// Returns the bytes the rows of a sort or of the hash table of a hash join
+/// may take before they are written to a file.
+pub fn memory_budget() -> usize {
+    MEMORY_BUDGET.load(Ordering::SeqCst)
+}
+
+/// Sets the budget of the operators started from now on.
+pub fn set_memory_budget(bytes: usize) {
+    MEMORY_BUDGET.store(bytes, Ordering::SeqCst);
+}
+
+/// A temporary file of rows, removed when dropped
+pub struct SpillFile {
+    path: PathBuf,
+    /// Seals the rows, see the module documentation
+    cipher: Option<Aes256Gcm>,
+}
+
+impl SpillFile {
+    /// Creates an empty file, `operator` becomes part of its name.
+    pub fn create(operator: &str) -> Result<(SpillFile, SpillWriter), Error> {
+        SpillFile::create_sealed(operator, encrypt::cipher())
+    }
+
+    /// Creates an empty file whose rows are sealed with the cipher, if any.
+    fn create_sealed(
+        operator: &str,
+        cipher: Option<Aes256Gcm>,
+    ) -> Result<(SpillFile, SpillWriter), Error> {
+        let number = FILES.fetch_add(1, Ordering::SeqCst);
+        let name = format!("uosql-{}-{}-{}", operator, process::id(), number);
+        let file = SpillFile {
+            path: env::temp_dir().join(name),
+            cipher: cipher.clone(),
+        };
+        let writer = SpillWriter {
+            file: BufWriter::new(try!(File::create(&file.path))),
+            cipher: cipher,
+            chunk: Vec::new(),
+            chunks: 0,
+        };
+        Ok((file, writer))
+    }
+
+    /// Reads all rows of the file.
+    pub fn read(&self, row_size: usize) -> Result<Vec<Vec<u8>>, Error> {
+        let mut data = Vec::new();
+        try!(try!(self.reader()).read_to_end(&mut data));
+        Ok(data.chunks(row_size.max(1)).map(|r| r.to_vec()).collect())
+    }
+
+    /// Returns a reader of the rows of the file, one after another.
+    pub fn rows(&self, row_size: usize) -> Result<SpillRows, Error> {
+        Ok(SpillRows {
+            reader: try!(self.reader()),
+            row_size: row_size,
+        })
+    }
+
+    fn reader(&self) -> Result<SpillReader, Error> {
+        Ok(SpillReader {
+            file: BufReader::new(try!(File::open(&self.path))),
+            cipher: self.cipher.clone(),
+            chunk: Cursor::new(Vec::new()),
+            chunks: 0,
+        })
+    }
+}
+
+/// Writes the rows of a `SpillFile`. Must be flushed before the file is
+/// read.
+pub struct SpillWriter {
+    file: BufWriter<File>,
+    cipher: Option<Aes256Gcm>,
+    /// Rows not sealed yet
+    chunk: Vec<u8>,
+    /// Number of chunks sealed
+    chunks: u64,
+}
+
+impl SpillWriter {
+    fn seal_chunk(&mut self) -> io::Result<()> {
+        if let Some(ref cipher) = self.cipher {
+            let sealed = encrypt::seal(cipher, &chunk_number(self.chunks), &self.chunk);
+            try!(self.file.write_u32::<BigEndian>(sealed.len() as u32));
+            try!(self.file.write_all(&sealed));
+            self.chunks += 1;
+            self.chunk.clear();
+        }
+        Ok(())
+    }
+}
+
+impl Write for SpillWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.cipher.is_none() {
+            return self.file.write(buf);
+        }
+        let len = buf.len().min(CHUNK_SIZE - self.chunk.len());
+        self.chunk.extend_from_slice(&buf[..len]);
+        if self.chunk.len() == CHUNK_SIZE {
+            try!(self.seal_chunk());
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.chunk.is_empty() {
+            try!(self.seal_chunk());
+        }
+        self.file.flush()
+    }
+}
+
+/// Reads the rows of a `SpillFile`, unsealing its chunks
+struct SpillReader {
+    file: BufReader<File>,
+    cipher: Option<Aes256Gcm>,
+    /// The chunk read last
+    chunk: Cursor<Vec<u8>>,
+    /// Number of chunks read
+    chunks: u64,
+}
+
+impl Read for SpillReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let cipher = match self.cipher {
+            Some(ref cipher) => cipher,
+            None => return self.file.read(buf),
+        };
+        if self.chunk.position() == self.chunk.get_ref().len() as u64 {
+            let len = match self.file.read_u32::<BigEndian>() {
+                Ok(len) => len,
+                Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(0),
+                Err(e) => return Err(e),
+            };
+            let mut sealed = vec![0; len as usize];
+            try!(self.file.read_exact(&mut sealed));
+            let chunk = match encrypt::unseal(cipher, &chunk_number(self.chunks), &sealed) {
+                Some(chunk) => chunk,
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "a chunk of the spill file was changed",
+                    ))
+                }
+            };
+            self.chunks += 1;
+            self.chunk = Cursor::new(chunk);
+        }
+        self.chunk.read(buf)
+    }
+}
+
+/// Returns the number of a chunk as it is sealed with the chunk.
+fn chunk_number(number: u64) -> [u8; 8] {
+    let mut bytes = [0; 8];
+    BigEndian::write_u64(&mut bytes, number);
+    bytes
+}
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            warn!("Cannot remove spill file {:?}: {:?}", self.path, e);
+        }
+    }
+}
+
+pub struct SpillRows {
+    reader: SpillReader,
+    row_size: usize,
+}
+
+impl SpillRows {
+    /// Returns the next row, None at the end of the file.
+    pub fn next_row(&mut self) -> Result<Option<Vec<u8>>, Error> {
+        let mut row = vec![0; self.row_size];
+        match self.reader.read_exact(&mut row) {
+            Ok(()) => Ok(Some(row)),
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+#[test]
+fn test_spill_file() {
+    let (file, mut writer) = SpillFile::create_sealed("test", None).unwrap();
+    writer.write_all(&[1, 2, 3, 4, 5, 6]).unwrap();
+    writer.flush().unwrap();
+    drop(writer);
+    assert_eq!(file.read(3).unwrap(), vec![vec![1, 2, 3], vec![4, 5, 6]]);
+    let mut rows = file.rows(3).unwrap();
+    assert_eq!(rows.next_row().unwrap(), Some(vec![1, 2, 3]));
+    assert_eq!(rows.next_row().unwrap(), Some(vec![4, 5, 6]));
+    assert_eq!(rows.next_row().unwrap(), None);
+
+    let path = file.path.clone();
+    drop(file);
+    assert!(!path.exists());
+}
+
+#[test]
+fn test_sealed_spill_file() {
+    use aes_gcm::aead::KeyInit;
+
+    let cipher = Aes256Gcm::new_from_slice(&[7; encrypt::KEY_SIZE]).unwrap();
+    let (file, mut writer) = SpillFile::create_sealed("test", Some(cipher)).unwrap();
+    let row = b"secret row";
+    // more than a chunk
+    for _ in 0..CHUNK_SIZE / row.len() + 10 {
+        writer.write_all(row).unwrap();
+    }
+    writer.flush().unwrap();
+    drop(writer);
+    let text = fs::read(&file.path).unwrap();
+    assert!(!text.windows(6).any(|w| w == b"secret"));
+    let rows = file.read(row.len()).unwrap();
+    assert_eq!(rows.len(), CHUNK_SIZE / row.len() + 10);
+    assert!(rows.iter().all(|r| r == row));
+    let mut reader = file.rows(row.len()).unwrap();
+    assert_eq!(reader.next_row().unwrap(), Some(row.to_vec()));
+
+    // a changed chunk is noticed
+    let mut changed = text.clone();
+    changed[20] ^= 1;
+    fs::write(&file.path, changed).unwrap();
+    assert!(file.read(row.len()).is_err());
+}
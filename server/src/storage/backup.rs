@@ -0,0 +1,209 @@
+//! Hot backups of databases
+//!
+//! `backup` copies the files of a database into a new directory while
+//! readers go on. Writes wait until the files are copied. Rows that the
+//! snapshot of the statement does not see, i.e. changes of transactions
+//! that were running or started later, are undone in the copies. Tables
+//! whose schema a running transaction changed are copied from the files the
+//! transaction saved, see `transaction`. Temporary tables are left out.
+//!
+//! There is no write-ahead log to point into: the manifest of a backup
+//! records the position of its snapshot in the order of transactions
+//! instead, see `Statement::position`.
+//!
+//! `restore` copies the files of a backup into a new database of the name
+//! the backup was taken of. The database shows up once all files are there.
+
+use super::meta::Database;
+use super::mvcc;
+use super::temporal;
+use super::transaction::{self, Statement};
+use super::Error;
+
+use bincode::{deserialize_from, serialize_into};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use serde::{Deserialize, Serialize};
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, Seek};
+use std::mem;
+use std::path::Path;
+
+const MAGIC_NUMBER: u64 = 0x49616D4261636B55;
+/// version of the manifest format
+const VERSION_NO: u8 = 1;
+/// name of the manifest in the directory of a backup
+const MANIFEST: &'static str = "backup.meta";
+
+/// Describes a backup, is stored next to the copied files
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Manifest {
+    version_nmbr: u8,
+    pub database: String,
+    /// When the backup was taken, see `temporal::now`
+    pub taken: i64,
+    /// The backup holds the changes of all transactions with a smaller id,
+    /// except those of the `running` ones
+    pub next_xid: u64,
+    pub running: Vec<u64>,
+    /// Names and sizes of the copied files
+    pub files: Vec<(String, u64)>,
+}
+
+impl Manifest {
+    /// Returns the size of all copied files in bytes.
+    pub fn size(&self) -> u64 {
+        self.files.iter().map(|f| f.1).sum()
+    }
+}
+
+/// Copies the files of a database as seen by the snapshot of `statement`
+/// into the directory `path`, which must not exist yet.
+pub fn backup(database: &Database, statement: &Statement, path: &str) -> Result<Manifest, Error> {
+    if Path::new(path).exists() {
+        let msg = format!("{} exists already", path);
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, msg).into());
+    }
+    try!(fs::create_dir_all(path));
+    let result = copy_database(database, statement, path);
+    if result.is_err() {
+        info!("backup to {:?} failed, removing it", path);
+        let _ = fs::remove_dir_all(path);
+    }
+    result
+}
+
+fn copy_database(
+    database: &Database,
+    statement: &Statement,
+    path: &str,
+) -> Result<Manifest, Error> {
+    {
+        let _writing = transaction::lock_writes();
+        let saved = transaction::saved_tables(&database.name);
+        for entry in try!(fs::read_dir(&database.name)) {
+            let entry = try!(entry);
+            // undo directories
+            if !try!(entry.file_type()).is_file() {
+                continue;
+            }
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let table = name.split('.').next().unwrap_or("");
+            // files of rewrites in progress and of changed schemas
+            let skip = table.contains('~') || saved.iter().any(|s| s.0 == table);
+            if name == "db.meta" || !skip {
+                try!(fs::copy(entry.path(), format!("{}/{}", path, name)));
+            }
+        }
+        for &(_, ref dir, ref files) in &saved {
+            for name in files {
+                try!(fs::copy(
+                    format!("{}/{}", dir, name),
+                    format!("{}/{}", path, name)
+                ));
+            }
+        }
+
+        let copy = try!(Database::load(path));
+        for name in try!(copy.list_tables()) {
+            let changes = statement.invisible_changes(&database.name, &name);
+            if changes.is_empty() {
+                continue;
+            }
+            let table = try!(copy.load_table(&name));
+            let rows = try!(try!(copy.load_table(&name)).create_engine().full_scan());
+            let all = |_: &[u8]| Ok(true);
+            let rows = try!(mvcc::undo_changes(table.columns(), rows, changes, all));
+            try!(table.replace_rows(&table, rows, |row| Ok::<_, Error>(row.to_vec())));
+        }
+    }
+
+    let mut files = Vec::new();
+    for entry in try!(fs::read_dir(path)) {
+        let entry = try!(entry);
+        let name = entry.file_name().to_string_lossy().into_owned();
+        files.push((name, try!(entry.metadata()).len()));
+    }
+    files.sort();
+    let (next_xid, running) = statement.position();
+    let manifest = Manifest {
+        version_nmbr: VERSION_NO,
+        database: database.name.clone(),
+        taken: temporal::now(),
+        next_xid: next_xid,
+        running: running,
+        files: files,
+    };
+    let mut file = try!(OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(format!("{}/{}", path, MANIFEST)));
+    try!(file.write_u64::<BigEndian>(MAGIC_NUMBER));
+    try!(serialize_into(&mut file, &manifest));
+    info!(
+        "backed up database {:?} to {:?}: {} files, {} bytes",
+        manifest.database,
+        path,
+        manifest.files.len(),
+        manifest.size()
+    );
+    Ok(manifest)
+}
+
+/// Reads the manifest of the backup in the directory `path`.
+pub fn read_manifest(path: &str) -> Result<Manifest, Error> {
+    let mut file = try!(OpenOptions::new()
+        .read(true)
+        .open(format!("{}/{}", path, MANIFEST)));
+    let ma_nmbr = try!(file.read_uint::<BigEndian>(mem::size_of_val(&MAGIC_NUMBER)));
+    if ma_nmbr != MAGIC_NUMBER {
+        return Err(Error::WrongMagicNmbr);
+    }
+    let version = try!(file.read_u8());
+    if version > VERSION_NO {
+        warn!("backup {:?} was written by a newer server", path);
+        return Err(Error::UnsupportedVersion(version));
+    }
+    try!(file.seek(io::SeekFrom::Current(-1)));
+    Ok(try!(deserialize_from(&mut file)))
+}
+
+/// Creates the database a backup was taken of from the files in the
+/// directory `path`.
+///
+/// # Failures
+/// Fails with `Error::DatabaseExists` if there is a database of the name and
+/// with `Error::WrongLength` if a file of the backup is missing or does not
+/// have the size the manifest records.
+pub fn restore(path: &str) -> Result<Manifest, Error> {
+    let manifest = try!(read_manifest(path));
+    if Path::new(&manifest.database).exists() {
+        return Err(Error::DatabaseExists(manifest.database));
+    }
+    // a restore that crashed may have left the directory behind
+    let temp = format!("{}~restore", manifest.database);
+    match fs::remove_dir_all(&temp) {
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
+        result => try!(result),
+    }
+    try!(fs::create_dir(&temp));
+    let copied = (|| {
+        for &(ref name, size) in &manifest.files {
+            let from = format!("{}/{}", path, name);
+            match fs::metadata(&from) {
+                Ok(ref m) if m.len() == size => (),
+                _ => return Err(Error::WrongLength),
+            }
+            try!(fs::copy(&from, format!("{}/{}", temp, name)));
+        }
+        Ok(())
+    })();
+    if let Err(e) = copied {
+        info!("restoring {:?} failed, removing {:?}", path, temp);
+        let _ = fs::remove_dir_all(&temp);
+        return Err(e);
+    }
+    try!(fs::rename(&temp, &manifest.database));
+    info!("restored database {:?} from {:?}", manifest.database, path);
+    Ok(manifest)
+}
@@ -0,0 +1,159 @@
+//! Out-of-row storage of BLOB values
+//!
+//! A BLOB column takes `SLOT_SIZE` bytes of its row: the length of the
+//! value, where the value is kept and then either the value itself or its
+//! offset in the overflow file of the table. Values longer than
+//! `INLINE_SIZE` bytes go to the overflow file, so that scans of the table
+//! only read the slots.
+//!
+//! The overflow file is only appended to: slots of older row versions stay
+//! valid, but the space of deleted values is not reclaimed.
+
+use super::meta::Table;
+use super::types::{column_range, row_size, SqlType};
+use super::Error;
+
+use byteorder::{BigEndian, ByteOrder};
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+use std::sync::Mutex;
+
+/// Maximum length of a value kept in its row
+pub const INLINE_SIZE: usize = 64;
+/// Size of a BLOB column in a row
+pub const SLOT_SIZE: u32 = 4 + 1 + INLINE_SIZE as u32;
+
+const IN_ROW: u8 = 0;
+const IN_OVERFLOW_FILE: u8 = 1;
+
+/// Appends to overflow files one at a time, so that offsets stay right
+static APPEND_LOCK: Mutex<()> = Mutex::new(());
+
+/// Returns the slot of a value kept in its row, or None if it is too long.
+pub fn inline_slot(data: &[u8]) -> Option<Vec<u8>> {
+    if data.len() > INLINE_SIZE {
+        return None;
+    }
+    let mut slot = vec![0; SLOT_SIZE as usize];
+    BigEndian::write_u32(&mut slot[..4], data.len() as u32);
+    slot[4] = IN_ROW;
+    slot[5..5 + data.len()].copy_from_slice(data);
+    Some(slot)
+}
+
+/// Stores a value read from data and returns its slot. Long values are
+/// copied to the overflow file of the table without being buffered.
+pub fn write(table: &Table, data: &mut dyn Read) -> Result<Vec<u8>, Error> {
+    let mut head = Vec::new();
+    try!(data.take(INLINE_SIZE as u64 + 1).read_to_end(&mut head));
+    if let Some(slot) = inline_slot(&head) {
+        return Ok(slot);
+    }
+
+    let _appending = APPEND_LOCK.lock().unwrap();
+    let mut file = try!(OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(table.get_table_blob_path()));
+    let offset = try!(file.seek(SeekFrom::End(0)));
+    try!(file.write_all(&head));
+    let len = head.len() as u64 + try!(io::copy(data, &mut file));
+    if len > u32::max_value() as u64 {
+        // the value is not referenced and therefore lost space
+        return Err(Error::OutOfBounds);
+    }
+
+    let mut slot = vec![0; SLOT_SIZE as usize];
+    BigEndian::write_u32(&mut slot[..4], len as u32);
+    slot[4] = IN_OVERFLOW_FILE;
+    BigEndian::write_u64(&mut slot[5..13], offset);
+    Ok(slot)
+}
+
+/// Returns the length of the value of a slot.
+pub fn length(slot: &[u8]) -> u32 {
+    BigEndian::read_u32(&slot[..4])
+}
+
+/// Returns a reader over the value of a slot.
+pub fn read<'b>(table: &Table, slot: &'b [u8]) -> Result<Box<dyn Read + 'b>, Error> {
+    if slot.len() != SLOT_SIZE as usize {
+        return Err(Error::WrongLength);
+    }
+    let len = length(slot);
+    match slot[4] {
+        IN_ROW => Ok(Box::new(Cursor::new(&slot[5..5 + len as usize]))),
+        IN_OVERFLOW_FILE => {
+            let mut file = try!(OpenOptions::new()
+                .read(true)
+                .open(table.get_table_blob_path()));
+            try!(file.seek(SeekFrom::Start(BigEndian::read_u64(&slot[5..13]))));
+            Ok(Box::new(file.take(len as u64)))
+        }
+        _ => Err(Error::InvalidState),
+    }
+}
+
+/// Returns the values of the columns of a row that are kept in the
+/// overflow file of the table, the first column first.
+pub fn overflow_values(table: &Table, row: &[u8]) -> Result<Vec<Vec<u8>>, Error> {
+    let columns = table.columns();
+    let mut values = Vec::new();
+    if row.len() != row_size(columns) {
+        return Ok(values);
+    }
+    for (i, column) in columns.iter().enumerate() {
+        let slot = &row[column_range(columns, i)];
+        if column.sql_type == SqlType::Blob && slot[4] == IN_OVERFLOW_FILE {
+            let mut value = Vec::new();
+            try!(try!(read(table, slot)).read_to_end(&mut value));
+            values.push(value);
+        }
+    }
+    Ok(values)
+}
+
+/// Stores the values `overflow_values` returned for a row of another server
+/// and points the columns of the row to them.
+pub fn store_overflow_values(
+    table: &Table,
+    row: &mut [u8],
+    values: &[Vec<u8>],
+) -> Result<(), Error> {
+    let columns = table.columns();
+    if row.len() != row_size(columns) {
+        return Err(Error::WrongLength);
+    }
+    let mut values = values.iter();
+    for (i, column) in columns.iter().enumerate() {
+        let range = column_range(columns, i);
+        if column.sql_type != SqlType::Blob || row[range.start + 4] != IN_OVERFLOW_FILE {
+            continue;
+        }
+        let value = match values.next() {
+            Some(value) => value,
+            None => return Err(Error::WrongLength),
+        };
+        let slot = try!(write(table, &mut &value[..]));
+        row[range].copy_from_slice(&slot);
+    }
+    Ok(())
+}
+
+/// Deletes the overflow file of a table, if it has one.
+pub fn delete(table: &Table) -> Result<(), Error> {
+    match fs::remove_file(table.get_table_blob_path()) {
+        Ok(()) => Ok(()),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(Error::Io(e)),
+    }
+}
+
+#[test]
+fn test_inline_slot() {
+    let slot = inline_slot(b"abc").unwrap();
+    assert_eq!(slot.len(), SLOT_SIZE as usize);
+    assert_eq!(length(&slot), 3);
+    assert!(inline_slot(&[0; INLINE_SIZE + 1]).is_none());
+}
@@ -242,7 +242,11 @@ impl<T: KnownSize + PartialOrd + Clone + Debug> Bstar<T> {
 
                 if originalnode.node_list.elementcount == self.order * 2 {
                     // Node Overflow: split up and generate new father
-                    originalnode.node_list.insert(key);
+                    if originalnode.node_list.insert(key) == 0 {
+                        // key for reaching this node changed!
+                        let oldkey = originalnode.node_list.get_by_index(1).unwrap().key.clone();
+                        try!(self.delegate_reaching_key(&mut originalnode, oldkey));
+                    }
 
                     try!(self.inc_elementcount());
                     try!(self.delegate_overflow_father(&mut originalnode, lookup.addr));
@@ -264,11 +268,12 @@ impl<T: KnownSize + PartialOrd + Clone + Debug> Bstar<T> {
             }
         } else {
             // if tree is empty create new root node
-            try!(self.dat.seek(SeekFrom::Start(lookup.addr)));
+            let addr = try!(self.use_free_addr());
+            try!(self.update_root(addr));
             let mut list = SortedList::<KeyAddr<T>>::with_capacity((self.order * 2) as usize);
             list.insert(key);
             let mut node = Bnode::create(list, 0, None, None, true, true, self.order);
-            try!(node.write(&mut self.dat, Some(lookup.addr)));
+            try!(node.write(&mut self.dat, Some(addr)));
 
             try!(self.inc_elementcount());
             Ok(true)
@@ -421,6 +426,9 @@ impl<T: KnownSize + PartialOrd + Clone + Debug> Bstar<T> {
         }
     }
 
+    // Deletes the key at keyindex from node. Nodes are not merged with their
+    // brothers: a node that gets empty is unlinked from its brothers and
+    // removed from its father.
     // node: Node found.
     // keyindex: the index of the key to be deleted
     // nodeaddr: the address of the node
@@ -430,213 +438,57 @@ impl<T: KnownSize + PartialOrd + Clone + Debug> Bstar<T> {
         keyindex: u64,
         nodeaddr: u64,
     ) -> Result<()> {
-        if node.node_list.elementcount > self.order {
-            // normal delete
-            let res = node.node_list.delete_by_index(keyindex as usize);
+        let deleted = node.node_list.delete_by_index(keyindex as usize).unwrap();
+        if node.node_list.elementcount > 0 {
+            if node.is_root && !node.is_leaf && node.node_list.elementcount == 1 {
+                // root has a single son left, which becomes the new root
+                let sonaddr = node.node_list.list[0].addr;
+                let mut son = try!(Bnode::<T>::read(&mut self.dat, Some(sonaddr)));
+                son.is_root = true;
+                try!(son.write(&mut self.dat, Some(sonaddr)));
+                try!(self.update_root(sonaddr));
+                return self.update_free_addr(nodeaddr);
+            }
             if keyindex == 0 {
-                try!(self.delegate_reaching_key(node, res.unwrap().key));
+                // key for reaching this node changed!
+                try!(self.delegate_reaching_key(node, deleted.key));
             }
-            node.write(&mut self.dat, Some(nodeaddr));
+            node.write(&mut self.dat, Some(nodeaddr))
+        } else if node.is_root {
+            // the last key of the tree was deleted
+            try!(self.update_root(0));
+            self.freeaddr = 0;
+            try!(self.meta.seek(SeekFrom::Start(FreeAdrr)));
+            try!(self.meta.write_u64::<BigEndian>(0));
+            try!(self.update_eof(0));
+            self.dat.set_len(0)
         } else {
-            if node.is_root {
-                // delete from root
-                if node.node_list.elementcount <= 2 {
-                    // delete old root if root elements is 2 or less
-                    if node.is_leaf {
-                        // root is the only node
-                        // TODO: you know the index, use it to make Deletion efficient!!!
-                        if node.node_list.elementcount == 2 {
-                            node.node_list.delete_by_index(keyindex as usize);
-                            node.write(&mut self.dat, Some(nodeaddr));
-                        } else {
-                            self.update_free_addr(0);
-                            self.update_root(0);
-                            self.update_eof(0);
-                            self.dat.set_len(0);
-                        }
-                    } else {
-                        // root is not the only node => update node to it's remaining son
-                        self.update_free_addr(nodeaddr);
-                        self.update_root(node.node_list.list[0].addr);
-                        let mut newroot = try!(Bnode::<T>::read(&mut self.dat, Some(self.root)));
-
-                        // TODO: effective root info change possible!!!
-                        newroot.is_root = true;
-                        try!(newroot.write(&mut self.dat, Some(self.root)));
-                    }
-                } else {
-                    // normal
-                    node.node_list.delete_by_index(keyindex as usize);
-                    node.write(&mut self.dat, Some(nodeaddr));
-                }
-            } else {
-                // Node is no root and does not have enough keys to delete from
-                let deleted = node.node_list.delete_by_index(keyindex as usize).unwrap();
-
-                let mut father = try!(Bnode::<T>::read(&mut self.dat, Some(node.father)));
-                // calculate the index the node has on father
-                let mut indexonfather = father.node_list.get_index_by_key(&deleted).1;
-                if father.node_list.list[indexonfather].gt(&deleted) && indexonfather != 0 {
-                    indexonfather -= 1;
-                }
-                if keyindex == 0 {
-                    try!(self.delegate_reaching_key(node, deleted.key.clone()));
-                    father = try!(Bnode::<T>::read(&mut self.dat, Some(node.father)));
-                }
-
-                //TODO: more effective through right and left brother address in Bnode
-                // right brother
-                let rightbaddr = match father.node_list.get_by_index(indexonfather + 1) {
-                    Some(keyaddr) => Some(keyaddr.addr),
-                    None => None,
-                };
-                // left brother
-                let leftbaddr = match indexonfather {
-                    0 => None,
-                    _ => Some(
-                        father
-                            .node_list
-                            .get_by_index(indexonfather - 1)
-                            .unwrap()
-                            .addr,
-                    ),
-                };
-                // determine which son to choose
-                let mut peernode = {
-                    if rightbaddr != None && leftbaddr != None {
-                        // compare size ofbrother nodes
-                        let rightbnode =
-                            try!(Bnode::<T>::read(&mut self.dat, Some(rightbaddr.unwrap())));
-                        let leftbnode =
-                            try!(Bnode::<T>::read(&mut self.dat, Some(leftbaddr.unwrap())));
-                        if rightbnode.node_list.elementcount > leftbnode.node_list.elementcount {
-                            (rightbnode, Side::Right, rightbaddr.unwrap())
-                        } else {
-                            (leftbnode, Side::Left, leftbaddr.unwrap())
-                        }
-                    } else if rightbaddr == None {
-                        // return left brother
-                        (
-                            try!(Bnode::<T>::read(&mut self.dat, Some(leftbaddr.unwrap()))),
-                            Side::Left,
-                            leftbaddr.unwrap(),
-                        )
-                    } else {
-                        // return right brother
-                        (
-                            try!(Bnode::<T>::read(&mut self.dat, Some(rightbaddr.unwrap()))),
-                            Side::Right,
-                            rightbaddr.unwrap(),
-                        )
-                    }
-                };
-
-                let mut merged = false;
-                if peernode.0.node_list.elementcount <= self.order {
-                    // merge the two brothers if peer has too little elements
-                    if peernode.1 == Side::Left {
-                        // peer ond left side => append node list to peer list.
-                        for _i in 0..(node.node_list.elementcount) {
-                            let tmp = node.node_list.delete_by_index(0);
-                            peernode.0.node_list.insert_at_index(0, tmp.unwrap());
-                        }
-                        // node can be deleted
-                        try!(self.update_free_addr(nodeaddr));
-
-                        // update left and rightbrother addresses
-                        if node.rightbrother.is_some() {
-                            let mut tmp = try!(Bnode::<T>::read(
-                                &mut self.dat,
-                                Some(node.rightbrother.unwrap())
-                            ));
-                            tmp.leftbrother = node.leftbrother;
-                            try!(tmp.write(&mut self.dat, node.rightbrother));
-                        }
-                        peernode.0.rightbrother = node.rightbrother;
-
-                        // write peer to disk
-                        try!(peernode.0.write(&mut self.dat, Some(peernode.2)));
-                        // delete original nodes reaching key from father by recursion
-                        try!(self.delegate_underflow_node(
-                            &mut father,
-                            indexonfather as u64,
-                            node.father
-                        ));
-                    } else {
-                        // peer on right side => append peer list to node list
-                        for _i in 0..(peernode.0.node_list.elementcount) {
-                            let tmp = peernode.0.node_list.delete_by_index(0);
-                            node.node_list.insert_at_index(0, tmp.unwrap());
-                        }
-                        // right hand side peer can be deleted
-                        // TODO: file.set_len if the deleted node was the last!
-                        try!(self.update_free_addr(peernode.2));
-                        // write node to disk
-
-                        // update left and rightbrother addresses
-                        if peernode.0.rightbrother.is_some() {
-                            let mut tmp = try!(Bnode::<T>::read(
-                                &mut self.dat,
-                                Some(peernode.0.rightbrother.unwrap())
-                            ));
-                            tmp.leftbrother = peernode.0.leftbrother;
-                            try!(tmp.write(&mut self.dat, peernode.0.rightbrother));
-                        }
-                        node.rightbrother = peernode.0.rightbrother;
-
-                        // write node to disk
-                        try!(node.write(&mut self.dat, Some(nodeaddr)));
-                        // delete right peer reaching key from father by recursion
-                        try!(self.delegate_underflow_node(
-                            &mut father,
-                            (indexonfather + 1) as u64,
-                            node.father
-                        ));
-                    }
-                    merged = true;
-                } else {
-                    // distribute nodelists from both nodes equally
-
-                    let peerlength = peernode.0.node_list.elementcount;
-                    let nodelength = node.node_list.elementcount;
-
-                    if peernode.1 == Side::Left {
-                        // peer is left node
-                        for i in 0..((peerlength - nodelength) / 2) {
-                            let tmp = peernode
-                                .0
-                                .node_list
-                                .delete_by_index((peerlength - i - 1) as usize);
-                            node.node_list.insert_at_index(0, tmp.unwrap());
-                        }
-
-                        father.node_list.get_by_index(indexonfather).unwrap().key =
-                            node.node_list.get_by_index(0).unwrap().key.clone();
-                    } else {
-                        for _i in 0..((peerlength - nodelength) / 2) {
-                            let tmp = peernode.0.node_list.delete_by_index(0);
-                            node.node_list
-                                .insert_at_index(nodelength as usize, tmp.unwrap());
-                            let _nodelength = node.node_list.elementcount;
-                        }
-                        father
-                            .node_list
-                            .get_by_index(indexonfather + 1)
-                            .unwrap()
-                            .key = peernode.0.node_list.get_by_index(0).unwrap().key.clone();
-                    }
-                }
-
-                if !merged {
-                    try!(peernode.0.write(&mut self.dat, Some(peernode.2)));
-                    node.write(&mut self.dat, Some(nodeaddr));
-                    // TODO: Make more effective disc usage: update the father
-                    // only on the index where it is changed
-                    father.write(&mut self.dat, Some(node.father));
-                }
+            // unlink the empty node from its brothers...
+            if let Some(addr) = node.leftbrother {
+                let mut brother = try!(Bnode::<T>::read(&mut self.dat, Some(addr)));
+                brother.rightbrother = node.rightbrother;
+                try!(brother.write(&mut self.dat, Some(addr)));
             }
+            if let Some(addr) = node.rightbrother {
+                let mut brother = try!(Bnode::<T>::read(&mut self.dat, Some(addr)));
+                brother.leftbrother = node.leftbrother;
+                try!(brother.write(&mut self.dat, Some(addr)));
+            }
+            try!(self.update_free_addr(nodeaddr));
+
+            // ...and delete its reaching key from the father
+            let mut father = try!(Bnode::<T>::read(&mut self.dat, Some(node.father)));
+            let position = father
+                .node_list
+                .list
+                .iter()
+                .position(|k| k.addr == nodeaddr);
+            let indexonfather = match position {
+                Some(index) => index,
+                None => return Err(Error::new(ErrorKind::InvalidData, "node missing in father")),
+            };
+            self.delegate_underflow_node(&mut father, indexonfather as u64, node.father)
         }
-        Ok(())
     }
 
     fn inc_elementcount(&mut self) -> Result<()> {
@@ -664,7 +516,7 @@ impl<T: KnownSize + PartialOrd + Clone + Debug> Bstar<T> {
             Ok(InternalLookup {
                 found: false,
                 bnode: None,
-                addr: try!(self.use_free_addr()),
+                addr: 0,
                 index: None,
                 target: None,
             })
@@ -782,10 +634,13 @@ impl<T: KnownSize + PartialOrd + Clone + Debug> Bstar<T> {
     // Importend!!!!!!!!! THIS WILL MAKE THE NODE AT addr INVALID!!
     // ONLY USE AFTER DELETING THE NODE AT addr!!!!!!!!!!!
     fn update_free_addr(&mut self, addr: u64) -> Result<()> {
-        if addr + Bnode::<T>::size(self.order) == self.eof {
+        if self.freeaddr == self.eof && addr + Bnode::<T>::size(self.order) == self.eof {
+            // the free list is empty and the node is the last one: shrink the file
             try!(self.dat.set_len(addr));
+            self.freeaddr = addr;
             self.eof = addr;
-            try!(self.meta.seek(SeekFrom::Start(EoF)));
+            try!(self.meta.seek(SeekFrom::Start(FreeAdrr)));
+            try!(self.meta.write_u64::<BigEndian>(addr));
             try!(self.meta.write_u64::<BigEndian>(addr));
         } else {
             try!(self.meta.seek(SeekFrom::Start(FreeAdrr)));
@@ -911,85 +766,49 @@ impl<T: KnownSize + PartialOrd + Clone + Debug> Bstar<T> {
             }
         } else {
             let iteroption = key.unwrap();
-            let value = iteroption.unwrap();
-            let olookup = self.lookup_internal(&KeyAddr::<T>::new(value.clone(), 0));
-            if olookup.is_ok() {
-                let lookup = olookup.unwrap();
-
-                let mut bnode = lookup.bnode.unwrap();
-                let mut index = if direction == IterDirection::Forward {
-                    0
-                } else {
-                    bnode.node_list.elementcount - 1
-                };
-
-                let modifier = {
-                    if direction == IterDirection::Forward {
-                        if iteroption == IterOption::Excluding(value) {
-                            if lookup.index.unwrap() != 0 {
-                                1
-                            } else {
-                                if lookup.found {
-                                    1
-                                } else {
-                                    0
-                                }
-                            }
-                        } else {
-                            if lookup.found {
-                                0
-                            } else {
-                                if lookup.index.unwrap() == 0 {
-                                    0
-                                } else {
-                                    1
-                                }
-                            }
-                        }
-                    } else {
-                        if iteroption == IterOption::Excluding(value) {
-                            if lookup.found {
-                                1
-                            } else {
-                                if lookup.index.unwrap() == 0 {
-                                    1
-                                } else {
-                                    0
-                                }
-                            }
-                        } else {
-                            0
-                        }
-                    }
-                };
-                for _i in if direction == IterDirection::Forward {
-                    0..lookup.index.unwrap() + modifier
-                } else {
-                    0..(bnode.node_list.elementcount - 1 - lookup.index.unwrap() + modifier)
-                } {
-                    bnode.node_list.delete_by_index(index as usize);
-                    index = if direction == IterDirection::Forward {
-                        0
-                    } else {
-                        if bnode.node_list.elementcount == 0 {
+            let value = KeyAddr::<T>::new(iteroption.unwrap(), 0);
+            let excluding = match iteroption {
+                IterOption::Excluding(_) => true,
+                IterOption::Including(_) => false,
+            };
+            match self.lookup_internal(&value) {
+                Ok(InternalLookup {
+                    bnode: Some(mut bnode),
+                    addr,
+                    ..
+                }) => {
+                    // drop the keys of the leaf that lie before the given key
+                    while bnode.node_list.elementcount > 0 {
+                        let index = match direction {
+                            IterDirection::Forward => 0,
+                            IterDirection::Backward => bnode.node_list.elementcount - 1,
+                        };
+                        let skip = {
+                            let first = &bnode.node_list.list[index as usize];
+                            let before = match direction {
+                                IterDirection::Forward => first.lt(&value),
+                                IterDirection::Backward => first.gt(&value),
+                            };
+                            before || excluding && first.eq(&value)
+                        };
+                        if !skip {
                             break;
                         }
-                        bnode.node_list.elementcount - 1
+                        bnode.node_list.delete_by_index(index as usize);
+                    }
+                    Bterator {
+                        dat: &mut self.dat,
+                        addr: addr,
+                        node: bnode,
+                        direction: direction,
                     }
                 }
-                Bterator {
-                    dat: &mut self.dat,
-                    addr: lookup.addr,
-                    node: bnode,
-                    direction: direction,
-                }
-            } else {
-                Bterator {
+                _ => Bterator {
                     dat: &mut self.dat,
                     addr: 0,
                     node: dummy,
-                    direction: IterDirection::Forward,
-                }
+                    direction: direction,
+                },
             }
         }
     }
@@ -1239,7 +1058,7 @@ impl<T: PartialOrd + Debug> SortedList<T> {
 
     /// deletes a key at indexposition
     pub fn delete_by_index(&mut self, index: usize) -> Option<T> {
-        if index >= 0 && index <= (self.elementcount - 1) as usize {
+        if index < self.elementcount as usize {
             self.elementcount -= 1;
             Some(self.list.remove(index))
         } else {
@@ -1249,7 +1068,7 @@ impl<T: PartialOrd + Debug> SortedList<T> {
 
     /// returns a mutable reference to a key found at index
     pub fn get_by_index(&mut self, index: usize) -> Option<&mut T> {
-        if index >= 0 && index <= (self.elementcount - 1) as usize {
+        if index < self.elementcount as usize {
             Some(&mut self.list[index])
         } else {
             None
@@ -1435,3 +1254,66 @@ impl<'a, T: KnownSize + PartialOrd + Debug> Iterator for Bterator<'a, T> {
         Some(self.node.node_list.delete_by_index(index as usize).unwrap())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+    use std::env;
+
+    fn keys(tree: &mut Bstar<u64>, from: Option<IterOption<u64>>) -> Vec<u64> {
+        tree.iter_options(IterDirection::Forward, from)
+            .map(|k| k.key)
+            .collect()
+    }
+
+    #[test]
+    fn test_insert_delete_iterate() {
+        let name = env::temp_dir()
+            .join(format!("uosql-bstar-{}", std::process::id()))
+            .to_string_lossy()
+            .into_owned();
+        for order in 2..5 {
+            let mut tree = Bstar::<u64>::create(&name, "test", order).unwrap();
+            let mut expected = BTreeSet::new();
+            // descending inserts always add a new minimum to a full leaf
+            let mut x: u64 = 12345;
+            for step in 0..3000u64 {
+                x = x
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                let key = if step < 200 {
+                    1000 - step
+                } else {
+                    (x >> 33) % 300
+                };
+                if step >= 200 && (x >> 20) % 3 == 0 {
+                    let deleted = tree.delete_keyaddr(key).unwrap();
+                    assert_eq!(deleted.is_some(), expected.remove(&key));
+                } else {
+                    let inserted = tree.insert_keyaddr(KeyAddr::new(key, key * 2)).unwrap();
+                    assert_eq!(inserted, expected.insert(key));
+                }
+            }
+
+            let mut tree = Bstar::<u64>::load(&name).unwrap();
+            let all: Vec<u64> = expected.iter().cloned().collect();
+            assert_eq!(keys(&mut tree, None), all);
+            for probe in &[0, 150, 299, 300, 1000] {
+                let from = Some(IterOption::Including(*probe));
+                let including: Vec<u64> = all.iter().cloned().filter(|k| k >= probe).collect();
+                assert_eq!(keys(&mut tree, from), including);
+                let from = Some(IterOption::Excluding(*probe));
+                let excluding: Vec<u64> = all.iter().cloned().filter(|k| k > probe).collect();
+                assert_eq!(keys(&mut tree, from), excluding);
+            }
+            for key in &all {
+                assert_eq!(tree.lookup_keyaddr(*key).unwrap().unwrap().addr, key * 2);
+                assert!(tree.delete_keyaddr(*key).unwrap().is_some());
+            }
+            assert_eq!(tree.iter().count(), 0);
+            assert_eq!(tree.eof, 0);
+            Bstar::<u64>::delete(&name).unwrap();
+        }
+    }
+}
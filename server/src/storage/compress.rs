@@ -0,0 +1,468 @@
+//! Block compression of data files
+//!
+//! The data file of a compressed or encrypted table is a sequence of
+//! blocks, each holding `BLOCK_SIZE` bytes of the uncompressed file, the
+//! last one fewer. A block starts with a header: how its data is stored,
+//! the length of the uncompressed and of the stored data and the CRC-32 of
+//! the uncompressed data. Data is compressed in the LZ4 block format or as
+//! a Zstandard frame, or kept as it is if that does not make it smaller.
+//! Blocks are read whichever way they are stored.
+//!
+//! The blocks of an encrypted table are then sealed, see `encrypt`. Their
+//! header has the CRC-32 of the sealed data instead, which gives nothing
+//! of the rows away.
+//!
+//! A `BlockFile` holds the uncompressed file in memory, so rows keep their
+//! addresses. Changes rewrite the blocks from the first changed one to the
+//! end of the file, appending rows only rewrites the last block.
+
+use super::encrypt;
+use super::Error;
+
+use aes_gcm::Aes256Gcm;
+use byteorder::{BigEndian, ByteOrder};
+use serde::{Deserialize, Serialize};
+
+use std::cmp::min;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+/// Bytes of the uncompressed file in a block
+pub const BLOCK_SIZE: usize = 64 * 1024;
+/// Size of a block header
+const HEADER_SIZE: usize = 1 + 4 + 4 + 4;
+
+const STORED: u8 = 0;
+const LZ4: u8 = 1;
+const ZSTD: u8 = 2;
+/// Level blocks are compressed at with Zstandard, its default
+const ZSTD_LEVEL: i32 = 3;
+/// Flag of the method of a sealed block
+const ENCRYPTED: u8 = 0x80;
+
+/// How the data file of a table is stored
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Compression {
+    None,
+    Lz4,
+    Zstd,
+}
+
+impl Compression {
+    /// Returns the name of the compression in `COMPRESSION = '...'`
+    pub fn name(&self) -> &'static str {
+        match self {
+            &Compression::None => "none",
+            &Compression::Lz4 => "lz4",
+            &Compression::Zstd => "zstd",
+        }
+    }
+
+    /// Returns the compression of a name, ignoring case
+    pub fn from_name(name: &str) -> Option<Compression> {
+        match &name.to_lowercase()[..] {
+            "none" => Some(Compression::None),
+            "lz4" => Some(Compression::Lz4),
+            "zstd" => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+}
+
+/// The data file of a table, plain or in blocks
+pub enum DataFile {
+    Plain(File),
+    Blocks(BlockFile),
+}
+
+impl DataFile {
+    /// Truncates or extends the file to len bytes of data.
+    pub fn set_len(&mut self, len: u64) -> Result<(), Error> {
+        match self {
+            &mut DataFile::Plain(ref file) => Ok(try!(file.set_len(len))),
+            &mut DataFile::Blocks(ref mut file) => {
+                file.set_len(len);
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Read for DataFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            &mut DataFile::Plain(ref mut file) => file.read(buf),
+            &mut DataFile::Blocks(ref mut file) => file.read(buf),
+        }
+    }
+}
+
+impl Write for DataFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            &mut DataFile::Plain(ref mut file) => file.write(buf),
+            &mut DataFile::Blocks(ref mut file) => file.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            &mut DataFile::Plain(ref mut file) => file.flush(),
+            &mut DataFile::Blocks(ref mut file) => file.flush(),
+        }
+    }
+}
+
+impl Seek for DataFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            &mut DataFile::Plain(ref mut file) => file.seek(pos),
+            &mut DataFile::Blocks(ref mut file) => file.seek(pos),
+        }
+    }
+}
+
+/// A data file in blocks, read and written like the uncompressed file.
+/// Changes are written by `flush`, or when the BlockFile is dropped.
+pub struct BlockFile {
+    file: File,
+    data: Vec<u8>,
+    pos: u64,
+    /// offsets of the blocks in the file
+    offsets: Vec<u64>,
+    /// the first byte of data changed since the last flush
+    changed: Option<usize>,
+    /// blocks are compressed if that makes them smaller
+    compression: Compression,
+    /// blocks are sealed with the cipher, and only sealed blocks are read
+    cipher: Option<Aes256Gcm>,
+}
+
+impl BlockFile {
+    /// Reads and decompresses all blocks of a file, whose blocks are
+    /// written with the compression and sealed if there is a cipher.
+    /// Returns Error::CorruptBlock if a block cannot be decompressed.
+    pub fn open(
+        mut file: File,
+        compression: Compression,
+        cipher: Option<Aes256Gcm>,
+    ) -> Result<BlockFile, Error> {
+        let mut stored = Vec::new();
+        try!(file.seek(SeekFrom::Start(0)));
+        try!(file.read_to_end(&mut stored));
+        let mut data = Vec::with_capacity(stored.len() * 2);
+        let mut offsets = Vec::new();
+        let mut offset = 0;
+        while offset < stored.len() {
+            let number = offsets.len() as u64;
+            let size = match decode_block(&stored[offset..], number, cipher.as_ref(), &mut data) {
+                Some(size) => size,
+                None => return Err(Error::CorruptBlock(offset as u64)),
+            };
+            offsets.push(offset as u64);
+            offset += size;
+        }
+        Ok(BlockFile {
+            file: file,
+            data: data,
+            pos: 0,
+            offsets: offsets,
+            changed: None,
+            compression: compression,
+            cipher: cipher,
+        })
+    }
+
+    /// Truncates or extends the uncompressed file to len bytes.
+    pub fn set_len(&mut self, len: u64) {
+        let len = len as usize;
+        self.mark_changed(min(len, self.data.len()));
+        self.data.resize(len, 0);
+    }
+
+    fn mark_changed(&mut self, from: usize) {
+        self.changed = Some(self.changed.map_or(from, |c| min(c, from)));
+    }
+}
+
+impl Read for BlockFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let start = min(self.pos as usize, self.data.len());
+        let n = min(buf.len(), self.data.len() - start);
+        buf[..n].copy_from_slice(&self.data[start..start + n]);
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Write for BlockFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let start = self.pos as usize;
+        let end = start + buf.len();
+        self.mark_changed(min(start, self.data.len()));
+        if end > self.data.len() {
+            self.data.resize(end, 0);
+        }
+        self.data[start..end].copy_from_slice(buf);
+        self.pos = end as u64;
+        Ok(buf.len())
+    }
+
+    /// Compresses the blocks from the first changed one on and writes them.
+    fn flush(&mut self) -> io::Result<()> {
+        let from = match self.changed.take() {
+            Some(from) => from,
+            None => return Ok(()),
+        };
+        let first = from / BLOCK_SIZE;
+        let offset = match self.offsets.get(first) {
+            Some(&offset) => offset,
+            None => try!(self.file.metadata()).len(),
+        };
+        self.offsets.truncate(first);
+        let mut out = Vec::new();
+        for block in self.data[first * BLOCK_SIZE..].chunks(BLOCK_SIZE) {
+            let number = self.offsets.len() as u64;
+            self.offsets.push(offset + out.len() as u64);
+            encode_block(
+                block,
+                number,
+                self.compression,
+                self.cipher.as_ref(),
+                &mut out,
+            );
+        }
+        try!(self.file.set_len(offset));
+        try!(self.file.seek(SeekFrom::Start(offset)));
+        self.file.write_all(&out)
+    }
+}
+
+impl Seek for BlockFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let new = match pos {
+            SeekFrom::Start(n) => n as i64,
+            SeekFrom::End(n) => self.data.len() as i64 + n,
+            SeekFrom::Current(n) => self.pos as i64 + n,
+        };
+        if new < 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "seek before the beginning of the file",
+            ));
+        }
+        self.pos = new as u64;
+        Ok(self.pos)
+    }
+}
+
+impl Drop for BlockFile {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush() {
+            warn!("cannot write compressed blocks: {:?}", e);
+        }
+    }
+}
+
+/// Returns the length of the uncompressed data of a file in blocks and the
+/// length of the file, reading only the block headers.
+pub fn sizes(file: &mut File) -> Result<(u64, u64), Error> {
+    let len = try!(file.metadata()).len();
+    let mut raw = 0;
+    let mut offset = 0;
+    let mut header = [0; HEADER_SIZE];
+    while offset < len {
+        try!(file.seek(SeekFrom::Start(offset)));
+        if file.read_exact(&mut header).is_err() {
+            return Err(Error::CorruptBlock(offset));
+        }
+        raw += BigEndian::read_u32(&header[1..5]) as u64;
+        offset += (HEADER_SIZE as u64) + BigEndian::read_u32(&header[5..9]) as u64;
+    }
+    Ok((raw, len))
+}
+
+/// Appends the block with the number holding data to out, compressed if
+/// that makes it smaller and sealed if there is a cipher.
+fn encode_block(
+    data: &[u8],
+    number: u64,
+    compression: Compression,
+    cipher: Option<&Aes256Gcm>,
+    out: &mut Vec<u8>,
+) {
+    let compressed = match compression {
+        Compression::None => None,
+        Compression::Lz4 => Some((LZ4, lz4_flex::block::compress(data))),
+        Compression::Zstd => zstd::bulk::compress(data, ZSTD_LEVEL)
+            .ok()
+            .map(|c| (ZSTD, c)),
+    };
+    let (method, stored) = match compressed {
+        Some((method, ref c)) if c.len() < data.len() => (method, &c[..]),
+        _ => (STORED, data),
+    };
+    let mut header = [0; HEADER_SIZE];
+    header[0] = method;
+    BigEndian::write_u32(&mut header[1..5], data.len() as u32);
+    let sealed = match cipher {
+        Some(cipher) => {
+            header[0] |= ENCRYPTED;
+            encrypt::seal(cipher, &sealed_data(&header, number), stored)
+        }
+        None => Vec::new(),
+    };
+    let (stored, checksum) = if cipher.is_some() {
+        (&sealed[..], crc32fast::hash(&sealed))
+    } else {
+        (stored, crc32fast::hash(data))
+    };
+    BigEndian::write_u32(&mut header[5..9], stored.len() as u32);
+    BigEndian::write_u32(&mut header[9..13], checksum);
+    out.extend_from_slice(&header);
+    out.extend_from_slice(stored);
+}
+
+/// Appends the data of the block with the number at the beginning of src
+/// to out. Blocks must be sealed if and only if there is a cipher.
+/// Returns the size of the block in src, or None if it is damaged.
+fn decode_block(
+    src: &[u8],
+    number: u64,
+    cipher: Option<&Aes256Gcm>,
+    out: &mut Vec<u8>,
+) -> Option<usize> {
+    if src.len() < HEADER_SIZE {
+        return None;
+    }
+    let raw_len = BigEndian::read_u32(&src[1..5]) as usize;
+    let stored_len = BigEndian::read_u32(&src[5..9]) as usize;
+    let checksum = BigEndian::read_u32(&src[9..13]);
+    if src.len() - HEADER_SIZE < stored_len {
+        return None;
+    }
+    let mut stored = &src[HEADER_SIZE..HEADER_SIZE + stored_len];
+    let unsealed;
+    match (src[0] & ENCRYPTED != 0, cipher) {
+        (true, Some(cipher)) => {
+            if crc32fast::hash(stored) != checksum {
+                return None;
+            }
+            unsealed = match encrypt::unseal(cipher, &sealed_data(src, number), stored) {
+                Some(data) => data,
+                None => return None,
+            };
+            stored = &unsealed;
+        }
+        (false, None) => (),
+        _ => return None,
+    }
+    let start = out.len();
+    match src[0] & !ENCRYPTED {
+        STORED => out.extend_from_slice(stored),
+        LZ4 => match lz4_flex::block::decompress(stored, raw_len) {
+            Ok(data) => out.extend_from_slice(&data),
+            Err(_) => return None,
+        },
+        ZSTD => match zstd::bulk::decompress(stored, raw_len) {
+            Ok(data) => out.extend_from_slice(&data),
+            Err(_) => return None,
+        },
+        _ => return None,
+    }
+    if out.len() - start != raw_len
+        || (cipher.is_none() && crc32fast::hash(&out[start..]) != checksum)
+    {
+        return None;
+    }
+    Some(HEADER_SIZE + stored_len)
+}
+
+/// Returns what the seal of a block authenticates besides its data: the
+/// method and raw length of its header and its number.
+fn sealed_data(header: &[u8], number: u64) -> [u8; 13] {
+    let mut data = [0; 13];
+    data[..5].copy_from_slice(&header[..5]);
+    BigEndian::write_u64(&mut data[5..], number);
+    data
+}
+
+#[test]
+fn test_compressed_blocks() {
+    let mut samples: Vec<Vec<u8>> = vec![Vec::new(), b"abc".to_vec(), vec![7; BLOCK_SIZE]];
+    // rows of a table repeat a lot
+    let mut rows = Vec::new();
+    for i in 0..5000u32 {
+        rows.push(0);
+        rows.extend_from_slice(&[0, 0, (i >> 8) as u8, i as u8]);
+        rows.extend_from_slice(format!("name {:<10}", i % 37).as_bytes());
+    }
+    rows.truncate(BLOCK_SIZE);
+    samples.push(rows);
+    let mut noise = Vec::new();
+    let mut x: u32 = 1;
+    for _ in 0..10_000 {
+        x = x.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+        noise.push((x >> 16) as u8);
+    }
+    samples.push(noise);
+
+    for &(compression, method) in &[(Compression::Lz4, LZ4), (Compression::Zstd, ZSTD)] {
+        for sample in &samples {
+            let mut block = Vec::new();
+            encode_block(sample, 0, compression, None, &mut block);
+            let mut out = vec![1, 2];
+            assert_eq!(decode_block(&block, 0, None, &mut out), Some(block.len()));
+            assert_eq!(&out[2..], &sample[..]);
+        }
+        let mut block = Vec::new();
+        encode_block(&samples[3], 0, compression, None, &mut block);
+        assert_eq!(block[0], method);
+        assert!(block.len() < samples[3].len() / 2);
+        let last = block.len() - 1;
+        block[last] ^= 1;
+        assert_eq!(decode_block(&block, 0, None, &mut Vec::new()), None);
+        // data that does not get smaller is stored
+        let mut block = Vec::new();
+        encode_block(&samples[4], 0, compression, None, &mut block);
+        assert_eq!(block[0], STORED);
+    }
+}
+
+#[test]
+fn test_encrypted_blocks() {
+    use aes_gcm::aead::KeyInit;
+
+    let cipher = Aes256Gcm::new_from_slice(&[1; encrypt::KEY_SIZE]).unwrap();
+    let rows = b"name alice name bob name carol name dave ".repeat(100);
+    let mut block = Vec::new();
+    encode_block(&rows, 3, Compression::Lz4, Some(&cipher), &mut block);
+    assert!(block[0] == LZ4 | ENCRYPTED && block.len() < rows.len());
+    assert!(!block.windows(5).any(|w| w == b"alice"));
+    let mut out = Vec::new();
+    assert_eq!(
+        decode_block(&block, 3, Some(&cipher), &mut out),
+        Some(block.len())
+    );
+    assert_eq!(out, rows);
+    // moved, read without the key or changed
+    assert_eq!(
+        decode_block(&block, 4, Some(&cipher), &mut Vec::new()),
+        None
+    );
+    assert_eq!(decode_block(&block, 3, None, &mut Vec::new()), None);
+    block[1] ^= 1;
+    assert_eq!(
+        decode_block(&block, 3, Some(&cipher), &mut Vec::new()),
+        None
+    );
+
+    // plain blocks are not read as part of an encrypted file
+    let mut plain = Vec::new();
+    encode_block(b"rows", 0, Compression::None, None, &mut plain);
+    assert_eq!(plain[0], STORED);
+    assert_eq!(
+        decode_block(&plain, 0, Some(&cipher), &mut Vec::new()),
+        None
+    );
+}
@@ -0,0 +1,108 @@
+//! Row counts of flat file tables
+//!
+//! The flat file engine keeps the number of rows of a table that are not
+//! marked as deleted in `<table>.cnt`, together with the length of the data
+//! file they were counted in. Every write of the engine updates both, so
+//! that `SELECT COUNT(*)` needs no scan. A count whose length is not that of
+//! the data file is out of date, e.g. because the table was written by an
+//! older server or a write failed halfway, and is not used until a scan
+//! replaced it, see `write`.
+//!
+//! The count holds all rows of the file, also those a snapshot does not
+//! see: the executor only uses it if the snapshot sees all of them.
+
+use super::meta::Table;
+use super::Error;
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use std::fs::{self, File};
+use std::io;
+use std::sync::Mutex;
+
+/// Length recorded while the data file is written, which no file has
+const WRITING: u64 = u64::MAX;
+
+/// Serializes reading and rewriting count files
+static COUNTS: Mutex<()> = Mutex::new(());
+
+/// Returns the number of rows of the table if the count is up to date.
+pub fn read(table: &Table) -> Result<Option<u64>, Error> {
+    let _counts = COUNTS.lock().unwrap();
+    match try!(read_file(table)) {
+        Some((rows, len)) if len == try!(data_len(table)) => Ok(Some(rows)),
+        _ => Ok(None),
+    }
+}
+
+/// Stores the number of rows of the table, which must have been counted
+/// while no rows were written.
+pub fn write(table: &Table, rows: u64) -> Result<(), Error> {
+    let _counts = COUNTS.lock().unwrap();
+    let len = try!(data_len(table));
+    write_file(table, rows, len)
+}
+
+/// Marks the count as out of date before the data file is written and
+/// returns the number of rows it held, if it was up to date, for `end`.
+/// A count that is not ended stays out of date.
+pub fn begin(table: &Table) -> Result<Option<u64>, Error> {
+    let _counts = COUNTS.lock().unwrap();
+    match try!(read_file(table)) {
+        Some((rows, len)) if len == try!(data_len(table)) => {
+            try!(write_file(table, rows, WRITING));
+            Ok(Some(rows))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Stores the count after the data file was written: the rows returned by
+/// `begin` and `added` ones, which are negative for deleted rows.
+pub fn end(table: &Table, rows: Option<u64>, added: i64) -> Result<(), Error> {
+    let rows = match rows {
+        Some(rows) => rows,
+        None => return Ok(()),
+    };
+    let _counts = COUNTS.lock().unwrap();
+    let len = try!(data_len(table));
+    write_file(table, (rows as i64 + added) as u64, len)
+}
+
+/// Deletes the count file of a table, if it has one.
+pub fn delete(table: &Table) -> Result<(), Error> {
+    match fs::remove_file(table.get_table_count_path()) {
+        Ok(()) => Ok(()),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(Error::Io(e)),
+    }
+}
+
+fn data_len(table: &Table) -> Result<u64, Error> {
+    Ok(try!(fs::metadata(table.get_table_data_path())).len())
+}
+
+/// Returns the rows and the length of the count file, None if there is
+/// none or it is cut short.
+fn read_file(table: &Table) -> Result<Option<(u64, u64)>, Error> {
+    let mut file = match File::open(table.get_table_count_path()) {
+        Ok(file) => file,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let read = file
+        .read_u64::<BigEndian>()
+        .and_then(|rows| Ok((rows, try!(file.read_u64::<BigEndian>()))));
+    match read {
+        Ok(count) => Ok(Some(count)),
+        Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn write_file(table: &Table, rows: u64, len: u64) -> Result<(), Error> {
+    let mut buf = Vec::with_capacity(16);
+    try!(buf.write_u64::<BigEndian>(rows));
+    try!(buf.write_u64::<BigEndian>(len));
+    Ok(try!(fs::write(table.get_table_count_path(), buf)))
+}
@@ -1,11 +1,18 @@
 use super::super::parse::ast::CompType;
-use super::types::Column;
+use super::types::{self, Column};
 use super::Error;
+use std::collections::HashSet;
 use std::io::{Cursor, Read, Seek, SeekFrom, Write};
+use std::ops::Bound;
 use std::vec::Vec;
 
+use byteorder::{BigEndian, ByteOrder};
 use serde::{Deserialize, Serialize};
 
+/// Size of the checksum following the data of a row, see
+/// `Rows::with_checksums`
+pub const CHECKSUM_SIZE: u64 = 4;
+
 #[derive(Debug)]
 pub struct Rows<B: Write + Read + Seek> {
     data_src: B,
@@ -13,6 +20,19 @@ pub struct Rows<B: Write + Read + Seek> {
     columns_size: u64,
     pub column_offsets: Vec<u64>,
     pos: u64,
+    checksums: bool,
+}
+
+/// A defect of a data file found by `Rows::check`
+#[derive(Debug, PartialEq)]
+pub enum Damage {
+    /// The data of the row at the address does not match its checksum
+    Checksum(u64),
+    /// The row header at the address is neither that of a row nor that of a
+    /// deleted row
+    Header(u64),
+    /// The file ends within the row at the address
+    Truncated(u64),
 }
 
 /// Represents the lines read from file.
@@ -31,38 +51,20 @@ impl<B: Write + Read + Seek> Rows<B> {
             columns_size: Self::get_columns_size(columns),
             column_offsets: column_offsets,
             pos: 0,
+            checksums: false,
         }
     }
-    /// returns the sum of the column sizes
-    fn get_columns_size(columns: &[Column]) -> u64 {
-        let mut size: u64 = 0;
-        for c in columns {
-            size += c.get_size() as u64;
-        }
-        size
-    }
-
-    /// Returns the last not deleted row.
-    /// The search begins with the first row located before position.
-    /// An error is returned if no row could be found.
-    /// Moves cursor to the end of the read row.
-    fn get_last_row(
-        &mut self,
-        position: SeekFrom,
-        ignore_deleted_rows: bool,
-    ) -> Result<Vec<u8>, Error> {
-        try!(self.set_pos(position));
-        try!(self.prev_row());
-
-        // Moves cursor to the beginning of the first not deleted row
-        // from the end.
-        while ignore_deleted_rows && try!(self.is_next_row_deleted(true)) {
-            try!(self.prev_row());
-        }
 
-        let mut row_data = Vec::<u8>::new();
-        try!(self.next_row_ex(&mut row_data, false));
-        Ok(row_data)
+    /// Like `new`, but every row of data_src is followed by the CRC-32 of its
+    /// data, which is verified when the row is read.
+    pub fn with_checksums(data_src: B, columns: &[Column]) -> Rows<B> {
+        let mut rows = Rows::new(data_src, columns);
+        rows.checksums = true;
+        rows
+    }
+    /// returns the sum of the column sizes and the size of the null bitmap
+    fn get_columns_size(columns: &[Column]) -> u64 {
+        types::row_size(columns) as u64
     }
 
     /// Tests if the row after the cursor is marked as deleted
@@ -78,15 +80,6 @@ impl<B: Write + Read + Seek> Rows<B> {
         Ok(row_header.is_deleted())
     }
 
-    /// Moves the cursor to the beginning of the next deleted row
-    fn move_to_next_deleted_row(&mut self) -> Result<(), Error> {
-        let mut row_data = Vec::<u8>::new();
-        while try!(self.is_next_row_deleted(true)) {
-            try!(self.next_row_ex(&mut row_data, false));
-        }
-        Ok(())
-    }
-
     /// reads the next row, which is not marked as deleted
     /// and writes the data into target_buf
     /// returns the bytes read or an Error otherwise.
@@ -107,13 +100,12 @@ impl<B: Write + Read + Seek> Rows<B> {
     ) -> Result<u64, Error> {
         info!("Moving to next row.");
         let mut target_vec = Vec::<u8>::new();
-        let columns_size = self.columns_size;
 
         while try!(self.is_next_row_deleted(false)) && ignore_deleted_rows {
             try!(self.skip_row());
         }
 
-        try!(self.read_bytes(columns_size, &mut target_vec));
+        try!(self.read_row_data(&mut target_vec));
         try!(target_buf.write_all(&target_vec));
         info!("Row written to target_buf. Leaving next_row.");
         Ok(target_vec.len() as u64)
@@ -123,14 +115,49 @@ impl<B: Write + Read + Seek> Rows<B> {
     /// Be sure to only call skip_row after the row header was
     /// read.
     fn skip_row(&mut self) -> Result<u64, Error> {
-        let columns_size = self.columns_size as i64;
-        self.set_pos(SeekFrom::Current(columns_size))
+        let data_size = self.get_data_size() as i64;
+        self.set_pos(SeekFrom::Current(data_size))
     }
 
     /// Returns the size of a row including the size of the
     /// row header.
     fn get_row_size(&self) -> u64 {
-        self.columns_size + RowHeader::size()
+        self.get_data_size() + RowHeader::size()
+    }
+
+    /// Returns the size of a row without the row header: its data and its
+    /// checksum, if rows have one.
+    fn get_data_size(&self) -> u64 {
+        if self.checksums {
+            self.columns_size + CHECKSUM_SIZE
+        } else {
+            self.columns_size
+        }
+    }
+
+    /// Returns true if the bytes of a row after its header hold the checksum
+    /// of its data, or if rows have no checksums.
+    fn checksum_matches(&self, data: &[u8]) -> bool {
+        if !self.checksums {
+            return true;
+        }
+        let columns_size = self.columns_size as usize;
+        crc32fast::hash(&data[..columns_size]) == BigEndian::read_u32(&data[columns_size..])
+    }
+
+    /// Reads the data of the row after its header into target_buf and
+    /// verifies its checksum.
+    /// Returns Error::ChecksumMismatch if the data does not match.
+    fn read_row_data(&mut self, target_buf: &mut Vec<u8>) -> Result<u64, Error> {
+        let addr = self.pos - RowHeader::size();
+        let start = target_buf.len();
+        let data_size = self.get_data_size();
+        try!(self.read_bytes(data_size, target_buf));
+        if !self.checksum_matches(&target_buf[start..]) {
+            return Err(Error::ChecksumMismatch(addr));
+        }
+        target_buf.truncate(start + self.columns_size as usize);
+        Ok(self.columns_size)
     }
 
     /// sets pos to the beginning of the previous row
@@ -145,6 +172,34 @@ impl<B: Write + Read + Seek> Rows<B> {
         self.set_pos(SeekFrom::Current(-row_size))
     }
 
+    /// Returns the address of the row read last, i.e. the position of its
+    /// row header.
+    pub fn last_row_addr(&self) -> u64 {
+        self.pos - self.get_row_size()
+    }
+
+    /// Reads the row whose row header is located at addr.
+    /// Returns None if the row is marked as deleted.
+    pub fn row_at(&mut self, addr: u64) -> Result<Option<Vec<u8>>, Error> {
+        try!(self.set_pos(SeekFrom::Start(addr)));
+        if try!(self.is_next_row_deleted(false)) {
+            return Ok(None);
+        }
+        let mut row_data = Vec::<u8>::new();
+        try!(self.read_row_data(&mut row_data));
+        Ok(Some(row_data))
+    }
+
+    /// Returns the data source
+    pub fn get_mut(&mut self) -> &mut B {
+        &mut self.data_src
+    }
+
+    /// Writes changes buffered by the data source through
+    pub fn flush(&mut self) -> Result<(), Error> {
+        Ok(try!(self.data_src.flush()))
+    }
+
     /// sets position before the first line
     pub fn reset_pos(&mut self) -> Result<u64, Error> {
         self.set_pos(SeekFrom::Start(0))
@@ -173,9 +228,22 @@ impl<B: Write + Read + Seek> Rows<B> {
     /// writes a new row into buf, returns bytes written
     pub fn add_row(&mut self, data: &[u8]) -> Result<u64, Error> {
         info!("Adding Row");
-        let new_row_header = RowHeader::new(0);
-        try!(self.write_bytes(&new_row_header.to_raw_data()));
-        Ok(try!(self.write_bytes(&data)))
+        let mut stored = Vec::new();
+        self.append_stored_row(&mut stored, data);
+        try!(self.write_bytes(&stored));
+        Ok(data.len() as u64)
+    }
+
+    /// Appends a row to the end with its header and checksum, as it is
+    /// stored, to buf.
+    fn append_stored_row(&self, buf: &mut Vec<u8>, data: &[u8]) {
+        buf.extend(RowHeader::new(0).to_raw_data());
+        buf.extend_from_slice(data);
+        if self.checksums {
+            let mut checksum = [0; CHECKSUM_SIZE as usize];
+            BigEndian::write_u32(&mut checksum, crc32fast::hash(data));
+            buf.extend_from_slice(&checksum);
+        }
     }
 
     /// set delete bit for one row
@@ -188,53 +256,115 @@ impl<B: Write + Read + Seek> Rows<B> {
         Ok(())
     }
 
+    /// Counts the rows from the beginning: those which are not and those
+    /// which are marked as deleted.
+    pub fn count_rows(&mut self) -> Result<(u64, u64), Error> {
+        try!(self.reset_pos());
+        let (mut rows, mut deleted) = (0, 0);
+        loop {
+            match self.is_next_row_deleted(false) {
+                Ok(true) => deleted += 1,
+                Ok(false) => rows += 1,
+                Err(Error::EndOfFile) => return Ok((rows, deleted)),
+                Err(e) => return Err(e),
+            }
+            try!(self.skip_row());
+        }
+    }
+
     /// Reorganizes the current instance
-    /// Removes all rows which are marked as deleted.
+    /// Removes all rows which are marked as deleted by moving the
+    /// following rows forward, keeping their order.
     /// Returns the new size of the reorganized object.
     pub fn reorganize(&mut self) -> Result<u64, Error> {
+        let row_size = self.get_row_size();
+        let mut row_data = Vec::<u8>::new();
+        // end of the rows kept so far
+        let mut end = 0;
         try!(self.reset_pos());
-        let mut move_result: Result<(), Error>;
-        let mut saved_pos;
-        let mut last_row_found: bool;
-        let mut row_data: Vec<u8> = Vec::<u8>::new();
-        let mut last_row_seek_from = SeekFrom::End(0);
-
         loop {
-            // find first deleted row
-            move_result = self.move_to_next_deleted_row();
-            match move_result {
-                Ok(_) => {}
-                Err(Error::EndOfFile) => break, // No deleted row found.
+            let addr = self.pos;
+            match self.is_next_row_deleted(false) {
+                Ok(true) => {
+                    try!(self.skip_row());
+                    continue;
+                }
+                Ok(false) => {}
+                Err(Error::EndOfFile) => return Ok(end),
                 Err(e) => return Err(e),
-            };
+            }
+            if addr != end {
+                row_data.clear();
+                try!(self.read_row_data(&mut row_data));
+                try!(self.set_pos(SeekFrom::Start(end)));
+                try!(self.add_row(&row_data));
+                try!(self.set_pos(SeekFrom::Start(addr + row_size)));
+            } else {
+                try!(self.skip_row());
+            }
+            end += row_size;
+        }
+    }
 
-            saved_pos = self.pos; // pos before deleted row.
-            last_row_found = match self.get_last_row(last_row_seek_from, true) {
-                Err(Error::BeginningOfFile) => false, // No not deleted row found.
-                Err(e) => return Err(e),
-                Ok(v) => {
-                    row_data = v;
-                    true
+    /// Reads all rows from the beginning and returns the defects found.
+    pub fn check(&mut self) -> Result<Vec<Damage>, Error> {
+        let mut damages = Vec::new();
+        try!(self.examine(|row| {
+            if let Err(damage) = row {
+                damages.push(damage);
+            }
+        }));
+        Ok(damages)
+    }
+
+    /// Copies the rows which are not deleted into a new Rows object, leaving
+    /// out damaged rows.
+    /// Returns the copied rows and the number of rows left out.
+    pub fn salvage(&mut self) -> Result<(Rows<Cursor<Vec<u8>>>, u64), Error> {
+        let mut rows = Rows::new(Cursor::new(Vec::new()), &self.columns);
+        let mut lost = 0;
+        let mut result = Ok(());
+        try!(self.examine(|row| match row {
+            Ok(row_data) => {
+                if result.is_ok() {
+                    result = rows.add_row(row_data).map(|_| ());
                 }
-            };
-            // pos is located after the row returned by the previous call
-            // to self.get_last_row().
-            // deleted row found && last_row found
-            if last_row_found {
-                if self.pos == saved_pos {
-                    // Cursor is located after the first not deleted line.
-                    return Ok(self.pos);
-                };
-                last_row_seek_from = SeekFrom::Start(self.pos - self.get_row_size());
-                try!(self.set_pos(SeekFrom::Start(saved_pos)));
-                try!(self.add_row(&row_data));
+            }
+            Err(_) => lost += 1,
+        }));
+        try!(result);
+        Ok((rows, lost))
+    }
+
+    /// Reads all rows from the beginning and calls found with the data of
+    /// every row which is not deleted, or with the defect of a damaged row.
+    fn examine<F>(&mut self, mut found: F) -> Result<(), Error>
+    where
+        F: FnMut(Result<&[u8], Damage>),
+    {
+        let row_size = self.get_row_size();
+        let len = try!(self.set_pos(SeekFrom::End(0)));
+        try!(self.reset_pos());
+        let mut row = Vec::<u8>::new();
+        while self.pos + row_size <= len {
+            let addr = self.pos;
+            row.clear();
+            try!(self.read_bytes(row_size, &mut row));
+            let header = row[0];
+            if header > 1 {
+                found(Err(Damage::Header(addr)));
+            } else if RowHeader::new(header).is_deleted() {
+                continue;
+            } else if self.checksum_matches(&row[1..]) {
+                found(Ok(&row[1..1 + self.columns_size as usize]));
             } else {
-                // gelöschte Zeile gefunden && !last_row found
-                try!(self.set_pos(SeekFrom::Start(saved_pos)));
-                return Ok(self.pos);
+                found(Err(Damage::Checksum(addr)));
             }
         }
-        Err(Error::InvalidState)
+        if self.pos < len {
+            found(Err(Damage::Truncated(self.pos)));
+        }
+        Ok(())
     }
 
     /// returns the value of the column_index' column of the current row
@@ -250,8 +380,29 @@ impl<B: Write + Read + Seek> Rows<B> {
         Ok(d)
     }
 
+    /// returns the value of the column_index' column of the current row or
+    /// None if it is null
+    pub fn get_nullable_value(
+        &self,
+        row_data: &[u8],
+        column_index: usize,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        if types::is_null(&self.columns, row_data, column_index) {
+            return Ok(None);
+        }
+        self.get_value(row_data, column_index).map(Some)
+    }
+
+    /// writes a new row built from the values of its columns into buf,
+    /// None stands for null. Returns bytes written.
+    pub fn add_values(&mut self, values: &[Option<Vec<u8>>]) -> Result<u64, Error> {
+        let row_data = try!(types::encode_row(&self.columns, values));
+        self.add_row(&row_data)
+    }
+
     /// Sets value of column_index' column to new_value.
-    fn set_value(&self, row_data: &mut [u8], new_value: &[u8], column_index: usize) {
+    pub fn set_value(&self, row_data: &mut [u8], new_value: &[u8], column_index: usize) {
+        types::set_null(&self.columns, row_data, column_index, false);
         // start index of column
         let s = self.column_offsets[column_index] as usize;
         // end index of column
@@ -313,6 +464,97 @@ impl<B: Write + Read + Seek> Rows<B> {
         Ok(try!(self.add_row(row_data)))
     }
 
+    /// Returns the rows whose value of a column lies within the bounds,
+    /// ordered by that value. Rows whose value is null are left out.
+    pub fn range(
+        &mut self,
+        column_index: usize,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<Rows<Cursor<Vec<u8>>>, Error> {
+        let sql_type = self.columns[column_index].sql_type;
+        let value_range = types::column_range(&self.columns, column_index);
+        try!(self.reset_pos());
+        let mut selected = Vec::new();
+        loop {
+            let mut row = Vec::new();
+            match self.next_row(&mut row) {
+                Ok(_) => (),
+                Err(Error::EndOfFile) => break,
+                Err(e) => return Err(e),
+            }
+            if try!(types::within(
+                &self.columns,
+                &row,
+                column_index,
+                lower,
+                upper
+            )) {
+                selected.push((sql_type.sort_key(&row[value_range.clone()]), row));
+            }
+        }
+        selected.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut rows = Rows::new(Cursor::new(Vec::new()), &self.columns);
+        for (_, row) in selected {
+            try!(rows.add_row(&row));
+        }
+        Ok(rows)
+    }
+
+    /// Appends rows at the end in a single write and returns their
+    /// addresses. Does not check if their primary keys exist.
+    pub fn insert_rows_without_primary(&mut self, rows: &[&[u8]]) -> Result<Vec<u64>, Error> {
+        let start = try!(self.set_pos(SeekFrom::End(0)));
+        let mut stored = Vec::new();
+        let mut addrs = Vec::with_capacity(rows.len());
+        for row_data in rows {
+            addrs.push(start + stored.len() as u64);
+            self.append_stored_row(&mut stored, row_data);
+        }
+        try!(self.write_bytes(&stored));
+        Ok(addrs)
+    }
+
+    /// Fails with `Error::PrimaryKeyValueExists` if the primary key of one of
+    /// the rows exists already or is that of another one of them. Reads the
+    /// stored rows once, however many are given.
+    pub fn check_primary_keys(&mut self, rows: &[&[u8]]) -> Result<(), Error> {
+        let pks: Vec<usize> = (0..self.columns.len())
+            .filter(|&i| self.columns[i].is_primary_key)
+            .collect();
+        if pks.is_empty() {
+            return Err(Error::FoundNoPrimaryKey);
+        }
+        let mut keys = HashSet::new();
+        for row_data in rows {
+            if !keys.insert(try!(self.primary_key(&pks, row_data))) {
+                return Err(Error::PrimaryKeyValueExists);
+            }
+        }
+        try!(self.reset_pos());
+        let mut row = Vec::new();
+        loop {
+            match self.next_row(&mut row) {
+                Ok(_) => (),
+                Err(Error::EndOfFile) => return Ok(()),
+                Err(e) => return Err(e),
+            }
+            if keys.contains(&try!(self.primary_key(&pks, &row))) {
+                return Err(Error::PrimaryKeyValueExists);
+            }
+            row.clear();
+        }
+    }
+
+    /// Returns the values of the primary key columns of a row.
+    fn primary_key(&self, pks: &[usize], row_data: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut key = Vec::new();
+        for &i in pks {
+            key.extend(try!(self.get_value(row_data, i)));
+        }
+        Ok(key)
+    }
+
     /// Inserts a new row with row_data.
     /// Returns the number of rows inserted.
     pub fn insert_row(&mut self, row_data: &[u8]) -> Result<u64, Error> {
@@ -533,28 +775,18 @@ impl<B: Write + Read + Seek> Rows<B> {
         while b {
             b = match self.next_row(&mut row) {
                 Ok(_) => {
-                    let col = self.get_column(column_index);
-
-                    let row_value: &Vec<u8> = &try!(self.get_value(&row, column_index));
-                    if value.1.is_none() {
-                        let cmp_result = try!(col.sql_type.cmp(row_value, value.0, comp));
-
-                        if cmp_result {
-                            false
-                        } else {
-                            row.clear();
-                            true
-                        }
+                    let cmp_result = try!(types::fulfills(
+                        &self.columns,
+                        &row,
+                        column_index,
+                        value,
+                        comp
+                    ));
+                    if cmp_result {
+                        false
                     } else {
-                        let cmpindex = value.1.unwrap();
-                        let cmp_value: &Vec<u8> = &try!(self.get_value(&row, cmpindex));
-                        let cmp_result = try!(col.sql_type.cmp(row_value, cmp_value, comp));
-                        if cmp_result {
-                            false
-                        } else {
-                            row.clear();
-                            true
-                        }
+                        row.clear();
+                        true
                     }
                 }
                 Err(e) => return Err(e),
@@ -649,3 +881,75 @@ pub struct ResultSet {
     pub data: Vec<u8>,
     pub columns: Vec<Column>,
 }
+
+#[test]
+fn test_reorganize() {
+    use super::types::SqlType;
+
+    let columns = [Column::new("id", SqlType::Int, false, "", true)];
+    let mut rows = Rows::new(Cursor::new(Vec::new()), &columns);
+    for i in 0..6u8 {
+        rows.add_row(&[0, 0, 0, i]).unwrap();
+    }
+    // deletes the rows 1, 2 and 4
+    for &i in &[1, 2, 4] {
+        rows.set_pos(SeekFrom::Start((i + 1) * rows.get_row_size()))
+            .unwrap();
+        rows.delete_row().unwrap();
+    }
+    assert_eq!(rows.count_rows().unwrap(), (3, 3));
+
+    let size = rows.reorganize().unwrap();
+    assert_eq!(size, 3 * rows.get_row_size());
+    rows.data_src.get_mut().truncate(size as usize);
+    assert_eq!(rows.count_rows().unwrap(), (3, 0));
+    let mut ids = Vec::new();
+    rows.reset_pos().unwrap();
+    let mut row = Vec::new();
+    while rows.next_row(&mut row).is_ok() {
+        ids.push(row[3]);
+        row.clear();
+    }
+    ids.sort();
+    assert_eq!(ids, vec![0, 3, 5]);
+
+    // nothing to do without deleted rows
+    assert_eq!(rows.reorganize().unwrap(), size);
+}
+
+#[test]
+fn test_checksums() {
+    use super::types::SqlType;
+
+    let columns = [Column::new("id", SqlType::Int, false, "", true)];
+    let mut rows = Rows::with_checksums(Cursor::new(Vec::new()), &columns);
+    for i in 0..4u8 {
+        rows.add_row(&[0, 0, 0, i]).unwrap();
+    }
+    let row_size = rows.get_row_size();
+    assert_eq!(row_size, 1 + 4 + CHECKSUM_SIZE);
+    assert_eq!(rows.check().unwrap(), vec![]);
+
+    // damages the data of row 1, the header of row 2 and cuts off row 3
+    rows.data_src.get_mut()[row_size as usize + 2] ^= 0xFF;
+    rows.data_src.get_mut()[2 * row_size as usize] = 7;
+    let len = rows.data_src.get_ref().len();
+    rows.data_src.get_mut().truncate(len - 1);
+    assert_eq!(
+        rows.check().unwrap(),
+        vec![
+            Damage::Checksum(row_size),
+            Damage::Header(2 * row_size),
+            Damage::Truncated(3 * row_size),
+        ]
+    );
+    match rows.row_at(row_size) {
+        Err(Error::ChecksumMismatch(addr)) => assert_eq!(addr, row_size),
+        other => panic!("unexpected {:?}", other),
+    }
+
+    let (mut salvaged, lost) = rows.salvage().unwrap();
+    assert_eq!(lost, 3);
+    assert_eq!(salvaged.count_rows().unwrap(), (1, 0));
+    assert_eq!(rows.row_at(0).unwrap(), Some(vec![0, 0, 0, 0]));
+}
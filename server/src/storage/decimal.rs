@@ -0,0 +1,218 @@
+//! Fixed-point numbers
+//!
+//! A DECIMAL(p, s) value is stored as the integer `value * 10^s`, so that
+//! it holds at most p digits of which s follow the decimal point. The
+//! integer is an i64, which limits the precision to `MAX_PRECISION` digits.
+//! All arithmetic is exact, results that would need rounding are rounded
+//! half away from zero.
+
+use std::cmp::max;
+
+/// Maximum number of digits of a DECIMAL
+pub const MAX_PRECISION: u8 = 18;
+/// Number of digits after the point of a quotient beyond those of the
+/// dividend
+pub const DIVISION_SCALE: u8 = 4;
+
+/// Parses a number like `-12.50` into its digits and its scale.
+pub fn parse(s: &str) -> Option<(i64, u8)> {
+    let s = s.trim();
+    let (negative, s) = if s.starts_with('-') {
+        (true, &s[1..])
+    } else {
+        (false, s.trim_start_matches('+'))
+    };
+    let (whole, fraction) = match s.find('.') {
+        Some(pos) => (&s[..pos], &s[pos + 1..]),
+        None => (s, ""),
+    };
+    if whole.is_empty() && fraction.is_empty() {
+        return None;
+    }
+    if !whole
+        .chars()
+        .chain(fraction.chars())
+        .all(|c| c.is_digit(10))
+    {
+        return None;
+    }
+    let digits = whole.trim_start_matches('0').len() + fraction.len();
+    if digits > MAX_PRECISION as usize {
+        return None;
+    }
+    let mut value: i64 = 0;
+    for c in whole.chars().chain(fraction.chars()) {
+        value = value * 10 + c.to_digit(10).unwrap_or(0) as i64;
+    }
+    let value = if negative { -value } else { value };
+    Some((value, fraction.len() as u8))
+}
+
+/// Formats the digits of a number with the given scale, e.g. `-12.50`.
+pub fn format(value: i64, scale: u8) -> String {
+    let digits = (value as i128).abs().to_string();
+    let sign = if value < 0 { "-" } else { "" };
+    if scale == 0 {
+        return format!("{}{}", sign, digits);
+    }
+    let scale = scale as usize;
+    let digits = if digits.len() <= scale {
+        format!("{}{}", "0".repeat(scale + 1 - digits.len()), digits)
+    } else {
+        digits
+    };
+    let point = digits.len() - scale;
+    format!("{}{}.{}", sign, &digits[..point], &digits[point..])
+}
+
+/// Converts a float into a number with the given scale.
+pub fn from_f64(f: f64, scale: u8) -> Option<i64> {
+    // the shortest representation of f is the number that was written
+    parse(&format!("{}", f)).and_then(|(value, s)| rescale(value, s, scale))
+}
+
+/// Returns the nearest float of a number.
+pub fn to_f64(value: i64, scale: u8) -> f64 {
+    value as f64 / 10f64.powi(scale as i32)
+}
+
+/// Changes the scale of a number, rounding if digits get lost. Returns None
+/// on overflow.
+pub fn rescale(value: i64, from: u8, to: u8) -> Option<i64> {
+    if to >= from {
+        to_i64(value as i128 * pow10(to - from))
+    } else {
+        narrow(value as i128, from - to)
+    }
+}
+
+/// Returns true if a number has at most `precision` digits.
+pub fn fits(value: i64, precision: u8) -> bool {
+    (value as i128).abs() < pow10(precision)
+}
+
+/// Adds two numbers, the sum has the larger scale of both.
+pub fn add(a: (i64, u8), b: (i64, u8)) -> Option<(i64, u8)> {
+    let scale = max(a.1, b.1);
+    match (rescale(a.0, a.1, scale), rescale(b.0, b.1, scale)) {
+        (Some(x), Some(y)) => x.checked_add(y).map(|sum| (sum, scale)),
+        _ => None,
+    }
+}
+
+/// Subtracts b from a, the difference has the larger scale of both.
+pub fn sub(a: (i64, u8), b: (i64, u8)) -> Option<(i64, u8)> {
+    b.0.checked_neg().and_then(|neg| add(a, (neg, b.1)))
+}
+
+/// Multiplies two numbers, the scale of the product is the sum of both.
+pub fn mul(a: (i64, u8), b: (i64, u8)) -> Option<(i64, u8)> {
+    let scale = a.1 + b.1;
+    let product = a.0 as i128 * b.0 as i128;
+    if scale > MAX_PRECISION {
+        // drop the digits which can not be stored
+        let excess = scale - MAX_PRECISION;
+        return narrow(product, excess).map(|p| (p, MAX_PRECISION));
+    }
+    to_i64(product).map(|p| (p, scale))
+}
+
+/// Divides a by b, the quotient has `DIVISION_SCALE` more digits after the
+/// point than a. Returns None if b is zero or on overflow.
+pub fn div(a: (i64, u8), b: (i64, u8)) -> Option<(i64, u8)> {
+    if b.0 == 0 {
+        return None;
+    }
+    let scale = min_scale(a.1 + DIVISION_SCALE);
+    // a * 10^(scale + b.1 - a.1) / b has the wanted scale
+    let shift = scale as i32 + b.1 as i32 - a.1 as i32;
+    let dividend = if shift >= 0 {
+        match (a.0 as i128).checked_mul(pow10(shift as u8)) {
+            Some(d) => d,
+            None => return None,
+        }
+    } else {
+        a.0 as i128 / pow10((-shift) as u8)
+    };
+    to_i64(round_div(dividend, b.0 as i128)).map(|q| (q, scale))
+}
+
+/// Returns the remainder of the division of a by b, with the sign of a.
+pub fn rem(a: (i64, u8), b: (i64, u8)) -> Option<(i64, u8)> {
+    if b.0 == 0 {
+        return None;
+    }
+    let scale = max(a.1, b.1);
+    match (rescale(a.0, a.1, scale), rescale(b.0, b.1, scale)) {
+        (Some(x), Some(y)) => Some((x % y, scale)),
+        _ => None,
+    }
+}
+
+fn min_scale(scale: u8) -> u8 {
+    if scale > MAX_PRECISION {
+        MAX_PRECISION
+    } else {
+        scale
+    }
+}
+
+/// Drops the last `digits` digits of a number, rounding half away from
+/// zero.
+fn narrow(value: i128, digits: u8) -> Option<i64> {
+    to_i64(round_div(value, pow10(digits)))
+}
+
+fn round_div(a: i128, b: i128) -> i128 {
+    let q = a / b;
+    let r = a % b;
+    if r.abs() * 2 >= b.abs() {
+        if (a < 0) == (b < 0) {
+            q + 1
+        } else {
+            q - 1
+        }
+    } else {
+        q
+    }
+}
+
+fn to_i64(value: i128) -> Option<i64> {
+    if value > i64::max_value() as i128 || value < i64::min_value() as i128 {
+        None
+    } else {
+        Some(value as i64)
+    }
+}
+
+fn pow10(exp: u8) -> i128 {
+    10i128.pow(exp as u32)
+}
+
+#[test]
+fn test_parse_and_format() {
+    assert_eq!(parse("12.50"), Some((1250, 2)));
+    assert_eq!(parse("-0.05"), Some((-5, 2)));
+    assert_eq!(parse("7"), Some((7, 0)));
+    assert_eq!(parse("1.2.3"), None);
+    assert_eq!(parse("1234567890.1234567890"), None);
+    assert_eq!(format(1250, 2), "12.50");
+    assert_eq!(format(-5, 2), "-0.05");
+    assert_eq!(format(7, 0), "7");
+    assert_eq!(from_f64(0.1, 2), Some(10));
+}
+
+#[test]
+fn test_arithmetic() {
+    assert_eq!(add((10, 1), (20, 1)), Some((30, 1)));
+    assert_eq!(sub((100, 2), (5, 1)), Some((50, 2)));
+    assert_eq!(mul((150, 2), (3, 0)), Some((450, 2)));
+    assert_eq!(div((100, 2), (3, 0)), Some((333_333, 6)));
+    assert_eq!(div((2, 0), (3, 0)), Some((6667, 4)));
+    assert_eq!(div((1, 0), (0, 0)), None);
+    assert_eq!(rem((75, 1), (2, 0)), Some((15, 1)));
+    assert_eq!(rescale(125, 2, 1), Some(13));
+    assert_eq!(rescale(-125, 2, 1), Some(-13));
+    assert!(fits(99_999, 5));
+    assert!(!fits(100_000, 5));
+}
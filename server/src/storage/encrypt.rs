@@ -0,0 +1,133 @@
+//! Encryption of data files at rest
+//!
+//! The blocks of the data file of an encrypted table (see `compress`) are
+//! sealed with AES-256-GCM under the key of the server: a random nonce of
+//! 12 bytes, followed by the encrypted data and the tag of 16 bytes. The
+//! tag also covers the header of the block and its number, so blocks
+//! cannot be changed, swapped or moved without being noticed.
+//!
+//! Files written line by line, like the replication log, are sealed line
+//! by line, see `seal_line`.
+//!
+//! The key is set once at startup, see `set_key`, and never written to a
+//! file of the server. Encrypted tables cannot be opened without it.
+
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+
+use std::sync::RwLock;
+
+/// Bytes of a key
+pub const KEY_SIZE: usize = 32;
+const NONCE_SIZE: usize = 12;
+
+static CIPHER: RwLock<Option<Aes256Gcm>> = RwLock::new(None);
+
+/// Sets the key data files are encrypted with, None forgets it.
+pub fn set_key(key: Option<&[u8; KEY_SIZE]>) {
+    *CIPHER.write().unwrap() = key.map(|key| Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key)));
+}
+
+/// Returns the cipher of the key of the server, None if there is none.
+pub fn cipher() -> Option<Aes256Gcm> {
+    CIPHER.read().unwrap().clone()
+}
+
+/// Returns the key of the contents of a key file: 64 hexadecimal digits,
+/// surrounded by whitespace or not, or 32 bytes as they are.
+pub fn parse_key(text: &[u8]) -> Option<[u8; KEY_SIZE]> {
+    let mut key = [0; KEY_SIZE];
+    let hex = String::from_utf8_lossy(text);
+    let hex = hex.trim();
+    if hex.len() == 2 * KEY_SIZE && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        for (i, byte) in key.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).unwrap();
+        }
+        Some(key)
+    } else if text.len() == KEY_SIZE {
+        key.copy_from_slice(text);
+        Some(key)
+    } else {
+        None
+    }
+}
+
+/// Encrypts data, authenticating aad with it, and returns the nonce, the
+/// encrypted data and the tag.
+pub fn seal(cipher: &Aes256Gcm, aad: &[u8], data: &[u8]) -> Vec<u8> {
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let payload = Payload {
+        msg: data,
+        aad: aad,
+    };
+    // only fails for more data than a block ever holds
+    let sealed = cipher.encrypt(&nonce, payload).unwrap();
+    let mut out = nonce.to_vec();
+    out.extend(sealed);
+    out
+}
+
+/// Decrypts what `seal` returned for the same aad. Returns None if it was
+/// changed or sealed with another key.
+pub fn unseal(cipher: &Aes256Gcm, aad: &[u8], sealed: &[u8]) -> Option<Vec<u8>> {
+    if sealed.len() < NONCE_SIZE {
+        return None;
+    }
+    let payload = Payload {
+        msg: &sealed[NONCE_SIZE..],
+        aad: aad,
+    };
+    cipher
+        .decrypt(Nonce::from_slice(&sealed[..NONCE_SIZE]), payload)
+        .ok()
+}
+
+/// Seals a line of text with `seal` and returns it in base64, which holds
+/// no line breaks.
+pub fn seal_line(cipher: &Aes256Gcm, aad: &[u8], line: &str) -> String {
+    STANDARD.encode(seal(cipher, aad, line.as_bytes()))
+}
+
+/// Returns the line `seal_line` returned for the same aad, None if it was
+/// changed or sealed with another key.
+pub fn unseal_line(cipher: &Aes256Gcm, aad: &[u8], sealed: &str) -> Option<String> {
+    let sealed = match STANDARD.decode(sealed.trim_end()) {
+        Ok(sealed) => sealed,
+        Err(_) => return None,
+    };
+    unseal(cipher, aad, &sealed).and_then(|line| String::from_utf8(line).ok())
+}
+
+#[test]
+fn test_encrypt() {
+    let hex = b"000102030405060708090a0b0c0d0e0f101112131415161718191A1B1C1D1E1F\n";
+    let key = parse_key(hex).unwrap();
+    assert_eq!(key[0x1f], 0x1f);
+    assert_eq!(parse_key(&key), Some(key));
+    assert_eq!(parse_key(b"secret"), None);
+
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key));
+    let sealed = seal(&cipher, b"block 1", b"rows");
+    assert_eq!(sealed.len(), NONCE_SIZE + 4 + 16);
+    assert!(!sealed.windows(4).any(|w| w == b"rows"));
+    assert_eq!(unseal(&cipher, b"block 1", &sealed), Some(b"rows".to_vec()));
+    // the same data is sealed differently every time
+    assert!(seal(&cipher, b"block 1", b"rows") != sealed);
+    assert_eq!(unseal(&cipher, b"block 2", &sealed), None);
+    let mut changed = sealed.clone();
+    changed[NONCE_SIZE] ^= 1;
+    assert_eq!(unseal(&cipher, b"block 1", &changed), None);
+    let other = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&[7; KEY_SIZE]));
+    assert_eq!(unseal(&other, b"block 1", &sealed), None);
+
+    let line = seal_line(&cipher, b"log", "{\"rows\":1}");
+    assert!(!line.contains('\n') && !line.contains("rows"));
+    assert_eq!(
+        unseal_line(&cipher, b"log", &(line.clone() + "\n")),
+        Some("{\"rows\":1}".into())
+    );
+    assert_eq!(unseal_line(&other, b"log", &line), None);
+    assert_eq!(unseal_line(&cipher, b"log", "{\"rows\":1}"), None);
+}
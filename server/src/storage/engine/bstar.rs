@@ -0,0 +1,456 @@
+use super::super::super::parse::ast::CompType;
+use super::super::bstar::{Bstar, IterDirection, IterOption, KeyAddr, KnownSize};
+use super::super::data::{RowHeader, Rows};
+use super::super::index::SecondaryIndexes;
+use super::super::meta::Table;
+use super::super::types::{self, SqlType};
+use super::super::{Compression, Engine, Error};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+//---------------------------------------------------------------
+// BStar-Engine
+//---------------------------------------------------------------
+
+/// Maximum size of the primary key of a table in bytes
+const KEY_SIZE: usize = 256;
+/// Order of the index tree, a node holds up to `2 * INDEX_ORDER` keys
+const INDEX_ORDER: u64 = 7;
+
+/// Primary key of a row as stored in the index.
+///
+/// The values of all primary key columns are concatenated and padded to
+/// `KEY_SIZE` bytes, so that comparing two keys byte by byte orders them
+/// like their values.
+#[derive(Clone, Debug, PartialEq, PartialOrd)]
+pub struct IndexKey(Vec<u8>);
+
+impl IndexKey {
+    /// Builds the key from the raw values of the primary key columns.
+    fn new(values: &[(SqlType, &[u8])]) -> Result<IndexKey, Error> {
+        let mut key = Vec::with_capacity(KEY_SIZE);
+        for &(sql_type, value) in values {
+            key.extend_from_slice(&sql_type.sort_key(value));
+        }
+        if key.len() > KEY_SIZE {
+            return Err(Error::KeyTooLong);
+        }
+        key.resize(KEY_SIZE, 0);
+        Ok(IndexKey(key))
+    }
+}
+
+impl KnownSize for IndexKey {
+    fn size() -> u64 {
+        KEY_SIZE as u64
+    }
+
+    fn read(file: &mut File, addr: Option<u64>) -> io::Result<IndexKey> {
+        if let Some(addr) = addr {
+            try!(file.seek(SeekFrom::Start(addr)));
+        }
+        let mut key = vec![0; KEY_SIZE];
+        try!(file.read_exact(&mut key));
+        Ok(IndexKey(key))
+    }
+
+    fn write(&self, file: &mut File, addr: Option<u64>) -> io::Result<()> {
+        if let Some(addr) = addr {
+            try!(file.seek(SeekFrom::Start(addr)));
+        }
+        file.write_all(&self.0)
+    }
+
+    fn write_default(file: &mut File, addr: Option<u64>) -> io::Result<()> {
+        if let Some(addr) = addr {
+            try!(file.seek(SeekFrom::Start(addr)));
+        }
+        file.write_all(&[0; KEY_SIZE])
+    }
+}
+
+/// Stores the rows like `FlatFile`, but keeps a B* tree index on the primary
+/// key. Rows are returned ordered by primary key, and constraints on the
+/// primary key are answered through the index.
+pub struct BStar<'a> {
+    table: Table<'a>,
+}
+
+impl<'a> BStar<'a> {
+    ///
+    pub fn new<'b>(table: Table<'b>) -> BStar<'b> {
+        info!("new bstar with table: {:?}", table);
+        BStar { table: table }
+    }
+
+    /// Removes the index files of the table.
+    pub fn delete_index(table: &Table) -> Result<(), Error> {
+        info!("remove index files: {:?}", table.get_table_index_path());
+        Ok(try!(Bstar::<IndexKey>::delete(
+            &table.get_table_index_path()
+        )))
+    }
+
+    /// Opens table data file with read write access.
+    fn open_file_rw(&self) -> Result<File, Error> {
+        Ok(try!(OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&self.table.get_table_data_path())))
+    }
+
+    fn open_index(&self) -> Result<Bstar<IndexKey>, Error> {
+        Ok(try!(Bstar::load(&self.table.get_table_index_path())))
+    }
+
+    /// Replaces the index by an empty one.
+    fn recreate_index(&self) -> Result<Bstar<IndexKey>, Error> {
+        try!(Self::delete_index(&self.table));
+        self.create_index()
+    }
+
+    fn create_index(&self) -> Result<Bstar<IndexKey>, Error> {
+        let path = self.table.get_table_index_path();
+        Ok(try!(Bstar::create(&path, &self.table.name, INDEX_ORDER)))
+    }
+
+    /// Returns the indices of the primary key columns.
+    fn key_columns(&self) -> Vec<usize> {
+        let columns = self.table.columns().iter().enumerate();
+        columns
+            .filter(|&(_, c)| c.is_primary_key)
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /// Returns the position of a column inside of a row.
+    fn column_range(&self, column_index: usize) -> Range<usize> {
+        let columns = self.table.columns();
+        let start: usize = columns[..column_index]
+            .iter()
+            .map(|c| c.get_size() as usize)
+            .sum();
+        start..start + columns[column_index].get_size() as usize
+    }
+
+    /// Returns the value of a column inside of row_data.
+    fn value<'r>(&self, row_data: &'r [u8], column_index: usize) -> &'r [u8] {
+        &row_data[self.column_range(column_index)]
+    }
+
+    /// Returns the index key of a row.
+    fn key(&self, row_data: &[u8]) -> Result<IndexKey, Error> {
+        let columns = self.table.columns();
+        let values: Vec<_> = self
+            .key_columns()
+            .into_iter()
+            .map(|i| (columns[i].sql_type, self.value(row_data, i)))
+            .collect();
+        IndexKey::new(&values)
+    }
+
+    /// Reads the row at addr of the data file.
+    fn read_row(&self, dat: &mut File, addr: u64) -> Result<Vec<u8>, Error> {
+        let size = types::row_size(self.table.columns());
+        try!(dat.seek(SeekFrom::Start(addr + RowHeader::size())));
+        let mut row_data = vec![0; size];
+        try!(dat.read_exact(&mut row_data));
+        Ok(row_data)
+    }
+
+    /// Returns the index entries and the data of all rows which fulfill a
+    /// constraint, ordered by primary key. Comparisons of a single column
+    /// primary key with a value are answered through the index, those of
+    /// other columns through a secondary index, if there is one.
+    fn find(
+        &self,
+        index: &mut Bstar<IndexKey>,
+        indexes: &mut SecondaryIndexes,
+        dat: &mut File,
+        column_index: usize,
+        value: (&[u8], Option<usize>),
+        comp: CompType,
+    ) -> Result<Vec<(KeyAddr<IndexKey>, Vec<u8>)>, Error> {
+        let columns = self.table.columns();
+        let column = &columns[column_index];
+        let mut found = Vec::new();
+        let ordered = match comp {
+            CompType::NEqu | CompType::Match | CompType::NMatch => false,
+            CompType::IsNull | CompType::IsNotNull => false,
+            CompType::Like | CompType::NLike | CompType::Regexp | CompType::NRegexp => false,
+            _ => true,
+        };
+        if value.1.is_none() && ordered && self.key_columns() == [column_index] {
+            let key = try!(IndexKey::new(&[(column.sql_type, value.0)]));
+            let entries: Vec<_> = match comp {
+                CompType::Equ => try!(index.lookup_keyaddr(key)).into_iter().collect(),
+                CompType::GThan => {
+                    let from = Some(IterOption::Excluding(key));
+                    index.iter_options(IterDirection::Forward, from).collect()
+                }
+                CompType::GEThan => {
+                    let from = Some(IterOption::Including(key));
+                    index.iter_options(IterDirection::Forward, from).collect()
+                }
+                CompType::SThan => index.iter().take_while(|e| e.key < key).collect(),
+                CompType::SEThan => index.iter().take_while(|e| e.key <= key).collect(),
+                _ => unreachable!(),
+            };
+            for entry in entries {
+                let row_data = try!(self.read_row(dat, entry.addr));
+                found.push((entry, row_data));
+            }
+        } else if let Some(addrs) = indexes.lookup(column_index, value, comp) {
+            for addr in addrs {
+                let row_data = try!(self.read_row(dat, addr));
+                let key = try!(self.key(&row_data));
+                found.push((KeyAddr::new(key, addr), row_data));
+            }
+        } else {
+            let entries: Vec<_> = index.iter().collect();
+            for entry in entries {
+                let row_data = try!(self.read_row(dat, entry.addr));
+                let matches = try!(types::fulfills(
+                    columns,
+                    &row_data,
+                    column_index,
+                    value,
+                    comp
+                ));
+                if matches {
+                    found.push((entry, row_data));
+                }
+            }
+        }
+        Ok(found)
+    }
+
+    /// Returns a rows object containing the given rows.
+    fn to_rows<I>(&self, rows: I) -> Result<Rows<Cursor<Vec<u8>>>, Error>
+    where
+        I: IntoIterator<Item = Vec<u8>>,
+    {
+        let mut result = Rows::new(Cursor::new(Vec::new()), self.table.columns());
+        for row_data in rows {
+            try!(result.add_row(&row_data));
+        }
+        Ok(result)
+    }
+}
+
+impl<'a> Drop for BStar<'a> {
+    /// drops the BStar
+    fn drop(&mut self) {
+        info!("drop engine bstar");
+    }
+}
+
+impl<'a> Engine for BStar<'a> {
+    /// creates the data file and an empty index
+    /// returns with error if the table has no primary key
+    fn create_table(&mut self) -> Result<(), Error> {
+        // rows are read from the data file one by one
+        if self.table.meta_data.compression != Compression::None || self.table.meta_data.encrypted {
+            return Err(Error::NoImplementation);
+        }
+        let key_columns = self.key_columns();
+        if key_columns.is_empty() {
+            return Err(Error::MissingPrimaryKey);
+        }
+        let columns = self.table.columns();
+        let key_size: u32 = key_columns.iter().map(|&i| columns[i].get_size()).sum();
+        if key_size as usize > KEY_SIZE {
+            return Err(Error::KeyTooLong);
+        }
+
+        let _file = try!(OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&self.table.get_table_data_path()));
+        try!(self.create_index());
+        info!("created files for data and index: {:?}", _file);
+        Ok(())
+    }
+
+    /// returns own table
+    fn table(&self) -> &Table {
+        &self.table
+    }
+
+    /// returns all rows ordered by primary key
+    fn full_scan(&self) -> Result<Rows<Cursor<Vec<u8>>>, Error> {
+        info!("full scan");
+        let mut index = try!(self.open_index());
+        let mut dat = try!(self.open_file_rw());
+        let entries: Vec<_> = index.iter().collect();
+        let mut rows = Vec::with_capacity(entries.len());
+        for entry in entries {
+            rows.push(try!(self.read_row(&mut dat, entry.addr)));
+        }
+        self.to_rows(rows)
+    }
+
+    /// returns an new Rows object which fulfills a constraint
+    fn lookup(
+        &self,
+        column_index: usize,
+        value: (&[u8], Option<usize>),
+        comp: CompType,
+    ) -> Result<Rows<Cursor<Vec<u8>>>, Error> {
+        let mut index = try!(self.open_index());
+        let mut indexes = try!(SecondaryIndexes::open(&self.table));
+        let mut dat = try!(self.open_file_rw());
+        let found = try!(self.find(
+            &mut index,
+            &mut indexes,
+            &mut dat,
+            column_index,
+            value,
+            comp
+        ));
+        self.to_rows(found.into_iter().map(|(_, row_data)| row_data))
+    }
+
+    /// Inserts a new row with row_data.
+    /// Returns the number of rows inserted.
+    fn insert_row(&mut self, row_data: &[u8]) -> Result<u64, Error> {
+        let key = try!(self.key(row_data));
+        let mut index = try!(self.open_index());
+        if try!(index.lookup_keyaddr(key.clone())).is_some() {
+            return Err(Error::PrimaryKeyValueExists);
+        }
+        let mut indexes = try!(SecondaryIndexes::open(&self.table));
+        try!(indexes.check_unique(row_data));
+        let mut dat = try!(self.open_file_rw());
+        let addr = try!(dat.seek(SeekFrom::End(0)));
+        try!(dat.write_all(&RowHeader::new(0).to_raw_data()));
+        try!(dat.write_all(row_data));
+        try!(index.insert_keyaddr(KeyAddr::new(key, addr)));
+        try!(indexes.insert(row_data, addr));
+        Ok(1)
+    }
+
+    /// delete rows which fulfills a constraint
+    /// returns amount of deleted rows
+    fn delete(
+        &self,
+        column_index: usize,
+        value: (&[u8], Option<usize>),
+        comp: CompType,
+    ) -> Result<u64, Error> {
+        info!("Delete row");
+        let mut index = try!(self.open_index());
+        let mut indexes = try!(SecondaryIndexes::open(&self.table));
+        let mut dat = try!(self.open_file_rw());
+        let found = try!(self.find(
+            &mut index,
+            &mut indexes,
+            &mut dat,
+            column_index,
+            value,
+            comp
+        ));
+        for &(ref entry, ref row_data) in &found {
+            try!(dat.seek(SeekFrom::Start(entry.addr)));
+            try!(dat.write_all(&RowHeader::new(1).to_raw_data()));
+            try!(index.delete_keyaddr(entry.key.clone()));
+            try!(indexes.remove(row_data, entry.addr));
+        }
+        Ok(found.len() as u64)
+    }
+
+    /// Updates the rows which fulfill a constraint in place. The primary key
+    /// cannot be changed.
+    fn modify(
+        &mut self,
+        constraint_column_index: usize,
+        constraint_value: (&[u8], Option<usize>),
+        comp: CompType,
+        values: &[(usize, &[u8])],
+    ) -> Result<u64, Error> {
+        info!("modify row");
+        let key_columns = self.key_columns();
+        if values.iter().any(|&(i, _)| key_columns.contains(&i)) {
+            return Err(Error::PrimaryKeyNotAllowed);
+        }
+        let mut index = try!(self.open_index());
+        let mut indexes = try!(SecondaryIndexes::open(&self.table));
+        let mut dat = try!(self.open_file_rw());
+        let found = try!(self.find(
+            &mut index,
+            &mut indexes,
+            &mut dat,
+            constraint_column_index,
+            constraint_value,
+            comp
+        ));
+        let updated_rows = found.len() as u64;
+        let mut changes = Vec::with_capacity(found.len());
+        for (entry, old_data) in found {
+            let mut row_data = old_data.clone();
+            for &(column_index, new_value) in values {
+                let range = self.column_range(column_index);
+                let len = range.len();
+                row_data[range].copy_from_slice(&new_value[..len]);
+                types::set_null(self.table.columns(), &mut row_data, column_index, false);
+            }
+            changes.push((entry.addr, old_data, row_data));
+        }
+        try!(indexes.replace(&changes));
+        for (addr, _, row_data) in changes {
+            try!(dat.seek(SeekFrom::Start(addr + RowHeader::size())));
+            try!(dat.write_all(&row_data));
+        }
+        Ok(updated_rows)
+    }
+
+    /// Rewrites the data file without deleted rows, ordered by primary key,
+    /// and rebuilds the index.
+    fn reorganize(&mut self) -> Result<(), Error> {
+        info!("Reorganizing structure.");
+        let mut dat = try!(self.open_file_rw());
+        let mut rows = Vec::new();
+        {
+            let mut index = try!(self.open_index());
+            let entries: Vec<_> = index.iter().collect();
+            for entry in entries {
+                let row_data = try!(self.read_row(&mut dat, entry.addr));
+                rows.push((entry.key, row_data));
+            }
+        }
+        let mut index = try!(self.recreate_index());
+        try!(dat.set_len(0));
+        let mut addr = try!(dat.seek(SeekFrom::Start(0)));
+        for (key, row_data) in rows {
+            try!(dat.write_all(&RowHeader::new(0).to_raw_data()));
+            try!(dat.write_all(&row_data));
+            try!(index.insert_keyaddr(KeyAddr::new(key, addr)));
+            addr += RowHeader::size() + row_data.len() as u64;
+        }
+        // rows were moved, so their addresses changed
+        self.rebuild_indexes()
+    }
+
+    fn reset(&mut self) -> Result<(), Error> {
+        info!("Reset structure.");
+        let file = try!(self.open_file_rw());
+        try!(file.set_len(0));
+        try!(self.recreate_index());
+        try!(SecondaryIndexes::recreate(&self.table));
+        Ok(())
+    }
+
+    fn rebuild_indexes(&mut self) -> Result<(), Error> {
+        info!("Rebuilding secondary indexes.");
+        let mut indexes = try!(SecondaryIndexes::recreate(&self.table));
+        let mut index = try!(self.open_index());
+        let mut dat = try!(self.open_file_rw());
+        let entries: Vec<_> = index.iter().collect();
+        for entry in entries {
+            let row_data = try!(self.read_row(&mut dat, entry.addr));
+            try!(indexes.insert(&row_data, entry.addr));
+        }
+        Ok(())
+    }
+}
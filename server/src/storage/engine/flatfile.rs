@@ -1,9 +1,15 @@
 use super::super::super::parse::ast::CompType;
+use super::super::compress::DataFile;
+use super::super::count;
 use super::super::data::Rows;
+use super::super::encrypt;
+use super::super::index::SecondaryIndexes;
 use super::super::meta::Table;
+use super::super::types::SqlType;
 use super::super::{Engine, Error};
-use std::fs::{File, OpenOptions};
-use std::io::{Cursor, Read, Write};
+use std::fs::OpenOptions;
+use std::io::Cursor;
+use std::ops::Bound;
 //---------------------------------------------------------------
 // FlatFile-Engine
 //---------------------------------------------------------------
@@ -19,23 +25,30 @@ impl<'a> FlatFile<'a> {
         FlatFile { table: table }
     }
 
-    /// Opens table data file with read write access.
-    fn open_file_rw(&self) -> Result<File, Error> {
-        info!("Trying to open file: {}", &self.table.get_table_data_path());
-        let file = try!(OpenOptions::new()
-            .read(true)
-            .write(true)
-            .open(&self.table.get_table_data_path()));
-        info!("opened file {:?}", file);
-        Ok(file)
+    /// return a rows object with the table.dat file as data_src
+    pub fn get_reader(&self) -> Result<Rows<DataFile>, Error> {
+        self.table.open_rows()
     }
 
-    /// return a rows object with the table.dat file as data_src
-    pub fn get_reader(&self) -> Result<Rows<File>, Error> {
-        Ok(Rows::new(
-            try!(self.open_file_rw()),
-            &self.table.meta_data.columns,
-        ))
+    /// Returns the addresses and the data of all rows which fulfill a
+    /// constraint.
+    fn find(
+        &self,
+        reader: &mut Rows<DataFile>,
+        column_index: usize,
+        value: (&[u8], Option<usize>),
+        comp: CompType,
+    ) -> Result<Vec<(u64, Vec<u8>)>, Error> {
+        try!(reader.reset_pos());
+        let mut found = Vec::new();
+        loop {
+            match reader.get_next_row(column_index, value, comp) {
+                Ok(row_data) => found.push((reader.last_row_addr(), row_data)),
+                Err(Error::EndOfFile) => break,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(found)
     }
 }
 
@@ -50,12 +63,29 @@ impl<'a> Engine for FlatFile<'a> {
     /// creates table for use later
     /// returns with error when it has either no permission or full disk
     fn create_table(&mut self) -> Result<(), Error> {
+        if self.table.meta_data.encrypted {
+            if encrypt::cipher().is_none() {
+                return Err(Error::NoEncryptionKey);
+            }
+            // the overflow file of blobs would hold the values in plain
+            if self
+                .table
+                .columns()
+                .iter()
+                .any(|c| c.sql_type == SqlType::Blob)
+            {
+                return Err(Error::NoImplementation);
+            }
+        }
         let mut _file = try!(OpenOptions::new()
             .write(true)
             .create(true)
             .open(&self.table.get_table_data_path()));
 
         info!("created file for data: {:?}", _file);
+        if try!(_file.metadata()).len() == 0 {
+            try!(count::write(&self.table, 0));
+        }
         Ok(())
     }
     /// returns own table
@@ -78,14 +108,62 @@ impl<'a> Engine for FlatFile<'a> {
         comp: CompType,
     ) -> Result<Rows<Cursor<Vec<u8>>>, Error> {
         let mut reader = try!(self.get_reader());
-        reader.lookup(column_index, value, comp)
+        let mut indexes = try!(SecondaryIndexes::open(&self.table));
+        match indexes.lookup(column_index, value, comp) {
+            Some(addrs) => {
+                let mut rows = Rows::new(Cursor::new(Vec::new()), &self.table.meta_data.columns);
+                for addr in addrs {
+                    if let Some(row_data) = try!(reader.row_at(addr)) {
+                        try!(rows.add_row(&row_data));
+                    }
+                }
+                Ok(rows)
+            }
+            None => reader.lookup(column_index, value, comp),
+        }
+    }
+
+    fn scan_range(
+        &self,
+        column_index: usize,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<Rows<Cursor<Vec<u8>>>, Error> {
+        let mut reader = try!(self.get_reader());
+        let mut indexes = try!(SecondaryIndexes::open(&self.table));
+        match indexes.range(column_index, lower, upper) {
+            Some(addrs) => {
+                let mut rows = Rows::new(Cursor::new(Vec::new()), &self.table.meta_data.columns);
+                for addr in addrs {
+                    if let Some(row_data) = try!(reader.row_at(addr)) {
+                        try!(rows.add_row(&row_data));
+                    }
+                }
+                Ok(rows)
+            }
+            None => reader.range(column_index, lower, upper),
+        }
     }
 
     /// Inserts a new row with row_data.
     /// Returns the number of rows inserted.
     fn insert_row(&mut self, row_data: &[u8]) -> Result<u64, Error> {
+        self.insert_rows(&[row_data])
+    }
+
+    /// Checks the keys of all rows before the first one is written, writes
+    /// them at once and then adds them to the indexes.
+    fn insert_rows(&mut self, rows: &[&[u8]]) -> Result<u64, Error> {
         let mut reader = try!(self.get_reader());
-        reader.insert_row(row_data)
+        let mut indexes = try!(SecondaryIndexes::open(&self.table));
+        try!(reader.check_primary_keys(rows));
+        try!(indexes.check_unique_rows(rows));
+        let counted = try!(count::begin(&self.table));
+        let addrs = try!(reader.insert_rows_without_primary(rows));
+        try!(reader.flush());
+        try!(count::end(&self.table, counted, rows.len() as i64));
+        try!(indexes.insert_rows(rows, &addrs));
+        Ok(rows.len() as u64)
     }
 
     /// delete rows which fulfills a constraint
@@ -98,7 +176,23 @@ impl<'a> Engine for FlatFile<'a> {
     ) -> Result<u64, Error> {
         info!("Delete row");
         let mut reader = try!(self.get_reader());
-        reader.delete(column_index, value, comp)
+        let mut indexes = try!(SecondaryIndexes::open(&self.table));
+        if indexes.is_empty() {
+            let counted = try!(count::begin(&self.table));
+            let deleted = try!(reader.delete(column_index, value, comp));
+            try!(reader.flush());
+            try!(count::end(&self.table, counted, -(deleted as i64)));
+            return Ok(deleted);
+        }
+        let found = try!(self.find(&mut reader, column_index, value, comp));
+        let counted = try!(count::begin(&self.table));
+        let deleted = try!(reader.delete(column_index, value, comp));
+        try!(reader.flush());
+        try!(count::end(&self.table, counted, -(deleted as i64)));
+        for (addr, row_data) in found {
+            try!(indexes.remove(&row_data, addr));
+        }
+        Ok(deleted)
     }
 
     fn modify(
@@ -110,27 +204,86 @@ impl<'a> Engine for FlatFile<'a> {
     ) -> Result<u64, Error> {
         info!("modify row");
         let mut reader = try!(self.get_reader());
-        reader.modify(constraint_column_index, constraint_value, comp, values)
+        let mut indexes = try!(SecondaryIndexes::open(&self.table));
+        if indexes.is_empty() {
+            // updated rows may be moved to the end of the file
+            let counted = try!(count::begin(&self.table));
+            let updated =
+                try!(reader.modify(constraint_column_index, constraint_value, comp, values));
+            try!(reader.flush());
+            try!(count::end(&self.table, counted, 0));
+            return Ok(updated);
+        }
+        let found = try!(self.find(&mut reader, constraint_column_index, constraint_value, comp));
+        let mut changes = Vec::with_capacity(found.len());
+        for (addr, old_data) in found {
+            let mut new_data = old_data.clone();
+            for &(column_index, new_value) in values {
+                reader.set_value(&mut new_data, new_value, column_index);
+            }
+            changes.push((addr, old_data, new_data));
+        }
+        // the indexes are updated first, as they refuse duplicate values
+        try!(indexes.replace(&changes));
+        let counted = try!(count::begin(&self.table));
+        match reader
+            .modify(constraint_column_index, constraint_value, comp, values)
+            .and_then(|updated| reader.flush().map(|_| updated))
+        {
+            Ok(updated) => {
+                try!(count::end(&self.table, counted, 0));
+                Ok(updated)
+            }
+            Err(e) => {
+                let undo: Vec<_> = changes
+                    .into_iter()
+                    .map(|(a, old, new)| (a, new, old))
+                    .collect();
+                try!(indexes.replace(&undo));
+                Err(e)
+            }
+        }
     }
 
     fn reorganize(&mut self) -> Result<(), Error> {
         info!("Reorganizing structure.");
-        let new_size: u64;
         {
+            let counted = try!(count::begin(&self.table));
             let mut reader = try!(self.get_reader());
-            new_size = try!(reader.reorganize());
+            let new_size = try!(reader.reorganize());
+            try!(reader.get_mut().set_len(new_size));
+            try!(reader.flush());
+            try!(count::end(&self.table, counted, 0));
         }
-        let file = try!(self.open_file_rw());
-
-        try!(file.set_len(new_size));
-        Ok(())
+        // rows were moved, so their addresses changed
+        self.rebuild_indexes()
     }
     fn reset(&mut self) -> Result<(), Error> {
         info!("Reset structure.");
 
-        let file = try!(self.open_file_rw());
+        let mut reader = try!(self.get_reader());
+        try!(reader.get_mut().set_len(0));
+        try!(reader.flush());
+        try!(count::write(&self.table, 0));
+        try!(SecondaryIndexes::recreate(&self.table));
+        Ok(())
+    }
 
-        try!(file.set_len(0));
+    fn rebuild_indexes(&mut self) -> Result<(), Error> {
+        info!("Rebuilding secondary indexes.");
+        let mut indexes = try!(SecondaryIndexes::recreate(&self.table));
+        let mut reader = try!(self.get_reader());
+        let mut row_data = Vec::<u8>::new();
+        loop {
+            match reader.next_row(&mut row_data) {
+                Ok(_) => {
+                    try!(indexes.insert(&row_data, reader.last_row_addr()));
+                    row_data.clear();
+                }
+                Err(Error::EndOfFile) => break,
+                Err(e) => return Err(e),
+            }
+        }
         Ok(())
     }
 }
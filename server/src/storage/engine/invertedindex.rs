@@ -0,0 +1,477 @@
+use super::super::super::parse::ast::CompType;
+use super::super::data::{RowHeader, Rows};
+use super::super::index::SecondaryIndexes;
+use super::super::meta::Table;
+use super::super::pattern;
+use super::super::types::{self, tokenize};
+use super::super::{Compression, Engine, Error};
+use bincode::{deserialize_from, serialize_into};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{Cursor, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+//---------------------------------------------------------------
+// InvertedIndex-Engine
+//---------------------------------------------------------------
+
+/// Maps every word of the char columns to the addresses of the rows
+/// containing it.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Postings {
+    columns: BTreeMap<usize, BTreeMap<String, BTreeSet<u64>>>,
+}
+
+impl Postings {
+    /// Adds the words of text to the postings of a column.
+    fn add(&mut self, column_index: usize, text: &[u8], addr: u64) {
+        let words = self
+            .columns
+            .entry(column_index)
+            .or_insert_with(BTreeMap::new);
+        for word in tokenize(text) {
+            words.entry(word).or_insert_with(BTreeSet::new).insert(addr);
+        }
+    }
+
+    /// Removes the words of text from the postings of a column.
+    fn remove(&mut self, column_index: usize, text: &[u8], addr: u64) {
+        let words = match self.columns.get_mut(&column_index) {
+            Some(words) => words,
+            None => return,
+        };
+        for word in tokenize(text) {
+            let now_empty = match words.get_mut(&word) {
+                Some(addrs) => {
+                    addrs.remove(&addr);
+                    addrs.is_empty()
+                }
+                None => false,
+            };
+            if now_empty {
+                words.remove(&word);
+            }
+        }
+    }
+
+    /// Returns the addresses of the rows containing every word of term.
+    /// Returns None if term contains no words, every row matches then.
+    fn find(&self, column_index: usize, term: &[u8]) -> Option<BTreeSet<u64>> {
+        let empty = BTreeMap::new();
+        let words = self.columns.get(&column_index).unwrap_or(&empty);
+        let mut result: Option<BTreeSet<u64>> = None;
+        for word in tokenize(term) {
+            let addrs = words.get(&word).cloned().unwrap_or_default();
+            result = Some(match result {
+                Some(found) => found.intersection(&addrs).cloned().collect(),
+                None => addrs,
+            });
+        }
+        result
+    }
+
+    /// Returns the addresses of the rows that may match a `LIKE` pattern,
+    /// those containing the words every match contains. Returns None if
+    /// there are no such words.
+    fn find_like(&self, column_index: usize, like: &str) -> Option<BTreeSet<u64>> {
+        let empty = BTreeMap::new();
+        let words = self.columns.get(&column_index).unwrap_or(&empty);
+        let mut result: Option<BTreeSet<u64>> = None;
+        for (word, whole) in pattern::words(like) {
+            let addrs = if whole {
+                words.get(&word).cloned().unwrap_or_default()
+            } else {
+                // the word is the beginning of a word of the text
+                words
+                    .range(word.clone()..)
+                    .take_while(|&(w, _)| w.starts_with(&word))
+                    .flat_map(|(_, addrs)| addrs.iter().cloned())
+                    .collect()
+            };
+            result = Some(match result {
+                Some(found) => found.intersection(&addrs).cloned().collect(),
+                None => addrs,
+            });
+        }
+        result
+    }
+}
+
+/// Stores the rows like `FlatFile` and keeps a postings index of the words
+/// in all char columns. `MATCH` constraints are answered through the index,
+/// `LIKE` constraints only look at the rows containing the words of the
+/// pattern, everything else scans the data file.
+pub struct InvertedIndex<'a> {
+    table: Table<'a>,
+}
+
+impl<'a> InvertedIndex<'a> {
+    ///
+    pub fn new<'b>(table: Table<'b>) -> InvertedIndex<'b> {
+        info!("new inverted index with table: {:?}", table);
+        InvertedIndex { table: table }
+    }
+
+    /// Removes the postings file of the table.
+    pub fn delete_postings(table: &Table) -> Result<(), Error> {
+        info!(
+            "remove postings file: {:?}",
+            table.get_table_postings_path()
+        );
+        match fs::remove_file(table.get_table_postings_path()) {
+            Err(ref e) if e.kind() == ErrorKind::NotFound => Ok(()),
+            result => Ok(try!(result)),
+        }
+    }
+
+    /// Opens table data file with read write access.
+    fn open_file_rw(&self) -> Result<File, Error> {
+        Ok(try!(OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&self.table.get_table_data_path())))
+    }
+
+    fn load_postings(&self) -> Result<Postings, Error> {
+        let mut file = try!(File::open(&self.table.get_table_postings_path()));
+        Ok(try!(deserialize_from(&mut file)))
+    }
+
+    fn save_postings(&self, postings: &Postings) -> Result<(), Error> {
+        let mut file = try!(File::create(&self.table.get_table_postings_path()));
+        try!(serialize_into(&mut file, postings));
+        Ok(())
+    }
+
+    /// Returns the indices of the text columns.
+    fn text_columns(&self) -> Vec<usize> {
+        let columns = self.table.columns().iter().enumerate();
+        columns
+            .filter(|&(_, c)| c.sql_type.is_text())
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /// Adds (or removes) the words of a row to the postings.
+    fn index_row(&self, postings: &mut Postings, row_data: &[u8], addr: u64, add: bool) {
+        let columns = self.table.columns();
+        for i in self.text_columns() {
+            let text = columns[i].sql_type.content(self.value(row_data, i));
+            if add {
+                postings.add(i, text, addr);
+            } else {
+                postings.remove(i, text, addr);
+            }
+        }
+    }
+
+    /// Returns the position of a column inside of a row.
+    fn column_range(&self, column_index: usize) -> Range<usize> {
+        let columns = self.table.columns();
+        let start: usize = columns[..column_index]
+            .iter()
+            .map(|c| c.get_size() as usize)
+            .sum();
+        start..start + columns[column_index].get_size() as usize
+    }
+
+    /// Returns the value of a column inside of row_data.
+    fn value<'r>(&self, row_data: &'r [u8], column_index: usize) -> &'r [u8] {
+        &row_data[self.column_range(column_index)]
+    }
+
+    fn row_size(&self) -> u64 {
+        types::row_size(self.table.columns()) as u64
+    }
+
+    /// Reads the row at addr of the data file.
+    /// Returns None if the row is marked as deleted.
+    fn read_row(&self, dat: &mut File, addr: u64) -> Result<Option<Vec<u8>>, Error> {
+        try!(dat.seek(SeekFrom::Start(addr)));
+        let mut header = vec![0; RowHeader::size() as usize];
+        try!(dat.read_exact(&mut header));
+        let mut row_data = vec![0; self.row_size() as usize];
+        try!(dat.read_exact(&mut row_data));
+        if RowHeader::new(header[0]).is_deleted() {
+            Ok(None)
+        } else {
+            Ok(Some(row_data))
+        }
+    }
+
+    /// Returns the addresses and the data of all rows which are not deleted.
+    fn scan(&self, dat: &mut File) -> Result<Vec<(u64, Vec<u8>)>, Error> {
+        let len = try!(dat.seek(SeekFrom::End(0)));
+        let mut rows = Vec::new();
+        let mut addr = 0;
+        while addr < len {
+            if let Some(row_data) = try!(self.read_row(dat, addr)) {
+                rows.push((addr, row_data));
+            }
+            addr += RowHeader::size() + self.row_size();
+        }
+        Ok(rows)
+    }
+
+    /// Returns the addresses and the data of all rows which fulfill a
+    /// constraint. `MATCH` and `LIKE` on a char column are answered through
+    /// the postings, other constraints through a secondary index, if there is
+    /// one.
+    fn find(
+        &self,
+        postings: &Postings,
+        indexes: &mut SecondaryIndexes,
+        dat: &mut File,
+        column_index: usize,
+        value: (&[u8], Option<usize>),
+        comp: CompType,
+    ) -> Result<Vec<(u64, Vec<u8>)>, Error> {
+        let columns = self.table.columns();
+        let indexed = comp == CompType::Match
+            && value.1.is_none()
+            && self.text_columns().contains(&column_index);
+        if indexed {
+            if let Some(addrs) = postings.find(column_index, value.0) {
+                let mut found = Vec::new();
+                for addr in addrs {
+                    if let Some(row_data) = try!(self.read_row(dat, addr)) {
+                        found.push((addr, row_data));
+                    }
+                }
+                return Ok(found);
+            }
+        }
+        let text_column = value.1.is_none() && self.text_columns().contains(&column_index);
+        if comp == CompType::Like && text_column {
+            let like = String::from_utf8_lossy(value.0);
+            if let Some(addrs) = postings.find_like(column_index, &like) {
+                // the candidates contain the words, the rest of the pattern
+                // is checked row by row
+                let mut found = Vec::new();
+                for addr in addrs {
+                    if let Some(row_data) = try!(self.read_row(dat, addr)) {
+                        if try!(types::fulfills(
+                            columns,
+                            &row_data,
+                            column_index,
+                            value,
+                            comp
+                        )) {
+                            found.push((addr, row_data));
+                        }
+                    }
+                }
+                return Ok(found);
+            }
+        }
+        if let Some(addrs) = indexes.lookup(column_index, value, comp) {
+            let mut found = Vec::new();
+            for addr in addrs {
+                if let Some(row_data) = try!(self.read_row(dat, addr)) {
+                    found.push((addr, row_data));
+                }
+            }
+            return Ok(found);
+        }
+        let mut found = Vec::new();
+        for (addr, row_data) in try!(self.scan(dat)) {
+            let matches = try!(types::fulfills(
+                columns,
+                &row_data,
+                column_index,
+                value,
+                comp
+            ));
+            if matches {
+                found.push((addr, row_data));
+            }
+        }
+        Ok(found)
+    }
+
+    /// Returns a rows object containing the given rows.
+    fn to_rows<I>(&self, rows: I) -> Result<Rows<Cursor<Vec<u8>>>, Error>
+    where
+        I: IntoIterator<Item = Vec<u8>>,
+    {
+        let mut result = Rows::new(Cursor::new(Vec::new()), self.table.columns());
+        for row_data in rows {
+            try!(result.add_row(&row_data));
+        }
+        Ok(result)
+    }
+}
+
+impl<'a> Drop for InvertedIndex<'a> {
+    /// drops the InvertedIndex
+    fn drop(&mut self) {
+        info!("drop engine inverted index");
+    }
+}
+
+impl<'a> Engine for InvertedIndex<'a> {
+    /// creates the data file and empty postings
+    fn create_table(&mut self) -> Result<(), Error> {
+        // rows are read from the data file one by one
+        if self.table.meta_data.compression != Compression::None || self.table.meta_data.encrypted {
+            return Err(Error::NoImplementation);
+        }
+        let _file = try!(OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&self.table.get_table_data_path()));
+        try!(self.save_postings(&Postings::default()));
+        info!("created files for data and postings: {:?}", _file);
+        Ok(())
+    }
+
+    /// returns own table
+    fn table(&self) -> &Table {
+        &self.table
+    }
+
+    /// returns all rows which are not deleted
+    fn full_scan(&self) -> Result<Rows<Cursor<Vec<u8>>>, Error> {
+        info!("full scan");
+        let mut dat = try!(self.open_file_rw());
+        let rows = try!(self.scan(&mut dat));
+        self.to_rows(rows.into_iter().map(|(_, row_data)| row_data))
+    }
+
+    /// returns an new Rows object which fulfills a constraint
+    fn lookup(
+        &self,
+        column_index: usize,
+        value: (&[u8], Option<usize>),
+        comp: CompType,
+    ) -> Result<Rows<Cursor<Vec<u8>>>, Error> {
+        let postings = try!(self.load_postings());
+        let mut indexes = try!(SecondaryIndexes::open(&self.table));
+        let mut dat = try!(self.open_file_rw());
+        let found = try!(self.find(&postings, &mut indexes, &mut dat, column_index, value, comp));
+        self.to_rows(found.into_iter().map(|(_, row_data)| row_data))
+    }
+
+    /// Inserts a new row with row_data and adds its words to the postings.
+    /// Returns the number of rows inserted.
+    fn insert_row(&mut self, row_data: &[u8]) -> Result<u64, Error> {
+        let mut postings = try!(self.load_postings());
+        let mut indexes = try!(SecondaryIndexes::open(&self.table));
+        try!(indexes.check_unique(row_data));
+        let mut dat = try!(self.open_file_rw());
+        let addr = try!(dat.seek(SeekFrom::End(0)));
+        let inserted = {
+            let mut reader = Rows::new(dat, self.table.columns());
+            try!(reader.insert_row(row_data))
+        };
+        self.index_row(&mut postings, row_data, addr, true);
+        try!(self.save_postings(&postings));
+        try!(indexes.insert(row_data, addr));
+        Ok(inserted)
+    }
+
+    /// delete rows which fulfills a constraint
+    /// returns amount of deleted rows
+    fn delete(
+        &self,
+        column_index: usize,
+        value: (&[u8], Option<usize>),
+        comp: CompType,
+    ) -> Result<u64, Error> {
+        info!("Delete row");
+        let mut postings = try!(self.load_postings());
+        let mut indexes = try!(SecondaryIndexes::open(&self.table));
+        let mut dat = try!(self.open_file_rw());
+        let found = try!(self.find(&postings, &mut indexes, &mut dat, column_index, value, comp));
+        for &(addr, ref row_data) in &found {
+            try!(dat.seek(SeekFrom::Start(addr)));
+            try!(dat.write_all(&RowHeader::new(1).to_raw_data()));
+            self.index_row(&mut postings, row_data, addr, false);
+            try!(indexes.remove(row_data, addr));
+        }
+        try!(self.save_postings(&postings));
+        Ok(found.len() as u64)
+    }
+
+    /// Updates the rows which fulfill a constraint in place and reindexes
+    /// their words.
+    fn modify(
+        &mut self,
+        constraint_column_index: usize,
+        constraint_value: (&[u8], Option<usize>),
+        comp: CompType,
+        values: &[(usize, &[u8])],
+    ) -> Result<u64, Error> {
+        info!("modify row");
+        let mut postings = try!(self.load_postings());
+        let mut indexes = try!(SecondaryIndexes::open(&self.table));
+        let mut dat = try!(self.open_file_rw());
+        let found = try!(self.find(
+            &postings,
+            &mut indexes,
+            &mut dat,
+            constraint_column_index,
+            constraint_value,
+            comp
+        ));
+        let updated_rows = found.len() as u64;
+        let mut changes = Vec::with_capacity(found.len());
+        for (addr, old_data) in found {
+            let mut row_data = old_data.clone();
+            for &(column_index, new_value) in values {
+                let range = self.column_range(column_index);
+                let len = range.len();
+                row_data[range].copy_from_slice(&new_value[..len]);
+                types::set_null(self.table.columns(), &mut row_data, column_index, false);
+            }
+            changes.push((addr, old_data, row_data));
+        }
+        try!(indexes.replace(&changes));
+        for (addr, old_data, row_data) in changes {
+            self.index_row(&mut postings, &old_data, addr, false);
+            try!(dat.seek(SeekFrom::Start(addr + RowHeader::size())));
+            try!(dat.write_all(&row_data));
+            self.index_row(&mut postings, &row_data, addr, true);
+        }
+        try!(self.save_postings(&postings));
+        Ok(updated_rows)
+    }
+
+    /// Removes deleted rows from the data file, which moves rows, so the
+    /// postings are rebuilt.
+    fn reorganize(&mut self) -> Result<(), Error> {
+        info!("Reorganizing structure.");
+        let new_size = {
+            let mut reader = Rows::new(try!(self.open_file_rw()), self.table.columns());
+            try!(reader.reorganize())
+        };
+        let mut dat = try!(self.open_file_rw());
+        try!(dat.set_len(new_size));
+
+        let mut postings = Postings::default();
+        for (addr, row_data) in try!(self.scan(&mut dat)) {
+            self.index_row(&mut postings, &row_data, addr, true);
+        }
+        try!(self.save_postings(&postings));
+        self.rebuild_indexes()
+    }
+
+    fn reset(&mut self) -> Result<(), Error> {
+        info!("Reset structure.");
+        let file = try!(self.open_file_rw());
+        try!(file.set_len(0));
+        try!(SecondaryIndexes::recreate(&self.table));
+        self.save_postings(&Postings::default())
+    }
+
+    fn rebuild_indexes(&mut self) -> Result<(), Error> {
+        info!("Rebuilding secondary indexes.");
+        let mut indexes = try!(SecondaryIndexes::recreate(&self.table));
+        let mut dat = try!(self.open_file_rw());
+        for (addr, row_data) in try!(self.scan(&mut dat)) {
+            try!(indexes.insert(&row_data, addr));
+        }
+        Ok(())
+    }
+}
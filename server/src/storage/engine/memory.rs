@@ -0,0 +1,123 @@
+use super::super::super::parse::ast::CompType;
+use super::super::data::Rows;
+use super::super::memory;
+use super::super::meta::Table;
+use super::super::types::SqlType;
+use super::super::{Compression, Engine, Error};
+use std::io::{self, Cursor};
+//---------------------------------------------------------------
+// Memory-Engine
+//---------------------------------------------------------------
+
+/// Engine of temporary tables, whose rows are kept in memory until the
+/// connection owning the table ends, see `memory`. Primary keys are kept,
+/// but there are no secondary indexes.
+pub struct Memory<'a> {
+    table: Table<'a>,
+}
+
+impl<'a> Memory<'a> {
+    pub fn new<'b>(table: Table<'b>) -> Memory<'b> {
+        info!("new memory engine with table: {:?}", table);
+        Memory { table: table }
+    }
+
+    /// Runs `f` on the rows of the table.
+    fn with_rows<T, F>(&self, f: F) -> Result<T, Error>
+    where
+        F: FnOnce(&mut Rows<Cursor<Vec<u8>>>) -> Result<T, Error>,
+    {
+        match self.table.connection() {
+            Some(connection) => memory::with_rows(connection, &self.table, f),
+            None => Err(Error::NoImplementation),
+        }
+    }
+}
+
+impl<'a> Engine for Memory<'a> {
+    /// Empties the rows of the table. Fails for tables that are not
+    /// temporary and for features that would need files: secondary
+    /// indexes, foreign keys, compression, encryption and BLOB columns.
+    fn create_table(&mut self) -> Result<(), Error> {
+        let meta_data = &self.table.meta_data;
+        if self.table.connection().is_none()
+            || !meta_data.indexes.is_empty()
+            || !meta_data.foreign_keys.is_empty()
+            || meta_data.compression != Compression::None
+            || meta_data.encrypted
+            || meta_data
+                .columns
+                .iter()
+                .any(|c| c.sql_type == SqlType::Blob)
+        {
+            return Err(Error::NoImplementation);
+        }
+        self.with_rows(|rows| {
+            rows.get_mut().get_mut().clear();
+            Ok(())
+        })
+    }
+
+    fn table(&self) -> &Table {
+        &self.table
+    }
+
+    fn full_scan(&self) -> Result<Rows<Cursor<Vec<u8>>>, Error> {
+        self.with_rows(|rows| rows.full_scan())
+    }
+
+    fn lookup(
+        &self,
+        column_index: usize,
+        value: (&[u8], Option<usize>),
+        comp: CompType,
+    ) -> Result<Rows<Cursor<Vec<u8>>>, Error> {
+        self.with_rows(|rows| rows.lookup(column_index, value, comp))
+    }
+
+    fn insert_row(&mut self, row_data: &[u8]) -> Result<u64, Error> {
+        self.with_rows(|rows| rows.insert_row(row_data))
+    }
+
+    fn delete(
+        &self,
+        column_index: usize,
+        value: (&[u8], Option<usize>),
+        comp: CompType,
+    ) -> Result<u64, Error> {
+        self.with_rows(|rows| rows.delete(column_index, value, comp))
+    }
+
+    fn modify(
+        &mut self,
+        constraint_column_index: usize,
+        constraint_value: (&[u8], Option<usize>),
+        comp: CompType,
+        values: &[(usize, &[u8])],
+    ) -> Result<u64, Error> {
+        self.with_rows(|rows| rows.modify(constraint_column_index, constraint_value, comp, values))
+    }
+
+    fn reorganize(&mut self) -> Result<(), Error> {
+        self.with_rows(|rows| {
+            let new_size = try!(rows.reorganize());
+            rows.get_mut().get_mut().truncate(new_size as usize);
+            Ok(())
+        })
+    }
+
+    fn reset(&mut self) -> Result<(), Error> {
+        self.with_rows(|rows| {
+            rows.get_mut().get_mut().clear();
+            Ok(())
+        })
+    }
+
+    fn rebuild_indexes(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn write_blob(&self, _: &mut dyn io::Read) -> Result<Vec<u8>, Error> {
+        Err(Error::NoImplementation)
+    }
+}
@@ -1,3 +1,9 @@
+mod bstar;
 mod flatfile;
+mod invertedindex;
+mod memory;
 
+pub use self::bstar::BStar;
 pub use self::flatfile::FlatFile;
+pub use self::invertedindex::InvertedIndex;
+pub use self::memory::Memory;
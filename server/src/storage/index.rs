@@ -0,0 +1,406 @@
+//! Secondary indexes
+//!
+//! Every index created by `CREATE INDEX` is a B* tree mapping the values of
+//! one column to the addresses of the rows in the data file of the table.
+//! The engines keep the indexes in sync with the data and consult them for
+//! equality and range constraints on an indexed column.
+//!
+//! A `UNIQUE` constraint is enforced by a unique index, whose keys hold the
+//! values of all its columns one after another. The engines check the
+//! unique indexes before they write a row.
+
+use super::super::parse::ast::CompType;
+use super::bstar::{Bstar, IterDirection, IterOption, KeyAddr, KnownSize};
+use super::meta::{Index, Table};
+use super::types::{self, Column, SqlType};
+use super::Error;
+use byteorder::{BigEndian, ByteOrder};
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::ops::Bound;
+
+/// Space reserved for the column value inside of a key
+const VALUE_SIZE: usize = 256;
+/// Size of a key: the padded value followed by the row address
+const KEY_SIZE: usize = VALUE_SIZE + 8;
+/// Order of the index trees, a node holds up to `2 * INDEX_ORDER` keys
+const INDEX_ORDER: u64 = 7;
+
+/// Key of a secondary index.
+///
+/// Values may occur more than once, so the address of the row is appended
+/// to keep the keys unique. Comparing two keys byte by byte orders them by
+/// value and then by address.
+#[derive(Clone, Debug, PartialEq, PartialOrd)]
+pub struct ValueKey(Vec<u8>);
+
+impl ValueKey {
+    fn new(mut key: Vec<u8>, addr: u64) -> ValueKey {
+        key.resize(VALUE_SIZE, 0);
+        key.resize(KEY_SIZE, 0);
+        BigEndian::write_u64(&mut key[VALUE_SIZE..], addr);
+        ValueKey(key)
+    }
+
+    /// Returns the padded value without the row address.
+    fn value(&self) -> &[u8] {
+        &self.0[..VALUE_SIZE]
+    }
+}
+
+impl KnownSize for ValueKey {
+    fn size() -> u64 {
+        KEY_SIZE as u64
+    }
+
+    fn read(file: &mut File, addr: Option<u64>) -> io::Result<ValueKey> {
+        if let Some(addr) = addr {
+            try!(file.seek(SeekFrom::Start(addr)));
+        }
+        let mut key = vec![0; KEY_SIZE];
+        try!(file.read_exact(&mut key));
+        Ok(ValueKey(key))
+    }
+
+    fn write(&self, file: &mut File, addr: Option<u64>) -> io::Result<()> {
+        if let Some(addr) = addr {
+            try!(file.seek(SeekFrom::Start(addr)));
+        }
+        file.write_all(&self.0)
+    }
+
+    fn write_default(file: &mut File, addr: Option<u64>) -> io::Result<()> {
+        if let Some(addr) = addr {
+            try!(file.seek(SeekFrom::Start(addr)));
+        }
+        file.write_all(&[0; KEY_SIZE])
+    }
+}
+
+/// A column of an index
+struct Part {
+    column_index: usize,
+    sql_type: SqlType,
+    offset: usize,
+    size: usize,
+}
+
+/// A single opened index
+struct Opened {
+    name: String,
+    unique: bool,
+    parts: Vec<Part>,
+    tree: Bstar<ValueKey>,
+}
+
+impl Opened {
+    /// Returns the key of a row, or None if a value of its columns is null.
+    /// Null values are not indexed, as no comparison selects them and they
+    /// never violate a unique constraint.
+    fn key(&self, columns: &[Column], row_data: &[u8], addr: u64) -> Option<ValueKey> {
+        let mut key = Vec::new();
+        for part in &self.parts {
+            if types::is_null(columns, row_data, part.column_index) {
+                return None;
+            }
+            let value = &row_data[part.offset..part.offset + part.size];
+            key.extend_from_slice(&part.sql_type.sort_key(value));
+        }
+        Some(ValueKey::new(key, addr))
+    }
+}
+
+/// Returns true if the values of the columns fit into a key together, so
+/// that a unique index on them tells all of them apart.
+pub fn fits_key(columns: &[&Column]) -> bool {
+    let size: usize = columns
+        .iter()
+        .map(|c| c.sql_type.sort_key(&vec![0; c.get_size() as usize]).len())
+        .sum();
+    size <= VALUE_SIZE
+}
+
+/// All secondary indexes of a table
+pub struct SecondaryIndexes {
+    columns: Vec<Column>,
+    opened: Vec<Opened>,
+}
+
+impl SecondaryIndexes {
+    /// Opens all indexes of a table.
+    pub fn open(table: &Table) -> Result<SecondaryIndexes, Error> {
+        let mut opened = Vec::new();
+        for index in table.indexes() {
+            let path = table.get_secondary_index_path(&index.name);
+            let tree = try!(Bstar::load(&path));
+            opened.push(try!(Self::describe(table, index, tree)));
+        }
+        Ok(SecondaryIndexes {
+            columns: table.columns().to_vec(),
+            opened: opened,
+        })
+    }
+
+    /// Replaces all indexes of a table by empty ones.
+    pub fn recreate(table: &Table) -> Result<SecondaryIndexes, Error> {
+        let mut opened = Vec::new();
+        for index in table.indexes() {
+            try!(Self::delete(table, &index.name));
+            let path = table.get_secondary_index_path(&index.name);
+            let tree = try!(Bstar::create(&path, &table.name, INDEX_ORDER));
+            opened.push(try!(Self::describe(table, index, tree)));
+        }
+        Ok(SecondaryIndexes {
+            columns: table.columns().to_vec(),
+            opened: opened,
+        })
+    }
+
+    /// Removes the files of an index, if there are any.
+    pub fn delete(table: &Table, name: &str) -> Result<(), Error> {
+        let path = table.get_secondary_index_path(name);
+        info!("remove index files: {:?}", path);
+        match Bstar::<ValueKey>::delete(&path) {
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => Ok(try!(result)),
+        }
+    }
+
+    fn describe(table: &Table, index: &Index, tree: Bstar<ValueKey>) -> Result<Opened, Error> {
+        let columns = table.columns();
+        let mut parts = Vec::new();
+        for name in &index.columns {
+            let column_index = match columns.iter().position(|c| &c.name == name) {
+                Some(i) => i,
+                None => return Err(Error::InvalidColumn),
+            };
+            let offset: u32 = columns[..column_index].iter().map(|c| c.get_size()).sum();
+            parts.push(Part {
+                column_index: column_index,
+                sql_type: columns[column_index].sql_type,
+                offset: offset as usize,
+                size: columns[column_index].get_size() as usize,
+            });
+        }
+        Ok(Opened {
+            name: index.name.clone(),
+            unique: index.unique,
+            parts: parts,
+            tree: tree,
+        })
+    }
+
+    /// Returns true if the table has no indexes.
+    pub fn is_empty(&self) -> bool {
+        self.opened.is_empty()
+    }
+
+    /// Adds the row at addr to all indexes.
+    pub fn insert(&mut self, row_data: &[u8], addr: u64) -> Result<(), Error> {
+        for index in &mut self.opened {
+            if let Some(key) = index.key(&self.columns, row_data, addr) {
+                try!(index.tree.insert_keyaddr(KeyAddr::new(key, addr)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds rows written together, at the given addresses, to all indexes
+    /// in one pass over each index.
+    pub fn insert_rows(&mut self, rows: &[&[u8]], addrs: &[u64]) -> Result<(), Error> {
+        for index in &mut self.opened {
+            for (row_data, &addr) in rows.iter().zip(addrs) {
+                if let Some(key) = index.key(&self.columns, row_data, addr) {
+                    try!(index.tree.insert_keyaddr(KeyAddr::new(key, addr)));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes the row at addr from all indexes.
+    pub fn remove(&mut self, row_data: &[u8], addr: u64) -> Result<(), Error> {
+        for index in &mut self.opened {
+            if let Some(key) = index.key(&self.columns, row_data, addr) {
+                try!(index.tree.delete_keyaddr(key));
+            }
+        }
+        Ok(())
+    }
+
+    /// Fails with `Error::UniqueKeyValueExists` if a unique index already
+    /// holds the values of a row that is about to be written.
+    pub fn check_unique(&mut self, row_data: &[u8]) -> Result<(), Error> {
+        for index in self.opened.iter_mut().filter(|i| i.unique) {
+            let first = match index.key(&self.columns, row_data, 0) {
+                Some(key) => key,
+                None => continue,
+            };
+            let from = Some(IterOption::Including(first.clone()));
+            let taken = index
+                .tree
+                .iter_options(IterDirection::Forward, from)
+                .next()
+                .map_or(false, |e| e.key.value() == first.value());
+            if taken {
+                return Err(Error::UniqueKeyValueExists(index.name.clone()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Like `check_unique` for rows that are about to be written together,
+    /// which must not hold the same values either.
+    pub fn check_unique_rows(&mut self, rows: &[&[u8]]) -> Result<(), Error> {
+        for row_data in rows {
+            try!(self.check_unique(row_data));
+        }
+        for index in self.opened.iter().filter(|i| i.unique) {
+            let mut keys: Vec<Vec<u8>> = rows
+                .iter()
+                .filter_map(|r| index.key(&self.columns, r, 0))
+                .map(|k| k.value().to_vec())
+                .collect();
+            keys.sort();
+            if keys.windows(2).any(|w| w[0] == w[1]) {
+                return Err(Error::UniqueKeyValueExists(index.name.clone()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Replaces the entries of updated rows, given as address, old and new
+    /// data. If the new data violates a unique index, the indexes are left
+    /// as they were and `Error::UniqueKeyValueExists` is returned, so that
+    /// the caller writes no row.
+    pub fn replace(&mut self, rows: &[(u64, Vec<u8>, Vec<u8>)]) -> Result<(), Error> {
+        for &(addr, ref old_data, _) in rows {
+            try!(self.remove(old_data, addr));
+        }
+        for (i, &(addr, _, ref new_data)) in rows.iter().enumerate() {
+            if let Err(e) = self.check_unique(new_data) {
+                for &(addr, _, ref new_data) in &rows[..i] {
+                    try!(self.remove(new_data, addr));
+                }
+                for &(addr, ref old_data, _) in rows {
+                    try!(self.insert(old_data, addr));
+                }
+                return Err(e);
+            }
+            try!(self.insert(new_data, addr));
+        }
+        Ok(())
+    }
+
+    /// Returns the addresses of all rows whose column fulfills a
+    /// constraint, ordered by value. Returns None if there is no index on
+    /// the column or the constraint cannot be answered by it.
+    pub fn lookup(
+        &mut self,
+        column_index: usize,
+        value: (&[u8], Option<usize>),
+        comp: CompType,
+    ) -> Option<Vec<u64>> {
+        if value.1.is_some() {
+            return None;
+        }
+        let index = match self
+            .opened
+            .iter_mut()
+            .find(|i| i.parts.len() == 1 && i.parts[0].column_index == column_index)
+        {
+            Some(index) => index,
+            None => return None,
+        };
+        let value = index.parts[0].sql_type.sort_key(value.0);
+        // longer values are cut off in the keys, they would not be told apart
+        if value.len() > VALUE_SIZE {
+            return None;
+        }
+        let first = ValueKey::new(value.clone(), 0);
+        let last = ValueKey::new(value, u64::max_value());
+        let tree = &mut index.tree;
+        let entries: Vec<_> = match comp {
+            CompType::Equ => {
+                let from = Some(IterOption::Including(first.clone()));
+                tree.iter_options(IterDirection::Forward, from)
+                    .take_while(|e| e.key.value() == first.value())
+                    .collect()
+            }
+            CompType::GThan => {
+                let from = Some(IterOption::Excluding(last));
+                tree.iter_options(IterDirection::Forward, from).collect()
+            }
+            CompType::GEThan => {
+                let from = Some(IterOption::Including(first));
+                tree.iter_options(IterDirection::Forward, from).collect()
+            }
+            CompType::SThan => tree.iter().take_while(|e| e.key < first).collect(),
+            CompType::SEThan => tree.iter().take_while(|e| e.key <= last).collect(),
+            _ => return None,
+        };
+        Some(entries.into_iter().map(|e| e.addr).collect())
+    }
+
+    /// Returns the addresses of all rows whose column lies within the
+    /// bounds, ordered by value. Returns None if there is no index on the
+    /// column or a bound is too long for its keys.
+    pub fn range(
+        &mut self,
+        column_index: usize,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Option<Vec<u64>> {
+        let index = match self
+            .opened
+            .iter_mut()
+            .find(|i| i.parts.len() == 1 && i.parts[0].column_index == column_index)
+        {
+            Some(index) => index,
+            None => return None,
+        };
+        let sql_type = index.parts[0].sql_type;
+        // the first and the last key a value can have
+        let keys = |value: &[u8]| {
+            let value = sql_type.sort_key(value);
+            if value.len() > VALUE_SIZE {
+                return None;
+            }
+            Some((
+                ValueKey::new(value.clone(), 0),
+                ValueKey::new(value, u64::max_value()),
+            ))
+        };
+        let from = match lower {
+            Bound::Included(value) => match keys(value) {
+                Some((first, _)) => Some(IterOption::Including(first)),
+                None => return None,
+            },
+            Bound::Excluded(value) => match keys(value) {
+                Some((_, last)) => Some(IterOption::Excluding(last)),
+                None => return None,
+            },
+            Bound::Unbounded => None,
+        };
+        let to = match upper {
+            Bound::Included(value) => match keys(value) {
+                Some((_, last)) => Bound::Included(last),
+                None => return None,
+            },
+            Bound::Excluded(value) => match keys(value) {
+                Some((first, _)) => Bound::Excluded(first),
+                None => return None,
+            },
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        let entries = index
+            .tree
+            .iter_options(IterDirection::Forward, from)
+            .take_while(|e| match to {
+                Bound::Included(ref last) => e.key <= *last,
+                Bound::Excluded(ref first) => e.key < *first,
+                Bound::Unbounded => true,
+            });
+        Some(entries.map(|e| e.addr).collect())
+    }
+}
@@ -0,0 +1,391 @@
+//! Table locks of transactions
+//!
+//! Every statement locks the tables it uses for its transaction: reading or
+//! writing rows takes a shared lock, changing the schema of a table an
+//! exclusive one. Locks are held until the transaction ends. A transaction
+//! holding a shared lock may upgrade it to an exclusive one, it waits for
+//! the other holders to end then.
+//!
+//! A request that cannot be granted waits in the queue of the table, in the
+//! order the requests arrived, except for upgrades, which go first. Before a
+//! request waits, the graph of which transaction waits for which one is
+//! searched for a cycle through it. The youngest transaction of a cycle is
+//! the victim: its request fails with `Error::Deadlock`, so that it can be
+//! rolled back and its locks released (see `transaction::Statement::lock`).
+//!
+//! Shared locks let the transactions write the same table, each row is
+//! locked by its writer (see `transaction`). Writing the files of a table is
+//! kept from interleaving by a latch of the table instead, held only while
+//! a statement reads and records the rows it changes and writes them, see
+//! `latch`.
+
+use super::Error;
+
+use std::sync::{Condvar, Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::Duration;
+
+/// How often a waiting request checks whether it should stop waiting
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+static LOCKS: Mutex<Locks> = Mutex::new(Locks {
+    tables: Vec::new(),
+    victims: Vec::new(),
+});
+
+/// Wakes the waiting requests when locks are released
+static RELEASED: Condvar = Condvar::new();
+
+/// Tables being written, see `latch`
+static LATCHES: Mutex<Vec<(String, String)>> = Mutex::new(Vec::new());
+
+/// Wakes the waiting writers when a table latch is released
+static UNLATCHED: Condvar = Condvar::new();
+
+/// Held shared by every table latch, exclusively by `latch_all`
+static ALL_TABLES: RwLock<()> = RwLock::new(());
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+    Shared,
+    Exclusive,
+}
+
+impl Mode {
+    fn compatible(self, other: Mode) -> bool {
+        self == Mode::Shared && other == Mode::Shared
+    }
+
+    /// Returns true if holding the lock in this mode includes `other`.
+    fn covers(self, other: Mode) -> bool {
+        self == Mode::Exclusive || other == Mode::Shared
+    }
+}
+
+struct TableLock {
+    database: String,
+    table: String,
+    /// Transactions holding the lock
+    holders: Vec<(u64, Mode)>,
+    /// Requests waiting for the lock, in the order they are granted
+    waiting: Vec<(u64, Mode)>,
+}
+
+impl TableLock {
+    /// Returns the transactions a request of `xid` waits for: the holders
+    /// it conflicts with and the conflicting requests ahead of it.
+    fn blockers(&self, xid: u64, mode: Mode) -> Vec<u64> {
+        let ahead = self.waiting.iter().take_while(|&&(x, _)| x != xid);
+        self.holders
+            .iter()
+            .chain(ahead)
+            .filter(|&&(x, m)| x != xid && !m.compatible(mode))
+            .map(|&(x, _)| x)
+            .collect()
+    }
+}
+
+struct Locks {
+    tables: Vec<TableLock>,
+    /// Transactions chosen to resolve a deadlock, whose requests have not
+    /// failed yet
+    victims: Vec<u64>,
+}
+
+impl Locks {
+    fn position(&self, database: &str, table: &str) -> Option<usize> {
+        self.tables
+            .iter()
+            .position(|l| l.database == database && l.table == table)
+    }
+
+    /// Returns the transactions waiting transaction `xid` waits for.
+    fn waits_for(&self, xid: u64) -> Vec<u64> {
+        let mut blockers = Vec::new();
+        for lock in &self.tables {
+            if let Some(&(_, mode)) = lock.waiting.iter().find(|&&(x, _)| x == xid) {
+                blockers.extend(lock.blockers(xid, mode));
+            }
+        }
+        blockers
+    }
+
+    /// Returns the transactions of a cycle in the wait-for graph through
+    /// `xid`, if there is one.
+    fn cycle(&self, xid: u64) -> Option<Vec<u64>> {
+        let mut path = vec![xid];
+        let mut visited = Vec::new();
+        if self.search(xid, &mut path, &mut visited) {
+            Some(path)
+        } else {
+            None
+        }
+    }
+
+    /// Searches a path from the last transaction of `path` back to its first
+    /// one, depth first.
+    fn search(&self, start: u64, path: &mut Vec<u64>, visited: &mut Vec<u64>) -> bool {
+        let last = *path.last().unwrap();
+        for next in self.waits_for(last) {
+            if next == start {
+                return true;
+            }
+            if visited.contains(&next) {
+                continue;
+            }
+            visited.push(next);
+            path.push(next);
+            if self.search(start, path, visited) {
+                return true;
+            }
+            path.pop();
+        }
+        false
+    }
+}
+
+/// Locks a table for a transaction, waits until the lock is granted.
+/// Returns false if `cancelled` returned true before.
+///
+/// # Failures
+/// Fails with `Error::Deadlock` if the transaction was chosen as the victim
+/// of a deadlock. It still holds its other locks then.
+pub fn acquire<F>(
+    xid: u64,
+    database: &str,
+    table: &str,
+    mode: Mode,
+    cancelled: F,
+) -> Result<bool, Error>
+where
+    F: Fn() -> bool,
+{
+    let mut locks = LOCKS.lock().unwrap();
+    let pos = match locks.position(database, table) {
+        Some(pos) => pos,
+        None => {
+            locks.tables.push(TableLock {
+                database: database.to_string(),
+                table: table.to_string(),
+                holders: Vec::new(),
+                waiting: Vec::new(),
+            });
+            locks.tables.len() - 1
+        }
+    };
+    {
+        let lock = &mut locks.tables[pos];
+        let held = lock.holders.iter().position(|&(x, _)| x == xid);
+        if let Some(i) = held {
+            if lock.holders[i].1.covers(mode) {
+                return Ok(true);
+            }
+        }
+        if lock.blockers(xid, mode).is_empty() {
+            match held {
+                Some(i) => lock.holders[i].1 = mode,
+                None => lock.holders.push((xid, mode)),
+            }
+            return Ok(true);
+        }
+        if held.is_some() {
+            lock.waiting.insert(0, (xid, mode));
+        } else {
+            lock.waiting.push((xid, mode));
+        }
+    }
+
+    if let Some(cycle) = locks.cycle(xid) {
+        let victim = *cycle.iter().max().unwrap();
+        info!(
+            "deadlock of transactions {:?}, rolling back {}",
+            cycle, victim
+        );
+        locks.victims.push(victim);
+        RELEASED.notify_all();
+    }
+
+    loop {
+        if let Some(v) = locks.victims.iter().position(|&v| v == xid) {
+            locks.victims.remove(v);
+            stop_waiting(&mut locks, xid, database, table);
+            return Err(Error::Deadlock);
+        }
+        if cancelled() {
+            stop_waiting(&mut locks, xid, database, table);
+            return Ok(false);
+        }
+        let pos = locks.position(database, table).unwrap();
+        let lock = &mut locks.tables[pos];
+        if lock.blockers(xid, mode).is_empty() {
+            lock.waiting.retain(|&(x, _)| x != xid);
+            match lock.holders.iter().position(|&(x, _)| x == xid) {
+                Some(i) => lock.holders[i].1 = mode,
+                None => lock.holders.push((xid, mode)),
+            }
+            // requests behind it may be granted now, too
+            RELEASED.notify_all();
+            return Ok(true);
+        }
+        locks = RELEASED.wait_timeout(locks, POLL_INTERVAL).unwrap().0;
+    }
+}
+
+/// Runs `f` while no transaction holds or waits for a lock on a table of
+/// the database, no lock is granted meanwhile.
+///
+/// # Failures
+/// Fails with `Error::TransactionActive` if a table of the database is
+/// locked.
+pub fn without_locks<T, F>(database: &str, f: F) -> Result<T, Error>
+where
+    F: FnOnce() -> Result<T, Error>,
+{
+    let locks = LOCKS.lock().unwrap();
+    if locks.tables.iter().any(|l| l.database == database) {
+        return Err(Error::TransactionActive);
+    }
+    f()
+}
+
+/// Releases all locks of a transaction and drops its waiting requests.
+pub fn release(xid: u64) {
+    let mut locks = LOCKS.lock().unwrap();
+    for lock in &mut locks.tables {
+        lock.holders.retain(|&(x, _)| x != xid);
+        lock.waiting.retain(|&(x, _)| x != xid);
+    }
+    locks
+        .tables
+        .retain(|l| !l.holders.is_empty() || !l.waiting.is_empty());
+    locks.victims.retain(|&v| v != xid);
+    RELEASED.notify_all();
+}
+
+/// Removes a waiting request, the requests behind it may be granted now.
+fn stop_waiting(locks: &mut Locks, xid: u64, database: &str, table: &str) {
+    if let Some(pos) = locks.position(database, table) {
+        locks.tables[pos].waiting.retain(|&(x, _)| x != xid);
+        if locks.tables[pos].holders.is_empty() && locks.tables[pos].waiting.is_empty() {
+            locks.tables.remove(pos);
+        }
+    }
+    RELEASED.notify_all();
+}
+
+/// Keeps the files of a table from being written by others until dropped
+pub struct Latch {
+    database: String,
+    table: String,
+    _all: RwLockReadGuard<'static, ()>,
+}
+
+impl Drop for Latch {
+    fn drop(&mut self) {
+        let mut latches = LATCHES.lock().unwrap();
+        latches.retain(|&(ref d, ref t)| *d != self.database || *t != self.table);
+        UNLATCHED.notify_all();
+    }
+}
+
+/// Waits until no one else writes the files of the table, then keeps others
+/// from writing them until the latch is dropped. Writes to other tables go
+/// on meanwhile.
+pub fn latch(database: &str, table: &str) -> Latch {
+    let all = ALL_TABLES.read().unwrap();
+    let mut latches = LATCHES.lock().unwrap();
+    while latches
+        .iter()
+        .any(|&(ref d, ref t)| d == database && t == table)
+    {
+        latches = UNLATCHED.wait(latches).unwrap();
+    }
+    latches.push((database.to_string(), table.to_string()));
+    Latch {
+        database: database.to_string(),
+        table: table.to_string(),
+        _all: all,
+    }
+}
+
+/// Waits until no table is being written, then keeps all of them from
+/// being written until the guard is dropped.
+pub fn latch_all() -> RwLockWriteGuard<'static, ()> {
+    ALL_TABLES.write().unwrap()
+}
+
+#[test]
+fn test_shared_and_upgrade() {
+    let never = || false;
+    assert_eq!(
+        acquire(901, "locktest", "s", Mode::Shared, never).unwrap(),
+        true
+    );
+    assert_eq!(
+        acquire(902, "locktest", "s", Mode::Shared, never).unwrap(),
+        true
+    );
+    // the upgrade waits for the other holder
+    let cancelled = || true;
+    assert_eq!(
+        acquire(901, "locktest", "s", Mode::Exclusive, cancelled).unwrap(),
+        false
+    );
+    release(902);
+    assert_eq!(
+        acquire(901, "locktest", "s", Mode::Exclusive, never).unwrap(),
+        true
+    );
+    assert_eq!(
+        acquire(902, "locktest", "s", Mode::Shared, cancelled).unwrap(),
+        false
+    );
+    release(901);
+    assert_eq!(
+        acquire(902, "locktest", "s", Mode::Shared, never).unwrap(),
+        true
+    );
+    release(902);
+}
+
+#[test]
+fn test_deadlock() {
+    use std::thread;
+
+    assert!(acquire(911, "locktest", "a", Mode::Exclusive, || false).unwrap());
+    assert!(acquire(912, "locktest", "b", Mode::Exclusive, || false).unwrap());
+    let older = thread::spawn(|| acquire(911, "locktest", "b", Mode::Exclusive, || false));
+    thread::sleep(Duration::from_millis(50));
+    // whichever request waits last closes the cycle, the younger
+    // transaction is the victim
+    match acquire(912, "locktest", "a", Mode::Exclusive, || false) {
+        Err(Error::Deadlock) => {}
+        other => panic!("expected a deadlock, got {:?}", other),
+    }
+    release(912);
+    assert!(older.join().unwrap().unwrap());
+    release(911);
+}
+
+#[test]
+fn test_latch() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+
+    let first = latch("locktest", "l");
+    // other tables are not kept from being written
+    drop(latch("locktest", "other"));
+    let written = Arc::new(AtomicBool::new(false));
+    let writer = {
+        let written = written.clone();
+        thread::spawn(move || {
+            let _second = latch("locktest", "l");
+            written.store(true, Ordering::SeqCst);
+        })
+    };
+    thread::sleep(Duration::from_millis(50));
+    assert!(!written.load(Ordering::SeqCst));
+    drop(first);
+    writer.join().unwrap();
+    assert!(written.load(Ordering::SeqCst));
+}
@@ -0,0 +1,128 @@
+//! Temporary tables of connections
+//!
+//! `CREATE TEMPORARY TABLE` creates a table which only the connection
+//! creating it sees and which is dropped when the connection ends. Its meta
+//! data and its rows are kept in memory, the rows are read and written by
+//! the memory engine. A temporary table hides a table of the same name in
+//! its database.
+//!
+//! Rows of temporary tables are not versioned: changes are visible at once
+//! and are kept on `ROLLBACK`.
+
+use super::data::Rows;
+use super::meta::{Table, TableMetaData};
+use super::Error;
+
+use std::io::Cursor;
+use std::mem;
+use std::sync::Mutex;
+
+static TABLES: Mutex<Vec<Entry>> = Mutex::new(Vec::new());
+
+/// A temporary table
+struct Entry {
+    connection: u64,
+    database: String,
+    name: String,
+    meta_data: TableMetaData,
+    data: Vec<u8>,
+}
+
+impl Entry {
+    fn is(&self, connection: u64, database: &str, name: &str) -> bool {
+        self.connection == connection && self.database == database && self.name == name
+    }
+}
+
+/// Returns the meta data of a temporary table of a connection, if there is
+/// one.
+pub fn meta_data(connection: u64, database: &str, name: &str) -> Option<TableMetaData> {
+    TABLES
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|e| e.is(connection, database, name))
+        .map(|e| e.meta_data.clone())
+}
+
+/// Stores the meta data of a temporary table, creating it without rows if
+/// it does not exist yet.
+pub fn save(connection: u64, table: &Table) {
+    let database = &table.database().name;
+    let mut tables = TABLES.lock().unwrap();
+    match tables
+        .iter_mut()
+        .find(|e| e.is(connection, database, &table.name))
+    {
+        Some(entry) => entry.meta_data = table.meta_data.clone(),
+        None => tables.push(Entry {
+            connection: connection,
+            database: database.clone(),
+            name: table.name.clone(),
+            meta_data: table.meta_data.clone(),
+            data: Vec::new(),
+        }),
+    }
+}
+
+/// Drops a temporary table. Returns true if it existed.
+pub fn drop(connection: u64, database: &str, name: &str) -> bool {
+    let mut tables = TABLES.lock().unwrap();
+    let len = tables.len();
+    tables.retain(|e| !e.is(connection, database, name));
+    tables.len() != len
+}
+
+/// Renames a temporary table. Returns true if it existed.
+pub fn rename(connection: u64, database: &str, name: &str, new_name: &str) -> bool {
+    let mut tables = TABLES.lock().unwrap();
+    match tables.iter_mut().find(|e| e.is(connection, database, name)) {
+        Some(entry) => {
+            entry.name = new_name.to_string();
+            true
+        }
+        None => false,
+    }
+}
+
+/// Moves the temporary tables of all connections to a renamed database.
+pub fn rename_database(database: &str, new_name: &str) {
+    for entry in TABLES.lock().unwrap().iter_mut() {
+        if entry.database == database {
+            entry.database = new_name.to_string();
+        }
+    }
+}
+
+/// Drops all temporary tables of a connection, which ended.
+pub fn forget(connection: u64) {
+    TABLES
+        .lock()
+        .unwrap()
+        .retain(|e| e.connection != connection);
+}
+
+/// Runs `f` on the rows of a temporary table. Other temporary tables must
+/// not be accessed meanwhile.
+///
+/// # Failures
+/// Fails with `Error::InvalidState` if the table does not exist.
+pub fn with_rows<T, F>(connection: u64, table: &Table, f: F) -> Result<T, Error>
+where
+    F: FnOnce(&mut Rows<Cursor<Vec<u8>>>) -> Result<T, Error>,
+{
+    let database = &table.database().name;
+    let mut tables = TABLES.lock().unwrap();
+    let entry = match tables
+        .iter_mut()
+        .find(|e| e.is(connection, database, &table.name))
+    {
+        Some(entry) => entry,
+        None => return Err(Error::InvalidState),
+    };
+    let data = mem::replace(&mut entry.data, Vec::new());
+    let mut rows = Rows::new(Cursor::new(data), table.columns());
+    let result = f(&mut rows);
+    entry.data = mem::replace(rows.get_mut().get_mut(), Vec::new());
+    result
+}
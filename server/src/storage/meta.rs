@@ -1,16 +1,31 @@
 use std::fs;
 use std::fs::{create_dir, remove_dir_all, OpenOptions};
+use std::io;
 use std::io::prelude::*;
+use std::io::Cursor;
 use std::mem;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 
 use bincode::{deserialize_from, serialize_into};
 use serde::{Deserialize, Serialize};
 
+use super::super::parse::ast::Conditions;
+use super::super::parse::token::Lit;
 use super::SqlType;
 
-use super::engine::FlatFile;
+use super::blob;
+use super::compress::{self, BlockFile, Compression, DataFile};
+use super::count;
+use super::data::Rows;
+use super::encrypt;
+use super::engine::{BStar, FlatFile, InvertedIndex, Memory};
+use super::index::{self, SecondaryIndexes};
+use super::memory;
+use super::stats::TableStats;
 use super::types::Column;
 use super::Engine;
 use super::EngineID;
@@ -18,7 +33,14 @@ use super::Error;
 
 /// constants
 const MAGIC_NUMBER: u64 = 0x49616D4372616E43;
-const VERSION_NO: u8 = 1;
+/// version of the `db.meta` format, older versions are read through
+/// `DatabaseMetaDataV1` to `DatabaseMetaDataV5`
+const VERSION_NO: u8 = 6;
+/// version of the table meta data format, every version that changed the
+/// format has a struct to read older files, like `TableMetaDataV1`
+const TABLE_VERSION_NO: u8 = 9;
+/// Numbers the temporary files meta data is written to, see `replace_file`
+static NEXT_TMP_FILE: AtomicU64 = AtomicU64::new(0);
 
 //---------------------------------------------------------------
 // DataType
@@ -37,9 +59,353 @@ impl DataType {
     }
 }
 
+//---------------------------------------------------------------
+// Privileges
+//---------------------------------------------------------------
+/// Operations a user may be allowed to perform on a database or table
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Privilege {
+    Select,
+    Insert,
+    Update,
+    Delete,
+    Create,
+    Drop,
+}
+
+impl Privilege {
+    /// Returns all privileges (used for `GRANT ALL`)
+    pub fn all() -> Vec<Privilege> {
+        vec![
+            Privilege::Select,
+            Privilege::Insert,
+            Privilege::Update,
+            Privilege::Delete,
+            Privilege::Create,
+            Privilege::Drop,
+        ]
+    }
+
+    /// Returns the SQL keyword of the privilege
+    pub fn name(&self) -> &'static str {
+        match self {
+            &Privilege::Select => "SELECT",
+            &Privilege::Insert => "INSERT",
+            &Privilege::Update => "UPDATE",
+            &Privilege::Delete => "DELETE",
+            &Privilege::Create => "CREATE",
+            &Privilege::Drop => "DROP",
+        }
+    }
+}
+
+/// Privileges of one user on the whole database (`table == None`) or on a
+/// single table.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Grant {
+    pub user: String,
+    pub table: Option<String>,
+    pub privileges: Vec<Privilege>,
+}
+
+/// A named select that queries read like a table
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct View {
+    pub name: String,
+    /// The select as written in `CREATE VIEW`, parsed whenever the view is
+    /// read
+    pub definition: String,
+}
+
+/// A function defined by `CREATE FUNCTION`, an expression of its parameters
+/// that replaces its calls
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Function {
+    pub name: String,
+    pub params: Vec<(String, SqlType)>,
+    pub returns: SqlType,
+    /// The expression as written in `CREATE FUNCTION`, parsed whenever the
+    /// function is called
+    pub body: String,
+}
+
+/// An event defined by `CREATE EVENT`, a statement run again and again,
+/// see `scheduler`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Event {
+    pub name: String,
+    /// Seconds between two runs
+    pub every: u64,
+    /// Seconds since the Unix epoch when the event was created, it runs
+    /// every `every` seconds from then on
+    pub starts: u64,
+    /// The user the statement runs as
+    pub definer: String,
+    /// The statement as written in `CREATE EVENT`
+    pub body: String,
+}
+
+//---------------------------------------------------------------
+// DatabaseMetaData
+//---------------------------------------------------------------
+/// Contents of the `db.meta` file of a database. The file is optional, a
+/// missing file means that no privileges were granted yet.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DatabaseMetaData {
+    version_nmbr: u8,
+    pub grants: Vec<Grant>,
+    /// statistics of the tables gathered by `ANALYZE`
+    pub stats: Vec<TableStats>,
+    pub views: Vec<View>,
+    pub functions: Vec<Function>,
+    pub events: Vec<Event>,
+    pub limits: DatabaseLimits,
+}
+
+/// Caps on the resources of a database, so that one database cannot take
+/// those of the others. 0 is no cap.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct DatabaseLimits {
+    /// bytes all files of the database may take, see `Database::size`
+    pub max_size: u64,
+    /// connections that may use the database at the same time
+    pub max_connections: u64,
+}
+
+/// `db.meta` as written before tables had statistics
+#[derive(Deserialize)]
+struct DatabaseMetaDataV1 {
+    _version_nmbr: u8,
+    grants: Vec<Grant>,
+}
+
+/// `db.meta` as written before databases had views
+#[derive(Deserialize)]
+struct DatabaseMetaDataV2 {
+    _version_nmbr: u8,
+    grants: Vec<Grant>,
+    stats: Vec<TableStats>,
+}
+
+/// `db.meta` as written before databases had functions
+#[derive(Deserialize)]
+struct DatabaseMetaDataV3 {
+    _version_nmbr: u8,
+    grants: Vec<Grant>,
+    stats: Vec<TableStats>,
+    views: Vec<View>,
+}
+
+/// `db.meta` as written before databases had events
+#[derive(Deserialize)]
+struct DatabaseMetaDataV4 {
+    _version_nmbr: u8,
+    grants: Vec<Grant>,
+    stats: Vec<TableStats>,
+    views: Vec<View>,
+    functions: Vec<Function>,
+}
+
+/// `db.meta` as written before databases had limits
+#[derive(Deserialize)]
+struct DatabaseMetaDataV5 {
+    _version_nmbr: u8,
+    grants: Vec<Grant>,
+    stats: Vec<TableStats>,
+    views: Vec<View>,
+    functions: Vec<Function>,
+    events: Vec<Event>,
+}
+
+impl Default for DatabaseMetaData {
+    fn default() -> DatabaseMetaData {
+        DatabaseMetaData {
+            version_nmbr: VERSION_NO,
+            grants: Vec::new(),
+            stats: Vec::new(),
+            views: Vec::new(),
+            functions: Vec::new(),
+            events: Vec::new(),
+            limits: DatabaseLimits::default(),
+        }
+    }
+}
+
+impl DatabaseMetaData {
+    /// Returns true if `user` holds `privilege` on the given table, either
+    /// directly or through a database wide grant.
+    pub fn has_privilege(&self, user: &str, table: Option<&str>, privilege: Privilege) -> bool {
+        self.grants.iter().any(|g| {
+            g.user == user
+                && (g.table.is_none() || g.table.as_ref().map(|t| &t[..]) == table)
+                && g.privileges.contains(&privilege)
+        })
+    }
+
+    /// Returns true if `user` holds any privilege in this database.
+    pub fn has_any_privilege(&self, user: &str) -> bool {
+        self.grants
+            .iter()
+            .any(|g| g.user == user && !g.privileges.is_empty())
+    }
+
+    /// Adds the privileges to the grant of `user` on `table`.
+    pub fn grant(&mut self, user: &str, table: Option<&str>, privileges: &[Privilege]) {
+        let pos = self
+            .grants
+            .iter()
+            .position(|g| g.user == user && g.table.as_ref().map(|t| &t[..]) == table);
+        let grant = match pos {
+            Some(i) => &mut self.grants[i],
+            None => {
+                self.grants.push(Grant {
+                    user: user.to_string(),
+                    table: table.map(|t| t.to_string()),
+                    privileges: Vec::new(),
+                });
+                self.grants.last_mut().unwrap()
+            }
+        };
+        for p in privileges {
+            if !grant.privileges.contains(p) {
+                grant.privileges.push(*p);
+            }
+        }
+    }
+
+    /// Removes the privileges from the grant of `user` on `table`.
+    pub fn revoke(&mut self, user: &str, table: Option<&str>, privileges: &[Privilege]) {
+        for g in self.grants.iter_mut() {
+            if g.user == user && g.table.as_ref().map(|t| &t[..]) == table {
+                g.privileges.retain(|p| !privileges.contains(p));
+            }
+        }
+        self.grants.retain(|g| !g.privileges.is_empty());
+    }
+
+    /// Removes all grants and the statistics referring to the given table.
+    /// Returns true if anything was removed.
+    pub fn forget_table(&mut self, table: &str) -> bool {
+        let len = self.grants.len();
+        self.grants
+            .retain(|g| g.table.as_ref().map(|t| &t[..]) != Some(table));
+        self.forget_stats(table) || self.grants.len() != len
+    }
+
+    /// Moves the grants and the statistics of a table to its new name.
+    pub fn rename_table(&mut self, table: &str, new_name: &str) {
+        for grant in &mut self.grants {
+            if grant.table.as_ref().map(|t| &t[..]) == Some(table) {
+                grant.table = Some(new_name.to_string());
+            }
+        }
+        for stats in &mut self.stats {
+            if stats.table == table {
+                stats.table = new_name.to_string();
+            }
+        }
+    }
+
+    /// Returns the statistics of a table, if it was analyzed.
+    pub fn stats(&self, table: &str) -> Option<&TableStats> {
+        self.stats.iter().find(|s| s.table == table)
+    }
+
+    /// Replaces the statistics of a table.
+    pub fn set_stats(&mut self, stats: TableStats) {
+        self.forget_stats(&stats.table);
+        self.stats.push(stats);
+        self.stats.sort_by(|a, b| a.table.cmp(&b.table));
+    }
+
+    /// Removes the statistics of a table, which no longer describe it.
+    /// Returns true if the table had statistics.
+    pub fn forget_stats(&mut self, table: &str) -> bool {
+        let len = self.stats.len();
+        self.stats.retain(|s| s.table != table);
+        self.stats.len() != len
+    }
+
+    /// Returns the view of the given name, if there is one.
+    pub fn view(&self, name: &str) -> Option<&View> {
+        self.views.iter().find(|v| v.name == name)
+    }
+
+    /// Adds a view or replaces the one of the same name. Views are kept in
+    /// the order they were created, after the views they may refer to.
+    pub fn set_view(&mut self, view: View) {
+        match self.views.iter().position(|v| v.name == view.name) {
+            Some(i) => self.views[i] = view,
+            None => self.views.push(view),
+        }
+    }
+
+    /// Removes a view. Returns true if it existed.
+    pub fn remove_view(&mut self, name: &str) -> bool {
+        let len = self.views.len();
+        self.views.retain(|v| v.name != name);
+        self.views.len() != len
+    }
+
+    /// Returns the function of the given name, if there is one.
+    pub fn function(&self, name: &str) -> Option<&Function> {
+        self.functions.iter().find(|f| f.name == name)
+    }
+
+    /// Adds a function or replaces the one of the same name.
+    pub fn set_function(&mut self, function: Function) {
+        match self.functions.iter().position(|f| f.name == function.name) {
+            Some(i) => self.functions[i] = function,
+            None => self.functions.push(function),
+        }
+    }
+
+    /// Removes a function. Returns true if it existed.
+    pub fn remove_function(&mut self, name: &str) -> bool {
+        let len = self.functions.len();
+        self.functions.retain(|f| f.name != name);
+        self.functions.len() != len
+    }
+
+    /// Returns the event of the given name, if there is one.
+    pub fn event(&self, name: &str) -> Option<&Event> {
+        self.events.iter().find(|e| e.name == name)
+    }
+
+    /// Adds an event or replaces the one of the same name.
+    pub fn set_event(&mut self, event: Event) {
+        match self.events.iter().position(|e| e.name == event.name) {
+            Some(i) => self.events[i] = event,
+            None => self.events.push(event),
+        }
+    }
+
+    /// Removes an event. Returns true if it existed.
+    pub fn remove_event(&mut self, name: &str) -> bool {
+        let len = self.events.len();
+        self.events.retain(|e| e.name != name);
+        self.events.len() != len
+    }
+
+    /// Removes all grants of the given user.
+    /// Returns true if any grant was removed.
+    pub fn forget_user(&mut self, user: &str) -> bool {
+        let len = self.grants.len();
+        self.grants.retain(|g| g.user != user);
+        self.grants.len() != len
+    }
+}
+
 //---------------------------------------------------------------
 // Database
 //---------------------------------------------------------------
+
+/// Prefix of the folders of dropped databases which are being deleted
+const TRASH_PREFIX: &'static str = ".trash-";
+/// Suffix of the temporary table a table is rewritten into, see
+/// `Table::replace_rows`
+pub const REWRITE_SUFFIX: &'static str = "~new";
+
 #[derive(Debug)]
 pub struct Database {
     pub name: String,
@@ -80,11 +446,364 @@ impl Database {
     }
     /// Deletes the database folder and all its contents
     /// do not use RANDOM!!
+    ///
+    /// The folder is moved into the trash first, so the database disappears
+    /// at once. Files that cannot be deleted stay in the trash until
+    /// `empty_trash` runs.
     pub fn delete(&self) -> Result<(), Error> {
-        info!("deleting Database and all its tables");
-        try!(remove_dir_all(&self.name));
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or(0);
+        let trash = format!("{}{}-{}", TRASH_PREFIX, self.name, nanos);
+        try!(fs::rename(&self.name, &trash));
+        info!("deleting database {:?} and all its tables", self.name);
+        if let Err(e) = remove_dir_all(&trash) {
+            warn!("could not empty {:?}: {:?}", trash, e);
+        }
+        Ok(())
+    }
+
+    /// Deletes what is left of dropped databases, e.g. after a crash.
+    pub fn empty_trash() -> Result<(), Error> {
+        for entry in try!(fs::read_dir(".")) {
+            let entry = try!(entry);
+            let trash = entry
+                .file_name()
+                .to_string_lossy()
+                .starts_with(TRASH_PREFIX);
+            if trash && try!(entry.file_type()).is_dir() {
+                info!("emptying {:?}", entry.path());
+                try!(remove_dir_all(entry.path()));
+            }
+        }
         Ok(())
     }
+    /// Renames the database and its folder. The rename is atomic, the
+    /// database keeps its name if it fails.
+    pub fn rename(&mut self, new_name: &str) -> Result<(), Error> {
+        if fs::metadata(new_name).is_ok() {
+            return Err(Error::DatabaseExists(new_name.to_string()));
+        }
+        try!(fs::rename(&self.name, new_name));
+        info!("renamed database {:?} to {:?}", self.name, new_name);
+        self.name = new_name.to_string();
+        Ok(())
+    }
+
+    /// Renames a table: its files, its grants and statistics and the foreign
+    /// keys referencing it. If a step fails, the steps before are undone.
+    pub fn rename_table(&self, table: &str, new_name: &str) -> Result<(), Error> {
+        if try!(self.list_tables()).iter().any(|t| t == new_name) {
+            return Err(Error::TableExists(new_name.to_string()));
+        }
+        let mut renamed = Vec::new();
+        let result = self
+            .rename_files(table, new_name, &mut renamed)
+            .and_then(|_| self.rename_references(table, new_name));
+        if result.is_err() {
+            for (from, to) in renamed.into_iter().rev() {
+                if let Err(e) = fs::rename(&to, &from) {
+                    error!("could not rename {:?} back to {:?}: {:?}", to, from, e);
+                }
+            }
+        }
+        result
+    }
+
+    /// Renames all files of a table, all of them start with `<table>.`.
+    /// Collects the paths renamed so far.
+    fn rename_files(
+        &self,
+        table: &str,
+        new_name: &str,
+        renamed: &mut Vec<(String, String)>,
+    ) -> Result<(), Error> {
+        let prefix = format!("{}.", table);
+        for entry in try!(fs::read_dir(&self.name)) {
+            let entry = try!(entry);
+            if !try!(entry.file_type()).is_file() {
+                continue;
+            }
+            let file = entry.file_name().to_string_lossy().into_owned();
+            if file.starts_with(&prefix) {
+                let from = format!("{}/{}", self.name, file);
+                let to = format!("{}/{}.{}", self.name, new_name, &file[prefix.len()..]);
+                try!(fs::rename(&from, &to));
+                renamed.push((from, to));
+            }
+        }
+        Ok(())
+    }
+
+    /// Points the foreign keys referencing a table and the entries of
+    /// `db.meta` to its new name. Undoes the changes if a step fails.
+    fn rename_references(&self, table: &str, new_name: &str) -> Result<(), Error> {
+        let mut changed = Vec::new();
+        let result = self
+            .rename_foreign_keys(table, new_name, &mut changed)
+            .and_then(|_| {
+                let mut meta = try!(self.load_meta_data());
+                meta.rename_table(table, new_name);
+                self.save_meta_data(&meta)
+            });
+        if result.is_err() {
+            for (name, meta_data) in changed {
+                let undone = self.load_table(&name).and_then(|mut t| {
+                    t.meta_data = meta_data;
+                    t.save()
+                });
+                if let Err(e) = undone {
+                    error!("could not restore the foreign keys of {:?}: {:?}", name, e);
+                }
+            }
+        }
+        result
+    }
+
+    /// Collects the names and the old meta data of the tables changed so
+    /// far.
+    fn rename_foreign_keys(
+        &self,
+        table: &str,
+        new_name: &str,
+        changed: &mut Vec<(String, TableMetaData)>,
+    ) -> Result<(), Error> {
+        for name in try!(self.list_tables()) {
+            let mut other = try!(self.load_table(&name));
+            if !other.foreign_keys().iter().any(|f| f.ref_table == table) {
+                continue;
+            }
+            let old = other.meta_data.clone();
+            for foreign_key in &mut other.meta_data.foreign_keys {
+                if foreign_key.ref_table == table {
+                    foreign_key.ref_table = new_name.to_string();
+                }
+            }
+            try!(other.save());
+            changed.push((name, old));
+        }
+        Ok(())
+    }
+
+    /// Removes all files of a table, whatever is left of them.
+    fn remove_files(&self, table: &str) -> Result<(), Error> {
+        for path in try!(self.table_files(table)) {
+            try!(fs::remove_file(path));
+        }
+        Ok(())
+    }
+
+    /// Returns the paths of all files of a table, whatever is left of them.
+    pub fn table_files(&self, table: &str) -> Result<Vec<PathBuf>, Error> {
+        let prefix = format!("{}.", table);
+        let mut paths = Vec::new();
+        for entry in try!(fs::read_dir(&self.name)) {
+            let entry = try!(entry);
+            if entry.file_name().to_string_lossy().starts_with(&prefix) {
+                paths.push(entry.path());
+            }
+        }
+        paths.sort();
+        Ok(paths)
+    }
+
+    /// Lists the names of all tables of the database.
+    pub fn list_tables(&self) -> Result<Vec<String>, Error> {
+        let mut names = Vec::new();
+        for entry in try!(fs::read_dir(&self.name)) {
+            let path = try!(entry).path();
+            if path.extension().map_or(false, |e| e == "tbl") {
+                if let Some(name) = path.file_stem().and_then(|s| s.to_str()) {
+                    names.push(name.to_string());
+                }
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    /// Lists the names of all databases in the data directory.
+    pub fn list() -> Result<Vec<String>, Error> {
+        let mut names = Vec::new();
+        for entry in try!(fs::read_dir(".")) {
+            let entry = try!(entry);
+            if try!(entry.file_type()).is_dir() {
+                // the trash is hidden
+                if let Some(name) = entry.file_name().to_str().filter(|n| !n.starts_with('.')) {
+                    names.push(name.to_string());
+                }
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    /// Reads the `db.meta` file of the database, also in an older version.
+    /// Returns the default meta data if the file does not exist.
+    pub fn load_meta_data(&self) -> Result<DatabaseMetaData, Error> {
+        self.read_meta_data().map(|(meta_data, _)| meta_data)
+    }
+
+    /// Reads the `db.meta` file of the database, returns the meta data and
+    /// the version of the file.
+    fn read_meta_data(&self) -> Result<(DatabaseMetaData, u8), Error> {
+        let mut file = match OpenOptions::new()
+            .read(true)
+            .open(self.get_meta_data_path())
+        {
+            Ok(f) => f,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                return Ok((DatabaseMetaData::default(), VERSION_NO))
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let ma_nmbr = try!(file.read_uint::<BigEndian>(mem::size_of_val(&MAGIC_NUMBER)));
+        if ma_nmbr != MAGIC_NUMBER {
+            return Err(Error::WrongMagicNmbr);
+        }
+        let version = try!(file.read_u8());
+        if version > VERSION_NO {
+            warn!(
+                "{:?} was written by a newer server",
+                self.get_meta_data_path()
+            );
+            return Err(Error::UnsupportedVersion(version));
+        }
+        try!(file.seek(io::SeekFrom::Current(-1)));
+        let meta_data = if version == 1 {
+            let old: DatabaseMetaDataV1 = try!(deserialize_from(&mut file));
+            DatabaseMetaData {
+                grants: old.grants,
+                ..DatabaseMetaData::default()
+            }
+        } else if version == 2 {
+            let old: DatabaseMetaDataV2 = try!(deserialize_from(&mut file));
+            DatabaseMetaData {
+                grants: old.grants,
+                stats: old.stats,
+                ..DatabaseMetaData::default()
+            }
+        } else if version == 3 {
+            let old: DatabaseMetaDataV3 = try!(deserialize_from(&mut file));
+            DatabaseMetaData {
+                grants: old.grants,
+                stats: old.stats,
+                views: old.views,
+                ..DatabaseMetaData::default()
+            }
+        } else if version == 4 {
+            let old: DatabaseMetaDataV4 = try!(deserialize_from(&mut file));
+            DatabaseMetaData {
+                grants: old.grants,
+                stats: old.stats,
+                views: old.views,
+                functions: old.functions,
+                ..DatabaseMetaData::default()
+            }
+        } else if version == 5 {
+            let old: DatabaseMetaDataV5 = try!(deserialize_from(&mut file));
+            DatabaseMetaData {
+                grants: old.grants,
+                stats: old.stats,
+                views: old.views,
+                functions: old.functions,
+                events: old.events,
+                ..DatabaseMetaData::default()
+            }
+        } else {
+            try!(deserialize_from(&mut file))
+        };
+        Ok((meta_data, version))
+    }
+
+    /// Rewrites the meta data of the database and of its tables which an
+    /// older version wrote in the current version.
+    /// Temporary files a crash left behind while saving are removed.
+    pub fn upgrade(&self) -> Result<(), Error> {
+        for entry in try!(fs::read_dir(&self.name)) {
+            let path = try!(entry).path();
+            let temporary = path
+                .extension()
+                .and_then(|e| e.to_str())
+                .map_or(false, |e| {
+                    e.starts_with("tmp") && e[3..].parse::<u64>().is_ok()
+                });
+            if temporary {
+                try!(fs::remove_file(&path));
+            }
+        }
+        let (meta_data, version) = try!(self.read_meta_data());
+        if version < VERSION_NO {
+            try!(self.upgrade_meta_data(&meta_data, version));
+        }
+        for name in try!(self.list_tables()) {
+            let (table, version) = try!(Table::read(self, &name));
+            if version < TABLE_VERSION_NO {
+                try!(table.upgrade(version));
+            }
+        }
+        Ok(())
+    }
+
+    /// Upgrades the meta data of all databases, see `upgrade`. Runs once at
+    /// startup, before the server accepts connections. A database that
+    /// cannot be upgraded still works.
+    pub fn upgrade_all() -> Result<(), Error> {
+        for name in try!(Database::list()) {
+            if let Err(e) = try!(Database::load(&name)).upgrade() {
+                warn!("cannot upgrade database {:?}: {:?}", name, e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Rewrites the `db.meta` file, which was written in an older version,
+    /// in the current version. The old file is kept as `db.meta.v<n>`.
+    fn upgrade_meta_data(&self, meta_data: &DatabaseMetaData, version: u8) -> Result<(), Error> {
+        let path = self.get_meta_data_path();
+        try!(keep_copy(&path, &format!("{}.v{}", path, version)));
+        try!(self.save_meta_data(meta_data));
+        info!(
+            "upgraded {:?} from version {} to {}",
+            path, version, VERSION_NO
+        );
+        Ok(())
+    }
+
+    /// Writes the `db.meta` file of the database.
+    pub fn save_meta_data(&self, meta_data: &DatabaseMetaData) -> Result<(), Error> {
+        replace_file(&self.get_meta_data_path(), |file| {
+            try!(file.write_u64::<BigEndian>(MAGIC_NUMBER));
+            try!(serialize_into(file, meta_data));
+            Ok(())
+        })
+    }
+
+    /// Returns the bytes all files of the database take.
+    pub fn size(&self) -> Result<u64, Error> {
+        let mut size = 0;
+        for entry in try!(fs::read_dir(&self.name)) {
+            size += try!(try!(entry).metadata()).len();
+        }
+        Ok(size)
+    }
+
+    /// Fails with `Error::DatabaseFull` if the database would grow beyond
+    /// its `max_size` by `bytes` more.
+    pub fn check_size(&self, bytes: u64) -> Result<(), Error> {
+        let max_size = try!(self.load_meta_data()).limits.max_size;
+        if max_size > 0 && try!(self.size()) + bytes > max_size {
+            return Err(Error::DatabaseFull(self.name.clone()));
+        }
+        Ok(())
+    }
+
+    /// Returns the path of the `db.meta` file
+    fn get_meta_data_path(&self) -> String {
+        format!("{}/db.meta", self.name)
+    }
+
     /// Creates a new table in the DB folder
     /// Returns with Error on fail else Table
     pub fn create_table(
@@ -99,6 +818,17 @@ impl Database {
         Ok(t)
     }
 
+    /// Returns the temporary table of a connection, if it has one of the
+    /// name
+    pub fn load_temporary_table(&self, connection: u64, name: &str) -> Option<Table> {
+        memory::meta_data(connection, &self.name, name).map(|meta_data| Table {
+            database: self,
+            name: name.to_string(),
+            meta_data: meta_data,
+            connection: Some(connection),
+        })
+    }
+
     /// calls load for table with the database path
     /// Returns with Error on fail else Table
     pub fn load_table(&self, name: &str) -> Result<Table, Error> {
@@ -110,23 +840,227 @@ impl Database {
 // TableMetaData
 //---------------------------------------------------------------
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct TableMetaData {
     version_nmbr: u8,
     engine_id: EngineID,
     pub columns: Vec<Column>,
+    pub indexes: Vec<Index>,
+    pub foreign_keys: Vec<ForeignKey>,
+    pub checks: Vec<Check>,
+    /// the rows of the data file are followed by their checksum
+    pub checksums: bool,
+    pub compression: Compression,
+    /// the blocks of the data file are sealed with the key of the server
+    pub encrypted: bool,
+}
+
+/// Table meta data as written before secondary indexes existed
+#[derive(Deserialize)]
+struct TableMetaDataV1 {
+    _version_nmbr: u8,
+    engine_id: EngineID,
+    columns: Vec<ColumnV1>,
+}
+
+/// Table meta data as written before columns had default values
+#[derive(Deserialize)]
+struct TableMetaDataV2 {
+    _version_nmbr: u8,
+    engine_id: EngineID,
+    columns: Vec<ColumnV1>,
+    indexes: Vec<IndexV1>,
+}
+
+/// Table meta data as written before indexes could be unique
+#[derive(Deserialize)]
+struct TableMetaDataV3 {
+    _version_nmbr: u8,
+    engine_id: EngineID,
+    columns: Vec<Column>,
+    indexes: Vec<IndexV1>,
+}
+
+/// A column as written before columns had default values
+#[derive(Deserialize)]
+struct ColumnV1 {
+    name: String,
+    sql_type: SqlType,
+    is_primary_key: bool,
+    allow_null: bool,
+    description: String,
+}
+
+impl From<ColumnV1> for Column {
+    fn from(old: ColumnV1) -> Column {
+        Column::new(
+            &old.name,
+            old.sql_type,
+            old.allow_null,
+            &old.description,
+            old.is_primary_key,
+        )
+    }
+}
+
+/// Table meta data as written before foreign keys existed
+#[derive(Deserialize)]
+struct TableMetaDataV4 {
+    _version_nmbr: u8,
+    engine_id: EngineID,
+    columns: Vec<Column>,
+    indexes: Vec<Index>,
+}
+
+/// Table meta data as written before check constraints existed
+#[derive(Deserialize)]
+struct TableMetaDataV5 {
+    _version_nmbr: u8,
+    engine_id: EngineID,
+    columns: Vec<Column>,
+    indexes: Vec<Index>,
+    foreign_keys: Vec<ForeignKey>,
+}
+
+/// Table meta data as written before rows had checksums
+#[derive(Deserialize)]
+struct TableMetaDataV6 {
+    _version_nmbr: u8,
+    engine_id: EngineID,
+    columns: Vec<Column>,
+    indexes: Vec<Index>,
+    foreign_keys: Vec<ForeignKey>,
+    checks: Vec<Check>,
+}
+
+/// Table meta data as written before data files could be compressed
+#[derive(Deserialize)]
+struct TableMetaDataV7 {
+    _version_nmbr: u8,
+    engine_id: EngineID,
+    columns: Vec<Column>,
+    indexes: Vec<Index>,
+    foreign_keys: Vec<ForeignKey>,
+    checks: Vec<Check>,
+    checksums: bool,
+}
+
+/// Table meta data as written before data files could be encrypted
+#[derive(Deserialize)]
+struct TableMetaDataV8 {
+    _version_nmbr: u8,
+    engine_id: EngineID,
+    columns: Vec<Column>,
+    indexes: Vec<Index>,
+    foreign_keys: Vec<ForeignKey>,
+    checks: Vec<Check>,
+    checksums: bool,
+    compression: Compression,
+}
+
+/// A secondary index, created by `CREATE INDEX` on a single column or by a
+/// `UNIQUE` constraint on one or more columns
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Index {
+    pub name: String,
+    pub columns: Vec<String>,
+    pub unique: bool,
+}
+
+/// An index as written before indexes could be unique
+#[derive(Deserialize)]
+struct IndexV1 {
+    name: String,
+    column: String,
+}
+
+impl From<IndexV1> for Index {
+    fn from(old: IndexV1) -> Index {
+        Index {
+            name: old.name,
+            columns: vec![old.column],
+            unique: false,
+        }
+    }
+}
+
+/// What happens to the referencing rows when a referenced row is deleted
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ReferentialAction {
+    Restrict,
+    Cascade,
+    SetNull,
+}
+
+/// A foreign key, created by a `FOREIGN KEY` constraint: the values of the
+/// columns are null or those of the referenced columns in a row of the
+/// referenced table
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ForeignKey {
+    pub name: String,
+    pub columns: Vec<String>,
+    pub ref_table: String,
+    pub ref_columns: Vec<String>,
+    pub on_delete: ReferentialAction,
+}
+
+/// A check constraint, created by `CHECK (...)`: the condition is not false
+/// for any row of the table
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Check {
+    pub name: String,
+    pub condition: Conditions,
 }
 
 //---------------------------------------------------------------
 // Table
 //---------------------------------------------------------------
 
+/// Copies the file at `path` to `backup` before it is upgraded. The copy
+/// of an earlier upgrade, e.g. one a crash interrupted, is kept.
+fn keep_copy(path: &str, backup: &str) -> Result<(), Error> {
+    if fs::metadata(backup).is_ok() {
+        return Ok(());
+    }
+    let old = try!(fs::read(path));
+    replace_file(backup, |file| file.write_all(&old).map_err(Error::from))
+}
+
+/// Writes the file at `path` through a temporary file, which replaces it
+/// once it is complete. A crash leaves the old file or the new one behind.
+fn replace_file<F>(path: &str, write: F) -> Result<(), Error>
+where
+    F: FnOnce(&mut fs::File) -> Result<(), Error>,
+{
+    // saves of the same file may run at once
+    let tmp_path = format!(
+        "{}.tmp{}",
+        path,
+        NEXT_TMP_FILE.fetch_add(1, Ordering::SeqCst)
+    );
+    let written = (|| {
+        let mut file = try!(OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&tmp_path));
+        try!(write(&mut file));
+        try!(file.sync_all());
+        fs::rename(&tmp_path, path).map_err(Error::from)
+    })();
+    if written.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    written
+}
+
 /// Table struct that contains the table information
 #[derive(Debug)]
 pub struct Table<'a> {
     database: &'a Database,
     pub name: String,
     pub meta_data: TableMetaData,
+    /// the connection owning the table if it is temporary, see `memory`
+    connection: Option<u64>,
 }
 
 impl<'a> Table<'a> {
@@ -139,9 +1073,16 @@ impl<'a> Table<'a> {
         engine_id: EngineID,
     ) -> Table<'b> {
         let meta_data = TableMetaData {
-            version_nmbr: VERSION_NO,
+            version_nmbr: TABLE_VERSION_NO,
             engine_id: engine_id,
             columns: columns,
+            indexes: Vec::new(),
+            foreign_keys: Vec::new(),
+            checks: Vec::new(),
+            // only the flat file engine checks its rows
+            checksums: engine_id == EngineID::FlatFile,
+            compression: Compression::None,
+            encrypted: false,
         };
         info!("created meta data: {:?}", meta_data);
 
@@ -149,12 +1090,37 @@ impl<'a> Table<'a> {
             name: name.to_string(),
             database: database,
             meta_data: meta_data,
+            connection: None,
         }
     }
 
-    /// Loads the table from the DB
+    /// Creates a new temporary table of a connection, whose rows are kept
+    /// in memory
+    pub fn temporary<'b>(
+        database: &'b Database,
+        name: &str,
+        columns: Vec<Column>,
+        connection: u64,
+    ) -> Table<'b> {
+        let mut table = Table::new(database, name, columns, EngineID::Memory);
+        table.connection = Some(connection);
+        table
+    }
+
+    /// Returns the connection owning the table if it is temporary
+    pub fn connection(&self) -> Option<u64> {
+        self.connection
+    }
+
+    /// Loads the table from the DB, also if an older version wrote it
     /// Returns with Error on fail else Table
     fn load<'b>(database: &'b Database, name: &str) -> Result<Table<'b>, Error> {
+        Table::read(database, name).map(|(table, _)| table)
+    }
+
+    /// Reads the meta data file of a table, returns the table and the
+    /// version of the file.
+    fn read<'b>(database: &'b Database, name: &str) -> Result<(Table<'b>, u8), Error> {
         // TODO: Read the .tbl file from disk and parse it
 
         let path_to_table = Table::get_path(&database.name, name, "tbl");
@@ -168,27 +1134,158 @@ impl<'a> Table<'a> {
             info!("Magic Number not correct");
             return Err(Error::WrongMagicNmbr);
         }
-        let meta_data: TableMetaData = try!(deserialize_from(&mut file));
+        let version = try!(file.read_u8());
+        if version > TABLE_VERSION_NO {
+            warn!("table {:?} was written by a newer server", name);
+            return Err(Error::UnsupportedVersion(version));
+        }
+        try!(file.seek(io::SeekFrom::Current(-1)));
+        let meta_data: TableMetaData = if version == 1 {
+            let old: TableMetaDataV1 = try!(deserialize_from(&mut file));
+            TableMetaData {
+                version_nmbr: TABLE_VERSION_NO,
+                engine_id: old.engine_id,
+                columns: old.columns.into_iter().map(Column::from).collect(),
+                indexes: Vec::new(),
+                foreign_keys: Vec::new(),
+                checks: Vec::new(),
+                checksums: false,
+                compression: Compression::None,
+                encrypted: false,
+            }
+        } else if version == 2 {
+            let old: TableMetaDataV2 = try!(deserialize_from(&mut file));
+            TableMetaData {
+                version_nmbr: TABLE_VERSION_NO,
+                engine_id: old.engine_id,
+                columns: old.columns.into_iter().map(Column::from).collect(),
+                indexes: old.indexes.into_iter().map(Index::from).collect(),
+                foreign_keys: Vec::new(),
+                checks: Vec::new(),
+                checksums: false,
+                compression: Compression::None,
+                encrypted: false,
+            }
+        } else if version == 3 {
+            let old: TableMetaDataV3 = try!(deserialize_from(&mut file));
+            TableMetaData {
+                version_nmbr: TABLE_VERSION_NO,
+                engine_id: old.engine_id,
+                columns: old.columns,
+                indexes: old.indexes.into_iter().map(Index::from).collect(),
+                foreign_keys: Vec::new(),
+                checks: Vec::new(),
+                checksums: false,
+                compression: Compression::None,
+                encrypted: false,
+            }
+        } else if version == 4 {
+            let old: TableMetaDataV4 = try!(deserialize_from(&mut file));
+            TableMetaData {
+                version_nmbr: TABLE_VERSION_NO,
+                engine_id: old.engine_id,
+                columns: old.columns,
+                indexes: old.indexes,
+                foreign_keys: Vec::new(),
+                checks: Vec::new(),
+                checksums: false,
+                compression: Compression::None,
+                encrypted: false,
+            }
+        } else if version == 5 {
+            let old: TableMetaDataV5 = try!(deserialize_from(&mut file));
+            TableMetaData {
+                version_nmbr: TABLE_VERSION_NO,
+                engine_id: old.engine_id,
+                columns: old.columns,
+                indexes: old.indexes,
+                foreign_keys: old.foreign_keys,
+                checks: Vec::new(),
+                checksums: false,
+                compression: Compression::None,
+                encrypted: false,
+            }
+        } else if version == 6 {
+            let old: TableMetaDataV6 = try!(deserialize_from(&mut file));
+            TableMetaData {
+                version_nmbr: TABLE_VERSION_NO,
+                engine_id: old.engine_id,
+                columns: old.columns,
+                indexes: old.indexes,
+                foreign_keys: old.foreign_keys,
+                checks: old.checks,
+                checksums: false,
+                compression: Compression::None,
+                encrypted: false,
+            }
+        } else if version == 7 {
+            let old: TableMetaDataV7 = try!(deserialize_from(&mut file));
+            TableMetaData {
+                version_nmbr: TABLE_VERSION_NO,
+                engine_id: old.engine_id,
+                columns: old.columns,
+                indexes: old.indexes,
+                foreign_keys: old.foreign_keys,
+                checks: old.checks,
+                checksums: old.checksums,
+                compression: Compression::None,
+                encrypted: false,
+            }
+        } else if version == 8 {
+            let old: TableMetaDataV8 = try!(deserialize_from(&mut file));
+            TableMetaData {
+                version_nmbr: TABLE_VERSION_NO,
+                engine_id: old.engine_id,
+                columns: old.columns,
+                indexes: old.indexes,
+                foreign_keys: old.foreign_keys,
+                checks: old.checks,
+                checksums: old.checksums,
+                compression: old.compression,
+                encrypted: false,
+            }
+        } else {
+            try!(deserialize_from(&mut file))
+        };
         info!("getting meta data{:?}", meta_data);
 
-        let table = Table::new(database, name, meta_data.columns, meta_data.engine_id);
+        let mut table = Table::new(database, name, meta_data.columns, meta_data.engine_id);
+        table.meta_data.indexes = meta_data.indexes;
+        table.meta_data.foreign_keys = meta_data.foreign_keys;
+        table.meta_data.checks = meta_data.checks;
+        table.meta_data.checksums = meta_data.checksums;
+        table.meta_data.compression = meta_data.compression;
+        table.meta_data.encrypted = meta_data.encrypted;
         info!("returning table: {:?}", table);
-        Ok(table)
+        Ok((table, version))
+    }
+
+    /// Rewrites the meta data file, which was written in an older version,
+    /// in the current version. The old file is kept as `<name>.tbl.v<n>`.
+    fn upgrade(&self, version: u8) -> Result<(), Error> {
+        let path = self.get_table_metadata_path();
+        try!(keep_copy(&path, &format!("{}.v{}", path, version)));
+        try!(self.save());
+        info!(
+            "upgraded table {:?} from version {} to {}",
+            self.name, version, TABLE_VERSION_NO
+        );
+        Ok(())
     }
 
     /// Saves the table with a identification number in table file
     /// Returns Error on fail else Nothing
     pub fn save(&self) -> Result<(), Error> {
-        // call for open file
-        info!("opening file to write");
-        let mut file = try!(OpenOptions::new()
-            .write(true)
-            .create(true)
-            .open(self.get_table_metadata_path()));
-        info!("writing magic number in file: {:?}", file);
-        try!(file.write_u64::<BigEndian>(MAGIC_NUMBER)); //MAGIC_NUMBER
-        info!("writing meta data in file: {:?}", file);
-        try!(serialize_into(&mut file, &self.meta_data));
+        if let Some(connection) = self.connection {
+            memory::save(connection, self);
+            return Ok(());
+        }
+        info!("writing meta data of table {:?}", self.name);
+        try!(replace_file(&self.get_table_metadata_path(), |file| {
+            try!(file.write_u64::<BigEndian>(MAGIC_NUMBER)); //MAGIC_NUMBER
+            try!(serialize_into(file, &self.meta_data));
+            Ok(())
+        }));
 
         // debug message all okay
         info!("I Wrote my File");
@@ -200,20 +1297,203 @@ impl<'a> Table<'a> {
     /// if the user lacks permissions to remove the file,
     /// or if some other filesystem-level error occurs.
     pub fn delete(&self) -> Result<(), Error> {
+        if let Some(connection) = self.connection {
+            memory::drop(connection, &self.database.name, &self.name);
+            return Ok(());
+        }
         info!("remove meta file: {:?}", self.get_table_metadata_path());
         try!(fs::remove_file(self.get_table_metadata_path()));
 
         info!("remove data file: {:?}", self.get_table_data_path());
         try!(fs::remove_file(self.get_table_data_path()));
 
+        if self.meta_data.engine_id == EngineID::BStar {
+            try!(BStar::delete_index(self));
+        }
+        if self.meta_data.engine_id == EngineID::InvertedIndex {
+            try!(InvertedIndex::delete_postings(self));
+        }
+        for index in self.indexes() {
+            try!(SecondaryIndexes::delete(self, &index.name));
+        }
+        try!(blob::delete(self));
+        try!(count::delete(self));
+
         Ok(())
     }
 
+    /// Removes all rows of the table at once: the files of the table are
+    /// emptied instead of deleting row by row
+    pub fn truncate(self) -> Result<(), Error> {
+        let mut engine = self.create_engine();
+        try!(engine.reset());
+        if engine.table().connection.is_some() {
+            return Ok(());
+        }
+        blob::delete(engine.table())
+    }
+
+    /// Returns the number of rows in the data file and the number of those
+    /// marked as deleted, whose space `Engine::reorganize` reclaims
+    pub fn count_rows(&self) -> Result<(u64, u64), Error> {
+        try!(self.open_rows()).count_rows()
+    }
+
+    /// Returns the length of the data of the table and that of its data
+    /// file, which is shorter if the table is compressed
+    pub fn data_sizes(&self) -> Result<(u64, u64), Error> {
+        let mut file = try!(OpenOptions::new()
+            .read(true)
+            .open(self.get_table_data_path()));
+        if self.in_blocks() {
+            compress::sizes(&mut file)
+        } else {
+            let len = try!(file.metadata()).len();
+            Ok((len, len))
+        }
+    }
+
+    /// Returns true if the data file is a sequence of blocks, see
+    /// `compress`, as it is for compressed and encrypted tables
+    fn in_blocks(&self) -> bool {
+        self.meta_data.compression != Compression::None || self.meta_data.encrypted
+    }
+
+    /// Opens the data file of the table with read write access, reading and
+    /// writing rows with checksums if the table has them
+    pub fn open_rows(&self) -> Result<Rows<DataFile>, Error> {
+        // temporary tables have no files
+        if self.connection.is_some() {
+            return Err(Error::NoImplementation);
+        }
+        let file = try!(OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(self.get_table_data_path()));
+        let file = if self.in_blocks() {
+            let cipher = if self.meta_data.encrypted {
+                Some(try!(encrypt::cipher().ok_or(Error::NoEncryptionKey)))
+            } else {
+                None
+            };
+            let compression = self.meta_data.compression;
+            DataFile::Blocks(try!(BlockFile::open(file, compression, cipher)))
+        } else {
+            DataFile::Plain(file)
+        };
+        if self.meta_data.checksums {
+            Ok(Rows::with_checksums(file, self.columns()))
+        } else {
+            Ok(Rows::new(file, self.columns()))
+        }
+    }
+
+    /// Returns the storage engine of the table
+    pub fn engine_id(&self) -> EngineID {
+        self.meta_data.engine_id
+    }
+
     /// Returns columns of table as array
     pub fn columns(&self) -> &[Column] {
         &self.meta_data.columns
     }
 
+    /// Returns the secondary indexes of the table
+    pub fn indexes(&self) -> &[Index] {
+        &self.meta_data.indexes
+    }
+
+    /// Adds a secondary index on some columns. The index still has to be
+    /// built by the engine, see `Engine::rebuild_indexes`.
+    pub fn add_index(&mut self, name: &str, columns: &[String], unique: bool) -> Result<(), Error> {
+        // the indexes of temporary tables would need files
+        if self.connection.is_some() {
+            return Err(Error::NoImplementation);
+        }
+        if self.meta_data.indexes.iter().any(|i| i.name == name) {
+            warn!("Index {:?} already exists", name);
+            return Err(Error::IndexExists);
+        }
+        let mut indexed = Vec::new();
+        for column in columns {
+            match self.meta_data.columns.iter().find(|c| &c.name == column) {
+                Some(c) => indexed.push(c),
+                None => {
+                    warn!("Column {:?} could not be found", column);
+                    return Err(Error::InvalidColumn);
+                }
+            }
+        }
+        // the slots of blobs do not order like their values
+        if indexed.iter().any(|c| c.sql_type == SqlType::Blob) {
+            return Err(Error::NoOperationPossible);
+        }
+        // the index files would hold the values in plain
+        if self.meta_data.encrypted {
+            return Err(Error::NoImplementation);
+        }
+        if unique && !index::fits_key(&indexed) {
+            return Err(Error::KeyTooLong);
+        }
+        self.meta_data.indexes.push(Index {
+            name: name.to_string(),
+            columns: columns.to_vec(),
+            unique: unique,
+        });
+        Ok(())
+    }
+
+    /// Returns the foreign keys of the table
+    pub fn foreign_keys(&self) -> &[ForeignKey] {
+        &self.meta_data.foreign_keys
+    }
+
+    /// Adds a foreign key. Whether the referenced columns may be referenced
+    /// is up to the caller, as they belong to another table.
+    pub fn add_foreign_key(&mut self, foreign_key: ForeignKey) -> Result<(), Error> {
+        if self
+            .meta_data
+            .foreign_keys
+            .iter()
+            .any(|f| f.name == foreign_key.name)
+        {
+            warn!("Foreign key {:?} already exists", foreign_key.name);
+            return Err(Error::IndexExists);
+        }
+        for column in &foreign_key.columns {
+            if !self.meta_data.columns.iter().any(|c| &c.name == column) {
+                warn!("Column {:?} could not be found", column);
+                return Err(Error::InvalidColumn);
+            }
+        }
+        self.meta_data.foreign_keys.push(foreign_key);
+        Ok(())
+    }
+
+    /// Returns the check constraints of the table
+    pub fn checks(&self) -> &[Check] {
+        &self.meta_data.checks
+    }
+
+    /// Adds a check constraint. Whether the condition makes sense for the
+    /// columns is up to the caller, who evaluates it.
+    pub fn add_check(&mut self, check: Check) -> Result<(), Error> {
+        if self.meta_data.checks.iter().any(|c| c.name == check.name) {
+            warn!("Check constraint {:?} already exists", check.name);
+            return Err(Error::IndexExists);
+        }
+        self.meta_data.checks.push(check);
+        Ok(())
+    }
+
+    /// Removes a check constraint, returns false if there is none with the
+    /// name.
+    pub fn remove_check(&mut self, name: &str) -> bool {
+        let len = self.meta_data.checks.len();
+        self.meta_data.checks.retain(|c| c.name != name);
+        self.meta_data.checks.len() != len
+    }
+
     /// Adds a column to the tabel
     /// Returns name of Column or on fail Error
     pub fn add_column(
@@ -223,6 +1503,7 @@ impl<'a> Table<'a> {
         allow_null: bool,
         description: &str,
         is_primary_key: bool,
+        default: Option<Lit>,
     ) -> Result<(), Error> {
         match self.meta_data.columns.iter().find(|x| x.name == name) {
             Some(_) => {
@@ -234,13 +1515,44 @@ impl<'a> Table<'a> {
             }
         }
 
-        self.meta_data.columns.push(Column::new(
-            name,
-            sql_type,
-            allow_null,
-            description,
-            is_primary_key,
-        ));
+        let mut column = Column::new(name, sql_type, allow_null, description, is_primary_key);
+        column.default = default;
+        self.meta_data.columns.push(column);
+        Ok(())
+    }
+
+    /// Replaces the column of the same name, the indexes on it have to fit
+    /// its new type
+    pub fn modify_column(&mut self, column: Column) -> Result<(), Error> {
+        let pos = match self
+            .meta_data
+            .columns
+            .iter()
+            .position(|c| c.name == column.name)
+        {
+            Some(pos) => pos,
+            None => {
+                warn!("Column {:?} could not be found", column.name);
+                return Err(Error::InvalidColumn);
+            }
+        };
+        self.meta_data.columns[pos] = column;
+        let columns = &self.meta_data.columns;
+        for index in &self.meta_data.indexes {
+            if !index.columns.contains(&columns[pos].name) {
+                continue;
+            }
+            if columns[pos].sql_type == SqlType::Blob {
+                return Err(Error::NoOperationPossible);
+            }
+            let indexed: Vec<_> = columns
+                .iter()
+                .filter(|c| index.columns.contains(&c.name))
+                .collect();
+            if index.unique && !index::fits_key(&indexed) {
+                return Err(Error::KeyTooLong);
+            }
+        }
         Ok(())
     }
 
@@ -257,18 +1569,138 @@ impl<'a> Table<'a> {
                 return Err(Error::RemoveColumn);
             }
         };
-        self.meta_data.columns.swap_remove(index);
+        self.meta_data.columns.remove(index);
+
+        // indexes on the column are dropped with it, their files are deleted
+        // by `rewrite`
+        self.meta_data
+            .indexes
+            .retain(|i| !i.columns.iter().any(|c| c == name));
+        // and so are its foreign keys
+        self.meta_data
+            .foreign_keys
+            .retain(|f| !f.columns.iter().any(|c| c == name));
+        // and its check constraints
+        self.meta_data
+            .checks
+            .retain(|c| !c.condition.uses_column(name));
+        Ok(())
+    }
+
+    /// Rewrites the rows of the table into the layout of `new`, the table
+    /// after ALTER TABLE. Every row is converted by `convert`, see
+    /// `replace_rows`.
+    pub fn rewrite<F, E>(&self, new: &Table, convert: F) -> Result<(), E>
+    where
+        F: FnMut(&[u8]) -> Result<Vec<u8>, E>,
+        E: From<Error>,
+    {
+        let old = try!(self.database.load_table(&self.name)).create_engine();
+        let rows = try!(old.full_scan());
+        self.replace_rows(new, rows, convert)
+    }
+
+    /// Replaces the files of the table by those of a table in the layout of
+    /// `new` holding `rows`. Every row is converted by `convert` and written
+    /// to a temporary table, whose files then replace those of the table;
+    /// the meta data is renamed last. BLOB values stay in the overflow file
+    /// of the table.
+    pub fn replace_rows<F, E>(
+        &self,
+        new: &Table,
+        mut rows: Rows<Cursor<Vec<u8>>>,
+        mut convert: F,
+    ) -> Result<(), E>
+    where
+        F: FnMut(&[u8]) -> Result<Vec<u8>, E>,
+        E: From<Error>,
+    {
+        if self.connection.is_some() {
+            return Err(E::from(Error::NoImplementation));
+        }
+        // a rewrite that crashed may have left files behind
+        let temp_name = format!("{}{}", self.name, REWRITE_SUFFIX);
+        try!(self.database.remove_files(&temp_name));
+        let mut temp = Table::new(
+            self.database,
+            &temp_name,
+            new.meta_data.columns.clone(),
+            new.meta_data.engine_id,
+        );
+        temp.meta_data.indexes = new.meta_data.indexes.clone();
+        temp.meta_data.foreign_keys = new.meta_data.foreign_keys.clone();
+        temp.meta_data.checks = new.meta_data.checks.clone();
+        temp.meta_data.compression = new.meta_data.compression;
+        temp.meta_data.encrypted = new.meta_data.encrypted;
+        try!(temp.save());
+
+        let filled = (|| {
+            let mut engine = try!(self.database.load_table(&temp_name)).create_engine();
+            try!(engine.create_table());
+            // creates the files of the indexes, inserting keeps them
+            try!(engine.rebuild_indexes());
+            try!(rows.reset_pos());
+            let mut row = Vec::new();
+            loop {
+                row.clear();
+                match rows.next_row(&mut row) {
+                    Ok(_) => {}
+                    Err(Error::EndOfFile) => break,
+                    Err(e) => return Err(E::from(e)),
+                }
+                try!(engine.insert_row(&try!(convert(&row))));
+            }
+            Ok(())
+        })();
+        if let Err(e) = filled {
+            info!(
+                "rewriting table {:?} failed, removing temporary files",
+                self.name
+            );
+            let _ = self.database.remove_files(&temp_name);
+            return Err(e);
+        }
+
+        // renaming replaces the files of the table one by one
+        let prefix = format!("{}.", temp_name);
+        for entry in try!(fs::read_dir(&self.database.name).map_err(Error::from)) {
+            let entry = try!(entry.map_err(Error::from));
+            let file_name = entry.file_name().to_string_lossy().into_owned();
+            if file_name.starts_with(&prefix) && file_name != format!("{}tbl", prefix) {
+                let target = format!("{}.{}", self.name, &file_name[prefix.len()..]);
+                try!(
+                    fs::rename(entry.path(), entry.path().with_file_name(target))
+                        .map_err(Error::from)
+                );
+            }
+        }
+        for index in self.indexes() {
+            if !new.indexes().iter().any(|i| i.name == index.name) {
+                try!(SecondaryIndexes::delete(self, &index.name));
+            }
+        }
+        try!(fs::rename(
+            temp.get_table_metadata_path(),
+            self.get_table_metadata_path()
+        )
+        .map_err(Error::from));
         Ok(())
     }
 
+    /// Returns the database the table belongs to
+    pub fn database(&self) -> &Database {
+        self.database
+    }
+
     /// Creates an engine for Table
     /// Returns Box<Engine>
     pub fn create_engine(self) -> Box<dyn Engine + 'a> {
         // add engines here
         match self.meta_data.engine_id {
             EngineID::FlatFile => Box::new(FlatFile::new(self)),
-            EngineID::InvertedIndex => Box::new(FlatFile::new(self)),
-            EngineID::BStar => Box::new(FlatFile::new(self)),
+            EngineID::InvertedIndex => Box::new(InvertedIndex::new(self)),
+            EngineID::BStar => Box::new(BStar::new(self)),
+            EngineID::Memory => Box::new(Memory::new(self)),
         }
     }
 
@@ -282,8 +1714,127 @@ impl<'a> Table<'a> {
         Self::get_path(&self.database.name, &self.name, "dat")
     }
 
+    /// Returns the path of the index files, the B* tree adds the extensions
+    pub fn get_table_index_path(&self) -> String {
+        Self::get_path(&self.database.name, &self.name, "idx")
+    }
+
+    /// Returns the path of the postings file of the inverted index
+    pub fn get_table_postings_path(&self) -> String {
+        Self::get_path(&self.database.name, &self.name, "inv")
+    }
+
+    /// Returns the path of the overflow file of BLOB values
+    pub fn get_table_blob_path(&self) -> String {
+        Self::get_path(&self.database.name, &self.name, "blob")
+    }
+
+    /// Returns the path of the row count, see `count`
+    pub fn get_table_count_path(&self) -> String {
+        Self::get_path(&self.database.name, &self.name, "cnt")
+    }
+
+    /// Returns the path of a secondary index, the B* tree adds the extensions
+    pub fn get_secondary_index_path(&self, index: &str) -> String {
+        Self::get_path(
+            &self.database.name,
+            &format!("{}.{}", self.name, index),
+            "sidx",
+        )
+    }
+
     /// Returns the path of the table
     fn get_path(database: &str, name: &str, ext: &str) -> String {
         format!("{}/{}.{}", database, name, ext)
     }
 }
+
+#[cfg(test)]
+fn test_database(name: &str) -> Database {
+    let dir = ::std::env::temp_dir().join(format!("uosql-{}-{}", name, ::std::process::id()));
+    let _ = remove_dir_all(&dir);
+    Database::create(&dir.to_string_lossy()).unwrap()
+}
+
+#[cfg(test)]
+fn write_file<T: Serialize>(path: &str, content: &T) {
+    let mut file = fs::File::create(path).unwrap();
+    file.write_u64::<BigEndian>(MAGIC_NUMBER).unwrap();
+    serialize_into(&mut file, content).unwrap();
+}
+
+#[test]
+fn test_upgrade_table_v1() {
+    let database = test_database("upgrade-table");
+    let path = Table::get_path(&database.name, "t", "tbl");
+    // version, engine and the columns before they had default values
+    let columns = vec![(
+        "id".to_string(),
+        SqlType::Int,
+        true,
+        false,
+        "key".to_string(),
+    )];
+    write_file(&path, &(1u8, EngineID::FlatFile, columns));
+    let old = fs::read(&path).unwrap();
+
+    let table = database.load_table("t").unwrap();
+    assert_eq!(table.meta_data.version_nmbr, TABLE_VERSION_NO);
+    assert_eq!(table.columns().len(), 1);
+    assert_eq!(table.columns()[0].name, "id");
+    assert!(table.columns()[0].is_primary_key);
+    assert!(table.meta_data.indexes.is_empty());
+    // loading does not write, the upgrade at startup does
+    assert_eq!(fs::read(&path).unwrap(), old);
+    database.upgrade().unwrap();
+    assert_eq!(fs::read(format!("{}.v1", path)).unwrap(), old);
+    assert_eq!(fs::read(&path).unwrap()[8], TABLE_VERSION_NO);
+    // the upgraded file is read as it is
+    assert_eq!(database.load_table("t").unwrap().columns()[0].name, "id");
+    let _ = remove_dir_all(&database.name);
+}
+
+#[test]
+fn test_upgrade_database_meta_data_v1() {
+    let database = test_database("upgrade-meta");
+    let path = database.get_meta_data_path();
+    let grant = Grant {
+        user: "alice".into(),
+        table: None,
+        privileges: vec![Privilege::Select],
+    };
+    write_file(&path, &(1u8, vec![grant.clone()]));
+    let old = fs::read(&path).unwrap();
+    // a save cut off by a crash
+    fs::write(format!("{}.tmp7", path), b"cut").unwrap();
+
+    let meta_data = database.load_meta_data().unwrap();
+    assert_eq!(meta_data.grants, vec![grant.clone()]);
+    assert!(meta_data.views.is_empty());
+    assert_eq!(fs::read(&path).unwrap(), old);
+    database.upgrade().unwrap();
+    assert!(fs::metadata(format!("{}.tmp7", path)).is_err());
+    assert_eq!(fs::read(format!("{}.v1", path)).unwrap(), old);
+    assert_eq!(fs::read(&path).unwrap()[8], VERSION_NO);
+    assert_eq!(database.load_meta_data().unwrap().grants, vec![grant]);
+    let _ = remove_dir_all(&database.name);
+}
+
+#[test]
+fn test_newer_versions_are_refused() {
+    let database = test_database("newer-version");
+    let path = Table::get_path(&database.name, "t", "tbl");
+    write_file(&path, &(TABLE_VERSION_NO + 1, EngineID::FlatFile));
+    match database.load_table("t") {
+        Err(Error::UnsupportedVersion(v)) => assert_eq!(v, TABLE_VERSION_NO + 1),
+        other => panic!("{:?}", other.map(|t| t.name)),
+    }
+    write_file(&database.get_meta_data_path(), &(VERSION_NO + 1, 0u64));
+    match database.load_meta_data() {
+        Err(Error::UnsupportedVersion(v)) => assert_eq!(v, VERSION_NO + 1),
+        other => panic!("{:?}", other),
+    }
+    // nothing is rewritten
+    assert_eq!(fs::read(&path).unwrap()[8], TABLE_VERSION_NO + 1);
+    let _ = remove_dir_all(&database.name);
+}
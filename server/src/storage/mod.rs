@@ -1,20 +1,42 @@
 //! Storage Engine trait and several implementations
 //!
 //!
+pub mod backup;
+pub mod blob;
 pub mod bstar;
+mod compress;
+pub mod count;
+pub mod decimal;
+pub mod encrypt;
 mod engine;
+mod index;
+pub mod lock;
+pub mod memory;
 mod meta;
+pub mod mvcc;
+pub mod pattern;
+pub mod recovery;
+pub mod repair;
+pub mod stats;
+pub mod temporal;
+pub mod transaction;
 pub mod types;
+pub mod vacuum;
 
 mod data;
 
 use serde::{Deserialize, Serialize};
 
+pub use self::compress::Compression;
+pub use self::data::Damage;
 pub use self::data::ResultSet;
 pub use self::data::Rows;
 pub use self::engine::FlatFile;
 pub use self::meta::Database;
 pub use self::meta::Table;
+pub use self::meta::{Check, ForeignKey, ReferentialAction};
+pub use self::meta::{DatabaseLimits, DatabaseMetaData, Event, Function, Grant, Privilege, View};
+pub use self::mvcc::Versioned;
 pub use self::types::Column;
 pub use self::types::SqlType;
 
@@ -24,14 +46,15 @@ pub use parse::ast::CompType;
 use std::ffi::NulError;
 use std::io;
 use std::io::Cursor;
+use std::ops::Bound;
 use std::str::Utf8Error;
 pub use std::string::FromUtf8Error;
 /// A database table
 ///
 /// Through this type, you can retreive certain meta information about the
-/// table (like column names, column types, storage engine, ...). It's `access`
-/// method locks the table globally and returns a storage engine to access
-/// the table data.
+/// table (like column names, column types, storage engine, ...). Tables are
+/// not locked for reading: rows are versioned, so that every statement reads
+/// a snapshot of them while writers lock single rows, see `transaction`.
 #[derive(Debug)]
 pub enum Error {
     Io(io::Error),
@@ -58,8 +81,34 @@ pub enum Error {
     EndOfFile,
     BeginningOfFile,
     PrimaryKeyValueExists,
+    UniqueKeyValueExists(String),
     FoundNoPrimaryKey,
     PrimaryKeyNotAllowed,
+    KeyTooLong,
+    IndexExists,
+    TransactionActive,
+    NoTransaction,
+    RowLocked,
+    /// The transaction was rolled back to resolve a deadlock, see `lock`
+    Deadlock,
+    NullNotAllowed,
+    /// The data of the row at the address does not match its checksum
+    ChecksumMismatch(u64),
+    /// The file was written in a newer version of its format
+    UnsupportedVersion(u8),
+    /// The compressed block at the offset of the data file is damaged
+    CorruptBlock(u64),
+    /// The table is encrypted, but the server has no key, see `encrypt`
+    NoEncryptionKey,
+    /// A table of the name exists already
+    TableExists(String),
+    /// A database of the name exists already
+    DatabaseExists(String),
+    /// The database would grow beyond its `max_size`, holds its name, see
+    /// `DatabaseLimits`
+    DatabaseFull(String),
+    /// The pattern of `REGEXP` is no regular expression, holds the reason
+    InvalidPattern(String),
 }
 
 impl From<NulError> for Error {
@@ -118,8 +167,31 @@ pub trait Engine {
         comp: CompType,
     ) -> Result<Rows<Cursor<Vec<u8>>>, Error>;
 
+    /// Returns the rows whose value of a column lies within the bounds,
+    /// ordered by that value, e.g. for `BETWEEN` or `ORDER BY`. Rows whose
+    /// value is null are left out. Engines with an index on the column read
+    /// only the rows in the range.
+    fn scan_range(
+        &self,
+        column_index: usize,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<Rows<Cursor<Vec<u8>>>, Error> {
+        try!(self.full_scan()).range(column_index, lower, upper)
+    }
+
     fn insert_row(&mut self, row_data: &[u8]) -> Result<u64, Error>;
 
+    /// Inserts rows given together, like those of a multi-row `INSERT` or a
+    /// `COPY`. Either all of them are written or, if one cannot be, none.
+    fn insert_rows(&mut self, rows: &[&[u8]]) -> Result<u64, Error> {
+        let mut inserted = 0;
+        for row_data in rows {
+            inserted += try!(self.insert_row(row_data));
+        }
+        Ok(inserted)
+    }
+
     fn delete(
         &self,
         column_index: usize,
@@ -138,14 +210,61 @@ pub trait Engine {
     fn reorganize(&mut self) -> Result<(), Error>;
 
     fn reset(&mut self) -> Result<(), Error>;
+
+    /// rebuilds all secondary indexes of the table from its data
+    fn rebuild_indexes(&mut self) -> Result<(), Error>;
+
+    /// Stores a BLOB value and returns the bytes of its column, see `blob`
+    fn write_blob(&self, data: &mut dyn io::Read) -> Result<Vec<u8>, Error> {
+        blob::write(self.table(), data)
+    }
+
+    /// Returns a reader over the BLOB value of a column
+    fn read_blob<'b>(&self, value: &'b [u8]) -> Result<Box<dyn io::Read + 'b>, Error> {
+        blob::read(self.table(), value)
+    }
 }
 
 #[repr(u8)]
-#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub enum EngineID {
     FlatFile = 1,
     InvertedIndex,
     BStar,
+    /// Rows in memory, only for temporary tables
+    Memory,
+}
+
+impl EngineID {
+    /// Returns the name of the engine in `ENGINE = ...`
+    pub fn name(&self) -> &'static str {
+        match self {
+            &EngineID::FlatFile => "flatfile",
+            &EngineID::InvertedIndex => "invertedindex",
+            &EngineID::BStar => "bstar",
+            &EngineID::Memory => "memory",
+        }
+    }
+
+    /// Returns all engines (listed by `SHOW ENGINES`)
+    pub fn all() -> Vec<EngineID> {
+        vec![
+            EngineID::FlatFile,
+            EngineID::InvertedIndex,
+            EngineID::BStar,
+            EngineID::Memory,
+        ]
+    }
+
+    /// Returns how the engine keeps the rows of a table
+    pub fn description(&self) -> &'static str {
+        match self {
+            &EngineID::FlatFile => "rows one after another in a data file",
+            &EngineID::InvertedIndex => "rows in a data file, with an index of the words of text",
+            &EngineID::BStar => "rows in a tree ordered by the primary key",
+            &EngineID::Memory => "rows in memory, only for temporary tables",
+        }
+    }
 }
 
 // # Some information for the `storage` working group:
@@ -166,7 +285,8 @@ pub enum EngineID {
 //   database from file
 // - create a new table in a database
 // - query meta information about a table (columns for example)
-// - lock a table for reading/writing it's data through a storage engine
+// - version the rows of a table, so that readers and writers of it do not
+//   block each other
 //
 // The other main task is to:
 // - specify the storage engine interface
@@ -0,0 +1,483 @@
+//! Multi-version access to the rows of a table
+//!
+//! `Versioned` wraps the storage engine of a table for a single statement.
+//! Rows read through it are those of the snapshot of the statement, rows
+//! written through it are recorded in the undo log of the transaction of the
+//! statement first, see `transaction`.
+//!
+//! Versions of a row are told apart by their content, a row is locked by the
+//! values of its primary key columns. A write latches its table while it
+//! reads, records and writes the rows it changes, see `lock::latch`.
+//!
+//! The rows read and written through `Versioned` are counted for the
+//! metrics of the server, see `rows_read` and `rows_written`. Rows are only
+//! inserted while the database stays within its `max_size`, see
+//! `DatabaseLimits`.
+
+use super::data::Rows;
+use super::lock::{self, Latch};
+use super::meta::Database;
+use super::transaction::{Change, Statement};
+use super::types::{self, column_range, row_size, Column};
+use super::{CompType, Engine, EngineID, Error, Table};
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::ops::Bound;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+static ROWS_READ: AtomicU64 = AtomicU64::new(0);
+static ROWS_WRITTEN: AtomicU64 = AtomicU64::new(0);
+
+/// Returns the number of rows statements read from tables so far.
+pub fn rows_read() -> u64 {
+    ROWS_READ.load(Ordering::SeqCst)
+}
+
+/// Returns the number of rows statements inserted, deleted or changed so
+/// far.
+pub fn rows_written() -> u64 {
+    ROWS_WRITTEN.load(Ordering::SeqCst)
+}
+
+/// Counts the rows read from a table.
+fn counted(mut rows: Rows<Cursor<Vec<u8>>>) -> Result<Rows<Cursor<Vec<u8>>>, Error> {
+    let (count, _) = try!(rows.count_rows());
+    try!(rows.reset_pos());
+    ROWS_READ.fetch_add(count, Ordering::SeqCst);
+    Ok(rows)
+}
+
+/// Counts the rows a write returns to have written.
+fn written(result: Result<u64, Error>) -> Result<u64, Error> {
+    if let Ok(count) = result {
+        ROWS_WRITTEN.fetch_add(count, Ordering::SeqCst);
+    }
+    result
+}
+
+pub struct Versioned<'a> {
+    inner: Box<dyn Engine + 'a>,
+    statement: &'a Statement,
+}
+
+impl<'a> Versioned<'a> {
+    pub fn new<'b>(inner: Box<dyn Engine + 'b>, statement: &'b Statement) -> Versioned<'b> {
+        Versioned {
+            inner: inner,
+            statement: statement,
+        }
+    }
+
+    /// Returns the names of the database and of the table.
+    fn names(&self) -> (String, String) {
+        let table = self.inner.table();
+        (table.database().name.clone(), table.name.clone())
+    }
+
+    /// Keeps others from writing the table until the latch is dropped.
+    fn latch(&self) -> Latch {
+        let (database, table) = self.names();
+        lock::latch(&database, &table)
+    }
+
+    /// Undoes the changes the snapshot does not see, the newest first.
+    /// Deleted rows only come back if they fulfill the constraint of the
+    /// read.
+    fn revert<F>(
+        &self,
+        rows: Rows<Cursor<Vec<u8>>>,
+        filter: F,
+    ) -> Result<Rows<Cursor<Vec<u8>>>, Error>
+    where
+        F: Fn(&[u8]) -> Result<bool, Error>,
+    {
+        let (database, table) = self.names();
+        let changes = self.statement.invisible_changes(&database, &table);
+        undo_changes(self.inner.table().columns(), rows, changes, filter)
+    }
+
+    /// Records the deletion of all rows read, returns their number.
+    fn record_deleted(&self, rows: Rows<Cursor<Vec<u8>>>) -> Result<u64, Error> {
+        let columns = self.inner.table().columns();
+        let changes: Vec<_> = try!(read_all(rows))
+            .into_iter()
+            .map(|row| (key(columns, &row), Change::Deleted(row)))
+            .collect();
+        let count = changes.len() as u64;
+        let (database, table) = self.names();
+        try!(self.statement.record(&database, &table, changes));
+        Ok(count)
+    }
+}
+
+impl<'a> Engine for Versioned<'a> {
+    fn create_table(&mut self) -> Result<(), Error> {
+        self.inner.create_table()
+    }
+
+    fn table(&self) -> &Table {
+        self.inner.table()
+    }
+
+    fn full_scan(&self) -> Result<Rows<Cursor<Vec<u8>>>, Error> {
+        let rows = try!(self.inner.full_scan());
+        counted(try!(self.revert(rows, |_| Ok(true))))
+    }
+
+    fn lookup(
+        &self,
+        column_index: usize,
+        value: (&[u8], Option<usize>),
+        comp: CompType,
+    ) -> Result<Rows<Cursor<Vec<u8>>>, Error> {
+        let rows = try!(self.inner.lookup(column_index, value, comp));
+        let columns = self.inner.table().columns();
+        counted(try!(self.revert(rows, |row| {
+            types::fulfills(columns, row, column_index, value, comp)
+        })))
+    }
+
+    /// Rows of the snapshot that are not in the table anymore are put back
+    /// in their place.
+    fn scan_range(
+        &self,
+        column_index: usize,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<Rows<Cursor<Vec<u8>>>, Error> {
+        let rows = try!(self.inner.scan_range(column_index, lower, upper));
+        let (database, table) = self.names();
+        let changes = self.statement.invisible_changes(&database, &table);
+        if changes.is_empty() {
+            return counted(rows);
+        }
+        let columns = self.inner.table().columns();
+        let mut reverted = try!(undo_changes(columns, rows, changes, |row| {
+            types::within(columns, row, column_index, lower, upper)
+        }));
+        counted(try!(reverted.range(column_index, lower, upper)))
+    }
+
+    fn insert_row(&mut self, row_data: &[u8]) -> Result<u64, Error> {
+        let _writing = self.latch();
+        try!(self.table().database().check_size(row_data.len() as u64));
+        let (database, table) = self.names();
+        let key = key(self.inner.table().columns(), row_data);
+        let change = Change::Inserted(row_data.to_vec());
+        try!(self
+            .statement
+            .record(&database, &table, vec![(key, change)]));
+        let result = self.inner.insert_row(row_data);
+        if result.is_err() {
+            self.statement.forget_last(1);
+        }
+        written(result)
+    }
+
+    fn insert_rows(&mut self, rows: &[&[u8]]) -> Result<u64, Error> {
+        let _writing = self.latch();
+        let bytes = rows.iter().map(|r| r.len() as u64).sum();
+        try!(self.table().database().check_size(bytes));
+        let (database, table) = self.names();
+        let changes: Vec<_> = {
+            let columns = self.inner.table().columns();
+            rows.iter()
+                .map(|r| (key(columns, r), Change::Inserted(r.to_vec())))
+                .collect()
+        };
+        try!(self.statement.record(&database, &table, changes));
+        let result = self.inner.insert_rows(rows);
+        if result.is_err() {
+            self.statement.forget_last(rows.len());
+        }
+        written(result)
+    }
+
+    fn delete(
+        &self,
+        column_index: usize,
+        value: (&[u8], Option<usize>),
+        comp: CompType,
+    ) -> Result<u64, Error> {
+        let _writing = self.latch();
+        try!(self.record_deleted(try!(self.inner.lookup(column_index, value, comp))));
+        written(self.inner.delete(column_index, value, comp))
+    }
+
+    fn modify(
+        &mut self,
+        constraint_column_index: usize,
+        constraint_value: (&[u8], Option<usize>),
+        comp: CompType,
+        values: &[(usize, &[u8])],
+    ) -> Result<u64, Error> {
+        let _writing = self.latch();
+        let found = try!(self
+            .inner
+            .lookup(constraint_column_index, constraint_value, comp));
+        // all old rows go before the new ones take their keys
+        let (mut changes, mut inserted) = (Vec::new(), Vec::new());
+        {
+            let columns = self.inner.table().columns();
+            for row in try!(read_all(found)) {
+                let mut new_row = row.clone();
+                for &(column_index, new_value) in values {
+                    let range = column_range(columns, column_index);
+                    let len = range.len();
+                    new_row[range].copy_from_slice(&new_value[..len]);
+                    types::set_null(columns, &mut new_row, column_index, false);
+                }
+                changes.push((key(columns, &row), Change::Deleted(row)));
+                inserted.push((key(columns, &new_row), Change::Inserted(new_row)));
+            }
+        }
+        changes.extend(inserted);
+        let (database, table) = self.names();
+        try!(self.statement.record(&database, &table, changes));
+        written(
+            self.inner
+                .modify(constraint_column_index, constraint_value, comp, values),
+        )
+    }
+
+    fn reorganize(&mut self) -> Result<(), Error> {
+        self.inner.reorganize()
+    }
+
+    fn reset(&mut self) -> Result<(), Error> {
+        let _writing = self.latch();
+        let count = try!(self.record_deleted(try!(self.inner.full_scan())));
+        try!(self.inner.reset());
+        ROWS_WRITTEN.fetch_add(count, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn rebuild_indexes(&mut self) -> Result<(), Error> {
+        self.inner.rebuild_indexes()
+    }
+}
+
+/// Undoes changes, the newest first, in rows read from a table. Deleted rows
+/// only come back if they fulfill `filter`. The rows are found by their
+/// content; a deleted row takes the place of the row with the same primary
+/// key removed before, so updated rows stay where they were.
+pub fn undo_changes<F>(
+    columns: &[Column],
+    rows: Rows<Cursor<Vec<u8>>>,
+    changes: Vec<Change>,
+    filter: F,
+) -> Result<Rows<Cursor<Vec<u8>>>, Error>
+where
+    F: Fn(&[u8]) -> Result<bool, Error>,
+{
+    if changes.is_empty() {
+        return Ok(rows);
+    }
+    let size = row_size(columns);
+    let mut data: Vec<Option<Vec<u8>>> = try!(read_all(rows)).into_iter().map(Some).collect();
+    let mut places: HashMap<Vec<u8>, Vec<usize>> = HashMap::new();
+    for (i, row) in data.iter().enumerate() {
+        if let Some(ref row) = *row {
+            places.entry(row.clone()).or_insert_with(Vec::new).push(i);
+        }
+    }
+    // places of removed rows by their primary key
+    let mut freed: HashMap<Vec<u8>, Vec<usize>> = HashMap::new();
+    for change in changes {
+        match change {
+            Change::Inserted(row) => {
+                if let Some(i) = places.get_mut(&row).and_then(|p| p.pop()) {
+                    data[i] = None;
+                    freed
+                        .entry(key(columns, &row))
+                        .or_insert_with(Vec::new)
+                        .push(i);
+                }
+            }
+            Change::Deleted(row) => {
+                let present = places.get(&row).map_or(false, |p| !p.is_empty());
+                // older versions may not fit the current columns
+                if row.len() == size && !present && try!(filter(&row)) {
+                    let i = match freed.get_mut(&key(columns, &row)).and_then(|p| p.pop()) {
+                        Some(i) => i,
+                        None => {
+                            data.push(None);
+                            data.len() - 1
+                        }
+                    };
+                    places.entry(row.clone()).or_insert_with(Vec::new).push(i);
+                    data[i] = Some(row);
+                }
+            }
+        }
+    }
+    to_rows(columns, data.into_iter().filter_map(|row| row).collect())
+}
+
+/// Reverts changes of the rows of a table in its files, the newest first.
+/// Changes that are not there (anymore), e.g. because the write failed, are
+/// skipped. Looking up a row of a flat file reads the whole file, so its
+/// rows are reverted in one pass and written to new files, where they keep
+/// their order. Other engines keep the rows in the order of their keys and
+/// revert row by row.
+pub fn revert(database: &str, table: &str, changes: &[Change]) -> Result<(), Error> {
+    let database = try!(Database::load(database));
+    let table = try!(database.load_table(table));
+    let columns = table.columns().to_vec();
+    if table.engine_id() == EngineID::FlatFile && table.connection().is_none() {
+        let rows = try!(try!(database.load_table(&table.name))
+            .create_engine()
+            .full_scan());
+        let reverted = try!(undo_changes(&columns, rows, changes.to_vec(), |_| Ok(true)));
+        return table.replace_rows(&table, reverted, |row| Ok::<_, Error>(row.to_vec()));
+    }
+    let mut engine = table.create_engine();
+    for change in changes {
+        try!(revert_row(&mut *engine, &columns, change));
+    }
+    Ok(())
+}
+
+/// Reverts a single change of a row, through its primary key.
+fn revert_row(engine: &mut dyn Engine, columns: &[Column], change: &Change) -> Result<(), Error> {
+    let row = match change {
+        &Change::Inserted(ref row) | &Change::Deleted(ref row) => row,
+    };
+    if row.len() != row_size(columns) {
+        return Ok(());
+    }
+    let key_column = match columns.iter().position(|c| c.is_primary_key) {
+        Some(i) => i,
+        None => return Err(Error::FoundNoPrimaryKey),
+    };
+    let value = row[column_range(columns, key_column)].to_vec();
+    let same_key = try!(read_all(try!(engine.lookup(
+        key_column,
+        (&value, None),
+        CompType::Equ
+    ))));
+    match change {
+        &Change::Inserted(_) => {
+            if same_key.contains(row) {
+                // rows only sharing the first key column are put back
+                try!(engine.delete(key_column, (&value, None), CompType::Equ));
+                for other in same_key.iter().filter(|r| *r != row) {
+                    try!(engine.insert_row(other));
+                }
+            }
+        }
+        &Change::Deleted(_) => {
+            if !same_key.contains(row) {
+                try!(engine.insert_row(row));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Applies a change of a row of another server to the table, through its
+/// primary key: the row with the key of the changed row is replaced by the
+/// inserted one or removed. Applying a change twice does no harm.
+pub fn replay(engine: &mut dyn Engine, change: &Change) -> Result<(), Error> {
+    let columns = engine.table().columns().to_vec();
+    let (row, inserted) = match change {
+        &Change::Inserted(ref row) => (row, true),
+        &Change::Deleted(ref row) => (row, false),
+    };
+    if row.len() != row_size(&columns) {
+        return Err(Error::WrongLength);
+    }
+    let key_column = match columns.iter().position(|c| c.is_primary_key) {
+        Some(i) => i,
+        None => return Err(Error::FoundNoPrimaryKey),
+    };
+    let value = row[column_range(&columns, key_column)].to_vec();
+    let own_key = key(&columns, row);
+    let same_first = try!(read_all(try!(engine.lookup(
+        key_column,
+        (&value, None),
+        CompType::Equ
+    ))));
+    if same_first.iter().any(|r| key(&columns, r) == own_key) {
+        // rows only sharing the first key column are put back
+        try!(engine.delete(key_column, (&value, None), CompType::Equ));
+        for other in same_first.iter().filter(|r| key(&columns, r) != own_key) {
+            try!(engine.insert_row(other));
+        }
+    }
+    if inserted {
+        try!(engine.insert_row(row));
+    }
+    Ok(())
+}
+
+/// Returns the values of the primary key columns of a row.
+fn key(columns: &[Column], row: &[u8]) -> Vec<u8> {
+    let mut key = Vec::new();
+    for (i, column) in columns.iter().enumerate() {
+        if column.is_primary_key {
+            key.extend_from_slice(&row[column_range(columns, i)]);
+        }
+    }
+    key
+}
+
+fn read_all(mut rows: Rows<Cursor<Vec<u8>>>) -> Result<Vec<Vec<u8>>, Error> {
+    try!(rows.reset_pos());
+    let mut all = Vec::new();
+    loop {
+        let mut row = Vec::new();
+        match rows.next_row(&mut row) {
+            Ok(_) => all.push(row),
+            Err(Error::EndOfFile) => break,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(all)
+}
+
+fn to_rows(columns: &[Column], data: Vec<Vec<u8>>) -> Result<Rows<Cursor<Vec<u8>>>, Error> {
+    let mut rows = Rows::new(Cursor::new(Vec::new()), columns);
+    for row in data {
+        try!(rows.add_row(&row));
+    }
+    Ok(rows)
+}
+
+#[test]
+fn test_undo_changes_in_place() {
+    use super::types::SqlType;
+
+    let columns = vec![
+        Column::new("id", SqlType::Char(1), false, "", true),
+        Column::new("v", SqlType::Char(1), false, "", false),
+    ];
+    let table = |rows: &[&[u8]]| to_rows(&columns, rows.iter().map(|r| r.to_vec()).collect());
+    let rows = table(&[b"1a", b"2x", b"3c", b"5e"]).unwrap();
+    // the newest first: 2 was updated, 4 deleted and 5 inserted
+    let changes = vec![
+        Change::Inserted(b"5e".to_vec()),
+        Change::Deleted(b"4d".to_vec()),
+        Change::Inserted(b"2x".to_vec()),
+        Change::Deleted(b"2b".to_vec()),
+        // changes not there anymore are skipped
+        Change::Inserted(b"6f".to_vec()),
+        Change::Deleted(b"1a".to_vec()),
+    ];
+    let reverted = undo_changes(&columns, rows, changes, |_| Ok(true)).unwrap();
+    assert_eq!(
+        read_all(reverted).unwrap(),
+        vec![
+            b"1a".to_vec(),
+            b"2b".to_vec(),
+            b"3c".to_vec(),
+            b"4d".to_vec()
+        ]
+    );
+
+    // deleted rows only come back if they fulfill the filter
+    let rows = table(&[b"1a"]).unwrap();
+    let changes = vec![Change::Deleted(b"2b".to_vec())];
+    let reverted = undo_changes(&columns, rows, changes, |row| Ok(row[1] != b'b')).unwrap();
+    assert_eq!(read_all(reverted).unwrap(), vec![b"1a".to_vec()]);
+}
@@ -0,0 +1,193 @@
+//! Patterns of `LIKE` and `REGEXP`
+//!
+//! In a `LIKE` pattern `%` stands for any number of characters and `_` for
+//! exactly one. A backslash takes away the special meaning of the character
+//! after it; the parser rewrites patterns with another `ESCAPE` character
+//! to use a backslash. The pattern has to match the whole text.
+//!
+//! `REGEXP` patterns are regular expressions in the syntax of the `regex`
+//! crate, they match if they match any part of the text. Both kinds of
+//! patterns are case sensitive.
+
+use super::Error;
+use regex::Regex;
+
+use std::cell::RefCell;
+
+thread_local! {
+    /// The regular expression compiled last, the rows of a statement are all
+    /// compared with the same one
+    static LAST_REGEX: RefCell<Option<Regex>> = RefCell::new(None);
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Part {
+    /// `%`
+    Many,
+    /// `_`
+    One,
+    Char(char),
+}
+
+fn parts(pattern: &str) -> Vec<Part> {
+    let mut parts = Vec::new();
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        parts.push(match c {
+            '%' => Part::Many,
+            '_' => Part::One,
+            // a backslash at the end stands for itself
+            '\\' => Part::Char(chars.next().unwrap_or('\\')),
+            c => Part::Char(c),
+        });
+    }
+    parts
+}
+
+/// Rewrites a `LIKE` pattern with the escape character `escape` to escape
+/// with a backslash.
+pub fn escape(pattern: &str, escape: char) -> String {
+    let mut result = String::new();
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        if c == escape {
+            result.push('\\');
+            result.push(chars.next().unwrap_or(c));
+        } else if c == '\\' {
+            result.push_str("\\\\");
+        } else {
+            result.push(c);
+        }
+    }
+    result
+}
+
+/// Returns whether a text matches a `LIKE` pattern.
+pub fn like(text: &str, pattern: &str) -> bool {
+    let text: Vec<char> = text.chars().collect();
+    let parts = parts(pattern);
+    let (mut t, mut p) = (0, 0);
+    // the part after the last `%` and the character it was tried at, a
+    // mismatch tries it one character later
+    let mut retry = None;
+    while t < text.len() {
+        match parts.get(p) {
+            Some(&Part::Many) => {
+                p += 1;
+                retry = Some((p, t));
+                continue;
+            }
+            Some(&Part::One) => {
+                p += 1;
+                t += 1;
+                continue;
+            }
+            Some(&Part::Char(c)) if c == text[t] => {
+                p += 1;
+                t += 1;
+                continue;
+            }
+            _ => {}
+        }
+        match retry {
+            Some((after, start)) => {
+                retry = Some((after, start + 1));
+                p = after;
+                t = start + 1;
+            }
+            None => return false,
+        }
+    }
+    parts[p..].iter().all(|&part| part == Part::Many)
+}
+
+/// Returns whether a regular expression matches a part of a text. Fails
+/// with `Error::InvalidPattern` if the pattern is no regular expression.
+pub fn regexp(text: &str, pattern: &str) -> Result<bool, Error> {
+    LAST_REGEX.with(|last| {
+        let mut last = last.borrow_mut();
+        let compiled = match *last {
+            Some(ref regex) => regex.as_str() == pattern,
+            None => false,
+        };
+        if !compiled {
+            let regex = try!(Regex::new(pattern).map_err(|e| Error::InvalidPattern(e.to_string())));
+            *last = Some(regex);
+        }
+        Ok(last.as_ref().map_or(false, |regex| regex.is_match(text)))
+    })
+}
+
+/// Returns the words, as split by `types::tokenize`, that every text
+/// matching a `LIKE` pattern contains. A word is paired with `false` if the
+/// text may continue it, then it is only the beginning of a word of the
+/// text. Words the text may have more characters in front of are left out.
+pub fn words(pattern: &str) -> Vec<(String, bool)> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    // whether the text has no more characters in front of the word
+    let mut starts = true;
+    for part in parts(pattern) {
+        match part {
+            Part::Char(c) if c.is_alphanumeric() => {
+                word.push(c);
+                continue;
+            }
+            Part::Char(_) => {
+                if starts && !word.is_empty() {
+                    words.push((word.to_lowercase(), true));
+                }
+                starts = true;
+            }
+            Part::Many | Part::One => {
+                if starts && !word.is_empty() {
+                    words.push((word.to_lowercase(), false));
+                }
+                starts = false;
+            }
+        }
+        word.clear();
+    }
+    if starts && !word.is_empty() {
+        words.push((word.to_lowercase(), true));
+    }
+    words
+}
+
+#[test]
+fn test_like() {
+    assert!(like("foobar", "foo%"));
+    assert!(like("foo", "foo%"));
+    assert!(!like("afoo", "foo%"));
+    assert!(like("abc", "a_c"));
+    assert!(!like("abbc", "a_c"));
+    assert!(like("abcabd", "%ab_"));
+    assert!(like("", "%"));
+    assert!(!like("", "_"));
+    assert!(like("100%", "100\\%"));
+    assert!(!like("1000", "100\\%"));
+    assert!(like("a_b", "a\\_b"));
+    assert!(!like("Foo", "foo"));
+
+    assert_eq!(escape("10!%\\", '!'), "10\\%\\\\");
+    assert!(like("a\\b%", &escape("a\\b!%", '!')));
+}
+
+#[test]
+fn test_regexp() {
+    assert!(regexp("foobar", "o+b").unwrap());
+    assert!(!regexp("foobar", "^bar").unwrap());
+    assert!(regexp("bar", "^bar").unwrap());
+    assert!(regexp("x", "(").is_err());
+}
+
+#[test]
+fn test_words() {
+    assert_eq!(
+        words("Foo bar%"),
+        vec![("foo".to_string(), true), ("bar".to_string(), false)]
+    );
+    // the text may have more characters in front of "oo"
+    assert_eq!(words("%oo bar_"), vec![("bar".to_string(), false)]);
+    assert_eq!(words("%"), Vec::new());
+}
@@ -0,0 +1,265 @@
+//! Consistency check of all tables at startup
+//!
+//! A crash may leave half-written files behind. Before the server accepts
+//! connections, `check_all` looks at every table of every database:
+//!
+//! - A table whose meta data cannot be read, e.g. because of a wrong magic
+//!   number or a file cut short, is quarantined: its files are moved to
+//!   `.quarantine/<time>/<database>/` in the data directory, where they can
+//!   be looked at or put back by hand. The rest of the database keeps
+//!   working.
+//! - A table whose data file has damaged rows (see `repair::check`) is
+//!   repaired, keeping its intact rows. The damaged data file is copied to
+//!   the quarantine first. There is no log to replay, so the damaged rows
+//!   are lost to the table.
+//! - A table whose data file cannot be read at all is quarantined.
+//! - A rewrite of a table (see `Table::replace_rows`) that was interrupted
+//!   is finished if all new files had replaced the old ones already but
+//!   the meta data, otherwise the new files are quarantined and the table
+//!   keeps its old ones.
+//!
+//! Every finding is logged and kept for `SHOW RECOVERY`.
+
+use super::meta::{Database, REWRITE_SUFFIX};
+use super::repair;
+use super::Error;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Directory of the data directory holding quarantined files, hidden from
+/// `Database::list`
+const QUARANTINE: &'static str = ".quarantine";
+
+/// The findings of the last check
+static FINDINGS: Mutex<Vec<Finding>> = Mutex::new(Vec::new());
+
+/// What was done about a damaged table
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    /// The intact rows were kept, the damaged data file was copied to the
+    /// quarantine
+    Repaired,
+    /// The files were moved to the quarantine
+    Quarantined,
+    /// An interrupted rewrite was finished
+    Completed,
+    /// Nothing could be done
+    None,
+}
+
+impl Action {
+    pub fn name(&self) -> &'static str {
+        match self {
+            &Action::Repaired => "repaired",
+            &Action::Quarantined => "quarantined",
+            &Action::Completed => "completed",
+            &Action::None => "none",
+        }
+    }
+}
+
+/// A damaged table and what was done about it
+#[derive(Clone, Debug)]
+pub struct Finding {
+    pub database: String,
+    pub table: String,
+    /// What is wrong with the table
+    pub problem: String,
+    pub action: Action,
+    /// The outcome of the action
+    pub detail: String,
+}
+
+/// Checks all tables of all databases, repairs or quarantines the damaged
+/// ones and returns the findings. Runs before the server accepts
+/// connections.
+pub fn check_all() -> Vec<Finding> {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let quarantine = Path::new(QUARANTINE).join(secs.to_string());
+    let mut findings = Vec::new();
+    let mut checked = 0;
+    let names = match Database::list() {
+        Ok(names) => names,
+        Err(e) => {
+            error!("Cannot list the databases to check them: {:?}", e);
+            Vec::new()
+        }
+    };
+    for name in names {
+        let base = match Database::load(&name) {
+            Ok(base) => base,
+            Err(_) => continue,
+        };
+        let tables = match base.list_tables() {
+            Ok(tables) => tables,
+            Err(e) => {
+                error!("Cannot list the tables of database {:?}: {:?}", name, e);
+                continue;
+            }
+        };
+        for table in tables {
+            checked += 1;
+            if let Some(finding) = check_table(&base, &table, &quarantine) {
+                warn!(
+                    "Table {}.{}: {}, {}: {}",
+                    finding.database,
+                    finding.table,
+                    finding.problem,
+                    finding.action.name(),
+                    finding.detail
+                );
+                findings.push(finding);
+            }
+        }
+    }
+    info!(
+        "Checked {} tables at startup, {} damaged",
+        checked,
+        findings.len()
+    );
+    *FINDINGS.lock().unwrap() = findings.clone();
+    findings
+}
+
+/// Returns the findings of the last check.
+pub fn findings() -> Vec<Finding> {
+    FINDINGS.lock().unwrap().clone()
+}
+
+/// Checks a table and returns what was wrong with it, if anything.
+fn check_table(base: &Database, name: &str, quarantine: &Path) -> Option<Finding> {
+    let finding = |problem: String, action: Action, detail: String| {
+        Some(Finding {
+            database: base.name.clone(),
+            table: name.to_string(),
+            problem: problem,
+            action: action,
+            detail: detail,
+        })
+    };
+    if name.ends_with(REWRITE_SUFFIX) {
+        return finish_rewrite(base, name, quarantine);
+    }
+    let table = match base.load_table(name) {
+        Ok(table) => table,
+        Err(e) => {
+            let problem = format!("cannot read its meta data: {:?}", e);
+            return match quarantine_files(base, name, quarantine) {
+                Ok(dir) => finding(
+                    problem,
+                    Action::Quarantined,
+                    format!("files moved to {}", dir.display()),
+                ),
+                Err(e) => finding(problem, Action::None, format!("cannot move files: {:?}", e)),
+            };
+        }
+    };
+    match repair::check(&table) {
+        Ok(ref damages) if damages.is_empty() => None,
+        Ok(damages) => {
+            let problem = format!("{} damaged rows", damages.len());
+            let copied = copy_to_quarantine(base, &table.get_table_data_path(), quarantine);
+            match copied.and_then(|dir| repair::repair(table).map(|report| (dir, report))) {
+                Ok((dir, report)) => finding(
+                    problem,
+                    Action::Repaired,
+                    format!(
+                        "{} rows kept, {} left out, damaged data file copied to {}",
+                        report.rows,
+                        report.lost,
+                        dir.display()
+                    ),
+                ),
+                Err(e) => finding(problem, Action::None, format!("cannot repair: {:?}", e)),
+            }
+        }
+        Err(Error::NoEncryptionKey) => finding(
+            "cannot be checked without the encryption key".into(),
+            Action::None,
+            String::new(),
+        ),
+        Err(e) => {
+            let problem = format!("cannot read its rows: {:?}", e);
+            match quarantine_files(base, name, quarantine) {
+                Ok(dir) => finding(
+                    problem,
+                    Action::Quarantined,
+                    format!("files moved to {}", dir.display()),
+                ),
+                Err(e) => finding(problem, Action::None, format!("cannot move files: {:?}", e)),
+            }
+        }
+    }
+}
+
+/// Finishes or undoes the interrupted rewrite into the temporary table.
+/// The meta data is renamed last, so the rewrite got that far if it is the
+/// only file left and the data file of the table is not older than it.
+fn finish_rewrite(base: &Database, temp: &str, quarantine: &Path) -> Option<Finding> {
+    let name = &temp[..temp.len() - REWRITE_SUFFIX.len()];
+    let problem = "a rewrite of the table was interrupted".to_string();
+    let finding = |action: Action, detail: String| {
+        Some(Finding {
+            database: base.name.clone(),
+            table: name.to_string(),
+            problem: problem.clone(),
+            action: action,
+            detail: detail,
+        })
+    };
+    let path = |table: &str, ext: &str| Path::new(&base.name).join(format!("{}.{}", table, ext));
+    let modified = |path: &Path| fs::metadata(path).and_then(|m| m.modified()).ok();
+    let files = base.table_files(temp).unwrap_or_default();
+    let renamed = files.len() == 1
+        && match (modified(&path(name, "dat")), modified(&path(temp, "tbl"))) {
+            (Some(data), Some(meta)) => data >= meta,
+            _ => false,
+        };
+    if renamed {
+        return match fs::rename(path(temp, "tbl"), path(name, "tbl")) {
+            Ok(()) => finding(
+                Action::Completed,
+                "the new meta data was put in place".into(),
+            ),
+            Err(e) => finding(Action::None, format!("cannot rename meta data: {}", e)),
+        };
+    }
+    match quarantine_files(base, temp, quarantine) {
+        Ok(dir) => finding(
+            Action::Quarantined,
+            format!(
+                "the table kept its old files, the new ones were moved to {}",
+                dir.display()
+            ),
+        ),
+        Err(e) => finding(Action::None, format!("cannot move files: {:?}", e)),
+    }
+}
+
+/// Moves all files of a table to the quarantine, returns where they are.
+fn quarantine_files(base: &Database, name: &str, quarantine: &Path) -> Result<PathBuf, Error> {
+    let dir = quarantine.join(&base.name);
+    try!(fs::create_dir_all(&dir));
+    for path in try!(base.table_files(name)) {
+        if let Some(file_name) = path.file_name() {
+            try!(fs::rename(&path, dir.join(file_name)));
+        }
+    }
+    Ok(dir)
+}
+
+/// Copies a file of a database to the quarantine, returns where it is.
+fn copy_to_quarantine(base: &Database, path: &str, quarantine: &Path) -> Result<PathBuf, Error> {
+    let dir = quarantine.join(&base.name);
+    try!(fs::create_dir_all(&dir));
+    if let Some(file_name) = Path::new(path).file_name() {
+        try!(fs::copy(path, dir.join(file_name)));
+    }
+    Ok(dir)
+}
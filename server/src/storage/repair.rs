@@ -0,0 +1,41 @@
+//! Finding and repairing damaged tables
+//!
+//! `check` reads the data file of a table and reports its damaged rows:
+//! rows whose data does not match their checksum, which only the rows of
+//! flat file tables have, rows whose header is broken and a row the file
+//! ends within. `repair` writes the intact rows into fresh files of the
+//! table, which also gives rows of older flat file tables a checksum.
+
+use super::data::Damage;
+use super::meta::Table;
+use super::transaction;
+use super::Error;
+
+/// Outcome of repairing a table
+#[derive(Debug)]
+pub struct Report {
+    /// Rows of the table which were kept
+    pub rows: u64,
+    /// Damaged rows which were left out
+    pub lost: u64,
+}
+
+/// Returns the damaged rows of a table. Writes to tables wait meanwhile.
+pub fn check(table: &Table) -> Result<Vec<Damage>, Error> {
+    // a row being written would look cut off
+    let _writing = transaction::lock_writes();
+    try!(table.open_rows()).check()
+}
+
+/// Replaces the files of a table by files holding its intact rows. Writes
+/// to tables wait meanwhile.
+pub fn repair(table: Table) -> Result<Report, Error> {
+    let _writing = transaction::lock_writes();
+    let (mut rows, lost) = try!(try!(table.open_rows()).salvage());
+    let (kept, _) = try!(rows.count_rows());
+    try!(table.replace_rows(&table, rows, |row| Ok::<_, Error>(row.to_vec())));
+    Ok(Report {
+        rows: kept,
+        lost: lost,
+    })
+}
@@ -0,0 +1,202 @@
+//! Statistics of the rows of tables
+//!
+//! `ANALYZE` reads all rows of a table and keeps, for every column, the
+//! number of nulls and of distinct values, the smallest and the largest
+//! value and an equi-depth histogram: the upper bounds of up to `BUCKETS`
+//! buckets holding about the same number of values. The statistics are
+//! stored in the `db.meta` file of the database and are only as recent as
+//! the last `ANALYZE` of the table. They estimate the share of the rows a
+//! condition selects, see `TableStats::selectivity`.
+
+use super::data::Rows;
+use super::types::SqlType;
+use super::{CompType, Error};
+
+use serde::{Deserialize, Serialize};
+
+use std::io::Cursor;
+
+/// Maximum number of buckets of a histogram
+pub const BUCKETS: usize = 16;
+
+/// Statistics of a table
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TableStats {
+    pub table: String,
+    pub rows: u64,
+    /// When the statistics were gathered, see `temporal::now`
+    pub analyzed: i64,
+    pub columns: Vec<ColumnStats>,
+}
+
+/// Statistics of a column. Values are encoded like in rows, BLOB columns
+/// only count their nulls.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ColumnStats {
+    pub name: String,
+    pub nulls: u64,
+    pub distinct: u64,
+    pub min: Option<Vec<u8>>,
+    pub max: Option<Vec<u8>>,
+    /// upper bounds of the buckets, in ascending order
+    pub histogram: Vec<Vec<u8>>,
+}
+
+/// Gathers the statistics of the rows of a table.
+pub fn collect(
+    table: &str,
+    rows: &mut Rows<Cursor<Vec<u8>>>,
+    analyzed: i64,
+) -> Result<TableStats, Error> {
+    let columns = rows.columns.clone();
+    let mut nulls = vec![0; columns.len()];
+    // values of every column with their sort keys
+    let mut values: Vec<Vec<(Vec<u8>, Vec<u8>)>> = vec![Vec::new(); columns.len()];
+    let mut count = 0;
+    try!(rows.reset_pos());
+    let mut row = Vec::new();
+    loop {
+        row.clear();
+        match rows.next_row(&mut row) {
+            Ok(_) => (),
+            Err(Error::EndOfFile) => break,
+            Err(e) => return Err(e),
+        }
+        count += 1;
+        for (i, column) in columns.iter().enumerate() {
+            match try!(rows.get_nullable_value(&row, i)) {
+                None => nulls[i] += 1,
+                Some(_) if column.sql_type == SqlType::Blob => (),
+                Some(value) => values[i].push((column.sql_type.sort_key(&value), value)),
+            }
+        }
+    }
+
+    let mut stats = Vec::with_capacity(columns.len());
+    for ((column, nulls), mut values) in columns.iter().zip(nulls).zip(values) {
+        values.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut distinct = 0;
+        for i in 0..values.len() {
+            if i == 0 || values[i].0 != values[i - 1].0 {
+                distinct += 1;
+            }
+        }
+        let buckets = values.len().min(BUCKETS);
+        let histogram = (1..buckets + 1)
+            .map(|b| values[values.len() * b / buckets - 1].1.clone())
+            .collect();
+        stats.push(ColumnStats {
+            name: column.name.clone(),
+            nulls: nulls,
+            distinct: distinct,
+            min: values.first().map(|v| v.1.clone()),
+            max: values.last().map(|v| v.1.clone()),
+            histogram: histogram,
+        });
+    }
+    Ok(TableStats {
+        table: table.to_string(),
+        rows: count,
+        analyzed: analyzed,
+        columns: stats,
+    })
+}
+
+impl TableStats {
+    /// Estimates the share of the rows whose value of a column compares to
+    /// an encoded value like `op` asks for. Returns None if the column has
+    /// no statistics or the comparison cannot be estimated.
+    pub fn selectivity(
+        &self,
+        column: &str,
+        sql_type: SqlType,
+        op: CompType,
+        value: &[u8],
+    ) -> Option<f64> {
+        let stats = match self.columns.iter().find(|c| c.name == column) {
+            Some(stats) => stats,
+            None => return None,
+        };
+        if self.rows == 0 {
+            return Some(0.0);
+        }
+        let rows = self.rows as f64;
+        let nulls = stats.nulls as f64 / rows;
+        let (min, max) = match (&stats.min, &stats.max) {
+            (&Some(ref min), &Some(ref max)) => (sql_type.sort_key(min), sql_type.sort_key(max)),
+            _ => return Some(0.0),
+        };
+        let key = sql_type.sort_key(value);
+        let equal = if key < min || key > max {
+            0.0
+        } else {
+            (1.0 - nulls) / stats.distinct as f64
+        };
+        // share of the rows with a value below the value, counted in
+        // buckets
+        let buckets = stats
+            .histogram
+            .iter()
+            .filter(|bound| sql_type.sort_key(bound) < key)
+            .count();
+        let below = (1.0 - nulls) * buckets as f64 / stats.histogram.len() as f64;
+        match op {
+            CompType::Equ => Some(equal),
+            CompType::NEqu => Some(1.0 - nulls - equal),
+            CompType::SThan => Some(below),
+            CompType::SEThan => Some(below + equal),
+            CompType::GThan => Some(1.0 - nulls - below - equal),
+            CompType::GEThan => Some(1.0 - nulls - below),
+            CompType::IsNull => Some(nulls),
+            CompType::IsNotNull => Some(1.0 - nulls),
+            CompType::Match | CompType::NMatch => None,
+            CompType::Like | CompType::NLike | CompType::Regexp | CompType::NRegexp => None,
+        }
+        .map(|share| share.max(0.0).min(1.0))
+    }
+}
+
+#[test]
+fn test_collect_and_selectivity() {
+    use super::super::parse::token::Lit;
+    use super::types::Column;
+
+    let columns = vec![
+        Column::new("id", SqlType::Int, false, "", true),
+        Column::new("tag", SqlType::Int, true, "", false),
+    ];
+    let mut rows = Rows::new(Cursor::new(Vec::<u8>::new()), &columns);
+    for i in 0..100i32 {
+        let mut id = Vec::new();
+        SqlType::Int
+            .encode_into(&mut id, &Lit::Int(i as i64))
+            .unwrap();
+        let mut tag = Vec::new();
+        SqlType::Int
+            .encode_into(&mut tag, &Lit::Int((i % 4) as i64))
+            .unwrap();
+        let tag = if i % 10 == 0 { None } else { Some(tag) };
+        rows.add_values(&[Some(id), tag]).unwrap();
+    }
+
+    let stats = collect("t", &mut rows, 0).unwrap();
+    assert_eq!(stats.rows, 100);
+    assert_eq!(stats.columns[0].distinct, 100);
+    assert_eq!(stats.columns[0].histogram.len(), BUCKETS);
+    assert_eq!(stats.columns[1].nulls, 10);
+    assert_eq!(stats.columns[1].distinct, 4);
+
+    let mut value = Vec::new();
+    SqlType::Int.encode_into(&mut value, &Lit::Int(25)).unwrap();
+    let equal = stats.selectivity("id", SqlType::Int, CompType::Equ, &value);
+    assert_eq!(equal, Some(0.01));
+    let below = stats
+        .selectivity("id", SqlType::Int, CompType::SThan, &value)
+        .unwrap();
+    assert!(below > 0.15 && below < 0.35);
+    let null = stats.selectivity("tag", SqlType::Int, CompType::IsNull, &value);
+    assert_eq!(null, Some(0.1));
+    assert!(stats
+        .selectivity("nope", SqlType::Int, CompType::Equ, &value)
+        .is_none());
+}
@@ -0,0 +1,223 @@
+//! Dates and times
+//!
+//! A DATE is stored as the number of days since 1970-01-01, a TIME as the
+//! number of microseconds since midnight and a TIMESTAMP as the number of
+//! microseconds since 1970-01-01 00:00:00 UTC. All of them use the
+//! proleptic Gregorian calendar and know no time zones.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const MICROS_PER_SECOND: i64 = 1_000_000;
+pub const MICROS_PER_DAY: i64 = 86_400 * MICROS_PER_SECOND;
+
+/// Returns the current time as a timestamp.
+pub fn now() -> i64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs() as i64 * MICROS_PER_SECOND + d.subsec_micros() as i64,
+        Err(e) => -(e.duration().as_secs() as i64 * MICROS_PER_SECOND),
+    }
+}
+
+/// Splits a timestamp into the date and the time of the day.
+pub fn split_timestamp(timestamp: i64) -> (i32, i64) {
+    let days = div_floor(timestamp, MICROS_PER_DAY);
+    (days as i32, timestamp - days * MICROS_PER_DAY)
+}
+
+/// Returns the timestamp of midnight of a date.
+pub fn date_to_timestamp(date: i32) -> i64 {
+    date as i64 * MICROS_PER_DAY
+}
+
+/// Parses a date of the form `YYYY-MM-DD`.
+pub fn parse_date(s: &str) -> Option<i32> {
+    let parts: Vec<&str> = s.trim().split('-').collect();
+    if parts.len() != 3 {
+        return None;
+    }
+    let year = match parts[0].parse::<i32>() {
+        Ok(y) => y,
+        Err(_) => return None,
+    };
+    let month = match parts[1].parse::<u32>() {
+        Ok(m) if m >= 1 && m <= 12 => m,
+        _ => return None,
+    };
+    let day = match parts[2].parse::<u32>() {
+        Ok(d) if d >= 1 && d <= days_in_month(year, month) => d,
+        _ => return None,
+    };
+    Some(days_from_civil(year, month, day))
+}
+
+/// Parses a time of the form `HH:MM[:SS[.ffffff]]`.
+pub fn parse_time(s: &str) -> Option<i64> {
+    let parts: Vec<&str> = s.trim().split(':').collect();
+    if parts.len() < 2 || parts.len() > 3 {
+        return None;
+    }
+    let hours = match parts[0].parse::<i64>() {
+        Ok(h) if h >= 0 && h < 24 => h,
+        _ => return None,
+    };
+    let minutes = match parts[1].parse::<i64>() {
+        Ok(m) if m >= 0 && m < 60 => m,
+        _ => return None,
+    };
+    let micros = if parts.len() == 3 {
+        match parse_seconds(parts[2]) {
+            Some(micros) => micros,
+            None => return None,
+        }
+    } else {
+        0
+    };
+    Some((hours * 60 + minutes) * 60 * MICROS_PER_SECOND + micros)
+}
+
+/// Parses a timestamp of the form `YYYY-MM-DD[ HH:MM[:SS[.ffffff]]]`, the
+/// date and the time may also be separated by `T`.
+pub fn parse_timestamp(s: &str) -> Option<i64> {
+    let s = s.trim();
+    let (date, time) = match s.find(|c| c == ' ' || c == 'T') {
+        Some(pos) => (&s[..pos], Some(&s[pos + 1..])),
+        None => (s, None),
+    };
+    let date = match parse_date(date) {
+        Some(date) => date,
+        None => return None,
+    };
+    let time = match time.map(parse_time) {
+        Some(Some(time)) => time,
+        Some(None) => return None,
+        None => 0,
+    };
+    Some(date_to_timestamp(date) + time)
+}
+
+/// Formats a date as `YYYY-MM-DD`.
+pub fn format_date(date: i32) -> String {
+    let (year, month, day) = civil_from_days(date);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Formats a time as `HH:MM:SS`, followed by the fraction of the second if
+/// there is one.
+pub fn format_time(time: i64) -> String {
+    let seconds = time / MICROS_PER_SECOND;
+    let fraction = time % MICROS_PER_SECOND;
+    let mut s = format!(
+        "{:02}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    );
+    if fraction != 0 {
+        s.push_str(&format!(".{:06}", fraction));
+    }
+    s
+}
+
+/// Formats a timestamp as `YYYY-MM-DD HH:MM:SS[.ffffff]`.
+pub fn format_timestamp(timestamp: i64) -> String {
+    let (date, time) = split_timestamp(timestamp);
+    format!("{} {}", format_date(date), format_time(time))
+}
+
+/// Parses seconds with an optional fraction into microseconds.
+fn parse_seconds(s: &str) -> Option<i64> {
+    let (whole, fraction) = match s.find('.') {
+        Some(pos) => (&s[..pos], &s[pos + 1..]),
+        None => (s, ""),
+    };
+    let seconds = match whole.parse::<i64>() {
+        Ok(sec) if sec >= 0 && sec < 60 => sec,
+        _ => return None,
+    };
+    if fraction.len() > 6 || !fraction.chars().all(|c| c.is_digit(10)) {
+        return None;
+    }
+    let mut micros = 0;
+    for (i, c) in fraction.chars().enumerate() {
+        micros += c.to_digit(10).unwrap_or(0) as i64 * 10i64.pow(5 - i as u32);
+    }
+    Some(seconds * MICROS_PER_SECOND + micros)
+}
+
+fn is_leap_year(year: i32) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+fn days_in_month(year: i32, month: u32) -> u32 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+fn div_floor(a: i64, b: i64) -> i64 {
+    let d = a / b;
+    if a % b < 0 {
+        d - 1
+    } else {
+        d
+    }
+}
+
+/// Returns the number of days since 1970-01-01 of a date.
+fn days_from_civil(year: i32, month: u32, day: u32) -> i32 {
+    // shift the year to start in March, so that the leap day comes last
+    let year = if month <= 2 { year - 1 } else { year } as i64;
+    let era = div_floor(year, 400);
+    let year_of_era = year - era * 400;
+    let month = month as i64;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    (era * 146_097 + day_of_era - 719_468) as i32
+}
+
+/// Returns year, month and day of a number of days since 1970-01-01.
+fn civil_from_days(days: i32) -> (i32, u32, u32) {
+    let days = days as i64 + 719_468;
+    let era = div_floor(days, 146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year as i32, month, day)
+}
+
+#[test]
+fn test_dates() {
+    assert_eq!(parse_date("1970-01-01"), Some(0));
+    assert_eq!(parse_date("1969-12-31"), Some(-1));
+    assert_eq!(parse_date("2024-03-01"), Some(19_783));
+    assert_eq!(parse_date("2023-02-29"), None);
+    assert_eq!(parse_date("2024-13-01"), None);
+    for &days in &[-25_000, -1, 0, 59, 19_783, 2_932_896] {
+        assert_eq!(parse_date(&format_date(days)), Some(days));
+    }
+}
+
+#[test]
+fn test_times() {
+    assert_eq!(parse_time("00:00"), Some(0));
+    assert_eq!(parse_time("12:30:15.5"), Some(45_015_500_000));
+    assert_eq!(parse_time("24:00:00"), None);
+    assert_eq!(format_time(45_015_500_000), "12:30:15.500000");
+    assert_eq!(
+        parse_timestamp("1969-12-31 23:59:59"),
+        Some(-MICROS_PER_SECOND)
+    );
+    assert_eq!(format_timestamp(-MICROS_PER_SECOND), "1969-12-31 23:59:59");
+    assert_eq!(
+        parse_timestamp("2024-01-01T00:00"),
+        parse_timestamp("2024-01-01")
+    );
+}
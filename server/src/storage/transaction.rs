@@ -0,0 +1,672 @@
+//! Transactions and the versions of rows they leave behind
+//!
+//! Every statement runs inside of a transaction. Outside of `BEGIN` and
+//! `COMMIT` a statement gets a transaction of its own, which is committed
+//! when the statement succeeds and rolled back otherwise (autocommit).
+//! Transaction ids are handed out in ascending order. Statements lock the
+//! tables they use for their transaction, see `lock`.
+//!
+//! Writers change the tables in place, but record every row they insert or
+//! delete in the undo log of their transaction beforehand. A statement takes
+//! a snapshot when it starts: rows read through `mvcc::Versioned` are those
+//! of the snapshot, because the changes of all transactions which had not
+//! ended at that point are undone on the fly. Readers never wait for
+//! writers of rows. The undo log of an ended transaction is kept until every
+//! snapshot sees its changes, a background thread throws it away then
+//! (`cleanup`).
+//!
+//! A row written by a transaction is locked until the transaction ends, rows
+//! are identified by their primary key. Writing it from another transaction
+//! fails with `Error::RowLocked`. `ROLLBACK` reverts the recorded changes
+//! through the storage engines.
+//!
+//! Changes of the schema are not versioned. Before a table is created,
+//! altered, indexed or dropped inside of `BEGIN`, all files of the table are
+//! copied into an undo directory of the database, `ROLLBACK` copies them
+//! back. Such a table is locked exclusively until the transaction ends.
+//!
+//! The replication learns of every transaction that ends through `on_end`,
+//! while the transaction still holds its locks.
+
+use super::lock::{self, Mode};
+use super::mvcc;
+use super::Error;
+use std::fs;
+use std::sync::{Mutex, RwLockWriteGuard};
+use std::thread;
+use std::time::Duration;
+
+/// How often the undo logs of ended transactions are checked
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Called whenever a transaction ends, see `on_end`
+static ON_END: Mutex<Option<fn(u64, bool, &[(&str, &str, &Change)])>> = Mutex::new(None);
+
+static MANAGER: Mutex<Manager> = Mutex::new(Manager {
+    next_xid: 1,
+    next_seq: 1,
+    next_snapshot: 1,
+    transactions: Vec::new(),
+    snapshots: Vec::new(),
+});
+
+/// State shared by all connections
+struct Manager {
+    next_xid: u64,
+    /// Orders the recorded changes of all transactions
+    next_seq: u64,
+    next_snapshot: u64,
+    /// Running transactions and ended ones whose changes are still needed
+    transactions: Vec<Transaction>,
+    /// Snapshots of the running statements
+    snapshots: Vec<(u64, Visibility)>,
+}
+
+impl Manager {
+    fn begin(&mut self, connection: u64, explicit: bool) -> u64 {
+        let xid = self.next_xid;
+        self.next_xid += 1;
+        self.transactions.push(Transaction {
+            xid: xid,
+            connection: connection,
+            explicit: explicit,
+            running: true,
+            undo_log: Vec::new(),
+        });
+        xid
+    }
+
+    /// Returns the running transaction of a connection.
+    fn running(&mut self, connection: u64) -> Option<&mut Transaction> {
+        self.transactions
+            .iter_mut()
+            .find(|t| t.running && t.connection == connection)
+    }
+
+    /// Returns the id of the transaction started by `BEGIN` on a connection.
+    fn explicit(&mut self, connection: u64) -> Result<u64, Error> {
+        match self.running(connection) {
+            Some(ref t) if t.explicit => Ok(t.xid),
+            _ => Err(Error::NoTransaction),
+        }
+    }
+}
+
+/// A row inserted or deleted by a transaction
+#[derive(Clone, Debug, PartialEq)]
+pub enum Change {
+    Inserted(Vec<u8>),
+    Deleted(Vec<u8>),
+}
+
+#[derive(Clone)]
+struct RowChange {
+    seq: u64,
+    database: String,
+    table: String,
+    /// Values of the primary key columns, identify the locked row
+    key: Vec<u8>,
+    change: Change,
+}
+
+/// The files of a table as they were before the transaction changed its
+/// schema
+#[derive(Clone)]
+struct Saved {
+    database: String,
+    table: String,
+    files: Vec<String>,
+}
+
+#[derive(Clone)]
+enum Undo {
+    Row(RowChange),
+    Files(Saved),
+}
+
+impl Undo {
+    fn touches(&self, database: &str, table: &str) -> bool {
+        match self {
+            &Undo::Row(ref r) => r.database == database && r.table == table,
+            &Undo::Files(ref s) => s.database == database && s.table == table,
+        }
+    }
+}
+
+struct Transaction {
+    xid: u64,
+    connection: u64,
+    /// Started by `BEGIN` and not just for a single statement
+    explicit: bool,
+    running: bool,
+    undo_log: Vec<Undo>,
+}
+
+/// Which transactions a snapshot sees
+#[derive(Clone, Debug)]
+struct Visibility {
+    xid: u64,
+    next_xid: u64,
+    running: Vec<u64>,
+}
+
+impl Visibility {
+    /// Returns true if the changes of a transaction are part of the tables
+    /// as seen by the snapshot: its own ones and those of transactions that
+    /// ended before the snapshot was taken.
+    fn sees(&self, xid: u64) -> bool {
+        xid == self.xid || (xid < self.next_xid && !self.running.contains(&xid))
+    }
+}
+
+/// A statement and the transaction it runs in.
+///
+/// Holds the snapshot taken when the statement started. A statement that is
+/// dropped without `end` counts as failed.
+pub struct Statement {
+    snapshot: u64,
+    visibility: Visibility,
+    /// The transaction was started for this statement only
+    autocommit: bool,
+    ended: bool,
+}
+
+/// Starts a statement, in the open transaction of the connection if there is
+/// one, in a new transaction otherwise.
+pub fn start_statement(connection: u64) -> Statement {
+    let mut manager = MANAGER.lock().unwrap();
+    let (xid, autocommit) = match manager.running(connection) {
+        Some(t) => (t.xid, false),
+        None => (manager.begin(connection, false), true),
+    };
+    let running = manager
+        .transactions
+        .iter()
+        .filter(|t| t.running && t.xid != xid)
+        .map(|t| t.xid)
+        .collect();
+    let visibility = Visibility {
+        xid: xid,
+        next_xid: manager.next_xid,
+        running: running,
+    };
+    let snapshot = manager.next_snapshot;
+    manager.next_snapshot += 1;
+    manager.snapshots.push((snapshot, visibility.clone()));
+    Statement {
+        snapshot: snapshot,
+        visibility: visibility,
+        autocommit: autocommit,
+        ended: false,
+    }
+}
+
+impl Statement {
+    /// Ends the statement. Its own transaction is committed if the statement
+    /// succeeded and rolled back otherwise, unless `BEGIN` took it over.
+    pub fn end(&mut self, success: bool) -> Result<(), Error> {
+        if self.ended {
+            return Ok(());
+        }
+        self.ended = true;
+        if !self.autocommit {
+            return Ok(());
+        }
+        let xid = self.visibility.xid;
+        {
+            let manager = MANAGER.lock().unwrap();
+            match manager.transactions.iter().find(|t| t.xid == xid) {
+                Some(t) if t.running && !t.explicit => {}
+                _ => return Ok(()),
+            }
+        }
+        finish(xid, success)
+    }
+
+    /// Records changes of rows in the undo log of the transaction. Must be
+    /// called before the rows are written.
+    ///
+    /// # Failures
+    /// Fails with `Error::RowLocked` if another running transaction wrote one
+    /// of the rows.
+    pub fn record(
+        &self,
+        database: &str,
+        table: &str,
+        changes: Vec<(Vec<u8>, Change)>,
+    ) -> Result<(), Error> {
+        let mut manager = MANAGER.lock().unwrap();
+        let xid = self.visibility.xid;
+        {
+            let others = manager
+                .transactions
+                .iter()
+                .filter(|t| t.running && t.xid != xid);
+            for undo in others.flat_map(|t| t.undo_log.iter()) {
+                if let &Undo::Row(ref r) = undo {
+                    let locked = undo.touches(database, table)
+                        && changes.iter().any(|&(ref key, _)| key == &r.key);
+                    if locked {
+                        return Err(Error::RowLocked);
+                    }
+                }
+            }
+        }
+
+        let mut seq = manager.next_seq;
+        manager.next_seq += changes.len() as u64;
+        let own = match manager.transactions.iter_mut().find(|t| t.xid == xid) {
+            Some(t) => t,
+            None => return Err(Error::NoTransaction),
+        };
+        for (key, change) in changes {
+            own.undo_log.push(Undo::Row(RowChange {
+                seq: seq,
+                database: database.to_string(),
+                table: table.to_string(),
+                key: key,
+                change: change,
+            }));
+            seq += 1;
+        }
+        Ok(())
+    }
+
+    /// Locks a table for the transaction, see `lock::acquire`. Returns false
+    /// if `cancelled` returned true before the lock was granted.
+    ///
+    /// # Failures
+    /// Fails with `Error::Deadlock` if the transaction was chosen as the
+    /// victim of a deadlock. It is rolled back then, also if `BEGIN` started
+    /// it.
+    pub fn lock<F>(
+        &self,
+        database: &str,
+        table: &str,
+        mode: Mode,
+        cancelled: F,
+    ) -> Result<bool, Error>
+    where
+        F: Fn() -> bool,
+    {
+        let xid = self.visibility.xid;
+        match lock::acquire(xid, database, table, mode, cancelled) {
+            Err(Error::Deadlock) => {
+                try!(finish(xid, false));
+                Err(Error::Deadlock)
+            }
+            result => result,
+        }
+    }
+
+    /// Removes the `count` changes recorded last, if writing them failed.
+    pub fn forget_last(&self, count: usize) {
+        let mut manager = MANAGER.lock().unwrap();
+        let xid = self.visibility.xid;
+        if let Some(t) = manager.transactions.iter_mut().find(|t| t.xid == xid) {
+            let len = t.undo_log.len();
+            t.undo_log.truncate(len - count.min(len));
+        }
+    }
+
+    /// Returns the position of the snapshot in the order of transactions:
+    /// it sees the changes of all transactions with a smaller id than the
+    /// one returned, except those of the running ones returned.
+    pub fn position(&self) -> (u64, Vec<u64>) {
+        (self.visibility.next_xid, self.visibility.running.clone())
+    }
+
+    /// Returns the changes of a table the snapshot does not see, the newest
+    /// first.
+    pub fn invisible_changes(&self, database: &str, table: &str) -> Vec<Change> {
+        let manager = MANAGER.lock().unwrap();
+        let mut changes: Vec<&RowChange> = manager
+            .transactions
+            .iter()
+            .filter(|t| !self.visibility.sees(t.xid))
+            .flat_map(|t| t.undo_log.iter())
+            .filter_map(|undo| match undo {
+                &Undo::Row(ref r) if undo.touches(database, table) => Some(r),
+                _ => None,
+            })
+            .collect();
+        changes.sort_by(|a, b| b.seq.cmp(&a.seq));
+        changes.into_iter().map(|r| r.change.clone()).collect()
+    }
+}
+
+impl Drop for Statement {
+    fn drop(&mut self) {
+        if let Err(e) = self.end(false) {
+            error!("rollback of a failed statement failed: {:?}", e);
+        }
+        let mut manager = MANAGER.lock().unwrap();
+        let snapshot = self.snapshot;
+        manager.snapshots.retain(|&(id, _)| id != snapshot);
+    }
+}
+
+/// Starts a transaction for the connection. The transaction of the running
+/// statement is continued.
+pub fn begin(connection: u64) -> Result<(), Error> {
+    let mut manager = MANAGER.lock().unwrap();
+    match manager.running(connection) {
+        Some(ref t) if t.explicit => return Err(Error::TransactionActive),
+        Some(t) => t.explicit = true,
+        None => {
+            manager.begin(connection, true);
+        }
+    }
+    debug!("connection {} started a transaction", connection);
+    Ok(())
+}
+
+/// Returns true if the connection is inside of a transaction started by
+/// `BEGIN`.
+pub fn is_active(connection: u64) -> bool {
+    MANAGER.lock().unwrap().explicit(connection).is_ok()
+}
+
+/// Makes all changes of the transaction permanent.
+pub fn commit(connection: u64) -> Result<(), Error> {
+    let xid = try!(MANAGER.lock().unwrap().explicit(connection));
+    debug!("connection {} commits its transaction", connection);
+    finish(xid, true)
+}
+
+/// Reverts all changes of the transaction.
+pub fn rollback(connection: u64) -> Result<(), Error> {
+    let xid = try!(MANAGER.lock().unwrap().explicit(connection));
+    debug!("connection {} rolls back its transaction", connection);
+    finish(xid, false)
+}
+
+/// Rolls back the transaction of a connection that ends without `COMMIT`.
+pub fn abort(connection: u64) {
+    if !is_active(connection) {
+        return;
+    }
+    info!(
+        "rolling back the open transaction of connection {}",
+        connection
+    );
+    if let Err(e) = rollback(connection) {
+        error!("rollback of connection {} failed: {:?}", connection, e);
+    }
+}
+
+/// Must be called before the schema of a table changes, while the table is
+/// locked exclusively. Saves the files of the table, if this is the first
+/// change of it inside of `BEGIN`.
+pub fn before_write(connection: u64, database: &str, table: &str) -> Result<(), Error> {
+    let mut manager = MANAGER.lock().unwrap();
+    // old versions of the rows do not fit the new schema
+    for t in manager.transactions.iter_mut().filter(|t| !t.running) {
+        t.undo_log.retain(|u| !u.touches(database, table));
+    }
+    manager
+        .transactions
+        .retain(|t| t.running || !t.undo_log.is_empty());
+
+    let transaction = match manager.running(connection) {
+        Some(t) => t,
+        None => return Ok(()),
+    };
+    if !transaction.explicit {
+        return Ok(());
+    }
+    let saved = transaction.undo_log.iter().any(|u| match u {
+        &Undo::Files(_) => u.touches(database, table),
+        _ => false,
+    });
+    if saved {
+        return Ok(());
+    }
+
+    let undo_dir = undo_dir(database, connection);
+    try!(fs::create_dir_all(&undo_dir));
+    let files = try!(table_files(database, table));
+    for file in &files {
+        try!(fs::copy(
+            format!("{}/{}", database, file),
+            format!("{}/{}", undo_dir, file)
+        ));
+    }
+    debug!("saved {:?} of table '{}' to {}", files, table, undo_dir);
+    transaction.undo_log.push(Undo::Files(Saved {
+        database: database.to_string(),
+        table: table.to_string(),
+        files: files,
+    }));
+    Ok(())
+}
+
+/// Returns the tables of a database whose schema running transactions
+/// changed, with the directory and the names of their saved files. A table
+/// without saved files did not exist before.
+pub fn saved_tables(database: &str) -> Vec<(String, String, Vec<String>)> {
+    let manager = MANAGER.lock().unwrap();
+    let mut tables = Vec::new();
+    for t in manager.transactions.iter().filter(|t| t.running) {
+        for undo in &t.undo_log {
+            if let &Undo::Files(ref saved) = undo {
+                if saved.database == database {
+                    let dir = undo_dir(database, t.connection);
+                    tables.push((saved.table.clone(), dir, saved.files.clone()));
+                }
+            }
+        }
+    }
+    tables
+}
+
+/// Keeps all tables from being written, e.g. while their files are copied.
+/// A single write only latches its table, see `lock::latch`.
+pub fn lock_writes() -> RwLockWriteGuard<'static, ()> {
+    lock::latch_all()
+}
+
+/// Throws away the undo logs of ended transactions which every snapshot
+/// sees.
+pub fn cleanup() {
+    let mut manager = MANAGER.lock().unwrap();
+    let Manager {
+        ref mut transactions,
+        ref snapshots,
+        ..
+    } = *manager;
+    let count = transactions.len();
+    transactions.retain(|t| t.running || snapshots.iter().any(|&(_, ref v)| !v.sees(t.xid)));
+    if transactions.len() < count {
+        debug!(
+            "threw away the undo logs of {} transactions",
+            count - transactions.len()
+        );
+    }
+}
+
+/// Starts the thread which calls `cleanup` periodically.
+pub fn spawn_cleanup() {
+    thread::spawn(|| loop {
+        thread::sleep(CLEANUP_INTERVAL);
+        cleanup();
+    });
+}
+
+/// Calls `hook` whenever a transaction ends, with the connection, whether
+/// the transaction was committed and the rows it inserted and deleted: the
+/// database, the table and the change, the oldest first. The transaction
+/// still holds its locks meanwhile, so transactions writing the same rows
+/// reach the hook in the order they wrote them.
+pub fn on_end(hook: fn(u64, bool, &[(&str, &str, &Change)])) {
+    *ON_END.lock().unwrap() = Some(hook);
+}
+
+/// Returns the number of rows the running transaction of a connection
+/// inserted and deleted so far, `0` if it has none.
+pub fn recorded(connection: u64) -> usize {
+    let mut manager = MANAGER.lock().unwrap();
+    match manager.running(connection) {
+        Some(t) => t
+            .undo_log
+            .iter()
+            .filter(|u| match u {
+                &&Undo::Row(_) => true,
+                _ => false,
+            })
+            .count(),
+        None => 0,
+    }
+}
+
+/// Ends a running transaction. Its changes are reverted first, unless it is
+/// committed. Meanwhile it still counts as running, so that no snapshot
+/// sees a half reverted table, and holds its locks.
+fn finish(xid: u64, commit: bool) -> Result<(), Error> {
+    let (connection, undo_log) = {
+        let manager = MANAGER.lock().unwrap();
+        match manager.transactions.iter().find(|t| t.xid == xid) {
+            Some(t) => (t.connection, t.undo_log.clone()),
+            None => return Err(Error::NoTransaction),
+        }
+    };
+    let result = if commit {
+        Ok(())
+    } else {
+        undo(&undo_log, connection)
+    };
+    let hook = *ON_END.lock().unwrap();
+    if let Some(hook) = hook {
+        let rows: Vec<_> = undo_log
+            .iter()
+            .filter_map(|undo| match undo {
+                &Undo::Row(ref r) => Some((&r.database[..], &r.table[..], &r.change)),
+                _ => None,
+            })
+            .collect();
+        hook(connection, commit, &rows);
+    }
+
+    let mut manager = MANAGER.lock().unwrap();
+    let pos = match manager.transactions.iter().position(|t| t.xid == xid) {
+        Some(pos) => pos,
+        None => return Err(Error::NoTransaction),
+    };
+    let mut transaction = manager.transactions.remove(pos);
+    lock::release(xid);
+    for undo in &transaction.undo_log {
+        if let &Undo::Files(ref saved) = undo {
+            try!(remove_undo_dir(&saved.database, connection));
+        }
+    }
+    transaction.running = false;
+    transaction.undo_log.retain(|u| match u {
+        &Undo::Row(_) => true,
+        _ => false,
+    });
+    if !transaction.undo_log.is_empty() {
+        manager.transactions.push(transaction);
+    }
+    result
+}
+
+/// Reverts the changes of an undo log, the newest first. The changes of
+/// rows following each other in a table are reverted together.
+fn undo(undo_log: &[Undo], connection: u64) -> Result<(), Error> {
+    let mut rows: Vec<&RowChange> = Vec::new();
+    for undo in undo_log.iter().rev() {
+        match undo {
+            &Undo::Row(ref r) => {
+                let other_table = rows
+                    .last()
+                    .map_or(false, |l| l.database != r.database || l.table != r.table);
+                if other_table {
+                    try!(revert_rows(&rows));
+                    rows.clear();
+                }
+                rows.push(r);
+            }
+            &Undo::Files(ref saved) => {
+                try!(revert_rows(&rows));
+                rows.clear();
+                let _writing = lock::latch(&saved.database, &saved.table);
+                try!(restore(saved, connection));
+            }
+        }
+    }
+    revert_rows(&rows)
+}
+
+/// Reverts changes of rows of the same table, the newest first.
+fn revert_rows(rows: &[&RowChange]) -> Result<(), Error> {
+    let first = match rows.first() {
+        Some(r) => r,
+        None => return Ok(()),
+    };
+    let _writing = lock::latch(&first.database, &first.table);
+    let changes: Vec<Change> = rows.iter().map(|r| r.change.clone()).collect();
+    mvcc::revert(&first.database, &first.table, &changes)
+}
+
+/// Replaces the files of a table by the saved ones. Files created by the
+/// transaction, e.g. of a new index, are removed.
+fn restore(saved: &Saved, connection: u64) -> Result<(), Error> {
+    for file in try!(table_files(&saved.database, &saved.table)) {
+        try!(fs::remove_file(format!("{}/{}", saved.database, file)));
+    }
+    let undo_dir = undo_dir(&saved.database, connection);
+    for file in &saved.files {
+        try!(fs::copy(
+            format!("{}/{}", undo_dir, file),
+            format!("{}/{}", saved.database, file)
+        ));
+    }
+    Ok(())
+}
+
+fn remove_undo_dir(database: &str, connection: u64) -> Result<(), Error> {
+    match fs::remove_dir_all(undo_dir(database, connection)) {
+        Err(ref e) if e.kind() == ::std::io::ErrorKind::NotFound => Ok(()),
+        result => Ok(try!(result)),
+    }
+}
+
+fn undo_dir(database: &str, connection: u64) -> String {
+    format!("{}/.undo-{}", database, connection)
+}
+
+/// Returns the names of all files of a table: the meta data, the data and
+/// the files of its indexes all start with `<table>.`
+fn table_files(database: &str, table: &str) -> Result<Vec<String>, Error> {
+    let prefix = format!("{}.", table);
+    let mut files = Vec::new();
+    for entry in try!(fs::read_dir(database)) {
+        let entry = try!(entry);
+        if !try!(entry.file_type()).is_file() {
+            continue;
+        }
+        if let Some(name) = entry.file_name().to_str() {
+            if name.starts_with(&prefix) {
+                files.push(name.to_string());
+            }
+        }
+    }
+    Ok(files)
+}
+
+#[test]
+fn test_visibility() {
+    let visibility = Visibility {
+        xid: 5,
+        next_xid: 7,
+        running: vec![3],
+    };
+    // ended before the snapshot
+    assert!(visibility.sees(2));
+    assert!(visibility.sees(4));
+    // own changes
+    assert!(visibility.sees(5));
+    // running at the snapshot or started later
+    assert!(!visibility.sees(3));
+    assert!(!visibility.sees(7));
+}
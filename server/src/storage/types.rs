@@ -1,14 +1,20 @@
 use super::super::parse::ast::CompType;
 use super::super::parse::token::Lit;
+use super::blob;
+use super::decimal;
+use super::pattern;
+use super::temporal;
 use super::Error;
 
-use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use byteorder::{BigEndian, ByteOrder, ReadBytesExt, WriteBytesExt};
 
 use serde::{Deserialize, Serialize};
 
+use std::cmp::min;
 use std::ffi::CString;
 use std::io::Read;
 use std::io::Write;
+use std::ops::{Bound, Range};
 use std::str;
 /// General enums in SQL
 #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
@@ -16,8 +22,38 @@ pub enum SqlType {
     Int,
     Bool,
     Char(u8),
+    /// Text of up to n bytes, stored behind its length
+    VarChar(u16),
+    /// Text of up to `TEXT_SIZE` bytes, stored behind its length
+    Text,
+    /// IEEE-754 single precision
+    Float,
+    /// IEEE-754 double precision
+    Double,
+    BigInt,
+    /// Days since 1970-01-01, see `temporal`
+    Date,
+    /// Microseconds since midnight
+    Time,
+    /// Microseconds since 1970-01-01 00:00:00
+    Timestamp,
+    /// Fixed-point number of up to p digits, s of them after the point,
+    /// see `decimal`
+    Decimal(u8, u8),
+    /// Bytes of up to n bytes, stored behind their length
+    VarBinary(u32),
+    /// Bytes of any length, long values are stored outside of the row,
+    /// see `blob`
+    Blob,
 }
 
+/// Maximum number of bytes of a TEXT value
+pub const TEXT_SIZE: u16 = 4096;
+/// Size of the length in front of VARCHAR and TEXT values
+const LENGTH_SIZE: u32 = 2;
+/// Size of the length in front of VARBINARY values
+const BINARY_LENGTH_SIZE: u32 = 4;
+
 /// Defines the size of Sql data types
 /// and returns them
 impl SqlType {
@@ -26,9 +62,106 @@ impl SqlType {
             &SqlType::Int => 4 as u32,
             &SqlType::Bool => 1 as u32,
             &SqlType::Char(len) => (len) as u32,
+            &SqlType::VarChar(len) => len as u32 + LENGTH_SIZE,
+            &SqlType::Text => TEXT_SIZE as u32 + LENGTH_SIZE,
+            &SqlType::Float => 4,
+            &SqlType::Double => 8,
+            &SqlType::BigInt => 8,
+            &SqlType::Date => 4,
+            &SqlType::Time => 8,
+            &SqlType::Timestamp => 8,
+            &SqlType::Decimal(..) => 8,
+            &SqlType::VarBinary(len) => len + BINARY_LENGTH_SIZE,
+            &SqlType::Blob => blob::SLOT_SIZE,
+        }
+    }
+
+    /// Returns true for the types holding text.
+    pub fn is_text(&self) -> bool {
+        match self {
+            &SqlType::Char(_) | &SqlType::VarChar(_) | &SqlType::Text => true,
+            _ => false,
+        }
+    }
+
+    /// Returns the maximum length of a length prefixed text.
+    fn max_length(&self) -> Option<usize> {
+        match self {
+            &SqlType::VarChar(len) => Some(len as usize),
+            &SqlType::Text => Some(TEXT_SIZE as usize),
+            _ => None,
+        }
+    }
+
+    /// Returns the bytes of a text value without its length or padding.
+    /// Values of other types are returned unchanged.
+    pub fn content<'a>(&self, val: &'a [u8]) -> &'a [u8] {
+        match self {
+            &SqlType::Char(_) => {
+                let end = val.iter().position(|&b| b == 0).unwrap_or(val.len());
+                &val[..end]
+            }
+            &SqlType::VarChar(_) | &SqlType::Text => {
+                if val.len() < LENGTH_SIZE as usize {
+                    return &[];
+                }
+                let len = BigEndian::read_u16(val) as usize;
+                let end = min(LENGTH_SIZE as usize + len, val.len());
+                &val[LENGTH_SIZE as usize..end]
+            }
+            &SqlType::VarBinary(_) => {
+                if val.len() < BINARY_LENGTH_SIZE as usize {
+                    return &[];
+                }
+                let len = BigEndian::read_u32(val) as usize;
+                let end = min(BINARY_LENGTH_SIZE as usize + len, val.len());
+                &val[BINARY_LENGTH_SIZE as usize..end]
+            }
+            _ => val,
         }
     }
 
+    /// Returns the value in a form whose byte order is the order of the
+    /// values, as used for the keys of indexes.
+    pub fn sort_key(&self, val: &[u8]) -> Vec<u8> {
+        let mut key = Vec::with_capacity(val.len());
+        match self {
+            &SqlType::Int
+            | &SqlType::BigInt
+            | &SqlType::Date
+            | &SqlType::Time
+            | &SqlType::Timestamp
+            | &SqlType::Decimal(..) => {
+                key.extend_from_slice(val);
+                // flip the sign bit, so that negative numbers come first
+                if key.len() > 0 {
+                    key[0] ^= 0x80;
+                }
+            }
+            &SqlType::Float | &SqlType::Double => {
+                key.extend_from_slice(val);
+                // negative numbers are ordered the other way round
+                if key.len() > 0 && key[0] & 0x80 != 0 {
+                    for b in &mut key {
+                        *b = !*b;
+                    }
+                } else if key.len() > 0 {
+                    key[0] ^= 0x80;
+                }
+            }
+            &SqlType::VarChar(_) | &SqlType::Text => {
+                key.extend_from_slice(self.content(val));
+                key.resize(self.max_length().unwrap_or(0), 0);
+            }
+            &SqlType::VarBinary(len) => {
+                key.extend_from_slice(self.content(val));
+                key.resize(len as usize, 0);
+            }
+            &SqlType::Bool | &SqlType::Char(_) | &SqlType::Blob => key.extend_from_slice(val),
+        }
+        key
+    }
+
     /// Decodes the data in buf according to SqlType into a Lit enum.
     pub fn decode_from<R: Read>(&self, buf: &mut R) -> Result<Lit, Error> {
         match self {
@@ -45,6 +178,38 @@ impl SqlType {
                 try!(buf.read_to_string(&mut s));
                 Ok(Lit::String(s))
             }
+            &SqlType::VarChar(_) | &SqlType::Text => {
+                let len = try!(buf.read_u16::<BigEndian>());
+                let mut s = String::new();
+                try!(buf.take(len as u64).read_to_string(&mut s));
+                Ok(Lit::String(s))
+            }
+            &SqlType::Float => {
+                let f = try!(buf.read_f32::<BigEndian>());
+                Ok(Lit::Float(f as f64))
+            }
+            &SqlType::Double => {
+                let f = try!(buf.read_f64::<BigEndian>());
+                Ok(Lit::Float(f))
+            }
+            &SqlType::BigInt => {
+                let i = try!(buf.read_i64::<BigEndian>());
+                Ok(Lit::Int(i))
+            }
+            &SqlType::Date => Ok(Lit::Date(try!(buf.read_i32::<BigEndian>()))),
+            &SqlType::Time => Ok(Lit::Time(try!(buf.read_i64::<BigEndian>()))),
+            &SqlType::Timestamp => Ok(Lit::Timestamp(try!(buf.read_i64::<BigEndian>()))),
+            &SqlType::Decimal(_, scale) => {
+                Ok(Lit::Decimal(try!(buf.read_i64::<BigEndian>()), scale))
+            }
+            &SqlType::VarBinary(_) => {
+                let len = try!(buf.read_u32::<BigEndian>());
+                let mut bytes = Vec::new();
+                try!(buf.take(len as u64).read_to_end(&mut bytes));
+                Ok(Lit::Bytes(bytes))
+            }
+            // values outside of the row are read through the engine
+            &SqlType::Blob => Err(Error::NoOperationPossible),
         }
     }
 
@@ -83,6 +248,172 @@ impl SqlType {
                 }
                 _ => Err(Error::InvalidType),
             },
+            &SqlType::VarChar(_) | &SqlType::Text => match data {
+                &Lit::String(ref a) => {
+                    let max_length = self.max_length().unwrap_or(0);
+                    let mut end = min(a.len(), max_length);
+                    // do not cut a character in half
+                    while !a.is_char_boundary(end) {
+                        end -= 1;
+                    }
+                    try!(buf.write_u16::<BigEndian>(end as u16));
+                    try!(buf.write_all(&a.as_bytes()[..end]));
+                    try!(buf.write_all(&vec![0; max_length - end]));
+                    Ok(self.size())
+                }
+                _ => Err(Error::InvalidType),
+            },
+            &SqlType::Float => match data {
+                &Lit::Float(a) => {
+                    try!(buf.write_f32::<BigEndian>(a as f32));
+                    Ok(self.size())
+                }
+                &Lit::Decimal(a, scale) => {
+                    try!(buf.write_f32::<BigEndian>(decimal::to_f64(a, scale) as f32));
+                    Ok(self.size())
+                }
+                &Lit::Int(a) => {
+                    try!(buf.write_f32::<BigEndian>(a as f32));
+                    Ok(self.size())
+                }
+                _ => Err(Error::InvalidType),
+            },
+            &SqlType::Double => match data {
+                &Lit::Float(a) => {
+                    try!(buf.write_f64::<BigEndian>(a));
+                    Ok(self.size())
+                }
+                &Lit::Decimal(a, scale) => {
+                    try!(buf.write_f64::<BigEndian>(decimal::to_f64(a, scale)));
+                    Ok(self.size())
+                }
+                &Lit::Int(a) => {
+                    try!(buf.write_f64::<BigEndian>(a as f64));
+                    Ok(self.size())
+                }
+                _ => Err(Error::InvalidType),
+            },
+            &SqlType::BigInt => match data {
+                &Lit::Int(a) => {
+                    try!(buf.write_i64::<BigEndian>(a));
+                    Ok(self.size())
+                }
+                _ => Err(Error::InvalidType),
+            },
+            &SqlType::Date => {
+                let date = match data {
+                    &Lit::Date(a) => a,
+                    &Lit::Timestamp(a) => temporal::split_timestamp(a).0,
+                    &Lit::String(ref a) => match temporal::parse_date(a) {
+                        Some(a) => a,
+                        None => return Err(Error::InvalidType),
+                    },
+                    _ => return Err(Error::InvalidType),
+                };
+                try!(buf.write_i32::<BigEndian>(date));
+                Ok(self.size())
+            }
+            &SqlType::Time => {
+                let time = match data {
+                    &Lit::Time(a) => a,
+                    &Lit::Timestamp(a) => temporal::split_timestamp(a).1,
+                    &Lit::String(ref a) => match temporal::parse_time(a) {
+                        Some(a) => a,
+                        None => return Err(Error::InvalidType),
+                    },
+                    _ => return Err(Error::InvalidType),
+                };
+                try!(buf.write_i64::<BigEndian>(time));
+                Ok(self.size())
+            }
+            &SqlType::Timestamp => {
+                let timestamp = match data {
+                    &Lit::Timestamp(a) => a,
+                    &Lit::Date(a) => temporal::date_to_timestamp(a),
+                    &Lit::String(ref a) => match temporal::parse_timestamp(a) {
+                        Some(a) => a,
+                        None => return Err(Error::InvalidType),
+                    },
+                    _ => return Err(Error::InvalidType),
+                };
+                try!(buf.write_i64::<BigEndian>(timestamp));
+                Ok(self.size())
+            }
+            &SqlType::Decimal(precision, scale) => {
+                let value = match data {
+                    &Lit::Decimal(a, from) => decimal::rescale(a, from, scale),
+                    &Lit::Int(a) => decimal::rescale(a, 0, scale),
+                    &Lit::Float(a) => decimal::from_f64(a, scale),
+                    &Lit::String(ref a) => {
+                        decimal::parse(a).and_then(|(a, from)| decimal::rescale(a, from, scale))
+                    }
+                    _ => None,
+                };
+                // values with too many digits are rejected, not cut
+                match value {
+                    Some(value) if decimal::fits(value, precision) => {
+                        try!(buf.write_i64::<BigEndian>(value));
+                        Ok(self.size())
+                    }
+                    _ => Err(Error::InvalidType),
+                }
+            }
+            &SqlType::VarBinary(len) => {
+                let bytes = match data {
+                    &Lit::Bytes(ref a) => &a[..],
+                    &Lit::String(ref a) => a.as_bytes(),
+                    _ => return Err(Error::InvalidType),
+                };
+                let end = min(bytes.len(), len as usize);
+                try!(buf.write_u32::<BigEndian>(end as u32));
+                try!(buf.write_all(&bytes[..end]));
+                try!(buf.write_all(&vec![0; len as usize - end]));
+                Ok(self.size())
+            }
+            // longer values are written through `Engine::write_blob`
+            &SqlType::Blob => {
+                let bytes = match data {
+                    &Lit::Bytes(ref a) => &a[..],
+                    &Lit::String(ref a) => a.as_bytes(),
+                    _ => return Err(Error::InvalidType),
+                };
+                match blob::inline_slot(bytes) {
+                    Some(slot) => {
+                        try!(buf.write_all(&slot));
+                        Ok(self.size())
+                    }
+                    None => Err(Error::InvalidType),
+                }
+            }
+        }
+    }
+
+    /// Returns true if a literal can be stored in a column of this type.
+    pub fn accepts(&self, data: &Lit) -> bool {
+        match (self, data) {
+            (&SqlType::Int, &Lit::Int(_)) | (&SqlType::BigInt, &Lit::Int(_)) => true,
+            (&SqlType::Float, &Lit::Int(_))
+            | (&SqlType::Float, &Lit::Float(_))
+            | (&SqlType::Float, &Lit::Decimal(..)) => true,
+            (&SqlType::Double, &Lit::Int(_))
+            | (&SqlType::Double, &Lit::Float(_))
+            | (&SqlType::Double, &Lit::Decimal(..)) => true,
+            (&SqlType::Decimal(..), &Lit::Int(_))
+            | (&SqlType::Decimal(..), &Lit::Float(_))
+            | (&SqlType::Decimal(..), &Lit::Decimal(..)) => true,
+            (&SqlType::Bool, &Lit::Bool(_)) => true,
+            (&SqlType::Date, &Lit::Date(_)) | (&SqlType::Date, &Lit::Timestamp(_)) => true,
+            (&SqlType::Time, &Lit::Time(_)) | (&SqlType::Time, &Lit::Timestamp(_)) => true,
+            (&SqlType::Timestamp, &Lit::Timestamp(_)) => true,
+            (&SqlType::Timestamp, &Lit::Date(_)) => true,
+            // dates and times may be written as strings
+            (&SqlType::Date, &Lit::String(_))
+            | (&SqlType::Time, &Lit::String(_))
+            | (&SqlType::Timestamp, &Lit::String(_)) => true,
+            (&SqlType::VarBinary(_), &Lit::Bytes(_))
+            | (&SqlType::VarBinary(_), &Lit::String(_)) => true,
+            (_, &Lit::String(_)) => self.is_text(),
+            _ => false,
         }
     }
 
@@ -114,6 +445,7 @@ impl SqlType {
                 CompType::SThan => self.lesser_than_for_int_with_value(val, val2),
                 CompType::GEThan => self.lesser_than_for_int_with_value(val, val2).map(|x| !x),
                 CompType::SEThan => self.greater_than_for_int_with_value(val, val2).map(|x| !x),
+                _ => Err(Error::NoOperationPossible),
             },
 
             &SqlType::Bool => match comp {
@@ -129,9 +461,54 @@ impl SqlType {
                 CompType::SThan => self.compare_byte_lesser_than(val, val2),
                 CompType::GEThan => self.compare_byte_lesser_than(val, val2).map(|x| !x),
                 CompType::SEThan => self.compare_byte_greater_than(val, val2).map(|x| !x),
+                CompType::Match => Ok(self.contains_words(val, val2)),
+                CompType::NMatch => Ok(!self.contains_words(val, val2)),
+                CompType::Like | CompType::NLike | CompType::Regexp | CompType::NRegexp => {
+                    self.matches_pattern(self.content(val), val2, comp)
+                }
+                CompType::IsNull | CompType::IsNotNull => Err(Error::NoOperationPossible),
+            },
+
+            &SqlType::VarChar(_) | &SqlType::Text => match comp {
+                CompType::Match => Ok(self.contains_words(self.content(val), val2)),
+                CompType::NMatch => Ok(!self.contains_words(self.content(val), val2)),
+                CompType::Like | CompType::NLike | CompType::Regexp | CompType::NRegexp => {
+                    self.matches_pattern(self.content(val), val2, comp)
+                }
+                _ => compare(self.content(val), self.content(val2), comp),
             },
+            &SqlType::Float => compare(&try!(f32::from_sql(val)), &try!(f32::from_sql(val2)), comp),
+            &SqlType::Double => {
+                compare(&try!(f64::from_sql(val)), &try!(f64::from_sql(val2)), comp)
+            }
+            &SqlType::BigInt | &SqlType::Time | &SqlType::Timestamp | &SqlType::Decimal(..) => {
+                compare(&try!(i64::from_sql(val)), &try!(i64::from_sql(val2)), comp)
+            }
+            &SqlType::Date => compare(&try!(i32::from_sql(val)), &try!(i32::from_sql(val2)), comp),
+            &SqlType::VarBinary(_) => compare(self.content(val), self.content(val2), comp),
+            // the slots of blobs do not order like their values
+            &SqlType::Blob => Err(Error::NoOperationPossible),
+        }
+    }
+
+    /// returns if val matches the LIKE or REGEXP pattern val2, see `pattern`
+    fn matches_pattern(&self, val: &[u8], val2: &[u8], comp: CompType) -> Result<bool, Error> {
+        let text = String::from_utf8_lossy(val);
+        let pattern = String::from_utf8_lossy(val2);
+        match comp {
+            CompType::Like => Ok(pattern::like(&text, &pattern)),
+            CompType::NLike => Ok(!pattern::like(&text, &pattern)),
+            CompType::Regexp => pattern::regexp(&text, &pattern),
+            _ => pattern::regexp(&text, &pattern).map(|x| !x),
         }
     }
+
+    /// returns if every word of val2 occurs in val, ignoring case
+    fn contains_words(&self, val: &[u8], val2: &[u8]) -> bool {
+        let words = tokenize(val);
+        tokenize(val2).iter().all(|w| words.contains(w))
+    }
+
     /// fn compares slices of u8 byte for byte and returns if both values are equal
     /// returns boolean on success and Error when given values do not have the same size
     fn compare_byte_for_equal(&self, val: &[u8], val2: &[u8]) -> Result<bool, Error> {
@@ -277,6 +654,7 @@ pub struct Column {
     pub is_primary_key: bool, // defines if column is PK
     pub allow_null: bool,     // defines if cloumn allows null
     pub description: String,  //Displays text describing this column.
+    pub default: Option<Lit>, // value of the column if an insert leaves it out
 }
 
 impl Column {
@@ -295,6 +673,7 @@ impl Column {
             allow_null: allow_null,
             description: description.to_string(),
             is_primary_key: is_primary_key,
+            default: None,
         }
     }
 
@@ -311,6 +690,161 @@ impl Column {
     }
 }
 
+//---------------------------------------------------------------
+// Rows
+//---------------------------------------------------------------
+
+// A row holds the values of all columns one after another. If a column
+// allows null, the values are followed by a bitmap with one bit per column,
+// a set bit marks the value of the column as null. The value of a null
+// column is stored as zeros.
+
+/// Returns the size of the null bitmap of a row.
+pub fn null_bitmap_size(columns: &[Column]) -> usize {
+    if columns.iter().any(|c| c.allow_null) {
+        (columns.len() + 7) / 8
+    } else {
+        0
+    }
+}
+
+/// Returns the size of a row: the values of all columns and the null bitmap.
+pub fn row_size(columns: &[Column]) -> usize {
+    values_size(columns) + null_bitmap_size(columns)
+}
+
+fn values_size(columns: &[Column]) -> usize {
+    columns.iter().map(|c| c.get_size() as usize).sum()
+}
+
+/// Returns the position of the value of a column inside of a row.
+pub fn column_range(columns: &[Column], column_index: usize) -> Range<usize> {
+    let start = values_size(&columns[..column_index]);
+    start..start + columns[column_index].get_size() as usize
+}
+
+/// Returns the position of the byte holding the null bit of a column and
+/// the mask of the bit, or None if the row has no null bitmap.
+fn null_bit(columns: &[Column], column_index: usize) -> Option<(usize, u8)> {
+    if null_bitmap_size(columns) == 0 {
+        return None;
+    }
+    let pos = values_size(columns) + column_index / 8;
+    Some((pos, 1 << (column_index % 8)))
+}
+
+/// Returns true if the value of a column is null.
+pub fn is_null(columns: &[Column], row_data: &[u8], column_index: usize) -> bool {
+    match null_bit(columns, column_index) {
+        Some((pos, mask)) => row_data.get(pos).map_or(false, |b| b & mask != 0),
+        None => false,
+    }
+}
+
+/// Marks the value of a column as null or not null. Does nothing if the
+/// row has no null bitmap.
+pub fn set_null(columns: &[Column], row_data: &mut [u8], column_index: usize, null: bool) {
+    if let Some((pos, mask)) = null_bit(columns, column_index) {
+        if null {
+            row_data[pos] |= mask;
+        } else {
+            row_data[pos] &= !mask;
+        }
+    }
+}
+
+/// Builds a row from the values of its columns, None stands for null.
+/// Returns Error::NullNotAllowed if a column does not allow null.
+pub fn encode_row(columns: &[Column], values: &[Option<Vec<u8>>]) -> Result<Vec<u8>, Error> {
+    let mut row_data = vec![0; row_size(columns)];
+    for (i, value) in values.iter().enumerate() {
+        match value {
+            &Some(ref value) => {
+                let range = column_range(columns, i);
+                let len = range.len();
+                row_data[range].copy_from_slice(&value[..len]);
+            }
+            &None => {
+                if !columns[i].allow_null {
+                    return Err(Error::NullNotAllowed);
+                }
+                set_null(columns, &mut row_data, i, true);
+            }
+        }
+    }
+    Ok(row_data)
+}
+
+/// Returns true if the value of a column of a row fulfills a constraint.
+/// The value is compared with value.0, or with the value of the column
+/// value.1 of the same row. A comparison with null is unknown, so neither
+/// it nor its negation is fulfilled.
+pub fn fulfills(
+    columns: &[Column],
+    row_data: &[u8],
+    column_index: usize,
+    value: (&[u8], Option<usize>),
+    comp: CompType,
+) -> Result<bool, Error> {
+    let null = is_null(columns, row_data, column_index);
+    match comp {
+        CompType::IsNull => return Ok(null),
+        CompType::IsNotNull => return Ok(!null),
+        _ => {}
+    }
+    let cmp_value = match value.1 {
+        Some(cmpindex) => {
+            if is_null(columns, row_data, cmpindex) {
+                return Ok(false);
+            }
+            &row_data[column_range(columns, cmpindex)]
+        }
+        None => value.0,
+    };
+    if null {
+        return Ok(false);
+    }
+    let row_value = &row_data[column_range(columns, column_index)];
+    columns[column_index]
+        .sql_type
+        .cmp(row_value, cmp_value, comp)
+}
+
+/// Returns true if the value of a column of a row lies within the bounds.
+/// A null value lies within no bounds.
+pub fn within(
+    columns: &[Column],
+    row_data: &[u8],
+    column_index: usize,
+    lower: Bound<&[u8]>,
+    upper: Bound<&[u8]>,
+) -> Result<bool, Error> {
+    if is_null(columns, row_data, column_index) {
+        return Ok(false);
+    }
+    let bounds = [
+        (lower, CompType::GEThan, CompType::GThan),
+        (upper, CompType::SEThan, CompType::SThan),
+    ];
+    for &(bound, including, excluding) in &bounds {
+        let (value, comp) = match bound {
+            Bound::Included(value) => (value, including),
+            Bound::Excluded(value) => (value, excluding),
+            Bound::Unbounded => continue,
+        };
+        if !try!(fulfills(
+            columns,
+            row_data,
+            column_index,
+            (value, None),
+            comp
+        )) {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
 //---------------------------------------------------------------
 // FromSql
 //---------------------------------------------------------------
@@ -356,3 +890,45 @@ impl FromSql for bool {
         Ok(try!(data.read_u8()) != 0)
     }
 }
+
+impl FromSql for i64 {
+    fn from_sql(mut data: &[u8]) -> Result<Self, Error> {
+        Ok(try!(data.read_i64::<BigEndian>()))
+    }
+}
+
+impl FromSql for f32 {
+    fn from_sql(mut data: &[u8]) -> Result<Self, Error> {
+        Ok(try!(data.read_f32::<BigEndian>()))
+    }
+}
+
+impl FromSql for f64 {
+    fn from_sql(mut data: &[u8]) -> Result<Self, Error> {
+        Ok(try!(data.read_f64::<BigEndian>()))
+    }
+}
+
+/// Compares two decoded values.
+fn compare<T: PartialOrd + ?Sized>(val: &T, val2: &T, comp: CompType) -> Result<bool, Error> {
+    match comp {
+        CompType::Equ => Ok(val == val2),
+        CompType::NEqu => Ok(val != val2),
+        CompType::GThan => Ok(val > val2),
+        CompType::SThan => Ok(val < val2),
+        CompType::GEThan => Ok(val >= val2),
+        CompType::SEThan => Ok(val <= val2),
+        _ => Err(Error::NoOperationPossible),
+    }
+}
+
+/// Splits a text into lowercase words for full text search. The text ends at
+/// the first nul byte, everything that is not alphanumeric separates words.
+pub fn tokenize(text: &[u8]) -> Vec<String> {
+    let end = text.iter().position(|&b| b == 0).unwrap_or(text.len());
+    String::from_utf8_lossy(&text[..end])
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(|w| w.to_lowercase())
+        .collect()
+}
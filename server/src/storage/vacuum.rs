@@ -0,0 +1,99 @@
+//! Reclaiming the space of deleted rows
+//!
+//! Deleting a row only marks it as deleted in the data file of its table.
+//! `vacuum` moves the remaining rows together by `Engine::reorganize`, the
+//! thread started by `spawn_maintenance` does so for every table whose
+//! share of deleted rows exceeds a threshold.
+
+use super::meta::{Database, Table};
+use super::transaction;
+use super::Error;
+
+use std::fs;
+use std::thread;
+use std::time::Duration;
+
+/// Tables with fewer deleted rows are not compacted by the maintenance
+/// thread, however large their share
+const MIN_DELETED_ROWS: u64 = 32;
+
+/// Outcome of compacting a table
+#[derive(Debug)]
+pub struct Report {
+    /// Rows of the table
+    pub rows: u64,
+    /// Deleted rows whose space was reclaimed
+    pub removed: u64,
+    /// Size of the data file before and after, in bytes
+    pub size_before: u64,
+    pub size_after: u64,
+}
+
+/// Removes the deleted rows from the data file of a table. Writes to other
+/// tables wait meanwhile.
+pub fn vacuum(table: Table) -> Result<Report, Error> {
+    // reads see the rows moving, so they are kept from each other
+    let _writing = transaction::lock_writes();
+    let path = table.get_table_data_path();
+    let (rows, removed) = try!(table.count_rows());
+    let size_before = try!(fs::metadata(&path)).len();
+    if removed > 0 {
+        try!(table.create_engine().reorganize());
+    }
+    Ok(Report {
+        rows: rows,
+        removed: removed,
+        size_before: size_before,
+        size_after: try!(fs::metadata(&path)).len(),
+    })
+}
+
+/// Starts the thread which looks at all tables every `interval` and
+/// compacts those of which at least `threshold` percent of the rows in the
+/// data file are deleted.
+pub fn spawn_maintenance(interval: Duration, threshold: u8) {
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+        match Database::list() {
+            Ok(names) => {
+                for name in names {
+                    maintain(&name, threshold);
+                }
+            }
+            Err(e) => warn!("cannot list the databases to compact: {:?}", e),
+        }
+    });
+}
+
+/// Compacts the fragmented tables of a database. Tables that cannot be
+/// compacted are skipped.
+fn maintain(database: &str, threshold: u8) {
+    let database = match Database::load(database) {
+        Ok(d) => d,
+        Err(_) => return,
+    };
+    let names = match database.list_tables() {
+        Ok(names) => names,
+        Err(_) => return,
+    };
+    for name in names {
+        let result = database.load_table(&name).and_then(|table| {
+            let (rows, deleted) = try!(table.count_rows());
+            if deleted < MIN_DELETED_ROWS || deleted * 100 < (rows + deleted) * threshold as u64 {
+                return Ok(None);
+            }
+            vacuum(table).map(Some)
+        });
+        match result {
+            Ok(Some(report)) => info!(
+                "compacted table '{}' of database '{}': {:?}",
+                name, database.name, report
+            ),
+            Ok(None) => {}
+            Err(e) => warn!(
+                "cannot compact table '{}' of database '{}': {:?}",
+                name, database.name, e
+            ),
+        }
+    }
+}
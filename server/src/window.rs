@@ -0,0 +1,288 @@
+//! Window functions of selects
+//!
+//! A window function like `RANK() OVER (PARTITION BY dept ORDER BY salary
+//! DESC)` computes a value for every row of a select from the rows of its
+//! partition, those with equal values in the columns of `PARTITION BY`, in
+//! the order of the `ORDER BY` of the window. Rows with equal values in
+//! those columns are peers; without `ORDER BY` all rows of a partition are.
+//!
+//! - `ROW_NUMBER()` numbers the rows of a partition from 1, peers in the
+//!   order they are read
+//! - `RANK()` is the number of the first peer of a row, so that ranks have
+//!   gaps after peers
+//! - `SUM(x)` and `AVG(x)` add up and average the values of the rows of the
+//!   partition up to the last peer of the row, a running total. Null values
+//!   are left out; without other values the result is null.
+//!
+//! The functions are computed after the WHERE condition and before the
+//! `ORDER BY` of the select. For every function the rows are sorted by its
+//! partition and order, see `sort`, and read once: only the peers of the
+//! current row are kept, as its value depends on them and the rows before.
+
+use parse::token::Lit;
+use sort::{self, SortKey};
+use spill;
+use storage::decimal;
+use storage::{Column, Error, Rows, SqlType};
+
+use std::io::Cursor;
+
+/// A window function, arguments are given by the index of their column
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Function {
+    RowNumber,
+    Rank,
+    Sum(usize),
+    Avg(usize),
+}
+
+/// A window function with the columns of its window
+#[derive(Clone, Debug, PartialEq)]
+pub struct Window {
+    pub function: Function,
+    pub partition: Vec<usize>,
+    pub order: Vec<SortKey>,
+}
+
+/// Returns the type of the values of a function on rows of the columns:
+/// numbers of rows and sums of integers are big integers, sums of decimals
+/// decimals and other sums and averages doubles.
+pub fn result_type(function: Function, columns: &[Column]) -> Result<SqlType, Error> {
+    let argument = match function {
+        Function::RowNumber | Function::Rank => return Ok(SqlType::BigInt),
+        Function::Sum(i) | Function::Avg(i) => columns[i].sql_type,
+    };
+    Ok(match (function, argument) {
+        (Function::Sum(_), SqlType::Int) | (Function::Sum(_), SqlType::BigInt) => SqlType::BigInt,
+        (Function::Sum(_), SqlType::Decimal(_, scale)) => {
+            SqlType::Decimal(decimal::MAX_PRECISION, scale)
+        }
+        (_, SqlType::Int)
+        | (_, SqlType::BigInt)
+        | (_, SqlType::Float)
+        | (_, SqlType::Double)
+        | (_, SqlType::Decimal(..)) => SqlType::Double,
+        _ => return Err(Error::InvalidType),
+    })
+}
+
+/// Returns the rows with a column of the values of a window function
+/// appended, named `name`, in the order of the window. `add` adds two
+/// values of the argument of a sum; `check` is called for every row, e.g.
+/// to stop the statement once it is cancelled.
+pub fn apply<E, A, F>(
+    rows: Rows<Cursor<Vec<u8>>>,
+    window: &Window,
+    name: &str,
+    add: A,
+    mut check: F,
+) -> Result<Rows<Cursor<Vec<u8>>>, E>
+where
+    E: From<Error>,
+    A: Fn(&Lit, &Lit) -> Result<Lit, E>,
+    F: FnMut() -> Result<(), E>,
+{
+    let sql_type = try!(result_type(window.function, &rows.columns));
+    let partition: Vec<SortKey> = window
+        .partition
+        .iter()
+        .map(|&column| SortKey {
+            column: column,
+            descending: false,
+        })
+        .collect();
+    let keys: Vec<SortKey> = partition.iter().chain(&window.order).cloned().collect();
+    let mut sorted = try!(sort::sort(rows, &keys, spill::memory_budget(), &mut check));
+
+    let mut columns = sorted.columns.clone();
+    columns.push(Column::new(name, sql_type, true, "", false));
+    let mut result = Rows::new(Cursor::new(Vec::new()), &columns);
+    let mut state = State {
+        function: window.function,
+        sql_type: sql_type,
+        before: 0,
+        sum: None,
+        count: 0,
+        peers: Vec::new(),
+    };
+    let mut current = None;
+    try!(sorted.reset_pos());
+    loop {
+        try!(check());
+        let mut row = Vec::new();
+        match sorted.next_row(&mut row) {
+            Ok(_) => (),
+            Err(Error::EndOfFile) => break,
+            Err(e) => return Err(e.into()),
+        }
+        let partition_key = sort::sort_key(&sorted.columns, &partition, &row);
+        let peer_key = sort::sort_key(&sorted.columns, &window.order, &row);
+        let same = match current {
+            Some((ref p, ref o)) => (*p == partition_key, *o == peer_key),
+            None => (false, false),
+        };
+        if same != (true, true) {
+            try!(state.flush(&sorted, &mut result));
+            if !same.0 {
+                state.before = 0;
+                state.sum = None;
+                state.count = 0;
+            }
+            current = Some((partition_key, peer_key));
+        }
+        if let Function::Sum(i) | Function::Avg(i) = window.function {
+            let value = try!(sorted.get_nullable_value(&row, i));
+            if let Some(value) = value {
+                let value = try!(sorted.columns[i].sql_type.decode_from(&mut &value[..]));
+                state.sum = Some(match state.sum.take() {
+                    Some(sum) => try!(add(&sum, &value)),
+                    None => value,
+                });
+                state.count += 1;
+            }
+        }
+        state.peers.push(row);
+    }
+    try!(state.flush(&sorted, &mut result));
+    Ok(result)
+}
+
+/// The rows of a partition read so far
+struct State {
+    function: Function,
+    sql_type: SqlType,
+    /// Number of rows of the partition before the peers
+    before: u64,
+    /// Sum of the values of the argument up to the last peer
+    sum: Option<Lit>,
+    /// Number of those values
+    count: u64,
+    /// The peers of the current row, which share its value
+    peers: Vec<Vec<u8>>,
+}
+
+impl State {
+    /// Adds the peers with their values to the result.
+    fn flush(
+        &mut self,
+        rows: &Rows<Cursor<Vec<u8>>>,
+        result: &mut Rows<Cursor<Vec<u8>>>,
+    ) -> Result<(), Error> {
+        for (i, row) in self.peers.iter().enumerate() {
+            let value = match self.function {
+                Function::RowNumber => Some(Lit::Int((self.before + i as u64 + 1) as i64)),
+                Function::Rank => Some(Lit::Int((self.before + 1) as i64)),
+                Function::Sum(_) => self.sum.clone(),
+                Function::Avg(_) => self
+                    .sum
+                    .as_ref()
+                    .and_then(to_f64)
+                    .map(|sum| Lit::Float(sum / self.count as f64)),
+            };
+            let mut values = Vec::with_capacity(result.columns.len());
+            for column in 0..rows.columns.len() {
+                values.push(try!(rows.get_nullable_value(row, column)));
+            }
+            values.push(match value {
+                Some(value) => {
+                    let mut data = Vec::new();
+                    try!(self.sql_type.encode_into(&mut data, &value));
+                    Some(data)
+                }
+                None => None,
+            });
+            try!(result.add_values(&values));
+        }
+        self.before += self.peers.len() as u64;
+        self.peers.clear();
+        Ok(())
+    }
+}
+
+fn to_f64(lit: &Lit) -> Option<f64> {
+    match lit {
+        &Lit::Int(i) => Some(i as f64),
+        &Lit::Float(f) => Some(f),
+        &Lit::Decimal(d, scale) => Some(decimal::to_f64(d, scale)),
+        _ => None,
+    }
+}
+
+#[test]
+fn test_window_functions() {
+    let columns = vec![
+        Column::new("dept", SqlType::Int, false, "", false),
+        Column::new("salary", SqlType::Int, true, "", false),
+    ];
+    let mut rows = Rows::new(Cursor::new(Vec::new()), &columns);
+    let int = |v: i64| {
+        let mut data = Vec::new();
+        SqlType::Int.encode_into(&mut data, &Lit::Int(v)).unwrap();
+        data
+    };
+    for &(dept, salary) in &[
+        (2, Some(5)),
+        (1, Some(30)),
+        (1, Some(10)),
+        (2, None),
+        (1, Some(10)),
+    ] {
+        rows.add_values(&[Some(int(dept)), salary.map(int)])
+            .unwrap();
+    }
+    let mut run = |function: Function, order: bool| {
+        let window = Window {
+            function: function,
+            partition: vec![0],
+            order: if order {
+                vec![SortKey {
+                    column: 1,
+                    descending: false,
+                }]
+            } else {
+                Vec::new()
+            },
+        };
+        let mut copy = Rows::new(Cursor::new(Vec::new()), &columns);
+        rows.reset_pos().unwrap();
+        let mut row = Vec::new();
+        while rows.next_row(&mut row).is_ok() {
+            copy.add_row(&row).unwrap();
+            row.clear();
+        }
+        let add = |a: &Lit, b: &Lit| match (a, b) {
+            (&Lit::Int(a), &Lit::Int(b)) => Ok(Lit::Int(a + b)),
+            _ => Err(Error::InvalidType),
+        };
+        let mut result = apply(copy, &window, "w", add, || Ok::<(), Error>(())).unwrap();
+        let mut values = Vec::new();
+        result.reset_pos().unwrap();
+        while result.next_row(&mut row).is_ok() {
+            let value = result.get_nullable_value(&row, 2).unwrap();
+            values
+                .push(value.map(|v| result.columns[2].sql_type.decode_from(&mut &v[..]).unwrap()));
+            row.clear();
+        }
+        values
+    };
+    let ints = |values: &[i64]| {
+        values
+            .iter()
+            .map(|&v| Some(Lit::Int(v)))
+            .collect::<Vec<_>>()
+    };
+    // dept 1 ordered by salary: 10, 10, 30, then dept 2: 5, null
+    assert_eq!(run(Function::RowNumber, true), ints(&[1, 2, 3, 1, 2]));
+    assert_eq!(run(Function::Rank, true), ints(&[1, 1, 3, 1, 2]));
+    assert_eq!(run(Function::Sum(1), true), ints(&[20, 20, 50, 5, 5]));
+    // without an order all rows of a partition are peers
+    assert_eq!(run(Function::Sum(1), false), ints(&[50, 50, 50, 5, 5]));
+    assert_eq!(
+        run(Function::Avg(1), false)[3..].to_vec(),
+        vec![Some(Lit::Float(5.0)), Some(Lit::Float(5.0))]
+    );
+    assert_eq!(
+        result_type(Function::Avg(1), &columns).unwrap(),
+        SqlType::Double
+    );
+}
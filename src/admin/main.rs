@@ -0,0 +1,214 @@
+//! Administration of a server from the command line
+//!
+//! Every routine task is a subcommand, which sends the statement doing it
+//! over a `uosql::Connection` and prints what the server returns as a table,
+//! so that operators do not have to write SQL. The user logging in needs the
+//! privileges of the task, most of them are for administrators only.
+extern crate docopt;
+extern crate serde;
+extern crate server;
+extern crate uosql;
+
+use docopt::Docopt;
+use serde::Deserialize;
+use server::dump;
+use server::parse::token::Lit;
+use std::io::{self, Write};
+use std::process;
+use uosql::types::DataSet;
+use uosql::Connection;
+
+/// For console input, manages subcommands, flags and arguments
+const USAGE: &'static str = "
+Usage:
+    uosql-admin [options] user create <name> [<password>]
+    uosql-admin [options] user drop <name>
+    uosql-admin [options] database create <name>
+    uosql-admin [options] database drop <name> [--force]
+    uosql-admin [options] database list
+    uosql-admin [options] processlist
+    uosql-admin [options] kill <id>
+    uosql-admin [options] backup <name> <dir>
+    uosql-admin [options] status
+    uosql-admin --help
+
+Commands:
+    user create       Create a user, asks for the password if left out.
+    user drop         Drop a user.
+    database create   Create a database.
+    database drop     Drop a database.
+    database list     List the databases with their size and connections.
+    processlist       List the connections.
+    kill              Close a connection, see processlist.
+    backup            Back up a database to a directory on the server.
+    status            Show the counters of the server.
+
+Options:
+    --bind=<address>    Address of the server [default: 127.0.0.1].
+    --port=<port>       Port of the server [default: 4242].
+    --name=<username>   Login with given username [default: admin].
+    --pwd=<password>    Login with given password, asked for if left out.
+    --force             Drop the database even if connections use it.
+    -h, --help          Show this text.
+";
+
+#[derive(Debug, Deserialize)]
+struct Args {
+    cmd_user: bool,
+    cmd_database: bool,
+    cmd_create: bool,
+    cmd_drop: bool,
+    cmd_list: bool,
+    cmd_processlist: bool,
+    cmd_kill: bool,
+    cmd_backup: bool,
+    arg_name: Option<String>,
+    arg_password: Option<String>,
+    arg_id: Option<u64>,
+    arg_dir: Option<String>,
+    flag_bind: String,
+    flag_port: u16,
+    flag_name: String,
+    flag_pwd: Option<String>,
+    flag_force: bool,
+}
+
+fn main() {
+    let args: Args = Docopt::new(USAGE)
+        .and_then(|d| d.deserialize())
+        .unwrap_or_else(|e| e.exit());
+
+    let password = match args.flag_pwd {
+        Some(ref p) => p.clone(),
+        None => read_string(&format!("Password of {}", args.flag_name)),
+    };
+    let mut conn = match Connection::connect(
+        args.flag_bind.clone(),
+        args.flag_port,
+        args.flag_name.clone(),
+        password,
+    ) {
+        Ok(conn) => conn,
+        Err(e) => fail(&format!("Cannot connect: {}", e)),
+    };
+
+    let name = args.arg_name.as_ref().map(|n| &n[..]).unwrap_or("");
+    if args.cmd_kill {
+        let id = args.arg_id.unwrap_or(0);
+        let result = conn.kill(id);
+        let _ = conn.quit();
+        match result {
+            Ok(_) => println!("Connection {} killed.", id),
+            Err(e) => fail(&e.to_string()),
+        }
+        return;
+    }
+    let result = if args.cmd_user && args.cmd_create {
+        let password = match args.arg_password {
+            Some(ref p) => p.clone(),
+            None => read_string(&format!("Password of {}", name)),
+        };
+        conn.execute(format!(
+            "CREATE USER {} IDENTIFIED BY {}",
+            dump::name(name),
+            dump::literal(&Lit::String(password))
+        ))
+    } else if args.cmd_user && args.cmd_drop {
+        conn.execute(format!("DROP USER {}", dump::name(name)))
+    } else if args.cmd_database && args.cmd_create {
+        conn.execute(format!("CREATE DATABASE {}", dump::name(name)))
+    } else if args.cmd_database && args.cmd_drop {
+        let force = if args.flag_force { " FORCE" } else { "" };
+        conn.execute(format!("DROP DATABASE {}{}", dump::name(name), force))
+    } else if args.cmd_database && args.cmd_list {
+        conn.execute("SHOW DATABASE STATUS".into())
+    } else if args.cmd_processlist {
+        conn.execute("SHOW PROCESSLIST".into())
+    } else if args.cmd_backup {
+        conn.backup(
+            &dump::name(name),
+            args.arg_dir.as_ref().map(|d| &d[..]).unwrap_or(""),
+        )
+    } else {
+        conn.execute("SHOW STATUS".into())
+    };
+    let _ = conn.quit();
+
+    match result {
+        Ok(mut data) => {
+            if !print_table(&mut data) {
+                fail("The server could not execute the command, see its log.");
+            }
+        }
+        Err(e) => fail(&e.to_string()),
+    }
+}
+
+/// Prints the rows as a table whose columns are as wide as their longest
+/// value, OK for a result without columns. Returns false for the result of
+/// a statement that failed.
+fn print_table(data: &mut DataSet) -> bool {
+    let count = data.get_col_cnt();
+    let mut lines = vec![(0..count)
+        .map(|i| data.get_col_name(i).unwrap_or("").to_string())
+        .collect::<Vec<_>>()];
+    // the server answers failed statements with an empty column `error`
+    if data.data_empty() && lines[0] == ["error"] {
+        return false;
+    }
+    if count == 0 {
+        println!("OK");
+        return true;
+    }
+    while data.next() {
+        lines.push(
+            (0..count)
+                .map(|i| data.next_as_string_by_idx(i).unwrap_or("NULL".into()))
+                .collect(),
+        );
+    }
+    let widths: Vec<usize> = (0..count)
+        .map(|i| {
+            lines
+                .iter()
+                .map(|l| l[i].chars().count())
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+    let separator: String = widths
+        .iter()
+        .map(|w| format!("+{}", "-".repeat(w + 2)))
+        .collect();
+    println!("{}+", separator);
+    for (n, line) in lines.iter().enumerate() {
+        for (value, width) in line.iter().zip(&widths) {
+            print!("| {1: <0$} ", width, value);
+        }
+        println!("|");
+        if n == 0 {
+            println!("{}+", separator);
+        }
+    }
+    println!("{}+", separator);
+    println!("{} rows", lines.len() - 1);
+    true
+}
+
+/// Asks for a line on the console, without its line break.
+fn read_string(msg: &str) -> String {
+    print!("{}: ", msg);
+    io::stdout().flush().ok();
+    let mut line = String::new();
+    if io::stdin().read_line(&mut line).is_err() {
+        fail("Cannot read from the console");
+    }
+    line.trim_end_matches(|c| c == '\r' || c == '\n')
+        .to_string()
+}
+
+/// Prints the message and exits with status 1.
+fn fail(msg: &str) -> ! {
+    eprintln!("{}", msg);
+    process::exit(1)
+}
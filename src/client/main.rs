@@ -20,7 +20,7 @@ mod specialcrate;
 use bincode::{deserialize_from, serialize_into};
 use docopt::Docopt;
 use regex::Regex;
-use serde::{Deserialize};
+use serde::Deserialize;
 use server::storage::SqlType;
 use std::cmp::{max, min};
 use std::error::Error;
@@ -30,18 +30,26 @@ use std::net::Ipv4Addr;
 use std::str::FromStr;
 use uosql::logger;
 use uosql::types::DataSet;
-use uosql::Connection;
+use uosql::{Connection, TlsOptions};
 
 /// For console input, manages flags and arguments
 const USAGE: &'static str = "
 Usage: uosql-client [--bind=<address>] [--port=<port>] [--name=<username>]
-        [--pwd=<password>]
+        [--pwd=<password>] [--tls] [--tls-ca=<file>] [--tls-cert=<file>]
+        [--tls-key=<file>] [--tls-domain=<name>]
 
 Options:
     --bind=<address>    Change the bind address.
     --port=<port>       Change the port.
     --name=<username>   Login with given username.
     --pwd=<password>    Login with given password.
+    --tls               Encrypt the connection.
+    --tls-ca=<file>     Trust the authority in this PEM file, implies --tls.
+    --tls-cert=<file>   Login by the certificate in this PEM file, without
+                        username and password, implies --tls.
+    --tls-key=<file>    Enter the PKCS#8 private key of the certificate.
+    --tls-domain=<name> Expect the certificate of the server for this name
+                        instead of the address.
 ";
 
 #[derive(Debug, Deserialize)]
@@ -50,6 +58,11 @@ struct Args {
     flag_port: Option<u16>,
     flag_name: Option<String>,
     flag_pwd: Option<String>,
+    flag_tls: bool,
+    flag_tls_ca: Option<String>,
+    flag_tls_cert: Option<String>,
+    flag_tls_key: Option<String>,
+    flag_tls_domain: Option<String>,
 }
 
 fn main() {
@@ -91,10 +104,14 @@ fn main() {
         }
     };
 
+    // A client certificate logs in without username and password
+    let by_certificate = args.flag_tls_cert.is_some();
+
     // Set username for connection
     let username = {
         match args.flag_name {
             Some(u) => u,
+            None if by_certificate => String::new(),
             None => read_string("Username"),
         }
     };
@@ -103,12 +120,24 @@ fn main() {
     let password = {
         match args.flag_pwd {
             Some(p) => p,
+            None if by_certificate => String::new(),
             None => read_string("Password"),
         }
     };
 
     // Connect to uosql server with given parameters.
-    let mut conn = match Connection::connect(address, port, username, password) {
+    let connected = if args.flag_tls || args.flag_tls_ca.is_some() || by_certificate {
+        let tls = TlsOptions {
+            ca: args.flag_tls_ca,
+            cert: args.flag_tls_cert,
+            key: args.flag_tls_key,
+            domain: args.flag_tls_domain.unwrap_or_default(),
+        };
+        Connection::connect_tls(address, port, username, password, tls)
+    } else {
+        Connection::connect(address, port, username, password)
+    };
+    let mut conn = match connected {
         Ok(conn) => conn,
         Err(e) => match e {
             uosql::Error::AddrParse(_) => {
@@ -131,6 +160,22 @@ fn main() {
                 info!("{}", e.description());
                 return;
             }
+            uosql::Error::ProtocolMismatch => {
+                error!("{}", e.description());
+                return;
+            }
+            uosql::Error::TooManyConnections => {
+                error!("{}", e.description());
+                return;
+            }
+            uosql::Error::CorruptPacket | uosql::Error::PacketTooLarge => {
+                error!("{}", e.description());
+                return;
+            }
+            uosql::Error::Tls(_) => {
+                error!("{}", e);
+                return;
+            }
             uosql::Error::Server(_) => {
                 error!("{}", e.description());
                 return;
@@ -139,8 +184,9 @@ fn main() {
     };
 
     println!(
-        "Connected (version: {}) to {}:{}\n{}",
+        "Connected (version: {}, protocol: {}) to {}:{}\n{}",
         conn.get_version(),
+        conn.get_protocol_version(),
         conn.get_ip(),
         conn.get_port(),
         conn.get_message()
@@ -282,10 +328,34 @@ fn main() {
                     }
                     return; // end client
                 }
-                true => continue, // next iteration
+                true => {
+                    print_notifications(&mut conn);
+                    continue; // next iteration
+                }
+            }
+        }
+    }
+}
+
+/// Print all notifications received on listened channels and whether the
+/// server shuts down.
+fn print_notifications(conn: &mut Connection) {
+    loop {
+        match conn.poll_notification() {
+            Ok(Some(n)) => println!("Notification on '{}': {}", n.channel, n.payload),
+            Ok(None) => break,
+            Err(e) => {
+                error!("Notification: {}", e.description());
+                break;
             }
         }
     }
+    if let Some(warning) = conn.take_shutdown_warning() {
+        println!(
+            "The server shuts down, the connection is closed in {} s at the latest",
+            warning.grace_period
+        );
+    }
 }
 
 /// Process commandline-input from user.
@@ -319,6 +389,103 @@ fn process_input(input: &str, conn: &mut Connection, history: &Vec<String>) -> b
         return execute_sql(f, conn);
     }
 
+    // :kill with connection id (see SHOW PROCESSLIST)
+    if input.to_lowercase().starts_with(":kill ") {
+        match input[6..].trim().parse::<u64>() {
+            Ok(id) => match conn.kill(id) {
+                Ok(_) => println!("Connection {} killed.", id),
+                Err(e) => error!("Kill: {}", e.description()),
+            },
+            Err(_) => println!("Usage: :kill <connection id>"),
+        }
+        return true;
+    }
+
+    // :listen with channel name (see NOTIFY)
+    if input.to_lowercase().starts_with(":listen ") {
+        let channel = input[8..].trim();
+        match conn.listen(channel) {
+            Ok(_) => println!("Listening on channel '{}'.", channel),
+            Err(e) => error!("Listen: {}", e.description()),
+        }
+        return true;
+    }
+
+    // :prepare with a statement to execute by its id later
+    if input.to_lowercase().starts_with(":prepare ") {
+        match conn.prepare(input[9..].trim()) {
+            Ok(id) => println!("Statement {} prepared.", id),
+            Err(e) => error!("Prepare: {}", e),
+        }
+        return true;
+    }
+
+    // :execute with the id of a prepared statement
+    if input.to_lowercase().starts_with(":execute ") {
+        match input[9..].trim().parse::<u64>() {
+            Ok(id) => match conn.execute_prepared(id) {
+                Ok(mut data) => display(&mut data),
+                Err(e) => error!("Execute: {}", e),
+            },
+            Err(_) => println!("Usage: :execute <statement id>"),
+        }
+        return true;
+    }
+
+    // :deallocate with the id of a prepared statement
+    if input.to_lowercase().starts_with(":deallocate ") {
+        match input[12..].trim().parse::<u64>() {
+            Ok(id) => match conn.deallocate(id) {
+                Ok(_) => println!("Statement {} deallocated.", id),
+                Err(e) => error!("Deallocate: {}", e),
+            },
+            Err(_) => println!("Usage: :deallocate <statement id>"),
+        }
+        return true;
+    }
+
+    // :dump with database and file (see DUMP DATABASE)
+    if input.to_lowercase().starts_with(":dump ") {
+        let args: Vec<&str> = input[6..].split_whitespace().collect();
+        if args.len() != 2 {
+            println!("Usage: :dump <database> <file>");
+            return true;
+        }
+        let mut f = match File::create(args[1]) {
+            Ok(f) => f,
+            Err(_) => {
+                println!("Could not create file");
+                return true;
+            }
+        };
+        match conn.dump_to(args[0], &mut f) {
+            Ok(_) => println!("Database '{}' dumped to {}.", args[0], args[1]),
+            Err(e) => error!("Dump: {}", e),
+        }
+        return true;
+    }
+
+    // :copy with table and CSV file (see COPY)
+    if input.to_lowercase().starts_with(":copy ") {
+        let args: Vec<&str> = input[6..].split_whitespace().collect();
+        if args.len() != 2 {
+            println!("Usage: :copy <table> <file>");
+            return true;
+        }
+        let f = match File::open(args[1]) {
+            Ok(f) => f,
+            Err(_) => {
+                println!("Could not open file");
+                return true;
+            }
+        };
+        match conn.copy_in(args[0], f) {
+            Ok(rows) => println!("{} rows copied into '{}'.", rows, args[0]),
+            Err(e) => error!("Copy: {}", e),
+        }
+        return true;
+    }
+
     // standard match for command and queries
     let input_low = input.to_lowercase();
     match &*input_low {
@@ -353,6 +520,16 @@ fn process_input(input: &str, conn: &mut Connection, history: &Vec<String>) -> b
             let help = include_str!("readme.txt");
             println!("{}", help);
         }
+        ":server" => {
+            let server = conn.capabilities();
+            println!("Version:          {}", conn.get_version());
+            println!("Protocol:         {}", conn.get_protocol_version());
+            println!("SQL features:     {}", server.sql_features.join(", "));
+            println!("Auth methods:     {}", server.auth_methods.join(", "));
+            if let Some(ref database) = server.default_database {
+                println!("Default database: {}", database);
+            }
+        }
         ":hello" => {
             println!("Hello, Dave. You're looking well today.");
         }
@@ -402,20 +579,23 @@ fn process_input(input: &str, conn: &mut Connection, history: &Vec<String>) -> b
                     display(&mut data);
                 }
                 Err(e) => match e {
-                    uosql::Error::Io(_) => {
-                        error!("{}", e.description());
-                        return true;
-                    }
-                    uosql::Error::Bincode(_) => {
+                    uosql::Error::Io(_) | uosql::Error::Bincode(_) => {
                         error!("{}", e.description());
+                        // The connection may have dropped: continue the session
+                        match conn.resume() {
+                            Ok(_) => println!("Reconnected. Please repeat the query."),
+                            Err(e) => error!("Resume: {}", e.description()),
+                        }
                         return true;
                     }
-                    uosql::Error::UnexpectedPkg => {
+                    uosql::Error::UnexpectedPkg
+                    | uosql::Error::CorruptPacket
+                    | uosql::Error::PacketTooLarge => {
                         error!("{}", e.description());
                         return true;
                     }
                     uosql::Error::Server(_) => {
-                        error!("{}", e.description());
+                        error!("{}", e);
                         return true;
                     }
                     _ => {
@@ -642,6 +822,38 @@ fn display_data(table: &mut DataSet) {
             SqlType::Char(size) => {
                 cols.push(max(size as usize, table.get_col_name(i).unwrap().len()));
             }
+            SqlType::VarChar(size) => {
+                cols.push(max(size as usize, table.get_col_name(i).unwrap().len()));
+            }
+            SqlType::Text => {
+                cols.push(max(30, table.get_col_name(i).unwrap().len()));
+            }
+            SqlType::Float | SqlType::Double | SqlType::BigInt => {
+                cols.push(max(20, table.get_col_name(i).unwrap().len()));
+            }
+            SqlType::Date => {
+                cols.push(max(10, table.get_col_name(i).unwrap().len()));
+            }
+            SqlType::Time => {
+                cols.push(max(15, table.get_col_name(i).unwrap().len()));
+            }
+            SqlType::Timestamp => {
+                cols.push(max(26, table.get_col_name(i).unwrap().len()));
+            }
+            SqlType::Decimal(precision, _) => {
+                cols.push(max(
+                    precision as usize + 2,
+                    table.get_col_name(i).unwrap().len(),
+                ));
+            }
+            // two hex digits per byte behind \x
+            SqlType::VarBinary(size) => {
+                let width = min(size as usize * 2 + 2, 30);
+                cols.push(max(width, table.get_col_name(i).unwrap().len()));
+            }
+            SqlType::Blob => {
+                cols.push(max(30, table.get_col_name(i).unwrap().len()));
+            }
         }
     }
 
@@ -671,16 +883,16 @@ fn display_data(table: &mut DataSet) {
                 Some(t) => match t {
                     SqlType::Int => match table.next_int_by_idx(i) {
                         Some(val) => print!("| {1: ^0$} ", min(30, cols[i]), val),
-                        None => print!("| {1: ^0$} ", min(30, cols[i]), "none"),
+                        None => print!("| {1: ^0$} ", min(30, cols[i]), "NULL"),
                     },
                     SqlType::Bool => match table.next_bool_by_idx(i) {
                         Some(val) => print!("| {1: ^0$} ", min(30, cols[i]), val),
-                        None => print!("| {1: ^0$} ", min(30, cols[i]), "none"),
+                        None => print!("| {1: ^0$} ", min(30, cols[i]), "NULL"),
                     },
-                    SqlType::Char(_) => print!(
+                    _ => print!(
                         "| {1: ^0$} ",
                         min(30, cols[i]),
-                        table.next_char_by_idx(i).unwrap_or("none".into())
+                        table.next_as_string_by_idx(i).unwrap_or("NULL".into())
                     ),
                 },
                 None => continue,
@@ -1,20 +1,31 @@
 #[macro_use]
 extern crate server;
 extern crate bincode;
+extern crate native_tls;
 
-use bincode::{deserialize_from, serialize_into};
+use native_tls::{Certificate, Identity, TlsConnector, TlsStream};
 pub use server::logger;
 pub use server::net::types;
+use server::net::{self as net, deserialize_from, read_packet, Packet, PacketWriter};
 use server::storage::ResultSet;
+use std::collections::VecDeque;
 use std::fmt;
-use std::io::{self, Write};
+use std::fs;
+use std::io::{self, Read, Write};
 use std::net::{AddrParseError, TcpStream};
 use std::str::FromStr;
 use types::*;
 
-use std::io::Read;
-
-const PROTOCOL_VERSION: u8 = 1;
+/// Highest protocol version this client speaks.
+const PROTOCOL_VERSION: u8 = 20;
+/// Lowest protocol version this client still speaks.
+const MIN_PROTOCOL_VERSION: u8 = 20;
+/// Optional protocol features implemented by this client.
+const CLIENT_CAPABILITIES: Capabilities = Capabilities::PREPARED_STATEMENTS;
+/// Size of the chunks of CSV text `copy_in` sends.
+const COPY_CHUNK_SIZE: usize = 64 * 1024;
+/// Longest packet this client accepts.
+const MAX_PACKET_SIZE: u32 = 16 * 1024 * 1024;
 
 /// Client specific Error definition.
 #[derive(Debug)]
@@ -24,13 +35,23 @@ pub enum Error {
     UnexpectedPkg,
     Bincode(bincode::Error),
     Auth,
+    ProtocolMismatch,
+    TooManyConnections,
+    CorruptPacket,
+    PacketTooLarge,
+    Tls(String),
     Server(ClientErrMsg),
 }
 
-/// Implement display for description of Error
+/// Implement display for description of Error. Server errors are shown
+/// with position and hint.
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        std::error::Error::description(self).fmt(f)
+        match self {
+            &Error::Server(ref e) => e.fmt(f),
+            &Error::Tls(ref e) => write!(f, "TLS failed: {}", e),
+            _ => std::error::Error::description(self).fmt(f),
+        }
     }
 }
 
@@ -43,6 +64,11 @@ impl std::error::Error for Error {
             &Error::UnexpectedPkg => "received unexpected package",
             &Error::Bincode(_) => "could not encode/decode send package",
             &Error::Auth => "could not authenticate user",
+            &Error::ProtocolMismatch => "client and server share no protocol version",
+            &Error::TooManyConnections => "server refused connection: too many connections",
+            &Error::CorruptPacket => "received package with wrong checksum",
+            &Error::PacketTooLarge => "received package exceeds the maximum packet size",
+            &Error::Tls(_) => "could not set up TLS with the server",
             &Error::Server(ref e) => &e.msg,
         }
     }
@@ -69,10 +95,33 @@ impl From<bincode::Error> for Error {
     }
 }
 
+/// Implement the conversion from the errors of reading a package
+impl From<net::Error> for Error {
+    fn from(err: net::Error) -> Error {
+        match err {
+            net::Error::Io(e) => Error::Io(e),
+            net::Error::Bincode(e) => Error::Bincode(e),
+            net::Error::CorruptPacket => Error::CorruptPacket,
+            net::Error::PacketTooLarge => Error::PacketTooLarge,
+            _ => Error::UnexpectedPkg,
+        }
+    }
+}
+
+/// Implement the conversion from the errors of setting up TLS
+impl From<native_tls::Error> for Error {
+    fn from(err: native_tls::Error) -> Error {
+        Error::Tls(err.to_string())
+    }
+}
+
 /// Implement the conversion from ClientErrMsg to NetworkError
 impl From<ClientErrMsg> for Error {
     fn from(err: ClientErrMsg) -> Error {
-        Error::Server(err)
+        match err.code() {
+            ClientErrMsg::PROTOCOL_MISMATCH => Error::ProtocolMismatch,
+            _ => Error::Server(err),
+        }
     }
 }
 
@@ -81,9 +130,88 @@ impl From<ClientErrMsg> for Error {
 pub struct Connection {
     ip: String,
     port: u16,
-    tcp: TcpStream,
+    tcp: Stream,
+    /// Set if the connection is encrypted, used again by `resume`
+    tls: Option<TlsOptions>,
     greeting: Greeting,
+    negotiation: Negotiation,
+    session: SessionInfo,
     user_data: Login,
+    /// Packages the server pushed while waiting for a response
+    pushed: Pushed,
+}
+
+/// How to encrypt a connection, see `Connection::connect_tls`. Files are
+/// in PEM format.
+#[derive(Debug, Clone, Default)]
+pub struct TlsOptions {
+    /// Authority the certificate of the server must be signed by, besides
+    /// the authorities of the system
+    pub ca: Option<String>,
+    /// Certificate and PKCS#8 private key of the client. The server logs
+    /// in the user its certificate maps to without a password.
+    pub cert: Option<String>,
+    pub key: Option<String>,
+    /// Name the certificate of the server must be issued for, the address
+    /// of the server if empty
+    pub domain: String,
+}
+
+/// The connection to the server, encrypted or not
+enum Stream {
+    Plain(TcpStream),
+    Tls(TlsStream<TcpStream>),
+}
+
+impl Stream {
+    /// Returns the underlying socket.
+    fn tcp(&self) -> &TcpStream {
+        match self {
+            &Stream::Plain(ref tcp) => tcp,
+            &Stream::Tls(ref tls) => tls.get_ref(),
+        }
+    }
+
+    /// Returns true if decrypted data waits to be read.
+    fn has_buffered(&self) -> Result<bool, Error> {
+        match self {
+            &Stream::Plain(_) => Ok(false),
+            &Stream::Tls(ref tls) => Ok(try!(tls.buffered_read_size()) > 0),
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            &mut Stream::Plain(ref mut tcp) => tcp.read(buf),
+            &mut Stream::Tls(ref mut tls) => tls.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            &mut Stream::Plain(ref mut tcp) => tcp.write(buf),
+            &mut Stream::Tls(ref mut tls) => tls.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            &mut Stream::Plain(ref mut tcp) => tcp.flush(),
+            &mut Stream::Tls(ref mut tls) => tls.flush(),
+        }
+    }
+}
+
+/// Packages the server sends without being asked, see `receive_one_of`
+#[derive(Default)]
+struct Pushed {
+    notifications: VecDeque<Notification>,
+    /// Set once the server announced that it shuts down
+    shutdown: Option<ShutdownWarning>,
 }
 
 impl Connection {
@@ -94,54 +222,75 @@ impl Connection {
         usern: String,
         passwd: String,
     ) -> Result<Connection, Error> {
-        // Parse IPv4 address from String
-        let tmp_addr = match std::net::Ipv4Addr::from_str(&addr) {
-            Ok(tmp_addr) => tmp_addr,
-            Err(e) => return Err(e.into()),
-        };
+        Connection::login(addr, port, usern, passwd, None)
+    }
 
-        // Establish Tcp connection
-        let mut tmp_tcp = match TcpStream::connect((tmp_addr, port)) {
-            Ok(tmp_tcp) => tmp_tcp,
-            Err(e) => return Err(e.into()),
-        };
+    /// Establish an encrypted connection. With a client certificate, the
+    /// server may log in the user it maps to without password; `usern` may
+    /// then be empty.
+    pub fn connect_tls(
+        addr: String,
+        port: u16,
+        usern: String,
+        passwd: String,
+        tls: TlsOptions,
+    ) -> Result<Connection, Error> {
+        Connection::login(addr, port, usern, passwd, Some(tls))
+    }
 
-        // Greeting message
-        match receive(&mut tmp_tcp, PkgType::Greet) {
-            Ok(_) => {}
-            Err(e) => return Err(e),
-        };
-        let greet: Greeting = try!(deserialize_from(&mut tmp_tcp));
+    fn login(
+        addr: String,
+        port: u16,
+        usern: String,
+        passwd: String,
+        tls: Option<TlsOptions>,
+    ) -> Result<Connection, Error> {
+        let (mut tmp_tcp, greet) = try!(open(&addr, port, tls.as_ref()));
 
         // Login package
         let log = Login {
             username: usern,
             password: passwd,
+            min_version: MIN_PROTOCOL_VERSION,
+            max_version: PROTOCOL_VERSION,
+            capabilities: CLIENT_CAPABILITIES,
+            max_packet_size: MAX_PACKET_SIZE,
         };
-        match serialize_into(&mut tmp_tcp, &PkgType::Login) {
-            Ok(_) => {}
-            Err(e) => return Err(e.into()),
-        }
-
-        // Login data
-        match serialize_into(&mut tmp_tcp, &log) {
-            Ok(_) => {}
-            Err(e) => return Err(e.into()),
-        }
+        let mut packet = PacketWriter::new(PkgType::Login);
+        try!(packet.add(&log));
+        try!(packet.send(&mut tmp_tcp));
 
         // Get Login response - either user is authorized or unauthorized
-        let status: PkgType = try!(deserialize_from(&mut tmp_tcp));
-        match status {
-            PkgType::AccGranted => Ok(Connection {
-                ip: addr,
-                port: port,
-                tcp: tmp_tcp,
-                greeting: greet,
-                user_data: log,
-            }),
-            PkgType::AccDenied => Err(Error::Auth),
-            _ => Err(Error::UnexpectedPkg),
-        }
+        let (negotiation, session) = try!(receive_acc_granted(&mut tmp_tcp));
+        Ok(Connection {
+            ip: addr,
+            port: port,
+            tcp: tmp_tcp,
+            tls: tls,
+            greeting: greet,
+            negotiation: negotiation,
+            session: session,
+            user_data: log,
+            pushed: Pushed::default(),
+        })
+    }
+
+    /// Reconnect after the connection dropped and continue the session with
+    /// its current database, without logging in again. Only works for a few
+    /// minutes after the connection dropped.
+    pub fn resume(&mut self) -> Result<(), Error> {
+        let (mut tmp_tcp, greet) = try!(open(&self.ip, self.port, self.tls.as_ref()));
+        try!(send_cmd(
+            &mut tmp_tcp,
+            Command::Resume(self.session.token.clone()),
+            1024
+        ));
+        let (negotiation, session) = try!(receive_acc_granted(&mut tmp_tcp));
+        self.tcp = tmp_tcp;
+        self.greeting = greet;
+        self.negotiation = negotiation;
+        self.session = session;
+        Ok(())
     }
 
     /// Send ping-command to server and receive Ok-package
@@ -150,7 +299,7 @@ impl Connection {
             Ok(_) => {}
             Err(e) => return Err(e),
         };
-        match receive(&mut self.tcp, PkgType::Ok) {
+        match self.receive(PkgType::Ok) {
             Ok(_) => Ok(()),
             Err(err) => Err(err),
         }
@@ -162,21 +311,92 @@ impl Connection {
             Ok(_) => {}
             Err(e) => return Err(e),
         };
-        match receive(&mut self.tcp, PkgType::Ok) {
+        match self.receive(PkgType::Ok) {
+            Ok(_) => Ok(()),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Send kill-command to server and receive Ok-package. Cancels the
+    /// running query of connection `id` and closes that connection.
+    pub fn kill(&mut self, id: u64) -> Result<(), Error> {
+        match send_cmd(&mut self.tcp, Command::Kill(id), 1024) {
+            Ok(_) => {}
+            Err(e) => return Err(e),
+        };
+        match self.receive(PkgType::Ok) {
+            Ok(_) => Ok(()),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Send listen-command to server and receive Ok-package. Afterwards the
+    /// server sends a notification for every `NOTIFY` on the channel, see
+    /// `poll_notification`.
+    pub fn listen(&mut self, channel: &str) -> Result<(), Error> {
+        match send_cmd(&mut self.tcp, Command::Listen(channel.into()), 1024) {
+            Ok(_) => {}
+            Err(e) => return Err(e),
+        };
+        match self.receive(PkgType::Ok) {
             Ok(_) => Ok(()),
             Err(err) => Err(err),
         }
     }
 
+    /// Return the oldest notification received on a listened channel, or
+    /// `None` if there is none. Does not block.
+    pub fn poll_notification(&mut self) -> Result<Option<Notification>, Error> {
+        loop {
+            if let Some(n) = self.pushed.notifications.pop_front() {
+                return Ok(Some(n));
+            }
+
+            // Check for a pending package without waiting for one
+            let pending = if try!(self.tcp.has_buffered()) {
+                Ok(1)
+            } else {
+                let tcp = self.tcp.tcp();
+                try!(tcp.set_nonblocking(true));
+                let pending = tcp.peek(&mut [0; 1]);
+                try!(tcp.set_nonblocking(false));
+                pending
+            };
+            match pending {
+                Ok(0) => {
+                    return Err(
+                        io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed").into(),
+                    )
+                }
+                Ok(_) => {
+                    let max_size = self.negotiation.max_packet_size;
+                    let packet = try!(read_packet(&mut self.tcp, max_size));
+                    if !try!(self.pushed.add(&packet)) {
+                        return Err(Error::UnexpectedPkg);
+                    }
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(None),
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    /// Return the warning the server sent when it began shutting down, once.
+    /// The connection is closed after the grace period of the warning.
+    /// Warnings pending on the connection are read by `poll_notification`.
+    pub fn take_shutdown_warning(&mut self) -> Option<ShutdownWarning> {
+        self.pushed.shutdown.take()
+    }
+
     // TODO: Return results (response-package)
     pub fn execute(&mut self, query: String) -> Result<DataSet, Error> {
         match send_cmd(&mut self.tcp, Command::Query(query), 1024) {
             Ok(_) => {}
             Err(e) => return Err(e),
         };
-        match receive(&mut self.tcp, PkgType::Response) {
-            Ok(_) => {
-                let rows: ResultSet = try!(deserialize_from(&mut self.tcp));
+        match self.receive(PkgType::Response) {
+            Ok(packet) => {
+                let rows: ResultSet = try!(deserialize_from(packet.payload()));
                 let dataset = preprocess(&rows);
                 Ok(dataset)
             }
@@ -184,12 +404,147 @@ impl Connection {
         }
     }
 
-    /// Return server version number.
-    pub fn get_version(&self) -> u8 {
-        self.greeting.protocol_version
+    /// Parse a statement on the server to execute it later by the returned
+    /// id, see `execute_prepared`. Needs the `PREPARED_STATEMENTS`
+    /// capability.
+    pub fn prepare(&mut self, query: &str) -> Result<u64, Error> {
+        try!(send_cmd(
+            &mut self.tcp,
+            Command::Prepare(query.into()),
+            1024
+        ));
+        let packet = try!(self.receive(PkgType::Response));
+        let rows: ResultSet = try!(deserialize_from(packet.payload()));
+        let mut dataset = preprocess(&rows);
+        dataset.next();
+        Ok(dataset.next_bigint_by_idx(0).unwrap_or(0) as u64)
+    }
+
+    /// Execute a statement prepared by `prepare`.
+    pub fn execute_prepared(&mut self, id: u64) -> Result<DataSet, Error> {
+        try!(send_cmd(&mut self.tcp, Command::Execute(id), 1024));
+        let packet = try!(self.receive(PkgType::Response));
+        let rows: ResultSet = try!(deserialize_from(packet.payload()));
+        Ok(preprocess(&rows))
+    }
+
+    /// Drop a statement prepared by `prepare` on the server.
+    pub fn deallocate(&mut self, id: u64) -> Result<(), Error> {
+        try!(send_cmd(&mut self.tcp, Command::Deallocate(id), 1024));
+        try!(self.receive(PkgType::Ok));
+        Ok(())
+    }
+
+    /// Back up database `db` to the directory `path` on the server, see
+    /// `BACKUP DATABASE`. Returns the row describing the backup.
+    pub fn backup(&mut self, db: &str, path: &str) -> Result<DataSet, Error> {
+        self.execute(format!("BACKUP DATABASE {} TO {}", db, quote_path(path)))
+    }
+
+    /// Create the database backed up to the directory `path` on the server,
+    /// see `RESTORE DATABASE`. Returns the row describing the backup.
+    pub fn restore(&mut self, path: &str) -> Result<DataSet, Error> {
+        self.execute(format!("RESTORE DATABASE FROM {}", quote_path(path)))
+    }
+
+    /// Dump database `db` as SQL text into `out`, see `DUMP DATABASE`. The
+    /// text is written chunk by chunk as it arrives. If writing fails, the
+    /// rest of the text is still read, so that the connection stays usable.
+    pub fn dump_to<W: Write>(&mut self, db: &str, mut out: W) -> Result<(), Error> {
+        try!(send_cmd(&mut self.tcp, Command::Dump(db.into()), 1024));
+        let mut written = Ok(());
+        loop {
+            let packet = try!(receive_one_of(
+                &mut self.tcp,
+                &[PkgType::Response, PkgType::Ok],
+                &mut self.pushed,
+                self.negotiation.max_packet_size
+            ));
+            if packet.pkg == PkgType::Ok {
+                break;
+            }
+            let rows: ResultSet = try!(deserialize_from(packet.payload()));
+            let mut chunks = preprocess(&rows);
+            while chunks.next() {
+                let chunk = chunks.next_char_by_idx(0).unwrap_or_default();
+                if written.is_ok() {
+                    written = out.write_all(chunk.as_bytes());
+                }
+            }
+        }
+        Ok(try!(written))
+    }
+
+    /// Insert the CSV text read from `input` into table `table`, see
+    /// `COPY`. The text is sent in chunks as it is read. Returns the number
+    /// of rows inserted. If reading fails, the server is told to give up and
+    /// no row is inserted.
+    pub fn copy_in<R: Read>(&mut self, table: &str, mut input: R) -> Result<u64, Error> {
+        try!(send_cmd(&mut self.tcp, Command::CopyIn(table.into()), 1024));
+        let mut read = Ok(());
+        loop {
+            let mut chunk = vec![0; COPY_CHUNK_SIZE];
+            match input.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(len) => {
+                    chunk.truncate(len);
+                    try!(send_cmd(&mut self.tcp, Command::CopyData(chunk), 1024));
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => {
+                    read = Err(e);
+                    break;
+                }
+            }
+        }
+        let end = match read {
+            Ok(_) => Command::CopyDone,
+            Err(ref e) => Command::CopyFail(e.to_string()),
+        };
+        try!(send_cmd(&mut self.tcp, end, 1024));
+        // the server answers the failure with an error of its own
+        let reply = self.receive(PkgType::Response);
+        try!(read);
+        let packet = try!(reply);
+        let rows: ResultSet = try!(deserialize_from(packet.payload()));
+        let mut dataset = preprocess(&rows);
+        dataset.next();
+        Ok(dataset.next_bigint_by_idx(0).unwrap_or(0) as u64)
+    }
+
+    /// Receive the expected package within the negotiated packet size.
+    fn receive(&mut self, cmd: PkgType) -> Result<Packet, Error> {
+        let max_size = self.negotiation.max_packet_size;
+        receive(&mut self.tcp, cmd, &mut self.pushed, max_size)
+    }
+
+    /// Return the version of the server software, followed by the commit it
+    /// was built from if known.
+    pub fn get_version(&self) -> String {
+        let server = &self.greeting.server;
+        match &*server.build_commit {
+            "" => server.version.clone(),
+            commit => format!("{} ({})", server.version, commit),
+        }
+    }
+
+    /// Return what the server supports: SQL features, default database and
+    /// authentication methods.
+    pub fn capabilities(&self) -> &ServerCapabilities {
+        &self.greeting.server
     }
 
-    /// Return server greeting message.
+    /// Return the protocol version negotiated for this connection.
+    pub fn get_protocol_version(&self) -> u8 {
+        self.negotiation.version
+    }
+
+    /// Return true if the given feature may be used on this connection.
+    pub fn has_capability(&self, cap: Capabilities) -> bool {
+        self.negotiation.capabilities.contains(cap)
+    }
+
+    /// Return the welcome message of the server greeting.
     pub fn get_message(&self) -> &str {
         &self.greeting.message
     }
@@ -204,10 +559,70 @@ impl Connection {
         self.port
     }
 
+    /// Return the id of this connection, as shown by `SHOW PROCESSLIST`.
+    pub fn get_connection_id(&self) -> u64 {
+        self.session.id
+    }
+
     /// Return username used for current connection authentication.
     pub fn get_username(&self) -> &str {
         &self.user_data.username
     }
+
+    /// Return true if the connection is encrypted.
+    pub fn is_tls(&self) -> bool {
+        self.tls.is_some()
+    }
+}
+
+/// Open a connection, receive the greeting and set up TLS if asked for.
+fn open(addr: &str, port: u16, tls: Option<&TlsOptions>) -> Result<(Stream, Greeting), Error> {
+    // Parse IPv4 address from String
+    let tmp_addr = try!(std::net::Ipv4Addr::from_str(addr));
+    let mut tmp_tcp = try!(TcpStream::connect((tmp_addr, port)));
+
+    // Greeting message
+    let greet = try!(receive_greeting(&mut tmp_tcp));
+
+    // The greeting contains the highest version the server speaks
+    if greet.protocol_version < MIN_PROTOCOL_VERSION {
+        return Err(Error::ProtocolMismatch);
+    }
+
+    let tls = match tls {
+        Some(tls) => tls,
+        None => return Ok((Stream::Plain(tmp_tcp), greet)),
+    };
+    try!(send_cmd(&mut tmp_tcp, Command::StartTls, 1024));
+    try!(receive(
+        &mut tmp_tcp,
+        PkgType::Ok,
+        &mut Pushed::default(),
+        MAX_PACKET_SIZE
+    ));
+
+    let mut connector = TlsConnector::builder();
+    if let Some(ref ca) = tls.ca {
+        let pem = try!(fs::read(ca));
+        connector.add_root_certificate(try!(Certificate::from_pem(&pem)));
+    }
+    match (&tls.cert, &tls.key) {
+        (&Some(ref cert), &Some(ref key)) => {
+            let cert = try!(fs::read(cert));
+            let key = try!(fs::read(key));
+            connector.identity(try!(Identity::from_pkcs8(&cert, &key)));
+        }
+        (&None, &None) => (),
+        _ => return Err(Error::Tls("certificate and key go together".into())),
+    }
+    let domain = match &*tls.domain {
+        "" => addr,
+        domain => domain,
+    };
+    match try!(connector.build()).connect(domain, tmp_tcp) {
+        Ok(stream) => Ok((Stream::Tls(stream), greet)),
+        Err(e) => Err(Error::Tls(e.to_string())),
+    }
 }
 
 /// Return current library version.
@@ -217,33 +632,106 @@ fn get_lib_version() -> u8 {
 }
 
 /// Send command package with actual command, e.g. quit, ping, query.
-fn send_cmd<W: Write>(mut s: &mut W, cmd: Command, _size: u64) -> Result<(), Error> {
-    try!(serialize_into(&mut s, &PkgType::Command));
-    try!(serialize_into(&mut s, &cmd));
+fn send_cmd<W: Write>(s: &mut W, cmd: Command, _size: u64) -> Result<(), Error> {
+    let mut packet = PacketWriter::new(PkgType::Command);
+    try!(packet.add(&cmd));
+    try!(packet.send(s));
     Ok(())
 }
 
-/// Match received packages to expected packages.
-fn receive(s: &mut TcpStream, cmd: PkgType) -> Result<(), Error> {
-    let status: PkgType = try!(deserialize_from(s.take(1024)));
+/// Receive the greeting the server sends after the connection was opened.
+fn receive_greeting<S: Read>(s: &mut S) -> Result<Greeting, Error> {
+    let packet = try!(receive(
+        s,
+        PkgType::Greet,
+        &mut Pushed::default(),
+        MAX_PACKET_SIZE
+    ));
+    Ok(try!(deserialize_from(packet.payload())))
+}
+
+/// Receive the answer to a login or resume and check the negotiated
+/// protocol settings.
+fn receive_acc_granted<S: Read>(s: &mut S) -> Result<(Negotiation, SessionInfo), Error> {
+    let packet = try!(read_packet(s, MAX_PACKET_SIZE));
+    let mut payload = packet.payload();
+    match packet.pkg {
+        PkgType::AccGranted => {
+            let negotiation: Negotiation = try!(deserialize_from(&mut payload));
+            let session: SessionInfo = try!(deserialize_from(&mut payload));
+            if negotiation.version < MIN_PROTOCOL_VERSION
+                || negotiation.version > PROTOCOL_VERSION
+                || !CLIENT_CAPABILITIES.contains(negotiation.capabilities)
+                || negotiation.max_packet_size > MAX_PACKET_SIZE
+            {
+                return Err(Error::ProtocolMismatch);
+            }
+            Ok((negotiation, session))
+        }
+        PkgType::AccDenied => Err(Error::Auth),
+        PkgType::TooManyConnections => Err(Error::TooManyConnections),
+        PkgType::Error => {
+            let err: ClientErrMsg = try!(deserialize_from(payload));
+            Err(err.into())
+        }
+        _ => Err(Error::UnexpectedPkg),
+    }
+}
 
-    if status == PkgType::Error {
-        let err: ClientErrMsg = try!(deserialize_from(s));
+/// Match received packages to expected packages. Notifications and other
+/// packages the server pushes in between are kept. Packages longer than
+/// `max_size` are refused.
+fn receive<S: Read>(
+    s: &mut S,
+    cmd: PkgType,
+    pushed: &mut Pushed,
+    max_size: u32,
+) -> Result<Packet, Error> {
+    receive_one_of(s, &[cmd], pushed, max_size)
+}
+
+/// Like `receive`, for commands answered by packages of several types.
+fn receive_one_of<S: Read>(
+    s: &mut S,
+    cmds: &[PkgType],
+    pushed: &mut Pushed,
+    max_size: u32,
+) -> Result<Packet, Error> {
+    let mut packet = try!(read_packet(&mut *s, max_size));
+    while try!(pushed.add(&packet)) {
+        packet = try!(read_packet(&mut *s, max_size));
+    }
+
+    if packet.pkg == PkgType::Error {
+        let err: ClientErrMsg = try!(deserialize_from(packet.payload()));
         return Err(Error::Server(err));
     }
 
-    if status != cmd {
-        match status {
-            PkgType::Ok => {}
-            PkgType::Response => {
-                let _: ResultSet = try!(deserialize_from(s));
+    if !cmds.contains(&packet.pkg) {
+        return Err(Error::UnexpectedPkg);
+    }
+    Ok(packet)
+}
+
+impl Pushed {
+    /// Keep a package the server pushed. Returns false for other packages.
+    fn add(&mut self, packet: &Packet) -> Result<bool, Error> {
+        match packet.pkg {
+            PkgType::Notification => {
+                let notification = try!(deserialize_from(packet.payload()));
+                self.notifications.push_back(notification);
             }
-            PkgType::Greet => {
-                let _: Greeting = try!(deserialize_from(s));
+            PkgType::ShuttingDown => {
+                self.shutdown = Some(try!(deserialize_from(packet.payload())));
             }
-            _ => {}
+            _ => return Ok(false),
         }
-        return Err(Error::UnexpectedPkg);
+        Ok(true)
     }
-    Ok(())
+}
+
+/// Quote a path as a string literal. Quotation marks in the path are
+/// doubled, backslashes escaped.
+fn quote_path(path: &str) -> String {
+    format!("'{}'", path.replace('\\', "\\\\").replace('\'', "''"))
 }
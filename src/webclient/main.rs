@@ -4,95 +4,562 @@ extern crate log;
 #[macro_use]
 extern crate nickel;
 extern crate cookie;
+extern crate docopt;
 extern crate hyper;
+extern crate hyper_native_tls;
+extern crate native_tls;
 extern crate plugin;
+extern crate rand;
+#[macro_use]
+extern crate serde;
+#[macro_use]
+extern crate serde_json;
 extern crate server;
 extern crate typemap;
 extern crate uosql;
 extern crate url;
 
 use cookie::Cookie as CookiePair;
+use cookie::SameSite;
+use docopt::Docopt;
+use hyper_native_tls::NativeTlsServer;
+use native_tls::{Identity, TlsAcceptor};
 use nickel::hyper::header::{Cookie, SetCookie};
 use nickel::hyper::method::Method;
+use nickel::status::StatusCode;
 use nickel::QueryString;
-use nickel::{HttpRouter, Nickel};
+use nickel::{HttpRouter, MiddlewareResult, Nickel, Response};
 use plugin::Extensible;
+use serde_json::Value;
+use server::dump;
+use server::parse;
+use server::parse::ast::{Col, ManipulationStmt, Query, SpecOps};
+use server::parse::token::Lit;
 use server::storage::SqlType;
-use std::collections::hash_map::Entry;
-use std::collections::HashMap;
-use std::io::Read;
-use std::net::Ipv4Addr;
-use std::ops::DerefMut;
+use std::cmp;
+use std::collections::{HashMap, VecDeque};
+use std::env;
+use std::fs;
+use std::io::{self, Read};
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::Path;
+use std::process;
 use std::str::FromStr;
 use std::sync::{Arc, Mutex};
+use std::time::Instant;
 use uosql::types::DataSet;
 use uosql::Connection;
 use uosql::Error;
 use url::form_urlencoded as urlencode;
 
+/// For console input, manages flags. Every flag can be given by an
+/// environment variable as well, the flag wins.
+const USAGE: &'static str = "
+Usage: web-client [options]
+
+Options:
+    --listen=<address>    Address to listen on, UOSQL_WEB_LISTEN
+                          [default: 127.0.0.1].
+    --port=<port>         Port to listen on, UOSQL_WEB_PORT [default: 6767].
+    --cert=<file>         Certificate chain in PEM, UOSQL_WEB_CERT. Serves
+                          HTTPS instead of HTTP, together with --key.
+    --key=<file>          Private key of the certificate in PKCS#8 PEM,
+                          UOSQL_WEB_KEY.
+    --server=<address>    Database server offered at login,
+                          UOSQL_WEB_SERVER [default: 127.0.0.1].
+    --server-port=<port>  Port of the database server offered at login,
+                          UOSQL_WEB_SERVER_PORT [default: 4242].
+    --templates=<dir>     Directory of the templates, UOSQL_WEB_TEMPLATES
+                          [default: src/webclient/templates].
+    --store=<file>        File of the saved queries, UOSQL_WEB_STORE
+                          [default: saved-queries.json].
+    -h, --help            Show this text.
+";
+
+#[derive(Debug, Deserialize)]
+struct Args {
+    flag_listen: Option<String>,
+    flag_port: Option<String>,
+    flag_cert: Option<String>,
+    flag_key: Option<String>,
+    flag_server: Option<String>,
+    flag_server_port: Option<String>,
+    flag_templates: Option<String>,
+    flag_store: Option<String>,
+}
+
+/// Where the web client listens and what it offers
+#[derive(Debug)]
+struct Settings {
+    listen: String,
+    port: u16,
+    /// The files of the certificate and key, for HTTPS
+    tls: Option<(String, String)>,
+    /// The database server filled in at login
+    server: String,
+    server_port: u16,
+    templates: String,
+    store: String,
+}
+
+impl Settings {
+    /// Returns the settings of the flags, else of the environment, else the
+    /// defaults. Exits for a port that is no number or a certificate
+    /// without key.
+    fn load() -> Settings {
+        // docopt fills in the defaults, which must not hide the environment
+        let args: Args = Docopt::new(USAGE)
+            .and_then(|d| d.deserialize())
+            .unwrap_or_else(|e| e.exit());
+        let given =
+            |flag: &str| env::args().any(|a| a == flag || a.starts_with(&format!("{}=", flag)));
+        let value = |flag: &str, var: &str, arg: Option<String>| {
+            if given(flag) {
+                arg
+            } else {
+                env::var(var).ok().or(arg)
+            }
+        };
+        let port = |flag: &str, var: &str, arg: Option<String>| {
+            let text = value(flag, var, arg).unwrap_or(String::new());
+            text.parse::<u16>().unwrap_or_else(|_| {
+                eprintln!("{} is no port: {}", flag, text);
+                process::exit(1)
+            })
+        };
+        let tls = match (
+            value("--cert", "UOSQL_WEB_CERT", args.flag_cert),
+            value("--key", "UOSQL_WEB_KEY", args.flag_key),
+        ) {
+            (Some(cert), Some(key)) => Some((cert, key)),
+            (None, None) => None,
+            _ => {
+                eprintln!("HTTPS needs both a certificate and a key");
+                process::exit(1)
+            }
+        };
+        Settings {
+            listen: value("--listen", "UOSQL_WEB_LISTEN", args.flag_listen).unwrap_or_default(),
+            port: port("--port", "UOSQL_WEB_PORT", args.flag_port),
+            tls: tls,
+            server: value("--server", "UOSQL_WEB_SERVER", args.flag_server).unwrap_or_default(),
+            server_port: port(
+                "--server-port",
+                "UOSQL_WEB_SERVER_PORT",
+                args.flag_server_port,
+            ),
+            templates: value("--templates", "UOSQL_WEB_TEMPLATES", args.flag_templates)
+                .unwrap_or_default(),
+            store: value("--store", "UOSQL_WEB_STORE", args.flag_store).unwrap_or_default(),
+        }
+    }
+
+    /// Returns the path of a template
+    fn template(&self, name: &str) -> String {
+        Path::new(&self.templates)
+            .join(name)
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    /// Returns the acceptor of HTTPS connections with the certificate and
+    /// key
+    fn acceptor(&self, cert: &str, key: &str) -> Result<TlsAcceptor, String> {
+        let cert = try!(fs::read(cert).map_err(|e| format!("Cannot read {}: {}", cert, e)));
+        let key = try!(fs::read(key).map_err(|e| format!("Cannot read {}: {}", key, e)));
+        let identity = try!(Identity::from_pkcs8(&cert, &key).map_err(|e| e.to_string()));
+        TlsAcceptor::new(identity).map_err(|e| e.to_string())
+    }
+}
+
+/// Rows of a result shown on a page, unless the page asks for others
+const PAGE_SIZE: usize = 100;
+/// Most rows shown on a page
+const MAX_PAGE_SIZE: usize = 1000;
+/// Queries kept in the history of a session
+const HISTORY_SIZE: usize = 20;
+/// Failed logins as a user name before it is locked out
+const MAX_FAILED_LOGINS: u32 = 5;
+/// Failed logins of a source, as any user, before it is locked out
+const MAX_FAILED_LOGINS_PER_SOURCE: u32 = 20;
+/// Seconds a source or user name cannot log in after too many failed
+/// logins, after which the failures are forgotten
+const LOCKOUT_SECS: u64 = 300;
+
 // Dummy key for typemap
 struct ConnKey;
 impl typemap::Key for ConnKey {
     type Value = Arc<Mutex<Connection>>;
 }
 
+// Key for all connections of the session
+struct ConnsKey;
+impl typemap::Key for ConnsKey {
+    type Value = Vec<NamedConnection>;
+}
+
+// Key for the session string of the request
+struct SessKey;
+impl typemap::Key for SessKey {
+    type Value = String;
+}
+
 #[derive(Debug)]
 struct Login {
     user: String,
     password: String,
 }
 
+/// A connection of a session to a server, under a name of the user
+#[derive(Clone)]
+struct NamedConnection {
+    name: String,
+    con: Arc<Mutex<Connection>>,
+}
+
+/// The result of a query of a single table whose rows are known by their
+/// primary key, so that its cells can be changed
+#[derive(Clone)]
+struct Editable {
+    /// The name of the connection the query ran on
+    target: String,
+    /// The query, run again after the changes are saved
+    sql: String,
+    table: String,
+    /// The columns of the primary key, all of them in the result
+    keys: Vec<String>,
+}
+
+/// Logins of a source or user name that failed or are still running
+struct Attempts {
+    count: u32,
+    /// Start of the last one
+    last: Instant,
+}
+
+/// Why a login was refused
+enum Refused {
+    /// Too many failed logins, the seconds until the next may be tried
+    Locked(u64),
+    /// The server refused the password, the attempts left
+    Failed(u32),
+    /// The server could not be asked, the login is not counted
+    Unreachable(Error),
+}
+
+/// The failed logins of the sources and of the user names, to stop the
+/// guessing of passwords. Counting them separately also stops a source
+/// trying many user names and many sources trying one.
+struct LoginThrottle {
+    /// Both in one lock, an attempt is counted for both at once
+    attempts: Mutex<(HashMap<IpAddr, Attempts>, HashMap<String, Attempts>)>,
+}
+
+impl LoginThrottle {
+    fn new() -> LoginThrottle {
+        LoginThrottle {
+            attempts: Mutex::new((HashMap::new(), HashMap::new())),
+        }
+    }
+
+    /// Connects to a server as the user, which checks the password. Every
+    /// login goes through here, so only the answers of the server count: a
+    /// refused password against the source and the user name, an accepted
+    /// one forgets the failures of the user name.
+    fn connect(
+        &self,
+        source: IpAddr,
+        login: &Login,
+        bind: String,
+        port: u16,
+    ) -> Result<Connection, Refused> {
+        let left = try!(self.reserve(source, &login.user).map_err(Refused::Locked));
+        match Connection::connect(bind, port, login.user.clone(), login.password.clone()) {
+            Ok(con) => {
+                self.succeed(source, &login.user);
+                Ok(con)
+            }
+            Err(Error::Auth) => Err(Refused::Failed(left)),
+            Err(e) => {
+                self.release(source, &login.user);
+                Err(Refused::Unreachable(e))
+            }
+        }
+    }
+
+    /// Counts a login of the source as the user as failed before it runs,
+    /// so that parallel logins cannot exceed the limits. Returns the
+    /// attempts left if it does fail, or the seconds until the source or
+    /// user name may try again.
+    fn reserve(&self, source: IpAddr, user: &str) -> Result<u32, u64> {
+        let mut attempts = self.attempts.lock().unwrap();
+        let (ref mut sources, ref mut users) = *attempts;
+        sources.retain(|_, a| a.last.elapsed().as_secs() < LOCKOUT_SECS);
+        users.retain(|_, a| a.last.elapsed().as_secs() < LOCKOUT_SECS);
+
+        let wait = |a: Option<&Attempts>, max: u32| {
+            a.filter(|a| a.count >= max)
+                .map(|a| LOCKOUT_SECS - a.last.elapsed().as_secs())
+        };
+        let locked = cmp::max(
+            wait(sources.get(&source), MAX_FAILED_LOGINS_PER_SOURCE),
+            wait(users.get(user), MAX_FAILED_LOGINS),
+        );
+        if let Some(secs) = locked {
+            return Err(secs);
+        }
+
+        let count = |a: &mut Attempts| {
+            a.count += 1;
+            a.last = Instant::now();
+            a.count
+        };
+        let new = || Attempts {
+            count: 0,
+            last: Instant::now(),
+        };
+        let by_source = count(sources.entry(source).or_insert_with(&new));
+        let by_user = count(users.entry(user.to_string()).or_insert_with(&new));
+        Ok(cmp::min(
+            MAX_FAILED_LOGINS_PER_SOURCE - by_source,
+            MAX_FAILED_LOGINS - by_user,
+        ))
+    }
+
+    /// Forgets the failures of a user name that logged in. The failures of
+    /// the source as other users still count.
+    fn succeed(&self, source: IpAddr, user: &str) {
+        let mut attempts = self.attempts.lock().unwrap();
+        if let Some(a) = attempts.0.get_mut(&source) {
+            a.count = a.count.saturating_sub(1);
+        }
+        attempts.1.remove(user);
+    }
+
+    /// Takes back a reserved login that neither failed nor succeeded, e.g.
+    /// because the server could not be reached
+    fn release(&self, source: IpAddr, user: &str) {
+        let mut attempts = self.attempts.lock().unwrap();
+        if let Some(a) = attempts.0.get_mut(&source) {
+            a.count = a.count.saturating_sub(1);
+        }
+        if let Some(a) = attempts.1.get_mut(user) {
+            a.count = a.count.saturating_sub(1);
+        }
+    }
+}
+
+/// A query saved under a name, which the link `/q/<id>` opens in the
+/// editor again
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct SavedQuery {
+    /// Random, so that the links of others cannot be guessed
+    id: String,
+    name: String,
+    sql: String,
+}
+
+/// The saved queries of the users, kept in a JSON file
+struct QueryStore {
+    path: String,
+    queries: Mutex<HashMap<String, Vec<SavedQuery>>>,
+}
+
+impl QueryStore {
+    /// Loads the saved queries of the file, none if it does not exist yet
+    fn open(path: &str) -> Result<QueryStore, String> {
+        let queries = match fs::read(path) {
+            Ok(json) => try!(serde_json::from_slice(&json).map_err(|e| e.to_string())),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e.to_string()),
+        };
+        Ok(QueryStore {
+            path: path.to_string(),
+            queries: Mutex::new(queries),
+        })
+    }
+
+    /// Returns the saved queries of a user, in the order they were saved
+    fn list(&self, user: &str) -> Vec<SavedQuery> {
+        let queries = self.queries.lock().unwrap();
+        queries.get(user).cloned().unwrap_or_default()
+    }
+
+    /// Returns the saved query of the id, whoever saved it. It is only ever
+    /// shown, not run for the one who follows its link.
+    fn find(&self, id: &str) -> Option<SavedQuery> {
+        let queries = self.queries.lock().unwrap();
+        queries
+            .values()
+            .flat_map(|q| q)
+            .find(|q| q.id == id)
+            .cloned()
+    }
+
+    /// Saves a query of a user under the name. A query saved under the
+    /// same name before is replaced, its link stays the same.
+    fn save(&self, user: &str, name: &str, sql: &str) -> Result<(), String> {
+        let mut queries = self.queries.lock().unwrap();
+        {
+            let saved = queries.entry(user.to_string()).or_insert(Vec::new());
+            match saved.iter_mut().find(|q| q.name == name) {
+                Some(query) => query.sql = sql.to_string(),
+                None => saved.push(SavedQuery {
+                    id: format!("{:016x}", rand::random::<u64>()),
+                    name: name.to_string(),
+                    sql: sql.to_string(),
+                }),
+            }
+        }
+        self.write(&queries)
+    }
+
+    /// Deletes a saved query of a user, the queries of others are refused
+    fn delete(&self, user: &str, id: &str) -> Result<(), String> {
+        let mut queries = self.queries.lock().unwrap();
+        {
+            let saved = queries.get_mut(user);
+            let pos = saved
+                .as_ref()
+                .and_then(|s| s.iter().position(|q| q.id == id));
+            match (saved, pos) {
+                (Some(saved), Some(pos)) => {
+                    saved.remove(pos);
+                }
+                _ => return Err("You have no saved query of that id.".into()),
+            }
+        }
+        self.write(&queries)
+    }
+
+    /// Writes the queries to a new file, which then replaces the file, so
+    /// that it is never written in part
+    fn write(&self, queries: &HashMap<String, Vec<SavedQuery>>) -> Result<(), String> {
+        let json = try!(serde_json::to_vec_pretty(queries).map_err(|e| e.to_string()));
+        let temp = format!("{}.new", self.path);
+        try!(fs::write(&temp, json).map_err(|e| e.to_string()));
+        fs::rename(&temp, &self.path).map_err(|e| e.to_string())
+    }
+}
+
+/// What is known about the execution of a query besides its result, shown
+/// under it
+struct Execution {
+    /// Milliseconds from sending the query to receiving the result
+    millis: f64,
+    /// The rows inserted, as counted by the server
+    affected: Option<i64>,
+    /// The `EXPLAIN` of a select
+    plan: Option<DataSet>,
+}
+
 /// A web based client that is able to connect to a server and saves session
 /// data in a cookie. Queries can be sent and the results are displayed in
-/// html tables. The user is able to logout.
+/// html tables, a page of rows at a time. A session may connect to several
+/// servers, queries run on the active one unless another target is chosen.
+/// The user is able to logout.
+///
+/// The same sessions are offered to scripts and single page applications
+/// by JSON endpoints below `/api/`:
+///
+/// - `POST /api/login` takes `{"user", "password", "bind", "port"}`, the
+///   last two may be left out, and sets the session cookie
+/// - `POST /api/query` takes `{"sql", "target"}`, the target may be left
+///   out, and returns the columns and rows of the result
+/// - `GET /api/tables?target=` returns the tables of the current database
+///
+/// Errors are answered with `{"error"}` and a status other than 200.
 fn main() {
+    // Lives as long as the web client, so that the handlers can share it
+    let settings: &'static Settings = Box::leak(Box::new(Settings::load()));
     let mut server = Nickel::new();
-    let map: HashMap<String, Arc<Mutex<Connection>>> = HashMap::new();
+    // The connections of every session, the active one first
+    let map: HashMap<String, Vec<NamedConnection>> = HashMap::new();
     let map = Arc::new(Mutex::new(map));
     let map2 = map.clone();
+    let map3 = map.clone();
+    let map4 = map.clone();
+    let map5 = map.clone();
+    let map6 = map.clone();
+    // Failed logins, to the first server as well as to further ones
+    let throttle = Arc::new(LoginThrottle::new());
+    let throttle2 = throttle.clone();
+    let throttle3 = throttle.clone();
+    // The result of the last query of every session, whose pages are shown
+    // without running the query again
+    let results: HashMap<String, DataSet> = HashMap::new();
+    let results = Arc::new(Mutex::new(results));
+    let results2 = results.clone();
+    let results3 = results.clone();
+    let results4 = results.clone();
+    let results5 = results.clone();
+    // Whether the result of a session can be edited, and how
+    let editables: HashMap<String, Editable> = HashMap::new();
+    let editables = Arc::new(Mutex::new(editables));
+    let editables2 = editables.clone();
+    let editables3 = editables.clone();
+    let editables4 = editables.clone();
+    // How the last query of every session went
+    let executions: HashMap<String, Execution> = HashMap::new();
+    let executions = Arc::new(Mutex::new(executions));
+    let executions2 = executions.clone();
+    // The saved queries of the users
+    let store = match QueryStore::open(&settings.store) {
+        Ok(store) => Arc::new(store),
+        Err(e) => {
+            eprintln!("Cannot load the saved queries of {}: {}", settings.store, e);
+            process::exit(1)
+        }
+    };
+    let store2 = store.clone();
+    let store3 = store.clone();
+    let store4 = store.clone();
+    // The last queries of every session, the newest first
+    let histories: HashMap<String, VecDeque<String>> = HashMap::new();
+    let histories = Arc::new(Mutex::new(histories));
+    let histories2 = histories.clone();
+    let histories3 = histories.clone();
 
     // Cookie managing
     server.utilize(middleware! { |req, res|
 
         // If login data has been posted, continue
-        if req.origin.method == Method::Post {
+        let path = req.path_without_query().unwrap_or("").to_string();
+        if req.origin.method == Method::Post && (path == "/login" || path == "/api/login") {
             return Ok(nickel::Action::Continue(res));
         }
 
         // Look for session string in Cookies
+        // (or find the matching UosqlDB-Cookie and extract session string)
         let sess = match req.origin.headers.get::<Cookie>() {
-            // If no Cookie found, go to Login
-            None => {
-                let m = HashMap::<i8, i8>::new();
-                return res.render("src/webclient/templates/login.tpl", &m);
-            }
-            // If there is a Cookie, eat it
-            // (or find the matching UosqlDB-Cookie and extract session string)
-            Some(cs) => {
-                if let Some(sess) = cs.to_cookie_jar(&[1u8]).find("UosqlDB") {
-                    sess.value
-                // There is a cookie, but it is not ours :'(
-                // Return to Login
-                } else {
-                    let m = HashMap::<i8, i8>::new();
-                    return res.render("src/webclient/templates/login.tpl", &m);
-                }
-            },
+            None => None,
+            Some(cs) => cs.to_cookie_jar(&[1u8]).find("UosqlDB").map(|c| c.value),
         };
 
         // We have a session string and look for the matching connection in
         // our Session-Connection map
         let guard = map.lock().unwrap();
-        match guard.get(&sess) {
+        match sess.as_ref().and_then(|s| guard.get(s)) {
+            // Scripts are not sent to the login page
+            None if path.starts_with("/api/") => {
+                let error = json!({ "error": "Not logged in." });
+                return send_json(res, StatusCode::Unauthorized, &error);
+            }
+            // No Cookie found, or none of ours: go to Login
+            None if sess.is_none() => {
+                let data = login_page_data(settings, "");
+                return res.render(settings.template("login.tpl"), &data);
+            }
             // No matching session: Old cookie
             None => {
-                let mut data = HashMap::new();
-                data.insert("err_msg", "Invalid Session");
-                return res.render("src/webclient/templates/login.tpl", &data);
+                let data = login_page_data(settings, "Invalid Session");
+                return res.render(settings.template("login.tpl"), &data);
             }
             // There is a connection, we are logged in, we can enter the site!
-            Some(con) => {
-                req.extensions_mut().insert::<ConnKey>(con.clone());
+            Some(conns) => {
+                req.extensions_mut().insert::<ConnKey>(conns[0].con.clone());
+                req.extensions_mut().insert::<ConnsKey>(conns.clone());
+                req.extensions_mut().insert::<SessKey>(sess.clone().unwrap());
                 return Ok(nickel::Action::Continue(res));
             }
         }
@@ -109,9 +576,8 @@ fn main() {
 
             // Not sufficiently filled in, return to Login with error msg
             if read < 15 {
-                let mut data = HashMap::new();
-                data.insert("err_msg", "No data given");
-                return res.render("src/webclient/templates/login.tpl", &data);
+                let data = login_page_data(settings, "No data given");
+                return res.render(settings.template("login.tpl"), &data);
             }
 
             // Extract login data from Post string
@@ -123,19 +589,20 @@ fn main() {
 
             // If eihter username or password are empty, return to Login page
             if username.is_none() || password.is_none()  {
-                let mut data = HashMap::new();
-                data.insert("err_msg", "Not all required fields given");
-                return res.render("src/webclient/templates/login.tpl", &data);
+                let data = login_page_data(settings, "Not all required fields given");
+                return res.render(settings.template("login.tpl"), &data);
             }
 
-            let mut connection = "127.0.0.1".to_string();
+            let mut connection = settings.server.clone();
             // Bind_in is never none, for inexplicable reasons
             if bind_in.clone().unwrap().len() > 8 {
                 connection = bind_in.unwrap();
                 test_bind(&connection);
             }
 
-            let port = port_in.unwrap_or("4242".into()).parse::<u16>().unwrap_or(4242);
+            let port = port_in
+                .and_then(|p| p.parse::<u16>().ok())
+                .unwrap_or(settings.server_port);
 
             // build Login struct
             let login = Login {
@@ -143,58 +610,30 @@ fn main() {
                 password: password.unwrap()
             };
 
-            // Generate new session string
-            let sess_str = login.user.clone(); // Dummy
-
-            // Try connect to db server
-            // Insert connection and session string into hashmap
-            let mut guard = map2.lock().unwrap();
-
-            // create new connections
-            match guard.deref_mut().entry(sess_str.clone()) {
-                Entry::Occupied(_) => {},
-                Entry::Vacant(v) => {
-                    let cres = Connection::connect(connection, port,
-                                                   login.user.clone(), login.password.clone());
-                    match cres {
-                        Err(e) => {
-                            let errstr = match e {
-                                // Connection error handling
-                                // TO DO: Wait for Display/Debug
-                                Error::AddrParse(_) => {
-                                    "Could not connect to specified server."
-                                },
-                                Error::Io(_) => {
-                                    "Connection failure. Try again later."
-                                },
-                                Error::Bincode(_) => {
-                                    "Could not readfsdfd data from server."
-                                },
-                                Error::UnexpectedPkg => {
-                                    "Unexpected Package."
-                                },
-                                Error::Auth => {
-                                    "Authentication failed."
-                                },
-                                Error::Server(_) => {
-                                    "Network Error."
-                                },
-                            };
-                            let mut data = HashMap::new();
-                            data.insert("err", errstr);
-                            return res.render("src/webclient/templates/error.tpl", &data);
-                        }
-                        Ok(c) => {
-                            v.insert(Arc::new(Mutex::new(c)));
-                        },
-                    }
+            let source = req.origin.remote_addr.ip();
+            // The first connection is named by the address
+            let name = format!("{}:{}", connection, port);
+            let sess_str = match throttle.connect(source, &login, connection, port) {
+                Ok(con) => open_session(&map2, name, con),
+                Err(Refused::Locked(secs)) => {
+                    *res.status_mut() = StatusCode::TooManyRequests;
+                    let data = login_page_data(settings, &locked_message(secs));
+                    return res.render(settings.template("login.tpl"), &data);
+                }
+                Err(Refused::Failed(left)) => {
+                    let data = login_page_data(settings, &failed_message(left));
+                    return res.render(settings.template("login.tpl"), &data);
+                }
+                Err(Refused::Unreachable(e)) => {
+                    let mut data = HashMap::new();
+                    data.insert("err", connect_error(&e));
+                    return res.render(settings.template("error.tpl"), &data);
                 }
             };
 
             // Set a Cookie with the session string as its value
             // sess_str is set to a value here, so we can safely unwrap
-            let keks = CookiePair::new("UosqlDB".to_owned(), sess_str.clone());
-            res.headers_mut().set(SetCookie(vec![keks.to_string()]));
+            res.headers_mut().set(SetCookie(vec![session_cookie(&sess_str)]));
 
             // Redirect to the greeting page
             *res.status_mut() = nickel::status::StatusCode::Found;
@@ -203,19 +642,120 @@ fn main() {
         },
     );
 
-    // Disconnect from server
+    // Connect the session to another server, which becomes the active one
+    server.post(
+        "/connect",
+        middleware! { |req, mut res|
+
+            let sess = req.extensions().get::<SessKey>().unwrap().clone();
+            let mut form = String::new();
+            if req.origin.read_to_string(&mut form).is_err() {
+                let mut data = HashMap::new();
+                data.insert("err", "No data given");
+                return res.render(settings.template("error.tpl"), &data);
+            }
+            let pairs = urlencode::parse(form.as_bytes());
+            let field = |key: &str| {
+                pairs.clone().into_iter()
+                    .find(|e| e.0 == key)
+                    .map(|e| e.1.trim().to_string())
+                    .unwrap_or(String::new())
+            };
+            let mut bind = field("bind");
+            if bind.is_empty() {
+                bind = settings.server.clone();
+            }
+            let port = field("port").parse::<u16>().unwrap_or(settings.server_port);
+            let mut name = field("name");
+            if name.is_empty() {
+                name = format!("{}:{}", bind, port);
+            }
+            if req.extensions().get::<ConnsKey>().unwrap().iter().any(|c| c.name == name) {
+                let mut data = HashMap::new();
+                data.insert("err", "There is a connection of that name already.");
+                return res.render(settings.template("error.tpl"), &data);
+            }
+
+            let source = req.origin.remote_addr.ip();
+            let login = Login {
+                user: field("user"),
+                password: field("password"),
+            };
+
+            // Connecting may take a while, the other sessions need not wait
+            let con = match throttle2.connect(source, &login, bind, port) {
+                Ok(c) => c,
+                Err(Refused::Locked(secs)) => {
+                    *res.status_mut() = StatusCode::TooManyRequests;
+                    let mut data = HashMap::new();
+                    data.insert("err", locked_message(secs));
+                    return res.render(settings.template("error.tpl"), &data);
+                }
+                Err(Refused::Failed(left)) => {
+                    let mut data = HashMap::new();
+                    data.insert("err", failed_message(left));
+                    return res.render(settings.template("error.tpl"), &data);
+                }
+                Err(Refused::Unreachable(e)) => {
+                    let mut data = HashMap::new();
+                    data.insert("err", connect_error(&e));
+                    return res.render(settings.template("error.tpl"), &data);
+                }
+            };
+            if let Some(conns) = map3.lock().unwrap().get_mut(&sess) {
+                conns.insert(0, NamedConnection {
+                    name: name,
+                    con: Arc::new(Mutex::new(con)),
+                });
+            }
+
+            *res.status_mut() = nickel::status::StatusCode::Found;
+            res.headers_mut().set_raw("location", vec![b"/".to_vec()]);
+            return res.send("");
+        },
+    );
+
+    // Make another connection of the session the active one
+    server.get(
+        "/switch",
+        middleware! { |req, mut res|
+
+            let sess = req.extensions().get::<SessKey>().unwrap().clone();
+            let name = req.query().get("name").unwrap_or("").to_string();
+            if let Some(conns) = map4.lock().unwrap().get_mut(&sess) {
+                if let Some(pos) = conns.iter().position(|c| c.name == name) {
+                    let active = conns.remove(pos);
+                    conns.insert(0, active);
+                }
+            }
+
+            *res.status_mut() = nickel::status::StatusCode::Found;
+            res.headers_mut().set_raw("location", vec![b"/".to_vec()]);
+            return res.send("");
+        },
+    );
+
+    // Disconnect from all servers
     server.get(
         "/logout",
         middleware! { |req, mut res|
 
-            let mut con = req.extensions().get::<ConnKey>().unwrap().lock().unwrap();
             let mut data = HashMap::new();
+            {
+                let con = req.extensions().get::<ConnKey>().unwrap().lock().unwrap();
+                data.insert("name", con.get_username().to_string());
+            }
 
-            data.insert("name", con.get_username().to_string());
-
-            match con.quit(){
-                Ok(_) => { },
-                Err(_) => error!("Connection could not be quit."),
+            for named in req.extensions().get::<ConnsKey>().unwrap() {
+                match named.con.lock().unwrap().quit() {
+                    Ok(_) => { },
+                    Err(_) => error!("Connection {} could not be quit.", named.name),
+                }
+            }
+            if let Some(sess) = req.extensions().get::<SessKey>() {
+                map5.lock().unwrap().remove(sess);
+                results2.lock().unwrap().remove(sess);
+                histories2.lock().unwrap().remove(sess);
             }
 
             // Remove Cookie
@@ -229,7 +769,7 @@ fn main() {
                 },
             };
 
-            return res.render("src/webclient/templates/logout.tpl", &data);
+            return res.render(settings.template("logout.tpl"), &data);
         },
     );
 
@@ -238,35 +778,91 @@ fn main() {
         "/",
         middleware! { |req, res|
 
-            // Look for connection
-            let tmp = req.extensions().get::<ConnKey>().unwrap().clone();
-            let mut con = tmp.lock().unwrap();
+            // Look for connections, queries run on the active one unless
+            // another target is chosen
+            let active = req.extensions().get::<ConnKey>().unwrap().clone();
+            let conns = req.extensions().get::<ConnsKey>().unwrap().clone();
+            let target = find_target(&conns, req.query().get("target")).clone();
+
+            let sess = req.extensions().get::<SessKey>().unwrap().clone();
 
             let mut data = HashMap::new();
 
+            // The page of the result to show, counted from 1, and its rows
+            let page = req.query().get("page").and_then(|p| p.parse::<usize>().ok());
+            let limit = req.query()
+                .get("limit")
+                .and_then(|l| l.parse::<usize>().ok())
+                .unwrap_or(PAGE_SIZE);
+            let limit = cmp::min(cmp::max(limit, 1), MAX_PAGE_SIZE);
+
+            let mut results = results.lock().unwrap();
+            let mut histories = histories.lock().unwrap();
+            let mut editables = editables.lock().unwrap();
+            let mut executions = executions.lock().unwrap();
             let query = req.query().get("sql");
             if !query.is_none() {
-                let mut result = match con.execute(query.unwrap().trim().to_string()) {
+                // failed queries are kept too, to be corrected
+                let history = histories.entry(sess.clone()).or_insert(VecDeque::new());
+                remember(history, query.unwrap().trim());
+                let mut con = target.con.lock().unwrap();
+                let start = Instant::now();
+                let executed = con.execute(query.unwrap().trim().to_string());
+                let millis = start.elapsed().as_secs_f64() * 1000.0;
+                let mut result = match executed {
                     Ok(r) => r,
                     Err(e) => {
                         let errstr = match e {
-                            Error::Io(_) => "Connection failure. Try again later.",
-                            Error::Bincode(_) => "Could not read data from server.",
-                            Error::UnexpectedPkg => "Received unexpected package.",
-                            Error::Server(_) => "Server error.",
-                            _ => "Unexpected behaviour during execute().",
+                            Error::Io(_) => "Connection failure. Try again later.".into(),
+                            Error::Bincode(_) => "Could not read data from server.".into(),
+                            Error::UnexpectedPkg => "Received unexpected package.".into(),
+                            // e.g. the violated constraint
+                            Error::Server(ref err) => err.to_string(),
+                            _ => "Unexpected behaviour during execute().".into(),
                         };
                         let mut data = HashMap::new();
                         data.insert("err", errstr);
-                        return res.render("src/webclient/templates/error.tpl", &data);
+                        return res.render(settings.template("error.tpl"), &data);
                     }
                 };
 
-                let res_output = display_html(&mut result);
-                data.insert("result", res_output);
+                match find_editable(&mut con, &target.name, query.unwrap().trim(), &mut result) {
+                    Some(editable) => editables.insert(sess.clone(), editable),
+                    None => editables.remove(&sess),
+                };
+                let execution = Execution {
+                    millis: millis,
+                    affected: inserted_count(&mut result),
+                    plan: explain(&mut con, query.unwrap().trim()),
+                };
+                executions.insert(sess.clone(), execution);
+                results.insert(sess.clone(), result);
+            }
+            // A new result starts on its first page
+            if query.is_some() || page.is_some() {
+                if let Some(result) = results.get_mut(&sess) {
+                    let editable = editables.get(&sess);
+                    let mut res_output = display_html(result, page.unwrap_or(1), limit, editable);
+                    if let Some(execution) = executions.get_mut(&sess) {
+                        res_output.push_str(&display_execution_html(execution, result));
+                    }
+                    data.insert("result", res_output);
+                }
+            }
+            // A saved query opened by its link is put into the editor, it
+            // only runs once the user sends it
+            if let Some(saved) = req.query().get("open").and_then(|id| store.find(id)) {
+                data.insert("editor", saved.sql);
             }
+            if let Some(history) = histories.get(&sess) {
+                data.insert("history", display_history_html(history));
+            }
+            data.insert("connections", display_connections_html(&conns));
+            data.insert("targets", display_targets_html(&conns));
 
             // Current display with short welcome message
+            let con = active.lock().unwrap();
+            data.insert("saved", display_saved_html(&store.list(con.get_username())));
             let version = con.get_version().to_string();
             let port = con.get_port().to_string();
 
@@ -275,11 +871,669 @@ fn main() {
             data.insert("bind", con.get_ip().to_string());
             data.insert("port", port);
             data.insert("msg", con.get_message().to_string());
-            return res.render("src/webclient/templates/main.tpl", &data);
+            return res.render(settings.template("main.tpl"), &data);
+        },
+    );
+
+    // Download of the last result as a file
+    server.get(
+        "/export",
+        middleware! { |req, mut res|
+
+            let sess = req.extensions().get::<SessKey>().unwrap().clone();
+            let (content_type, extension) = match req.query().get("format").unwrap_or("csv") {
+                "csv" => ("text/csv; charset=utf-8", "csv"),
+                "json" => ("application/json", "json"),
+                _ => {
+                    let mut data = HashMap::new();
+                    data.insert("err", "Results can be downloaded as csv or json.");
+                    return res.render(settings.template("error.tpl"), &data);
+                }
+            };
+
+            // The result is taken out while it is sent, so that the other
+            // sessions need not wait
+            let result = results3.lock().unwrap().remove(&sess);
+            let mut result = match result {
+                Some(r) => r,
+                None => {
+                    let mut data = HashMap::new();
+                    data.insert("err", "There is no result to download.");
+                    return res.render(settings.template("error.tpl"), &data);
+                }
+            };
+
+            let disposition = format!("attachment; filename=\"result.{}\"", extension);
+            res.headers_mut().set_raw("content-type", vec![content_type.as_bytes().to_vec()]);
+            res.headers_mut().set_raw("content-disposition", vec![disposition.into_bytes()]);
+            let mut stream = try!(res.start());
+            let written = if extension == "csv" {
+                result.write_csv(&mut stream)
+            } else {
+                result.write_json(&mut stream)
+            };
+            if let Err(e) = written {
+                error!("Result could not be sent: {}", e);
+            }
+            results3.lock().unwrap().entry(sess).or_insert(result);
+            return Ok(nickel::Action::Halt(stream));
         },
     );
 
-    server.listen("127.0.0.1:6767");
+    // Login of the API, the session is the one of the html pages
+    server.post(
+        "/api/login",
+        middleware! { |req, mut res|
+
+            let mut body = String::new();
+            let _ = req.origin.read_to_string(&mut body);
+            let request = serde_json::from_str::<Value>(&body).unwrap_or(Value::Null);
+            let field = |key: &str| request.get(key).and_then(|v| v.as_str());
+            let login = match (field("user"), field("password")) {
+                (Some(user), Some(password)) => Login {
+                    user: user.to_string(),
+                    password: password.to_string(),
+                },
+                _ => {
+                    let error = json!({ "error": "Not all required fields given." });
+                    return send_json(res, StatusCode::BadRequest, &error);
+                }
+            };
+            let bind = field("bind").unwrap_or(&settings.server).to_string();
+            let port = request
+                .get("port")
+                .and_then(|p| p.as_u64())
+                .map_or(settings.server_port, |p| p as u16);
+
+            let source = req.origin.remote_addr.ip();
+            // The first connection is named by the address
+            let name = format!("{}:{}", bind, port);
+            let sess_str = match throttle3.connect(source, &login, bind, port) {
+                Ok(con) => open_session(&map6, name, con),
+                Err(Refused::Locked(secs)) => {
+                    res.headers_mut().set_raw("retry-after", vec![secs.to_string().into_bytes()]);
+                    let error = json!({ "error": locked_message(secs), "retry_after": secs });
+                    return send_json(res, StatusCode::TooManyRequests, &error);
+                }
+                Err(Refused::Failed(left)) => {
+                    let error = json!({ "error": failed_message(left), "attempts_left": left });
+                    return send_json(res, StatusCode::Unauthorized, &error);
+                }
+                Err(Refused::Unreachable(e)) => {
+                    let error = json!({ "error": connect_error(&e) });
+                    return send_json(res, StatusCode::BadGateway, &error);
+                }
+            };
+
+            res.headers_mut().set(SetCookie(vec![session_cookie(&sess_str)]));
+            return send_json(res, StatusCode::Ok, &json!({ "session": sess_str }));
+        },
+    );
+
+    // Query of the API, whose result is the last one of the session like
+    // those of the html pages
+    server.post(
+        "/api/query",
+        middleware! { |req, res|
+
+            let conns = req.extensions().get::<ConnsKey>().unwrap().clone();
+            let sess = req.extensions().get::<SessKey>().unwrap().clone();
+            let mut body = String::new();
+            let _ = req.origin.read_to_string(&mut body);
+            let request = serde_json::from_str::<Value>(&body).unwrap_or(Value::Null);
+            let query = match request.get("sql").and_then(|q| q.as_str()) {
+                Some(q) => q.trim().to_string(),
+                None => {
+                    let error = json!({ "error": "No query given." });
+                    return send_json(res, StatusCode::BadRequest, &error);
+                }
+            };
+            let target = find_target(&conns, request.get("target").and_then(|t| t.as_str()))
+                .con
+                .clone();
+
+            remember(
+                histories3.lock().unwrap().entry(sess.clone()).or_insert(VecDeque::new()),
+                &query,
+            );
+            let executed = target.lock().unwrap().execute(query);
+            let mut result = match executed {
+                Ok(r) => r,
+                Err(e) => {
+                    let error = json!({ "error": e.to_string() });
+                    return send_json(res, StatusCode::BadGateway, &error);
+                }
+            };
+            if is_failed(&mut result) {
+                let error = json!({ "error": "The server could not execute the query." });
+                return send_json(res, StatusCode::BadRequest, &error);
+            }
+            let columns: Vec<Value> = (0..result.get_col_cnt())
+                .map(|i| json!({
+                    "name": result.get_col_name(i).unwrap_or(""),
+                    "type": result.get_type_by_idx(i).map(|t| type_name(&t)),
+                }))
+                .collect();
+            let answer = json!({ "columns": columns, "rows": rows_json(&mut result) });
+            // the result is shown on the html pages, but not for editing
+            editables2.lock().unwrap().remove(&sess);
+            executions2.lock().unwrap().remove(&sess);
+            results4.lock().unwrap().insert(sess, result);
+            return send_json(res, StatusCode::Ok, &answer);
+        },
+    );
+
+    // Tables of the current database of a connection
+    server.get(
+        "/api/tables",
+        middleware! { |req, res|
+
+            let conns = req.extensions().get::<ConnsKey>().unwrap().clone();
+            let target = find_target(&conns, req.query().get("target")).con.clone();
+            let executed = target.lock().unwrap().execute("SHOW TABLES".into());
+            let mut result = match executed {
+                Ok(r) => r,
+                Err(e) => {
+                    let error = json!({ "error": e.to_string() });
+                    return send_json(res, StatusCode::BadGateway, &error);
+                }
+            };
+            if is_failed(&mut result) {
+                let error = json!({ "error": "No database is in use." });
+                return send_json(res, StatusCode::BadRequest, &error);
+            }
+            return send_json(res, StatusCode::Ok, &json!({ "tables": rows_json(&mut result) }));
+        },
+    );
+
+    // Saving of a query of the editor under a name
+    server.post(
+        "/queries",
+        middleware! { |req, mut res|
+
+            let user = req
+                .extensions()
+                .get::<ConnKey>()
+                .unwrap()
+                .lock()
+                .unwrap()
+                .get_username()
+                .to_string();
+            let mut form = String::new();
+            let _ = req.origin.read_to_string(&mut form);
+            let pairs = urlencode::parse(form.as_bytes());
+            let field = |key: &str| {
+                pairs.clone().into_iter()
+                    .find(|e| e.0 == key)
+                    .map(|e| e.1.trim().to_string())
+                    .unwrap_or(String::new())
+            };
+            let (name, sql) = (field("save_name"), field("sql"));
+            if name.is_empty() || sql.is_empty() {
+                let mut data = HashMap::new();
+                data.insert("err", "A query is saved with a name.".to_string());
+                return res.render(settings.template("error.tpl"), &data);
+            }
+            if let Err(e) = store2.save(&user, &name, &sql) {
+                let mut data = HashMap::new();
+                data.insert("err", format!("Could not save the query: {}", e));
+                return res.render(settings.template("error.tpl"), &data);
+            }
+
+            *res.status_mut() = nickel::status::StatusCode::Found;
+            res.headers_mut().set_raw("location", vec![b"/".to_vec()]);
+            return res.send("");
+        },
+    );
+
+    // Deletion of a saved query, of the user only
+    server.post(
+        "/queries/delete",
+        middleware! { |req, mut res|
+
+            let user = req
+                .extensions()
+                .get::<ConnKey>()
+                .unwrap()
+                .lock()
+                .unwrap()
+                .get_username()
+                .to_string();
+            let mut form = String::new();
+            let _ = req.origin.read_to_string(&mut form);
+            let id = urlencode::parse(form.as_bytes())
+                .into_iter()
+                .find(|e| e.0 == "id")
+                .map(|e| e.1.to_string())
+                .unwrap_or(String::new());
+            if let Err(e) = store3.delete(&user, &id) {
+                let mut data = HashMap::new();
+                data.insert("err", format!("Could not delete the query: {}", e));
+                return res.render(settings.template("error.tpl"), &data);
+            }
+
+            *res.status_mut() = nickel::status::StatusCode::Found;
+            res.headers_mut().set_raw("location", vec![b"/".to_vec()]);
+            return res.send("");
+        },
+    );
+
+    // Link opening a saved query in the editor of whoever follows it, who
+    // may then run it
+    server.get(
+        "/q/:id",
+        middleware! { |req, mut res|
+
+            let query = match store4.find(req.param("id").unwrap_or("")) {
+                Some(q) => q,
+                None => {
+                    let mut data = HashMap::new();
+                    data.insert("err", "There is no such saved query.");
+                    return res.render(settings.template("error.tpl"), &data);
+                }
+            };
+            let encoded: String = urlencode::byte_serialize(query.id.as_bytes()).collect();
+            let location = format!("/?open={}", encoded);
+            *res.status_mut() = nickel::status::StatusCode::Found;
+            res.headers_mut().set_raw("location", vec![location.into_bytes()]);
+            return res.send("");
+        },
+    );
+
+    // Saving of the changed cells of an editable result
+    server.post(
+        "/save",
+        middleware! { |req, mut res|
+
+            let sess = req.extensions().get::<SessKey>().unwrap().clone();
+            let conns = req.extensions().get::<ConnsKey>().unwrap().clone();
+            let mut form = String::new();
+            let _ = req.origin.read_to_string(&mut form);
+            let pairs = urlencode::parse(form.as_bytes());
+            let field = |key: &str| {
+                pairs.clone()
+                    .into_iter()
+                    .find(|e| e.0 == key)
+                    .map(|e| e.1.to_string())
+                    .unwrap_or(String::new())
+            };
+
+            let editable = editables3.lock().unwrap().get(&sess).cloned();
+            let editable = match editable {
+                Some(e) => e,
+                None => {
+                    let mut data = HashMap::new();
+                    data.insert("err", "The result cannot be edited.");
+                    return res.render(settings.template("error.tpl"), &data);
+                }
+            };
+            let target = match conns.iter().find(|c| c.name == editable.target) {
+                Some(c) => c.con.clone(),
+                None => {
+                    let mut data = HashMap::new();
+                    data.insert("err", "The connection of the result is closed.");
+                    return res.render(settings.template("error.tpl"), &data);
+                }
+            };
+
+            // The result is taken out while the changes are saved, so that
+            // the other sessions need not wait
+            let result = results5.lock().unwrap().remove(&sess);
+            let mut result = match result {
+                Some(r) => r,
+                None => {
+                    let mut data = HashMap::new();
+                    data.insert("err", "There is no result to edit.");
+                    return res.render(settings.template("error.tpl"), &data);
+                }
+            };
+            let mut statements = Vec::new();
+            let mut error = None;
+            for (name, value) in pairs.clone().into_iter() {
+                // the cells are named r<row>c<column>
+                let position: Vec<usize> = name.trim_start_matches('r')
+                    .split('c')
+                    .filter_map(|n| n.parse().ok())
+                    .collect();
+                if !name.starts_with('r') || position.len() != 2 {
+                    continue;
+                }
+                match update_statement(&mut result, &editable, position[0], position[1], &value) {
+                    Ok(Some(statement)) => statements.push(statement),
+                    Ok(None) => {}
+                    Err(e) => {
+                        error = Some(e);
+                        break;
+                    }
+                }
+            }
+
+            // All changes are saved or none
+            let mut con = target.lock().unwrap();
+            if error.is_none() && !statements.is_empty() {
+                statements.insert(0, "BEGIN".into());
+                statements.push("COMMIT".into());
+                for statement in &statements {
+                    let failed = match con.execute(statement.clone()) {
+                        Ok(mut r) => is_failed(&mut r),
+                        Err(_) => true,
+                    };
+                    if failed {
+                        error = Some(format!("Could not save the change: {}", statement));
+                        let _ = con.execute("ROLLBACK".into());
+                        break;
+                    }
+                }
+            }
+            if let Some(e) = error {
+                results5.lock().unwrap().entry(sess).or_insert(result);
+                let mut data = HashMap::new();
+                data.insert("err", e);
+                return res.render(settings.template("error.tpl"), &data);
+            }
+
+            // The query is run again to show the saved rows
+            if !statements.is_empty() {
+                if let Ok(mut r) = con.execute(editable.sql.clone()) {
+                    if find_editable(&mut con, &editable.target, &editable.sql, &mut r).is_none() {
+                        editables4.lock().unwrap().remove(&sess);
+                    }
+                    result = r;
+                }
+            }
+            results5.lock().unwrap().insert(sess, result);
+
+            let location = format!("/?page={}&limit={}", field("page"), field("limit"));
+            *res.status_mut() = nickel::status::StatusCode::Found;
+            res.headers_mut().set_raw("location", vec![location.into_bytes()]);
+            return res.send("");
+        },
+    );
+
+    let address = (&settings.listen[..], settings.port);
+    let listening = match settings.tls {
+        Some((ref cert, ref key)) => match settings.acceptor(cert, key) {
+            Ok(acceptor) => server.listen_https(address, NativeTlsServer::from(acceptor)),
+            Err(e) => {
+                eprintln!("Cannot serve HTTPS: {}", e);
+                process::exit(1)
+            }
+        },
+        None => server.listen(address),
+    };
+    if let Err(e) = listening {
+        eprintln!(
+            "Cannot listen on {}:{}: {}",
+            settings.listen, settings.port, e
+        );
+        process::exit(1)
+    }
+}
+
+/// Returns the message of a failed login with the attempts left
+fn failed_message(left: u32) -> String {
+    if left == 0 {
+        format!(
+            "Authentication failed. Too many failed logins, try again in {} seconds.",
+            LOCKOUT_SECS
+        )
+    } else {
+        format!("Authentication failed. {} attempts left.", left)
+    }
+}
+
+/// Returns the message of a login refused for too many failures
+fn locked_message(secs: u64) -> String {
+    format!("Too many failed logins, try again in {} seconds.", secs)
+}
+
+/// Returns the data of the login page, whose server is the default one
+fn login_page_data(settings: &Settings, err_msg: &str) -> HashMap<&'static str, String> {
+    let mut data = HashMap::new();
+    data.insert("err_msg", err_msg.to_string());
+    data.insert("bind", settings.server.clone());
+    data.insert("port", settings.server_port.to_string());
+    data
+}
+
+/// Opens a new session with the connection of a login, also if the user
+/// has one already, under the name of the connection. Returns the session
+/// string, random so that the sessions of others cannot be guessed.
+fn open_session(
+    map: &Mutex<HashMap<String, Vec<NamedConnection>>>,
+    name: String,
+    c: Connection,
+) -> String {
+    let sess_str = format!("{:032x}", rand::random::<u128>());
+
+    // Insert connection and session string into hashmap
+    map.lock().unwrap().insert(
+        sess_str.clone(),
+        vec![NamedConnection {
+            name: name,
+            con: Arc::new(Mutex::new(c)),
+        }],
+    );
+    sess_str
+}
+
+/// Returns the cookie holding the session string. Other sites cannot send
+/// it along with their forms, and scripts of pages cannot read it.
+fn session_cookie(sess_str: &str) -> String {
+    let mut keks = CookiePair::new("UosqlDB".to_owned(), sess_str.to_owned());
+    keks.set_same_site(SameSite::Lax);
+    keks.set_http_only(true);
+    keks.to_string()
+}
+
+/// Returns the connection of the session of that name, the active one if
+/// there is none or no name is given
+fn find_target<'a>(conns: &'a [NamedConnection], name: Option<&str>) -> &'a NamedConnection {
+    name.and_then(|n| conns.iter().find(|c| c.name == n))
+        .unwrap_or(&conns[0])
+}
+
+/// Returns the rows inserted by a statement, of the row the server answers
+/// INSERT and COPY with.
+fn inserted_count(result: &mut DataSet) -> Option<i64> {
+    if result.get_col_cnt() != 1
+        || result.get_col_name(0) != Some("rows")
+        || result.get_description_by_idx(0) != Some("rows inserted")
+    {
+        return None;
+    }
+    result.first();
+    let count = if result.next() {
+        result.next_bigint_by_idx(0)
+    } else {
+        None
+    };
+    result.first();
+    count
+}
+
+/// Returns the plan of a select, None for other statements and if it
+/// cannot be explained.
+fn explain(con: &mut Connection, sql: &str) -> Option<DataSet> {
+    match parse::parse(sql) {
+        Ok(Query::ManipulationStmt(ManipulationStmt::Select(_)))
+        | Ok(Query::ManipulationStmt(ManipulationStmt::Compound(_))) => {}
+        _ => return None,
+    }
+    let mut plan = match con.execute(format!("EXPLAIN {}", sql)) {
+        Ok(p) => p,
+        Err(_) => return None,
+    };
+    if is_failed(&mut plan) {
+        None
+    } else {
+        Some(plan)
+    }
+}
+
+/// Returns the table a query selects from if its result can be edited: it
+/// selects columns of a single table as they are, and those of the primary
+/// key of the table are among them.
+fn find_editable(
+    con: &mut Connection,
+    target: &str,
+    sql: &str,
+    result: &mut DataSet,
+) -> Option<Editable> {
+    let select = match parse::parse(sql) {
+        Ok(Query::ManipulationStmt(ManipulationStmt::Select(s))) => s,
+        _ => return None,
+    };
+    let plain = select.target.iter().all(|t| match t.col {
+        Col::Every => true,
+        Col::Specified(_) => t.rename.is_none(),
+        _ => false,
+    });
+    let grouped = match select.spec_op {
+        Some(SpecOps::GroupBy(_)) => true,
+        _ => false,
+    };
+    if select.tid.len() != 1 || !select.joins.is_empty() || !plain || grouped {
+        return None;
+    }
+    let table = select.tid[0].clone();
+
+    let mut described = match con.execute(format!("DESCRIBE {}", dump::name(&table))) {
+        Ok(d) => d,
+        Err(_) => return None,
+    };
+    if is_failed(&mut described) {
+        return None;
+    }
+    let mut keys = Vec::new();
+    for i in 0..described.get_col_cnt() {
+        if described.get_is_primary_key_by_idx(i) == Some(true) {
+            keys.push(described.get_col_name(i).unwrap_or("").to_string());
+        }
+    }
+    let names: Vec<String> = (0..result.get_col_cnt())
+        .filter_map(|i| result.get_col_name(i).map(|n| n.to_string()))
+        .collect();
+    if keys.is_empty() || !keys.iter().all(|k| names.contains(k)) {
+        return None;
+    }
+    Some(Editable {
+        target: target.to_string(),
+        sql: sql.to_string(),
+        table: table,
+        keys: keys,
+    })
+}
+
+/// Returns the statement changing a cell of an editable result to the
+/// value, None if the value is unchanged. An empty value is NULL if the
+/// column allows it.
+fn update_statement(
+    table: &mut DataSet,
+    editable: &Editable,
+    row: usize,
+    col: usize,
+    value: &str,
+) -> Result<Option<String>, String> {
+    let name = table.get_col_name(col).unwrap_or("").to_string();
+    if row >= table.get_line_cnt() || col >= table.get_col_cnt() || editable.keys.contains(&name) {
+        return Err("The cell cannot be edited.".into());
+    }
+    table.seek(row);
+    table.next();
+    if cell_value(table, col).unwrap_or(String::new()) == value {
+        return Ok(None);
+    }
+    let new = if value.is_empty() && table.get_allow_null_by_idx(col) == Some(true) {
+        "NULL".to_string()
+    } else {
+        dump::literal(&try!(literal(table.get_type_by_idx(col), value)))
+    };
+
+    // The row is the one of the values of its primary key
+    let mut conditions = Vec::new();
+    for key in &editable.keys {
+        let idx = match table.get_col_idx(key.clone()) {
+            Some(idx) => idx,
+            None => return Err("The cell cannot be edited.".into()),
+        };
+        let text = cell_value(table, idx).unwrap_or(String::new());
+        let lit = try!(literal(table.get_type_by_idx(idx), &text));
+        conditions.push(format!("{} = {}", dump::name(key), dump::literal(&lit)));
+    }
+    Ok(Some(format!(
+        "UPDATE {} SET {} = {} WHERE {}",
+        dump::name(&editable.table),
+        dump::name(&name),
+        new,
+        conditions.join(" AND ")
+    )))
+}
+
+/// Returns the literal of a value as entered for a column of the type
+fn literal(sql_type: Option<SqlType>, text: &str) -> Result<Lit, String> {
+    let text = text.trim();
+    match sql_type {
+        Some(SqlType::Int) | Some(SqlType::BigInt) => text
+            .parse()
+            .map(Lit::Int)
+            .map_err(|_| format!("'{}' is not a whole number.", text)),
+        Some(SqlType::Float) | Some(SqlType::Double) => text
+            .parse()
+            .map(Lit::Float)
+            .map_err(|_| format!("'{}' is not a number.", text)),
+        Some(SqlType::Bool) => match &text.to_lowercase()[..] {
+            "true" | "1" => Ok(Lit::Bool(1)),
+            "false" | "0" => Ok(Lit::Bool(0)),
+            _ => Err(format!("'{}' is neither true nor false.", text)),
+        },
+        _ => Ok(Lit::String(text.to_string())),
+    }
+}
+
+/// Sends a JSON value as the answer of the API
+fn send_json<'mw>(
+    mut res: Response<'mw>,
+    status: StatusCode,
+    value: &Value,
+) -> MiddlewareResult<'mw> {
+    *res.status_mut() = status;
+    res.headers_mut()
+        .set_raw("content-type", vec![b"application/json".to_vec()]);
+    res.send(value.to_string())
+}
+
+/// Returns the rows of a result as a JSON array of objects, see
+/// `DataSet::write_json`
+fn rows_json(table: &mut DataSet) -> Value {
+    let mut json = Vec::new();
+    match table.write_json(&mut json) {
+        Ok(()) => serde_json::from_slice(&json).unwrap_or(Value::Null),
+        Err(_) => Value::Null,
+    }
+}
+
+/// Whether the result is the answer of the server to a statement it could
+/// not execute, an empty column `error`
+fn is_failed(table: &mut DataSet) -> bool {
+    table.data_empty() && table.get_col_cnt() == 1 && table.get_col_name(0) == Some("error")
+}
+
+/// Returns the message shown for an error connecting to a server
+fn connect_error(e: &Error) -> &'static str {
+    match e {
+        &Error::AddrParse(_) => "Could not connect to specified server.",
+        &Error::Io(_) => "Connection failure. Try again later.",
+        &Error::Bincode(_) => "Could not read data from server.",
+        &Error::UnexpectedPkg => "Unexpected Package.",
+        &Error::Auth => "Authentication failed.",
+        &Error::ProtocolMismatch => "Server speaks an incompatible protocol version.",
+        &Error::TooManyConnections => "Server has too many open connections.",
+        &Error::CorruptPacket => "Received a damaged package.",
+        &Error::PacketTooLarge => "Received a package that is too large.",
+        &Error::Tls(_) => "Could not encrypt the connection.",
+        &Error::Server(_) => "Network Error.",
+    }
 }
 
 /// Test if binding address is a valid address
@@ -291,14 +1545,215 @@ fn test_bind(bind: &str) -> bool {
     result
 }
 
-pub fn display_html(table: &mut DataSet) -> String {
+/// Returns the html of a result, of its rows those of the page, counted
+/// from 1, with `limit` rows per page. The cells of an editable result can
+/// be changed.
+pub fn display_html(
+    table: &mut DataSet,
+    page: usize,
+    limit: usize,
+    editable: Option<&Editable>,
+) -> String {
     if table.data_empty() && table.metadata_empty() {
         // println!("done.");
         return String::new();
     } else if table.data_empty() {
         display_meta_html(table)
     } else {
-        display_data_html(table)
+        display_data_html(table, page, limit, editable)
+    }
+}
+
+/// Puts a query in front of the history, moving it there if it is in it
+/// already, and forgets the oldest queries beyond `HISTORY_SIZE`.
+fn remember(history: &mut VecDeque<String>, query: &str) {
+    if query.is_empty() {
+        return;
+    }
+    history.retain(|q| q != query);
+    history.push_front(query.to_string());
+    history.truncate(HISTORY_SIZE);
+}
+
+/// Returns the rows of the result, those inserted, the time the query took
+/// and the plan of a select, which is folded
+/// returns the summary in a String with html syntax
+fn display_execution_html(execution: &mut Execution, table: &DataSet) -> String {
+    let mut result = String::new();
+    result.push_str(&format!(
+        "<p style=\"text-align:center\">{} rows returned",
+        table.get_line_cnt()
+    ));
+    if let Some(affected) = execution.affected {
+        result.push_str(&format!(", {} rows affected", affected));
+    }
+    result.push_str(&format!(", {:.1} ms</p>", execution.millis));
+
+    if let Some(ref mut plan) = execution.plan {
+        result.push_str("<details><summary>Plan</summary><table id=\"t01\"><tr>");
+        let cols = plan.get_col_cnt();
+        for i in 0..cols {
+            result.push_str(&cell("th", plan.get_col_name(i).unwrap_or("none")));
+        }
+        result.push_str("</tr>");
+        plan.first();
+        while plan.next() {
+            result.push_str("<tr>");
+            for i in 0..cols {
+                let value = cell_value(plan, i);
+                result.push_str(&cell("td", &value.unwrap_or("NULL".to_string())));
+            }
+            result.push_str("</tr>");
+        }
+        result.push_str("</table></details>");
+    }
+    result
+}
+
+/// Lists the queries of the history, the newest first, each with a link
+/// running it again and a button copying it into the editor
+/// returns the list in a String with html syntax
+fn display_history_html(history: &VecDeque<String>) -> String {
+    let mut result = String::new();
+    result.push_str("<table id=\"t01\"><caption>History</caption>");
+    for query in history {
+        let encoded: String = urlencode::byte_serialize(query.as_bytes()).collect();
+        let escaped = escape_html(query);
+        result.push_str(&format!(
+            "<tr><td><code>{}</code></td><td><a href=\"/?sql={}\">Run</a></td>\
+             <td><button type=\"button\" data-sql=\"{}\" \
+             onclick=\"document.getElementsByName('sql')[0].value = this.dataset.sql\">\
+             Copy to editor</button></td></tr>",
+            escaped, encoded, escaped
+        ));
+    }
+    result.push_str("</table>");
+    result
+}
+
+/// Lists the connections of the session in a dropdown switching the active
+/// one, followed by a form adding another connection
+/// returns the forms in a String with html syntax
+fn display_connections_html(conns: &[NamedConnection]) -> String {
+    let mut result = String::new();
+    result.push_str(
+        "<form action=\"/switch\">Active connection: \
+         <select name=\"name\" onchange=\"this.form.submit()\">",
+    );
+    for named in conns {
+        let con = named.con.lock().unwrap();
+        result.push_str(&format!(
+            "<option value=\"{}\">{} ({}@{}:{})</option>",
+            escape_html(&named.name),
+            escape_html(&named.name),
+            escape_html(con.get_username()),
+            escape_html(con.get_ip()),
+            con.get_port()
+        ));
+    }
+    result.push_str("</select> <input type=\"submit\" value=\"Switch\"></form>");
+    result.push_str(
+        "<form method=\"post\" action=\"/connect\">\
+         <input type=\"text\" name=\"name\" placeholder=\"Name\"> \
+         <input type=\"text\" name=\"bind\" placeholder=\"127.0.0.1\"> \
+         <input type=\"text\" name=\"port\" placeholder=\"4242\"> \
+         <input type=\"text\" name=\"user\" placeholder=\"Username\" required> \
+         <input type=\"password\" name=\"password\" placeholder=\"Password\" required> \
+         <input type=\"submit\" value=\"Add connection\"></form>",
+    );
+    result
+}
+
+/// Returns the dropdown choosing the connection a query runs on, the
+/// active one first
+fn display_targets_html(conns: &[NamedConnection]) -> String {
+    let mut result = String::new();
+    result.push_str("Run on <select name=\"target\">");
+    for named in conns {
+        let name = escape_html(&named.name);
+        result.push_str(&format!("<option value=\"{}\">{}</option>", name, name));
+    }
+    result.push_str("</select>");
+    result
+}
+
+/// Returns a cell of a table row, `td` or `th`, holding the text. Every
+/// value from a server goes through here: it may be anything a user stored,
+/// markup included, and is shown as text.
+fn cell(tag: &str, text: &str) -> String {
+    format!("<{0}>{1}</{0}>", tag, escape_html(text))
+}
+
+/// Lists the saved queries of the user, each with its link and a button
+/// deleting it
+/// returns the list in a String with html syntax
+fn display_saved_html(queries: &[SavedQuery]) -> String {
+    if queries.is_empty() {
+        return String::new();
+    }
+    let mut result = String::new();
+    result.push_str("<table id=\"t01\"><caption>Saved queries</caption>");
+    for query in queries {
+        result.push_str(&format!(
+            "<tr>{}<td><code>{}</code></td><td><a href=\"/q/{}\">/q/{}</a></td>\
+             <td><form method=\"post\" action=\"/queries/delete\">\
+             <input type=\"hidden\" name=\"id\" value=\"{}\">\
+             <input type=\"submit\" value=\"Delete\"></form></td></tr>",
+            cell("td", &query.name),
+            escape_html(&query.sql),
+            query.id,
+            query.id,
+            query.id
+        ));
+    }
+    result.push_str("</table>");
+    result
+}
+
+/// Escapes the characters of text that are special in html, also within
+/// attribute values
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Returns the value of a column of the current row as shown to the user,
+/// None for NULL
+fn cell_value(table: &mut DataSet, idx: usize) -> Option<String> {
+    match table.get_type_by_idx(idx) {
+        Some(SqlType::Int) => table.next_int_by_idx(idx).map(|val| val.to_string()),
+        Some(SqlType::Bool) => table.next_bool_by_idx(idx).map(|val| val.to_string()),
+        _ => table.next_as_string_by_idx(idx),
+    }
+}
+
+/// Returns the name of a type as shown to the user
+fn type_name(sql_type: &SqlType) -> String {
+    match sql_type {
+        &SqlType::Int => "int".to_string(),
+        &SqlType::Bool => "bool".to_string(),
+        &SqlType::Char(p) => format!("Char({})", p),
+        &SqlType::VarChar(p) => format!("VarChar({})", p),
+        &SqlType::Text => "text".to_string(),
+        &SqlType::Float => "float".to_string(),
+        &SqlType::Double => "double".to_string(),
+        &SqlType::BigInt => "bigint".to_string(),
+        &SqlType::Date => "date".to_string(),
+        &SqlType::Time => "time".to_string(),
+        &SqlType::Timestamp => "timestamp".to_string(),
+        &SqlType::Decimal(p, s) => format!("Decimal({}, {})", p, s),
+        &SqlType::VarBinary(p) => format!("VarBinary({})", p),
+        &SqlType::Blob => "blob".to_string(),
     }
 }
 
@@ -312,8 +1767,7 @@ fn display_meta_html(table: &mut DataSet) -> String {
     result.push_str("<tr><th>Column name</th>");
     let cols = table.get_col_cnt();
     for i in 0..cols {
-        result
-            .push_str(&format!("<th>{}</th>", table.get_col_name(i).unwrap_or("none")).to_string());
+        result.push_str(&cell("th", table.get_col_name(i).unwrap_or("none")));
     }
     result.push_str("</tr>");
 
@@ -321,14 +1775,10 @@ fn display_meta_html(table: &mut DataSet) -> String {
     result.push_str("<tr><td>Type</td>");
     for i in 0..cols {
         let s = match table.get_type_by_idx(i) {
-            Some(n) => match n {
-                SqlType::Int => "int".to_string(),
-                SqlType::Bool => "bool".to_string(),
-                SqlType::Char(p) => format!("Char({})", p),
-            },
+            Some(n) => type_name(&n),
             None => "none".to_string(),
         };
-        result.push_str(&format!("<td>{}</td>", s).to_string());
+        result.push_str(&cell("td", &s));
     }
     result.push_str("</tr>");
 
@@ -339,7 +1789,7 @@ fn display_meta_html(table: &mut DataSet) -> String {
             Some(n) => n.to_string(),
             None => "none".to_string(),
         };
-        result.push_str(&format!("<td>{}</td>", b).to_string());
+        result.push_str(&cell("td", &b));
     }
     result.push_str("</tr>");
 
@@ -350,20 +1800,17 @@ fn display_meta_html(table: &mut DataSet) -> String {
             Some(n) => n.to_string(),
             None => "none".to_string(),
         };
-        result.push_str(&format!("<td>{}</td>", tmp).to_string());
+        result.push_str(&cell("td", &tmp));
     }
     result.push_str("</tr>");
 
     // Fifth table row (Description)
     result.push_str("<tr><td>Description</td>");
     for i in 0..cols {
-        result.push_str(
-            &format!(
-                "<td>{}</td>",
-                table.get_description_by_idx(i).unwrap_or("none")
-            )
-            .to_string(),
-        );
+        result.push_str(&cell(
+            "td",
+            table.get_description_by_idx(i).unwrap_or("none"),
+        ));
     }
     result.push_str("</tr>");
     // End table
@@ -371,40 +1818,70 @@ fn display_meta_html(table: &mut DataSet) -> String {
     result
 }
 
-// Fill table with row data
+// Fill table with the row data of a page, pages beyond the last show the
+// last one, followed by links to the previous and next page. The cells of
+// an editable result are inputs, but for those of the primary key, in a
+// form saving the changes.
 // returns the data in a String with html syntax
-fn display_data_html(table: &mut DataSet) -> String {
+fn display_data_html(
+    table: &mut DataSet,
+    page: usize,
+    limit: usize,
+    editable: Option<&Editable>,
+) -> String {
+    let lines = table.get_line_cnt();
+    let pages = (lines + limit - 1) / limit;
+    let page = cmp::min(cmp::max(page, 1), pages);
+    let first = (page - 1) * limit;
+    let last = cmp::min(first + limit, lines);
+
     let mut result = String::new();
-    result.push_str("<table id=\"t01\"><caption>Results</caption>");
+    if editable.is_some() {
+        result.push_str(&format!(
+            "<form method=\"post\" action=\"/save\">\
+             <input type=\"hidden\" name=\"page\" value=\"{}\">\
+             <input type=\"hidden\" name=\"limit\" value=\"{}\">",
+            page, limit
+        ));
+    }
+    result.push_str(&format!(
+        "<table id=\"t01\"><caption>Results {} to {} of {}</caption>",
+        first + 1,
+        last,
+        lines
+    ));
 
     let cols = table.get_col_cnt();
 
-    // Row of column names
+    // Row of column names, and whether their cells can be changed
+    let mut editable_cols = Vec::new();
+    result.push_str("<tr>");
     for i in 0..cols {
-        result
-            .push_str(&format!("<th>{}</th>", table.get_col_name(i).unwrap_or("none")).to_string());
+        let name = table.get_col_name(i).unwrap_or("none").to_string();
+        editable_cols.push(editable.map_or(false, |e| !e.keys.contains(&name)));
+        result.push_str(&cell("th", &name));
     }
     result.push_str("</tr>");
 
     // Actual data input
-    while table.next() {
+    table.seek(first);
+    for row in first..last {
+        table.next();
+        result.push_str("<tr>");
         for i in 0..cols {
-            match table.get_type_by_idx(i) {
-                Some(t) => match t {
-                    SqlType::Int => match table.next_int_by_idx(i) {
-                        Some(val) => result.push_str(&format!("<td>{}</td>", val).to_string()),
-                        None => result.push_str("<td>none</td>"),
-                    },
-                    SqlType::Bool => match table.next_bool_by_idx(i) {
-                        Some(val) => result.push_str(&format!("<td>{}</td>", val).to_string()),
-                        None => result.push_str("<td>none</td>"),
-                    },
-                    SqlType::Char(_) => result.push_str(&format!(
-                        "<td>{}</td>",
-                        table.next_char_by_idx(i).unwrap_or("none".to_string())
-                    )),
-                },
-                None => continue,
+            if table.get_type_by_idx(i).is_none() {
+                continue;
+            }
+            let value = cell_value(table, i);
+            if editable_cols[i] {
+                result.push_str(&format!(
+                    "<td><input name=\"r{}c{}\" value=\"{}\" placeholder=\"NULL\"></td>",
+                    row,
+                    i,
+                    escape_html(&value.unwrap_or(String::new()))
+                ));
+            } else {
+                result.push_str(&cell("td", &value.unwrap_or("NULL".to_string())));
             }
         }
         result.push_str("</tr>");
@@ -412,5 +1889,39 @@ fn display_data_html(table: &mut DataSet) -> String {
 
     // End table
     result.push_str("</table>");
+    if editable.is_some() {
+        result.push_str(
+            "<p style=\"text-align:center\"><input type=\"submit\" value=\"Save changes\">\
+             </p></form>",
+        );
+    }
+
+    // Previous and next page
+    if pages > 1 {
+        result.push_str("<p style=\"text-align:center\">");
+        if page > 1 {
+            result.push_str(&format!(
+                "<a href=\"/?page={}&limit={}\">Previous</a> ",
+                page - 1,
+                limit
+            ));
+        }
+        result.push_str(&format!("Page {} of {}", page, pages));
+        if page < pages {
+            result.push_str(&format!(
+                " <a href=\"/?page={}&limit={}\">Next</a>",
+                page + 1,
+                limit
+            ));
+        }
+        result.push_str("</p>");
+    }
+
+    // Download of all rows
+    result.push_str(
+        "<p style=\"text-align:center\">Download: \
+         <a href=\"/export?format=csv\">CSV</a> \
+         <a href=\"/export?format=json\">JSON</a></p>",
+    );
     result
 }
@@ -21,9 +21,9 @@
             <label for ="password"> Password*<br></label>
             <input type = "password" name="password" id="password" required><br>
             <label for ="Bind"> Bind to<br></label>
-            <input type = "text" name="bind" id="bind"><br>
+            <input type = "text" name="bind" id="bind" value="{{ bind }}"><br>
             <label for ="port"> Port<br></label>
-            <input type = "port" name="port" id="port"><br>
+            <input type = "port" name="port" id="port" value="{{ port }}"><br>
             <input type = "submit" value="Login">
         </form>
     </p>
@@ -30,9 +30,15 @@
         Connected (version : {{ version }}) to {{ bind }} : {{ port }} <br>
         {{ msg }}
     </h4>
+    <div style="text-align:center">
+        {{{ connections }}}
+    </div>
     <form style="text-align:center">
-        <textarea name="sql" rows="5" cols="50"></textarea><br>
+        <textarea name="sql" rows="5" cols="50">{{ editor }}</textarea><br>
+        {{{ targets }}}
         <input type = "submit" value="Query">
+        <input type="text" name="save_name" placeholder="Name">
+        <input type="submit" value="Save" formmethod="post" formaction="/queries">
     </form>
     <p style = "text-align:center">
         <pre>
@@ -41,6 +47,12 @@
             </font>
         </pre>
     </p>
+    <p style = "text-align:center">
+        {{{ history }}}
+    </p>
+    <p style = "text-align:center">
+        {{{ saved }}}
+    </p>
     <form style = "text-align:right">
         <button method = "post" action = "/logout" onClick ="location = '/logout'"type="button" id = "logout"> Logout </button>
     </form>